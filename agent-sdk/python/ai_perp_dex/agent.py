@@ -234,9 +234,9 @@ class TradingAgent:
     
     # ==================== 资金管理 ====================
     
-    def deposit(self, amount: float) -> TradeResult:
-        """存入抵押品 (USDC)"""
-        return self.client.deposit(amount)
+    def deposit(self, amount: float, tx_signature: str) -> TradeResult:
+        """存入抵押品 (USDC)，tx_signature 为链上转账交易"""
+        return self.client.deposit(amount, tx_signature)
     
     def withdraw(self, amount: float) -> TradeResult:
         """提取抵押品"""
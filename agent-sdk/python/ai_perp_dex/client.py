@@ -56,7 +56,7 @@ class PerpDexClient:
                 # Generate new keypair for testing
                 import secrets
                 self._private_key = secrets.token_bytes(32)
-                self._public_key = secrets.token_bytes(32)
+                self._public_key = self._derive_public_key(self._private_key)
         elif private_key:
             import base58
             self._private_key = base58.b58decode(private_key)[:32]
@@ -65,20 +65,23 @@ class PerpDexClient:
             # Generate random keypair for testing
             import secrets
             self._private_key = secrets.token_bytes(32)
-            self._public_key = secrets.token_bytes(32)
+            self._public_key = self._derive_public_key(self._private_key)
         
         import base58
         self.pubkey = base58.b58encode(self._public_key).decode()
         self._registered = False
     
+    @staticmethod
+    def _derive_public_key(seed: bytes) -> bytes:
+        """Derive the ed25519 public key for a 32-byte seed."""
+        from solders.keypair import Keypair
+        return bytes(Keypair.from_seed(seed).pubkey())
+    
     def _sign_message(self, message: bytes) -> str:
-        """Sign a message with the agent's private key."""
-        # Simplified signature for now
-        # In production, use proper Ed25519 signing
-        import hashlib
-        import base58
-        sig = hashlib.sha256(self._private_key + message).digest() * 2
-        return base58.b58encode(sig).decode()
+        """Sign a message with the agent's ed25519 key (base58 signature)."""
+        from solders.keypair import Keypair
+        keypair = Keypair.from_seed(self._private_key)
+        return str(keypair.sign_message(message))
     
     def _request(
         self,
@@ -162,7 +165,9 @@ class PerpDexClient:
     ) -> TradeResult:
         """Open a position."""
         # Create order data
+        nonce = time.time_ns() // 1_000_000
         order_data = {
+            "pubkey": self.pubkey,
             "market": market,
             "side": side.value,
             "order_type": order_type.value,
@@ -171,10 +176,19 @@ class PerpDexClient:
             "price": price,
             "take_profit": take_profit,
             "stop_loss": stop_loss,
+            "nonce": nonce,
         }
         
-        # Sign the order
-        message = json.dumps(order_data, sort_keys=True).encode()
+        # Sign the canonical order message over every field (see api-server
+        # auth::order_message); this client sends no stop price or client order id
+        def field(value: Optional[float]) -> str:
+            return f"{value:.6f}" if value is not None else ""
+        
+        message = (
+            f"ai-perp-dex:order|{self.pubkey}|{market}|{side.value}|{order_type.value}"
+            f"|{size_usd:.6f}|{leverage}|{field(price)}|{field(None)}"
+            f"|{field(take_profit)}|{field(stop_loss)}||{nonce}"
+        ).encode()
         order_data["signature"] = self._sign_message(message)
         
         result = self._request("POST", "/v1/order", order_data)
@@ -344,11 +358,17 @@ class PerpDexClient:
     
     # ==================== Account Management ====================
     
-    def deposit(self, amount: float) -> TradeResult:
-        """Deposit collateral."""
+    def deposit(self, amount: float, tx_signature: str) -> TradeResult:
+        """Credit collateral moved by the on-chain transaction `tx_signature`."""
+        nonce = time.time_ns() // 1_000_000
+        # Sign the canonical deposit message (see api-server auth::deposit_message)
+        message = f"ai-perp-dex:deposit|{self.pubkey}|{amount:.6f}|{tx_signature}|{nonce}".encode()
         result = self._request("POST", "/v1/account/deposit", {
+            "pubkey": self.pubkey,
             "amount": amount,
-            "tx_signature": "mock_deposit_tx",
+            "tx_signature": tx_signature,
+            "nonce": nonce,
+            "signature": self._sign_message(message),
         })
         
         return TradeResult(
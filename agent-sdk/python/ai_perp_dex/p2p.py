@@ -78,7 +78,7 @@ class P2PClient:
     P2P Trading Client for connecting to the Trade Router.
     
     Example usage:
-        async with P2PClient("ws://localhost:8080/ws", "my_agent_id") as client:
+        async with P2PClient("ws://localhost:8080/ws", agent_id="my_agent_id", api_key="th_...") as client:
             # Subscribe to events
             client.on_trade_request = lambda req: print(f"New request: {req}")
             
@@ -97,11 +97,13 @@ class P2PClient:
         self,
         ws_url: str = "ws://localhost:8080/ws",
         rest_url: str = "http://localhost:8080",
-        agent_id: str = None
+        agent_id: str = None,
+        api_key: Optional[str] = None,
     ):
         self.ws_url = ws_url
         self.rest_url = rest_url
         self.agent_id = agent_id or str(uuid.uuid4())
+        self.api_key = api_key
         self._ws = None
         self._running = False
         self._handlers: Dict[str, Callable] = {}
@@ -122,7 +124,11 @@ class P2PClient:
     
     async def connect(self):
         """Connect to the Trade Router WebSocket."""
-        self._ws = await websockets.connect(self.ws_url)
+        # The router closes unauthenticated sockets with a policy violation
+        url = self.ws_url
+        if self.api_key:
+            url += ("&" if "?" in url else "?") + f"api_key={self.api_key}"
+        self._ws = await websockets.connect(url)
         self._running = True
         asyncio.create_task(self._listen())
     
@@ -224,6 +230,9 @@ class P2PClient:
         async with aiohttp.ClientSession() as session:
             async with session.post(f"{self.rest_url}{path}", json=data) as resp:
                 result = await resp.json()
+                if "code" in result:
+                    # Structured error: {code, message, details}
+                    raise Exception(f"{result['code']}: {result.get('message', '')}")
                 if not result.get("success"):
                     raise Exception(result.get("error", "Unknown error"))
                 return result.get("data")
@@ -3,7 +3,7 @@
 //! Keypair-based authentication for AI Agents.
 
 use ed25519_dalek::{Signature, VerifyingKey, Verifier};
-use bs58;
+use std::collections::HashMap;
 
 /// Verify an agent's signature
 pub fn verify_signature(
@@ -44,11 +44,99 @@ pub fn verify_signature(
         .map_err(|_| AuthError::VerificationFailed)
 }
 
+/// Every field of an order request, all of which its signature covers
+#[derive(Debug, Clone, Copy)]
+pub struct OrderFields<'a> {
+    pub pubkey: &'a str,
+    pub market: &'a str,
+    pub side: &'a str,
+    pub order_type: &'a str,
+    pub size_usd: f64,
+    pub leverage: u8,
+    pub price: Option<f64>,
+    pub stop_price: Option<f64>,
+    pub take_profit: Option<f64>,
+    pub stop_loss: Option<f64>,
+    pub client_order_id: Option<&'a str>,
+    pub nonce: u64,
+}
+
+/// Build the canonical message an agent signs to submit an order.
+///
+/// Fields are joined with `|` in a fixed order; amounts and prices are rendered
+/// with six decimals and absent optional fields as empty fields, so clients in
+/// any language can reproduce the exact bytes.
+pub fn order_message(order: &OrderFields) -> String {
+    let optional = |value: Option<f64>| value.map(|v| format!("{:.6}", v)).unwrap_or_default();
+    format!(
+        "ai-perp-dex:order|{}|{}|{}|{}|{:.6}|{}|{}|{}|{}|{}|{}|{}",
+        order.pubkey,
+        order.market,
+        order.side,
+        order.order_type,
+        order.size_usd,
+        order.leverage,
+        optional(order.price),
+        optional(order.stop_price),
+        optional(order.take_profit),
+        optional(order.stop_loss),
+        order.client_order_id.unwrap_or_default(),
+        order.nonce,
+    )
+}
+
+/// Build the canonical message an agent signs to cancel one of its orders
+pub fn cancel_message(pubkey: &str, order_id: u64, nonce: u64) -> String {
+    format!("ai-perp-dex:cancel|{}|{}|{}", pubkey, order_id, nonce)
+}
+
+/// Build the canonical message an agent signs to look up one of its orders
+pub fn get_order_message(pubkey: &str, order_id: u64, nonce: u64) -> String {
+    format!("ai-perp-dex:order-status|{}|{}|{}", pubkey, order_id, nonce)
+}
+
+/// Build the canonical message an agent signs to list its open orders
+pub fn list_orders_message(pubkey: &str, nonce: u64) -> String {
+    format!("ai-perp-dex:orders|{}|{}", pubkey, nonce)
+}
+
+/// Build the canonical message an agent signs to credit a deposit, in the
+/// same `|`-joined format as `order_message`
+pub fn deposit_message(pubkey: &str, amount: f64, tx_signature: &str, nonce: u64) -> String {
+    format!("ai-perp-dex:deposit|{}|{:.6}|{}|{}", pubkey, amount, tx_signature, nonce)
+}
+
+/// Per-agent nonces for replay protection.
+///
+/// Each signed request must carry a nonce strictly greater than the last one
+/// accepted for that agent; a millisecond timestamp works well.
+#[derive(Debug, Default)]
+pub struct NonceTracker {
+    last: HashMap<String, u64>,
+}
+
+impl NonceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Accept `nonce` for `pubkey` if it is newer than the last one seen
+    pub fn check_and_advance(&mut self, pubkey: &str, nonce: u64) -> Result<(), AuthError> {
+        let last = self.last.get(pubkey).copied();
+        if last.is_some_and(|last| nonce <= last) {
+            return Err(AuthError::StaleNonce);
+        }
+        self.last.insert(pubkey.to_string(), nonce);
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum AuthError {
     InvalidPubkey,
     InvalidSignature,
     VerificationFailed,
+    StaleNonce,
 }
 
 impl std::fmt::Display for AuthError {
@@ -57,6 +145,7 @@ impl std::fmt::Display for AuthError {
             AuthError::InvalidPubkey => write!(f, "Invalid public key"),
             AuthError::InvalidSignature => write!(f, "Invalid signature"),
             AuthError::VerificationFailed => write!(f, "Signature verification failed"),
+            AuthError::StaleNonce => write!(f, "Nonce already used"),
         }
     }
 }
@@ -66,9 +155,79 @@ impl std::error::Error for AuthError {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    
+    fn keypair() -> (SigningKey, String) {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let pubkey = bs58::encode(key.verifying_key().as_bytes()).into_string();
+        (key, pubkey)
+    }
+    
+    fn sign(key: &SigningKey, message: &str) -> String {
+        bs58::encode(key.sign(message.as_bytes()).to_bytes()).into_string()
+    }
+    
+    fn order(pubkey: &str) -> OrderFields<'_> {
+        OrderFields {
+            pubkey,
+            market: "BTC-PERP",
+            side: "long",
+            order_type: "stop_limit",
+            size_usd: 1000.0,
+            leverage: 5,
+            price: Some(50000.0),
+            stop_price: Some(49500.0),
+            take_profit: None,
+            stop_loss: Some(48000.0),
+            client_order_id: Some("bot-1"),
+            nonce: 1,
+        }
+    }
     
     #[test]
     fn test_verify_signature() {
-        // TODO: Add tests with real keypairs
+        let (key, pubkey) = keypair();
+        let message = order_message(&order(&pubkey));
+        let signature = sign(&key, &message);
+        
+        assert!(verify_signature(&pubkey, message.as_bytes(), &signature).is_ok());
+    }
+    
+    #[test]
+    fn test_tampered_payload_fails() {
+        let (key, pubkey) = keypair();
+        let signed = order_message(&order(&pubkey));
+        let signature = sign(&key, &signed);
+        
+        // Changing any field, including the optional ones, breaks the signature
+        let tampered = [
+            OrderFields { size_usd: 100000.0, ..order(&pubkey) },
+            OrderFields { stop_price: Some(40000.0), ..order(&pubkey) },
+            OrderFields { stop_price: None, ..order(&pubkey) },
+            OrderFields { take_profit: Some(60000.0), ..order(&pubkey) },
+            OrderFields { stop_loss: None, ..order(&pubkey) },
+            OrderFields { client_order_id: Some("bot-2"), ..order(&pubkey) },
+        ];
+        for fields in tampered {
+            assert!(matches!(
+                verify_signature(&pubkey, order_message(&fields).as_bytes(), &signature),
+                Err(AuthError::VerificationFailed)
+            ));
+        }
+        
+        // A valid signature from a different key
+        let other = bs58::encode(SigningKey::from_bytes(&[9u8; 32]).verifying_key().as_bytes()).into_string();
+        assert!(verify_signature(&other, signed.as_bytes(), &signature).is_err());
+    }
+    
+    #[test]
+    fn test_nonce_must_increase() {
+        let mut nonces = NonceTracker::new();
+        
+        assert!(nonces.check_and_advance("agent", 5).is_ok());
+        assert!(matches!(nonces.check_and_advance("agent", 5), Err(AuthError::StaleNonce)));
+        assert!(nonces.check_and_advance("agent", 4).is_err());
+        assert!(nonces.check_and_advance("agent", 6).is_ok());
+        assert!(nonces.check_and_advance("other", 1).is_ok());
     }
 }
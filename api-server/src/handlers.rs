@@ -12,12 +12,23 @@ use std::sync::Arc;
 use chrono::Utc;
 use uuid::Uuid;
 
+use ai_perp_dex_matching_engine::agent::AgentRiskLimits;
+use ai_perp_dex_matching_engine::engine::EngineError;
+use ai_perp_dex_matching_engine::order::{CancelOrderRequest, OrderStatus as EngineOrderStatus, PlaceOrderRequest};
+use ai_perp_dex_matching_engine::{
+    Order as EngineOrder, OrderOutcome, OrderType as EngineOrderType, PriceLevel, Side,
+};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+use crate::auth;
 use crate::AppState;
 use crate::types::*;
 
 // ==================== Agent Management ====================
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)] // Not every field is acted on yet
 pub struct RegisterAgentRequest {
     pub pubkey: String,
     pub name: String,
@@ -64,7 +75,7 @@ pub async fn get_agent_info(
 
 pub async fn set_risk_params(
     State(_state): State<Arc<AppState>>,
-    Json(params): Json<RiskParams>,
+    Json(_params): Json<RiskParams>,
 ) -> Result<Json<ApiResponse>, StatusCode> {
     // TODO: Update risk params
     Ok(Json(ApiResponse {
@@ -77,9 +88,10 @@ pub async fn set_risk_params(
 
 #[derive(Debug, Deserialize)]
 pub struct SubmitOrderRequest {
+    pub pubkey: String,
     pub market: String,
     pub side: String,         // "long" or "short"
-    pub order_type: String,   // "market", "limit", "stop"
+    pub order_type: String,   // "market", "limit", "stop", "stop_limit"
     pub size_usd: f64,
     pub leverage: u8,
     pub price: Option<f64>,   // Required for limit orders
@@ -87,6 +99,9 @@ pub struct SubmitOrderRequest {
     pub take_profit: Option<f64>,
     pub stop_loss: Option<f64>,
     pub client_order_id: Option<String>,
+    /// Must increase with every order from this pubkey
+    pub nonce: u64,
+    /// Base58 ed25519 signature over `auth::order_message`
     pub signature: String,
 }
 
@@ -101,62 +116,336 @@ pub struct SubmitOrderResponse {
     pub message: String,
 }
 
+impl SubmitOrderResponse {
+    fn rejected(client_order_id: Option<String>, message: String) -> Self {
+        Self {
+            success: false,
+            order_id: None,
+            client_order_id,
+            status: "rejected".to_string(),
+            filled_size: 0.0,
+            avg_price: None,
+            message,
+        }
+    }
+}
+
 pub async fn submit_order(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
     Json(req): Json<SubmitOrderRequest>,
 ) -> Result<Json<SubmitOrderResponse>, StatusCode> {
-    // TODO: Validate signature
-    // TODO: Check risk limits
-    // TODO: Submit to matching engine
+    // `|` separates the signed fields, so the one free-form field may not contain it
+    if req.client_order_id.as_deref().is_some_and(|id| id.contains('|')) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let message = auth::order_message(&auth::OrderFields {
+        pubkey: &req.pubkey,
+        market: &req.market,
+        side: &req.side,
+        order_type: &req.order_type,
+        size_usd: req.size_usd,
+        leverage: req.leverage,
+        price: req.price,
+        stop_price: req.stop_price,
+        take_profit: req.take_profit,
+        stop_loss: req.stop_loss,
+        client_order_id: req.client_order_id.as_deref(),
+        nonce: req.nonce,
+    });
+    auth::verify_signature(&req.pubkey, message.as_bytes(), &req.signature)
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    state.nonces.write().await
+        .check_and_advance(&req.pubkey, req.nonce)
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
     
-    let order_id = Uuid::new_v4().to_string();
+    let side = match req.side.as_str() {
+        "long" => Side::Buy,
+        "short" => Side::Sell,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    let order_type = match req.order_type.as_str() {
+        "market" => EngineOrderType::Market,
+        "limit" => EngineOrderType::Limit,
+        "stop" => EngineOrderType::StopMarket,
+        "stop_limit" => EngineOrderType::StopLimit,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    if req.size_usd <= 0.0 || req.leverage == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    
+    let engine = state.engine.read().await;
+    
+    // Size is quoted in USD; convert at the order's own price, or at the
+    // touch on the opposite side for market orders
+    let reference_price = match order_type {
+        EngineOrderType::Limit | EngineOrderType::StopLimit => req.price,
+        EngineOrderType::StopMarket => req.stop_price,
+        EngineOrderType::Market => {
+            let (bid, ask) = engine.get_bbo(&req.market).map_err(|_| StatusCode::NOT_FOUND)?;
+            let touch = if side == Side::Buy { ask } else { bid };
+            touch.and_then(|p| p.as_decimal().to_f64())
+        }
+    };
+    let Some(reference_price) = reference_price.filter(|p| *p > 0.0) else {
+        return Ok(Json(SubmitOrderResponse::rejected(
+            req.client_order_id,
+            "No price available to size the order".to_string(),
+        )));
+    };
+    let quantity = req.size_usd / reference_price;
+    
+    // Check risk limits against the resulting position
+    let limits = AgentRiskLimits::default();
+    if f64::from(req.leverage) > limits.max_leverage {
+        return Ok(Json(SubmitOrderResponse::rejected(
+            req.client_order_id,
+            format!("Leverage {}x exceeds max {}x", req.leverage, limits.max_leverage),
+        )));
+    }
+    let signed_quantity = match side {
+        Side::Buy => to_decimal(quantity),
+        Side::Sell => -to_decimal(quantity),
+    };
+    match engine.check_order_risk(&req.pubkey, &req.market, signed_quantity, to_decimal(reference_price), &limits) {
+        Ok(()) => {}
+        Err(EngineError::MarketNotFound(_)) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => return Ok(Json(SubmitOrderResponse::rejected(req.client_order_id, e.to_string()))),
+    }
+    
+    let request = PlaceOrderRequest {
+        agent_id: req.pubkey.clone(),
+        market: req.market.clone(),
+        side,
+        order_type,
+        price: req.price,
+        quantity,
+        time_in_force: None,
+        stop_price: req.stop_price,
+        reduce_only: None,
+        client_order_id: req.client_order_id.clone(),
+        self_trade_policy: None,
+        max_slippage_bps: None,
+        round: None,
+        fallback_limit: None,
+        display_qty: None,
+    };
+    
+    let (order, trades, outcome, _) = match engine.place_order(request) {
+        Ok(result) => result,
+        Err(EngineError::MarketNotFound(_)) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => return Ok(Json(SubmitOrderResponse::rejected(req.client_order_id, e.to_string()))),
+    };
+    
+    // Fills of this order only, not stops it may have triggered
+    let own_fills: Vec<_> = trades.iter().filter(|t| t.taker_order_id == order.id).collect();
+    let filled_qty: Decimal = own_fills.iter().map(|t| t.quantity.as_decimal()).sum();
+    let filled_usd: Decimal = own_fills
+        .iter()
+        .map(|t| t.quantity.as_decimal() * t.price.as_decimal())
+        .sum();
+    let avg_price = (!filled_qty.is_zero())
+        .then(|| (filled_usd / filled_qty).to_f64())
+        .flatten();
+    
+    let message = match outcome {
+        OrderOutcome::NoLiquidity { unfilled_quantity, .. } => format!(
+            "Order {} ran out of liquidity: ${:.2} unfilled",
+            order.id.0,
+            to_f64(unfilled_quantity.as_decimal()) * reference_price
+        ),
+        _ => format!(
+            "Order {} {} {} ${} @ {}x",
+            order.id.0, req.side, req.market, req.size_usd, req.leverage
+        ),
+    };
     
     Ok(Json(SubmitOrderResponse {
         success: true,
-        order_id: Some(order_id.clone()),
+        order_id: Some(order.id.0.to_string()),
         client_order_id: req.client_order_id,
-        status: "filled".to_string(),
-        filled_size: req.size_usd,
-        avg_price: Some(97500.0), // TODO: Get from matching engine
-        message: format!(
-            "Order {} {} {} ${} @ {}x",
-            order_id, req.side, req.market, req.size_usd, req.leverage
-        ),
+        status: format!("{:?}", order.status).to_lowercase(),
+        filled_size: filled_usd.to_f64().unwrap_or(0.0),
+        avg_price,
+        message,
     }))
 }
 
+fn to_decimal(value: f64) -> Decimal {
+    Decimal::try_from(value).unwrap_or_default()
+}
+
+fn to_f64(value: Decimal) -> f64 {
+    value.to_f64().unwrap_or(0.0)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetOrderParams {
+    pub pubkey: String,
+    /// Must increase with every signed request from this pubkey
+    pub nonce: u64,
+    /// Base58 ed25519 signature over `auth::get_order_message`
+    pub signature: String,
+}
+
+/// Full state of one of the signing agent's orders
 pub async fn get_order(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
     Path(order_id): Path<String>,
+    Query(params): Query<GetOrderParams>,
 ) -> Result<Json<Order>, StatusCode> {
-    // TODO: Get from database
-    Err(StatusCode::NOT_FOUND)
+    let id: u64 = order_id.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let message = auth::get_order_message(&params.pubkey, id, params.nonce);
+    verify_signed(&state, &params.pubkey, &message, params.nonce, &params.signature).await?;
+    
+    let engine = state.engine.read().await;
+    let order = engine.get_order(id).ok_or(StatusCode::NOT_FOUND)?;
+    if order.agent_id != params.pubkey {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    
+    Ok(Json(api_order(&order)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelOrderParams {
+    pub pubkey: String,
+    /// Must increase with every signed request from this pubkey
+    pub nonce: u64,
+    /// Base58 ed25519 signature over `auth::cancel_message`
+    pub signature: String,
 }
 
 pub async fn cancel_order(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
     Path(order_id): Path<String>,
+    Query(params): Query<CancelOrderParams>,
 ) -> Result<Json<ApiResponse>, StatusCode> {
-    // TODO: Cancel in matching engine
-    Ok(Json(ApiResponse {
-        success: true,
-        message: format!("Order {} cancelled", order_id),
-    }))
+    let id: u64 = order_id.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let message = auth::cancel_message(&params.pubkey, id, params.nonce);
+    verify_signed(&state, &params.pubkey, &message, params.nonce, &params.signature).await?;
+    
+    let engine = state.engine.read().await;
+    match engine.cancel_order(CancelOrderRequest { agent_id: params.pubkey, order_id: id }) {
+        Ok(_) => Ok(Json(ApiResponse {
+            success: true,
+            message: format!("Order {} cancelled", order_id),
+        })),
+        Err(EngineError::OrderNotFound(_)) => Err(StatusCode::NOT_FOUND),
+        Err(EngineError::InvalidOrder(_)) => Err(StatusCode::FORBIDDEN),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Check that `signature` is `pubkey`'s signature over `message` and consume `nonce`
+async fn verify_signed(
+    state: &AppState,
+    pubkey: &str,
+    message: &str,
+    nonce: u64,
+    signature: &str,
+) -> Result<(), StatusCode> {
+    auth::verify_signature(pubkey, message.as_bytes(), signature)
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    state.nonces.write().await
+        .check_and_advance(pubkey, nonce)
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetOrdersParams {
+    pub pubkey: String,
+    /// Must increase with every signed request from this pubkey
+    pub nonce: u64,
+    /// Base58 ed25519 signature over `auth::list_orders_message`
+    pub signature: String,
+    pub market: Option<String>,
+    pub status: Option<String>,
 }
 
+/// Resting orders of the signing agent, optionally filtered by `market` and `status`
 pub async fn get_orders(
-    State(_state): State<Arc<AppState>>,
-    Query(params): Query<std::collections::HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<GetOrdersParams>,
 ) -> Result<Json<Vec<Order>>, StatusCode> {
-    // TODO: Get from database
-    Ok(Json(vec![]))
+    let message = auth::list_orders_message(&params.pubkey, params.nonce);
+    verify_signed(&state, &params.pubkey, &message, params.nonce, &params.signature).await?;
+    
+    let engine = state.engine.read().await;
+    let orders = engine.open_orders(&params.pubkey).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    
+    Ok(Json(
+        orders
+            .iter()
+            .map(api_order)
+            .filter(|o| params.market.as_ref().is_none_or(|m| &o.market == m))
+            .filter(|o| params.status.as_ref().is_none_or(|s| order_status_name(&o.status) == *s))
+            .collect(),
+    ))
+}
+
+fn api_order(order: &EngineOrder) -> Order {
+    let price = order.price.and_then(|p| p.as_decimal().to_f64());
+    let quantity = to_f64(order.quantity.as_decimal());
+    let remaining_quantity = to_f64(order.remaining_quantity.as_decimal());
+    let filled_quantity = quantity - remaining_quantity;
+    // Resting orders fill at their own limit price
+    let reference_price = price
+        .or_else(|| order.stop_price.and_then(|p| p.as_decimal().to_f64()))
+        .unwrap_or(0.0);
+    
+    Order {
+        order_id: order.id.0.to_string(),
+        client_order_id: order.client_order_id.clone(),
+        agent_id: order.agent_id.clone(),
+        market: order.market.to_string(),
+        side: match order.side {
+            Side::Buy => OrderSide::Long,
+            Side::Sell => OrderSide::Short,
+        },
+        order_type: match order.order_type {
+            EngineOrderType::Market => OrderType::Market,
+            EngineOrderType::Limit => OrderType::Limit,
+            EngineOrderType::StopMarket => OrderType::Stop,
+            EngineOrderType::StopLimit => OrderType::StopLimit,
+        },
+        quantity,
+        remaining_quantity,
+        size_usd: quantity * reference_price,
+        filled_size_usd: filled_quantity * reference_price,
+        price,
+        avg_fill_price: (filled_quantity > 0.0).then_some(reference_price),
+        leverage: None,
+        stop_price: order.stop_price.and_then(|p| p.as_decimal().to_f64()),
+        take_profit: None,
+        stop_loss: None,
+        status: match order.status {
+            EngineOrderStatus::Open => OrderStatus::Open,
+            EngineOrderStatus::PartiallyFilled => OrderStatus::PartiallyFilled,
+            EngineOrderStatus::Filled => OrderStatus::Filled,
+            EngineOrderStatus::Cancelled => OrderStatus::Cancelled,
+            EngineOrderStatus::Rejected => OrderStatus::Rejected,
+            EngineOrderStatus::Expired => OrderStatus::Expired,
+        },
+        created_at: (order.created_at.0 / 1_000_000) as i64,
+        updated_at: (order.updated_at.0 / 1_000_000) as i64,
+    }
+}
+
+/// Wire name of a status, as used by the `status` query filter
+fn order_status_name(status: &OrderStatus) -> String {
+    serde_json::to_value(status)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
 }
 
 // ==================== Positions ====================
 
 pub async fn get_positions(
     State(_state): State<Arc<AppState>>,
-    Query(params): Query<std::collections::HashMap<String, String>>,
+    Query(_params): Query<std::collections::HashMap<String, String>>,
 ) -> Result<Json<Vec<Position>>, StatusCode> {
     // TODO: Get from database
     Ok(Json(vec![]))
@@ -164,7 +453,7 @@ pub async fn get_positions(
 
 pub async fn get_position(
     State(_state): State<Arc<AppState>>,
-    Path(market): Path<String>,
+    Path(_market): Path<String>,
 ) -> Result<Json<Position>, StatusCode> {
     // TODO: Get from database
     Err(StatusCode::NOT_FOUND)
@@ -195,6 +484,7 @@ pub async fn close_position(
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)] // Not every field is acted on yet
 pub struct ModifyPositionRequest {
     pub market: String,
     pub new_leverage: Option<u8>,
@@ -272,49 +562,118 @@ pub async fn get_markets(
 }
 
 pub async fn get_price(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
     Path(market): Path<String>,
 ) -> Result<Json<PriceResponse>, StatusCode> {
-    let price = match market.as_str() {
-        "BTC-PERP" => 97500.0,
-        "ETH-PERP" => 2750.0,
-        "SOL-PERP" => 195.0,
-        _ => return Err(StatusCode::NOT_FOUND),
+    let engine = state.engine.read().await;
+    let stats = engine.get_market_stats(&market).map_err(|_| StatusCode::NOT_FOUND)?;
+    let (bid, ask) = engine.get_bbo(&market).map_err(|_| StatusCode::NOT_FOUND)?;
+    
+    let mid = match (bid, ask) {
+        (Some(bid), Some(ask)) => Some((bid.as_decimal() + ask.as_decimal()) / Decimal::TWO),
+        _ => None,
     };
+    let last = stats.last_price.map(|p| p.as_decimal());
+    
+    // No index feed yet, so index and mark both track the book
+    let mark = mid.or(last).ok_or(StatusCode::NOT_FOUND)?;
+    let price = last.unwrap_or(mark);
     
     Ok(Json(PriceResponse {
-        market,
-        price,
-        index_price: price * 1.0002,
-        mark_price: price * 1.0001,
+        market: stats.market.to_string(),
+        price: to_f64(price),
+        index_price: to_f64(mark),
+        mark_price: to_f64(mark),
         timestamp: Utc::now().timestamp_millis(),
     }))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct OrderbookParams {
+    pub depth: Option<usize>,
+}
+
 pub async fn get_orderbook(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
     Path(market): Path<String>,
+    Query(params): Query<OrderbookParams>,
 ) -> Result<Json<Orderbook>, StatusCode> {
-    // TODO: Get from matching engine
+    let snapshot = state.engine.read().await
+        .get_orderbook(&market, params.depth.unwrap_or(20))
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    
     Ok(Json(Orderbook {
-        market,
-        bids: vec![
-            OrderbookLevel { price: 97490.0, size: 1.5 },
-            OrderbookLevel { price: 97480.0, size: 2.3 },
-            OrderbookLevel { price: 97470.0, size: 3.1 },
-        ],
-        asks: vec![
-            OrderbookLevel { price: 97510.0, size: 1.2 },
-            OrderbookLevel { price: 97520.0, size: 2.0 },
-            OrderbookLevel { price: 97530.0, size: 2.8 },
-        ],
+        market: snapshot.market.to_string(),
+        bids: orderbook_levels(snapshot.bids),
+        asks: orderbook_levels(snapshot.asks),
+        best_bid: snapshot.best_bid.map(|p| to_f64(p.as_decimal())),
+        best_ask: snapshot.best_ask.map(|p| to_f64(p.as_decimal())),
+        mid_price: snapshot.mid_price.map(|p| to_f64(p.as_decimal())),
+        spread: snapshot.spread.map(|p| to_f64(p.as_decimal())),
+        timestamp: (snapshot.timestamp.0 / 1_000_000) as i64,
+    }))
+}
+
+fn orderbook_levels(levels: Vec<PriceLevel>) -> Vec<OrderbookLevel> {
+    levels
+        .into_iter()
+        .map(|l| OrderbookLevel {
+            price: to_f64(l.price.as_decimal()),
+            size: to_f64(l.quantity.as_decimal()),
+        })
+        .collect()
+}
+
+/// Deepest book the bulk snapshot returns per side
+const SNAPSHOT_MAX_DEPTH: usize = 10;
+
+/// Price, mark, funding, open interest and top of book for every market in one
+/// call, with the top `?depth=` levels of each book when asked for
+pub async fn get_markets_snapshot(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<OrderbookParams>,
+) -> Result<Json<MarketsSnapshot>, StatusCode> {
+    let depth = params.depth.map(|d| d.min(SNAPSHOT_MAX_DEPTH));
+    
+    let engine = state.engine.read().await;
+    let mut markets = Vec::new();
+    for market in engine.markets() {
+        let symbol = market.to_string();
+        let book = engine.get_orderbook(&symbol, depth.unwrap_or(0))
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let stats = engine.get_market_stats(&symbol)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let open_interest = engine.open_interest(&symbol)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        
+        // No index feed yet, so index and mark both track the book and the premium is zero
+        let last = stats.last_price.map(|p| p.as_decimal());
+        let mark = book.mid_price.map(|p| p.as_decimal()).or(last);
+        
+        markets.push(MarketSnapshot {
+            market: symbol,
+            price: last.or(mark).map(to_f64),
+            index_price: mark.map(to_f64),
+            mark_price: mark.map(to_f64),
+            funding_rate: 0.0,
+            open_interest: to_f64(open_interest),
+            best_bid: book.best_bid.map(|p| to_f64(p.as_decimal())),
+            best_ask: book.best_ask.map(|p| to_f64(p.as_decimal())),
+            spread: book.spread.map(|p| to_f64(p.as_decimal())),
+            bids: depth.map(|_| orderbook_levels(book.bids)),
+            asks: depth.map(|_| orderbook_levels(book.asks)),
+        });
+    }
+    
+    Ok(Json(MarketsSnapshot {
+        markets,
         timestamp: Utc::now().timestamp_millis(),
     }))
 }
 
 pub async fn get_trades(
     State(_state): State<Arc<AppState>>,
-    Path(market): Path<String>,
+    Path(_market): Path<String>,
 ) -> Result<Json<Vec<Trade>>, StatusCode> {
     // TODO: Get from database
     Ok(Json(vec![]))
@@ -345,18 +704,43 @@ pub async fn get_account(
 
 #[derive(Debug, Deserialize)]
 pub struct DepositRequest {
+    pub pubkey: String,
     pub amount: f64,
+    /// Transaction that moved the funds; each one is credited once
     pub tx_signature: String,
+    /// Must increase with every signed request from this pubkey
+    pub nonce: u64,
+    /// Base58 ed25519 signature over `auth::deposit_message`
+    pub signature: String,
 }
 
+/// Credit a deposit to the agent's balance in the matching engine, which the
+/// pre-trade margin check reads
 pub async fn deposit(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
     Json(req): Json<DepositRequest>,
 ) -> Result<Json<ApiResponse>, StatusCode> {
-    // TODO: Verify tx and credit account
+    let message = auth::deposit_message(&req.pubkey, req.amount, &req.tx_signature, req.nonce);
+    auth::verify_signature(&req.pubkey, message.as_bytes(), &req.signature)
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    state.nonces.write().await
+        .check_and_advance(&req.pubkey, req.nonce)
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    
+    let amount = to_decimal(req.amount);
+    if req.tx_signature.is_empty() || amount <= Decimal::ZERO {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if !state.credited_deposits.write().await.insert(req.tx_signature.clone()) {
+        return Err(StatusCode::CONFLICT);
+    }
+    
+    let balance = state.engine.read().await
+        .deposit(&req.pubkey, amount)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     Ok(Json(ApiResponse {
         success: true,
-        message: format!("Deposited ${}", req.amount),
+        message: format!("Deposited ${}, balance ${}", req.amount, to_f64(balance)),
     }))
 }
 
@@ -380,7 +764,7 @@ pub async fn withdraw(
 
 pub async fn get_history(
     State(_state): State<Arc<AppState>>,
-    Query(params): Query<std::collections::HashMap<String, String>>,
+    Query(_params): Query<std::collections::HashMap<String, String>>,
 ) -> Result<Json<Vec<TradeHistory>>, StatusCode> {
     // TODO: Get from database
     Ok(Json(vec![]))
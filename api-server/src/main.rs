@@ -2,14 +2,15 @@
 //! 
 //! REST/WebSocket API for AI Agents to trade perpetual contracts.
 
+use ai_perp_dex_matching_engine::MatchingEngine;
 use axum::{
+    extract::State,
+    http::StatusCode,
     routing::{get, post, delete, put},
     Router,
     Json,
-    extract::{State, Path, Query},
-    http::StatusCode,
 };
-use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tower_http::cors::CorsLayer;
@@ -17,13 +18,31 @@ use tower_http::cors::CorsLayer;
 mod handlers;
 mod types;
 mod auth;
-mod risk;
-
-use types::*;
 
 /// Application state shared across handlers
 pub struct AppState {
-    // TODO: Add matching engine, risk engine, etc.
+    /// Orderbooks, matching, and the `RiskEngine` balances and positions behind risk checks
+    pub engine: Arc<RwLock<MatchingEngine>>,
+    /// Deposit transactions already credited, so each is credited once
+    pub credited_deposits: Arc<RwLock<HashSet<String>>>,
+    /// Last accepted signing nonce per agent
+    pub nonces: Arc<RwLock<auth::NonceTracker>>,
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        Self {
+            engine: Arc::new(RwLock::new(MatchingEngine::new())),
+            credited_deposits: Arc::new(RwLock::new(HashSet::new())),
+            nonces: Arc::new(RwLock::new(auth::NonceTracker::new())),
+        }
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[tokio::main]
@@ -38,9 +57,47 @@ async fn main() {
     ╚═══════════════════════════════════════════════════════╝
     "#);
 
-    let state = Arc::new(AppState {});
+    let state = Arc::new(AppState::new());
+    let app = create_router(state);
 
-    let app = Router::new()
+    let addr = "0.0.0.0:8080";
+    println!("🚀 API Server listening on {}", addr);
+    println!("📖 Docs: http://localhost:8080/docs");
+    
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
+}
+
+/// Resolve on Ctrl-C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("🛑 Shutdown signal received");
+}
+
+/// Build the API router over shared state
+pub fn create_router(state: Arc<AppState>) -> Router {
+    Router::new()
         // Health check
         .route("/health", get(health_check))
         
@@ -63,6 +120,7 @@ async fn main() {
         
         // Market data
         .route("/v1/markets", get(handlers::get_markets))
+        .route("/v1/markets/snapshot", get(handlers::get_markets_snapshot))
         .route("/v1/price/:market", get(handlers::get_price))
         .route("/v1/orderbook/:market", get(handlers::get_orderbook))
         .route("/v1/trades/:market", get(handlers::get_trades))
@@ -80,20 +138,526 @@ async fn main() {
         .route("/v1/skills/owned", get(handlers::get_owned_skills))
         
         .layer(CorsLayer::permissive())
-        .with_state(state);
-
-    let addr = "0.0.0.0:8080";
-    println!("🚀 API Server listening on {}", addr);
-    println!("📖 Docs: http://localhost:8080/docs");
-    
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+        .with_state(state)
 }
 
-async fn health_check() -> Json<serde_json::Value> {
-    Json(serde_json::json!({
-        "status": "ok",
+/// How long the health check waits for the engine lock; a writer stuck holding
+/// it shows up as an unresponsive component instead of hanging the probe
+const HEALTH_LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Liveness of the matching engine (which also holds balances and positions):
+/// `200` when its lock can be taken promptly, `503` with component status otherwise
+async fn health_check(State(state): State<Arc<AppState>>) -> (StatusCode, Json<serde_json::Value>) {
+    let (engine_ok, engine) = match tokio::time::timeout(HEALTH_LOCK_TIMEOUT, state.engine.read()).await {
+        Ok(engine) => (true, serde_json::json!({ "status": "ok", "markets": engine.markets().len() })),
+        Err(_) => (false, serde_json::json!({ "status": "unresponsive" })),
+    };
+    let status = if engine_ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(serde_json::json!({
+        "status": if engine_ok { "ok" } else { "degraded" },
         "version": "0.1.0",
-        "service": "ai-perp-dex"
-    }))
+        "service": "ai-perp-dex",
+        "components": {
+            "engine": engine,
+        }
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use ed25519_dalek::{Signer, SigningKey};
+    use rust_decimal::Decimal;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tower::ServiceExt;
+    
+    const MAKER: u8 = 1;
+    const TAKER: u8 = 2;
+    
+    static NONCE: AtomicU64 = AtomicU64::new(1);
+    
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+    
+    fn pubkey(seed: u8) -> String {
+        bs58::encode(signing_key(seed).verifying_key().as_bytes()).into_string()
+    }
+    
+    /// State where both agents have deposited through the API
+    async fn funded_state() -> Arc<AppState> {
+        let state = Arc::new(AppState::new());
+        for seed in [MAKER, TAKER] {
+            let (status, _) = send(
+                create_router(state.clone()),
+                deposit_request(seed, 100_000.0, &format!("funding-tx-{}", seed)),
+            ).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        state
+    }
+    
+    fn deposit_request(seed: u8, amount: f64, tx_signature: &str) -> Request<Body> {
+        let pubkey = pubkey(seed);
+        let nonce = NONCE.fetch_add(1, Ordering::SeqCst);
+        let message = auth::deposit_message(&pubkey, amount, tx_signature, nonce);
+        let signature = signing_key(seed).sign(message.as_bytes());
+        let body = serde_json::json!({
+            "pubkey": pubkey,
+            "amount": amount,
+            "tx_signature": tx_signature,
+            "nonce": nonce,
+            "signature": bs58::encode(signature.to_bytes()).into_string(),
+        });
+        Request::post("/v1/account/deposit")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+    
+    async fn send(app: Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+    
+    fn signed_order(seed: u8, side: &str, order_type: &str, price: Option<f64>, size_usd: f64) -> serde_json::Value {
+        signed_order_in(seed, "BTC-PERP", side, order_type, price, size_usd)
+    }
+    
+    fn signed_order_in(
+        seed: u8,
+        market: &str,
+        side: &str,
+        order_type: &str,
+        price: Option<f64>,
+        size_usd: f64,
+    ) -> serde_json::Value {
+        let pubkey = pubkey(seed);
+        let nonce = NONCE.fetch_add(1, Ordering::SeqCst);
+        let message = auth::order_message(&auth::OrderFields {
+            pubkey: &pubkey,
+            market,
+            side,
+            order_type,
+            size_usd,
+            leverage: 5,
+            price,
+            stop_price: None,
+            take_profit: None,
+            stop_loss: None,
+            client_order_id: None,
+            nonce,
+        });
+        let signature = signing_key(seed).sign(message.as_bytes());
+        
+        serde_json::json!({
+            "pubkey": pubkey,
+            "market": market,
+            "side": side,
+            "order_type": order_type,
+            "size_usd": size_usd,
+            "leverage": 5,
+            "price": price,
+            "nonce": nonce,
+            "signature": bs58::encode(signature.to_bytes()).into_string(),
+        })
+    }
+    
+    fn post_order(body: &serde_json::Value) -> Request<Body> {
+        Request::post("/v1/order")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+    
+    fn order_request(seed: u8, side: &str, order_type: &str, price: Option<f64>, size_usd: f64) -> Request<Body> {
+        post_order(&signed_order(seed, side, order_type, price, size_usd))
+    }
+    
+    #[tokio::test]
+    async fn test_get_orders_lists_open_orders_with_filters() {
+        let state = funded_state().await;
+        
+        let (_, btc) = send(
+            create_router(state.clone()),
+            order_request(MAKER, "long", "limit", Some(40000.0), 20000.0),
+        ).await;
+        let (_, eth) = send(
+            create_router(state.clone()),
+            post_order(&signed_order_in(MAKER, "ETH-PERP", "short", "limit", Some(4000.0), 8000.0)),
+        ).await;
+        // Someone else's order is not listed
+        send(create_router(state.clone()), order_request(TAKER, "long", "limit", Some(39000.0), 3900.0)).await;
+        
+        // `claimed` is the pubkey in the query, `signer` the key that signs
+        let list_as = |claimed: u8, signer: u8, query: &str| {
+            let nonce = NONCE.fetch_add(1, Ordering::SeqCst);
+            let message = auth::list_orders_message(&pubkey(claimed), nonce);
+            let signature = bs58::encode(signing_key(signer).sign(message.as_bytes()).to_bytes()).into_string();
+            Request::get(format!("/v1/orders?pubkey={}&nonce={}&signature={}{}", pubkey(claimed), nonce, signature, query))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let list = |query: &str| list_as(MAKER, MAKER, query);
+        
+        let (status, all) = send(create_router(state.clone()), list("")).await;
+        assert_eq!(status, StatusCode::OK);
+        let all = all.as_array().unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0]["order_id"], btc["order_id"]);
+        assert_eq!(all[0]["market"], "BTC-PERP");
+        assert_eq!(all[0]["side"], "long");
+        assert_eq!(all[0]["remaining_quantity"], 0.5);
+        assert_eq!(all[0]["size_usd"], 20000.0);
+        assert!(all[0]["created_at"].as_i64().unwrap() > 0);
+        assert_eq!(all[1]["order_id"], eth["order_id"]);
+        assert_eq!(all[1]["remaining_quantity"], 2.0);
+        
+        let (_, eth_only) = send(create_router(state.clone()), list("&market=ETH-PERP")).await;
+        let eth_only = eth_only.as_array().unwrap();
+        assert_eq!(eth_only.len(), 1);
+        assert_eq!(eth_only[0]["order_id"], eth["order_id"]);
+        
+        let (_, open) = send(create_router(state.clone()), list("&status=open")).await;
+        assert_eq!(open.as_array().unwrap().len(), 2);
+        let (_, filled) = send(create_router(state.clone()), list("&status=filled")).await;
+        assert!(filled.as_array().unwrap().is_empty());
+        
+        // Naming an agent without its key is not enough
+        let (status, _) = send(create_router(state.clone()), list_as(MAKER, TAKER, "")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(
+            create_router(state),
+            Request::get(format!("/v1/orders?pubkey={}", pubkey(MAKER))).body(Body::empty()).unwrap(),
+        ).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+    
+    #[tokio::test]
+    async fn test_submitted_order_appears_in_orderbook() {
+        let state = funded_state().await;
+        
+        let (status, body) = send(
+            create_router(state.clone()),
+            order_request(MAKER, "short", "limit", Some(50000.0), 25000.0),
+        ).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+        assert_eq!(body["status"], "open");
+        
+        let (status, book) = send(
+            create_router(state),
+            Request::get("/v1/orderbook/BTC-PERP").body(Body::empty()).unwrap(),
+        ).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(book["bids"].as_array().unwrap().len(), 0);
+        assert_eq!(book["asks"][0]["price"], 50000.0);
+        assert_eq!(book["asks"][0]["size"], 0.5);
+        assert_eq!(book["best_ask"], 50000.0);
+        assert!(book["best_bid"].is_null());
+        assert!(book["mid_price"].is_null());
+        assert!(book["spread"].is_null());
+    }
+    
+    #[tokio::test]
+    async fn test_markets_snapshot_covers_every_market() {
+        let state = funded_state().await;
+        // BTC: a fill at 50000 (0.2 BTC open interest) with 49000 / 50000 left on the book
+        for order in [
+            order_request(MAKER, "short", "limit", Some(50000.0), 25000.0),
+            order_request(MAKER, "long", "limit", Some(49000.0), 4900.0),
+            order_request(TAKER, "long", "market", None, 10000.0),
+        ] {
+            send(create_router(state.clone()), order).await;
+        }
+        // ETH: only a resting bid
+        send(
+            create_router(state.clone()),
+            post_order(&signed_order_in(MAKER, "ETH-PERP", "long", "limit", Some(3000.0), 3000.0)),
+        ).await;
+        
+        let (status, body) = send(
+            create_router(state.clone()),
+            Request::get("/v1/markets/snapshot").body(Body::empty()).unwrap(),
+        ).await;
+        assert_eq!(status, StatusCode::OK);
+        let markets = body["markets"].as_array().unwrap();
+        let listed: Vec<&str> = markets.iter().map(|m| m["market"].as_str().unwrap()).collect();
+        let expected: Vec<String> = state.engine.read().await.markets().iter().map(|m| m.to_string()).collect();
+        assert_eq!(listed, expected);
+        
+        let market = |symbol: &str| markets.iter().find(|m| m["market"] == symbol).unwrap();
+        let btc = market("BTC-PERP");
+        assert_eq!(btc["price"], 50000.0);
+        assert_eq!(btc["mark_price"], 49500.0);
+        assert_eq!(btc["best_bid"], 49000.0);
+        assert_eq!(btc["best_ask"], 50000.0);
+        assert_eq!(btc["spread"], 1000.0);
+        assert_eq!(btc["open_interest"], 0.2);
+        assert_eq!(btc["funding_rate"], 0.0);
+        // The book is only included when asked for
+        assert!(btc.get("bids").is_none());
+        
+        // One-sided and untraded: top of book but no price yet
+        let eth = market("ETH-PERP");
+        assert!(eth["price"].is_null());
+        assert_eq!(eth["best_bid"], 3000.0);
+        assert!(eth["best_ask"].is_null());
+        assert_eq!(eth["open_interest"], 0.0);
+        
+        let (_, body) = send(
+            create_router(state),
+            Request::get("/v1/markets/snapshot?depth=1").body(Body::empty()).unwrap(),
+        ).await;
+        let btc = body["markets"].as_array().unwrap().iter().find(|m| m["market"] == "BTC-PERP").unwrap().clone();
+        assert_eq!(btc["bids"], serde_json::json!([{ "price": 49000.0, "size": 0.1 }]));
+        assert_eq!(btc["asks"][0]["price"], 50000.0);
+        assert_eq!(btc["asks"].as_array().unwrap().len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_market_order_fills_and_moves_price() {
+        let state = funded_state().await;
+        send(
+            create_router(state.clone()),
+            order_request(MAKER, "short", "limit", Some(50000.0), 25000.0),
+        ).await;
+        
+        let (_, body) = send(
+            create_router(state.clone()),
+            order_request(TAKER, "long", "market", None, 10000.0),
+        ).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["status"], "filled");
+        assert_eq!(body["avg_price"], 50000.0);
+        assert_eq!(body["filled_size"], 10000.0);
+        
+        let (status, price) = send(
+            create_router(state),
+            Request::get("/v1/price/BTC-PERP").body(Body::empty()).unwrap(),
+        ).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(price["price"], 50000.0);
+    }
+    
+    #[tokio::test]
+    async fn test_cancel_order_removes_it_from_book() {
+        let state = funded_state().await;
+        let (_, body) = send(
+            create_router(state.clone()),
+            order_request(MAKER, "long", "limit", Some(49000.0), 4900.0),
+        ).await;
+        let order_id = body["order_id"].as_str().unwrap().to_string();
+        
+        let id: u64 = order_id.parse().unwrap();
+        // `claimed` is the pubkey in the query, `signer` the key that signs
+        let cancel = |claimed: u8, signer: u8| {
+            let nonce = NONCE.fetch_add(1, Ordering::SeqCst);
+            let message = auth::cancel_message(&pubkey(claimed), id, nonce);
+            let signature = bs58::encode(signing_key(signer).sign(message.as_bytes()).to_bytes()).into_string();
+            Request::delete(format!("/v1/order/{}?pubkey={}&nonce={}&signature={}", order_id, pubkey(claimed), nonce, signature))
+                .body(Body::empty())
+                .unwrap()
+        };
+        // Naming the owner without its key is not enough
+        let (status, _) = send(create_router(state.clone()), cancel(MAKER, TAKER)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(
+            create_router(state.clone()),
+            Request::delete(format!("/v1/order/{}?pubkey={}", order_id, pubkey(MAKER))).body(Body::empty()).unwrap(),
+        ).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        // A correctly signed cancel of someone else's order
+        let (status, _) = send(create_router(state.clone()), cancel(TAKER, TAKER)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(create_router(state.clone()), cancel(MAKER, MAKER)).await;
+        assert_eq!(status, StatusCode::OK);
+        
+        let (_, book) = send(
+            create_router(state),
+            Request::get("/v1/orderbook/BTC-PERP").body(Body::empty()).unwrap(),
+        ).await;
+        assert_eq!(book["bids"].as_array().unwrap().len(), 0);
+    }
+    
+    #[tokio::test]
+    async fn test_get_order_reports_state_to_owner_only() {
+        let state = funded_state().await;
+        let (_, resting) = send(
+            create_router(state.clone()),
+            order_request(MAKER, "short", "limit", Some(51000.0), 51000.0),
+        ).await;
+        let (_, partial) = send(
+            create_router(state.clone()),
+            order_request(MAKER, "long", "limit", Some(49000.0), 49000.0),
+        ).await;
+        send(create_router(state.clone()), order_request(TAKER, "short", "limit", Some(49000.0), 12250.0)).await;
+        
+        // `claimed` is the pubkey in the query, `signer` the key that signs
+        let get_as = |order_id: &serde_json::Value, claimed: u8, signer: u8| {
+            let order_id = order_id.as_str().unwrap();
+            let nonce = NONCE.fetch_add(1, Ordering::SeqCst);
+            let message = auth::get_order_message(&pubkey(claimed), order_id.parse().unwrap(), nonce);
+            let signature = bs58::encode(signing_key(signer).sign(message.as_bytes()).to_bytes()).into_string();
+            Request::get(format!("/v1/order/{}?pubkey={}&nonce={}&signature={}", order_id, pubkey(claimed), nonce, signature))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let get = |order_id: &serde_json::Value, seed: u8| get_as(order_id, seed, seed);
+        
+        let (status, order) = send(create_router(state.clone()), get(&resting["order_id"], MAKER)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(order["status"], "open");
+        assert_eq!(order["remaining_quantity"], 1.0);
+        assert_eq!(order["filled_size_usd"], 0.0);
+        assert!(order["avg_fill_price"].is_null());
+        
+        let (status, order) = send(create_router(state.clone()), get(&partial["order_id"], MAKER)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(order["status"], "partiallyfilled");
+        assert_eq!(order["remaining_quantity"], 0.75);
+        assert_eq!(order["filled_size_usd"], 12250.0);
+        assert_eq!(order["avg_fill_price"], 49000.0);
+        
+        // Naming the owner without its key is not enough
+        let (status, _) = send(create_router(state.clone()), get_as(&resting["order_id"], MAKER, TAKER)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(create_router(state.clone()), get(&resting["order_id"], TAKER)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(create_router(state), get(&serde_json::json!("999999"), MAKER)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+    
+    #[tokio::test]
+    async fn test_market_order_reports_unfilled_size_when_book_runs_out() {
+        let state = funded_state().await;
+        send(create_router(state.clone()), order_request(MAKER, "short", "limit", Some(50000.0), 10000.0)).await;
+        
+        let (status, body) = send(
+            create_router(state),
+            order_request(TAKER, "long", "market", None, 25000.0),
+        ).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "cancelled");
+        assert_eq!(body["filled_size"], 10000.0);
+        assert!(body["message"].as_str().unwrap().contains("$15000.00 unfilled"));
+    }
+    
+    #[tokio::test]
+    async fn test_unfunded_agent_is_rejected() {
+        let state = Arc::new(AppState::new());
+        let (status, body) = send(
+            create_router(state),
+            order_request(3, "long", "limit", Some(50000.0), 1000.0),
+        ).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], false);
+        assert_eq!(body["status"], "rejected");
+    }
+    
+    #[tokio::test]
+    async fn test_tampered_order_is_unauthorized() {
+        let state = funded_state().await;
+        let mut body = signed_order(MAKER, "long", "limit", Some(50000.0), 1000.0);
+        body["size_usd"] = serde_json::json!(50000.0);
+        
+        let (status, _) = send(create_router(state.clone()), post_order(&body)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        
+        let (_, book) = send(
+            create_router(state),
+            Request::get("/v1/orderbook/BTC-PERP").body(Body::empty()).unwrap(),
+        ).await;
+        assert_eq!(book["bids"].as_array().unwrap().len(), 0);
+    }
+    
+    #[tokio::test]
+    async fn test_deposit_funds_opening_orders() {
+        let state = Arc::new(AppState::new());
+        
+        // No balance yet: the margin check rejects the opening order
+        let (status, body) = send(
+            create_router(state.clone()),
+            order_request(MAKER, "long", "limit", Some(50000.0), 10000.0),
+        ).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], false);
+        assert!(body["message"].as_str().unwrap().contains("Insufficient margin"));
+        
+        let (status, body) = send(create_router(state.clone()), deposit_request(MAKER, 5000.0, "tx-1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+        assert_eq!(state.engine.read().await.get_balance(&pubkey(MAKER)).unwrap(), Decimal::from(5000));
+        
+        let (_, body) = send(
+            create_router(state.clone()),
+            order_request(MAKER, "long", "limit", Some(50000.0), 10000.0),
+        ).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["status"], "open");
+        
+        // The same transaction is credited once
+        let (status, _) = send(create_router(state.clone()), deposit_request(MAKER, 5000.0, "tx-1")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        // Only the key holder can credit its account
+        let mut forged: serde_json::Value = serde_json::json!({
+            "pubkey": pubkey(TAKER),
+            "amount": 1000.0,
+            "tx_signature": "tx-2",
+            "nonce": NONCE.fetch_add(1, Ordering::SeqCst),
+        });
+        let message = auth::deposit_message(&pubkey(TAKER), 1000.0, "tx-2", forged["nonce"].as_u64().unwrap());
+        forged["signature"] = bs58::encode(signing_key(MAKER).sign(message.as_bytes()).to_bytes()).into_string().into();
+        let (status, _) = send(
+            create_router(state.clone()),
+            Request::post("/v1/account/deposit")
+                .header("content-type", "application/json")
+                .body(Body::from(forged.to_string()))
+                .unwrap(),
+        ).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(state.engine.read().await.get_balance(&pubkey(TAKER)).unwrap(), Decimal::ZERO);
+    }
+    
+    #[tokio::test]
+    async fn test_replayed_order_is_unauthorized() {
+        let state = funded_state().await;
+        let body = signed_order(MAKER, "long", "limit", Some(50000.0), 1000.0);
+        
+        let (status, _) = send(create_router(state.clone()), post_order(&body)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(create_router(state), post_order(&body)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    
+    #[tokio::test]
+    async fn test_health_reports_components() {
+        let state = Arc::new(AppState::new());
+        let (status, body) = send(
+            create_router(state),
+            Request::get("/health").body(Body::empty()).unwrap(),
+        ).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["components"]["engine"]["status"], "ok");
+    }
+    
+    #[tokio::test]
+    async fn test_health_degraded_while_engine_locked() {
+        let state = Arc::new(AppState::new());
+        let _writer = state.engine.clone().write_owned().await;
+        
+        let (status, body) = send(
+            create_router(state),
+            Request::get("/health").body(Body::empty()).unwrap(),
+        ).await;
+        
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["components"]["engine"]["status"], "unresponsive");
+    }
 }
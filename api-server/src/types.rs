@@ -102,11 +102,15 @@ pub struct Order {
     pub market: String,
     pub side: OrderSide,
     pub order_type: OrderType,
+    /// Base-asset quantity
+    pub quantity: f64,
+    pub remaining_quantity: f64,
     pub size_usd: f64,
     pub filled_size_usd: f64,
     pub price: Option<f64>,
     pub avg_fill_price: Option<f64>,
-    pub leverage: u8,
+    /// Not tracked by the matching engine
+    pub leverage: Option<u8>,
     pub stop_price: Option<f64>,
     pub take_profit: Option<f64>,
     pub stop_loss: Option<f64>,
@@ -179,6 +183,37 @@ pub struct Orderbook {
     pub market: String,
     pub bids: Vec<OrderbookLevel>,
     pub asks: Vec<OrderbookLevel>,
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+    pub mid_price: Option<f64>,
+    pub spread: Option<f64>,
+    pub timestamp: i64,
+}
+
+/// One market's entry in the bulk market-data snapshot
+#[derive(Debug, Serialize)]
+pub struct MarketSnapshot {
+    pub market: String,
+    /// Last trade price, falling back to the mark price (None for an empty, untraded book)
+    pub price: Option<f64>,
+    pub index_price: Option<f64>,
+    pub mark_price: Option<f64>,
+    pub funding_rate: f64,
+    /// Total long size in base units (equal to the total short size)
+    pub open_interest: f64,
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+    pub spread: Option<f64>,
+    /// Top levels of the book, only when `?depth=` is given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bids: Option<Vec<OrderbookLevel>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asks: Option<Vec<OrderbookLevel>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MarketsSnapshot {
+    pub markets: Vec<MarketSnapshot>,
     pub timestamp: i64,
 }
 
@@ -0,0 +1,64 @@
+//! Matching throughput: N crossing takers against a pre-built book.
+//!
+//! The book and the takers are fixed, so every run matches the same trades.
+//! Run with `cargo bench --bench orderbook_benchmark`.
+
+use ai_perp_dex_matching_engine::{MarketId, Order, OrderBook, OrderId, Price, Quantity, Side, TimeInForce};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+const BASE_PRICE: f64 = 50_000.0;
+
+fn limit(id: u64, agent: &str, side: Side, price: f64, quantity: f64) -> Order {
+    Order::new_limit(
+        OrderId(id),
+        agent.to_string(),
+        MarketId::BtcPerp,
+        side,
+        Price::from_f64(price),
+        Quantity::from_f64(quantity),
+        TimeInForce::GTC,
+    )
+}
+
+/// `levels` ask levels a tick apart, each holding `per_level` makers
+fn resting_book(levels: usize, per_level: usize) -> OrderBook {
+    let mut book = OrderBook::new(MarketId::BtcPerp);
+    let mut id = 1;
+    for level in 0..levels {
+        for _ in 0..per_level {
+            book.place_order(limit(id, "maker", Side::Sell, BASE_PRICE + level as f64 * 0.1, 1.0));
+            id += 1;
+        }
+    }
+    book
+}
+
+fn bench_crossing_orders(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crossing_orders");
+    for n in [100usize, 1_000] {
+        group.throughput(Throughput::Elements(n as u64));
+        
+        // Each taker consumes one maker, so the two shapes trade the same
+        // volume: one maker per level, or ten makers per level
+        for (name, per_level) in [("single_order_levels", 1), ("multi_order_levels", 10)] {
+            let book = resting_book(n / per_level, per_level);
+            group.bench_with_input(BenchmarkId::new(name, n), &n, |b, &n| {
+                b.iter_batched(
+                    || book.clone(),
+                    |mut book| {
+                        for i in 0..n {
+                            let taker = limit(1_000_000 + i as u64, "taker", Side::Buy, BASE_PRICE * 1.01, 1.0);
+                            black_box(book.place_order(taker));
+                        }
+                        book
+                    },
+                    BatchSize::LargeInput,
+                );
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_crossing_orders);
+criterion_main!(benches);
@@ -52,8 +52,14 @@ impl Default for AgentRiskLimits {
     }
 }
 
+/// Trades after which the trust score is fully driven by performance
+pub const TRUST_FULL_CONFIDENCE_TRADES: f64 = 20.0;
+
+/// Trust score of an agent with no track record
+pub const NEUTRAL_TRUST_SCORE: u8 = 50;
+
 /// Agent reputation/performance metrics
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentReputation {
     /// Total number of trades
     pub total_trades: u64,
@@ -67,6 +73,39 @@ pub struct AgentReputation {
     pub sharpe_ratio: Option<f64>,
     /// Trust score (0-100)
     pub trust_score: u8,
+    /// Compounded per-trade returns, starting at 1
+    #[serde(default = "unit_index")]
+    pub equity_index: f64,
+    /// Highest `equity_index` reached so far
+    #[serde(default = "unit_index")]
+    pub peak_equity_index: f64,
+    /// Sum of per-trade returns (PnL over notional)
+    #[serde(default)]
+    pub sum_returns: f64,
+    /// Sum of squared per-trade returns
+    #[serde(default)]
+    pub sum_sq_returns: f64,
+}
+
+fn unit_index() -> f64 {
+    1.0
+}
+
+impl Default for AgentReputation {
+    fn default() -> Self {
+        Self {
+            total_trades: 0,
+            winning_trades: 0,
+            total_pnl_usd: 0.0,
+            max_drawdown_pct: 0.0,
+            sharpe_ratio: None,
+            trust_score: NEUTRAL_TRUST_SCORE,
+            equity_index: 1.0,
+            peak_equity_index: 1.0,
+            sum_returns: 0.0,
+            sum_sq_returns: 0.0,
+        }
+    }
 }
 
 impl AgentReputation {
@@ -77,6 +116,49 @@ impl AgentReputation {
             self.winning_trades as f64 / self.total_trades as f64
         }
     }
+    
+    /// Drawdown from the peak of the equity index, as a percentage
+    pub fn current_drawdown_pct(&self) -> f64 {
+        if self.peak_equity_index <= 0.0 {
+            return 0.0;
+        }
+        (1.0 - self.equity_index / self.peak_equity_index).max(0.0) * 100.0
+    }
+    
+    /// Fold in one closed trade and refresh the derived metrics
+    pub fn record_trade(&mut self, realized_pnl: f64, notional: f64) {
+        self.total_trades += 1;
+        if realized_pnl > 0.0 {
+            self.winning_trades += 1;
+        }
+        self.total_pnl_usd += realized_pnl;
+        
+        // A loss can at most wipe out the trade's notional
+        let ret = if notional > 0.0 { (realized_pnl / notional).max(-1.0) } else { 0.0 };
+        self.sum_returns += ret;
+        self.sum_sq_returns += ret * ret;
+        self.equity_index *= 1.0 + ret;
+        self.peak_equity_index = self.peak_equity_index.max(self.equity_index);
+        self.max_drawdown_pct = self.max_drawdown_pct.max(self.current_drawdown_pct());
+        
+        // Per-trade Sharpe, not annualized; undefined without dispersion
+        let n = self.total_trades as f64;
+        let mean = self.sum_returns / n;
+        let variance = (self.sum_sq_returns / n - mean * mean).max(0.0);
+        self.sharpe_ratio = (self.total_trades >= 2 && variance > 0.0).then(|| mean / variance.sqrt());
+        
+        self.trust_score = self.compute_trust_score();
+    }
+    
+    /// Heuristic trust score: neutral at first, then pulled up by win rate
+    /// and Sharpe and down by drawdown as the track record grows
+    fn compute_trust_score(&self) -> u8 {
+        let confidence = (self.total_trades as f64 / TRUST_FULL_CONFIDENCE_TRADES).min(1.0);
+        let edge = (self.win_rate() - 0.5) * 80.0
+            + self.sharpe_ratio.unwrap_or(0.0).clamp(-1.0, 1.0) * 20.0
+            - self.max_drawdown_pct.min(100.0) * 0.4;
+        (NEUTRAL_TRUST_SCORE as f64 + confidence * edge).clamp(0.0, 100.0).round() as u8
+    }
 }
 
 /// An AI agent in the system
@@ -173,6 +255,18 @@ impl AgentRegistry {
         Ok(())
     }
     
+    /// Record a closed trade in an agent's reputation
+    pub fn record_trade_result(
+        &mut self,
+        agent_id: &AgentId,
+        realized_pnl: f64,
+        notional: f64,
+    ) -> Result<&AgentReputation, String> {
+        let agent = self.agents.get_mut(agent_id).ok_or("Agent not found")?;
+        agent.reputation.record_trade(realized_pnl, notional);
+        Ok(&agent.reputation)
+    }
+    
     /// List all agents
     pub fn list(&self) -> Vec<&Agent> {
         self.agents.values().collect()
@@ -233,4 +327,37 @@ mod tests {
         rep.winning_trades = 60;
         assert_eq!(rep.win_rate(), 0.6);
     }
+    
+    #[test]
+    fn test_trade_results_update_reputation() {
+        let mut registry = AgentRegistry::new();
+        let id = AgentId::new("aria-001");
+        registry.register(Agent::new(id.clone(), "wallet".to_string(), "Aria".to_string())).unwrap();
+        assert!(registry.record_trade_result(&AgentId::new("ghost"), 10.0, 1000.0).is_err());
+        
+        // Four 1% wins: no drawdown yet and the score climbs above neutral
+        for _ in 0..4 {
+            registry.record_trade_result(&id, 10.0, 1000.0).unwrap();
+        }
+        let rep = registry.get(&id).unwrap().reputation.clone();
+        assert_eq!(rep.win_rate(), 1.0);
+        assert_eq!(rep.max_drawdown_pct, 0.0);
+        assert!(rep.trust_score > NEUTRAL_TRUST_SCORE);
+        let after_wins = rep.trust_score;
+        
+        // Two 5% losses: down ~9.75% from the peak, and the score falls back
+        registry.record_trade_result(&id, -50.0, 1000.0).unwrap();
+        let rep = registry.record_trade_result(&id, -50.0, 1000.0).unwrap();
+        assert_eq!(rep.total_trades, 6);
+        assert!((rep.win_rate() - 4.0 / 6.0).abs() < 1e-9);
+        assert!((rep.total_pnl_usd - -60.0).abs() < 1e-9);
+        assert!((rep.max_drawdown_pct - 9.75).abs() < 1e-9);
+        assert!(rep.sharpe_ratio.unwrap() < 0.0);
+        assert!(rep.trust_score < after_wins);
+        
+        // A recovery shrinks the current drawdown but keeps the max
+        let rep = registry.record_trade_result(&id, 50.0, 1000.0).unwrap();
+        assert!(rep.current_drawdown_pct() < 9.75);
+        assert!((rep.max_drawdown_pct - 9.75).abs() < 1e-9);
+    }
 }
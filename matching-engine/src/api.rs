@@ -1,32 +1,45 @@
 //! REST and WebSocket API for the matching engine
 
 use axum::{
-    extract::{Path, Query, State, ws::WebSocketUpgrade},
+    extract::{Path, Query, State, ws::{Message, WebSocket, WebSocketUpgrade}},
     response::{IntoResponse, Response},
     routing::{get, post, delete},
     Json, Router,
 };
+use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::sync::Arc;
-use crate::engine::MatchingEngine;
-use crate::order::{PlaceOrderRequest, CancelOrderRequest};
+use tokio::sync::broadcast;
+use crate::agent::AgentReputation;
+use crate::engine::{EngineError, EngineEvent, MatchingEngine, PlaceOrderResult};
+use crate::types::{MarketId, MarketConfig};
+use crate::order::{OcoRequest, OrderOutcome, PlaceOrderRequest, CancelOrderRequest, Side};
 
 /// API state
 pub struct ApiState {
     pub engine: Arc<MatchingEngine>,
+    /// Token required in `x-admin-token` for admin endpoints; `None` disables them
+    pub admin_token: Option<String>,
 }
 
 /// Create the API router
-pub fn create_router(engine: Arc<MatchingEngine>) -> Router {
-    let state = Arc::new(ApiState { engine });
+pub fn create_router(engine: Arc<MatchingEngine>, admin_token: Option<String>) -> Router {
+    let state = Arc::new(ApiState { engine, admin_token });
     
     Router::new()
         .route("/health", get(health_check))
-        .route("/markets", get(list_markets))
-        .route("/markets/{market}/orderbook", get(get_orderbook))
-        .route("/markets/{market}/bbo", get(get_bbo))
-        .route("/orders", post(place_order))
-        .route("/orders/{order_id}", delete(cancel_order))
+        .route("/markets", get(list_markets).post(add_market))
+        .route("/markets/:market/orderbook", get(get_orderbook))
+        .route("/markets/:market/bbo", get(get_bbo))
+        .route("/markets/:market/stats", get(get_market_stats))
+        .route("/markets/:market/impact", get(get_impact))
+        .route("/orders", post(place_order).delete(cancel_all_orders))
+        .route("/orders/batch", post(place_batch))
+        .route("/orders/oco", post(place_oco))
+        .route("/orders/simulate", post(simulate_order))
+        .route("/orders/:order_id", delete(cancel_order))
+        .route("/agents/:agent_id", get(get_agent))
         .route("/ws", get(websocket_handler))
         .with_state(state)
 }
@@ -49,14 +62,74 @@ async fn list_markets(
 ) -> Json<Vec<String>> {
     let markets: Vec<String> = state.engine.markets()
         .iter()
-        .map(|m| m.0.clone())
+        .map(|m| m.to_string())
         .collect();
     Json(markets)
 }
 
+#[derive(Deserialize)]
+struct AddMarketRequest {
+    market: MarketId,
+    tick_size: f64,
+    min_qty: f64,
+    max_qty: f64,
+    max_leverage: u32,
+    /// Limit price band around the mark in basis points; omitted disables it
+    price_band_bps: Option<u32>,
+}
+
+/// Reject requests without the configured `x-admin-token`
+fn reject_non_admin(state: &ApiState, headers: &axum::http::HeaderMap) -> Option<Response> {
+    let authorized = state.admin_token.as_deref().is_some_and(|token| {
+        headers.get("x-admin-token").and_then(|v| v.to_str().ok()) == Some(token)
+    });
+    (!authorized).then(|| (
+        axum::http::StatusCode::FORBIDDEN,
+        Json(serde_json::json!({"error": "Admin token required"}))
+    ).into_response())
+}
+
+/// List a new market (admin only)
+async fn add_market(
+    State(state): State<Arc<ApiState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<AddMarketRequest>,
+) -> Response {
+    if let Some(forbidden) = reject_non_admin(&state, &headers) {
+        return forbidden;
+    }
+    
+    let (Ok(tick_size), Ok(min_qty), Ok(max_qty)) = (
+        rust_decimal::Decimal::try_from(request.tick_size),
+        rust_decimal::Decimal::try_from(request.min_qty),
+        rust_decimal::Decimal::try_from(request.max_qty),
+    ) else {
+        return (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "Invalid tick size or order size limits"}))
+        ).into_response();
+    };
+    let market = request.market;
+    
+    let config = MarketConfig::new(tick_size, min_qty, max_qty, request.max_leverage)
+        .with_price_band(request.price_band_bps);
+    match state.engine.add_market(market, config) {
+        Ok(()) => (
+            axum::http::StatusCode::CREATED,
+            Json(serde_json::json!({"market": market}))
+        ).into_response(),
+        Err(e) => (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": e.to_string()}))
+        ).into_response(),
+    }
+}
+
 #[derive(Deserialize)]
 struct OrderbookParams {
     depth: Option<usize>,
+    /// Optional bucket size for aggregated depth
+    tick: Option<f64>,
 }
 
 async fn get_orderbook(
@@ -65,7 +138,15 @@ async fn get_orderbook(
     Query(params): Query<OrderbookParams>,
 ) -> Response {
     let depth = params.depth.unwrap_or(20);
-    match state.engine.get_orderbook(&market, depth) {
+    let result = match params.tick {
+        Some(tick) => state.engine.get_aggregated_orderbook(
+            &market,
+            rust_decimal::Decimal::try_from(tick).unwrap_or_default(),
+            depth,
+        ),
+        None => state.engine.get_orderbook(&market, depth),
+    };
+    match result {
         Ok(snapshot) => Json(serde_json::to_value(snapshot).unwrap()).into_response(),
         Err(e) => (
             axum::http::StatusCode::NOT_FOUND,
@@ -74,6 +155,39 @@ async fn get_orderbook(
     }
 }
 
+async fn get_market_stats(
+    State(state): State<Arc<ApiState>>,
+    Path(market): Path<String>,
+) -> Response {
+    match state.engine.get_market_stats(&market) {
+        Ok(stats) => Json(serde_json::to_value(stats).unwrap()).into_response(),
+        Err(e) => (
+            axum::http::StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": e.to_string()}))
+        ).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ImpactParams {
+    side: Side,
+    qty: f64,
+}
+
+async fn get_impact(
+    State(state): State<Arc<ApiState>>,
+    Path(market): Path<String>,
+    Query(params): Query<ImpactParams>,
+) -> Response {
+    match state.engine.estimate_fill(&market, params.side, params.qty) {
+        Ok(estimate) => Json(serde_json::to_value(estimate).unwrap()).into_response(),
+        Err(e) => (
+            axum::http::StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": e.to_string()}))
+        ).into_response(),
+    }
+}
+
 #[derive(Serialize)]
 struct BboResponse {
     market: String,
@@ -106,29 +220,133 @@ async fn get_bbo(
     }
 }
 
+/// Public view of an agent: identity and track record, no keys or limits
+#[derive(Serialize)]
+struct AgentInfoResponse {
+    agent_id: String,
+    name: String,
+    is_verified: bool,
+    registered_at: u64,
+    win_rate: f64,
+    current_drawdown_pct: f64,
+    reputation: AgentReputation,
+}
+
+async fn get_agent(
+    State(state): State<Arc<ApiState>>,
+    Path(agent_id): Path<String>,
+) -> Response {
+    match state.engine.get_agent(&agent_id) {
+        Some(agent) => Json(AgentInfoResponse {
+            agent_id: agent.id.0,
+            name: agent.metadata.name,
+            is_verified: agent.is_verified,
+            registered_at: agent.registered_at,
+            win_rate: agent.reputation.win_rate(),
+            current_drawdown_pct: agent.reputation.current_drawdown_pct(),
+            reputation: agent.reputation,
+        }).into_response(),
+        None => (
+            axum::http::StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": EngineError::AgentNotFound(agent_id).to_string()}))
+        ).into_response(),
+    }
+}
+
 #[derive(Serialize)]
 struct PlaceOrderResponse {
     order_id: String,
     status: String,
+    filled_quantity: String,
+    average_price: Option<String>,
+    outcome: OrderOutcome,
     trades: Vec<serde_json::Value>,
+    /// The agent's resting orders cancelled by self-trade prevention
+    self_trade_cancelled: Vec<String>,
 }
 
+/// Place an order. An `Idempotency-Key` header, or failing that the
+/// request's `client_order_id`, makes retries return the original result.
 async fn place_order(
     State(state): State<Arc<ApiState>>,
+    headers: axum::http::HeaderMap,
     Json(request): Json<PlaceOrderRequest>,
 ) -> Response {
-    match state.engine.place_order(request) {
-        Ok((order, trades)) => {
-            let trades_json: Vec<serde_json::Value> = trades
-                .iter()
-                .map(|t| serde_json::to_value(t).unwrap())
+    let key = headers
+        .get("idempotency-key")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| request.client_order_id.clone());
+    
+    let result = match key {
+        Some(key) => state.engine.place_order_idempotent(&key, request),
+        None => state.engine.place_order(request).map(|result| (result, false)),
+    };
+    match result {
+        Ok((result, replayed)) => (
+            [("idempotent-replayed", replayed.to_string())],
+            Json(place_order_response(result)),
+        ).into_response(),
+        Err(e @ EngineError::IdempotencyConflict(_)) => (
+            axum::http::StatusCode::CONFLICT,
+            Json(serde_json::json!({"error": e.to_string()}))
+        ).into_response(),
+        Err(e) => (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": e.to_string()}))
+        ).into_response(),
+    }
+}
+
+fn place_order_response((order, trades, outcome, stp_cancelled): PlaceOrderResult) -> PlaceOrderResponse {
+    let trades_json: Vec<serde_json::Value> = trades
+        .iter()
+        .map(|t| serde_json::to_value(t).unwrap())
+        .collect();
+    
+    // Fills of this order only, not stops it may have triggered
+    let own_fills = trades.iter().filter(|t| t.taker_order_id == order.id);
+    let filled: rust_decimal::Decimal = own_fills.clone()
+        .map(|t| t.quantity.as_decimal())
+        .sum();
+    let notional: rust_decimal::Decimal = own_fills
+        .map(|t| t.quantity.as_decimal() * t.price.as_decimal())
+        .sum();
+    let average_price = (!filled.is_zero()).then(|| format!("{}", notional / filled));
+    
+    PlaceOrderResponse {
+        order_id: format!("{}", order.id),
+        status: format!("{:?}", order.status),
+        filled_quantity: format!("{}", filled),
+        average_price,
+        outcome,
+        trades: trades_json,
+        self_trade_cancelled: stp_cancelled.iter().map(|o| format!("{}", o.id)).collect(),
+    }
+}
+
+#[derive(Deserialize)]
+struct BatchOrderRequest {
+    orders: Vec<PlaceOrderRequest>,
+    /// Reject the whole batch if any order fails validation
+    #[serde(default)]
+    atomic: bool,
+}
+
+async fn place_batch(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<BatchOrderRequest>,
+) -> Response {
+    match state.engine.place_batch(request.orders, request.atomic) {
+        Ok(results) => {
+            let results: Vec<serde_json::Value> = results
+                .into_iter()
+                .map(|result| match result {
+                    Ok(result) => serde_json::to_value(place_order_response(result)).unwrap(),
+                    Err(e) => serde_json::json!({"error": e.to_string()}),
+                })
                 .collect();
-            
-            Json(PlaceOrderResponse {
-                order_id: format!("{}", order.id),
-                status: format!("{:?}", order.status),
-                trades: trades_json,
-            }).into_response()
+            Json(serde_json::json!({"results": results})).into_response()
         }
         Err(e) => (
             axum::http::StatusCode::BAD_REQUEST,
@@ -137,6 +355,42 @@ async fn place_order(
     }
 }
 
+#[derive(Serialize)]
+struct OcoResponse {
+    primary: PlaceOrderResponse,
+    secondary: PlaceOrderResponse,
+}
+
+async fn place_oco(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<OcoRequest>,
+) -> Response {
+    match state.engine.place_oco(request) {
+        Ok((primary, secondary)) => Json(OcoResponse {
+            primary: place_order_response(primary),
+            secondary: place_order_response(secondary),
+        }).into_response(),
+        Err(e) => (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": e.to_string()}))
+        ).into_response(),
+    }
+}
+
+/// Dry-run an order against the live book without placing it
+async fn simulate_order(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<PlaceOrderRequest>,
+) -> Response {
+    match state.engine.simulate_order(request) {
+        Ok(result) => Json(place_order_response(result)).into_response(),
+        Err(e) => (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": e.to_string()}))
+        ).into_response(),
+    }
+}
+
 #[derive(Serialize)]
 struct CancelOrderResponse {
     order_id: String,
@@ -164,21 +418,340 @@ async fn cancel_order(
     }
 }
 
+#[derive(Deserialize)]
+struct CancelAllParams {
+    agent_id: String,
+    market: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CancelAllResponse {
+    cancelled: Vec<String>,
+}
+
+/// Cancel all of an agent's orders (admin only)
+async fn cancel_all_orders(
+    State(state): State<Arc<ApiState>>,
+    headers: axum::http::HeaderMap,
+    Query(params): Query<CancelAllParams>,
+) -> Response {
+    if let Some(forbidden) = reject_non_admin(&state, &headers) {
+        return forbidden;
+    }
+    
+    let result = match &params.market {
+        Some(market) => state.engine.cancel_all_in_market(&params.agent_id, market),
+        None => state.engine.cancel_all(&params.agent_id),
+    };
+    
+    match result {
+        Ok(orders) => Json(CancelAllResponse {
+            cancelled: orders.iter().map(|o| format!("{}", o.id)).collect(),
+        }).into_response(),
+        Err(e) => (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": e.to_string()}))
+        ).into_response(),
+    }
+}
+
+/// Client → server WebSocket messages
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe { market: String },
+    Unsubscribe { market: String },
+}
+
 async fn websocket_handler(
     ws: WebSocketUpgrade,
+    State(state): State<Arc<ApiState>>,
 ) -> Response {
-    ws.on_upgrade(|_socket| async move {
-        // TODO: Implement WebSocket handling
-    })
+    // Subscribe before the upgrade so no event between handshake and first poll is lost
+    let events = state.engine.subscribe();
+    ws.on_upgrade(move |socket| handle_socket(socket, state, events))
+}
+
+/// Forward engine events for the markets the client subscribed to
+async fn handle_socket(
+    socket: WebSocket,
+    state: Arc<ApiState>,
+    mut events: broadcast::Receiver<EngineEvent>,
+) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut markets: HashSet<MarketId> = HashSet::new();
+    
+    loop {
+        let frame = tokio::select! {
+            incoming = receiver.next() => match incoming {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Subscribe { market }) => {
+                        match market.parse::<MarketId>() {
+                            Ok(m) if state.engine.has_market(&m) => {
+                                markets.insert(m);
+                                serde_json::json!({"type": "subscribed", "market": m})
+                            }
+                            _ => serde_json::json!({"type": "error", "message": format!("Market not found: {}", market)}),
+                        }
+                    }
+                    Ok(ClientMessage::Unsubscribe { market }) => {
+                        if let Ok(m) = market.parse::<MarketId>() {
+                            markets.remove(&m);
+                        }
+                        serde_json::json!({"type": "unsubscribed", "market": market})
+                    }
+                    Err(e) => serde_json::json!({"type": "error", "message": format!("Invalid message: {}", e)}),
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = events.recv() => match event {
+                Ok(event) if markets.contains(event.market()) => match serde_json::to_value(&event) {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                },
+                Ok(_) => continue,
+                // Slow client: tell it how many events were dropped; sequence gaps show where
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    serde_json::json!({"type": "lagged", "skipped": skipped})
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        
+        if sender.send(Message::Text(frame.to_string())).await.is_err() {
+            break;
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     
+    use crate::order::{OrderType, TimeInForce};
+    use tokio_tungstenite::tungstenite::Message as WsFrame;
+    
     #[tokio::test]
     async fn test_health_check() {
         let response = health_check().await;
         assert_eq!(response.0.status, "healthy");
     }
+    
+    fn limit(agent: &str, side: Side, price: f64) -> PlaceOrderRequest {
+        PlaceOrderRequest {
+            agent_id: agent.to_string(),
+            market: "BTC-PERP".to_string(),
+            side,
+            order_type: OrderType::Limit,
+            price: Some(price),
+            quantity: 1.0,
+            time_in_force: Some(TimeInForce::GTC),
+            stop_price: None,
+            reduce_only: None,
+            client_order_id: None,
+            self_trade_policy: None,
+            max_slippage_bps: None,
+            round: None,
+            fallback_limit: None,
+            display_qty: None,
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_idempotency_key_header_dedupes_orders() {
+        let engine = Arc::new(MatchingEngine::new());
+        let state = Arc::new(ApiState { engine: engine.clone(), admin_token: None });
+        let submit = |price: f64| {
+            let mut headers = axum::http::HeaderMap::new();
+            headers.insert("idempotency-key", "order-42".parse().unwrap());
+            place_order(State(state.clone()), headers, Json(limit("agent", Side::Buy, price)))
+        };
+        
+        let first = submit(50000.0).await;
+        assert_eq!(first.status(), axum::http::StatusCode::OK);
+        assert_eq!(first.headers()["idempotent-replayed"], "false");
+        
+        let retry = submit(50000.0).await;
+        assert_eq!(retry.status(), axum::http::StatusCode::OK);
+        assert_eq!(retry.headers()["idempotent-replayed"], "true");
+        
+        assert_eq!(submit(50100.0).await.status(), axum::http::StatusCode::CONFLICT);
+        assert_eq!(engine.open_orders("agent").unwrap().len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_agent_info_requires_registered_agent() {
+        let engine = Arc::new(MatchingEngine::new());
+        let state = Arc::new(ApiState { engine: engine.clone(), admin_token: None });
+        let agent = crate::agent::Agent::new(
+            crate::agent::AgentId::new("aria-001"),
+            "wallet".to_string(),
+            "Aria".to_string(),
+        );
+        engine.register_agent(agent).unwrap();
+        
+        let found = get_agent(State(state.clone()), Path("aria-001".to_string())).await;
+        assert_eq!(found.status(), axum::http::StatusCode::OK);
+        let missing = get_agent(State(state.clone()), Path("ghost".to_string())).await;
+        assert_eq!(missing.status(), axum::http::StatusCode::NOT_FOUND);
+    }
+    
+    #[tokio::test]
+    async fn test_add_market_requires_admin_token() {
+        let engine = Arc::new(MatchingEngine::new());
+        let state = Arc::new(ApiState { engine: engine.clone(), admin_token: Some("secret".to_string()) });
+        let add = |token: Option<&str>| {
+            let mut headers = axum::http::HeaderMap::new();
+            if let Some(token) = token {
+                headers.insert("x-admin-token", token.parse().unwrap());
+            }
+            let request = AddMarketRequest {
+                market: MarketId::LinkPerp,
+                tick_size: 0.001,
+                min_qty: 0.1,
+                max_qty: 100_000.0,
+                max_leverage: 20,
+                price_band_bps: Some(500),
+            };
+            add_market(State(state.clone()), headers, Json(request))
+        };
+        
+        assert_eq!(add(None).await.status(), axum::http::StatusCode::FORBIDDEN);
+        assert_eq!(add(Some("wrong")).await.status(), axum::http::StatusCode::FORBIDDEN);
+        assert!(!engine.has_market(&MarketId::LinkPerp));
+        
+        assert_eq!(add(Some("secret")).await.status(), axum::http::StatusCode::CREATED);
+        assert!(engine.has_market(&MarketId::LinkPerp));
+        let markets = list_markets(State(state.clone())).await;
+        assert!(markets.0.contains(&"LINK-PERP".to_string()));
+        assert_eq!(engine.market_config(&MarketId::LinkPerp).unwrap().price_band_bps, Some(500));
+        
+        // Listing it twice is rejected
+        assert_eq!(add(Some("secret")).await.status(), axum::http::StatusCode::BAD_REQUEST);
+    }
+    
+    #[tokio::test]
+    async fn test_cancel_all_requires_admin_token() {
+        let engine = Arc::new(MatchingEngine::new());
+        engine.place_order(limit("aria-001", Side::Buy, 49000.0)).unwrap();
+        let state = Arc::new(ApiState { engine: engine.clone(), admin_token: Some("secret".to_string()) });
+        let cancel_all = |token: Option<&str>| {
+            let mut headers = axum::http::HeaderMap::new();
+            if let Some(token) = token {
+                headers.insert("x-admin-token", token.parse().unwrap());
+            }
+            let params = CancelAllParams { agent_id: "aria-001".to_string(), market: None };
+            cancel_all_orders(State(state.clone()), headers, Query(params))
+        };
+        
+        assert_eq!(cancel_all(None).await.status(), axum::http::StatusCode::FORBIDDEN);
+        assert_eq!(cancel_all(Some("wrong")).await.status(), axum::http::StatusCode::FORBIDDEN);
+        assert_eq!(engine.open_orders("aria-001").unwrap().len(), 1);
+        
+        assert_eq!(cancel_all(Some("secret")).await.status(), axum::http::StatusCode::OK);
+        assert!(engine.open_orders("aria-001").unwrap().is_empty());
+    }
+    
+    /// GET `uri` through the full router
+    async fn get_json(engine: Arc<MatchingEngine>, uri: &str) -> (axum::http::StatusCode, serde_json::Value) {
+        use tower::ServiceExt;
+        
+        let request = axum::http::Request::get(uri).body(axum::body::Body::empty()).unwrap();
+        let response = create_router(engine, None).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null))
+    }
+    
+    #[tokio::test]
+    async fn test_bbo_and_orderbook_routes_resolve_market() {
+        let engine = Arc::new(MatchingEngine::new());
+        engine.place_order(limit("maker", Side::Buy, 49000.0)).unwrap();
+        engine.place_order(limit("maker", Side::Sell, 51000.0)).unwrap();
+        
+        let (status, bbo) = get_json(engine.clone(), "/markets/BTC-PERP/bbo").await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        assert_eq!(bbo["market"], "BTC-PERP");
+        assert_eq!(bbo["best_bid"], "49000");
+        assert_eq!(bbo["best_ask"], "51000");
+        
+        let (status, _) = get_json(engine.clone(), "/markets/BTC-PERP/orderbook?depth=5").await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        let (status, _) = get_json(engine, "/markets/NOPE-PERP/bbo").await;
+        assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+    }
+    
+    #[tokio::test]
+    async fn test_stats_route_resolves_market() {
+        let engine = Arc::new(MatchingEngine::new());
+        engine.place_order(limit("maker", Side::Sell, 50000.0)).unwrap();
+        engine.place_order(limit("taker", Side::Buy, 50000.0)).unwrap();
+        
+        let (status, stats) = get_json(engine.clone(), "/markets/BTC-PERP/stats").await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        assert_eq!(stats["trade_count_24h"], 1);
+        let (status, _) = get_json(engine, "/markets/NOPE-PERP/stats").await;
+        assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+    }
+    
+    #[tokio::test]
+    async fn test_impact_route_estimates_fill() {
+        let engine = Arc::new(MatchingEngine::new());
+        engine.place_order(limit("maker", Side::Sell, 50000.0)).unwrap();
+        
+        let (status, estimate) = get_json(engine.clone(), "/markets/BTC-PERP/impact?side=buy&qty=0.5").await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        assert_eq!(estimate["levels_consumed"], 1);
+        assert_eq!(estimate["fully_filled"], true);
+        let (status, _) = get_json(engine, "/markets/NOPE-PERP/impact?side=buy&qty=0.5").await;
+        assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+    }
+    
+    type Client = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+    
+    async fn next_json(ws: &mut Client) -> serde_json::Value {
+        loop {
+            let frame = tokio::time::timeout(std::time::Duration::from_secs(5), ws.next())
+                .await
+                .expect("timed out waiting for frame")
+                .unwrap()
+                .unwrap();
+            if let WsFrame::Text(text) = frame {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_websocket_delivers_trade_to_subscriber() {
+        let engine = Arc::new(MatchingEngine::new());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = create_router(engine.clone(), None);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        ws.send(WsFrame::Text(r#"{"op":"subscribe","market":"BTC-PERP"}"#.to_string())).await.unwrap();
+        
+        assert_eq!(next_json(&mut ws).await["type"], "subscribed");
+        
+        engine.place_order(limit("maker", Side::Sell, 50000.0)).unwrap();
+        engine.place_order(limit("taker", Side::Buy, 50000.0)).unwrap();
+        
+        let mut last_seq = 0;
+        let trade = loop {
+            let frame = next_json(&mut ws).await;
+            let seq = frame["seq"].as_u64().unwrap();
+            assert_eq!(seq, last_seq + 1);
+            last_seq = seq;
+            if frame["type"] == "trade" {
+                break frame;
+            }
+        };
+        assert_eq!(trade["trade"]["market"], "BTC-PERP");
+        assert_eq!(trade["trade"]["taker_agent_id"], "taker");
+    }
 }
@@ -1,13 +1,20 @@
 //! Matching Engine - orchestrates multiple orderbooks
 
-use crate::agent::{AgentId, AgentRegistry};
-use crate::order::{Order, PlaceOrderRequest, CancelOrderRequest, Side, OrderType, TimeInForce};
-use crate::orderbook::OrderBook;
-use crate::types::{Market, OrderId, Price, Quantity, Trade};
-use std::collections::HashMap;
+use crate::agent::{Agent, AgentId, AgentRegistry, AgentRiskLimits};
+use crate::order::{AmendOrderRequest, CancelReason, OcoRequest, Order, OrderOutcome, OrderStatus, PlaceOrderRequest, CancelOrderRequest, OrderType, RungSpec, Side, TimeInForce};
+use crate::orderbook::{BookState, OrderBook};
+use crate::risk::{ClosedFill, Position, RiskEngine};
+use crate::types::{FeeSchedule, FillEstimate, MarketId, MarketConfig, MarketStats, OrderBookSnapshot, OrderId, OrderRemovalCounts, Price, Quantity, Timestamp, Trade};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::RwLock;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
 use thiserror::Error;
+use tokio::sync::broadcast;
 
 #[derive(Error, Debug)]
 pub enum EngineError {
@@ -19,49 +26,310 @@ pub enum EngineError {
     OrderNotFound(u64),
     #[error("Invalid order: {0}")]
     InvalidOrder(String),
+    #[error("Invalid market: {0}")]
+    InvalidMarket(String),
+    #[error("Invalid agent: {0}")]
+    InvalidAgent(String),
+    #[error("Price out of band: {0}")]
+    PriceOutOfBand(String),
     #[error("Risk limit exceeded: {0}")]
     RiskLimitExceeded(String),
+    #[error("Idempotency key reused with a different request: {0}")]
+    IdempotencyConflict(String),
     #[error("Internal error: {0}")]
     InternalError(String),
 }
 
+/// The order as it stands after matching, its trades, what happened to it,
+/// and the agent's resting orders that self-trade prevention cancelled
+pub type PlaceOrderResult = (Order, Vec<Trade>, OrderOutcome, Vec<Order>);
+
+/// Net changes made by `cancel_replace_batch`
+#[derive(Debug, Clone, Default)]
+pub struct LadderUpdate {
+    /// Rungs already resting at the desired size, left in place
+    pub unchanged: Vec<OrderId>,
+    /// Resting orders no longer in the ladder
+    pub cancelled: Vec<Order>,
+    /// Rungs resized in place; a reduction keeps queue priority
+    pub amended: Vec<Order>,
+    /// Rungs that were not resting before
+    pub placed: Vec<Order>,
+    /// Trades from amended or placed rungs that crossed the book
+    pub trades: Vec<Trade>,
+}
+
+/// Default limit price band around the mark price (10%)
+pub const DEFAULT_PRICE_BAND_BPS: u32 = 1_000;
+
+/// Depth of the book snapshots published after each change
+pub const EVENT_SNAPSHOT_DEPTH: usize = 20;
+
+/// How long an idempotency key is remembered after its order was placed
+pub const IDEMPOTENCY_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Most idempotency keys remembered at once; the oldest are dropped first
+pub const IDEMPOTENCY_CAPACITY: usize = 10_000;
+
+/// Events published by the engine to subscribers such as WebSocket feeds.
+///
+/// `seq` counts up by one per market, so a subscriber to a market can
+/// detect missed events from a gap in the sequence.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EngineEvent {
+    /// A GTD order reached its expiry and was removed from the book
+    OrderExpired { seq: u64, order: Order },
+    /// A resting order was cancelled by the engine rather than its owner
+    OrderCancelled { seq: u64, order: Order, reason: CancelReason },
+    /// A fill between a maker and a taker order
+    Trade { seq: u64, trade: Trade },
+    /// Top of the book after it changed
+    OrderBookSnapshot { seq: u64, snapshot: OrderBookSnapshot },
+}
+
+impl EngineEvent {
+    /// Market the event belongs to
+    pub fn market(&self) -> &MarketId {
+        match self {
+            EngineEvent::OrderExpired { order, .. }
+            | EngineEvent::OrderCancelled { order, .. } => &order.market,
+            EngineEvent::Trade { trade, .. } => &trade.market,
+            EngineEvent::OrderBookSnapshot { snapshot, .. } => &snapshot.market,
+        }
+    }
+    
+    /// Per-market sequence number
+    pub fn seq(&self) -> u64 {
+        match self {
+            EngineEvent::OrderExpired { seq, .. }
+            | EngineEvent::OrderCancelled { seq, .. }
+            | EngineEvent::Trade { seq, .. }
+            | EngineEvent::OrderBookSnapshot { seq, .. } => *seq,
+        }
+    }
+}
+
+/// Results of recently placed orders by (agent, idempotency key), so a
+/// retried submission gets the original result instead of a second order
+struct IdempotencyCache {
+    entries: HashMap<(String, String), IdempotencyEntry>,
+    /// Keys in insertion order, for expiry and eviction
+    order: VecDeque<((String, String), Instant)>,
+}
+
+struct IdempotencyEntry {
+    /// The request as first submitted, to detect a key reused for another order
+    request: String,
+    result: PlaceOrderResult,
+    placed_at: Instant,
+}
+
+impl IdempotencyCache {
+    fn new() -> Self {
+        Self { entries: HashMap::new(), order: VecDeque::new() }
+    }
+    
+    /// Drop expired keys, then the oldest ones while over capacity
+    fn evict(&mut self, now: Instant) {
+        while let Some((key, placed_at)) = self.order.front() {
+            let expired = now.saturating_duration_since(*placed_at) >= IDEMPOTENCY_TTL;
+            if !expired && self.entries.len() < IDEMPOTENCY_CAPACITY {
+                break;
+            }
+            // Only remove the entry this queue slot was recorded for
+            if self.entries.get(key).is_some_and(|e| e.placed_at == *placed_at) {
+                self.entries.remove(key);
+            }
+            self.order.pop_front();
+        }
+    }
+}
+
 /// The main matching engine
 pub struct MatchingEngine {
     /// Orderbooks by market
-    orderbooks: RwLock<HashMap<Market, OrderBook>>,
+    orderbooks: RwLock<HashMap<MarketId, OrderBook>>,
     /// Agent registry
+    #[allow(dead_code)]
     agents: RwLock<AgentRegistry>,
+    /// Positions and balances
+    risk: RwLock<RiskEngine>,
+    /// Resting order IDs by agent
+    agent_orders: RwLock<HashMap<String, HashSet<OrderId>>>,
+    /// Engine event broadcast channel
+    events: broadcast::Sender<EngineEvent>,
+    /// Last event sequence number by market
+    event_seqs: Mutex<HashMap<MarketId, u64>>,
     /// Order ID counter
     order_counter: AtomicU64,
     /// Supported markets
-    markets: Vec<Market>,
+    markets: RwLock<Vec<MarketId>>,
+    /// Trading parameters by market
+    market_configs: RwLock<HashMap<MarketId, MarketConfig>>,
+    /// Fee schedule applied to new books
+    fees: FeeSchedule,
+    /// Recently used idempotency keys
+    idempotency: Mutex<IdempotencyCache>,
+    /// Each open OCO leg's sibling, both directions
+    oco_links: Mutex<HashMap<OrderId, OrderId>>,
 }
 
 impl MatchingEngine {
-    /// Create a new matching engine
+    /// Create a new matching engine with the default fee schedule
     pub fn new() -> Self {
-        let markets = vec![
-            Market::btc_perp(),
-            Market::eth_perp(),
-            Market::sol_perp(),
-        ];
-        
-        let mut orderbooks = HashMap::new();
-        for market in &markets {
-            orderbooks.insert(market.clone(), OrderBook::new(market.clone()));
-        }
+        Self::with_fee_schedule(FeeSchedule::default())
+    }
+    
+    /// Create a new matching engine charging the given fees
+    pub fn with_fee_schedule(fees: FeeSchedule) -> Self {
+        let (events, _) = broadcast::channel(1000);
         
-        Self {
-            orderbooks: RwLock::new(orderbooks),
+        let engine = Self {
+            orderbooks: RwLock::new(HashMap::new()),
             agents: RwLock::new(AgentRegistry::new()),
+            risk: RwLock::new(RiskEngine::new()),
+            agent_orders: RwLock::new(HashMap::new()),
+            events,
+            event_seqs: Mutex::new(HashMap::new()),
             order_counter: AtomicU64::new(1),
-            markets,
+            markets: RwLock::new(Vec::new()),
+            market_configs: RwLock::new(HashMap::new()),
+            fees,
+            idempotency: Mutex::new(IdempotencyCache::new()),
+            oco_links: Mutex::new(HashMap::new()),
+        };
+        
+        let defaults = [
+            (MarketId::BtcPerp, MarketConfig::new(Decimal::new(1, 1), Decimal::new(1, 4), Decimal::from(1_000), 50)),
+            (MarketId::EthPerp, MarketConfig::new(Decimal::new(1, 2), Decimal::new(1, 3), Decimal::from(10_000), 50)),
+            (MarketId::SolPerp, MarketConfig::new(Decimal::new(1, 3), Decimal::new(1, 2), Decimal::from(1_000_000), 30)),
+        ];
+        for (market, config) in defaults {
+            let config = config.with_price_band(Some(DEFAULT_PRICE_BAND_BPS));
+            engine.add_market(market, config).expect("default markets are valid");
         }
+        
+        engine
     }
     
     /// Get supported markets
-    pub fn markets(&self) -> &[Market] {
-        &self.markets
+    pub fn markets(&self) -> Vec<MarketId> {
+        self.markets.read().map(|m| m.clone()).unwrap_or_default()
+    }
+    
+    /// Check whether a market is listed
+    pub fn has_market(&self, market: &MarketId) -> bool {
+        self.markets.read().map(|m| m.contains(market)).unwrap_or(false)
+    }
+    
+    /// Get a market's trading parameters
+    pub fn market_config(&self, market: &MarketId) -> Option<MarketConfig> {
+        let market_configs = self.market_configs.read().ok()?;
+        market_configs.get(market).copied()
+    }
+    
+    /// List a new market with an empty book
+    pub fn add_market(&self, market: MarketId, config: MarketConfig) -> Result<(), EngineError> {
+        if config.tick_size <= Decimal::ZERO
+            || config.min_qty <= Decimal::ZERO
+            || config.max_qty < config.min_qty
+            || config.max_leverage == 0
+        {
+            return Err(EngineError::InvalidMarket(format!(
+                "{}: tick size, order size limits and max leverage must be positive",
+                market
+            )));
+        }
+        
+        let mut orderbooks = self.orderbooks.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let mut markets = self.markets.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let mut market_configs = self.market_configs.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        if markets.contains(&market) {
+            return Err(EngineError::InvalidMarket(format!("{} already exists", market)));
+        }
+        
+        let mut book = OrderBook::new(market);
+        book.set_fee_schedule(self.fees);
+        orderbooks.insert(market, book);
+        market_configs.insert(market, config);
+        markets.push(market);
+        
+        Ok(())
+    }
+    
+    /// Delist a market; its book must have no resting orders
+    pub fn remove_market(&self, market: &str) -> Result<(), EngineError> {
+        let market = parse_market(market)?;
+        
+        let mut orderbooks = self.orderbooks.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let mut markets = self.markets.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let mut market_configs = self.market_configs.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        let book = orderbooks.get(&market)
+            .ok_or_else(|| EngineError::MarketNotFound(market.to_string()))?;
+        if book.open_orders().next().is_some() {
+            return Err(EngineError::InvalidMarket(format!("{} has resting orders", market)));
+        }
+        
+        orderbooks.remove(&market);
+        market_configs.remove(&market);
+        markets.retain(|m| m != &market);
+        
+        Ok(())
+    }
+    
+    /// Subscribe to engine events
+    pub fn subscribe(&self) -> broadcast::Receiver<EngineEvent> {
+        self.events.subscribe()
+    }
+    
+    /// Publish an event with the market's next sequence number. Callers hold
+    /// the orderbooks lock so events reach subscribers in book order.
+    fn publish(&self, market: &MarketId, event: impl FnOnce(u64) -> EngineEvent) {
+        let seq = match self.event_seqs.lock() {
+            Ok(mut seqs) => {
+                let seq = seqs.entry(*market).or_insert(0);
+                *seq += 1;
+                *seq
+            }
+            Err(_) => return,
+        };
+        let _ = self.events.send(event(seq));
+    }
+    
+    /// Publish orders cancelled by self-trade prevention and a book's
+    /// trades, followed by its updated snapshot
+    fn publish_book_update(&self, book: &OrderBook, trades: &[Trade], stp_cancelled: &[Order]) {
+        for order in stp_cancelled {
+            self.publish(&order.market, |seq| EngineEvent::OrderCancelled {
+                seq,
+                order: order.clone(),
+                reason: CancelReason::SelfTradePrevention,
+            });
+        }
+        for trade in trades {
+            self.publish(&trade.market, |seq| EngineEvent::Trade { seq, trade: trade.clone() });
+        }
+        let snapshot = book.snapshot(EVENT_SNAPSHOT_DEPTH);
+        self.publish(book.market(), |seq| EngineEvent::OrderBookSnapshot { seq, snapshot });
+    }
+    
+    /// Reject a limit price outside the market's band around its mark price
+    fn check_price_band(&self, config: &MarketConfig, market: &MarketId, price: Price) -> Result<(), EngineError> {
+        let mark = {
+            let orderbooks = self.orderbooks.read()
+                .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+            orderbooks.get(market).and_then(|book| book.mark_price())
+        };
+        config.check_price_band(price, mark).map_err(EngineError::PriceOutOfBand)
     }
     
     /// Generate a new order ID
@@ -70,26 +338,214 @@ impl MatchingEngine {
     }
     
     /// Place a new order
-    pub fn place_order(&self, request: PlaceOrderRequest) -> Result<(Order, Vec<Trade>), EngineError> {
-        let market = Market::new(&request.market);
+    pub fn place_order(&self, request: PlaceOrderRequest) -> Result<PlaceOrderResult, EngineError> {
+        let order = self.build_order(request)?;
+        self.submit_order(order)
+    }
+    
+    /// Place an order at most once per idempotency key.
+    ///
+    /// Keys are scoped to the agent. Repeating a key within `IDEMPOTENCY_TTL`
+    /// returns the original result without touching the book, flagged by
+    /// `true`; repeating it with a different request is rejected. Failed
+    /// placements are not remembered, so a retry re-runs validation.
+    pub fn place_order_idempotent(
+        &self,
+        key: &str,
+        request: PlaceOrderRequest,
+    ) -> Result<(PlaceOrderResult, bool), EngineError> {
+        let fingerprint = serde_json::to_string(&request)
+            .map_err(|e| EngineError::InternalError(e.to_string()))?;
+        let cache_key = (request.agent_id.clone(), key.to_string());
+        let now = Instant::now();
+        
+        // Held while placing so concurrent retries can't both get through
+        let mut cache = self.idempotency.lock()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        cache.evict(now);
+        
+        if let Some(entry) = cache.entries.get(&cache_key) {
+            if entry.request != fingerprint {
+                return Err(EngineError::IdempotencyConflict(key.to_string()));
+            }
+            return Ok((entry.result.clone(), true));
+        }
+        
+        let result = self.place_order(request)?;
+        cache.entries.insert(cache_key.clone(), IdempotencyEntry {
+            request: fingerprint,
+            result: result.clone(),
+            placed_at: now,
+        });
+        cache.order.push_back((cache_key, now));
+        Ok((result, false))
+    }
+    
+    /// Place several orders in sequence and return a result for each.
+    ///
+    /// Orders are matched in batch order, so a later order can trade against
+    /// an earlier one from the same batch that came to rest on the book.
+    /// With `atomic` set, every request is validated (market, prices, risk)
+    /// before any book is touched and the whole batch is rejected if one
+    /// fails; risk checks count the exposure of the legs before each one as
+    /// if they filled in full. Otherwise each order succeeds or fails on its own.
+    pub fn place_batch(
+        &self,
+        requests: Vec<PlaceOrderRequest>,
+        atomic: bool,
+    ) -> Result<Vec<Result<PlaceOrderResult, EngineError>>, EngineError> {
+        if !atomic {
+            return Ok(requests.into_iter().map(|request| self.place_order(request)).collect());
+        }
+        
+        let orders = requests
+            .into_iter()
+            .enumerate()
+            .map(|(i, request)| {
+                self.build_order(request)
+                    .map_err(|e| EngineError::InvalidOrder(format!("Batch order {}: {}", i, e)))
+            })
+            .collect::<Result<Vec<Order>, EngineError>>()?;
+        
+        let limits: Vec<AgentRiskLimits> = orders
+            .iter()
+            .map(|order| self.get_agent(&order.agent_id).map(|a| a.risk_limits).unwrap_or_default())
+            .collect();
+        let mut legs = Vec::with_capacity(orders.len());
+        for (i, (order, limits)) in orders.iter().zip(&limits).enumerate() {
+            let price = self.risk_reference_price(order)?.ok_or_else(|| {
+                EngineError::RiskLimitExceeded(format!("Batch order {}: no price to check risk at", i))
+            })?;
+            let quantity = order.quantity.as_decimal();
+            let size = match order.side {
+                Side::Buy => quantity,
+                Side::Sell => -quantity,
+            };
+            legs.push((order.agent_id.as_str(), order.market, size, price.as_decimal(), limits));
+        }
+        self.risk.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?
+            .check_order_sequence(legs)
+            .map_err(|(i, e)| EngineError::RiskLimitExceeded(format!("Batch order {}: {}", i, e)))?;
+        
+        Ok(orders.into_iter().map(|order| self.submit_order(order)).collect())
+    }
+    
+    /// Price an order's risk is checked at: its limit or stop price, or the
+    /// opposite touch for a market order
+    fn risk_reference_price(&self, order: &Order) -> Result<Option<Price>, EngineError> {
+        if let Some(price) = order.price.or(order.stop_price) {
+            return Ok(Some(price));
+        }
+        let orderbooks = self.orderbooks.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        Ok(orderbooks.get(&order.market).and_then(|book| match order.side {
+            Side::Buy => book.best_ask(),
+            Side::Sell => book.best_bid(),
+        }))
+    }
+    
+    /// Place a linked pair of orders where a fill on either cancels the same
+    /// quantity of the other, and a leg that fills completely cancels the
+    /// rest of its sibling.
+    ///
+    /// Both legs are validated before either is placed. The primary goes to
+    /// the book first; whatever it fills straight away is taken off the
+    /// secondary before that is placed, so the secondary is not placed at all
+    /// if the primary filled in full.
+    pub fn place_oco(&self, request: OcoRequest) -> Result<(PlaceOrderResult, PlaceOrderResult), EngineError> {
+        for leg in [&request.primary, &request.secondary] {
+            if leg.agent_id != request.agent_id || leg.market != request.market {
+                return Err(EngineError::InvalidOrder(
+                    "OCO legs must match the request's agent and market".to_string(),
+                ));
+            }
+        }
+        
+        let mut primary = self.build_order(request.primary)?;
+        let mut secondary = self.build_order(request.secondary)?;
+        let group = primary.id.0;
+        primary.oco_group = Some(group);
+        secondary.oco_group = Some(group);
+        
+        let primary_result = self.submit_order(primary)?;
+        let primary_order = &primary_result.0;
+        let filled = primary_order.quantity - primary_order.remaining_quantity;
+        
+        if filled >= secondary.quantity || !primary_order.is_active() {
+            secondary.cancel();
+            let reason = CancelReason::OneCancelsOther;
+            return Ok((primary_result, (secondary, Vec::new(), OrderOutcome::Cancelled { reason }, Vec::new())));
+        }
+        secondary.quantity -= filled;
+        secondary.remaining_quantity = secondary.quantity;
+        
+        // Link before placing so fills of the secondary on arrival reduce the primary
+        {
+            let mut links = self.oco_links.lock()
+                .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+            links.insert(primary_order.id, secondary.id);
+            links.insert(secondary.id, primary_order.id);
+        }
+        let secondary_result = self.submit_order(secondary)?;
+        
+        Ok((primary_result, secondary_result))
+    }
+    
+    /// Validate a request and turn it into an order without touching any book
+    fn build_order(&self, request: PlaceOrderRequest) -> Result<Order, EngineError> {
+        let market = parse_market(&request.market)?;
         
         // Validate market
-        if !self.markets.contains(&market) {
+        if !self.has_market(&market) {
             return Err(EngineError::MarketNotFound(request.market));
         }
         
+        if request.quantity <= 0.0 {
+            return Err(EngineError::InvalidOrder("Quantity must be positive".to_string()));
+        }
+        
+        // Validate against the market's tick size and order size limits
+        let config = self.market_config(&market)
+            .ok_or_else(|| EngineError::MarketNotFound(market.to_string()))?;
+        config.check_quantity(Quantity::from_f64(request.quantity))
+            .map_err(EngineError::InvalidOrder)?;
+        let round = request.round.unwrap_or(false);
+        let align = |price: Option<f64>| {
+            price
+                .map(|p| config.align_price(Price::from_f64(p), round))
+                .transpose()
+                .map_err(EngineError::InvalidOrder)
+        };
+        let price = align(request.price)?;
+        let stop_price = align(request.stop_price)?;
+        let fallback_limit = align(request.fallback_limit)?;
+        if fallback_limit.is_some() && !matches!(request.order_type, OrderType::Market | OrderType::StopMarket) {
+            return Err(EngineError::InvalidOrder("fallback_limit only applies to market orders".to_string()));
+        }
+        if let (OrderType::Limit, Some(price)) = (request.order_type, price) {
+            self.check_price_band(&config, &market, price)?;
+        }
+        let display_qty = request.display_qty.map(Quantity::from_f64);
+        if let Some(display_qty) = display_qty {
+            if !matches!(request.order_type, OrderType::Limit | OrderType::StopLimit) {
+                return Err(EngineError::InvalidOrder("display_qty only applies to limit orders".to_string()));
+            }
+            config.check_quantity(display_qty).map_err(EngineError::InvalidOrder)?;
+        }
+        
         // Create order
         let order_id = self.next_order_id();
-        let order = match request.order_type {
+        let mut order = match request.order_type {
             OrderType::Limit => {
-                let price = request.price
+                let price = price
                     .ok_or_else(|| EngineError::InvalidOrder("Limit order requires price".to_string()))?;
                 Order::new_limit(
                     order_id,
                     request.agent_id,
-                    market.clone(),
+                    market,
                     request.side,
-                    Price::from_f64(price),
+                    price,
                     Quantity::from_f64(request.quantity),
                     request.time_in_force.unwrap_or(TimeInForce::GTC),
                 )
@@ -98,22 +554,260 @@ impl MatchingEngine {
                 Order::new_market(
                     order_id,
                     request.agent_id,
-                    market.clone(),
+                    market,
                     request.side,
                     Quantity::from_f64(request.quantity),
                 )
             }
-            _ => return Err(EngineError::InvalidOrder("Unsupported order type".to_string())),
+            OrderType::StopLimit => {
+                let price = price
+                    .ok_or_else(|| EngineError::InvalidOrder("Stop limit order requires price".to_string()))?;
+                let stop_price = stop_price
+                    .ok_or_else(|| EngineError::InvalidOrder("Stop order requires stop_price".to_string()))?;
+                Order::new_limit(
+                    order_id,
+                    request.agent_id,
+                    market,
+                    request.side,
+                    price,
+                    Quantity::from_f64(request.quantity),
+                    request.time_in_force.unwrap_or(TimeInForce::GTC),
+                ).with_stop_price(stop_price)
+            }
+            OrderType::StopMarket => {
+                let stop_price = stop_price
+                    .ok_or_else(|| EngineError::InvalidOrder("Stop order requires stop_price".to_string()))?;
+                Order::new_market(
+                    order_id,
+                    request.agent_id,
+                    market,
+                    request.side,
+                    Quantity::from_f64(request.quantity),
+                ).with_stop_price(stop_price)
+            }
         };
+        order.self_trade_policy = request.self_trade_policy.unwrap_or_default();
+        order.client_order_id = request.client_order_id;
+        order.max_slippage_bps = request.max_slippage_bps;
+        order.fallback_limit = fallback_limit;
+        order.display_qty = display_qty;
+        
+        if request.reduce_only.unwrap_or(false) {
+            self.apply_reduce_only(&mut order)?;
+        }
+        
+        Ok(order)
+    }
+    
+    /// Match a validated order and apply the resulting trades
+    fn submit_order(&self, order: Order) -> Result<PlaceOrderResult, EngineError> {
+        let market = order.market;
         
         // Place order in book
         let mut orderbooks = self.orderbooks.write()
             .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
         
         let book = orderbooks.get_mut(&market)
-            .ok_or_else(|| EngineError::MarketNotFound(market.0.clone()))?;
+            .ok_or_else(|| EngineError::MarketNotFound(market.to_string()))?;
+        
+        let (trades, outcome) = book.place_order_with_outcome(order.clone());
+        let stp_cancelled = self.sync_agent_orders(book, Some(&order), &trades)?;
+        self.publish_book_update(book, &trades, &stp_cancelled);
+        let order = order_after_matching(book, order, &trades, &outcome);
+        drop(orderbooks);
+        
+        self.apply_trades(&trades)?;
+        
+        Ok((order, trades, outcome, stp_cancelled))
+    }
+    
+    /// Run an order against a copy of its market's book and report what
+    /// `place_order` would return, without touching the real book, the
+    /// agent's positions or the event stream.
+    ///
+    /// The request is validated exactly as for a real placement, so the
+    /// result matches what `place_order` would return on the same book; the
+    /// simulated order still takes an ID from the engine's counter.
+    pub fn simulate_order(&self, request: PlaceOrderRequest) -> Result<PlaceOrderResult, EngineError> {
+        let order = self.build_order(request)?;
+        
+        let mut book = {
+            let orderbooks = self.orderbooks.read()
+                .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+            orderbooks.get(&order.market)
+                .ok_or_else(|| EngineError::MarketNotFound(order.market.to_string()))?
+                .clone()
+        };
+        
+        let (trades, outcome) = book.place_order_with_outcome(order.clone());
+        let stp_cancelled = book.take_cancelled_orders();
+        let order = order_after_matching(&book, order, &trades, &outcome);
+        Ok((order, trades, outcome, stp_cancelled))
+    }
+    
+    /// Apply trades to positions and record every close in the agents'
+    /// reputations. Agents not in the registry are not tracked
+    fn apply_trades(&self, trades: &[Trade]) -> Result<(), EngineError> {
+        let closes: Vec<ClosedFill> = {
+            let mut risk = self.risk.write()
+                .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+            trades.iter().flat_map(|trade| risk.apply_trade(trade)).collect()
+        };
+        if closes.is_empty() {
+            return Ok(());
+        }
+        
+        let mut agents = self.agents.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        for close in closes {
+            let pnl = close.realized_pnl.to_f64().unwrap_or_default();
+            let notional = close.notional.to_f64().unwrap_or_default();
+            let _ = agents.record_trade_result(&AgentId::new(close.agent_id), pnl, notional);
+        }
+        Ok(())
+    }
+    
+    /// Register an agent so its closed trades build a reputation
+    pub fn register_agent(&self, agent: Agent) -> Result<(), EngineError> {
+        let mut agents = self.agents.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        agents.register(agent).map_err(EngineError::InvalidAgent)
+    }
+    
+    /// Get a registered agent, including its reputation
+    pub fn get_agent(&self, agent_id: &str) -> Option<Agent> {
+        let agents = self.agents.read().ok()?;
+        agents.get(&AgentId::new(agent_id)).cloned()
+    }
+    
+    /// Cap a reduce-only order at the open position and reject it if it
+    /// would open or add to exposure
+    fn apply_reduce_only(&self, order: &mut Order) -> Result<(), EngineError> {
+        let risk = self.risk.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let size = risk.net_position(&order.agent_id, &order.market);
+        
+        if size.is_zero() {
+            return Err(EngineError::InvalidOrder(
+                "Reduce-only order has no open position to reduce".to_string(),
+            ));
+        }
+        
+        let increases = match order.side {
+            Side::Buy => size > rust_decimal::Decimal::ZERO,
+            Side::Sell => size < rust_decimal::Decimal::ZERO,
+        };
+        if increases {
+            return Err(EngineError::InvalidOrder(
+                "Reduce-only order would increase position".to_string(),
+            ));
+        }
+        
+        let open = Quantity::new(size.abs());
+        if order.quantity > open {
+            order.quantity = open;
+            order.remaining_quantity = open;
+        }
+        order.reduce_only = true;
+        
+        Ok(())
+    }
+    
+    /// Get an agent's position in a market
+    pub fn get_position(&self, agent_id: &str, market: &str) -> Result<Option<Position>, EngineError> {
+        let market = parse_market(market)?;
+        
+        let mut risk = self.risk.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let position = risk.get_position(agent_id, &market).clone();
+        
+        Ok((!position.is_flat()).then_some(position))
+    }
+    
+    /// Credit an agent's balance, returning the new balance
+    pub fn deposit(&self, agent_id: &str, amount: Decimal) -> Result<Decimal, EngineError> {
+        if amount <= Decimal::ZERO {
+            return Err(EngineError::InvalidOrder("Deposit amount must be positive".to_string()));
+        }
+        let mut risk = self.risk.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        risk.deposit(agent_id, amount);
+        Ok(risk.get_balance(agent_id))
+    }
+    
+    /// Get an agent's balance
+    pub fn get_balance(&self, agent_id: &str) -> Result<Decimal, EngineError> {
+        let risk = self.risk.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        Ok(risk.get_balance(agent_id))
+    }
+    
+    /// Pre-trade risk check of a signed `size` at `price` against the agent's
+    /// position and balance
+    pub fn check_order_risk(
+        &self,
+        agent_id: &str,
+        market: &str,
+        size: Decimal,
+        price: Decimal,
+        limits: &AgentRiskLimits,
+    ) -> Result<(), EngineError> {
+        let market = parse_market(market)?;
+        let mut risk = self.risk.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        risk.check_order(agent_id, &market, size, price, limits)
+            .map_err(|e| EngineError::RiskLimitExceeded(e.to_string()))
+    }
+    
+    /// Total long size open in a market
+    pub fn open_interest(&self, market: &str) -> Result<Decimal, EngineError> {
+        let market = parse_market(market)?;
+        let risk = self.risk.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        Ok(risk.open_interest(&market))
+    }
+    
+    /// Amend a resting order, keeping queue priority for same-price size reductions
+    pub fn amend_order(&self, request: AmendOrderRequest) -> Result<(Order, Vec<Trade>), EngineError> {
+        let order_id = OrderId(request.order_id);
+        if request.new_qty <= 0.0 {
+            return Err(EngineError::InvalidOrder("Amended quantity must be positive".to_string()));
+        }
+        
+        let mut orderbooks = self.orderbooks.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        let book = orderbooks.values_mut()
+            .find(|book| book.get_order(&order_id).is_some())
+            .ok_or(EngineError::OrderNotFound(request.order_id))?;
+        
+        // Verify ownership before touching the order
+        let owner = book.get_order(&order_id).map(|o| o.agent_id.as_str());
+        if owner != Some(request.agent_id.as_str()) {
+            return Err(EngineError::InvalidOrder("Not order owner".to_string()));
+        }
         
-        let trades = book.place_order(order.clone());
+        if let Some(config) = self.market_config(book.market()) {
+            config.align_price(Price::from_f64(request.new_price), false)
+                .map_err(EngineError::InvalidOrder)?;
+            config.check_price_band(Price::from_f64(request.new_price), book.mark_price())
+                .map_err(EngineError::PriceOutOfBand)?;
+            config.check_quantity(Quantity::from_f64(request.new_qty))
+                .map_err(EngineError::InvalidOrder)?;
+        }
+        
+        let (order, trades) = book
+            .amend_order(
+                &order_id,
+                Price::from_f64(request.new_price),
+                Quantity::from_f64(request.new_qty),
+            )
+            .ok_or_else(|| EngineError::InvalidOrder("Only resting orders can be amended".to_string()))?;
+        let stp_cancelled = self.sync_agent_orders(book, Some(&order), &trades)?;
+        self.publish_book_update(book, &trades, &stp_cancelled);
+        drop(orderbooks);
+        
+        self.apply_trades(&trades)?;
         
         Ok((order, trades))
     }
@@ -124,14 +818,27 @@ impl MatchingEngine {
         
         let mut orderbooks = self.orderbooks.write()
             .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let mut agent_orders = self.agent_orders.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        // Verify ownership before touching any book
+        let owned = agent_orders
+            .get(&request.agent_id)
+            .is_some_and(|ids| ids.contains(&order_id));
+        if !owned {
+            let exists = orderbooks.values().any(|book| book.get_order(&order_id).is_some());
+            return Err(if exists {
+                EngineError::InvalidOrder("Not order owner".to_string())
+            } else {
+                EngineError::OrderNotFound(request.order_id)
+            });
+        }
         
-        // Search all orderbooks for the order
         for book in orderbooks.values_mut() {
             if let Some(order) = book.cancel_order(&order_id) {
-                // Verify ownership
-                if order.agent_id != request.agent_id {
-                    return Err(EngineError::InvalidOrder("Not order owner".to_string()));
-                }
+                unindex_order(&mut agent_orders, &request.agent_id, &order_id);
+                self.unlink_oco(&order_id);
+                self.publish_book_update(book, &[], &[]);
                 return Ok(order);
             }
         }
@@ -139,50 +846,609 @@ impl MatchingEngine {
         Err(EngineError::OrderNotFound(request.order_id))
     }
     
-    /// Get orderbook snapshot
-    pub fn get_orderbook(&self, market: &str, depth: usize) -> Result<crate::types::OrderBookSnapshot, EngineError> {
-        let market = Market::new(market);
+    /// Replace an agent's quote ladder in one market with `ladder`.
+    ///
+    /// The ladder is diffed against the agent's resting limit orders by side
+    /// and price: matching rungs at the same size are left alone and keep
+    /// their queue priority, resized rungs are amended in place, and the rest
+    /// are cancelled or placed as GTC limits. Stop orders are not part of the
+    /// ladder. Every rung is validated before any order is touched; the
+    /// changes themselves are applied one by one, cancellations first.
+    pub fn cancel_replace_batch(
+        &self,
+        agent_id: &str,
+        market: &str,
+        ladder: Vec<RungSpec>,
+    ) -> Result<LadderUpdate, EngineError> {
+        let market_id = parse_market(market)?;
+        let config = self.market_config(&market_id)
+            .ok_or_else(|| EngineError::MarketNotFound(market.to_string()))?;
         
-        let orderbooks = self.orderbooks.read()
-            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let mut desired: Vec<(RungSpec, Price, Quantity)> = Vec::with_capacity(ladder.len());
+        for (i, rung) in ladder.into_iter().enumerate() {
+            let invalid = |e: String| EngineError::InvalidOrder(format!("Rung {}: {}", i, e));
+            if rung.quantity <= 0.0 {
+                return Err(invalid("Quantity must be positive".to_string()));
+            }
+            let price = config.align_price(Price::from_f64(rung.price), false).map_err(invalid)?;
+            let quantity = Quantity::from_f64(rung.quantity);
+            config.check_quantity(quantity).map_err(invalid)?;
+            self.check_price_band(&config, &market_id, price)
+                .map_err(|e| match e {
+                    EngineError::PriceOutOfBand(e) => EngineError::PriceOutOfBand(format!("Rung {}: {}", i, e)),
+                    e => e,
+                })?;
+            if desired.iter().any(|(other, p, _)| other.side == rung.side && *p == price) {
+                return Err(invalid(format!("Duplicate {:?} rung at {}", rung.side, price.0)));
+            }
+            desired.push((rung, price, quantity));
+        }
         
-        let book = orderbooks.get(&market)
-            .ok_or_else(|| EngineError::MarketNotFound(market.0.clone()))?;
+        // Oldest first, so the order with the best priority claims its rung
+        // and any duplicates at the same price are cancelled
+        let resting = self.open_orders(agent_id)?
+            .into_iter()
+            .filter(|o| o.market == market_id && !o.is_stop());
+        let mut update = LadderUpdate::default();
+        let mut cancels = Vec::new();
+        let mut amends = Vec::new();
+        for order in resting {
+            let rung = desired.iter()
+                .position(|(rung, price, _)| rung.side == order.side && order.price == Some(*price));
+            match rung.map(|i| desired.remove(i)) {
+                Some((_, _, quantity)) if quantity == order.remaining_quantity => update.unchanged.push(order.id),
+                Some(rung) => amends.push((order.id, rung)),
+                None => cancels.push(order.id),
+            }
+        }
         
-        Ok(book.snapshot(depth))
+        for order_id in cancels {
+            match self.cancel_order(CancelOrderRequest { agent_id: agent_id.to_string(), order_id: order_id.0 }) {
+                Ok(order) => update.cancelled.push(order),
+                // Filled since the ladder was read
+                Err(EngineError::OrderNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        for (order_id, rung) in amends {
+            let amend = AmendOrderRequest {
+                agent_id: agent_id.to_string(),
+                order_id: order_id.0,
+                new_price: rung.0.price,
+                new_qty: rung.0.quantity,
+            };
+            match self.amend_order(amend) {
+                Ok((order, trades)) => {
+                    update.amended.push(order);
+                    update.trades.extend(trades);
+                }
+                // Filled since the ladder was read: place the rung afresh
+                Err(EngineError::OrderNotFound(_)) => desired.push(rung),
+                Err(e) => return Err(e),
+            }
+        }
+        for (rung, _, _) in desired {
+            let (order, trades, _, stp_cancelled) = self.place_order(PlaceOrderRequest {
+                agent_id: agent_id.to_string(),
+                market: market.to_string(),
+                side: rung.side,
+                order_type: OrderType::Limit,
+                price: Some(rung.price),
+                quantity: rung.quantity,
+                time_in_force: Some(TimeInForce::GTC),
+                stop_price: None,
+                reduce_only: None,
+                client_order_id: None,
+                self_trade_policy: None,
+                max_slippage_bps: None,
+                round: None,
+                fallback_limit: None,
+                display_qty: None,
+            })?;
+            update.placed.push(order);
+            update.trades.extend(trades);
+            update.cancelled.extend(stp_cancelled);
+        }
+        
+        Ok(update)
     }
     
-    /// Get best bid/ask for a market
-    pub fn get_bbo(&self, market: &str) -> Result<(Option<Price>, Option<Price>), EngineError> {
-        let market = Market::new(market);
-        
-        let orderbooks = self.orderbooks.read()
+    /// Cancel every resting order for an agent, e.g. on disconnect
+    pub fn cancel_all(&self, agent_id: &str) -> Result<Vec<Order>, EngineError> {
+        self.cancel_agent_orders(agent_id, None)
+    }
+    
+    /// Cancel an agent's resting orders in a single market
+    pub fn cancel_all_in_market(&self, agent_id: &str, market: &str) -> Result<Vec<Order>, EngineError> {
+        let market = parse_market(market)?;
+        if !self.has_market(&market) {
+            return Err(EngineError::MarketNotFound(market.to_string()));
+        }
+        self.cancel_agent_orders(agent_id, Some(&market))
+    }
+    
+    /// Expire GTD orders across all books and broadcast each cancellation
+    pub fn sweep_expired(&self, now: Timestamp) -> Result<Vec<Order>, EngineError> {
+        let mut orderbooks = self.orderbooks.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let mut agent_orders = self.agent_orders.write()
             .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
         
-        let book = orderbooks.get(&market)
-            .ok_or_else(|| EngineError::MarketNotFound(market.0.clone()))?;
+        let mut expired = Vec::new();
+        for book in orderbooks.values_mut() {
+            let swept = book.sweep_expired(now);
+            if swept.is_empty() {
+                continue;
+            }
+            for order in swept {
+                unindex_order(&mut agent_orders, &order.agent_id, &order.id);
+                self.unlink_oco(&order.id);
+                self.publish(&order.market, |seq| EngineEvent::OrderExpired { seq, order: order.clone() });
+                expired.push(order);
+            }
+            self.publish_book_update(book, &[], &[]);
+        }
         
-        Ok((book.best_bid(), book.best_ask()))
+        Ok(expired)
     }
-}
-
-impl Default for MatchingEngine {
-    fn default() -> Self {
-        Self::new()
+    
+    /// Get the IDs of an agent's resting orders
+    pub fn open_order_ids(&self, agent_id: &str) -> Result<Vec<OrderId>, EngineError> {
+        let agent_orders = self.agent_orders.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        Ok(agent_orders
+            .get(agent_id)
+            .map(|ids| ids.iter().cloned().collect())
+            .unwrap_or_default())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
     
-    #[test]
-    fn test_engine_creation() {
-        let engine = MatchingEngine::new();
-        assert_eq!(engine.markets().len(), 3);
+    /// Look up a resting order (including untriggered stops) by ID
+    pub fn get_order(&self, order_id: u64) -> Option<Order> {
+        let orderbooks = self.orderbooks.read().ok()?;
+        let order_id = OrderId(order_id);
+        orderbooks.values().find_map(|book| book.get_order(&order_id)).cloned()
     }
     
-    #[test]
+    /// Get an agent's resting orders (including untriggered stops), oldest first
+    pub fn open_orders(&self, agent_id: &str) -> Result<Vec<Order>, EngineError> {
+        let orderbooks = self.orderbooks.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let agent_orders = self.agent_orders.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        let mut orders: Vec<Order> = agent_orders
+            .get(agent_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| orderbooks.values().find_map(|book| book.get_order(id)).cloned())
+            .collect();
+        orders.sort_by_key(|o| o.id.0);
+        
+        Ok(orders)
+    }
+    
+    fn cancel_agent_orders(&self, agent_id: &str, market: Option<&MarketId>) -> Result<Vec<Order>, EngineError> {
+        let mut orderbooks = self.orderbooks.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let mut agent_orders = self.agent_orders.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        let order_ids: Vec<OrderId> = agent_orders
+            .get(agent_id)
+            .map(|ids| ids.iter().cloned().collect())
+            .unwrap_or_default();
+        
+        let mut cancelled = Vec::new();
+        for order_id in order_ids {
+            let book = orderbooks.values_mut()
+                .filter(|book| market.is_none_or(|m| book.market() == m))
+                .find(|book| book.get_order(&order_id).is_some());
+            
+            if let Some(book) = book {
+                if let Some(order) = book.cancel_order(&order_id) {
+                    unindex_order(&mut agent_orders, agent_id, &order_id);
+                    self.unlink_oco(&order_id);
+                    self.publish_book_update(book, &[], &[]);
+                    cancelled.push(order);
+                }
+            }
+        }
+        
+        Ok(cancelled)
+    }
+    
+    /// Take each OCO leg's fills off its sibling, cancelling the sibling
+    /// outright once the leg is done. Returns the siblings cancelled.
+    fn apply_oco_fills(&self, book: &mut OrderBook, trades: &[Trade]) -> Result<Vec<Order>, EngineError> {
+        let mut links = self.oco_links.lock()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        if links.is_empty() {
+            return Ok(Vec::new());
+        }
+        
+        let mut cancelled = Vec::new();
+        for trade in trades {
+            for order_id in [trade.maker_order_id, trade.taker_order_id] {
+                let Some(sibling) = links.get(&order_id).copied() else {
+                    continue;
+                };
+                
+                let leg_done = book.get_order(&order_id).is_none();
+                let reduced = if leg_done {
+                    book.cancel_order(&sibling)
+                } else {
+                    book.reduce_order(&sibling, trade.quantity)
+                };
+                
+                let sibling_done = book.get_order(&sibling).is_none();
+                if let Some(order) = reduced.filter(|o| o.status == OrderStatus::Cancelled) {
+                    cancelled.push(order);
+                }
+                if leg_done || sibling_done {
+                    links.remove(&order_id);
+                    links.remove(&sibling);
+                }
+            }
+        }
+        Ok(cancelled)
+    }
+    
+    /// Forget the OCO link of an order that left the book without filling
+    fn unlink_oco(&self, order_id: &OrderId) {
+        if let Ok(mut links) = self.oco_links.lock() {
+            if let Some(sibling) = links.remove(order_id) {
+                links.remove(&sibling);
+            }
+        }
+    }
+    
+    /// Bring the open-order index in line with a book after matching: index
+    /// the placed order if it rests, and drop orders that filled or were
+    /// cancelled by self-trade prevention or by their OCO sibling filling.
+    /// Returns the orders self-trade prevention cancelled
+    fn sync_agent_orders(
+        &self,
+        book: &mut OrderBook,
+        placed: Option<&Order>,
+        trades: &[Trade],
+    ) -> Result<Vec<Order>, EngineError> {
+        let mut agent_orders = self.agent_orders.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        for cancelled in self.apply_oco_fills(book, trades)? {
+            unindex_order(&mut agent_orders, &cancelled.agent_id, &cancelled.id);
+        }
+        
+        if let Some(order) = placed {
+            if book.get_order(&order.id).is_some() {
+                agent_orders.entry(order.agent_id.clone()).or_default().insert(order.id);
+            }
+        }
+        
+        for trade in trades {
+            for (agent_id, order_id) in [
+                (&trade.maker_agent_id, trade.maker_order_id),
+                (&trade.taker_agent_id, trade.taker_order_id),
+            ] {
+                if book.get_order(&order_id).is_none() {
+                    unindex_order(&mut agent_orders, agent_id, &order_id);
+                }
+            }
+        }
+        
+        let stp_cancelled = book.take_cancelled_orders();
+        for cancelled in &stp_cancelled {
+            unindex_order(&mut agent_orders, &cancelled.agent_id, &cancelled.id);
+        }
+        
+        Ok(stp_cancelled)
+    }
+    
+    /// Get orderbook snapshot
+    pub fn get_orderbook(&self, market: &str, depth: usize) -> Result<crate::types::OrderBookSnapshot, EngineError> {
+        let market = parse_market(market)?;
+        
+        let orderbooks = self.orderbooks.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        let book = orderbooks.get(&market)
+            .ok_or_else(|| EngineError::MarketNotFound(market.to_string()))?;
+        
+        Ok(book.snapshot(depth))
+    }
+    
+    /// Get orderbook snapshot with levels merged into price buckets
+    pub fn get_aggregated_orderbook(
+        &self,
+        market: &str,
+        tick_size: rust_decimal::Decimal,
+        depth: usize,
+    ) -> Result<crate::types::OrderBookSnapshot, EngineError> {
+        let market = parse_market(market)?;
+        
+        let orderbooks = self.orderbooks.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        let book = orderbooks.get(&market)
+            .ok_or_else(|| EngineError::MarketNotFound(market.to_string()))?;
+        
+        Ok(book.aggregated_snapshot(tick_size, depth))
+    }
+    
+    /// Estimate the market impact of a hypothetical taker order
+    pub fn estimate_fill(&self, market: &str, side: Side, quantity: f64) -> Result<FillEstimate, EngineError> {
+        let market = parse_market(market)?;
+        
+        let orderbooks = self.orderbooks.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        let book = orderbooks.get(&market)
+            .ok_or_else(|| EngineError::MarketNotFound(market.to_string()))?;
+        
+        Ok(book.estimate_fill(side, Quantity::from_f64(quantity)))
+    }
+    
+    /// Get rolling 24h statistics for a market
+    pub fn get_market_stats(&self, market: &str) -> Result<MarketStats, EngineError> {
+        let market = parse_market(market)?;
+        
+        let mut orderbooks = self.orderbooks.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        let book = orderbooks.get_mut(&market)
+            .ok_or_else(|| EngineError::MarketNotFound(market.to_string()))?;
+        
+        Ok(book.market_stats(Timestamp::now()))
+    }
+    
+    /// Orders cancelled and expired across all markets
+    pub fn order_removal_counts(&self) -> Result<OrderRemovalCounts, EngineError> {
+        let orderbooks = self.orderbooks.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        Ok(orderbooks.values().fold(OrderRemovalCounts::default(), |total, book| {
+            let counts = book.removal_counts();
+            OrderRemovalCounts {
+                cancelled: total.cancelled + counts.cancelled,
+                expired: total.expired + counts.expired,
+            }
+        }))
+    }
+    
+    /// Get best bid/ask for a market
+    pub fn get_bbo(&self, market: &str) -> Result<(Option<Price>, Option<Price>), EngineError> {
+        let market = parse_market(market)?;
+        
+        let orderbooks = self.orderbooks.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        let book = orderbooks.get(&market)
+            .ok_or_else(|| EngineError::MarketNotFound(market.to_string()))?;
+        
+        Ok((book.best_bid(), book.best_ask()))
+    }
+    
+    /// Write every orderbook and the order ID counter to `path`
+    pub fn save_all(&self, path: impl AsRef<Path>) -> Result<(), EngineError> {
+        let orderbooks = self.orderbooks.read()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        let snapshot = EngineSnapshot {
+            order_counter: self.order_counter.load(Ordering::SeqCst),
+            books: orderbooks.values().map(OrderBook::to_state).collect(),
+        };
+        let bytes = serde_json::to_vec(&snapshot)
+            .map_err(|e| EngineError::InternalError(format!("Snapshot encode failed: {}", e)))?;
+        
+        // Write then rename so a crash mid-write never leaves a torn snapshot
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, bytes)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| EngineError::InternalError(format!("Snapshot write failed: {}", e)))
+    }
+    
+    /// Replace all orderbooks and the order ID counter with a snapshot
+    /// written by [`MatchingEngine::save_all`]
+    pub fn load_all(&self, path: impl AsRef<Path>) -> Result<(), EngineError> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| EngineError::InternalError(format!("Snapshot read failed: {}", e)))?;
+        let snapshot: EngineSnapshot = serde_json::from_slice(&bytes)
+            .map_err(|e| EngineError::InternalError(format!("Snapshot decode failed: {}", e)))?;
+        
+        let mut orderbooks = self.orderbooks.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        let mut agent_orders = self.agent_orders.write()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        
+        agent_orders.clear();
+        let mut oco_groups: HashMap<u64, Vec<OrderId>> = HashMap::new();
+        for state in snapshot.books {
+            let book = OrderBook::from_state(state);
+            if !self.has_market(book.market()) {
+                tracing::warn!("Skipping snapshot for unsupported market {}", book.market());
+                continue;
+            }
+            for order in book.open_orders() {
+                agent_orders.entry(order.agent_id.clone()).or_default().insert(order.id);
+                if let Some(group) = order.oco_group {
+                    oco_groups.entry(group).or_default().push(order.id);
+                }
+            }
+            orderbooks.insert(*book.market(), book);
+        }
+        
+        // Re-link OCO pairs where both legs are still open
+        let mut links = self.oco_links.lock()
+            .map_err(|_| EngineError::InternalError("Lock error".to_string()))?;
+        links.clear();
+        for legs in oco_groups.values() {
+            if let [a, b] = legs.as_slice() {
+                links.insert(*a, *b);
+                links.insert(*b, *a);
+            }
+        }
+        self.order_counter.fetch_max(snapshot.order_counter, Ordering::SeqCst);
+        
+        Ok(())
+    }
+}
+
+/// On-disk form of the engine's books and counters
+#[derive(Serialize, Deserialize)]
+struct EngineSnapshot {
+    order_counter: u64,
+    books: Vec<BookState>,
+}
+
+/// Periodically expire GTD orders (background task)
+pub async fn start_expiry_sweeper(engine: Arc<MatchingEngine>, interval_ms: u64) {
+    tracing::info!("⏰ GTD expiry sweeper starting (interval: {}ms)", interval_ms);
+    
+    let mut ticker = tokio::time::interval(std::time::Duration::from_millis(interval_ms));
+    
+    loop {
+        ticker.tick().await;
+        
+        match engine.sweep_expired(Timestamp::now()) {
+            Ok(expired) if !expired.is_empty() => {
+                let totals = engine.order_removal_counts().unwrap_or_default();
+                tracing::info!("Expired {} GTD orders ({} expired, {} cancelled in total)",
+                    expired.len(), totals.expired, totals.cancelled);
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Expiry sweep failed: {}", e),
+        }
+    }
+}
+
+/// Report an order as it stands after matching against `book`
+fn order_after_matching(book: &OrderBook, mut order: Order, trades: &[Trade], outcome: &OrderOutcome) -> Order {
+    // A market order's remainder may have been re-priced into a resting limit
+    if let Some(resting) = book.get_order(&order.id) {
+        order = resting.clone();
+    }
+    let filled: rust_decimal::Decimal = trades.iter()
+        .filter(|t| t.taker_order_id == order.id)
+        .map(|t| t.quantity.as_decimal())
+        .sum();
+    order.remaining_quantity = order.quantity - Quantity::new(filled);
+    order.status = outcome.status();
+    order
+}
+
+/// Parse a market symbol, reporting unknown symbols as a missing market
+fn parse_market(symbol: &str) -> Result<MarketId, EngineError> {
+    symbol.parse().map_err(|_| EngineError::MarketNotFound(symbol.to_string()))
+}
+
+/// Remove an order from an agent's open-order set, dropping empty sets
+fn unindex_order(agent_orders: &mut HashMap<String, HashSet<OrderId>>, agent_id: &str, order_id: &OrderId) {
+    if let Some(ids) = agent_orders.get_mut(agent_id) {
+        ids.remove(order_id);
+        if ids.is_empty() {
+            agent_orders.remove(agent_id);
+        }
+    }
+}
+
+impl Default for MatchingEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    
+    fn limit_request(agent: &str, side: Side, price: f64, quantity: f64) -> PlaceOrderRequest {
+        PlaceOrderRequest {
+            agent_id: agent.to_string(),
+            market: "BTC-PERP".to_string(),
+            side,
+            order_type: OrderType::Limit,
+            price: Some(price),
+            quantity,
+            time_in_force: Some(TimeInForce::GTC),
+            stop_price: None,
+            reduce_only: None,
+            client_order_id: None,
+            self_trade_policy: None,
+            max_slippage_bps: None,
+            round: None,
+            fallback_limit: None,
+            display_qty: None,
+        }
+    }
+    
+    fn market_request(agent: &str, side: Side, quantity: f64) -> PlaceOrderRequest {
+        PlaceOrderRequest {
+            order_type: OrderType::Market,
+            price: None,
+            time_in_force: None,
+            ..limit_request(agent, side, 0.0, quantity)
+        }
+    }
+    
+    #[test]
+    fn test_market_order_against_empty_book_reports_no_liquidity() {
+        let engine = MatchingEngine::new();
+        
+        let (order, trades, outcome, _) = engine.place_order(market_request("taker", Side::Buy, 1.0)).unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(outcome, OrderOutcome::NoLiquidity {
+            filled_quantity: Quantity::default(),
+            unfilled_quantity: Quantity::from_f64(1.0),
+        });
+        assert_eq!(order.status, crate::order::OrderStatus::Cancelled);
+    }
+    
+    #[test]
+    fn test_market_order_partial_fill_then_empty_book() {
+        let engine = MatchingEngine::new();
+        engine.place_order(limit_request("maker", Side::Sell, 50000.0, 0.4)).unwrap();
+        
+        let (order, trades, outcome, _) = engine.place_order(market_request("taker", Side::Buy, 1.0)).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(outcome, OrderOutcome::NoLiquidity {
+            filled_quantity: Quantity::from_f64(0.4),
+            unfilled_quantity: Quantity::from_f64(0.6),
+        });
+        assert_eq!(order.remaining_quantity, Quantity::from_f64(0.6));
+        assert!(engine.open_orders("taker").unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_market_order_fallback_limit_rests_remainder() {
+        let engine = MatchingEngine::new();
+        engine.place_order(limit_request("maker", Side::Sell, 50000.0, 0.4)).unwrap();
+        
+        let mut request = market_request("taker", Side::Buy, 1.0);
+        request.fallback_limit = Some(50100.0);
+        let (order, _, outcome, _) = engine.place_order(request).unwrap();
+        assert_eq!(outcome, OrderOutcome::Resting { filled_quantity: Quantity::from_f64(0.4) });
+        assert_eq!(order.order_type, OrderType::Limit);
+        assert_eq!(order.price, Some(Price::from_f64(50100.0)));
+        
+        let resting = engine.get_order(order.id.0).unwrap();
+        assert_eq!(resting.remaining_quantity, Quantity::from_f64(0.6));
+        assert_eq!(engine.get_bbo("BTC-PERP").unwrap().0, Some(Price::from_f64(50100.0)));
+        
+        // Limit orders cannot carry a fallback
+        let mut request = limit_request("taker", Side::Buy, 49000.0, 1.0);
+        request.fallback_limit = Some(49000.0);
+        assert!(matches!(engine.place_order(request), Err(EngineError::InvalidOrder(_))));
+    }
+    
+    #[test]
+    fn test_engine_creation() {
+        let engine = MatchingEngine::new();
+        assert_eq!(engine.markets().len(), 3);
+    }
+    
+    #[test]
     fn test_place_limit_order() {
         let engine = MatchingEngine::new();
         
@@ -197,14 +1463,21 @@ mod tests {
             stop_price: None,
             reduce_only: None,
             client_order_id: None,
+            self_trade_policy: None,
+            max_slippage_bps: None,
+            round: None,
+            fallback_limit: None,
+            display_qty: None,
         };
         
         let result = engine.place_order(request);
         assert!(result.is_ok());
         
-        let (order, trades) = result.unwrap();
+        let (order, trades, outcome, _) = result.unwrap();
         assert!(trades.is_empty()); // No matching orders
-        assert_eq!(order.market.0, "BTC-PERP");
+        assert_eq!(order.market, MarketId::BtcPerp);
+        assert_eq!(outcome, OrderOutcome::Resting { filled_quantity: Quantity::default() });
+        assert_eq!(order.status, crate::order::OrderStatus::Open);
     }
     
     #[test]
@@ -223,6 +1496,11 @@ mod tests {
             stop_price: None,
             reduce_only: None,
             client_order_id: None,
+            self_trade_policy: None,
+            max_slippage_bps: None,
+            round: None,
+            fallback_limit: None,
+            display_qty: None,
         };
         engine.place_order(sell_request).unwrap();
         
@@ -238,11 +1516,814 @@ mod tests {
             stop_price: None,
             reduce_only: None,
             client_order_id: None,
+            self_trade_policy: None,
+            max_slippage_bps: None,
+            round: None,
+            fallback_limit: None,
+            display_qty: None,
         };
         
-        let (_, trades) = engine.place_order(buy_request).unwrap();
+        let (_, trades, _, _) = engine.place_order(buy_request).unwrap();
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].maker_agent_id, "seller");
         assert_eq!(trades[0].taker_agent_id, "buyer");
     }
+    
+    #[test]
+    fn test_closing_trades_update_agent_reputation() {
+        let engine = MatchingEngine::new();
+        engine.register_agent(Agent::new(AgentId::new("trader"), "wallet".to_string(), "Trader".to_string())).unwrap();
+        assert!(matches!(
+            engine.register_agent(Agent::new(AgentId::new("trader"), "other".to_string(), "Trader".to_string())),
+            Err(EngineError::InvalidAgent(_))
+        ));
+        
+        // Opening fills are not trade results
+        engine.place_order(limit_request("mm", Side::Sell, 50000.0, 1.0)).unwrap();
+        engine.place_order(market_request("trader", Side::Buy, 1.0)).unwrap();
+        assert_eq!(engine.get_agent("trader").unwrap().reputation.total_trades, 0);
+        
+        // Closing at +2% is a win; the unregistered MM is not tracked
+        engine.place_order(limit_request("mm", Side::Buy, 51000.0, 1.0)).unwrap();
+        engine.place_order(market_request("trader", Side::Sell, 1.0)).unwrap();
+        let rep = engine.get_agent("trader").unwrap().reputation;
+        assert_eq!(rep.total_trades, 1);
+        assert_eq!(rep.winning_trades, 1);
+        assert!(rep.total_pnl_usd > 0.0 && rep.total_pnl_usd < 1000.0);
+        assert!(engine.get_agent("mm").is_none());
+        
+        // Closing a short at a loss counts against the agent
+        engine.place_order(limit_request("mm", Side::Buy, 51000.0, 1.0)).unwrap();
+        engine.place_order(market_request("trader", Side::Sell, 1.0)).unwrap();
+        engine.place_order(limit_request("mm", Side::Sell, 52000.0, 1.0)).unwrap();
+        engine.place_order(market_request("trader", Side::Buy, 1.0)).unwrap();
+        let rep = engine.get_agent("trader").unwrap().reputation;
+        assert_eq!(rep.total_trades, 2);
+        assert_eq!(rep.win_rate(), 0.5);
+        assert!(rep.max_drawdown_pct > 0.0);
+    }
+    
+    #[test]
+    fn test_reduce_only_caps_at_open_position() {
+        let engine = MatchingEngine::new();
+        
+        engine.place_order(limit_request("seller", Side::Sell, 50000.0, 1.0)).unwrap();
+        engine.place_order(limit_request("trader", Side::Buy, 50000.0, 1.0)).unwrap();
+        let position = engine.get_position("trader", "BTC-PERP").unwrap().unwrap();
+        assert_eq!(position.size, dec!(1));
+        
+        let mut request = limit_request("trader", Side::Sell, 51000.0, 3.0);
+        request.reduce_only = Some(true);
+        let (order, trades, _, _) = engine.place_order(request).unwrap();
+        
+        assert!(trades.is_empty());
+        assert!(order.reduce_only);
+        assert_eq!(order.quantity.as_decimal(), dec!(1));
+        assert_eq!(order.remaining_quantity.as_decimal(), dec!(1));
+    }
+    
+    #[test]
+    fn test_reduce_only_rejections() {
+        let engine = MatchingEngine::new();
+        
+        // No position at all
+        let mut request = limit_request("trader", Side::Sell, 50000.0, 1.0);
+        request.reduce_only = Some(true);
+        assert!(matches!(engine.place_order(request), Err(EngineError::InvalidOrder(_))));
+        
+        // Same side as an existing long
+        engine.place_order(limit_request("seller", Side::Sell, 50000.0, 1.0)).unwrap();
+        engine.place_order(limit_request("trader", Side::Buy, 50000.0, 1.0)).unwrap();
+        let mut request = limit_request("trader", Side::Buy, 50000.0, 1.0);
+        request.reduce_only = Some(true);
+        assert!(matches!(engine.place_order(request), Err(EngineError::InvalidOrder(_))));
+        
+        // The seller is short and may buy back
+        let mut request = limit_request("seller", Side::Buy, 49000.0, 0.5);
+        request.reduce_only = Some(true);
+        assert!(engine.place_order(request).is_ok());
+    }
+    
+    #[test]
+    fn test_amend_order_checks_ownership() {
+        let engine = MatchingEngine::new();
+        
+        let (order, _, _, _) = engine.place_order(limit_request("maker", Side::Sell, 50000.0, 1.0)).unwrap();
+        
+        let request = AmendOrderRequest {
+            agent_id: "intruder".to_string(),
+            order_id: order.id.0,
+            new_price: 50000.0,
+            new_qty: 0.5,
+        };
+        assert!(matches!(engine.amend_order(request), Err(EngineError::InvalidOrder(_))));
+        
+        let request = AmendOrderRequest {
+            agent_id: "maker".to_string(),
+            order_id: order.id.0,
+            new_price: 50000.0,
+            new_qty: 0.5,
+        };
+        let (amended, trades) = engine.amend_order(request).unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(amended.remaining_quantity.as_decimal(), dec!(0.5));
+    }
+    
+    #[test]
+    fn test_cancel_all_across_markets() {
+        let engine = MatchingEngine::new();
+        
+        engine.place_order(limit_request("agent-a", Side::Buy, 49000.0, 1.0)).unwrap();
+        engine.place_order(limit_request("agent-a", Side::Sell, 51000.0, 1.0)).unwrap();
+        let mut eth = limit_request("agent-a", Side::Buy, 3000.0, 2.0);
+        eth.market = "ETH-PERP".to_string();
+        engine.place_order(eth).unwrap();
+        engine.place_order(limit_request("agent-b", Side::Buy, 48000.0, 1.0)).unwrap();
+        assert_eq!(engine.open_order_ids("agent-a").unwrap().len(), 3);
+        
+        let cancelled = engine.cancel_all_in_market("agent-a", "ETH-PERP").unwrap();
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(engine.open_order_ids("agent-a").unwrap().len(), 2);
+        assert!(engine.get_orderbook("ETH-PERP", 10).unwrap().bids.is_empty());
+        
+        let cancelled = engine.cancel_all("agent-a").unwrap();
+        assert_eq!(cancelled.len(), 2);
+        assert!(engine.open_order_ids("agent-a").unwrap().is_empty());
+        
+        // Only the other agent's bid remains
+        let book = engine.get_orderbook("BTC-PERP", 10).unwrap();
+        assert!(book.asks.is_empty());
+        assert_eq!(book.bids.len(), 1);
+        assert_eq!(book.bids[0].price, Price::from_f64(48000.0));
+        assert_eq!(engine.open_order_ids("agent-b").unwrap().len(), 1);
+    }
+    
+    #[test]
+    fn test_filled_orders_leave_index() {
+        let engine = MatchingEngine::new();
+        
+        let (maker, _, _, _) = engine.place_order(limit_request("maker", Side::Sell, 50000.0, 1.0)).unwrap();
+        engine.place_order(limit_request("taker", Side::Buy, 50000.0, 0.4)).unwrap();
+        assert_eq!(engine.open_order_ids("maker").unwrap(), vec![maker.id]);
+        assert!(engine.open_order_ids("taker").unwrap().is_empty());
+        
+        engine.place_order(limit_request("taker", Side::Buy, 50000.0, 0.6)).unwrap();
+        assert!(engine.open_order_ids("maker").unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_cancel_order_rejects_non_owner() {
+        let engine = MatchingEngine::new();
+        
+        let (order, _, _, _) = engine.place_order(limit_request("owner", Side::Buy, 49000.0, 1.0)).unwrap();
+        let request = CancelOrderRequest { agent_id: "intruder".to_string(), order_id: order.id.0 };
+        assert!(matches!(engine.cancel_order(request), Err(EngineError::InvalidOrder(_))));
+        
+        // The order is still resting
+        assert_eq!(engine.get_orderbook("BTC-PERP", 10).unwrap().bids.len(), 1);
+        let request = CancelOrderRequest { agent_id: "owner".to_string(), order_id: order.id.0 };
+        assert!(engine.cancel_order(request).is_ok());
+    }
+    
+    #[test]
+    fn test_sweep_expired_broadcasts_and_unindexes() {
+        let engine = MatchingEngine::new();
+        let mut events = engine.subscribe();
+        let expiry = Timestamp(Timestamp::now().as_nanos() + 60_000_000_000);
+        
+        let mut request = limit_request("gtd-agent", Side::Buy, 49000.0, 1.0);
+        request.time_in_force = Some(TimeInForce::GTD(expiry));
+        let (order, _, _, _) = engine.place_order(request).unwrap();
+        assert_eq!(order.expires_at, Some(expiry));
+        
+        let expired = engine.sweep_expired(Timestamp(expiry.as_nanos() + 1)).unwrap();
+        assert_eq!(expired.len(), 1);
+        assert!(engine.open_order_ids("gtd-agent").unwrap().is_empty());
+        assert!(engine.get_orderbook("BTC-PERP", 10).unwrap().bids.is_empty());
+        
+        let mut saw_expiry = false;
+        while let Ok(event) = events.try_recv() {
+            if let EngineEvent::OrderExpired { order: o, .. } = event {
+                assert_eq!(o.id, order.id);
+                saw_expiry = true;
+            }
+        }
+        assert!(saw_expiry);
+    }
+    
+    #[test]
+    fn test_expired_and_cancelled_orders_are_told_apart() {
+        let engine = MatchingEngine::new();
+        let expiry = Timestamp(Timestamp::now().as_nanos() + 60_000_000_000);
+        
+        let mut gtd = limit_request("agent", Side::Buy, 49000.0, 1.0);
+        gtd.time_in_force = Some(TimeInForce::GTD(expiry));
+        engine.place_order(gtd).unwrap();
+        let (gtc, _, _, _) = engine.place_order(limit_request("agent", Side::Buy, 48000.0, 1.0)).unwrap();
+        
+        let cancelled = engine.cancel_order(CancelOrderRequest { agent_id: "agent".to_string(), order_id: gtc.id.0 }).unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        let expired = engine.sweep_expired(Timestamp(expiry.as_nanos() + 1)).unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].status, OrderStatus::Expired);
+        
+        let counts = OrderRemovalCounts { cancelled: 1, expired: 1 };
+        assert_eq!(engine.get_market_stats("BTC-PERP").unwrap().removals, counts);
+        assert_eq!(engine.order_removal_counts().unwrap(), counts);
+        assert_eq!(engine.get_market_stats("ETH-PERP").unwrap().removals, OrderRemovalCounts::default());
+    }
+    
+    #[test]
+    fn test_trades_and_snapshots_are_sequenced_per_market() {
+        let engine = MatchingEngine::new();
+        let mut events = engine.subscribe();
+        
+        engine.place_order(limit_request("maker", Side::Sell, 50000.0, 1.0)).unwrap();
+        engine.place_order(limit_request("taker", Side::Buy, 50000.0, 1.0)).unwrap();
+        let mut eth = limit_request("maker", Side::Buy, 3000.0, 1.0);
+        eth.market = "ETH-PERP".to_string();
+        engine.place_order(eth).unwrap();
+        
+        let received: Vec<EngineEvent> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        let btc: Vec<&EngineEvent> = received.iter().filter(|e| *e.market() == MarketId::BtcPerp).collect();
+        
+        // Resting sell -> snapshot; crossing buy -> trade, snapshot
+        assert_eq!(btc.iter().map(|e| e.seq()).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(matches!(btc[1], EngineEvent::Trade { trade, .. } if trade.quantity == Quantity::from_f64(1.0)));
+        assert!(matches!(btc[2], EngineEvent::OrderBookSnapshot { snapshot, .. } if snapshot.asks.is_empty()));
+        
+        // ETH keeps its own sequence
+        let eth: Vec<u64> = received.iter().filter(|e| *e.market() == MarketId::EthPerp).map(|e| e.seq()).collect();
+        assert_eq!(eth, vec![1]);
+    }
+    
+    #[test]
+    fn test_self_trade_cancelled_makers_are_returned_and_published() {
+        let engine = MatchingEngine::new();
+        let (own, ..) = engine.place_order(limit_request("agent", Side::Sell, 50000.0, 1.0)).unwrap();
+        engine.place_order(limit_request("maker", Side::Sell, 50100.0, 1.0)).unwrap();
+        let mut events = engine.subscribe();
+        
+        let request = PlaceOrderRequest {
+            self_trade_policy: Some(crate::order::SelfTradePolicy::CancelMaker),
+            ..limit_request("agent", Side::Buy, 50100.0, 1.0)
+        };
+        let (_, _, _, simulated) = engine.simulate_order(request.clone()).unwrap();
+        let (_, trades, outcome, cancelled) = engine.place_order(request).unwrap();
+        
+        // The agent's own ask is cancelled and the buy fills against the next level
+        assert_eq!(outcome, OrderOutcome::FullyFilled);
+        assert_eq!(trades[0].maker_agent_id, "maker");
+        assert_eq!(cancelled.iter().map(|o| o.id).collect::<Vec<_>>(), vec![own.id]);
+        assert_eq!(simulated.iter().map(|o| o.id).collect::<Vec<_>>(), vec![own.id]);
+        assert!(engine.open_orders("agent").unwrap().is_empty());
+        
+        let received: Vec<EngineEvent> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        assert!(matches!(
+            &received[0],
+            EngineEvent::OrderCancelled { order, reason: CancelReason::SelfTradePrevention, .. } if order.id == own.id
+        ));
+        assert!(matches!(received[1], EngineEvent::Trade { .. }));
+    }
+    
+    #[test]
+    fn test_add_and_remove_market() {
+        let engine = MatchingEngine::new();
+        let link = MarketId::LinkPerp;
+        let config = MarketConfig::new(dec!(0.001), dec!(0.1), dec!(100000), 20);
+        
+        let mut request = limit_request("agent", Side::Buy, 15.0, 10.0);
+        request.market = "LINK-PERP".to_string();
+        assert!(matches!(engine.place_order(request.clone()), Err(EngineError::MarketNotFound(_))));
+        
+        engine.add_market(link, config).unwrap();
+        assert!(engine.markets().contains(&link));
+        assert_eq!(engine.market_config(&MarketId::LinkPerp), Some(config));
+        assert!(matches!(engine.add_market(link, config), Err(EngineError::InvalidMarket(_))));
+        
+        let (order, _, _, _) = engine.place_order(request).unwrap();
+        assert_eq!(engine.get_orderbook("LINK-PERP", 10).unwrap().bids.len(), 1);
+        
+        // A market with resting orders cannot be removed
+        assert!(matches!(engine.remove_market("LINK-PERP"), Err(EngineError::InvalidMarket(_))));
+        engine.cancel_order(CancelOrderRequest { agent_id: "agent".to_string(), order_id: order.id.0 }).unwrap();
+        engine.remove_market("LINK-PERP").unwrap();
+        assert!(!engine.markets().contains(&link));
+        assert_eq!(engine.market_config(&MarketId::LinkPerp), None);
+        assert!(matches!(engine.remove_market("LINK-PERP"), Err(EngineError::MarketNotFound(_))));
+    }
+    
+    #[test]
+    fn test_limit_outside_price_band_rejected() {
+        let engine = MatchingEngine::new();
+        // No mark yet: nothing to compare against
+        engine.place_order(limit_request("mm", Side::Buy, 49900.0, 1.0)).unwrap();
+        engine.place_order(limit_request("mm", Side::Sell, 50100.0, 1.0)).unwrap();
+        
+        // Mark 50000 with the default 10% band: 45000 - 55000
+        let err = engine.place_order(limit_request("agent", Side::Buy, 500000.0, 1.0)).unwrap_err();
+        assert!(matches!(err, EngineError::PriceOutOfBand(_)));
+        assert!(matches!(
+            engine.place_order(limit_request("agent", Side::Buy, 44999.9, 1.0)),
+            Err(EngineError::PriceOutOfBand(_))
+        ));
+        let (inside, trades, _, _) = engine.place_order(limit_request("agent", Side::Sell, 54999.9, 1.0)).unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(inside.status, OrderStatus::Open);
+        
+        // Amending out of the band is rejected too
+        let amend = AmendOrderRequest { agent_id: "agent".to_string(), order_id: inside.id.0, new_price: 60000.0, new_qty: 1.0 };
+        assert!(matches!(engine.amend_order(amend), Err(EngineError::PriceOutOfBand(_))));
+        
+        // Markets listed without a band accept any price
+        let config = MarketConfig::new(dec!(0.001), dec!(0.1), dec!(100000), 20);
+        engine.add_market(MarketId::LinkPerp, config).unwrap();
+        let link = |side, price| PlaceOrderRequest { market: "LINK-PERP".to_string(), ..limit_request("mm", side, price, 1.0) };
+        engine.place_order(link(Side::Buy, 14.0)).unwrap();
+        engine.place_order(link(Side::Sell, 15.0)).unwrap();
+        engine.place_order(link(Side::Sell, 150.0)).unwrap();
+    }
+    
+    #[test]
+    fn test_off_tick_price_rejected_or_rounded() {
+        let engine = MatchingEngine::new();
+        
+        // BTC-PERP ticks at 0.1
+        let err = engine.place_order(limit_request("agent", Side::Buy, 50000.05, 1.0)).unwrap_err();
+        assert!(matches!(err, EngineError::InvalidOrder(ref msg) if msg.contains("tick size")));
+        
+        let mut request = limit_request("agent", Side::Buy, 50000.06, 1.0);
+        request.round = Some(true);
+        let (order, _, _, _) = engine.place_order(request).unwrap();
+        assert_eq!(order.price, Some(Price::new(dec!(50000.1))));
+        
+        let mut request = limit_request("agent", Side::Buy, 49999.95, 1.0);
+        request.round = Some(true);
+        let (order, _, _, _) = engine.place_order(request).unwrap();
+        assert_eq!(order.price, Some(Price::new(dec!(50000.0))));
+        
+        // Amending onto an off-tick price is rejected too
+        let err = engine.amend_order(AmendOrderRequest {
+            agent_id: "agent".to_string(),
+            order_id: order.id.0,
+            new_price: 49000.01,
+            new_qty: 1.0,
+        }).unwrap_err();
+        assert!(matches!(err, EngineError::InvalidOrder(_)));
+    }
+    
+    #[test]
+    fn test_order_size_limits() {
+        let engine = MatchingEngine::new();
+        
+        // BTC-PERP accepts 0.0001 to 1000 per order
+        let err = engine.place_order(limit_request("agent", Side::Buy, 50000.0, 0.00001)).unwrap_err();
+        assert!(matches!(err, EngineError::InvalidOrder(ref msg) if msg.contains("below minimum")));
+        let err = engine.place_order(limit_request("agent", Side::Buy, 50000.0, 1001.0)).unwrap_err();
+        assert!(matches!(err, EngineError::InvalidOrder(ref msg) if msg.contains("above maximum")));
+        
+        assert!(engine.place_order(limit_request("agent", Side::Buy, 50000.0, 0.0001)).is_ok());
+    }
+    
+    #[test]
+    fn test_add_market_rejects_invalid_config() {
+        let engine = MatchingEngine::new();
+        let config = MarketConfig::new(dec!(0), dec!(0.1), dec!(100000), 20);
+        assert!(engine.add_market(MarketId::LinkPerp, config).is_err());
+        assert!(!engine.has_market(&MarketId::LinkPerp));
+    }
+    
+    #[test]
+    fn test_get_order_reports_resting_and_partial_state() {
+        let engine = MatchingEngine::new();
+        
+        let (resting, _, _, _) = engine.place_order(limit_request("maker", Side::Sell, 51000.0, 1.0)).unwrap();
+        let (partial, _, _, _) = engine.place_order(limit_request("maker", Side::Buy, 49000.0, 2.0)).unwrap();
+        engine.place_order(limit_request("taker", Side::Sell, 49000.0, 0.5)).unwrap();
+        
+        let order = engine.get_order(resting.id.0).unwrap();
+        assert_eq!(order.status, crate::order::OrderStatus::Open);
+        assert_eq!(order.remaining_quantity, Quantity::from_f64(1.0));
+        
+        let order = engine.get_order(partial.id.0).unwrap();
+        assert_eq!(order.agent_id, "maker");
+        assert_eq!(order.status, crate::order::OrderStatus::PartiallyFilled);
+        assert_eq!(order.remaining_quantity, Quantity::from_f64(1.5));
+        
+        assert!(engine.get_order(999_999).is_none());
+    }
+    
+    #[test]
+    fn test_open_orders_lists_resting_orders_across_markets() {
+        let engine = MatchingEngine::new();
+        
+        let (btc, _, _, _) = engine.place_order(limit_request("agent", Side::Buy, 49000.0, 1.0)).unwrap();
+        let mut eth = limit_request("agent", Side::Sell, 3000.0, 2.0);
+        eth.market = "ETH-PERP".to_string();
+        eth.client_order_id = Some("my-eth".to_string());
+        let (eth, _, _, _) = engine.place_order(eth).unwrap();
+        engine.place_order(limit_request("other", Side::Buy, 48000.0, 1.0)).unwrap();
+        
+        // Partially fill the BTC bid
+        engine.place_order(limit_request("seller", Side::Sell, 49000.0, 0.25)).unwrap();
+        
+        let orders = engine.open_orders("agent").unwrap();
+        assert_eq!(orders.iter().map(|o| o.id).collect::<Vec<_>>(), vec![btc.id, eth.id]);
+        assert_eq!(orders[0].remaining_quantity, Quantity::from_f64(0.75));
+        assert_eq!(orders[1].client_order_id.as_deref(), Some("my-eth"));
+        assert!(engine.open_orders("nobody").unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_place_order_reports_final_state() {
+        let engine = MatchingEngine::new();
+        
+        engine.place_order(limit_request("seller", Side::Sell, 50000.0, 1.0)).unwrap();
+        
+        let mut request = limit_request("buyer", Side::Buy, 50000.0, 1.5);
+        request.time_in_force = Some(TimeInForce::IOC);
+        let (order, trades, outcome, _) = engine.place_order(request).unwrap();
+        
+        assert_eq!(trades.len(), 1);
+        assert_eq!(outcome, OrderOutcome::PartiallyFilledRemainderCancelled {
+            reason: crate::order::CancelReason::ImmediateOrCancel,
+        });
+        assert_eq!(order.status, crate::order::OrderStatus::Cancelled);
+        assert_eq!(order.remaining_quantity.as_decimal(), dec!(0.5));
+    }
+    
+    #[test]
+    fn test_engine_fee_schedule() {
+        let engine = MatchingEngine::with_fee_schedule(FeeSchedule::new(dec!(-2), dec!(5)));
+        
+        engine.place_order(limit_request("maker", Side::Sell, 50000.0, 0.5)).unwrap();
+        let (_, trades, _, _) = engine.place_order(limit_request("taker", Side::Buy, 50000.0, 0.5)).unwrap();
+        
+        assert_eq!(trades[0].maker_fee, dec!(-5));
+        assert_eq!(trades[0].taker_fee, dec!(12.5));
+    }
+    
+    #[test]
+    fn test_batch_orders_match_within_batch() {
+        let engine = MatchingEngine::new();
+        engine.deposit("mm", dec!(100000)).unwrap();
+        engine.deposit("taker", dec!(100000)).unwrap();
+        
+        let results = engine.place_batch(vec![
+            limit_request("mm", Side::Sell, 50100.0, 0.5),
+            limit_request("mm", Side::Sell, 50200.0, 0.5),
+            limit_request("taker", Side::Buy, 50100.0, 0.5),
+        ], true).unwrap();
+        
+        assert_eq!(results.len(), 3);
+        let (_, trades, _, _) = results[2].as_ref().unwrap();
+        let (first, _, _, _) = results[0].as_ref().unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, first.id);
+    }
+    
+    #[test]
+    fn test_rejected_atomic_batch_leaves_books_untouched() {
+        let engine = MatchingEngine::new();
+        
+        let mut missing_price = limit_request("mm", Side::Sell, 50200.0, 1.0);
+        missing_price.price = None;
+        let result = engine.place_batch(vec![
+            limit_request("mm", Side::Buy, 49900.0, 1.0),
+            limit_request("mm", Side::Sell, 50100.0, 1.0),
+            missing_price,
+        ], true);
+        
+        assert!(matches!(result, Err(EngineError::InvalidOrder(_))));
+        let book = engine.get_orderbook("BTC-PERP", 10).unwrap();
+        assert!(book.bids.is_empty());
+        assert!(book.asks.is_empty());
+        assert!(engine.open_order_ids("mm").unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_atomic_batch_checks_risk_across_legs() {
+        let engine = MatchingEngine::new();
+        // Each 1 BTC bid at 50k needs 5k of initial margin at 10x; together they need 10k
+        engine.deposit("agent", dec!(6000)).unwrap();
+        let legs = || vec![
+            limit_request("agent", Side::Buy, 50000.0, 1.0),
+            limit_request("agent", Side::Buy, 49900.0, 1.0),
+        ];
+        
+        let result = engine.place_batch(legs(), true);
+        assert!(matches!(result, Err(EngineError::RiskLimitExceeded(ref e)) if e.starts_with("Batch order 1")));
+        assert!(engine.get_orderbook("BTC-PERP", 10).unwrap().bids.is_empty());
+        
+        engine.deposit("agent", dec!(5000)).unwrap();
+        let results = engine.place_batch(legs(), true).unwrap();
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(engine.get_orderbook("BTC-PERP", 10).unwrap().bids.len(), 2);
+    }
+    
+    #[test]
+    fn test_non_atomic_batch_reports_each_order() {
+        let engine = MatchingEngine::new();
+        
+        let mut bad_market = limit_request("mm", Side::Sell, 50200.0, 1.0);
+        bad_market.market = "FOO-PERP".to_string();
+        let results = engine.place_batch(vec![
+            limit_request("mm", Side::Buy, 49900.0, 1.0),
+            bad_market,
+        ], false).unwrap();
+        
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(EngineError::MarketNotFound(_))));
+        assert_eq!(engine.get_orderbook("BTC-PERP", 10).unwrap().bids.len(), 1);
+    }
+    
+    #[test]
+    fn test_save_and_load_restores_books_and_counters() {
+        let engine = MatchingEngine::new();
+        engine.place_order(limit_request("mm", Side::Sell, 50100.0, 1.0)).unwrap();
+        engine.place_order(limit_request("mm", Side::Buy, 49900.0, 1.0)).unwrap();
+        let (last, _, _, _) = engine.place_order(limit_request("taker", Side::Buy, 50100.0, 0.4)).unwrap();
+        
+        let path = std::env::temp_dir().join(format!("engine-snapshot-{}.json", std::process::id()));
+        engine.save_all(&path).unwrap();
+        
+        let restored = MatchingEngine::new();
+        restored.load_all(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        
+        assert_eq!(restored.get_bbo("BTC-PERP").unwrap(), engine.get_bbo("BTC-PERP").unwrap());
+        assert_eq!(restored.open_order_ids("mm").unwrap().len(), 2);
+        
+        let (order, trades, _, _) = restored.place_order(limit_request("taker", Side::Buy, 50100.0, 0.1)).unwrap();
+        assert!(order.id.0 > last.id.0);
+        assert_eq!(trades[0].id.0, 1);
+    }
+    
+    #[test]
+    fn test_idempotent_retry_returns_original_result() {
+        let engine = MatchingEngine::new();
+        engine.place_order(limit_request("maker", Side::Sell, 50000.0, 2.0)).unwrap();
+        
+        let request = limit_request("taker", Side::Buy, 50000.0, 1.0);
+        let ((order, trades, _, _), replayed) = engine.place_order_idempotent("retry-1", request.clone()).unwrap();
+        assert!(!replayed);
+        assert_eq!(trades.len(), 1);
+        
+        let ((again, again_trades, _, _), replayed) = engine.place_order_idempotent("retry-1", request).unwrap();
+        assert!(replayed);
+        assert_eq!(again.id, order.id);
+        assert_eq!(again_trades.len(), 1);
+        assert_eq!(again_trades[0].id, trades[0].id);
+        
+        // The retry did not trade again: one of the maker's two units is left
+        let book = engine.get_orderbook("BTC-PERP", 10).unwrap();
+        assert_eq!(book.asks[0].quantity, Quantity::from_f64(1.0));
+        
+        // Keys are per agent
+        let ((other, _, _, _), replayed) = engine
+            .place_order_idempotent("retry-1", limit_request("other", Side::Buy, 49000.0, 1.0))
+            .unwrap();
+        assert!(!replayed);
+        assert_ne!(other.id, order.id);
+    }
+    
+    #[test]
+    fn test_idempotency_key_reuse_with_different_request_conflicts() {
+        let engine = MatchingEngine::new();
+        engine.place_order_idempotent("key", limit_request("agent", Side::Buy, 50000.0, 1.0)).unwrap();
+        
+        let result = engine.place_order_idempotent("key", limit_request("agent", Side::Buy, 50100.0, 1.0));
+        assert!(matches!(result, Err(EngineError::IdempotencyConflict(key)) if key == "key"));
+        assert_eq!(engine.open_orders("agent").unwrap().len(), 1);
+    }
+    
+    #[test]
+    fn test_idempotency_cache_expires_and_stays_bounded() {
+        let mut cache = IdempotencyCache::new();
+        let start = Instant::now();
+        let result = || (
+            Order::new_market(OrderId(1), "a".to_string(), MarketId::BtcPerp, Side::Buy, Quantity::from_f64(1.0)),
+            Vec::new(),
+            OrderOutcome::FullyFilled,
+            Vec::new(),
+        );
+        for i in 0..IDEMPOTENCY_CAPACITY + 5 {
+            cache.evict(start);
+            let key = ("a".to_string(), i.to_string());
+            cache.entries.insert(key.clone(), IdempotencyEntry { request: String::new(), result: result(), placed_at: start });
+            cache.order.push_back((key, start));
+        }
+        assert_eq!(cache.entries.len(), IDEMPOTENCY_CAPACITY);
+        assert!(!cache.entries.contains_key(&("a".to_string(), "0".to_string())));
+        
+        cache.evict(start + IDEMPOTENCY_TTL);
+        assert!(cache.entries.is_empty());
+        assert!(cache.order.is_empty());
+    }
+    
+    fn bracket(agent: &str, quantity: f64) -> OcoRequest {
+        OcoRequest {
+            agent_id: agent.to_string(),
+            market: "BTC-PERP".to_string(),
+            primary: limit_request(agent, Side::Sell, 51000.0, quantity),
+            secondary: PlaceOrderRequest {
+                order_type: OrderType::StopMarket,
+                price: None,
+                stop_price: Some(49000.0),
+                ..limit_request(agent, Side::Sell, 0.0, quantity)
+            },
+        }
+    }
+    
+    #[test]
+    fn test_oco_take_profit_fill_cancels_stop_loss() {
+        let engine = MatchingEngine::new();
+        
+        let ((tp, _, _, _), (sl, _, _, _)) = engine.place_oco(bracket("trader", 1.0)).unwrap();
+        assert_eq!(tp.oco_group, Some(tp.id.0));
+        assert_eq!(sl.oco_group, tp.oco_group);
+        assert_eq!(engine.open_order_ids("trader").unwrap().len(), 2);
+        
+        engine.place_order(limit_request("buyer", Side::Buy, 51000.0, 1.0)).unwrap();
+        
+        assert!(engine.get_order(tp.id.0).is_none());
+        assert!(engine.get_order(sl.id.0).is_none());
+        assert!(engine.open_order_ids("trader").unwrap().is_empty());
+        assert!(engine.oco_links.lock().unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_oco_partial_fill_reduces_sibling() {
+        let engine = MatchingEngine::new();
+        
+        let ((tp, _, _, _), (sl, _, _, _)) = engine.place_oco(bracket("trader", 1.0)).unwrap();
+        engine.place_order(limit_request("buyer", Side::Buy, 51000.0, 0.4)).unwrap();
+        
+        let tp = engine.get_order(tp.id.0).unwrap();
+        let sl = engine.get_order(sl.id.0).unwrap();
+        assert_eq!(tp.remaining_quantity, Quantity::from_f64(0.6));
+        assert_eq!(sl.remaining_quantity, Quantity::from_f64(0.6));
+        
+        // Cancelling one leg leaves the other standing on its own
+        engine.cancel_order(CancelOrderRequest { agent_id: "trader".to_string(), order_id: tp.id.0 }).unwrap();
+        assert!(engine.oco_links.lock().unwrap().is_empty());
+        assert!(engine.get_order(sl.id.0).is_some());
+    }
+    
+    #[test]
+    fn test_oco_secondary_skipped_when_primary_fills_on_arrival() {
+        let engine = MatchingEngine::new();
+        engine.place_order(limit_request("buyer", Side::Buy, 51000.0, 1.0)).unwrap();
+        
+        let ((tp, _, _, _), (sl, trades, outcome, _)) = engine.place_oco(bracket("trader", 1.0)).unwrap();
+        assert_eq!(tp.status, crate::order::OrderStatus::Filled);
+        assert_eq!(sl.status, crate::order::OrderStatus::Cancelled);
+        assert!(trades.is_empty());
+        assert!(matches!(outcome, OrderOutcome::Cancelled { reason: CancelReason::OneCancelsOther }));
+        assert!(engine.open_order_ids("trader").unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_oco_rejects_mismatched_legs() {
+        let engine = MatchingEngine::new();
+        let mut request = bracket("trader", 1.0);
+        request.secondary.agent_id = "someone-else".to_string();
+        
+        assert!(matches!(engine.place_oco(request), Err(EngineError::InvalidOrder(_))));
+        assert!(engine.open_order_ids("trader").unwrap().is_empty());
+    }
+    
+    /// Book with a plain ask, a partly consumed iceberg and a deeper ask
+    fn simulation_engine() -> MatchingEngine {
+        let engine = MatchingEngine::new();
+        engine.place_order(limit_request("maker-1", Side::Sell, 50000.0, 1.0)).unwrap();
+        let mut iceberg = limit_request("maker-2", Side::Sell, 50100.0, 3.0);
+        iceberg.display_qty = Some(1.0);
+        engine.place_order(iceberg).unwrap();
+        engine.place_order(limit_request("maker-3", Side::Sell, 50200.0, 2.0)).unwrap();
+        engine.place_order(limit_request("taker", Side::Buy, 50100.0, 1.5)).unwrap();
+        engine
+    }
+    
+    fn asks_json(engine: &MatchingEngine) -> serde_json::Value {
+        serde_json::to_value(engine.get_orderbook("BTC-PERP", 10).unwrap().asks).unwrap()
+    }
+    
+    fn assert_same_result(simulated: &PlaceOrderResult, real: &PlaceOrderResult) {
+        let (sim_order, sim_trades, sim_outcome, sim_cancelled) = simulated;
+        let (order, trades, outcome, cancelled) = real;
+        assert_eq!(sim_outcome, outcome);
+        let ids = |orders: &[Order]| orders.iter().map(|o| o.id).collect::<Vec<_>>();
+        assert_eq!(ids(sim_cancelled), ids(cancelled));
+        assert_eq!(sim_order.id, order.id);
+        assert_eq!(sim_order.status, order.status);
+        assert_eq!(sim_order.remaining_quantity, order.remaining_quantity);
+        assert_eq!(sim_order.price, order.price);
+        assert_eq!(sim_trades.len(), trades.len());
+        for (sim, real) in sim_trades.iter().zip(trades) {
+            assert_eq!(sim.id, real.id);
+            assert_eq!(sim.price, real.price);
+            assert_eq!(sim.quantity, real.quantity);
+            assert_eq!(sim.maker_order_id, real.maker_order_id);
+            assert_eq!(sim.maker_fee, real.maker_fee);
+            assert_eq!(sim.taker_fee, real.taker_fee);
+        }
+    }
+    
+    #[test]
+    fn test_simulate_order_matches_real_placement() {
+        let requests = [
+            limit_request("agent", Side::Buy, 50200.0, 4.0),
+            limit_request("agent", Side::Buy, 50100.0, 5.0),
+            market_request("agent", Side::Buy, 10.0),
+            limit_request("agent", Side::Sell, 49000.0, 1.0),
+        ];
+        
+        for request in requests {
+            let simulated_engine = simulation_engine();
+            let real_engine = simulation_engine();
+            let asks_before = asks_json(&simulated_engine);
+            
+            let simulated = simulated_engine.simulate_order(request.clone()).unwrap();
+            let real = real_engine.place_order(request).unwrap();
+            assert_same_result(&simulated, &real);
+            
+            // The simulated engine is untouched
+            assert_eq!(asks_json(&simulated_engine), asks_before);
+            assert!(simulated_engine.open_order_ids("agent").unwrap().is_empty());
+            assert!(simulated_engine.get_position("agent", "BTC-PERP").unwrap().is_none());
+        }
+    }
+    
+    #[test]
+    fn test_simulate_order_validates_like_place_order() {
+        let engine = simulation_engine();
+        let mut request = limit_request("agent", Side::Buy, 50000.0, 1.0);
+        request.market = "NOPE-PERP".to_string();
+        assert!(matches!(engine.simulate_order(request), Err(EngineError::MarketNotFound(_))));
+        
+        let mut request = market_request("agent", Side::Buy, 1.0);
+        request.display_qty = Some(0.5);
+        assert!(matches!(engine.simulate_order(request), Err(EngineError::InvalidOrder(_))));
+    }
+    
+    fn rung(side: Side, price: f64, quantity: f64) -> RungSpec {
+        RungSpec { side, price, quantity }
+    }
+    
+    #[test]
+    fn test_ladder_shift_reuses_overlapping_rungs() {
+        let engine = MatchingEngine::new();
+        let ladder = |top: f64| (0..3).map(|i| rung(Side::Buy, top - 0.1 * i as f64, 1.0)).collect::<Vec<_>>();
+        
+        let first = engine.cancel_replace_batch("mm", "BTC-PERP", ladder(49999.9)).unwrap();
+        assert_eq!(first.placed.len(), 3);
+        let id_at = |price: f64| first.placed.iter()
+            .find(|o| o.price == Some(Price::from_f64(price)))
+            .map(|o| o.id)
+            .unwrap();
+        
+        // Down one tick: 49999.8 and 49999.7 stay, 49999.9 goes, 49999.6 is new
+        let shifted = engine.cancel_replace_batch("mm", "BTC-PERP", ladder(49999.8)).unwrap();
+        let mut unchanged = shifted.unchanged.clone();
+        unchanged.sort_by_key(|id| id.0);
+        assert_eq!(unchanged, vec![id_at(49999.8), id_at(49999.7)]);
+        assert_eq!(shifted.cancelled.iter().map(|o| o.id).collect::<Vec<_>>(), vec![id_at(49999.9)]);
+        assert!(shifted.amended.is_empty());
+        assert_eq!(shifted.placed.len(), 1);
+        assert_eq!(shifted.placed[0].price, Some(Price::from_f64(49999.6)));
+        
+        let resting = engine.open_order_ids("mm").unwrap();
+        assert_eq!(resting.len(), 3);
+        assert!(resting.contains(&id_at(49999.8)) && resting.contains(&id_at(49999.7)));
+        
+        // Reposting the same ladder is a no-op
+        let again = engine.cancel_replace_batch("mm", "BTC-PERP", ladder(49999.8)).unwrap();
+        assert_eq!(again.unchanged.len(), 3);
+        assert!(again.cancelled.is_empty() && again.amended.is_empty() && again.placed.is_empty());
+    }
+    
+    #[test]
+    fn test_ladder_resize_keeps_priority_and_validates_first() {
+        let engine = MatchingEngine::new();
+        engine.cancel_replace_batch("mm", "BTC-PERP", vec![rung(Side::Sell, 50100.0, 2.0)]).unwrap();
+        engine.place_order(limit_request("other", Side::Sell, 50100.0, 1.0)).unwrap();
+        
+        // Shrinking the rung keeps it ahead of the other agent's order
+        let resized = engine.cancel_replace_batch("mm", "BTC-PERP", vec![rung(Side::Sell, 50100.0, 1.0)]).unwrap();
+        assert_eq!(resized.amended.len(), 1);
+        assert_eq!(resized.amended[0].remaining_quantity, Quantity::from_f64(1.0));
+        let (_, trades, _, _) = engine.place_order(market_request("taker", Side::Buy, 1.0)).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_agent_id, "mm");
+        
+        // A bad rung rejects the whole ladder before anything changes
+        engine.cancel_replace_batch("mm", "BTC-PERP", vec![rung(Side::Buy, 49000.0, 1.0)]).unwrap();
+        let before = engine.open_order_ids("mm").unwrap();
+        let bad = vec![rung(Side::Buy, 48900.0, 1.0), rung(Side::Buy, 48900.0, 2.0)];
+        assert!(matches!(engine.cancel_replace_batch("mm", "BTC-PERP", bad), Err(EngineError::InvalidOrder(_))));
+        let off_tick = vec![rung(Side::Buy, 48900.05, 1.0)];
+        assert!(matches!(engine.cancel_replace_batch("mm", "BTC-PERP", off_tick), Err(EngineError::InvalidOrder(_))));
+        assert_eq!(engine.open_order_ids("mm").unwrap(), before);
+    }
 }
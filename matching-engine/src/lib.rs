@@ -11,7 +11,7 @@ pub mod api;
 pub mod risk;
 
 pub use orderbook::OrderBook;
-pub use order::{Order, OrderType, Side, TimeInForce};
+pub use order::{CancelReason, Order, OrderOutcome, OrderType, SelfTradePolicy, Side, TimeInForce};
 pub use engine::MatchingEngine;
 pub use types::*;
 pub use agent::{Agent, AgentId};
@@ -1,6 +1,6 @@
 //! AI Perp DEX - Main Entry Point
 
-use ai_perp_dex_matching_engine::{api, MatchingEngine};
+use ai_perp_dex_matching_engine::{api, engine, MatchingEngine};
 use std::sync::Arc;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -19,21 +19,70 @@ async fn main() -> anyhow::Result<()> {
     // Create matching engine
     let engine = Arc::new(MatchingEngine::new());
     
+    // Restore resting orders from the last snapshot, if any
+    let snapshot_path = std::env::var("SNAPSHOT_PATH")
+        .unwrap_or_else(|_| "orderbook_snapshot.json".into());
+    if std::path::Path::new(&snapshot_path).exists() {
+        match engine.load_all(&snapshot_path) {
+            Ok(()) => tracing::info!("💾 Restored orderbooks from {}", snapshot_path),
+            Err(e) => tracing::warn!("Failed to restore orderbooks: {}", e),
+        }
+    }
+    
     tracing::info!(
         "📊 Loaded {} markets: {:?}",
         engine.markets().len(),
-        engine.markets().iter().map(|m| &m.0).collect::<Vec<_>>()
+        engine.markets().iter().map(|m| m.symbol()).collect::<Vec<_>>()
     );
     
+    // Start GTD expiry sweeper (background task)
+    let sweep_engine = engine.clone();
+    tokio::spawn(async move {
+        engine::start_expiry_sweeper(sweep_engine, 1000).await;
+    });
+    
     // Create API router
-    let app = api::create_router(engine);
+    let admin_token = std::env::var("ADMIN_TOKEN").ok();
+    let app = api::create_router(engine.clone(), admin_token);
     
     // Start server
     let addr = std::net::SocketAddr::from(([0, 0, 0, 0], 8080));
     tracing::info!("🌐 Listening on http://{}", addr);
     
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    
+    // Persist resting orders for the next start
+    match engine.save_all(&snapshot_path) {
+        Ok(()) => tracing::info!("💾 Saved orderbooks to {}", snapshot_path),
+        Err(e) => tracing::warn!("Failed to save orderbooks: {}", e),
+    }
     
     Ok(())
 }
+
+/// Resolve on Ctrl-C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("🛑 Shutdown signal received");
+}
@@ -1,6 +1,6 @@
 //! Order types and structures
 
-use crate::types::{Market, OrderId, Price, Quantity, Timestamp};
+use crate::types::{MarketId, OrderId, Price, Quantity, Timestamp};
 use serde::{Deserialize, Serialize};
 
 /// Order side (buy or sell)
@@ -45,6 +45,22 @@ pub enum TimeInForce {
     FOK,
     /// Post Only - only add liquidity, cancel if would take
     PostOnly,
+    /// Good Till Date - rests like GTC until the given time, then expires
+    GTD(Timestamp),
+}
+
+/// How to resolve an incoming order matching against a resting order
+/// from the same agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfTradePolicy {
+    /// Cancel the resting order and keep matching
+    #[default]
+    CancelMaker,
+    /// Cancel the remainder of the incoming order
+    CancelTaker,
+    /// Reduce both orders by the overlapping quantity without trading
+    DecrementBoth,
 }
 
 /// Order status
@@ -65,6 +81,58 @@ pub enum OrderStatus {
     Expired,
 }
 
+/// Why the unfilled remainder of an order was cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelReason {
+    /// IOC/market order could not fill immediately
+    ImmediateOrCancel,
+    /// Average fill price reached the slippage limit
+    SlippageProtection,
+    /// Matched a resting order from the same agent
+    SelfTradePrevention,
+    /// The other leg of its OCO pair filled
+    OneCancelsOther,
+}
+
+/// What happened to an order when it was placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum OrderOutcome {
+    /// Filled completely
+    FullyFilled,
+    /// Resting on the book, possibly after partial fills
+    Resting { filled_quantity: Quantity },
+    /// Held until its stop price trades
+    StopPending,
+    /// Part filled; the remainder was cancelled
+    PartiallyFilledRemainderCancelled { reason: CancelReason },
+    /// Nothing filled; the order was cancelled
+    Cancelled { reason: CancelReason },
+    /// PostOnly order would have taken liquidity
+    RejectedPostOnly,
+    /// FOK order could not be filled in full
+    RejectedFOK,
+    /// Market order ran out of opposite-side liquidity; the remainder was cancelled
+    NoLiquidity { filled_quantity: Quantity, unfilled_quantity: Quantity },
+}
+
+impl OrderOutcome {
+    /// Order status implied by this outcome
+    pub fn status(&self) -> OrderStatus {
+        match self {
+            OrderOutcome::FullyFilled => OrderStatus::Filled,
+            OrderOutcome::Resting { filled_quantity } if filled_quantity.is_zero() => OrderStatus::Open,
+            OrderOutcome::Resting { .. } => OrderStatus::PartiallyFilled,
+            OrderOutcome::StopPending => OrderStatus::Open,
+            OrderOutcome::PartiallyFilledRemainderCancelled { .. }
+            | OrderOutcome::Cancelled { .. }
+            | OrderOutcome::NoLiquidity { .. } => OrderStatus::Cancelled,
+            OrderOutcome::RejectedPostOnly | OrderOutcome::RejectedFOK => OrderStatus::Rejected,
+        }
+    }
+}
+
 /// An order in the system
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
@@ -73,7 +141,7 @@ pub struct Order {
     /// Agent who placed the order
     pub agent_id: String,
     /// Market (e.g., BTC-PERP)
-    pub market: Market,
+    pub market: MarketId,
     /// Buy or Sell
     pub side: Side,
     /// Order type
@@ -98,6 +166,35 @@ pub struct Order {
     pub reduce_only: bool,
     /// Client order ID (optional, for agent tracking)
     pub client_order_id: Option<String>,
+    /// Self-trade prevention applied when this order takes liquidity
+    #[serde(default)]
+    pub self_trade_policy: SelfTradePolicy,
+    /// Expiry time for GTD orders
+    #[serde(default)]
+    pub expires_at: Option<Timestamp>,
+    /// Maximum average fill price deviation from the best opposite price,
+    /// in basis points; the remainder is cancelled once it would be exceeded
+    #[serde(default)]
+    pub max_slippage_bps: Option<u32>,
+    /// Limit price at which a market order's remainder rests if the
+    /// opposite side of the book runs out
+    #[serde(default)]
+    pub fallback_limit: Option<Price>,
+    /// Shared by the two legs of an OCO pair; a fill on one leg cancels the
+    /// same quantity of the other
+    #[serde(default)]
+    pub oco_group: Option<u64>,
+    /// Iceberg orders: size of each slice shown in the book, with the rest
+    /// held back as a hidden reserve
+    #[serde(default)]
+    pub display_qty: Option<Quantity>,
+    /// Iceberg orders: unfilled part of the slice currently shown
+    #[serde(default)]
+    pub display_remaining: Option<Quantity>,
+    /// Time priority within a price level, lowest first. The book assigns a
+    /// fresh value whenever the order joins the back of a queue
+    #[serde(default)]
+    pub sequence: u64,
 }
 
 impl Order {
@@ -105,7 +202,7 @@ impl Order {
     pub fn new_limit(
         id: OrderId,
         agent_id: String,
-        market: Market,
+        market: MarketId,
         side: Side,
         price: Price,
         quantity: Quantity,
@@ -128,6 +225,17 @@ impl Order {
             stop_price: None,
             reduce_only: false,
             client_order_id: None,
+            self_trade_policy: SelfTradePolicy::default(),
+            expires_at: match time_in_force {
+                TimeInForce::GTD(expiry) => Some(expiry),
+                _ => None,
+            },
+            max_slippage_bps: None,
+            fallback_limit: None,
+            oco_group: None,
+            display_qty: None,
+            display_remaining: None,
+            sequence: 0,
         }
     }
     
@@ -135,7 +243,7 @@ impl Order {
     pub fn new_market(
         id: OrderId,
         agent_id: String,
-        market: Market,
+        market: MarketId,
         side: Side,
         quantity: Quantity,
     ) -> Self {
@@ -156,9 +264,55 @@ impl Order {
             stop_price: None,
             reduce_only: false,
             client_order_id: None,
+            self_trade_policy: SelfTradePolicy::default(),
+            expires_at: None,
+            max_slippage_bps: None,
+            fallback_limit: None,
+            oco_group: None,
+            display_qty: None,
+            display_remaining: None,
+            sequence: 0,
         }
     }
     
+    /// Turn this order into a stop order that activates at `stop_price`
+    pub fn with_stop_price(mut self, stop_price: Price) -> Self {
+        self.order_type = match self.order_type {
+            OrderType::Limit | OrderType::StopLimit => OrderType::StopLimit,
+            OrderType::Market | OrderType::StopMarket => OrderType::StopMarket,
+        };
+        self.stop_price = Some(stop_price);
+        self
+    }
+    
+    /// Check if this is a stop order waiting on its trigger
+    pub fn is_stop(&self) -> bool {
+        matches!(self.order_type, OrderType::StopLimit | OrderType::StopMarket)
+    }
+    
+    /// Check if a trade at `last_price` triggers this stop order
+    pub fn is_stop_triggered(&self, last_price: Price) -> bool {
+        match (self.side, self.stop_price) {
+            (Side::Buy, Some(stop)) => last_price >= stop,
+            (Side::Sell, Some(stop)) => last_price <= stop,
+            (_, None) => true,
+        }
+    }
+    
+    /// Convert a triggered stop into the limit or market order it becomes
+    pub fn trigger(&mut self) {
+        match self.order_type {
+            OrderType::StopLimit => self.order_type = OrderType::Limit,
+            OrderType::StopMarket => {
+                self.order_type = OrderType::Market;
+                self.price = None;
+                self.time_in_force = TimeInForce::IOC;
+            }
+            _ => return,
+        }
+        self.updated_at = Timestamp::now();
+    }
+    
     /// Check if order is fully filled
     pub fn is_filled(&self) -> bool {
         self.remaining_quantity.is_zero()
@@ -169,9 +323,31 @@ impl Order {
         matches!(self.status, OrderStatus::Open | OrderStatus::PartiallyFilled)
     }
     
+    /// Quantity shown in the book: the current slice for iceberg orders,
+    /// otherwise everything that is left
+    pub fn visible_quantity(&self) -> Quantity {
+        match self.display_remaining {
+            Some(slice) => std::cmp::min(slice, self.remaining_quantity),
+            None => self.remaining_quantity,
+        }
+    }
+    
+    /// Show a fresh iceberg slice from the hidden reserve
+    pub fn refill_display(&mut self) {
+        self.display_remaining = self.display_qty.map(|d| std::cmp::min(d, self.remaining_quantity));
+    }
+    
+    /// Check if an iceberg's shown slice is used up with hidden size left
+    pub fn needs_refill(&self) -> bool {
+        self.display_remaining.is_some_and(|slice| slice.is_zero()) && !self.is_filled()
+    }
+    
     /// Fill some quantity
     pub fn fill(&mut self, qty: Quantity) {
         self.remaining_quantity -= qty;
+        if let Some(slice) = self.display_remaining.as_mut() {
+            *slice -= std::cmp::min(qty, *slice);
+        }
         self.updated_at = Timestamp::now();
         
         if self.remaining_quantity.is_zero() {
@@ -186,6 +362,17 @@ impl Order {
         self.status = OrderStatus::Cancelled;
         self.updated_at = Timestamp::now();
     }
+    
+    /// Mark the order as expired
+    pub fn expire(&mut self) {
+        self.status = OrderStatus::Expired;
+        self.updated_at = Timestamp::now();
+    }
+    
+    /// Check if the order's GTD expiry has passed at `now`
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires_at.is_some_and(|expiry| expiry <= now)
+    }
 }
 
 /// Request to place a new order
@@ -201,6 +388,44 @@ pub struct PlaceOrderRequest {
     pub stop_price: Option<f64>,
     pub reduce_only: Option<bool>,
     pub client_order_id: Option<String>,
+    pub self_trade_policy: Option<SelfTradePolicy>,
+    pub max_slippage_bps: Option<u32>,
+    /// Snap an off-tick price to the nearest tick instead of rejecting the order
+    pub round: Option<bool>,
+    /// Market orders only: rest the unfilled remainder as a limit at this price
+    /// instead of cancelling it when the book runs out of liquidity
+    pub fallback_limit: Option<f64>,
+    /// Limit orders only: show at most this much at a time, refilling from
+    /// the hidden remainder as each slice fills
+    pub display_qty: Option<f64>,
+}
+
+/// Two linked orders where a fill on either cancels the same quantity of the
+/// other, e.g. a take-profit and stop-loss bracket around a position. Both
+/// legs must be for the request's agent and market.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OcoRequest {
+    pub agent_id: String,
+    pub market: String,
+    pub primary: PlaceOrderRequest,
+    pub secondary: PlaceOrderRequest,
+}
+
+/// Request to amend a resting order's price and remaining quantity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AmendOrderRequest {
+    pub agent_id: String,
+    pub order_id: u64,
+    pub new_price: f64,
+    pub new_qty: f64,
+}
+
+/// One rung of a market maker's quote ladder: a resting limit order at `price`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RungSpec {
+    pub side: Side,
+    pub price: f64,
+    pub quantity: f64,
 }
 
 /// Request to cancel an order
@@ -220,7 +445,7 @@ mod tests {
         let order = Order::new_limit(
             OrderId(1),
             "agent-1".to_string(),
-            Market::btc_perp(),
+            MarketId::BtcPerp,
             Side::Buy,
             Price::new(dec!(50000)),
             Quantity::new(dec!(0.1)),
@@ -237,7 +462,7 @@ mod tests {
         let mut order = Order::new_limit(
             OrderId(1),
             "agent-1".to_string(),
-            Market::btc_perp(),
+            MarketId::BtcPerp,
             Side::Buy,
             Price::new(dec!(50000)),
             Quantity::new(dec!(1.0)),
@@ -252,4 +477,24 @@ mod tests {
         assert_eq!(order.status, OrderStatus::Filled);
         assert!(order.is_filled());
     }
+    
+    #[test]
+    fn test_stop_order_trigger() {
+        let mut order = Order::new_market(
+            OrderId(1),
+            "agent-1".to_string(),
+            MarketId::BtcPerp,
+            Side::Sell,
+            Quantity::new(dec!(1.0)),
+        ).with_stop_price(Price::new(dec!(49000)));
+        
+        assert_eq!(order.order_type, OrderType::StopMarket);
+        assert!(order.is_stop());
+        assert!(!order.is_stop_triggered(Price::new(dec!(49500))));
+        assert!(order.is_stop_triggered(Price::new(dec!(49000))));
+        
+        order.trigger();
+        assert_eq!(order.order_type, OrderType::Market);
+        assert!(!order.is_stop());
+    }
 }
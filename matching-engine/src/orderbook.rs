@@ -1,17 +1,22 @@
 //! Orderbook implementation with price-time priority matching
 
-use crate::order::{Order, Side, TimeInForce};
-use crate::types::{Market, OrderId, Price, PriceLevel, Quantity, OrderBookSnapshot, Timestamp, Trade, TradeId};
+use crate::order::{CancelReason, Order, OrderOutcome, OrderStatus, OrderType, SelfTradePolicy, Side, TimeInForce};
+use crate::types::{FeeSchedule, FillEstimate, MarketId, MarketStats, OrderId, OrderRemovalCounts, Price, PriceLevel, Quantity, OrderBookSnapshot, Timestamp, Trade, TradeId};
 use indexmap::IndexMap;
-use std::collections::{BTreeMap, HashMap};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::atomic::{AtomicU64, Ordering};
 
+/// Length of the rolling market statistics window
+const STATS_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
 /// A single price level in the orderbook
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 struct Level {
     /// Orders at this price level, ordered by time (FIFO)
     orders: IndexMap<OrderId, Order>,
-    /// Total quantity at this level
+    /// Total quantity shown at this level; hidden iceberg reserves are excluded
     total_quantity: Quantity,
 }
 
@@ -23,16 +28,20 @@ impl Level {
         }
     }
     
+    /// Queue an order by its sequence; a newly sequenced order goes last
     fn add_order(&mut self, order: Order) {
-        let qty = order.remaining_quantity;
-        self.orders.insert(order.id, order);
+        let qty = order.visible_quantity();
+        let index = self.orders.values()
+            .rposition(|o| o.sequence <= order.sequence)
+            .map_or(0, |i| i + 1);
+        self.orders.shift_insert(index, order.id, order);
         self.total_quantity = Quantity::new(self.total_quantity.as_decimal() + qty.as_decimal());
     }
     
     fn remove_order(&mut self, order_id: &OrderId) -> Option<Order> {
         if let Some(order) = self.orders.shift_remove(order_id) {
             self.total_quantity = Quantity::new(
-                self.total_quantity.as_decimal() - order.remaining_quantity.as_decimal()
+                self.total_quantity.as_decimal() - order.visible_quantity().as_decimal()
             );
             Some(order)
         } else {
@@ -40,6 +49,24 @@ impl Level {
         }
     }
     
+    /// Show a fresh slice of an iceberg whose current one is used up,
+    /// moving it to the back of the queue under `sequence`. Returns whether
+    /// it was requeued.
+    fn refill_iceberg(&mut self, order_id: &OrderId, sequence: &AtomicU64) -> bool {
+        if !self.orders.get(order_id).is_some_and(|o| o.needs_refill()) {
+            return false;
+        }
+        match self.orders.shift_remove(order_id) {
+            Some(mut order) => {
+                order.refill_display();
+                order.sequence = sequence.fetch_add(1, Ordering::SeqCst) + 1;
+                self.add_order(order);
+                true
+            }
+            None => false,
+        }
+    }
+    
     fn is_empty(&self) -> bool {
         self.orders.is_empty()
     }
@@ -49,10 +76,32 @@ impl Level {
     }
 }
 
+/// Persisted form of an orderbook.
+///
+/// Orders are listed level by level from the lowest price up, each level in
+/// time priority. Queues are rebuilt from each order's `sequence`; snapshots
+/// written before sequences existed restore in listing order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BookState {
+    market: MarketId,
+    bids: Vec<Order>,
+    asks: Vec<Order>,
+    stops: Vec<Order>,
+    sequence: u64,
+    #[serde(default)]
+    order_sequence: u64,
+    trade_counter: u64,
+    last_trade_price: Option<Price>,
+    recent_trades: Vec<(Timestamp, Quantity, Price)>,
+    fees: FeeSchedule,
+    #[serde(default)]
+    removals: OrderRemovalCounts,
+}
+
 /// The orderbook for a single market
 pub struct OrderBook {
     /// Market identifier
-    market: Market,
+    market: MarketId,
     /// Bid levels (sorted descending by price - highest first)
     bids: BTreeMap<Price, Level>,
     /// Ask levels (sorted ascending by price - lowest first)
@@ -61,34 +110,83 @@ pub struct OrderBook {
     orders: HashMap<OrderId, (Price, Side)>,
     /// Sequence number for updates
     sequence: AtomicU64,
+    /// Last time-priority sequence handed to an order
+    order_sequence: AtomicU64,
     /// Trade ID counter
     trade_counter: AtomicU64,
     /// Best bid price
     best_bid: Option<Price>,
     /// Best ask price
     best_ask: Option<Price>,
+    /// Resting orders cancelled by self-trade prevention, drained by the caller
+    cancelled_orders: Vec<Order>,
+    /// Untriggered stop orders by stop price, in arrival order
+    stops: BTreeMap<Price, Vec<Order>>,
+    /// Price of the most recent trade
+    last_trade_price: Option<Price>,
+    /// Trades inside the rolling stats window, oldest first
+    recent_trades: VecDeque<(Timestamp, Quantity, Price)>,
+    /// Maker/taker fees applied to each trade
+    fees: FeeSchedule,
+    /// Orders cancelled or expired so far
+    removals: OrderRemovalCounts,
+}
+
+/// An independent copy, queues and iceberg slices included, for dry-run matching
+impl Clone for OrderBook {
+    fn clone(&self) -> Self {
+        Self {
+            market: self.market,
+            bids: self.bids.clone(),
+            asks: self.asks.clone(),
+            orders: self.orders.clone(),
+            sequence: AtomicU64::new(self.sequence.load(Ordering::SeqCst)),
+            order_sequence: AtomicU64::new(self.order_sequence.load(Ordering::SeqCst)),
+            trade_counter: AtomicU64::new(self.trade_counter.load(Ordering::SeqCst)),
+            best_bid: self.best_bid,
+            best_ask: self.best_ask,
+            cancelled_orders: self.cancelled_orders.clone(),
+            stops: self.stops.clone(),
+            last_trade_price: self.last_trade_price,
+            recent_trades: self.recent_trades.clone(),
+            fees: self.fees,
+            removals: self.removals,
+        }
+    }
 }
 
 impl OrderBook {
     /// Create a new orderbook for a market
-    pub fn new(market: Market) -> Self {
+    pub fn new(market: MarketId) -> Self {
         Self {
             market,
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
             orders: HashMap::new(),
             sequence: AtomicU64::new(0),
+            order_sequence: AtomicU64::new(0),
             trade_counter: AtomicU64::new(0),
             best_bid: None,
             best_ask: None,
+            cancelled_orders: Vec::new(),
+            stops: BTreeMap::new(),
+            last_trade_price: None,
+            recent_trades: VecDeque::new(),
+            fees: FeeSchedule::default(),
+            removals: OrderRemovalCounts::default(),
         }
     }
     
     /// Get the market
-    pub fn market(&self) -> &Market {
+    pub fn market(&self) -> &MarketId {
         &self.market
     }
     
+    /// Set the fee schedule used for new trades
+    pub fn set_fee_schedule(&mut self, fees: FeeSchedule) {
+        self.fees = fees;
+    }
+    
     /// Get best bid price
     pub fn best_bid(&self) -> Option<Price> {
         self.best_bid
@@ -118,63 +216,293 @@ impl OrderBook {
         }
     }
     
+    /// Reference price for sanity checks: the mid price, or the last trade
+    /// while one side of the book is empty
+    pub fn mark_price(&self) -> Option<Price> {
+        self.mid_price().or(self.last_trade_price)
+    }
+    
+    /// Get the last traded price
+    pub fn last_price(&self) -> Option<Price> {
+        self.last_trade_price
+    }
+    
+    /// Get traded volume over the last 24h
+    pub fn volume_24h(&mut self) -> Quantity {
+        self.market_stats(Timestamp::now()).volume_24h
+    }
+    
+    /// Get the highest traded price over the last 24h
+    pub fn high_24h(&mut self) -> Option<Price> {
+        self.market_stats(Timestamp::now()).high_24h
+    }
+    
+    /// Get the lowest traded price over the last 24h
+    pub fn low_24h(&mut self) -> Option<Price> {
+        self.market_stats(Timestamp::now()).low_24h
+    }
+    
+    /// Get rolling 24h statistics as of `now`, dropping trades that have
+    /// fallen out of the window
+    pub fn market_stats(&mut self, now: Timestamp) -> MarketStats {
+        self.prune_recent_trades(now);
+        
+        let volume = self.recent_trades
+            .iter()
+            .fold(Decimal::ZERO, |acc, (_, qty, _)| acc + qty.as_decimal());
+        
+        MarketStats {
+            market: self.market,
+            last_price: self.last_trade_price,
+            volume_24h: Quantity::new(volume),
+            high_24h: self.recent_trades.iter().map(|(_, _, price)| *price).max(),
+            low_24h: self.recent_trades.iter().map(|(_, _, price)| *price).min(),
+            trade_count_24h: self.recent_trades.len() as u64,
+            removals: self.removals,
+            timestamp: now,
+        }
+    }
+    
+    /// Drop trades older than the stats window
+    fn prune_recent_trades(&mut self, now: Timestamp) {
+        let cutoff = now.as_nanos().saturating_sub(STATS_WINDOW_NANOS);
+        while self.recent_trades.front().is_some_and(|(ts, _, _)| ts.as_nanos() < cutoff) {
+            self.recent_trades.pop_front();
+        }
+    }
+    
+    /// Take the resting orders cancelled by self-trade prevention since the
+    /// last call
+    pub fn take_cancelled_orders(&mut self) -> Vec<Order> {
+        std::mem::take(&mut self.cancelled_orders)
+    }
+    
     /// Place an order and return any resulting trades
-    pub fn place_order(&mut self, mut order: Order) -> Vec<Trade> {
-        let mut trades = Vec::new();
+    pub fn place_order(&mut self, order: Order) -> Vec<Trade> {
+        self.place_order_with_outcome(order).0
+    }
+    
+    /// Place an order and return any resulting trades along with what
+    /// happened to the order itself
+    pub fn place_order_with_outcome(&mut self, mut order: Order) -> (Vec<Trade>, OrderOutcome) {
+        order.sequence = self.next_order_sequence();
+        let (mut trades, outcome) = if order.is_stop() {
+            match self.last_trade_price {
+                Some(last) if order.is_stop_triggered(last) => {
+                    order.trigger();
+                    self.execute_order(order)
+                }
+                _ => {
+                    self.add_stop_order(order);
+                    (Vec::new(), OrderOutcome::StopPending)
+                }
+            }
+        } else {
+            self.execute_order(order)
+        };
+        
+        // Trades may have moved the last price through resting stops
+        self.trigger_stops(&mut trades);
+        
+        self.update_best_prices();
+        self.sequence.fetch_add(1, Ordering::SeqCst);
+        
+        (trades, outcome)
+    }
+    
+    /// Match an active order and rest or cancel any remainder per its time in force
+    fn execute_order(&mut self, mut order: Order) -> (Vec<Trade>, OrderOutcome) {
+        // FOK orders must be fully fillable before touching the book
+        if order.time_in_force == TimeInForce::FOK && !self.can_fully_fill(&order) {
+            order.status = OrderStatus::Rejected;
+            return (Vec::new(), OrderOutcome::RejectedFOK);
+        }
+        
+        // PostOnly orders must not take liquidity
+        if order.time_in_force == TimeInForce::PostOnly && self.would_cross(&order) {
+            order.status = OrderStatus::Rejected;
+            return (Vec::new(), OrderOutcome::RejectedPostOnly);
+        }
         
         // Try to match the order
-        trades = self.match_order(&mut order);
+        let (trades, mut cancel_reason) = self.match_order(&mut order);
+        let filled_quantity = Quantity::new(trades.iter().map(|t| t.quantity.as_decimal()).sum());
         
         // If order is still active and not IOC/FOK, add to book
         if order.is_active() && !order.remaining_quantity.is_zero() {
             match order.time_in_force {
-                TimeInForce::IOC => {
-                    order.cancel();
+                TimeInForce::IOC | TimeInForce::FOK if self.market_order_exhausted_book(&order) => {
+                    let unfilled_quantity = order.remaining_quantity;
+                    match order.fallback_limit {
+                        Some(price) => {
+                            order.order_type = OrderType::Limit;
+                            order.price = Some(price);
+                            order.time_in_force = TimeInForce::GTC;
+                            self.add_order_to_book(order);
+                            return (trades, OrderOutcome::Resting { filled_quantity });
+                        }
+                        None => {
+                            order.cancel();
+                            return (trades, OrderOutcome::NoLiquidity { filled_quantity, unfilled_quantity });
+                        }
+                    }
                 }
-                TimeInForce::FOK => {
+                TimeInForce::IOC | TimeInForce::FOK => {
                     // FOK should have been fully filled or rejected
                     order.cancel();
+                    cancel_reason = Some(CancelReason::ImmediateOrCancel);
                 }
-                TimeInForce::PostOnly => {
-                    // PostOnly orders that would have matched are rejected
-                    if !trades.is_empty() {
-                        trades.clear();
-                        order.cancel();
-                    } else {
-                        self.add_order_to_book(order);
-                    }
-                }
-                TimeInForce::GTC => {
+                TimeInForce::GTC | TimeInForce::GTD(_) | TimeInForce::PostOnly => {
                     self.add_order_to_book(order);
+                    return (trades, OrderOutcome::Resting { filled_quantity });
                 }
             }
         }
         
-        self.update_best_prices();
-        self.sequence.fetch_add(1, Ordering::SeqCst);
+        let outcome = match cancel_reason {
+            None => OrderOutcome::FullyFilled,
+            Some(reason) if filled_quantity.is_zero() => OrderOutcome::Cancelled { reason },
+            Some(reason) => OrderOutcome::PartiallyFilledRemainderCancelled { reason },
+        };
+        (trades, outcome)
+    }
+    
+    /// Check whether a market order stopped only because the opposite side is empty
+    fn market_order_exhausted_book(&self, order: &Order) -> bool {
+        let opposite_empty = match order.side {
+            Side::Buy => self.asks.is_empty(),
+            Side::Sell => self.bids.is_empty(),
+        };
+        order.order_type == OrderType::Market && opposite_empty
+    }
+    
+    /// Check whether an order's limit price crosses the opposite side
+    fn would_cross(&self, order: &Order) -> bool {
+        match (order.side, order.price) {
+            (_, None) => true,
+            (Side::Buy, Some(price)) => self.asks.keys().next().is_some_and(|ask| *ask <= price),
+            (Side::Sell, Some(price)) => self.bids.keys().next_back().is_some_and(|bid| *bid >= price),
+        }
+    }
+    
+    /// Hold a stop order until the last trade price reaches its stop price
+    fn add_stop_order(&mut self, order: Order) {
+        let stop_price = order.stop_price.expect("Stop order must have stop price");
+        self.stops.entry(stop_price).or_default().push(order);
+    }
+    
+    /// Activate every stop triggered by the last trade price, repeating while
+    /// the resulting fills trigger further stops
+    fn trigger_stops(&mut self, trades: &mut Vec<Trade>) {
+        while let Some(last) = self.last_trade_price {
+            let triggered = self.take_triggered_stops(last);
+            if triggered.is_empty() {
+                break;
+            }
+            
+            for mut order in triggered {
+                order.trigger();
+                order.sequence = self.next_order_sequence();
+                trades.extend(self.execute_order(order).0);
+            }
+        }
+    }
+    
+    /// Remove triggered stops in the order they were crossed: buy stops from
+    /// the lowest stop price up, sell stops from the highest down, and by
+    /// arrival time within a stop price
+    fn take_triggered_stops(&mut self, last: Price) -> Vec<Order> {
+        let mut triggered = Vec::new();
+        
+        for (_, orders) in self.stops.range_mut(..=last) {
+            let (fired, waiting): (Vec<Order>, Vec<Order>) = std::mem::take(orders)
+                .into_iter()
+                .partition(|o| o.side == Side::Buy);
+            *orders = waiting;
+            triggered.extend(fired);
+        }
+        
+        for (_, orders) in self.stops.range_mut(last..).rev() {
+            let (fired, waiting): (Vec<Order>, Vec<Order>) = std::mem::take(orders)
+                .into_iter()
+                .partition(|o| o.side == Side::Sell);
+            *orders = waiting;
+            triggered.extend(fired);
+        }
+        
+        self.stops.retain(|_, orders| !orders.is_empty());
+        triggered
+    }
+    
+    /// Check whether the opposite side has enough quantity within the
+    /// order's limit price to fill it completely
+    fn can_fully_fill(&self, order: &Order) -> bool {
+        let crosses = |price: &Price| match (order.side, order.price) {
+            (_, None) => true,
+            (Side::Buy, Some(limit)) => *price <= limit,
+            (Side::Sell, Some(limit)) => *price >= limit,
+        };
+        
+        let levels: Vec<&Level> = match order.side {
+            Side::Buy => self.asks.iter()
+                .take_while(|(price, _)| crosses(price))
+                .map(|(_, level)| level)
+                .collect(),
+            Side::Sell => self.bids.iter()
+                .rev()
+                .take_while(|(price, _)| crosses(price))
+                .map(|(_, level)| level)
+                .collect(),
+        };
         
-        trades
+        // Walk makers in priority order; own orders never provide liquidity
+        let needed = order.remaining_quantity.as_decimal();
+        let mut available = rust_decimal::Decimal::ZERO;
+        for maker_order in levels.into_iter().flat_map(|level| level.orders.values()) {
+            if maker_order.agent_id == order.agent_id {
+                match order.self_trade_policy {
+                    SelfTradePolicy::CancelMaker => continue,
+                    SelfTradePolicy::CancelTaker => return false,
+                    SelfTradePolicy::DecrementBoth => {}
+                }
+            }
+            available += maker_order.remaining_quantity.as_decimal();
+            if available >= needed {
+                return true;
+            }
+        }
+        false
     }
     
     /// Match an incoming order against the book
-    fn match_order(&mut self, order: &mut Order) -> Vec<Trade> {
+    fn match_order(&mut self, order: &mut Order) -> (Vec<Trade>, Option<CancelReason>) {
         let mut trades = Vec::new();
+        let mut cancel_reason = None;
         
         let opposite_side = match order.side {
             Side::Buy => &mut self.asks,
             Side::Sell => &mut self.bids,
         };
         
-        let prices_to_remove: Vec<Price> = Vec::new();
-        
         // Get prices to match against
         let matching_prices: Vec<Price> = match order.side {
             Side::Buy => opposite_side.keys().cloned().collect(),
             Side::Sell => opposite_side.keys().rev().cloned().collect(),
         };
         
+        // Average-price bound derived from the best opposite price at entry
+        let slippage_cap = order.max_slippage_bps.zip(matching_prices.first()).map(|(bps, best)| {
+            let tolerance = Decimal::from(bps) / Decimal::from(10_000);
+            match order.side {
+                Side::Buy => best.as_decimal() * (Decimal::ONE + tolerance),
+                Side::Sell => best.as_decimal() * (Decimal::ONE - tolerance),
+            }
+        });
+        let mut filled_qty = Decimal::ZERO;
+        let mut filled_notional = Decimal::ZERO;
+        
         for price in matching_prices {
-            if order.remaining_quantity.is_zero() {
+            if !order.is_active() || order.remaining_quantity.is_zero() {
                 break;
             }
             
@@ -189,32 +517,111 @@ impl OrderBook {
             
             // Match against orders at this price level
             if let Some(level) = opposite_side.get_mut(&price) {
-                let order_ids: Vec<OrderId> = level.orders.keys().cloned().collect();
+                // A lone maker needs no snapshot of the queue; the deque only
+                // allocates if an iceberg refill sends it round again
+                let mut single = None;
+                let mut order_ids: VecDeque<OrderId> = VecDeque::new();
+                if level.orders.len() == 1 {
+                    single = level.orders.keys().next().copied();
+                } else {
+                    order_ids.extend(level.orders.keys().copied());
+                }
+                let mut maker_filled = false;
                 
-                for maker_order_id in order_ids {
-                    if order.remaining_quantity.is_zero() {
+                while let Some(maker_order_id) = single.take().or_else(|| order_ids.pop_front()) {
+                    if !order.is_active() || order.remaining_quantity.is_zero() {
                         break;
                     }
                     
                     if let Some(maker_order) = level.orders.get_mut(&maker_order_id) {
-                        let fill_qty = std::cmp::min(
+                        // Self-trade prevention
+                        if maker_order.agent_id == order.agent_id {
+                            match order.self_trade_policy {
+                                SelfTradePolicy::CancelMaker => {
+                                    if let Some(mut cancelled) = level.remove_order(&maker_order_id) {
+                                        cancelled.cancel();
+                                        self.orders.remove(&maker_order_id);
+                                        self.cancelled_orders.push(cancelled);
+                                    }
+                                }
+                                SelfTradePolicy::CancelTaker => {
+                                    order.cancel();
+                                    cancel_reason = Some(CancelReason::SelfTradePrevention);
+                                }
+                                SelfTradePolicy::DecrementBoth => {
+                                    let overlap = std::cmp::min(
+                                        order.remaining_quantity,
+                                        maker_order.visible_quantity(),
+                                    );
+                                    maker_order.remaining_quantity -= overlap;
+                                    if let Some(slice) = maker_order.display_remaining.as_mut() {
+                                        *slice -= overlap;
+                                    }
+                                    level.total_quantity -= overlap;
+                                    order.remaining_quantity -= overlap;
+                                    if order.remaining_quantity.is_zero() {
+                                        order.cancel();
+                                        cancel_reason = Some(CancelReason::SelfTradePrevention);
+                                    }
+                                    if maker_order.remaining_quantity.is_zero() {
+                                        if let Some(mut cancelled) = level.orders.shift_remove(&maker_order_id) {
+                                            cancelled.cancel();
+                                            self.orders.remove(&maker_order_id);
+                                            self.cancelled_orders.push(cancelled);
+                                        }
+                                    } else if level.refill_iceberg(&maker_order_id, &self.order_sequence) {
+                                        order_ids.push_back(maker_order_id);
+                                    }
+                                }
+                            }
+                            continue;
+                        }
+                        
+                        // Only the shown slice of an iceberg maker is matchable
+                        let mut fill_qty = std::cmp::min(
                             order.remaining_quantity,
-                            maker_order.remaining_quantity,
+                            maker_order.visible_quantity(),
                         );
+                        let mut capped = false;
+                        
+                        // Slippage protection: stop once the average fill
+                        // price would breach the cap and drop the remainder
+                        if let Some(cap) = slippage_cap {
+                            let allowed = max_fill_within_cap(
+                                order.side, cap, filled_qty, filled_notional, price.as_decimal(),
+                            );
+                            if let Some(allowed) = allowed.filter(|a| *a < fill_qty.as_decimal()) {
+                                if allowed.is_zero() {
+                                    order.cancel();
+                                    cancel_reason = Some(CancelReason::SlippageProtection);
+                                    break;
+                                }
+                                fill_qty = Quantity::new(allowed);
+                                capped = true;
+                            }
+                        }
+                        filled_qty += fill_qty.as_decimal();
+                        filled_notional += fill_qty.as_decimal() * price.as_decimal();
                         
                         // Create trade
+                        let (maker_fee, taker_fee) = self.fees.fees_for(price, fill_qty);
                         let trade = Trade {
                             id: TradeId(self.trade_counter.fetch_add(1, Ordering::SeqCst)),
-                            market: self.market.clone(),
+                            market: self.market,
                             price,
                             quantity: fill_qty,
                             maker_order_id,
                             taker_order_id: order.id,
                             maker_agent_id: maker_order.agent_id.clone(),
                             taker_agent_id: order.agent_id.clone(),
+                            taker_side: order.side,
+                            maker_fee,
+                            taker_fee,
                             timestamp: Timestamp::now(),
                         };
                         
+                        self.last_trade_price = Some(price);
+                        self.recent_trades.push_back((trade.timestamp, fill_qty, price));
                         trades.push(trade);
                         
                         // Update quantities
@@ -227,27 +634,49 @@ impl OrderBook {
                         // Remove filled maker order
                         if maker_order.is_filled() {
                             self.orders.remove(&maker_order_id);
+                            maker_filled = true;
+                        }
+                        
+                        // A capped partial fill leaves a remainder we must not chase
+                        if capped {
+                            order.cancel();
+                            cancel_reason = Some(CancelReason::SlippageProtection);
                         }
                     }
+                    
+                    if level.refill_iceberg(&maker_order_id, &self.order_sequence) {
+                        order_ids.push_back(maker_order_id);
+                    }
                 }
                 
                 // Remove filled orders from level
-                level.orders.retain(|_, o| !o.is_filled());
+                if maker_filled {
+                    level.orders.retain(|_, o| !o.is_filled());
+                }
+                if level.is_empty() {
+                    opposite_side.remove(&price);
+                }
+            }
+            
+            // Keep the opposite best price current as levels empty mid-sweep
+            match order.side {
+                Side::Buy => self.best_ask = opposite_side.keys().next().cloned(),
+                Side::Sell => self.best_bid = opposite_side.keys().next_back().cloned(),
             }
         }
         
-        // Remove empty price levels
-        match order.side {
-            Side::Buy => self.asks.retain(|_, level| !level.is_empty()),
-            Side::Sell => self.bids.retain(|_, level| !level.is_empty()),
-        }
-        
-        trades
+        (trades, cancel_reason)
+    }
+    
+    /// Hand out the next time-priority sequence
+    fn next_order_sequence(&self) -> u64 {
+        self.order_sequence.fetch_add(1, Ordering::SeqCst) + 1
     }
     
-    /// Add an order to the orderbook
-    fn add_order_to_book(&mut self, order: Order) {
+    /// Add an order to the orderbook, queued by its existing sequence
+    fn add_order_to_book(&mut self, mut order: Order) {
         let price = order.price.expect("Limit order must have price");
+        order.refill_display();
         let side = order.side;
         let order_id = order.id;
         
@@ -264,29 +693,173 @@ impl OrderBook {
         self.orders.insert(order_id, (price, side));
     }
     
+    /// Amend a resting order's price and remaining quantity.
+    ///
+    /// A size reduction at the same price keeps the order's queue position;
+    /// a price change or size increase sends it to the back of the new level,
+    /// where it may match immediately. Returns the amended order and any trades.
+    pub fn amend_order(
+        &mut self,
+        order_id: &OrderId,
+        new_price: Price,
+        new_qty: Quantity,
+    ) -> Option<(Order, Vec<Trade>)> {
+        let (price, side) = *self.orders.get(order_id)?;
+        let levels = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        let level = levels.get_mut(&price)?;
+        
+        let current = level.orders.get(order_id)?.remaining_quantity;
+        if new_price == price && new_qty <= current {
+            let reduction = current - new_qty;
+            let order = level.orders.get_mut(order_id)?;
+            let shown = order.visible_quantity();
+            order.quantity -= reduction;
+            order.remaining_quantity = new_qty;
+            order.updated_at = Timestamp::now();
+            level.total_quantity -= shown - order.visible_quantity();
+            
+            let amended = order.clone();
+            self.sequence.fetch_add(1, Ordering::SeqCst);
+            return Some((amended, Vec::new()));
+        }
+        
+        // Lose priority: pull the order and resubmit it through matching
+        let mut order = level.remove_order(order_id)?;
+        if level.is_empty() {
+            levels.remove(&price);
+        }
+        self.orders.remove(order_id);
+        
+        let filled = order.quantity - order.remaining_quantity;
+        order.price = Some(new_price);
+        order.quantity = filled + new_qty;
+        order.remaining_quantity = new_qty;
+        order.updated_at = Timestamp::now();
+        order.sequence = self.next_order_sequence();
+        
+        let amended = order.clone();
+        let (mut trades, _) = self.execute_order(order);
+        self.trigger_stops(&mut trades);
+        
+        self.update_best_prices();
+        self.sequence.fetch_add(1, Ordering::SeqCst);
+        
+        Some((amended, trades))
+    }
+    
+    /// Shrink an open order's unfilled quantity by `by`, keeping its queue
+    /// position. An order reduced to nothing is cancelled. Returns the order
+    /// as it now stands.
+    pub fn reduce_order(&mut self, order_id: &OrderId, by: Quantity) -> Option<Order> {
+        let remaining = self.get_order(order_id)?.remaining_quantity;
+        if by >= remaining {
+            return self.cancel_order(order_id);
+        }
+        
+        if let Some(&(price, _)) = self.orders.get(order_id) {
+            return self.amend_order(order_id, price, remaining - by).map(|(order, _)| order);
+        }
+        
+        let order = self.stops.values_mut().flatten().find(|o| o.id == *order_id)?;
+        order.quantity -= by;
+        order.remaining_quantity = remaining - by;
+        order.updated_at = Timestamp::now();
+        let reduced = order.clone();
+        self.sequence.fetch_add(1, Ordering::SeqCst);
+        Some(reduced)
+    }
+    
     /// Cancel an order
     pub fn cancel_order(&mut self, order_id: &OrderId) -> Option<Order> {
-        if let Some((price, side)) = self.orders.remove(order_id) {
-            let levels = match side {
-                Side::Buy => &mut self.bids,
-                Side::Sell => &mut self.asks,
-            };
-            
-            if let Some(level) = levels.get_mut(&price) {
-                let mut order = level.remove_order(order_id)?;
+        let cancelled = match self.remove_resting_order(order_id) {
+            Some(mut order) => {
                 order.cancel();
-                
-                if level.is_empty() {
-                    levels.remove(&price);
-                }
-                
                 self.update_best_prices();
                 self.sequence.fetch_add(1, Ordering::SeqCst);
-                
-                return Some(order);
+                Some(order)
             }
+            None => self.cancel_stop_order(order_id),
+        };
+        if cancelled.is_some() {
+            self.removals.cancelled += 1;
         }
-        None
+        cancelled
+    }
+    
+    /// Orders cancelled or expired since the book was created
+    pub fn removal_counts(&self) -> OrderRemovalCounts {
+        self.removals
+    }
+    
+    /// Remove and return every GTD order whose expiry has passed at `now`
+    pub fn sweep_expired(&mut self, now: Timestamp) -> Vec<Order> {
+        let resting: Vec<OrderId> = self.bids.values()
+            .chain(self.asks.values())
+            .flat_map(|level| level.orders.values())
+            .filter(|order| order.is_expired(now))
+            .map(|order| order.id)
+            .collect();
+        
+        let mut expired: Vec<Order> = resting
+            .iter()
+            .filter_map(|order_id| self.remove_resting_order(order_id))
+            .collect();
+        
+        for orders in self.stops.values_mut() {
+            let (gone, waiting): (Vec<Order>, Vec<Order>) = std::mem::take(orders)
+                .into_iter()
+                .partition(|order| order.is_expired(now));
+            *orders = waiting;
+            expired.extend(gone);
+        }
+        self.stops.retain(|_, orders| !orders.is_empty());
+        
+        if !expired.is_empty() {
+            for order in &mut expired {
+                order.expire();
+            }
+            self.removals.expired += expired.len() as u64;
+            self.update_best_prices();
+            self.sequence.fetch_add(1, Ordering::SeqCst);
+        }
+        
+        expired
+    }
+    
+    /// Pull a resting order out of its level and the order index
+    fn remove_resting_order(&mut self, order_id: &OrderId) -> Option<Order> {
+        let (price, side) = self.orders.remove(order_id)?;
+        let levels = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        
+        let level = levels.get_mut(&price)?;
+        let order = level.remove_order(order_id)?;
+        if level.is_empty() {
+            levels.remove(&price);
+        }
+        Some(order)
+    }
+    
+    /// Cancel an untriggered stop order
+    fn cancel_stop_order(&mut self, order_id: &OrderId) -> Option<Order> {
+        let (stop_price, index) = self.stops.iter().find_map(|(price, orders)| {
+            orders.iter().position(|o| o.id == *order_id).map(|i| (*price, i))
+        })?;
+        
+        let orders = self.stops.get_mut(&stop_price)?;
+        let mut order = orders.remove(index);
+        order.cancel();
+        if orders.is_empty() {
+            self.stops.remove(&stop_price);
+        }
+        
+        self.sequence.fetch_add(1, Ordering::SeqCst);
+        Some(order)
     }
     
     /// Update best bid/ask prices
@@ -319,14 +892,74 @@ impl OrderBook {
             .collect();
         
         OrderBookSnapshot {
-            market: self.market.clone(),
+            market: self.market,
             bids,
             asks,
+            best_bid: self.best_bid,
+            best_ask: self.best_ask,
+            mid_price: self.mid_price(),
+            spread: self.spread().map(Price::new),
             timestamp: Timestamp::now(),
             sequence: self.sequence.load(Ordering::SeqCst),
         }
     }
     
+    /// Get a snapshot with levels merged into `tick_size` price buckets.
+    ///
+    /// Bid prices are rounded down and ask prices up, so every bucket is
+    /// quoted at a price no better than the liquidity it contains.
+    pub fn aggregated_snapshot(&self, tick_size: Decimal, depth: usize) -> OrderBookSnapshot {
+        if tick_size <= Decimal::ZERO {
+            return self.snapshot(depth);
+        }
+        
+        OrderBookSnapshot {
+            market: self.market,
+            bids: aggregate_levels(self.bids.iter().rev(), tick_size, false, depth),
+            asks: aggregate_levels(self.asks.iter(), tick_size, true, depth),
+            best_bid: self.best_bid,
+            best_ask: self.best_ask,
+            mid_price: self.mid_price(),
+            spread: self.spread().map(Price::new),
+            timestamp: Timestamp::now(),
+            sequence: self.sequence.load(Ordering::SeqCst),
+        }
+    }
+    
+    /// Estimate the average price of taking `quantity` on `side` without
+    /// touching the book
+    pub fn estimate_fill(&self, side: Side, quantity: Quantity) -> FillEstimate {
+        let levels: Box<dyn Iterator<Item = (&Price, &Level)>> = match side {
+            Side::Buy => Box::new(self.asks.iter()),
+            Side::Sell => Box::new(self.bids.iter().rev()),
+        };
+        
+        let wanted = quantity.as_decimal();
+        let mut filled = Decimal::ZERO;
+        let mut notional = Decimal::ZERO;
+        let mut worst_price = None;
+        let mut levels_consumed = 0;
+        
+        for (price, level) in levels {
+            if filled >= wanted {
+                break;
+            }
+            let take = std::cmp::min(wanted - filled, level.total_quantity.as_decimal());
+            filled += take;
+            notional += take * price.as_decimal();
+            worst_price = Some(*price);
+            levels_consumed += 1;
+        }
+        
+        FillEstimate {
+            avg_price: (!filled.is_zero()).then(|| Price::new(notional / filled)),
+            filled_qty: Quantity::new(filled),
+            worst_price,
+            levels_consumed,
+            fully_filled: filled >= wanted,
+        }
+    }
+    
     /// Get an order by ID
     pub fn get_order(&self, order_id: &OrderId) -> Option<&Order> {
         if let Some((price, side)) = self.orders.get(order_id) {
@@ -337,21 +970,144 @@ impl OrderBook {
             
             levels.get(price)?.orders.get(order_id)
         } else {
-            None
+            self.stops.values().flatten().find(|o| o.id == *order_id)
+        }
+    }
+    
+    /// Every open order: resting bids and asks, then untriggered stops
+    pub fn open_orders(&self) -> impl Iterator<Item = &Order> {
+        self.bids.values()
+            .chain(self.asks.values())
+            .flat_map(|level| level.orders.values())
+            .chain(self.stops.values().flatten())
+    }
+    
+    /// Capture the full book state, preserving queue order within each level
+    pub(crate) fn to_state(&self) -> BookState {
+        let orders = |levels: &BTreeMap<Price, Level>| -> Vec<Order> {
+            levels.values()
+                .flat_map(|level| level.orders.values().cloned())
+                .collect()
+        };
+        
+        BookState {
+            market: self.market,
+            bids: orders(&self.bids),
+            asks: orders(&self.asks),
+            stops: self.stops.values().flatten().cloned().collect(),
+            sequence: self.sequence.load(Ordering::SeqCst),
+            order_sequence: self.order_sequence.load(Ordering::SeqCst),
+            trade_counter: self.trade_counter.load(Ordering::SeqCst),
+            last_trade_price: self.last_trade_price,
+            recent_trades: self.recent_trades.iter().copied().collect(),
+            fees: self.fees,
+            removals: self.removals,
+        }
+    }
+    
+    /// Rebuild a book from captured state
+    pub(crate) fn from_state(state: BookState) -> Self {
+        let mut book = Self::new(state.market);
+        for order in state.bids.into_iter().chain(state.asks) {
+            book.add_order_to_book(order);
+        }
+        for order in state.stops {
+            book.add_stop_order(order);
+        }
+        // Older snapshots carry no counter; never hand out a sequence already in use
+        let last_order_sequence = book.open_orders().map(|o| o.sequence).max().unwrap_or(0);
+        book.order_sequence = AtomicU64::new(state.order_sequence.max(last_order_sequence));
+        book.sequence = AtomicU64::new(state.sequence);
+        book.trade_counter = AtomicU64::new(state.trade_counter);
+        book.last_trade_price = state.last_trade_price;
+        book.recent_trades = state.recent_trades.into();
+        book.fees = state.fees;
+        book.removals = state.removals;
+        book.update_best_prices();
+        book
+    }
+    
+    /// Serialize the full book state for persistence
+    pub fn to_snapshot_bytes(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(&self.to_state())
+    }
+    
+    /// Restore a book written by [`OrderBook::to_snapshot_bytes`]
+    pub fn from_snapshot_bytes(bytes: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(bytes).map(Self::from_state)
+    }
+}
+
+/// Merge price levels, walked from the top of the book, into tick buckets
+fn aggregate_levels<'a>(
+    levels: impl Iterator<Item = (&'a Price, &'a Level)>,
+    tick_size: Decimal,
+    round_up: bool,
+    depth: usize,
+) -> Vec<PriceLevel> {
+    let mut buckets: Vec<PriceLevel> = Vec::new();
+    
+    for (price, level) in levels {
+        let ticks = price.as_decimal() / tick_size;
+        let ticks = if round_up { ticks.ceil() } else { ticks.floor() };
+        let bucket = Price::new(ticks * tick_size);
+        
+        match buckets.last_mut() {
+            Some(last) if last.price == bucket => {
+                last.quantity = last.quantity + level.total_quantity;
+                last.order_count += level.order_count();
+            }
+            _ => {
+                if buckets.len() == depth {
+                    break;
+                }
+                buckets.push(PriceLevel {
+                    price: bucket,
+                    quantity: level.total_quantity,
+                    order_count: level.order_count(),
+                });
+            }
         }
     }
+    
+    buckets
+}
+
+/// Largest quantity that can be filled at `price` without the running
+/// average fill price crossing `cap`, or None if the whole level fits
+fn max_fill_within_cap(
+    side: Side,
+    cap: Decimal,
+    filled_qty: Decimal,
+    filled_notional: Decimal,
+    price: Decimal,
+) -> Option<Decimal> {
+    let (headroom, excess) = match side {
+        Side::Buy if price > cap => (cap * filled_qty - filled_notional, price - cap),
+        Side::Sell if price < cap => (filled_notional - cap * filled_qty, cap - price),
+        _ => return None,
+    };
+    
+    let allowed = (headroom / excess)
+        .round_dp_with_strategy(8, rust_decimal::RoundingStrategy::ToZero);
+    Some(allowed.max(Decimal::ZERO))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::order::OrderType;
     use rust_decimal_macros::dec;
     
     fn create_test_order(id: u64, side: Side, price: f64, qty: f64) -> Order {
+        create_agent_order(id, "test-agent", side, price, qty)
+    }
+    
+    fn create_agent_order(id: u64, agent: &str, side: Side, price: f64, qty: f64) -> Order {
         Order::new_limit(
             OrderId(id),
-            "test-agent".to_string(),
-            Market::btc_perp(),
+            agent.to_string(),
+            MarketId::BtcPerp,
             side,
             Price::from_f64(price),
             Quantity::from_f64(qty),
@@ -361,7 +1117,7 @@ mod tests {
     
     #[test]
     fn test_add_and_cancel_order() {
-        let mut book = OrderBook::new(Market::btc_perp());
+        let mut book = OrderBook::new(MarketId::BtcPerp);
         
         let order = create_test_order(1, Side::Buy, 50000.0, 1.0);
         let trades = book.place_order(order);
@@ -376,14 +1132,14 @@ mod tests {
     
     #[test]
     fn test_order_matching() {
-        let mut book = OrderBook::new(Market::btc_perp());
+        let mut book = OrderBook::new(MarketId::BtcPerp);
         
         // Add a sell order
         let sell_order = create_test_order(1, Side::Sell, 50000.0, 1.0);
         book.place_order(sell_order);
         
         // Add a matching buy order
-        let buy_order = create_test_order(2, Side::Buy, 50000.0, 0.5);
+        let buy_order = create_agent_order(2, "taker-agent", Side::Buy, 50000.0, 0.5);
         let trades = book.place_order(buy_order);
         
         assert_eq!(trades.len(), 1);
@@ -393,7 +1149,7 @@ mod tests {
     
     #[test]
     fn test_price_time_priority() {
-        let mut book = OrderBook::new(Market::btc_perp());
+        let mut book = OrderBook::new(MarketId::BtcPerp);
         
         // Add two sell orders at same price
         let sell1 = create_test_order(1, Side::Sell, 50000.0, 1.0);
@@ -402,7 +1158,7 @@ mod tests {
         book.place_order(sell2);
         
         // Buy order should match with first sell order (time priority)
-        let buy = create_test_order(3, Side::Buy, 50000.0, 0.5);
+        let buy = create_agent_order(3, "taker-agent", Side::Buy, 50000.0, 0.5);
         let trades = book.place_order(buy);
         
         assert_eq!(trades.len(), 1);
@@ -411,7 +1167,7 @@ mod tests {
     
     #[test]
     fn test_spread_calculation() {
-        let mut book = OrderBook::new(Market::btc_perp());
+        let mut book = OrderBook::new(MarketId::BtcPerp);
         
         book.place_order(create_test_order(1, Side::Buy, 49900.0, 1.0));
         book.place_order(create_test_order(2, Side::Sell, 50100.0, 1.0));
@@ -419,4 +1175,869 @@ mod tests {
         assert_eq!(book.spread(), Some(dec!(200.0)));
         assert_eq!(book.mid_price().map(|p| p.as_decimal()), Some(dec!(50000.0)));
     }
+    
+    fn create_fok_order(id: u64, side: Side, price: f64, qty: f64) -> Order {
+        Order::new_limit(
+            OrderId(id),
+            "fok-agent".to_string(),
+            MarketId::BtcPerp,
+            side,
+            Price::from_f64(price),
+            Quantity::from_f64(qty),
+            TimeInForce::FOK,
+        )
+    }
+    
+    #[test]
+    fn test_fok_fills_across_two_levels_exactly() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_test_order(1, Side::Sell, 50000.0, 1.0));
+        book.place_order(create_test_order(2, Side::Sell, 50100.0, 1.0));
+        
+        let trades = book.place_order(create_fok_order(3, Side::Buy, 50100.0, 2.0));
+        
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price, Price::from_f64(50000.0));
+        assert_eq!(trades[1].price, Price::from_f64(50100.0));
+        assert!(book.best_ask().is_none());
+    }
+    
+    #[test]
+    fn test_fok_one_lot_short_is_rejected_without_fills() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_test_order(1, Side::Sell, 50000.0, 1.0));
+        book.place_order(create_test_order(2, Side::Sell, 50100.0, 0.9));
+        
+        let trades = book.place_order(create_fok_order(3, Side::Buy, 50100.0, 2.0));
+        
+        assert!(trades.is_empty());
+        let snapshot = book.snapshot(10);
+        assert_eq!(snapshot.asks.len(), 2);
+        assert_eq!(snapshot.asks[0].quantity.as_decimal(), dec!(1.0));
+        assert_eq!(snapshot.asks[1].quantity.as_decimal(), dec!(0.9));
+        assert_eq!(
+            book.get_order(&OrderId(1)).map(|o| o.remaining_quantity.as_decimal()),
+            Some(dec!(1.0))
+        );
+    }
+    
+    #[test]
+    fn test_fok_against_empty_book() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        let trades = book.place_order(create_fok_order(1, Side::Sell, 50000.0, 1.0));
+        
+        assert!(trades.is_empty());
+        assert!(book.best_bid().is_none());
+        assert!(book.best_ask().is_none());
+    }
+    
+    fn create_stp_order(id: u64, side: Side, price: f64, qty: f64, policy: SelfTradePolicy) -> Order {
+        let mut order = create_agent_order(id, "agent-a", side, price, qty);
+        order.self_trade_policy = policy;
+        order
+    }
+    
+    #[test]
+    fn test_self_trade_cancel_maker() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_agent_order(1, "agent-a", Side::Sell, 50000.0, 1.0));
+        book.place_order(create_agent_order(2, "agent-b", Side::Sell, 50100.0, 1.0));
+        
+        let trades = book.place_order(
+            create_stp_order(3, Side::Buy, 50100.0, 1.0, SelfTradePolicy::CancelMaker)
+        );
+        
+        // Own quote is pulled and the taker fills against the next maker
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, OrderId(2));
+        let cancelled = book.take_cancelled_orders();
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].id, OrderId(1));
+        assert_eq!(cancelled[0].status, OrderStatus::Cancelled);
+        assert!(book.get_order(&OrderId(1)).is_none());
+        assert!(book.best_ask().is_none());
+        assert!(book.take_cancelled_orders().is_empty());
+    }
+    
+    #[test]
+    fn test_self_trade_cancel_taker() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_agent_order(1, "agent-b", Side::Sell, 50000.0, 0.5));
+        book.place_order(create_agent_order(2, "agent-a", Side::Sell, 50000.0, 1.0));
+        
+        let trades = book.place_order(
+            create_stp_order(3, Side::Buy, 50000.0, 1.0, SelfTradePolicy::CancelTaker)
+        );
+        
+        // Fills the other agent first, then the taker remainder is dropped
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, OrderId(1));
+        assert!(book.take_cancelled_orders().is_empty());
+        assert!(book.best_bid().is_none());
+        assert_eq!(
+            book.get_order(&OrderId(2)).map(|o| o.remaining_quantity.as_decimal()),
+            Some(dec!(1.0))
+        );
+    }
+    
+    #[test]
+    fn test_self_trade_decrement_both() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_agent_order(1, "agent-a", Side::Sell, 50000.0, 1.0));
+        
+        let trades = book.place_order(
+            create_stp_order(2, Side::Buy, 50000.0, 0.4, SelfTradePolicy::DecrementBoth)
+        );
+        
+        assert!(trades.is_empty());
+        assert!(book.take_cancelled_orders().is_empty());
+        assert!(book.best_bid().is_none());
+        let snapshot = book.snapshot(10);
+        assert_eq!(snapshot.asks[0].quantity.as_decimal(), dec!(0.6));
+        assert_eq!(
+            book.get_order(&OrderId(1)).map(|o| o.remaining_quantity.as_decimal()),
+            Some(dec!(0.6))
+        );
+        
+        // A larger taker exhausts the maker, which is reported as cancelled
+        let trades = book.place_order(
+            create_stp_order(3, Side::Buy, 50000.0, 1.0, SelfTradePolicy::DecrementBoth)
+        );
+        assert!(trades.is_empty());
+        let cancelled = book.take_cancelled_orders();
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].id, OrderId(1));
+        assert!(book.best_ask().is_none());
+        assert_eq!(book.best_bid(), Some(Price::from_f64(50000.0)));
+        assert_eq!(book.snapshot(10).bids[0].quantity.as_decimal(), dec!(0.4));
+    }
+    
+    #[test]
+    fn test_sweeping_every_ask_level_clears_best_ask() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        book.place_order(create_agent_order(1, "bidder", Side::Buy, 49000.0, 1.0));
+        for (id, price) in [(2, 50000.0), (3, 50100.0), (4, 50200.0)] {
+            book.place_order(create_agent_order(id, "maker", Side::Sell, price, 1.0));
+        }
+        
+        let trades = book.place_order(create_agent_order(5, "taker", Side::Buy, 50200.0, 3.0));
+        assert_eq!(trades.len(), 3);
+        assert!(book.best_ask().is_none());
+        assert!(book.snapshot(10).asks.is_empty());
+        assert_eq!(book.best_bid(), Some(Price::from_f64(49000.0)));
+        assert!(book.mid_price().is_none());
+    }
+    
+    #[test]
+    fn test_self_trade_after_maker_partially_filled() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_agent_order(1, "agent-a", Side::Sell, 50000.0, 1.0));
+        let trades = book.place_order(create_agent_order(2, "agent-b", Side::Buy, 50000.0, 0.4));
+        assert_eq!(trades.len(), 1);
+        
+        let trades = book.place_order(
+            create_stp_order(3, Side::Buy, 50000.0, 1.0, SelfTradePolicy::CancelMaker)
+        );
+        
+        assert!(trades.is_empty());
+        let cancelled = book.take_cancelled_orders();
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].remaining_quantity.as_decimal(), dec!(0.6));
+        assert!(book.best_ask().is_none());
+        assert_eq!(book.snapshot(10).bids[0].quantity.as_decimal(), dec!(1.0));
+    }
+    
+    #[test]
+    fn test_fok_ignores_own_liquidity() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_agent_order(1, "agent-a", Side::Sell, 50000.0, 1.0));
+        
+        let trades = book.place_order({
+            let mut order = create_stp_order(2, Side::Buy, 50000.0, 1.0, SelfTradePolicy::CancelMaker);
+            order.time_in_force = TimeInForce::FOK;
+            order
+        });
+        
+        assert!(trades.is_empty());
+        assert!(book.take_cancelled_orders().is_empty());
+        assert!(book.get_order(&OrderId(1)).is_some());
+    }
+    
+    fn create_stop_market(id: u64, agent: &str, side: Side, stop: f64, qty: f64) -> Order {
+        Order::new_market(
+            OrderId(id),
+            agent.to_string(),
+            MarketId::BtcPerp,
+            side,
+            Quantity::from_f64(qty),
+        ).with_stop_price(Price::from_f64(stop))
+    }
+    
+    #[test]
+    fn test_sell_stop_triggered_by_downward_trade() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_agent_order(1, "maker", Side::Buy, 50000.0, 1.0));
+        book.place_order(create_agent_order(2, "maker", Side::Buy, 49000.0, 1.0));
+        
+        let stop = create_stop_market(3, "stopper", Side::Sell, 49500.0, 1.0);
+        assert!(book.place_order(stop).is_empty());
+        assert_eq!(book.get_order(&OrderId(3)).map(|o| o.order_type), Some(OrderType::StopMarket));
+        
+        // A trade above the stop leaves it untouched
+        let trades = book.place_order(create_agent_order(4, "seller", Side::Sell, 50000.0, 0.5));
+        assert_eq!(trades.len(), 1);
+        assert!(book.get_order(&OrderId(3)).is_some());
+        
+        // A new buy at 49,400 trades through the stop price
+        book.place_order(create_agent_order(5, "maker", Side::Buy, 49400.0, 1.0));
+        let trades = book.place_order(create_agent_order(6, "seller", Side::Sell, 49400.0, 1.5));
+        
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[1].price, Price::from_f64(49400.0));
+        let stop_fill = &trades[2];
+        assert_eq!(stop_fill.taker_order_id, OrderId(3));
+        assert_eq!(stop_fill.price, Price::from_f64(49000.0));
+        assert_eq!(stop_fill.quantity.as_decimal(), dec!(1));
+        assert!(book.get_order(&OrderId(3)).is_none());
+    }
+    
+    #[test]
+    fn test_buy_stop_cascades_into_second_stop() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_agent_order(1, "maker", Side::Sell, 50000.0, 1.0));
+        book.place_order(create_agent_order(2, "maker", Side::Sell, 50200.0, 1.0));
+        book.place_order(create_agent_order(3, "maker", Side::Sell, 50400.0, 1.0));
+        
+        book.place_order(create_stop_market(4, "stopper-a", Side::Buy, 50000.0, 1.0));
+        book.place_order(create_stop_market(5, "stopper-b", Side::Buy, 50200.0, 1.0));
+        
+        let trades = book.place_order(create_agent_order(6, "taker", Side::Buy, 50000.0, 1.0));
+        
+        // Taker trades at 50,000 -> first stop lifts 50,200 -> second stop lifts 50,400
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[0].taker_order_id, OrderId(6));
+        assert_eq!(trades[1].taker_order_id, OrderId(4));
+        assert_eq!(trades[1].price, Price::from_f64(50200.0));
+        assert_eq!(trades[2].taker_order_id, OrderId(5));
+        assert_eq!(trades[2].price, Price::from_f64(50400.0));
+        assert!(book.best_ask().is_none());
+    }
+    
+    #[test]
+    fn test_cancel_untriggered_stop() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_stop_market(1, "stopper", Side::Buy, 51000.0, 1.0));
+        
+        let cancelled = book.cancel_order(&OrderId(1));
+        assert_eq!(cancelled.map(|o| o.status), Some(OrderStatus::Cancelled));
+        assert!(book.get_order(&OrderId(1)).is_none());
+    }
+    
+    #[test]
+    fn test_slippage_protection_stops_midway_through_second_level() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_agent_order(1, "maker", Side::Sell, 50000.0, 1.0));
+        book.place_order(create_agent_order(2, "maker", Side::Sell, 50150.0, 2.0));
+        
+        // 10 bps over 50,000 caps the average fill price at 50,050
+        let mut order = Order::new_market(
+            OrderId(3),
+            "taker".to_string(),
+            MarketId::BtcPerp,
+            Side::Buy,
+            Quantity::from_f64(3.0),
+        );
+        order.max_slippage_bps = Some(10);
+        let trades = book.place_order(order);
+        
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].quantity.as_decimal(), dec!(1));
+        assert_eq!(trades[1].price, Price::from_f64(50150.0));
+        assert_eq!(trades[1].quantity.as_decimal(), dec!(0.5));
+        
+        let notional: Decimal = trades.iter()
+            .map(|t| t.price.as_decimal() * t.quantity.as_decimal())
+            .sum();
+        assert_eq!(notional / dec!(1.5), dec!(50050));
+        
+        // The rest of the second level is left resting
+        assert_eq!(book.snapshot(10).asks[0].quantity.as_decimal(), dec!(1.5));
+    }
+    
+    #[test]
+    fn test_sell_slippage_protection_bounds_average_price() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_agent_order(1, "maker", Side::Buy, 50000.0, 1.0));
+        book.place_order(create_agent_order(2, "maker", Side::Buy, 45000.0, 1.0));
+        
+        let mut order = create_agent_order(3, "taker", Side::Sell, 40000.0, 2.0);
+        order.max_slippage_bps = Some(50);
+        let trades = book.place_order(order);
+        
+        // Only a sliver of the 45,000 bid fits above the 49,750 average floor
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[1].price, Price::from_f64(45000.0));
+        assert_eq!(trades[1].quantity.as_decimal(), dec!(0.05263157));
+        let filled: Decimal = trades.iter().map(|t| t.quantity.as_decimal()).sum();
+        let notional: Decimal = trades.iter()
+            .map(|t| t.price.as_decimal() * t.quantity.as_decimal())
+            .sum();
+        assert!(notional / filled >= dec!(49750));
+        
+        // The taker remainder is cancelled rather than resting
+        assert!(book.best_ask().is_none());
+        assert_eq!(book.best_bid(), Some(Price::from_f64(45000.0)));
+    }
+    
+    #[test]
+    fn test_amend_size_down_keeps_priority() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_test_order(1, Side::Sell, 50000.0, 1.0));
+        book.place_order(create_test_order(2, Side::Sell, 50000.0, 1.0));
+        
+        let (amended, trades) = book
+            .amend_order(&OrderId(1), Price::from_f64(50000.0), Quantity::from_f64(0.4))
+            .unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(amended.remaining_quantity.as_decimal(), dec!(0.4));
+        assert_eq!(book.snapshot(10).asks[0].quantity.as_decimal(), dec!(1.4));
+        
+        let trades = book.place_order(create_agent_order(3, "taker-agent", Side::Buy, 50000.0, 0.5));
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].maker_order_id, OrderId(1));
+        assert_eq!(trades[0].quantity.as_decimal(), dec!(0.4));
+        assert_eq!(trades[1].maker_order_id, OrderId(2));
+    }
+    
+    #[test]
+    fn test_amend_price_change_loses_priority() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_test_order(1, Side::Sell, 50000.0, 1.0));
+        book.place_order(create_test_order(2, Side::Sell, 50000.0, 1.0));
+        
+        book.amend_order(&OrderId(1), Price::from_f64(50100.0), Quantity::from_f64(1.0)).unwrap();
+        assert_eq!(book.get_order(&OrderId(1)).and_then(|o| o.price), Some(Price::from_f64(50100.0)));
+        assert_eq!(book.snapshot(10).asks.len(), 2);
+        
+        book.amend_order(&OrderId(1), Price::from_f64(50000.0), Quantity::from_f64(1.0)).unwrap();
+        assert_eq!(book.snapshot(10).asks.len(), 1);
+        assert_eq!(book.best_ask(), Some(Price::from_f64(50000.0)));
+        
+        let trades = book.place_order(create_agent_order(3, "taker-agent", Side::Buy, 50000.0, 0.5));
+        assert_eq!(trades[0].maker_order_id, OrderId(2));
+    }
+    
+    #[test]
+    fn test_amend_size_up_requeues_and_can_cross() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        
+        book.place_order(create_test_order(1, Side::Sell, 50000.0, 1.0));
+        book.place_order(create_agent_order(2, "bidder", Side::Buy, 49900.0, 1.0));
+        
+        // Repricing the ask onto the bid trades immediately
+        let (_, trades) = book
+            .amend_order(&OrderId(1), Price::from_f64(49900.0), Quantity::from_f64(1.5))
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].taker_order_id, OrderId(1));
+        assert!(book.best_bid().is_none());
+        assert_eq!(book.best_ask(), Some(Price::from_f64(49900.0)));
+        assert_eq!(book.snapshot(10).asks[0].quantity.as_decimal(), dec!(0.5));
+    }
+    
+    #[test]
+    fn test_market_stats_rolling_window() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        let hour = 60 * 60 * 1_000_000_000u64;
+        let now = Timestamp::now();
+        
+        // Simulated fills 30h, 10h and 1h ago
+        for (hours_ago, qty, price) in [(30, 5.0, 60000.0), (10, 1.0, 49000.0), (1, 2.0, 51000.0)] {
+            book.recent_trades.push_back((
+                Timestamp(now.as_nanos() - hours_ago * hour),
+                Quantity::from_f64(qty),
+                Price::from_f64(price),
+            ));
+        }
+        
+        let stats = book.market_stats(now);
+        assert_eq!(stats.volume_24h.as_decimal(), dec!(3));
+        assert_eq!(stats.high_24h, Some(Price::from_f64(51000.0)));
+        assert_eq!(stats.low_24h, Some(Price::from_f64(49000.0)));
+        assert_eq!(stats.trade_count_24h, 2);
+        assert_eq!(book.recent_trades.len(), 2);
+        
+        // Fifteen hours later only the most recent fill remains
+        let stats = book.market_stats(Timestamp(now.as_nanos() + 15 * hour));
+        assert_eq!(stats.volume_24h.as_decimal(), dec!(2));
+        assert_eq!(stats.low_24h, Some(Price::from_f64(51000.0)));
+    }
+    
+    #[test]
+    fn test_last_price_and_volume_from_matching() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        assert!(book.last_price().is_none());
+        
+        book.place_order(create_agent_order(1, "maker", Side::Sell, 50000.0, 1.0));
+        book.place_order(create_agent_order(2, "maker", Side::Sell, 50100.0, 1.0));
+        book.place_order(create_agent_order(3, "taker", Side::Buy, 50100.0, 1.5));
+        
+        assert_eq!(book.last_price(), Some(Price::from_f64(50100.0)));
+        assert_eq!(book.volume_24h().as_decimal(), dec!(1.5));
+        assert_eq!(book.high_24h(), Some(Price::from_f64(50100.0)));
+        assert_eq!(book.low_24h(), Some(Price::from_f64(50000.0)));
+    }
+    
+    fn fifty_level_book() -> OrderBook {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        for i in 0..50u64 {
+            let offset = i as f64 * 1.5;
+            book.place_order(create_test_order(i * 2 + 1, Side::Buy, 49990.0 - offset, 1.0));
+            book.place_order(create_test_order(i * 2 + 2, Side::Sell, 50010.0 + offset, 0.5));
+        }
+        book
+    }
+    
+    fn total_quantity(levels: &[PriceLevel]) -> Decimal {
+        levels.iter().map(|l| l.quantity.as_decimal()).sum()
+    }
+    
+    fn total_orders(levels: &[PriceLevel]) -> u32 {
+        levels.iter().map(|l| l.order_count).sum()
+    }
+    
+    #[test]
+    fn test_aggregated_snapshot_preserves_totals() {
+        let book = fifty_level_book();
+        let raw = book.snapshot(100);
+        assert_eq!(raw.bids.len(), 50);
+        assert_eq!(raw.asks.len(), 50);
+        
+        let aggregated = book.aggregated_snapshot(dec!(10), 100);
+        assert_eq!(total_quantity(&aggregated.bids), total_quantity(&raw.bids));
+        assert_eq!(total_quantity(&aggregated.asks), total_quantity(&raw.asks));
+        assert_eq!(total_orders(&aggregated.bids), 50);
+        assert_eq!(total_orders(&aggregated.asks), 50);
+        
+        // Buckets are rounded toward the book and strictly ordered
+        assert_eq!(aggregated.bids[0].price, Price::new(dec!(49990)));
+        assert_eq!(aggregated.asks[0].price, Price::new(dec!(50010)));
+        assert_eq!(aggregated.asks[1].price, Price::new(dec!(50020)));
+        assert_eq!(aggregated.asks[1].order_count, 6);
+        assert!(aggregated.bids.windows(2).all(|w| w[0].price > w[1].price));
+        assert!(aggregated.asks.windows(2).all(|w| w[0].price < w[1].price));
+    }
+    
+    #[test]
+    fn test_aggregated_snapshot_fine_and_coarse_ticks() {
+        let book = fifty_level_book();
+        let raw = book.snapshot(100);
+        
+        // Finer than the 1.5 spacing: one bucket per level
+        let fine = book.aggregated_snapshot(dec!(0.5), 100);
+        assert_eq!(fine.bids.len(), 50);
+        assert_eq!(total_quantity(&fine.asks), total_quantity(&raw.asks));
+        
+        // Coarser than the whole book side: a single bucket
+        let coarse = book.aggregated_snapshot(dec!(1000), 100);
+        assert_eq!(coarse.bids.len(), 1);
+        assert_eq!(coarse.bids[0].price, Price::new(dec!(49000)));
+        assert_eq!(coarse.bids[0].quantity.as_decimal(), dec!(50));
+        assert_eq!(coarse.asks[0].price, Price::new(dec!(51000)));
+        assert_eq!(coarse.asks[0].order_count, 50);
+        
+        // Depth limits buckets, not raw levels
+        let shallow = book.aggregated_snapshot(dec!(10), 2);
+        assert_eq!(shallow.asks.len(), 2);
+        assert_eq!(total_orders(&shallow.asks), 7);
+    }
+    
+    fn three_level_ask_book() -> OrderBook {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        book.place_order(create_test_order(1, Side::Sell, 50000.0, 1.0));
+        book.place_order(create_test_order(2, Side::Sell, 50100.0, 1.0));
+        book.place_order(create_test_order(3, Side::Sell, 50200.0, 2.0));
+        book
+    }
+    
+    #[test]
+    fn test_estimate_fill_single_level() {
+        let book = three_level_ask_book();
+        
+        let estimate = book.estimate_fill(Side::Buy, Quantity::from_f64(1.0));
+        assert_eq!(estimate.avg_price, Some(Price::from_f64(50000.0)));
+        assert_eq!(estimate.worst_price, Some(Price::from_f64(50000.0)));
+        assert_eq!(estimate.levels_consumed, 1);
+        assert!(estimate.fully_filled);
+    }
+    
+    #[test]
+    fn test_estimate_fill_spans_three_levels() {
+        let book = three_level_ask_book();
+        
+        let estimate = book.estimate_fill(Side::Buy, Quantity::from_f64(3.0));
+        assert_eq!(estimate.avg_price.map(|p| p.as_decimal()), Some(dec!(50100)));
+        assert_eq!(estimate.worst_price, Some(Price::from_f64(50200.0)));
+        assert_eq!(estimate.levels_consumed, 3);
+        assert_eq!(estimate.filled_qty.as_decimal(), dec!(3));
+        assert!(estimate.fully_filled);
+        
+        // Estimating never mutates the book
+        assert_eq!(book.snapshot(10).asks.len(), 3);
+    }
+    
+    #[test]
+    fn test_estimate_fill_exceeds_liquidity() {
+        let book = three_level_ask_book();
+        
+        let estimate = book.estimate_fill(Side::Buy, Quantity::from_f64(10.0));
+        assert_eq!(estimate.filled_qty.as_decimal(), dec!(4));
+        assert_eq!(estimate.levels_consumed, 3);
+        assert!(!estimate.fully_filled);
+        
+        let estimate = book.estimate_fill(Side::Sell, Quantity::from_f64(1.0));
+        assert!(estimate.avg_price.is_none());
+        assert_eq!(estimate.levels_consumed, 0);
+        assert!(!estimate.fully_filled);
+    }
+    
+    #[test]
+    fn test_gtd_order_expires_on_sweep() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        let expiry = Timestamp(Timestamp::now().as_nanos() + 60_000_000_000);
+        
+        book.place_order(create_test_order(1, Side::Buy, 49000.0, 1.0));
+        book.place_order(Order::new_limit(
+            OrderId(2),
+            "gtd-agent".to_string(),
+            MarketId::BtcPerp,
+            Side::Buy,
+            Price::from_f64(50000.0),
+            Quantity::from_f64(1.0),
+            TimeInForce::GTD(expiry),
+        ));
+        assert_eq!(book.best_bid(), Some(Price::from_f64(50000.0)));
+        
+        // Not yet expired
+        assert!(book.sweep_expired(Timestamp(expiry.as_nanos() - 1)).is_empty());
+        assert!(book.get_order(&OrderId(2)).is_some());
+        
+        let expired = book.sweep_expired(Timestamp(expiry.as_nanos() + 1));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, OrderId(2));
+        assert_eq!(expired[0].status, OrderStatus::Expired);
+        assert!(book.get_order(&OrderId(2)).is_none());
+        
+        // The 50,000 level is gone and the GTC bid is top of book again
+        let snapshot = book.snapshot(10);
+        assert_eq!(snapshot.bids.len(), 1);
+        assert_eq!(book.best_bid(), Some(Price::from_f64(49000.0)));
+    }
+    
+    #[test]
+    fn test_order_outcomes_by_time_in_force() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        book.place_order(create_agent_order(1, "maker", Side::Sell, 50000.0, 1.0));
+        
+        // GTC that fills completely
+        let (_, outcome) = book.place_order_with_outcome(
+            create_agent_order(2, "taker", Side::Buy, 50000.0, 0.4)
+        );
+        assert_eq!(outcome, OrderOutcome::FullyFilled);
+        
+        // IOC that outsizes the book cancels the remainder
+        let mut ioc = create_agent_order(3, "taker", Side::Buy, 50000.0, 1.0);
+        ioc.time_in_force = TimeInForce::IOC;
+        let (trades, outcome) = book.place_order_with_outcome(ioc);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(outcome, OrderOutcome::PartiallyFilledRemainderCancelled {
+            reason: CancelReason::ImmediateOrCancel,
+        });
+        
+        // IOC against an empty side cancels outright
+        let mut ioc = create_agent_order(4, "taker", Side::Buy, 50000.0, 1.0);
+        ioc.time_in_force = TimeInForce::IOC;
+        let (_, outcome) = book.place_order_with_outcome(ioc);
+        assert_eq!(outcome, OrderOutcome::Cancelled { reason: CancelReason::ImmediateOrCancel });
+        
+        // GTC with nothing to match rests
+        let (_, outcome) = book.place_order_with_outcome(
+            create_agent_order(5, "maker", Side::Sell, 50100.0, 1.0)
+        );
+        assert_eq!(outcome, OrderOutcome::Resting { filled_quantity: Quantity::default() });
+        
+        let (_, outcome) = book.place_order_with_outcome(
+            create_agent_order(6, "taker", Side::Buy, 50100.0, 1.5)
+        );
+        assert_eq!(outcome, OrderOutcome::Resting { filled_quantity: Quantity::from_f64(1.0) });
+        assert_eq!(outcome.status(), OrderStatus::PartiallyFilled);
+        
+        // FOK that cannot fill is rejected
+        let (_, outcome) = book.place_order_with_outcome(create_fok_order(7, Side::Sell, 50100.0, 1.0));
+        assert_eq!(outcome, OrderOutcome::RejectedFOK);
+    }
+    
+    #[test]
+    fn test_post_only_rejected_without_touching_book() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        book.place_order(create_agent_order(1, "maker", Side::Sell, 50000.0, 1.0));
+        
+        let mut post_only = create_agent_order(2, "taker", Side::Buy, 50000.0, 0.5);
+        post_only.time_in_force = TimeInForce::PostOnly;
+        let (trades, outcome) = book.place_order_with_outcome(post_only);
+        
+        assert!(trades.is_empty());
+        assert_eq!(outcome, OrderOutcome::RejectedPostOnly);
+        assert_eq!(book.snapshot(10).asks[0].quantity.as_decimal(), dec!(1.0));
+        assert!(book.best_bid().is_none());
+        
+        let mut post_only = create_agent_order(3, "taker", Side::Buy, 49900.0, 0.5);
+        post_only.time_in_force = TimeInForce::PostOnly;
+        let (_, outcome) = book.place_order_with_outcome(post_only);
+        assert_eq!(outcome.status(), OrderStatus::Open);
+        assert_eq!(book.best_bid(), Some(Price::from_f64(49900.0)));
+    }
+    
+    #[test]
+    fn test_snapshot_mid_and_spread_match_book() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        let check = |book: &OrderBook| {
+            let snapshot = book.snapshot(10);
+            assert_eq!(snapshot.best_bid, book.best_bid());
+            assert_eq!(snapshot.best_ask, book.best_ask());
+            assert_eq!(snapshot.mid_price, book.mid_price());
+            assert_eq!(snapshot.spread.map(|s| s.as_decimal()), book.spread());
+            snapshot
+        };
+        
+        // Empty book
+        let snapshot = check(&book);
+        assert!(snapshot.best_bid.is_none() && snapshot.mid_price.is_none() && snapshot.spread.is_none());
+        
+        // Bids only
+        book.place_order(create_agent_order(1, "bidder", Side::Buy, 49900.0, 1.0));
+        let snapshot = check(&book);
+        assert_eq!(snapshot.best_bid, Some(Price::from_f64(49900.0)));
+        assert!(snapshot.mid_price.is_none() && snapshot.spread.is_none());
+        
+        // Two-sided
+        book.place_order(create_agent_order(2, "asker", Side::Sell, 50100.0, 1.0));
+        let snapshot = check(&book);
+        assert_eq!(snapshot.mid_price, Some(Price::new(dec!(50000))));
+        assert_eq!(snapshot.spread, Some(Price::new(dec!(200))));
+        
+        // Bids taken out: asks only
+        book.place_order(create_agent_order(3, "taker", Side::Sell, 49900.0, 1.0));
+        let snapshot = check(&book);
+        assert!(snapshot.best_bid.is_none() && snapshot.mid_price.is_none());
+        assert_eq!(snapshot.best_ask, Some(Price::from_f64(50100.0)));
+        
+        // Aggregated snapshots carry the same top of book
+        let aggregated = book.aggregated_snapshot(dec!(1000), 10);
+        assert_eq!(aggregated.best_ask, book.best_ask());
+    }
+    
+    #[test]
+    fn test_trade_fees() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        book.set_fee_schedule(FeeSchedule::new(dec!(-2), dec!(5)));
+        
+        book.place_order(create_agent_order(1, "maker", Side::Sell, 50000.0, 1.0));
+        let trades = book.place_order(create_agent_order(2, "taker", Side::Buy, 50000.0, 0.5));
+        
+        assert_eq!(trades[0].maker_fee, dec!(-5));
+        assert_eq!(trades[0].taker_fee, dec!(12.5));
+        
+        let json = serde_json::to_value(&trades[0]).unwrap();
+        let parse = |v: &serde_json::Value| v.to_string().trim_matches('"').parse::<Decimal>().unwrap();
+        assert_eq!(parse(&json["maker_fee"]), dec!(-5));
+        assert_eq!(parse(&json["taker_fee"]), dec!(12.5));
+    }
+    
+    #[test]
+    fn test_snapshot_round_trip_preserves_priority() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        book.place_order(create_test_order(1, Side::Buy, 49900.0, 1.0));
+        book.place_order(create_test_order(2, Side::Buy, 49900.0, 2.0));
+        book.place_order(create_test_order(3, Side::Buy, 49800.0, 1.0));
+        book.place_order(create_test_order(4, Side::Sell, 50100.0, 1.5));
+        book.place_order(create_test_order(5, Side::Sell, 50100.0, 0.5));
+        book.place_order(create_stop_market(6, "stop-agent", Side::Sell, 49000.0, 1.0));
+        book.place_order(create_agent_order(7, "taker-agent", Side::Sell, 49900.0, 0.5));
+        
+        let bytes = book.to_snapshot_bytes().unwrap();
+        let mut restored = OrderBook::from_snapshot_bytes(&bytes).unwrap();
+        
+        let before = book.snapshot(10);
+        let after = restored.snapshot(10);
+        assert_eq!(after.sequence, before.sequence);
+        for (a, b) in [(&after.bids, &before.bids), (&after.asks, &before.asks)] {
+            assert_eq!(a.len(), b.len());
+            for (x, y) in a.iter().zip(b.iter()) {
+                assert_eq!((x.price, x.quantity, x.order_count), (y.price, y.quantity, y.order_count));
+            }
+        }
+        assert_eq!(restored.last_price(), book.last_price());
+        assert!(restored.get_order(&OrderId(6)).is_some());
+        
+        // Order 1 was partially filled but keeps its place ahead of order 2
+        let trades = restored.place_order(create_agent_order(8, "taker-agent", Side::Sell, 49900.0, 0.5));
+        assert_eq!(trades[0].maker_order_id, OrderId(1));
+        assert_eq!(trades[0].id, TradeId(1));
+        let trades = restored.place_order(create_agent_order(9, "taker-agent", Side::Sell, 49900.0, 0.5));
+        assert_eq!(trades[0].maker_order_id, OrderId(2));
+    }
+    
+    fn create_iceberg(id: u64, side: Side, price: f64, qty: f64, display: f64) -> Order {
+        let mut order = create_agent_order(id, "iceberg-agent", side, price, qty);
+        order.display_qty = Some(Quantity::from_f64(display));
+        order
+    }
+    
+    #[test]
+    fn test_iceberg_slice_fill_refills_at_back_of_level() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        book.place_order(create_iceberg(1, Side::Sell, 50000.0, 5.0, 1.0));
+        book.place_order(create_test_order(2, Side::Sell, 50000.0, 1.0));
+        assert_eq!(book.snapshot(10).asks[0].quantity.as_decimal(), dec!(2));
+        
+        // Fills exactly the shown slice; the reserve is not touched
+        let trades = book.place_order(create_agent_order(3, "taker-agent", Side::Buy, 50000.0, 1.0));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, OrderId(1));
+        
+        let iceberg = book.get_order(&OrderId(1)).unwrap();
+        assert_eq!(iceberg.remaining_quantity.as_decimal(), dec!(4));
+        assert_eq!(iceberg.visible_quantity().as_decimal(), dec!(1));
+        
+        // The refilled slice queues behind order 2
+        let trades = book.place_order(create_agent_order(4, "taker-agent", Side::Buy, 50000.0, 0.5));
+        assert_eq!(trades[0].maker_order_id, OrderId(2));
+        assert_eq!(book.snapshot(10).asks[0].quantity.as_decimal(), dec!(1.5));
+    }
+    
+    #[test]
+    fn test_iceberg_taker_larger_than_slice_fills_through_refills() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        book.place_order(create_iceberg(1, Side::Sell, 50000.0, 3.0, 1.0));
+        book.place_order(create_test_order(2, Side::Sell, 50100.0, 1.0));
+        
+        let trades = book.place_order(create_agent_order(3, "taker-agent", Side::Buy, 50100.0, 2.5));
+        let quantities: Vec<Decimal> = trades.iter().map(|t| t.quantity.as_decimal()).collect();
+        assert_eq!(quantities, vec![dec!(1), dec!(1), dec!(0.5)]);
+        assert!(trades.iter().all(|t| t.price == Price::from_f64(50000.0)));
+        
+        let snapshot = book.snapshot(10);
+        assert_eq!(snapshot.asks[0].quantity.as_decimal(), dec!(0.5));
+        assert_eq!(snapshot.asks[1].quantity.as_decimal(), dec!(1));
+    }
+    
+    #[test]
+    fn test_iceberg_depth_never_exceeds_display_size() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        book.place_order(create_iceberg(1, Side::Sell, 50000.0, 4.0, 1.0));
+        
+        let mut id = 2;
+        while book.get_order(&OrderId(1)).is_some() {
+            let snapshot = book.snapshot(10);
+            assert!(snapshot.asks[0].quantity.as_decimal() <= dec!(1));
+            assert!(book.estimate_fill(Side::Buy, Quantity::from_f64(10.0)).filled_qty.as_decimal() <= dec!(1));
+            
+            book.place_order(create_agent_order(id, "taker-agent", Side::Buy, 50000.0, 0.3));
+            id += 1;
+        }
+        assert!(book.snapshot(10).asks.is_empty());
+    }
+    
+    #[test]
+    fn test_same_timestamp_orders_fill_in_sequence_order() {
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        let stamp = Timestamp::now();
+        // IDs out of order and identical timestamps: only the sequence can explain the queue
+        let ids: Vec<u64> = (0..50).map(|i| (i * 37) % 101 + 1).collect();
+        for &id in &ids {
+            let mut order = create_agent_order(id, "maker-agent", Side::Sell, 50000.0, 1.0);
+            order.created_at = stamp;
+            order.updated_at = stamp;
+            book.place_order(order);
+        }
+        let sequences: Vec<u64> = ids.iter().map(|id| book.get_order(&OrderId(*id)).unwrap().sequence).collect();
+        assert!(sequences.windows(2).all(|w| w[0] < w[1]));
+        
+        // Restore from a snapshot that lists the level backwards
+        let mut state: serde_json::Value = serde_json::from_slice(&book.to_snapshot_bytes().unwrap()).unwrap();
+        state["asks"].as_array_mut().unwrap().reverse();
+        let mut restored = OrderBook::from_snapshot_bytes(&serde_json::to_vec(&state).unwrap()).unwrap();
+        
+        // Later orders queue behind every restored one
+        restored.place_order(create_agent_order(1000, "maker-agent", Side::Sell, 50000.0, 1.0));
+        assert!(restored.get_order(&OrderId(1000)).unwrap().sequence > *sequences.last().unwrap());
+        
+        let trades = restored.place_order(create_agent_order(2000, "taker-agent", Side::Buy, 50000.0, 51.0));
+        let makers: Vec<u64> = trades.iter().map(|t| t.maker_order_id.0).collect();
+        assert_eq!(makers[..50], ids[..]);
+        assert_eq!(makers[50], 1000);
+    }
+    
+    #[test]
+    fn test_single_and_multi_order_levels_match_identically() {
+        // Pinned results across every level shape the matcher special-cases:
+        // single order, several orders, a refilling iceberg alone at its
+        // level, and a self-trade decrement that fills nothing
+        let mut book = OrderBook::new(MarketId::BtcPerp);
+        book.place_order(create_agent_order(1, "maker-a", Side::Sell, 50000.0, 1.0));
+        book.place_order(create_agent_order(2, "maker-a", Side::Sell, 50100.0, 1.0));
+        book.place_order(create_agent_order(3, "maker-b", Side::Sell, 50100.0, 1.5));
+        book.place_order(create_iceberg(4, Side::Sell, 50200.0, 3.0, 1.0));
+        book.place_order(create_agent_order(5, "taker-agent", Side::Sell, 50300.0, 2.0));
+        book.place_order(create_agent_order(6, "maker-b", Side::Sell, 50300.0, 1.0));
+        
+        let mut taker = create_agent_order(7, "taker-agent", Side::Buy, 50300.0, 8.0);
+        taker.self_trade_policy = SelfTradePolicy::DecrementBoth;
+        let trades = book.place_order(taker);
+        let fills: Vec<(u64, Decimal, Decimal)> = trades.iter()
+            .map(|t| (t.maker_order_id.0, t.price.as_decimal(), t.quantity.as_decimal()))
+            .collect();
+        assert_eq!(fills, vec![
+            (1, dec!(50000), dec!(1)),
+            (2, dec!(50100), dec!(1)),
+            (3, dec!(50100), dec!(1.5)),
+            (4, dec!(50200), dec!(1)),
+            (4, dec!(50200), dec!(1)),
+            (4, dec!(50200), dec!(1)),
+        ]);
+        
+        // 1.5 left for the own order at 50300: both sides are decremented,
+        // the taker is done and the other maker at that level is untouched
+        assert!(book.get_order(&OrderId(7)).is_none());
+        assert_eq!(book.get_order(&OrderId(5)).unwrap().remaining_quantity.as_decimal(), dec!(0.5));
+        assert_eq!(book.get_order(&OrderId(6)).unwrap().remaining_quantity.as_decimal(), dec!(1));
+        let asks: Vec<(Decimal, Decimal)> = book.snapshot(10).asks.iter()
+            .map(|l| (l.price.as_decimal(), l.quantity.as_decimal()))
+            .collect();
+        assert_eq!(asks, vec![(dec!(50300), dec!(1.5))]);
+        assert_eq!(book.best_ask(), Some(Price::from_f64(50300.0)));
+        
+        // A taker that only hits the own order fills nothing and leaves the level intact
+        let mut own = create_agent_order(8, "taker-agent", Side::Buy, 50300.0, 0.25);
+        own.self_trade_policy = SelfTradePolicy::DecrementBoth;
+        assert!(book.place_order(own).is_empty());
+        assert_eq!(book.get_order(&OrderId(5)).unwrap().remaining_quantity.as_decimal(), dec!(0.25));
+        assert_eq!(book.snapshot(10).asks[0].quantity.as_decimal(), dec!(1.25));
+    }
 }
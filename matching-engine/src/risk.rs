@@ -7,7 +7,9 @@
 //! - Daily loss limits
 
 use crate::agent::AgentRiskLimits;
-use crate::types::Market;
+use crate::order::Side;
+use crate::types::{MarketId, Trade};
+use chrono::{DateTime, NaiveDate, Utc};
 use rust_decimal::Decimal;
 use rust_decimal::prelude::Signed;
 use serde::{Deserialize, Serialize};
@@ -35,7 +37,7 @@ pub enum RiskError {
 /// A position in a market
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Position {
-    pub market: Market,
+    pub market: MarketId,
     pub agent_id: String,
     /// Positive = long, negative = short
     pub size: Decimal,
@@ -50,7 +52,7 @@ pub struct Position {
 }
 
 impl Position {
-    pub fn new(market: Market, agent_id: String) -> Self {
+    pub fn new(market: MarketId, agent_id: String) -> Self {
         Self {
             market,
             agent_id,
@@ -86,22 +88,72 @@ impl Position {
         }
     }
     
+    /// Liquidation price implied by the current size, entry and margin
+    pub fn liquidation_price_at(&self, maintenance_margin_rate: Decimal) -> Decimal {
+        if self.is_flat() {
+            return Decimal::ZERO;
+        }
+        
+        // For long: liq_price = entry_price * (1 - margin_rate / leverage)
+        // For short: liq_price = entry_price * (1 + margin_rate / leverage)
+        let leverage = self.leverage();
+        if leverage == Decimal::ZERO {
+            return Decimal::ZERO;
+        }
+        
+        let margin_factor = maintenance_margin_rate / leverage;
+        
+        if self.is_long() {
+            self.entry_price * (Decimal::ONE - margin_factor)
+        } else {
+            self.entry_price * (Decimal::ONE + margin_factor)
+        }
+    }
+    
+    /// Store the liquidation price for the current size, entry and margin
+    pub fn recompute_liquidation(&mut self, maintenance_margin_rate: Decimal) {
+        self.liquidation_price = self.liquidation_price_at(maintenance_margin_rate);
+    }
+    
+    /// Carry margin through a fill from `old_size`: added size posts initial
+    /// margin at the fill price and reduced size releases its share
+    pub fn rebalance_margin(&mut self, old_size: Decimal, fill_price: Decimal, initial_margin_rate: Decimal) {
+        if self.is_flat() {
+            self.margin = Decimal::ZERO;
+        } else if self.size.signum() != old_size.signum() {
+            // New or flipped position
+            self.margin = self.size.abs() * fill_price * initial_margin_rate;
+        } else if self.size.abs() > old_size.abs() {
+            self.margin += (self.size.abs() - old_size.abs()) * fill_price * initial_margin_rate;
+        } else {
+            self.margin = self.margin * self.size.abs() / old_size.abs();
+        }
+    }
+    
     /// Calculate unrealized PnL
     pub fn calculate_pnl(&self, current_price: Decimal) -> Decimal {
         let price_diff = current_price - self.entry_price;
         self.size * price_diff
     }
     
-    /// Update position after a fill
-    pub fn update_after_fill(&mut self, fill_size: Decimal, fill_price: Decimal) {
+    /// Update position after a fill, returning the PnL realized by any
+    /// part of the fill that closed existing size
+    pub fn update_after_fill(&mut self, fill_size: Decimal, fill_price: Decimal) -> Decimal {
         let old_size = self.size;
         let new_size = old_size + fill_size;
         
+        let realized = if old_size.signum() == -fill_size.signum() {
+            let closed = fill_size.abs().min(old_size.abs());
+            closed * (fill_price - self.entry_price) * old_size.signum()
+        } else {
+            Decimal::ZERO
+        };
+        
         if new_size == Decimal::ZERO {
             // Position closed
             self.size = Decimal::ZERO;
             self.entry_price = Decimal::ZERO;
-        } else if old_size.signum() == new_size.signum() || old_size == Decimal::ZERO {
+        } else if old_size.signum() == fill_size.signum() || old_size == Decimal::ZERO {
             // Position increased or new position
             let old_notional = old_size.abs() * self.entry_price;
             let fill_notional = fill_size.abs() * fill_price;
@@ -115,17 +167,42 @@ impl Position {
                 self.entry_price = fill_price;
             }
         }
+        
+        realized
     }
 }
 
+/// The part of a fill that closed existing size
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClosedFill {
+    pub agent_id: String,
+    /// PnL realized by the closed size, net of the whole fill's fee
+    pub realized_pnl: Decimal,
+    /// Closed size at the fill price
+    pub notional: Decimal,
+}
+
+/// Maintenance margin rate applied when none is configured (5%)
+pub const DEFAULT_MAINTENANCE_MARGIN_RATE: Decimal = Decimal::from_parts(5, 0, 0, false, 2);
+
+/// Initial margin rate posted by fills when none is configured (10%, i.e. 10x)
+pub const DEFAULT_INITIAL_MARGIN_RATE: Decimal = Decimal::from_parts(10, 0, 0, false, 2);
+
 /// Risk engine for an agent
+#[derive(Clone)]
 pub struct RiskEngine {
     /// Position by market
-    positions: HashMap<(String, Market), Position>,
+    positions: HashMap<(String, MarketId), Position>,
     /// Account balance
     balances: HashMap<String, Decimal>,
     /// Daily PnL tracking
     daily_pnl: HashMap<String, Decimal>,
+    /// UTC date the daily PnL was last reset
+    trading_day: NaiveDate,
+    /// Maintenance margin rate used for stored liquidation prices
+    maintenance_margin_rate: Decimal,
+    /// Share of filled notional posted as position margin
+    initial_margin_rate: Decimal,
 }
 
 impl RiskEngine {
@@ -134,17 +211,114 @@ impl RiskEngine {
             positions: HashMap::new(),
             balances: HashMap::new(),
             daily_pnl: HashMap::new(),
+            trading_day: Utc::now().date_naive(),
+            maintenance_margin_rate: DEFAULT_MAINTENANCE_MARGIN_RATE,
+            initial_margin_rate: DEFAULT_INITIAL_MARGIN_RATE,
         }
     }
     
     /// Get or create position
-    pub fn get_position(&mut self, agent_id: &str, market: &Market) -> &mut Position {
-        let key = (agent_id.to_string(), market.clone());
+    pub fn get_position(&mut self, agent_id: &str, market: &MarketId) -> &mut Position {
+        let key = (agent_id.to_string(), *market);
         self.positions.entry(key).or_insert_with(|| {
-            Position::new(market.clone(), agent_id.to_string())
+            Position::new(*market, agent_id.to_string())
         })
     }
     
+    /// Get the signed position size without creating an entry
+    pub fn net_position(&self, agent_id: &str, market: &MarketId) -> Decimal {
+        self.positions
+            .get(&(agent_id.to_string(), *market))
+            .map(|p| p.size)
+            .unwrap_or(Decimal::ZERO)
+    }
+    
+    /// Open interest of a market: total long size, which equals the total short size
+    pub fn open_interest(&self, market: &MarketId) -> Decimal {
+        self.positions
+            .values()
+            .filter(|p| p.market == *market && p.size > Decimal::ZERO)
+            .map(|p| p.size)
+            .sum()
+    }
+    
+    /// Apply a trade to both counterparties' positions, returning a
+    /// `ClosedFill` for each side that reduced an existing position
+    pub fn apply_trade(&mut self, trade: &Trade) -> Vec<ClosedFill> {
+        let qty = trade.quantity.as_decimal();
+        let price = trade.price.as_decimal();
+        let taker_delta = match trade.taker_side {
+            Side::Buy => qty,
+            Side::Sell => -qty,
+        };
+        
+        let sides = [
+            (&trade.taker_agent_id, taker_delta, trade.taker_fee),
+            (&trade.maker_agent_id, -taker_delta, trade.maker_fee),
+        ];
+        let mut closes = Vec::new();
+        for (agent_id, delta, fee) in sides {
+            let before = self.net_position(agent_id, &trade.market);
+            let pnl = self.fill_position(agent_id, &trade.market, delta, price) - fee;
+            self.record_realized_pnl(agent_id, pnl);
+            
+            if before.signum() == -delta.signum() {
+                closes.push(ClosedFill {
+                    agent_id: agent_id.clone(),
+                    realized_pnl: pnl,
+                    notional: delta.abs().min(before.abs()) * price,
+                });
+            }
+        }
+        closes
+    }
+    
+    /// Apply a fill to a position, its margin and its liquidation price
+    fn fill_position(&mut self, agent_id: &str, market: &MarketId, size: Decimal, price: Decimal) -> Decimal {
+        let (mmr, imr) = (self.maintenance_margin_rate, self.initial_margin_rate);
+        let position = self.get_position(agent_id, market);
+        let old_size = position.size;
+        let realized = position.update_after_fill(size, price);
+        position.rebalance_margin(old_size, price, imr);
+        position.recompute_liquidation(mmr);
+        realized
+    }
+    
+    /// Set a position's margin and refresh its liquidation price
+    pub fn set_position_margin(&mut self, agent_id: &str, market: &MarketId, margin: Decimal) {
+        let mmr = self.maintenance_margin_rate;
+        let position = self.get_position(agent_id, market);
+        position.margin = margin;
+        position.recompute_liquidation(mmr);
+    }
+    
+    /// Set the maintenance margin rate used for liquidation prices
+    pub fn set_maintenance_margin_rate(&mut self, rate: Decimal) {
+        self.maintenance_margin_rate = rate;
+        for position in self.positions.values_mut() {
+            position.recompute_liquidation(rate);
+        }
+    }
+    
+    /// Set the initial margin rate posted by subsequent fills
+    pub fn set_initial_margin_rate(&mut self, rate: Decimal) {
+        self.initial_margin_rate = rate;
+    }
+    
+    /// Add realized PnL (net of fees) to the agent's daily tracker
+    pub fn record_realized_pnl(&mut self, agent_id: &str, pnl: Decimal) {
+        *self.daily_pnl.entry(agent_id.to_string()).or_insert(Decimal::ZERO) += pnl;
+    }
+    
+    /// Clear daily PnL once `now` falls on a later UTC date than the last reset
+    pub fn roll_day(&mut self, now: DateTime<Utc>) {
+        let today = now.date_naive();
+        if today > self.trading_day {
+            self.daily_pnl.clear();
+            self.trading_day = today;
+        }
+    }
+    
     /// Get agent balance
     pub fn get_balance(&self, agent_id: &str) -> Decimal {
         self.balances.get(agent_id).cloned().unwrap_or(Decimal::ZERO)
@@ -174,15 +348,22 @@ impl RiskEngine {
         Ok(())
     }
     
-    /// Check if order passes risk checks
+    /// Check if order passes risk checks.
+    ///
+    /// `size` is signed (positive buys, negative sells) and is applied to the
+    /// agent's existing position in `market`; limits apply to the resulting
+    /// position. Orders that only shrink the position skip the size, margin
+    /// and leverage checks so agents can always de-risk.
     pub fn check_order(
-        &self,
+        &mut self,
         agent_id: &str,
-        _market: &Market,
-        _size: Decimal,
-        _price: Decimal,
+        market: &MarketId,
+        size: Decimal,
+        price: Decimal,
         limits: &AgentRiskLimits,
     ) -> Result<(), RiskError> {
+        self.roll_day(Utc::now());
+        
         // Check daily loss limit
         let daily_loss = self.daily_pnl.get(agent_id).cloned().unwrap_or(Decimal::ZERO);
         if daily_loss < Decimal::ZERO {
@@ -195,11 +376,69 @@ impl RiskEngine {
             }
         }
         
-        // TODO: Add more risk checks
-        // - Position size limits
-        // - Leverage limits
-        // - Margin requirements
+        let current = self.net_position(agent_id, market);
+        let resulting = current + size;
+        if resulting.abs() <= current.abs() {
+            return Ok(());
+        }
+        
+        // Check position size limit
+        let notional = resulting.abs() * price;
+        if notional > to_decimal(limits.max_position_usd) {
+            return Err(RiskError::PositionLimitExceeded {
+                max: limits.max_position_usd,
+                requested: to_f64(notional),
+            });
+        }
         
+        // Check initial margin for the added exposure against free balance
+        let max_leverage = to_decimal(limits.max_leverage);
+        let balance = self.get_balance(agent_id);
+        let used_margin: Decimal = self.positions
+            .values()
+            .filter(|p| p.agent_id == agent_id)
+            .map(|p| p.margin)
+            .sum();
+        let available = balance - used_margin;
+        let required = (resulting.abs() - current.abs()) * price / max_leverage;
+        if required > available {
+            return Err(RiskError::InsufficientMargin {
+                required: to_f64(required),
+                available: to_f64(available),
+            });
+        }
+        
+        // Check account leverage after the order
+        let other_notional: Decimal = self.positions
+            .values()
+            .filter(|p| p.agent_id == agent_id && p.market != *market)
+            .map(|p| p.notional_value(p.entry_price))
+            .sum();
+        let leverage = (other_notional + notional)
+            .checked_div(balance)
+            .unwrap_or(Decimal::MAX);
+        if leverage > max_leverage {
+            return Err(RiskError::LeverageLimitExceeded {
+                max: limits.max_leverage,
+                requested: to_f64(leverage),
+            });
+        }
+        
+        Ok(())
+    }
+    
+    /// Check orders in sequence, each against the positions and margin the
+    /// earlier ones would leave if they filled in full. Fails with the index
+    /// of the first order that breaks a limit
+    pub fn check_order_sequence<'a>(
+        &self,
+        orders: impl IntoIterator<Item = (&'a str, MarketId, Decimal, Decimal, &'a AgentRiskLimits)>,
+    ) -> Result<(), (usize, RiskError)> {
+        let mut scratch = self.clone();
+        for (i, (agent_id, market, size, price, limits)) in orders.into_iter().enumerate() {
+            scratch.check_order(agent_id, &market, size, price, limits).map_err(|e| (i, e))?;
+            scratch.fill_position(agent_id, &market, size, price);
+        }
         Ok(())
     }
     
@@ -209,24 +448,34 @@ impl RiskEngine {
         position: &Position,
         maintenance_margin_rate: Decimal,
     ) -> Decimal {
-        if position.is_flat() {
-            return Decimal::ZERO;
-        }
-        
-        // For long: liq_price = entry_price * (1 - margin_rate / leverage)
-        // For short: liq_price = entry_price * (1 + margin_rate / leverage)
-        let leverage = position.leverage();
-        if leverage == Decimal::ZERO {
-            return Decimal::ZERO;
-        }
-        
-        let margin_factor = maintenance_margin_rate / leverage;
-        
-        if position.is_long() {
-            position.entry_price * (Decimal::ONE - margin_factor)
-        } else {
-            position.entry_price * (Decimal::ONE + margin_factor)
-        }
+        position.liquidation_price_at(maintenance_margin_rate)
+    }
+    
+    /// Balance plus unrealized PnL across all of an agent's positions.
+    ///
+    /// Positions in markets missing from `prices` are marked at entry.
+    pub fn account_equity(&self, agent_id: &str, prices: &HashMap<MarketId, Decimal>) -> Decimal {
+        let unrealized: Decimal = self.agent_positions(agent_id)
+            .map(|p| prices.get(&p.market).map_or(Decimal::ZERO, |price| p.calculate_pnl(*price)))
+            .sum();
+        self.get_balance(agent_id) + unrealized
+    }
+    
+    /// Sum of maintenance margin over all of an agent's positions at entry notional
+    pub fn account_maintenance_margin(&self, agent_id: &str) -> Decimal {
+        self.agent_positions(agent_id)
+            .map(|p| p.notional_value(p.entry_price) * self.maintenance_margin_rate)
+            .sum()
+    }
+    
+    /// Cross-margin check: equity across all positions below total maintenance
+    pub fn is_account_liquidatable(&self, agent_id: &str, prices: &HashMap<MarketId, Decimal>) -> bool {
+        let maintenance = self.account_maintenance_margin(agent_id);
+        maintenance > Decimal::ZERO && self.account_equity(agent_id, prices) < maintenance
+    }
+    
+    fn agent_positions<'a>(&'a self, agent_id: &'a str) -> impl Iterator<Item = &'a Position> {
+        self.positions.values().filter(move |p| p.agent_id == agent_id && !p.is_flat())
     }
     
     /// Check if position should be liquidated
@@ -247,6 +496,15 @@ impl RiskEngine {
     }
 }
 
+/// Convert a configured limit to a decimal, saturating if out of range
+fn to_decimal(value: f64) -> Decimal {
+    Decimal::try_from(value).unwrap_or(Decimal::MAX)
+}
+
+fn to_f64(value: Decimal) -> f64 {
+    value.to_string().parse().unwrap_or(0.0)
+}
+
 impl Default for RiskEngine {
     fn default() -> Self {
         Self::new()
@@ -256,11 +514,13 @@ impl Default for RiskEngine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::{OrderId, Price, Quantity, Timestamp, TradeId};
+    use chrono::Duration;
     use rust_decimal_macros::dec;
     
     #[test]
     fn test_position_pnl() {
-        let mut pos = Position::new(Market::btc_perp(), "agent-1".to_string());
+        let mut pos = Position::new(MarketId::BtcPerp, "agent-1".to_string());
         pos.size = dec!(1.0);
         pos.entry_price = dec!(50000);
         
@@ -275,7 +535,7 @@ mod tests {
     
     #[test]
     fn test_position_update() {
-        let mut pos = Position::new(Market::btc_perp(), "agent-1".to_string());
+        let mut pos = Position::new(MarketId::BtcPerp, "agent-1".to_string());
         
         // Open long
         pos.update_after_fill(dec!(1.0), dec!(50000));
@@ -305,4 +565,202 @@ mod tests {
         // Insufficient balance
         assert!(engine.withdraw("agent-1", dec!(10000)).is_err());
     }
+    
+    fn funded_engine(balance: Decimal) -> RiskEngine {
+        let mut engine = RiskEngine::new();
+        engine.deposit("agent-1", balance);
+        engine
+    }
+    
+    #[test]
+    fn test_check_order_passes_at_exact_limits() {
+        // $100k notional is both the size limit and 10x a $10k balance
+        let mut engine = funded_engine(dec!(10000));
+        let limits = AgentRiskLimits::default();
+        
+        assert!(engine.check_order("agent-1", &MarketId::BtcPerp, dec!(2), dec!(50000), &limits).is_ok());
+    }
+    
+    #[test]
+    fn test_check_order_position_limit_uses_resulting_size() {
+        let mut engine = funded_engine(dec!(1000000));
+        engine.get_position("agent-1", &MarketId::BtcPerp).update_after_fill(dec!(1.5), dec!(50000));
+        let limits = AgentRiskLimits::default();
+        
+        let result = engine.check_order("agent-1", &MarketId::BtcPerp, dec!(1), dec!(50000), &limits);
+        assert!(matches!(result, Err(RiskError::PositionLimitExceeded { .. })));
+        
+        // Reducing the same position is always allowed
+        assert!(engine.check_order("agent-1", &MarketId::BtcPerp, dec!(-1), dec!(50000), &limits).is_ok());
+    }
+    
+    #[test]
+    fn test_check_order_leverage_breach() {
+        let mut engine = funded_engine(dec!(10000));
+        engine.get_position("agent-1", &MarketId::EthPerp).update_after_fill(dec!(20), dec!(3000));
+        let limits = AgentRiskLimits::default();
+        
+        // $60k ETH + $50k BTC on $10k is 11x
+        let result = engine.check_order("agent-1", &MarketId::BtcPerp, dec!(1), dec!(50000), &limits);
+        assert!(matches!(result, Err(RiskError::LeverageLimitExceeded { .. })));
+    }
+    
+    #[test]
+    fn test_check_order_margin_shortfall() {
+        let mut engine = funded_engine(dec!(100));
+        let limits = AgentRiskLimits::default();
+        
+        // $5k notional at 10x needs $500 of margin
+        let result = engine.check_order("agent-1", &MarketId::BtcPerp, dec!(-0.1), dec!(50000), &limits);
+        assert!(matches!(
+            result,
+            Err(RiskError::InsufficientMargin { required, available }) if required == 500.0 && available == 100.0
+        ));
+    }
+    
+    #[test]
+    fn test_realized_pnl_on_partial_close() {
+        let mut pos = Position::new(MarketId::BtcPerp, "agent-1".to_string());
+        
+        assert_eq!(pos.update_after_fill(dec!(-2), dec!(50000)), dec!(0));
+        assert_eq!(pos.update_after_fill(dec!(1), dec!(49000)), dec!(1000));
+        
+        // Flipping realizes only the closed part
+        assert_eq!(pos.update_after_fill(dec!(3), dec!(51000)), dec!(-1000));
+        assert_eq!(pos.size, dec!(2));
+    }
+    
+    #[test]
+    fn test_daily_loss_limit_resets_next_day() {
+        let mut engine = funded_engine(dec!(100000));
+        let limits = AgentRiskLimits::default();
+        
+        engine.record_realized_pnl("agent-1", dec!(-6000));
+        assert!(engine.check_order("agent-1", &MarketId::BtcPerp, dec!(0.1), dec!(50000), &limits).is_ok());
+        
+        engine.record_realized_pnl("agent-1", dec!(-6000));
+        let result = engine.check_order("agent-1", &MarketId::BtcPerp, dec!(0.1), dec!(50000), &limits);
+        assert!(matches!(result, Err(RiskError::DailyLossLimitExceeded { .. })));
+        
+        // Same day: still blocked
+        engine.roll_day(Utc::now());
+        assert!(engine.check_order("agent-1", &MarketId::BtcPerp, dec!(0.1), dec!(50000), &limits).is_err());
+        
+        engine.roll_day(Utc::now() + Duration::days(1));
+        assert!(engine.check_order("agent-1", &MarketId::BtcPerp, dec!(0.1), dec!(50000), &limits).is_ok());
+    }
+    
+    fn btc_trade(taker: &str, maker: &str, taker_side: Side, qty: Decimal, price: Decimal) -> Trade {
+        Trade {
+            id: TradeId(0),
+            market: MarketId::BtcPerp,
+            price: Price::new(price),
+            quantity: Quantity::new(qty),
+            maker_order_id: OrderId(1),
+            taker_order_id: OrderId(2),
+            maker_agent_id: maker.to_string(),
+            taker_agent_id: taker.to_string(),
+            taker_side,
+            maker_fee: Decimal::ZERO,
+            taker_fee: Decimal::ZERO,
+            timestamp: Timestamp::now(),
+        }
+    }
+    
+    #[test]
+    fn test_open_interest_counts_one_side() {
+        let mut engine = RiskEngine::new();
+        engine.apply_trade(&btc_trade("a", "b", Side::Buy, dec!(2), dec!(50000)));
+        engine.apply_trade(&btc_trade("c", "a", Side::Buy, dec!(0.5), dec!(50000)));
+        
+        // a: +1.5, b: -2, c: +0.5
+        assert_eq!(engine.open_interest(&MarketId::BtcPerp), dec!(2));
+        assert_eq!(engine.open_interest(&MarketId::EthPerp), Decimal::ZERO);
+    }
+    
+    #[test]
+    fn test_liquidation_price_stored_for_long_and_short() {
+        let mut engine = RiskEngine::new();
+        let market = MarketId::BtcPerp;
+        
+        // 2 BTC at 50k posts 10k of margin at the 10% initial rate, so 10x
+        // leverage and the 5% maintenance rate moves liq by 0.5%
+        engine.apply_trade(&btc_trade("long", "short", Side::Buy, dec!(2), dec!(50000)));
+        
+        let long = engine.get_position("long", &market).clone();
+        assert_eq!(long.margin, dec!(10000));
+        assert_eq!(long.liquidation_price, dec!(49750));
+        assert_eq!(long.liquidation_price, engine.calculate_liquidation_price(&long, dec!(0.05)));
+        
+        let short = engine.get_position("short", &market).clone();
+        assert_eq!(short.liquidation_price, dec!(50250));
+        assert!(engine.should_liquidate(&short, dec!(50300)));
+        assert!(!engine.should_liquidate(&short, dec!(50200)));
+    }
+    
+    #[test]
+    fn test_liquidation_price_recomputed_on_add_and_close() {
+        let mut engine = RiskEngine::new();
+        let market = MarketId::BtcPerp;
+        engine.set_initial_margin_rate(dec!(0.2));
+        
+        // 5x: liq 1% below entry
+        engine.apply_trade(&btc_trade("long", "mm", Side::Buy, dec!(1), dec!(50000)));
+        assert_eq!(engine.get_position("long", &market).margin, dec!(10000));
+        assert_eq!(engine.get_position("long", &market).liquidation_price, dec!(49500));
+        
+        // Adding posts margin for the added size at its own price, keeping 5x at the blended entry
+        engine.apply_trade(&btc_trade("long", "mm", Side::Buy, dec!(1), dec!(52000)));
+        let pos = engine.get_position("long", &market).clone();
+        assert_eq!(pos.entry_price, dec!(51000));
+        assert_eq!(pos.margin, dec!(20400));
+        assert_eq!(pos.liquidation_price, dec!(50490));
+        
+        // Reducing releases margin pro rata, leaving leverage unchanged
+        engine.apply_trade(&btc_trade("long", "mm", Side::Sell, dec!(0.5), dec!(53000)));
+        let pos = engine.get_position("long", &market).clone();
+        assert_eq!(pos.margin, dec!(15300));
+        assert_eq!(pos.liquidation_price, dec!(50490));
+        
+        engine.apply_trade(&btc_trade("long", "mm", Side::Sell, dec!(1.5), dec!(51000)));
+        let pos = engine.get_position("long", &market).clone();
+        assert_eq!(pos.margin, Decimal::ZERO);
+        assert_eq!(pos.liquidation_price, Decimal::ZERO);
+    }
+    
+    /// $5k balance, long 1 BTC at 50k and short 10 ETH at 3k: $4k maintenance
+    fn cross_margined_engine() -> RiskEngine {
+        let mut engine = funded_engine(dec!(5000));
+        engine.get_position("agent-1", &MarketId::BtcPerp).update_after_fill(dec!(1), dec!(50000));
+        engine.get_position("agent-1", &MarketId::EthPerp).update_after_fill(dec!(-10), dec!(3000));
+        engine
+    }
+    
+    #[test]
+    fn test_account_equity_nets_winning_and_losing_positions() {
+        let engine = cross_margined_engine();
+        let prices = HashMap::from([
+            (MarketId::BtcPerp, dec!(52000)),
+            (MarketId::EthPerp, dec!(3150)),
+        ]);
+        
+        assert_eq!(engine.account_equity("agent-1", &prices), dec!(5500));
+        assert_eq!(engine.account_maintenance_margin("agent-1"), dec!(4000));
+        assert!(!engine.is_account_liquidatable("agent-1", &prices));
+    }
+    
+    #[test]
+    fn test_account_liquidatable_on_aggregate_losses() {
+        let engine = cross_margined_engine();
+        let btc_down = (MarketId::BtcPerp, dec!(49500));
+        let eth_up = (MarketId::EthPerp, dec!(3060));
+        
+        // Either loss alone leaves the account above maintenance
+        assert!(!engine.is_account_liquidatable("agent-1", &HashMap::from([btc_down])));
+        assert!(!engine.is_account_liquidatable("agent-1", &HashMap::from([eth_up])));
+        
+        let prices = HashMap::from([btc_down, eth_up]);
+        assert_eq!(engine.account_equity("agent-1", &prices), dec!(3900));
+        assert!(engine.is_account_liquidatable("agent-1", &prices));
+    }
 }
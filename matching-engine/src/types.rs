@@ -1,8 +1,10 @@
 //! Core types for AI Perp DEX
 
-use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
+use crate::order::Side;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
+use std::str::FromStr;
 
 /// Unique identifier for orders
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -24,31 +26,213 @@ impl fmt::Display for TradeId {
     }
 }
 
-/// Market identifier (e.g., BTC-PERP)
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct Market(pub String);
+/// Market identifier; serializes as its canonical symbol (e.g., "BTC-PERP")
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MarketId {
+    BtcPerp,
+    EthPerp,
+    SolPerp,
+    DogePerp,
+    AvaxPerp,
+    LinkPerp,
+}
+
+impl MarketId {
+    /// Every market the engine knows how to list
+    pub const ALL: [MarketId; 6] = [
+        MarketId::BtcPerp,
+        MarketId::EthPerp,
+        MarketId::SolPerp,
+        MarketId::DogePerp,
+        MarketId::AvaxPerp,
+        MarketId::LinkPerp,
+    ];
+    
+    /// Canonical symbol, as used on the wire
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            MarketId::BtcPerp => "BTC-PERP",
+            MarketId::EthPerp => "ETH-PERP",
+            MarketId::SolPerp => "SOL-PERP",
+            MarketId::DogePerp => "DOGE-PERP",
+            MarketId::AvaxPerp => "AVAX-PERP",
+            MarketId::LinkPerp => "LINK-PERP",
+        }
+    }
+}
+
+impl fmt::Display for MarketId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// Symbol that does not name a known market
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownMarket(pub String);
+
+impl fmt::Display for UnknownMarket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown market: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownMarket {}
+
+impl FromStr for MarketId {
+    type Err = UnknownMarket;
+    
+    /// Parse a symbol, ignoring case
+    fn from_str(symbol: &str) -> Result<Self, Self::Err> {
+        MarketId::ALL
+            .into_iter()
+            .find(|m| m.symbol().eq_ignore_ascii_case(symbol))
+            .ok_or_else(|| UnknownMarket(symbol.to_string()))
+    }
+}
 
-impl Market {
-    pub fn new(symbol: &str) -> Self {
-        Self(symbol.to_uppercase())
+impl Serialize for MarketId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.symbol())
+    }
+}
+
+impl<'de> Deserialize<'de> for MarketId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let symbol = String::deserialize(deserializer)?;
+        symbol.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Trading parameters of a market
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketConfig {
+    /// Smallest price increment
+    pub tick_size: Decimal,
+    /// Smallest order quantity
+    pub min_qty: Decimal,
+    /// Largest order quantity
+    pub max_qty: Decimal,
+    /// Maximum leverage allowed on positions
+    pub max_leverage: u32,
+    /// Furthest a limit price may sit from the book's mark price, in basis
+    /// points; `None` disables the check
+    #[serde(default)]
+    pub price_band_bps: Option<u32>,
+}
+
+impl MarketConfig {
+    pub fn new(tick_size: Decimal, min_qty: Decimal, max_qty: Decimal, max_leverage: u32) -> Self {
+        Self { tick_size, min_qty, max_qty, max_leverage, price_band_bps: None }
+    }
+    
+    /// Set the price band, or disable it with `None`
+    pub fn with_price_band(mut self, price_band_bps: Option<u32>) -> Self {
+        self.price_band_bps = price_band_bps;
+        self
     }
     
-    pub fn btc_perp() -> Self {
-        Self::new("BTC-PERP")
+    /// Check a price sits on the tick grid, or snap it to the nearest tick
+    /// when `round` is set
+    pub fn align_price(&self, price: Price, round: bool) -> Result<Price, String> {
+        let ticks = price.0 / self.tick_size;
+        if ticks.fract().is_zero() {
+            return Ok(price);
+        }
+        if !round {
+            return Err(format!("Price {} is not a multiple of tick size {}", price.0, self.tick_size));
+        }
+        
+        let rounded = ticks.round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero) * self.tick_size;
+        if rounded <= Decimal::ZERO {
+            return Err(format!("Price {} rounds to zero at tick size {}", price.0, self.tick_size));
+        }
+        Ok(Price::new(rounded.normalize()))
     }
     
-    pub fn eth_perp() -> Self {
-        Self::new("ETH-PERP")
+    /// Check a limit price is within the price band around `mark`. Passes
+    /// when the band is disabled or there is no mark to compare against
+    pub fn check_price_band(&self, price: Price, mark: Option<Price>) -> Result<(), String> {
+        let (Some(bps), Some(mark)) = (self.price_band_bps, mark) else {
+            return Ok(());
+        };
+        let width = mark.0 * Decimal::from(bps) / Decimal::from(10_000);
+        let (low, high) = (mark.0 - width, mark.0 + width);
+        if price.0 < low || price.0 > high {
+            return Err(format!("Price {} is outside the band {} - {} around mark {}", price.0, low, high, mark.0));
+        }
+        Ok(())
     }
     
-    pub fn sol_perp() -> Self {
-        Self::new("SOL-PERP")
+    /// Check a quantity is within the market's order size limits
+    pub fn check_quantity(&self, quantity: Quantity) -> Result<(), String> {
+        if quantity.0 < self.min_qty {
+            return Err(format!("Quantity {} is below minimum {}", quantity.0, self.min_qty));
+        }
+        if quantity.0 > self.max_qty {
+            return Err(format!("Quantity {} is above maximum {}", quantity.0, self.max_qty));
+        }
+        Ok(())
+    }
+}
+
+/// Serde for decimals that keeps them exact on the wire.
+///
+/// Values are written as strings (`"50000.5"`), or as JSON numbers when the
+/// `numeric-decimals` feature is enabled for clients that need them. Both
+/// forms are accepted when reading; numbers go through their shortest
+/// decimal representation rather than binary float arithmetic.
+pub mod decimal_serde {
+    use super::*;
+    
+    pub fn serialize<S: Serializer>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+        #[cfg(feature = "numeric-decimals")]
+        {
+            use rust_decimal::prelude::ToPrimitive;
+            serializer.serialize_f64(value.to_f64().unwrap_or_default())
+        }
+        #[cfg(not(feature = "numeric-decimals"))]
+        {
+            serializer.collect_str(value)
+        }
+    }
+    
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+    
+    struct DecimalVisitor;
+    
+    impl de::Visitor<'_> for DecimalVisitor {
+        type Value = Decimal;
+        
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a decimal string or number")
+        }
+        
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Decimal, E> {
+            Decimal::from_str(v.trim())
+                .or_else(|_| Decimal::from_scientific(v.trim()))
+                .map_err(E::custom)
+        }
+        
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<Decimal, E> {
+            self.visit_str(&v.to_string())
+        }
+        
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<Decimal, E> {
+            Ok(Decimal::from(v))
+        }
+        
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<Decimal, E> {
+            Ok(Decimal::from(v))
+        }
     }
 }
 
 /// Price with decimal precision
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-pub struct Price(pub Decimal);
+pub struct Price(#[serde(with = "decimal_serde")] pub Decimal);
 
 impl Price {
     pub fn new(value: Decimal) -> Self {
@@ -66,7 +250,7 @@ impl Price {
 
 /// Quantity/Size
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Default)]
-pub struct Quantity(pub Decimal);
+pub struct Quantity(#[serde(with = "decimal_serde")] pub Decimal);
 
 impl Quantity {
     pub fn new(value: Decimal) -> Self {
@@ -86,6 +270,14 @@ impl Quantity {
     }
 }
 
+impl std::ops::Add for Quantity {
+    type Output = Self;
+    
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(self.0 + rhs.0)
+    }
+}
+
 impl std::ops::Sub for Quantity {
     type Output = Self;
     
@@ -123,16 +315,51 @@ impl Timestamp {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
     pub id: TradeId,
-    pub market: Market,
+    pub market: MarketId,
     pub price: Price,
     pub quantity: Quantity,
     pub maker_order_id: OrderId,
     pub taker_order_id: OrderId,
     pub maker_agent_id: String,
     pub taker_agent_id: String,
+    /// Side of the taker (aggressor)
+    pub taker_side: Side,
+    /// Fee charged to the maker in quote currency (negative = rebate)
+    #[serde(with = "decimal_serde")]
+    pub maker_fee: Decimal,
+    /// Fee charged to the taker in quote currency
+    #[serde(with = "decimal_serde")]
+    pub taker_fee: Decimal,
     pub timestamp: Timestamp,
 }
 
+/// Trading fees in basis points of notional; a negative rate is a rebate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    pub maker_bps: Decimal,
+    pub taker_bps: Decimal,
+}
+
+impl FeeSchedule {
+    pub fn new(maker_bps: Decimal, taker_bps: Decimal) -> Self {
+        Self { maker_bps, taker_bps }
+    }
+    
+    /// Calculate (maker_fee, taker_fee) for a fill
+    pub fn fees_for(&self, price: Price, quantity: Quantity) -> (Decimal, Decimal) {
+        let notional = price.as_decimal() * quantity.as_decimal();
+        let bps = Decimal::from(10_000);
+        (notional * self.maker_bps / bps, notional * self.taker_bps / bps)
+    }
+}
+
+impl Default for FeeSchedule {
+    /// 2 bps maker rebate, 5 bps taker fee
+    fn default() -> Self {
+        Self::new(Decimal::from(-2), Decimal::from(5))
+    }
+}
+
 /// Orderbook snapshot at a price level
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PriceLevel {
@@ -144,13 +371,55 @@ pub struct PriceLevel {
 /// Full orderbook snapshot
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBookSnapshot {
-    pub market: Market,
+    pub market: MarketId,
     pub bids: Vec<PriceLevel>,
     pub asks: Vec<PriceLevel>,
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+    /// Midpoint of the best bid and ask (None unless both sides are quoted)
+    pub mid_price: Option<Price>,
+    /// Best ask minus best bid (None unless both sides are quoted)
+    pub spread: Option<Price>,
     pub timestamp: Timestamp,
     pub sequence: u64,
 }
 
+/// Estimated execution of a hypothetical taker order against the book
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FillEstimate {
+    /// Volume-weighted average fill price (None if nothing would fill)
+    pub avg_price: Option<Price>,
+    pub filled_qty: Quantity,
+    /// Price of the deepest level touched
+    pub worst_price: Option<Price>,
+    pub levels_consumed: u32,
+    pub fully_filled: bool,
+}
+
+/// Lifetime count of orders removed from a book unfilled, by cause
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderRemovalCounts {
+    /// Cancelled on request
+    pub cancelled: u64,
+    /// Swept after their GTD expiry
+    pub expired: u64,
+}
+
+/// Rolling 24h trade statistics for a market
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketStats {
+    pub market: MarketId,
+    pub last_price: Option<Price>,
+    pub volume_24h: Quantity,
+    pub high_24h: Option<Price>,
+    pub low_24h: Option<Price>,
+    pub trade_count_24h: u64,
+    /// Orders cancelled or expired since the book was created
+    #[serde(default)]
+    pub removals: OrderRemovalCounts,
+    pub timestamp: Timestamp,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -162,9 +431,66 @@ mod tests {
     }
     
     #[test]
-    fn test_market_creation() {
-        let market = Market::new("btc-perp");
-        assert_eq!(market.0, "BTC-PERP");
+    fn test_market_parsing() {
+        assert_eq!("btc-perp".parse::<MarketId>(), Ok(MarketId::BtcPerp));
+        assert_eq!(MarketId::LinkPerp.to_string(), "LINK-PERP");
+        assert_eq!("XRP-PERP".parse::<MarketId>(), Err(UnknownMarket("XRP-PERP".to_string())));
+        assert!("BTC".parse::<MarketId>().is_err());
+    }
+    
+    #[test]
+    fn test_market_serde_round_trip() {
+        for market in MarketId::ALL {
+            let json = serde_json::to_string(&market).unwrap();
+            assert_eq!(json, format!("\"{}\"", market.symbol()));
+            assert_eq!(serde_json::from_str::<MarketId>(&json).unwrap(), market);
+        }
+        assert!(serde_json::from_str::<MarketId>("\"XRP-PERP\"").is_err());
+    }
+    
+    #[test]
+    #[cfg(not(feature = "numeric-decimals"))]
+    fn test_decimals_serialize_as_exact_strings() {
+        use rust_decimal_macros::dec;
+        
+        let sum = Price::new(dec!(0.1) + dec!(0.2));
+        assert_eq!(serde_json::to_string(&sum).unwrap(), "\"0.3\"");
+        assert_eq!(serde_json::from_str::<Price>("\"0.3\"").unwrap(), Price::new(dec!(0.3)));
+        
+        let level = PriceLevel { price: Price::new(dec!(50000.5)), quantity: Quantity::new(dec!(0.1)), order_count: 1 };
+        let json = serde_json::to_value(&level).unwrap();
+        assert_eq!(json["price"], "50000.5");
+        assert_eq!(json["quantity"], "0.1");
+    }
+    
+    #[test]
+    fn test_decimals_deserialize_from_strings_and_numbers() {
+        use rust_decimal_macros::dec;
+        
+        for json in ["\"0.1\"", "0.1"] {
+            assert_eq!(serde_json::from_str::<Quantity>(json).unwrap(), Quantity::new(dec!(0.1)));
+        }
+        assert_eq!(serde_json::from_str::<Price>("50000").unwrap(), Price::new(dec!(50000)));
+        assert!(serde_json::from_str::<Price>("\"abc\"").is_err());
+        
+        let price = Price::new(dec!(123456789.123456789));
+        let round_trip: Price = serde_json::from_str(&serde_json::to_string(&price).unwrap()).unwrap();
+        #[cfg(not(feature = "numeric-decimals"))]
+        assert_eq!(round_trip, price);
+        #[cfg(feature = "numeric-decimals")]
+        assert!((round_trip.0 - price.0).abs() < dec!(0.000001));
+    }
+    
+    #[test]
+    fn test_fee_schedule_maker_rebate() {
+        use rust_decimal_macros::dec;
+        
+        let fees = FeeSchedule::new(dec!(-2), dec!(5));
+        let (maker_fee, taker_fee) = fees.fees_for(Price::new(dec!(50000)), Quantity::new(dec!(0.5)));
+        
+        // $25,000 notional: maker earns $5, taker pays $12.50
+        assert_eq!(maker_fee, dec!(-5));
+        assert_eq!(taker_fee, dec!(12.5));
     }
     
     #[test]
@@ -215,7 +215,8 @@ async def main():
     async with P2PClient(
         ws_url=os.getenv("WS_URL", "ws://localhost:8080/ws"),
         rest_url=os.getenv("REST_URL", "http://localhost:8080"),
-        agent_id=agent_id
+        agent_id=agent_id,
+        api_key=os.getenv("API_KEY"),
     ) as client:
         agent = ArbitrageMMAgent(
             client=client,
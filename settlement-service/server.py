@@ -26,6 +26,7 @@ TOKEN_PROGRAM = Pubkey.from_string("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"
 # Discriminators
 OPEN_POSITION_DISC = bytes([135, 128, 47, 77, 15, 152, 240, 49])
 CLOSE_POSITION_DISC = bytes([123, 134, 81, 0, 49, 68, 98, 98])
+CLOSE_POSITION_PARTIAL_DISC = bytes([131, 75, 187, 93, 82, 226, 96, 205])
 
 def find_pda(seeds: list, program_id: Pubkey) -> tuple[Pubkey, int]:
     return Pubkey.find_program_address(seeds, program_id)
@@ -189,6 +190,48 @@ class SettlementService:
         except Exception as e:
             return {"success": False, "error": str(e)}
 
+    async def settle_reduce_position(
+        self, 
+        owner: str, 
+        market_index: int, 
+        close_size: int,
+        exit_price: int
+    ) -> dict:
+        """链上部分平仓结算"""
+        exchange_pda, _ = find_pda([b"exchange"], PROGRAM_ID)
+        agent_pda = self.get_agent_pda(owner)
+        position_pda = self.get_position_pda(agent_pda, market_index)
+        
+        # 构建指令
+        data = CLOSE_POSITION_PARTIAL_DISC
+        data += struct.pack("<B", market_index)
+        data += struct.pack("<Q", close_size)
+        data += struct.pack("<Q", exit_price)
+        
+        accounts = [
+            AccountMeta(pubkey=self.wallet.pubkey(), is_signer=True, is_writable=False),
+            AccountMeta(pubkey=exchange_pda, is_signer=False, is_writable=False),
+            AccountMeta(pubkey=agent_pda, is_signer=False, is_writable=True),
+            AccountMeta(pubkey=position_pda, is_signer=False, is_writable=True),
+        ]
+        
+        ix = Instruction(PROGRAM_ID, data, accounts)
+        
+        blockhash_resp = await self.client.get_latest_blockhash()
+        blockhash = blockhash_resp.value.blockhash
+        
+        msg = Message.new_with_blockhash([ix], self.wallet.pubkey(), blockhash)
+        tx = Transaction([self.wallet], msg, blockhash)
+        
+        try:
+            result = await self.client.send_transaction(tx)
+            return {
+                "success": True,
+                "signature": str(result.value),
+            }
+        except Exception as e:
+            return {"success": False, "error": str(e)}
+
 # HTTP Handlers
 service = SettlementService()
 
@@ -225,6 +268,16 @@ async def close_position(request):
     )
     return web.json_response(result)
 
+async def reduce_position(request):
+    data = await request.json()
+    result = await service.settle_reduce_position(
+        owner=data['owner'],
+        market_index=data.get('market_index', 0),
+        close_size=data['close_size'],
+        exit_price=data['exit_price']
+    )
+    return web.json_response(result)
+
 async def init_app():
     await service.init()
     
@@ -235,6 +288,7 @@ async def init_app():
     app.router.add_get('/position/{owner}/{market}', get_position)
     app.router.add_post('/settle/open', open_position)
     app.router.add_post('/settle/close', close_position)
+    app.router.add_post('/settle/reduce', reduce_position)
     
     return app
 
@@ -46,4 +46,7 @@ pub enum PerpError {
     
     #[msg("Invalid parameter")]
     InvalidParameter,
+    
+    #[msg("Withdrawal exceeds free collateral")]
+    WithdrawalExceedsFreeCollateral,
 }
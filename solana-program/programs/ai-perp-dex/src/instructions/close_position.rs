@@ -1,6 +1,7 @@
 use anchor_lang::prelude::*;
 use crate::state::{Agent, Exchange, Position};
 use crate::errors::PerpError;
+use super::settle_pnl::replace_position_pnl;
 
 #[derive(Accounts)]
 #[instruction(market_index: u8)]
@@ -49,6 +50,7 @@ pub fn handler(ctx: Context<ClosePosition>, _market_index: u8, exit_price: u64)
     };
     
     // Update agent
+    agent.unrealized_pnl = replace_position_pnl(agent.unrealized_pnl, position.unrealized_pnl, 0)?;
     agent.collateral += total_return;
     agent.realized_pnl += pnl;
     agent.total_trades += 1;
@@ -74,7 +76,7 @@ pub fn handler(ctx: Context<ClosePosition>, _market_index: u8, exit_price: u64)
     Ok(())
 }
 
-fn calculate_pnl(size: i64, entry_price: u64, exit_price: u64) -> Result<i64> {
+pub(crate) fn calculate_pnl(size: i64, entry_price: u64, exit_price: u64) -> Result<i64> {
     let price_diff = exit_price as i64 - entry_price as i64;
     
     // PnL = size * price_diff / price_decimals
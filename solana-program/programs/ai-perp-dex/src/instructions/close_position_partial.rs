@@ -0,0 +1,177 @@
+use anchor_lang::prelude::*;
+use crate::state::{Agent, Exchange, Position};
+use crate::errors::PerpError;
+use super::close_position::calculate_pnl;
+use super::settle_pnl::replace_position_pnl;
+
+#[derive(Accounts)]
+#[instruction(market_index: u8)]
+pub struct ClosePositionPartial<'info> {
+    pub authority: Signer<'info>,
+    
+    #[account(
+        seeds = [b"exchange"],
+        bump = exchange.bump,
+        constraint = exchange.authority == authority.key() @ PerpError::Unauthorized
+    )]
+    pub exchange: Account<'info, Exchange>,
+    
+    #[account(
+        mut,
+        seeds = [b"agent", agent.owner.as_ref()],
+        bump = agent.bump
+    )]
+    pub agent: Account<'info, Agent>,
+    
+    #[account(
+        mut,
+        seeds = [b"position", agent.key().as_ref(), &[market_index]],
+        bump = position.bump,
+        constraint = position.size != 0 @ PerpError::NoPosition
+    )]
+    pub position: Account<'info, Position>,
+}
+
+/// Result of closing part of a position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialClose {
+    /// Signed size left open (same side as before)
+    pub remaining_size: i64,
+    /// Margin still locked in the remaining size
+    pub remaining_margin: u64,
+    /// Margin released by the closed portion
+    pub margin_released: u64,
+    /// PnL realized on the closed portion
+    pub pnl: i64,
+    /// Amount returned to the agent's collateral (released margin + PnL)
+    pub returned: u64,
+}
+
+/// Reduce `size` toward zero by `close_size` (unsigned, in size units) at `exit_price`.
+/// The entry price of the remainder is unchanged.
+pub fn partial_close(
+    size: i64,
+    entry_price: u64,
+    margin: u64,
+    close_size: u64,
+    exit_price: u64,
+) -> Result<PartialClose> {
+    require!(exit_price > 0, PerpError::InvalidPrice);
+    // Closing more than the open size would flip the side
+    require!(close_size > 0 && close_size <= size.unsigned_abs(), PerpError::InvalidSize);
+    
+    let closed = i64::try_from(close_size).map_err(|_| PerpError::MathOverflow)? * size.signum();
+    let remaining_size = size - closed;
+    
+    let pnl = calculate_pnl(closed, entry_price, exit_price)?;
+    
+    // Release margin in proportion to the closed size
+    let margin_released = if remaining_size == 0 {
+        margin
+    } else {
+        (margin as u128 * close_size as u128 / size.unsigned_abs() as u128) as u64
+    };
+    
+    let returned = if pnl >= 0 {
+        margin_released.checked_add(pnl as u64).ok_or(PerpError::MathOverflow)?
+    } else {
+        margin_released.saturating_sub(pnl.unsigned_abs())
+    };
+    
+    Ok(PartialClose {
+        remaining_size,
+        remaining_margin: margin - margin_released,
+        margin_released,
+        pnl,
+        returned,
+    })
+}
+
+pub fn handler(
+    ctx: Context<ClosePositionPartial>,
+    _market_index: u8,
+    close_size: u64,
+    exit_price: u64,
+) -> Result<()> {
+    let agent = &mut ctx.accounts.agent;
+    let position = &mut ctx.accounts.position;
+    let clock = Clock::get()?;
+    
+    let close = partial_close(position.size, position.entry_price, position.margin, close_size, exit_price)?;
+    
+    // Scale the remainder's unrealized PnL and keep the agent total in sync
+    let remaining_unrealized = (position.unrealized_pnl as i128 * close.remaining_size as i128
+        / position.size as i128) as i64;
+    agent.unrealized_pnl = replace_position_pnl(agent.unrealized_pnl, position.unrealized_pnl, remaining_unrealized)?;
+    
+    // Update agent
+    agent.collateral = agent.collateral
+        .checked_add(close.returned)
+        .ok_or(PerpError::MathOverflow)?;
+    agent.realized_pnl += close.pnl;
+    agent.total_trades += 1;
+    if close.pnl > 0 {
+        agent.win_count += 1;
+    }
+    
+    // Update position (entry and liquidation price unchanged)
+    position.size = close.remaining_size;
+    position.margin = close.remaining_margin;
+    position.unrealized_pnl = remaining_unrealized;
+    if close.remaining_size == 0 {
+        position.entry_price = 0;
+        position.liquidation_price = 0;
+    }
+    position.updated_at = clock.unix_timestamp;
+    
+    msg!(
+        "Partially closed position: closed={}, remaining={}, exit_price={}, pnl={}, returned={}",
+        close_size,
+        close.remaining_size,
+        exit_price,
+        close.pnl,
+        close.returned
+    );
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // 10 units long at $100 with $100 margin
+    const SIZE: i64 = 10_000_000;
+    const ENTRY: u64 = 100_000_000;
+    const MARGIN: u64 = 100_000_000;
+    
+    #[test]
+    fn test_close_half_then_rest_of_long() {
+        // Close 5 units at $110: +$50 on the closed half, half the margin back
+        let first = partial_close(SIZE, ENTRY, MARGIN, 5_000_000, 110_000_000).unwrap();
+        assert_eq!(first.remaining_size, 5_000_000);
+        assert_eq!(first.margin_released, 50_000_000);
+        assert_eq!(first.remaining_margin, 50_000_000);
+        assert_eq!(first.pnl, 50_000_000);
+        assert_eq!(first.returned, 100_000_000);
+        
+        // Close the remaining 5 units at $90: -$50 against the last $50 of margin
+        let rest = partial_close(first.remaining_size, ENTRY, first.remaining_margin, 5_000_000, 90_000_000).unwrap();
+        assert_eq!(rest.remaining_size, 0);
+        assert_eq!(rest.remaining_margin, 0);
+        assert_eq!(rest.pnl, -50_000_000);
+        assert_eq!(rest.returned, 0);
+    }
+    
+    #[test]
+    fn test_rejects_oversized_or_empty_close() {
+        assert!(partial_close(SIZE, ENTRY, MARGIN, 10_000_001, 110_000_000).is_err());
+        assert!(partial_close(-SIZE, ENTRY, MARGIN, 10_000_001, 110_000_000).is_err());
+        assert!(partial_close(SIZE, ENTRY, MARGIN, 0, 110_000_000).is_err());
+        
+        // A short reduces toward zero, not away from it
+        let close = partial_close(-SIZE, ENTRY, MARGIN, 4_000_000, 90_000_000).unwrap();
+        assert_eq!(close.remaining_size, -6_000_000);
+        assert_eq!(close.pnl, 40_000_000);
+    }
+}
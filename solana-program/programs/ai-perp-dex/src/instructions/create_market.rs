@@ -25,6 +25,9 @@ pub struct CreateMarket<'info> {
     )]
     pub market: Account<'info, Market>,
     
+    /// CHECK: Pyth price account for this market; parsed and validated by `oracle::get_market_price` on use
+    pub oracle: UncheckedAccount<'info>,
+    
     pub system_program: Program<'info, System>,
 }
 
@@ -45,7 +48,7 @@ pub fn handler(
     
     market.index = market_index;
     market.symbol = symbol;
-    market.oracle = Pubkey::default(); // To be set via update_market
+    market.oracle = ctx.accounts.oracle.key();
     market.initial_margin_rate = initial_margin_rate;
     market.maintenance_margin_rate = maintenance_margin_rate;
     market.max_leverage = max_leverage;
@@ -1,6 +1,6 @@
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
-use crate::state::Exchange;
+use crate::state::{Exchange, InsuranceFund};
 
 #[derive(Accounts)]
 pub struct Initialize<'info> {
@@ -29,6 +29,26 @@ pub struct Initialize<'info> {
     )]
     pub vault: InterfaceAccount<'info, TokenAccount>,
     
+    #[account(
+        init,
+        payer = authority,
+        space = InsuranceFund::SIZE,
+        seeds = [b"insurance_fund"],
+        bump
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+    
+    /// Insurance vault (same mint, owned by the exchange PDA)
+    #[account(
+        init,
+        payer = authority,
+        token::mint = collateral_mint,
+        token::authority = exchange,
+        seeds = [b"insurance_vault"],
+        bump
+    )]
+    pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
+    
     pub system_program: Program<'info, System>,
     pub token_program: Interface<'info, TokenInterface>,
     pub rent: Sysvar<'info, Rent>,
@@ -46,6 +66,12 @@ pub fn handler(ctx: Context<Initialize>, fee_rate_bps: u16) -> Result<()> {
     exchange.total_open_interest = 0;
     exchange.bump = ctx.bumps.exchange;
     
+    let insurance_fund = &mut ctx.accounts.insurance_fund;
+    insurance_fund.vault = ctx.accounts.insurance_vault.key();
+    insurance_fund.balance = 0;
+    insurance_fund.total_withdrawn = 0;
+    insurance_fund.bump = ctx.bumps.insurance_fund;
+    
     msg!("AI Perp DEX initialized with fee rate: {} bps", fee_rate_bps);
     
     Ok(())
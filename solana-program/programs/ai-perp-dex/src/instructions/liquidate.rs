@@ -1,6 +1,10 @@
 use anchor_lang::prelude::*;
-use crate::state::{Agent, Exchange, Position};
+use anchor_spl::token_interface::{self, TokenAccount, TokenInterface, TransferChecked};
+use crate::state::{Agent, Exchange, InsuranceFund, Market, Position};
 use crate::errors::PerpError;
+use crate::oracle::{get_market_price, OracleError};
+use super::close_position::calculate_pnl;
+use super::settle_pnl::replace_position_pnl;
 
 #[derive(Accounts)]
 #[instruction(market_index: u8)]
@@ -8,21 +12,21 @@ pub struct Liquidate<'info> {
     /// Liquidator (receives reward)
     #[account(mut)]
     pub liquidator: Signer<'info>,
-    
+
     #[account(
         mut,
         seeds = [b"exchange"],
         bump = exchange.bump
     )]
     pub exchange: Account<'info, Exchange>,
-    
+
     #[account(
         mut,
         seeds = [b"agent", agent.owner.as_ref()],
         bump = agent.bump
     )]
     pub agent: Account<'info, Agent>,
-    
+
     #[account(
         mut,
         seeds = [b"position", agent.key().as_ref(), &[market_index]],
@@ -30,7 +34,7 @@ pub struct Liquidate<'info> {
         constraint = position.size != 0 @ PerpError::NoPosition
     )]
     pub position: Account<'info, Position>,
-    
+
     /// Liquidator's agent account (to receive reward)
     #[account(
         mut,
@@ -38,45 +42,144 @@ pub struct Liquidate<'info> {
         bump = liquidator_agent.bump
     )]
     pub liquidator_agent: Account<'info, Agent>,
-    
-    // TODO: Add oracle account for price verification
+
+    #[account(
+        seeds = [b"market", &[market_index]],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Must be the market's Pyth price account; parsed by `get_market_price`
+    #[account(address = market.oracle @ OracleError::InvalidOracle)]
+    pub oracle: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"insurance_fund"],
+        bump = insurance_fund.bump
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        constraint = vault.key() == exchange.vault @ PerpError::Unauthorized
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = insurance_vault.key() == insurance_fund.vault @ PerpError::Unauthorized
+    )]
+    pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: Mint account for transfer_checked
+    pub mint: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
-pub fn handler(ctx: Context<Liquidate>, _market_index: u8) -> Result<()> {
-    let position = &mut ctx.accounts.position;
-    let agent = &mut ctx.accounts.agent;
-    let liquidator_agent = &mut ctx.accounts.liquidator_agent;
-    let clock = Clock::get()?;
-    
-    // TODO: Get current price from oracle
-    let current_price: u64 = 0; // Placeholder
-    
-    // Check if position is liquidatable
-    let is_liquidatable = if position.size > 0 {
-        current_price <= position.liquidation_price
+/// How a liquidated position's margin is split
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiquidationSettlement {
+    /// PnL realized by the agent (never worse than losing the whole margin)
+    pub realized_pnl: i64,
+    /// Margin returned to the agent's collateral
+    pub returned: u64,
+    pub liquidator_reward: u64,
+    pub insurance_fund: u64,
+}
+
+/// Check the position is past its liquidation price at `current_price` and
+/// split what is left of the margin after the loss.
+pub fn settle_liquidation(
+    size: i64,
+    entry_price: u64,
+    margin: u64,
+    liquidation_price: u64,
+    current_price: u64,
+) -> Result<LiquidationSettlement> {
+    require!(current_price > 0, PerpError::InvalidPrice);
+
+    let is_liquidatable = if size > 0 {
+        current_price <= liquidation_price
     } else {
-        current_price >= position.liquidation_price
+        current_price >= liquidation_price
     };
-    
     require!(is_liquidatable, PerpError::NotLiquidatable);
-    
-    // Calculate liquidation penalty (e.g., 5% of margin)
-    let liquidation_penalty = position.margin * 5 / 100;
+
+    // Equity left after the loss, floored at zero (bad debt is not charged to the agent)
+    let pnl = calculate_pnl(size, entry_price, current_price)?;
+    let margin_i64 = i64::try_from(margin).map_err(|_| PerpError::MathOverflow)?;
+    let equity = margin_i64.checked_add(pnl).ok_or(PerpError::MathOverflow)?.max(0) as u64;
+
+    // Liquidation penalty (5% of margin), paid out of the remaining equity
+    let liquidation_penalty = (margin * 5 / 100).min(equity);
     let liquidator_reward = liquidation_penalty / 2;
-    let insurance_fund = liquidation_penalty - liquidator_reward;
-    
-    // Calculate remaining margin after loss
-    let remaining_margin = position.margin.saturating_sub(liquidation_penalty);
-    
-    // Return remaining margin to agent
-    agent.collateral += remaining_margin;
+
+    Ok(LiquidationSettlement {
+        realized_pnl: equity as i64 - margin_i64,
+        returned: equity - liquidation_penalty,
+        liquidator_reward,
+        insurance_fund: liquidation_penalty - liquidator_reward,
+    })
+}
+
+/// Insurance fund balance after crediting a liquidation's insurance portion
+pub fn credit_insurance_fund(balance: u64, amount: u64) -> Result<u64> {
+    let balance = balance.checked_add(amount).ok_or(PerpError::MathOverflow)?;
+    Ok(balance)
+}
+
+pub fn handler(ctx: Context<Liquidate>, _market_index: u8) -> Result<()> {
+    let current_price = get_market_price(&ctx.accounts.oracle.to_account_info())?;
+    let clock = Clock::get()?;
+
+    let settlement = settle_liquidation(
+        ctx.accounts.position.size,
+        ctx.accounts.position.entry_price,
+        ctx.accounts.position.margin,
+        ctx.accounts.position.liquidation_price,
+        current_price,
+    )?;
+
+    // Move the insurance portion from the exchange vault to the insurance vault
+    if settlement.insurance_fund > 0 {
+        let exchange_seeds = &[b"exchange".as_ref(), &[ctx.accounts.exchange.bump]];
+        let signer_seeds = &[&exchange_seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.vault.to_account_info(),
+                to: ctx.accounts.insurance_vault.to_account_info(),
+                authority: ctx.accounts.exchange.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token_interface::transfer_checked(transfer_ctx, settlement.insurance_fund, 6)?;  // 6 decimals
+
+        let insurance_fund = &mut ctx.accounts.insurance_fund;
+        insurance_fund.balance = credit_insurance_fund(insurance_fund.balance, settlement.insurance_fund)?;
+        let exchange = &mut ctx.accounts.exchange;
+        exchange.total_deposits = exchange.total_deposits.saturating_sub(settlement.insurance_fund);
+    }
+
+    let position = &mut ctx.accounts.position;
+    let agent = &mut ctx.accounts.agent;
+    let liquidator_agent = &mut ctx.accounts.liquidator_agent;
+
+    // Settle the realized loss and return remaining margin to agent
+    agent.collateral = agent.collateral
+        .checked_add(settlement.returned)
+        .ok_or(PerpError::MathOverflow)?;
+    agent.realized_pnl += settlement.realized_pnl;
+    agent.unrealized_pnl = replace_position_pnl(agent.unrealized_pnl, position.unrealized_pnl, 0)?;
     agent.total_trades += 1;
-    
+
     // Reward liquidator
-    liquidator_agent.collateral += liquidator_reward;
-    
-    // TODO: Send insurance fund portion to insurance vault
-    
+    liquidator_agent.collateral += settlement.liquidator_reward;
+
     // Reset position
     position.size = 0;
     position.entry_price = 0;
@@ -84,13 +187,65 @@ pub fn handler(ctx: Context<Liquidate>, _market_index: u8) -> Result<()> {
     position.liquidation_price = 0;
     position.unrealized_pnl = 0;
     position.updated_at = clock.unix_timestamp;
-    
+
     msg!(
-        "Position liquidated: penalty={}, liquidator_reward={}, insurance={}",
-        liquidation_penalty,
-        liquidator_reward,
-        insurance_fund
+        "Position liquidated: price={}, pnl={}, returned={}, liquidator_reward={}, insurance={}",
+        current_price,
+        settlement.realized_pnl,
+        settlement.returned,
+        settlement.liquidator_reward,
+        settlement.insurance_fund
     );
-    
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 10 units long at $100 with $100 margin, 5% maintenance: liquidation at $95
+    const SIZE: i64 = 10_000_000;
+    const ENTRY: u64 = 100_000_000;
+    const MARGIN: u64 = 100_000_000;
+    const LIQ_LONG: u64 = 95_000_000;
+
+    #[test]
+    fn test_liquidates_underwater_long() {
+        let settlement = settle_liquidation(SIZE, ENTRY, MARGIN, LIQ_LONG, 94_000_000).unwrap();
+
+        // -$60 loss leaves $40; 5% penalty ($5) split with the insurance fund
+        assert_eq!(settlement.realized_pnl, -60_000_000);
+        assert_eq!(settlement.returned, 35_000_000);
+        assert_eq!(settlement.liquidator_reward, 2_500_000);
+        assert_eq!(settlement.insurance_fund, 2_500_000);
+    }
+
+    #[test]
+    fn test_insurance_fund_credited_exact_portion() {
+        let settlement = settle_liquidation(SIZE, ENTRY, MARGIN, LIQ_LONG, 94_000_000).unwrap();
+        let before = 7_000_000;
+        let after = credit_insurance_fund(before, settlement.insurance_fund).unwrap();
+        assert_eq!(after - before, settlement.insurance_fund);
+
+        assert!(credit_insurance_fund(u64::MAX, 1).is_err());
+    }
+
+    #[test]
+    fn test_rejects_healthy_long() {
+        assert!(settle_liquidation(SIZE, ENTRY, MARGIN, LIQ_LONG, 99_000_000).is_err());
+        assert!(settle_liquidation(SIZE, ENTRY, MARGIN, LIQ_LONG, 0).is_err());
+    }
+
+    #[test]
+    fn test_short_and_bad_debt() {
+        // Short liquidates above its liquidation price
+        assert!(settle_liquidation(-SIZE, ENTRY, MARGIN, 105_000_000, 104_000_000).is_err());
+
+        // A $15 rally costs $150, wiping out the margin: the agent loses exactly the margin
+        let settlement = settle_liquidation(-SIZE, ENTRY, MARGIN, 105_000_000, 115_000_000).unwrap();
+        assert_eq!(settlement.realized_pnl, -(MARGIN as i64));
+        assert_eq!(settlement.returned, 0);
+        assert_eq!(settlement.liquidator_reward, 0);
+    }
+}
@@ -4,10 +4,12 @@ pub mod deposit;
 pub mod withdraw;
 pub mod open_position;
 pub mod close_position;
+pub mod close_position_partial;
 pub mod liquidate;
 pub mod settle_pnl;
 pub mod update_collateral;
 pub mod create_market;
+pub mod withdraw_insurance;
 
 pub use initialize::*;
 pub use register_agent::*;
@@ -15,7 +17,9 @@ pub use deposit::*;
 pub use withdraw::*;
 pub use open_position::*;
 pub use close_position::*;
+pub use close_position_partial::*;
 pub use liquidate::*;
 pub use settle_pnl::*;
 pub use update_collateral::*;
 pub use create_market::*;
+pub use withdraw_insurance::*;
@@ -1,6 +1,7 @@
 use anchor_lang::prelude::*;
-use crate::state::{Agent, Position};
+use crate::state::{Agent, Market, Position};
 use crate::errors::PerpError;
+use crate::oracle::{get_market_price, OracleError};
 
 #[derive(Accounts)]
 #[instruction(market_index: u8)]
@@ -23,17 +24,24 @@ pub struct SettlePnl<'info> {
     )]
     pub position: Account<'info, Position>,
     
-    // TODO: Add oracle account for current price
+    #[account(
+        seeds = [b"market", &[market_index]],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    
+    /// CHECK: Must be the market's Pyth price account; parsed by `get_market_price`
+    #[account(address = market.oracle @ OracleError::InvalidOracle)]
+    pub oracle: UncheckedAccount<'info>,
 }
 
 pub fn handler(ctx: Context<SettlePnl>, _market_index: u8) -> Result<()> {
+    let current_price = get_market_price(&ctx.accounts.oracle.to_account_info())?;
+    
     let agent = &mut ctx.accounts.agent;
     let position = &mut ctx.accounts.position;
     let clock = Clock::get()?;
     
-    // TODO: Get current price from oracle
-    let current_price: u64 = position.entry_price; // Placeholder
-    
     // Calculate unrealized PnL
     let unrealized_pnl = if position.size != 0 {
         calculate_unrealized_pnl(position.size, position.entry_price, current_price)?
@@ -41,14 +49,13 @@ pub fn handler(ctx: Context<SettlePnl>, _market_index: u8) -> Result<()> {
         0
     };
     
+    // Agent total is the sum over its markets: swap this position's old value for the new one
+    agent.unrealized_pnl = replace_position_pnl(agent.unrealized_pnl, position.unrealized_pnl, unrealized_pnl)?;
+    
     // Update position unrealized PnL
     position.unrealized_pnl = unrealized_pnl;
     position.updated_at = clock.unix_timestamp;
     
-    // Update agent unrealized PnL
-    // Note: This is a simplified version. In production, you'd sum across all positions
-    agent.unrealized_pnl = unrealized_pnl;
-    
     msg!(
         "Settled PnL: current_price={}, unrealized_pnl={}",
         current_price,
@@ -58,6 +65,16 @@ pub fn handler(ctx: Context<SettlePnl>, _market_index: u8) -> Result<()> {
     Ok(())
 }
 
+/// Agent-level unrealized PnL after one position's value changes from `old` to `new`
+pub(crate) fn replace_position_pnl(agent_total: i64, old: i64, new: i64) -> Result<i64> {
+    let total = agent_total
+        .checked_sub(old)
+        .and_then(|total| total.checked_add(new))
+        .ok_or(PerpError::MathOverflow)?;
+    
+    Ok(total)
+}
+
 fn calculate_unrealized_pnl(size: i64, entry_price: u64, current_price: u64) -> Result<i64> {
     let price_diff = current_price as i64 - entry_price as i64;
     
@@ -68,3 +85,35 @@ fn calculate_unrealized_pnl(size: i64, entry_price: u64, current_price: u64) ->
     
     Ok(pnl)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // 1 unit at $100 (6 decimals)
+    const SIZE: i64 = 1_000_000;
+    const ENTRY: u64 = 100_000_000;
+    
+    #[test]
+    fn test_profitable_long_settles_positive() {
+        let pnl = calculate_unrealized_pnl(SIZE, ENTRY, 110_000_000).unwrap();
+        assert_eq!(pnl, 10_000_000);
+        assert_eq!(replace_position_pnl(0, 0, pnl).unwrap(), 10_000_000);
+    }
+    
+    #[test]
+    fn test_losing_short_settles_negative() {
+        let pnl = calculate_unrealized_pnl(-SIZE, ENTRY, 104_000_000).unwrap();
+        assert_eq!(pnl, -4_000_000);
+        assert_eq!(replace_position_pnl(0, 0, pnl).unwrap(), -4_000_000);
+    }
+    
+    #[test]
+    fn test_agent_total_aggregates_markets() {
+        // BTC long +10 settled earlier, then ETH short -4, then BTC re-settled at +6
+        let total = replace_position_pnl(0, 0, 10_000_000).unwrap();
+        let total = replace_position_pnl(total, 0, -4_000_000).unwrap();
+        let total = replace_position_pnl(total, 10_000_000, 6_000_000).unwrap();
+        assert_eq!(total, 2_000_000);
+    }
+}
@@ -48,7 +48,9 @@ pub fn handler(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
         PerpError::InsufficientCollateral
     );
     
-    // TODO: Check margin requirements for open positions
+    // Open positions' margin is already held outside `collateral`; an
+    // unrealized loss must still be covered by what stays behind
+    check_free_collateral(agent.collateral, agent.unrealized_pnl, amount)?;
     
     // Transfer tokens from vault to user
     let exchange_seeds = &[b"exchange".as_ref(), &[ctx.accounts.exchange.bump]];
@@ -74,3 +76,43 @@ pub fn handler(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
     
     Ok(())
 }
+
+/// Reject a withdrawal that would leave the agent's free balance
+/// (collateral + unrealized PnL) negative.
+///
+/// Position margin is moved out of `collateral` on open, so it never counts
+/// as free. `unrealized_pnl` is the last value written by `settle_pnl`, not a
+/// live mark against the oracle.
+pub fn check_free_collateral(
+    collateral: u64,
+    unrealized_pnl: i64,
+    amount: u64,
+) -> Result<()> {
+    let free = collateral as i128 + unrealized_pnl.min(0) as i128;
+    require!(
+        free >= amount as i128,
+        PerpError::WithdrawalExceedsFreeCollateral
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_withdraw_free_balance_succeeds() {
+        // Whole free balance, no unrealized loss
+        assert!(check_free_collateral(500_000_000, 0, 500_000_000).is_ok());
+        // Unrealized profit is not withdrawable before it is realized
+        assert!(check_free_collateral(500_000_000, 200_000_000, 500_000_000).is_ok());
+        assert!(check_free_collateral(500_000_000, 200_000_000, 600_000_000).is_err());
+    }
+    
+    #[test]
+    fn test_withdraw_undercollateralizing_positions_fails() {
+        // A 150 settled loss must stay covered by the free balance
+        assert!(check_free_collateral(500_000_000, -150_000_000, 400_000_000).is_err());
+        assert!(check_free_collateral(500_000_000, -150_000_000, 350_000_000).is_ok());
+    }
+}
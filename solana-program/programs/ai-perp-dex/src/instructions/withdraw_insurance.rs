@@ -0,0 +1,71 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, TokenAccount, TokenInterface, TransferChecked};
+use crate::state::{Exchange, InsuranceFund};
+use crate::errors::PerpError;
+
+/// Withdraw from the insurance fund (admin only)
+#[derive(Accounts)]
+pub struct WithdrawInsurance<'info> {
+    /// Authority (must be exchange authority)
+    pub authority: Signer<'info>,
+    
+    #[account(
+        seeds = [b"exchange"],
+        bump = exchange.bump,
+        has_one = authority,
+    )]
+    pub exchange: Account<'info, Exchange>,
+    
+    #[account(
+        mut,
+        seeds = [b"insurance_fund"],
+        bump = insurance_fund.bump
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+    
+    #[account(
+        mut,
+        constraint = insurance_vault.key() == insurance_fund.vault @ PerpError::Unauthorized
+    )]
+    pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    
+    /// CHECK: Mint account for transfer_checked
+    pub mint: UncheckedAccount<'info>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+pub fn handler(ctx: Context<WithdrawInsurance>, amount: u64) -> Result<()> {
+    require!(amount > 0, PerpError::InvalidParameter);
+    require!(
+        ctx.accounts.insurance_fund.balance >= amount,
+        PerpError::InsufficientCollateral
+    );
+    
+    // Transfer tokens from insurance vault to destination
+    let exchange_seeds = &[b"exchange".as_ref(), &[ctx.accounts.exchange.bump]];
+    let signer_seeds = &[&exchange_seeds[..]];
+    
+    let transfer_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        TransferChecked {
+            from: ctx.accounts.insurance_vault.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+            authority: ctx.accounts.exchange.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+        },
+        signer_seeds,
+    );
+    token_interface::transfer_checked(transfer_ctx, amount, 6)?;  // 6 decimals
+    
+    let insurance_fund = &mut ctx.accounts.insurance_fund;
+    insurance_fund.balance -= amount;
+    insurance_fund.total_withdrawn += amount;
+    
+    msg!("Withdrew {} USDC from insurance fund (balance: {})", amount, insurance_fund.balance);
+    
+    Ok(())
+}
@@ -5,6 +5,7 @@ declare_id!("AHjGBth6uAKVipLGnooZ9GYn7vwSKPJLX4Lq7Hio3CjT");
 pub mod state;
 pub mod instructions;
 pub mod errors;
+pub mod oracle;
 
 use instructions::*;
 
@@ -51,6 +52,16 @@ pub mod ai_perp_dex {
         instructions::close_position::handler(ctx, market_index, exit_price)
     }
 
+    /// Close part of a position, keeping the remainder open
+    pub fn close_position_partial(
+        ctx: Context<ClosePositionPartial>,
+        market_index: u8,
+        close_size: u64,
+        exit_price: u64,
+    ) -> Result<()> {
+        instructions::close_position_partial::handler(ctx, market_index, close_size, exit_price)
+    }
+
     /// Liquidate an underwater position
     pub fn liquidate(ctx: Context<Liquidate>, market_index: u8) -> Result<()> {
         instructions::liquidate::handler(ctx, market_index)
@@ -66,6 +77,11 @@ pub mod ai_perp_dex {
         instructions::update_collateral::handler(ctx)
     }
 
+    /// Withdraw from the insurance fund (admin only)
+    pub fn withdraw_insurance(ctx: Context<WithdrawInsurance>, amount: u64) -> Result<()> {
+        instructions::withdraw_insurance::handler(ctx, amount)
+    }
+
     /// Create a new market (admin only)
     pub fn create_market(
         ctx: Context<CreateMarket>,
@@ -13,6 +13,8 @@ pub enum OracleError {
     PriceUncertain,
     #[msg("Invalid price data")]
     InvalidPriceData,
+    #[msg("Price overflows after normalization")]
+    PriceOverflow,
 }
 
 /// Maximum age for price updates (60 seconds)
@@ -171,24 +173,34 @@ pub fn get_price_from_pyth(
     }
     
     // Convert to 6 decimal precision
-    let normalized_price = normalize_price(price_abs, price_data.expo, 6);
+    let normalized_price = normalize_price(price_abs, price_data.expo, 6)?;
     
     Ok(normalized_price)
 }
 
-/// Normalize a price from one decimal precision to another
-fn normalize_price(price: u64, from_exponent: i32, to_decimals: i32) -> u64 {
-    let from_decimals = -from_exponent;
-    let adjustment = to_decimals - from_decimals;
+/// Normalize `price * 10^from_exponent` to a value with `to_decimals` decimals.
+///
+/// Works for any exponent sign. Scaling up fails with `PriceOverflow` if the
+/// result does not fit in a u64; scaling down past u64 range saturates to 0.
+fn normalize_price(price: u64, from_exponent: i32, to_decimals: i32) -> Result<u64> {
+    let adjustment = (to_decimals as i64) + (from_exponent as i64);
     
-    if adjustment > 0 {
+    if adjustment >= 0 {
         // Need to multiply
-        price.saturating_mul(10u64.pow(adjustment as u32))
-    } else if adjustment < 0 {
-        // Need to divide
-        price / 10u64.pow((-adjustment) as u32)
+        let factor = u32::try_from(adjustment)
+            .ok()
+            .and_then(|exp| 10u64.checked_pow(exp));
+        match factor.and_then(|f| price.checked_mul(f)) {
+            Some(normalized) => Ok(normalized),
+            None if price == 0 => Ok(0),
+            None => Err(OracleError::PriceOverflow.into()),
+        }
     } else {
-        price
+        // Need to divide; a divisor beyond u64 range leaves nothing
+        let divisor = u32::try_from(-adjustment)
+            .ok()
+            .and_then(|exp| 10u64.checked_pow(exp));
+        Ok(divisor.map_or(0, |d| price / d))
     }
 }
 
@@ -212,17 +224,21 @@ impl OraclePrice {
         let data = price_account.try_borrow_data()?;
         let price_data = parse_pyth_price(&data)?;
         
+        if price_data.price < 0 {
+            return Err(OracleError::NegativePrice.into());
+        }
+        
         let normalized_price = normalize_price(
             price_data.price as u64,
             price_data.expo,
             6,
-        );
+        )?;
         
         let normalized_conf = normalize_price(
             price_data.conf,
             price_data.expo,
             6,
-        );
+        )?;
         
         Ok(Self {
             price: normalized_price,
@@ -241,17 +257,42 @@ mod tests {
         // BTC at $95,000 with -8 exponent
         // 9500000000000 * 10^-8 = $95,000
         let btc_price = 9500000000000u64;
-        let normalized = normalize_price(btc_price, -8, 6);
+        let normalized = normalize_price(btc_price, -8, 6).unwrap();
         assert_eq!(normalized, 95000000000); // $95,000 with 6 decimals
         
         // ETH at $3,500 with -8 exponent
         let eth_price = 350000000000u64;
-        let normalized = normalize_price(eth_price, -8, 6);
+        let normalized = normalize_price(eth_price, -8, 6).unwrap();
         assert_eq!(normalized, 3500000000); // $3,500 with 6 decimals
         
         // SOL at $150 with -8 exponent
         let sol_price = 15000000000u64;
-        let normalized = normalize_price(sol_price, -8, 6);
+        let normalized = normalize_price(sol_price, -8, 6).unwrap();
         assert_eq!(normalized, 150000000); // $150 with 6 decimals
     }
+    
+    #[test]
+    fn test_normalize_price_exponent_signs() {
+        // expo = +2: 950 * 10^2 = $95,000
+        assert_eq!(normalize_price(950, 2, 6).unwrap(), 95000000000);
+        
+        // expo = 0: $150
+        assert_eq!(normalize_price(150, 0, 6).unwrap(), 150000000);
+        
+        // expo = -8 with sub-micro precision truncates
+        assert_eq!(normalize_price(123456789, -8, 6).unwrap(), 1234567);
+    }
+    
+    #[test]
+    fn test_normalize_price_overflow_guard() {
+        // Scaling up past u64 is an error rather than a panic or silent saturation
+        assert!(normalize_price(u64::MAX / 10, 0, 6).is_err());
+        assert!(normalize_price(1, 20, 6).is_err());
+        assert!(normalize_price(1, i32::MAX, 6).is_err());
+        assert_eq!(normalize_price(0, 20, 6).unwrap(), 0);
+        
+        // Scaling down past u64 range saturates to zero
+        assert_eq!(normalize_price(u64::MAX, -40, 6).unwrap(), 0);
+        assert_eq!(normalize_price(u64::MAX, i32::MIN, 6).unwrap(), 0);
+    }
 }
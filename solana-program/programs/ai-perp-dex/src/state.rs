@@ -154,6 +154,28 @@ impl Market {
         1;   // bump
 }
 
+/// Insurance fund (receives liquidation penalties)
+#[account]
+#[derive(Default)]
+pub struct InsuranceFund {
+    /// Insurance token vault
+    pub vault: Pubkey,
+    /// Running balance credited to the fund
+    pub balance: u64,
+    /// Total ever withdrawn by the authority
+    pub total_withdrawn: u64,
+    /// Bump seed
+    pub bump: u8,
+}
+
+impl InsuranceFund {
+    pub const SIZE: usize = 8 + // discriminator
+        32 + // vault
+        8 +  // balance
+        8 +  // total_withdrawn
+        1;   // bump
+}
+
 /// Trade history record
 #[account]
 pub struct TradeRecord {
@@ -0,0 +1,27 @@
+//! Helpers shared by the program-test integration tests
+#![allow(dead_code)] // each test crate uses a different subset
+
+use anchor_lang::solana_program::{account_info::AccountInfo, entrypoint::ProgramResult};
+use solana_program_test::{processor, ProgramTest};
+use solana_sdk::pubkey::Pubkey;
+
+/// Anchor's entrypoint ties the account slice to the `'info` lifetime, which
+/// `processor!` cannot express
+fn process_instruction<'info>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'info>],
+    data: &[u8],
+) -> ProgramResult {
+    let accounts: &'info [AccountInfo<'info>] = unsafe { std::mem::transmute(accounts) };
+    ai_perp_dex::entry(program_id, accounts, data)
+}
+
+/// The program, run natively unless `BPF_OUT_DIR`/`SBF_OUT_DIR` points at a
+/// built `ai_perp_dex.so` (as `cargo test-sbf` does)
+pub fn program_test() -> ProgramTest {
+    ProgramTest::new("ai_perp_dex", ai_perp_dex::ID, processor!(process_instruction))
+}
+
+pub fn pda(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &ai_perp_dex::ID).0
+}
@@ -0,0 +1,283 @@
+//! End-to-end liquidation through the program's instruction handlers.
+//!
+//! Runs natively under `cargo test`; `cargo test-sbf` runs the same test
+//! against the SBF build of the program instead.
+
+mod common;
+
+use ai_perp_dex::state::{Agent, Position};
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use anchor_spl::token::spl_token;
+use common::{pda, program_test};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    clock::Clock,
+    instruction::Instruction,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signer},
+    system_instruction, system_program, sysvar,
+    transaction::Transaction,
+};
+
+const MARKET: u8 = 0;
+/// $1 in USDC base units (6 decimals)
+const USDC: u64 = 1_000_000;
+
+/// Pyth V2 price account at `price` dollars (expo -8), published at `publish_time`
+fn pyth_price_account(price: u64, publish_time: i64) -> AccountSharedData {
+    let mut data = vec![0u8; 256];
+    data[0..4].copy_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+    data[4..8].copy_from_slice(&2u32.to_le_bytes());
+    data[20..24].copy_from_slice(&(-8i32).to_le_bytes());
+    data[208..216].copy_from_slice(&((price * 100_000_000) as i64).to_le_bytes());
+    data[248..256].copy_from_slice(&publish_time.to_le_bytes());
+    AccountSharedData::from(Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: Pubkey::new_unique(),
+        executable: false,
+        rent_epoch: 0,
+    })
+}
+
+struct Harness {
+    ctx: ProgramTestContext,
+    mint: Keypair,
+    oracle: Pubkey,
+}
+
+impl Harness {
+    /// Exchange, one market (10% initial / 5% maintenance) and a funded mint
+    async fn new() -> Self {
+        let ctx = program_test().start_with_context().await;
+        let mut harness = Self { ctx, mint: Keypair::new(), oracle: Pubkey::new_unique() };
+
+        let authority = harness.ctx.payer.pubkey();
+        let mint = harness.mint.insecure_clone();
+        let rent = Rent::default();
+        harness.send(
+            &[
+                system_instruction::create_account(
+                    &authority,
+                    &mint.pubkey(),
+                    rent.minimum_balance(spl_token::state::Mint::LEN),
+                    spl_token::state::Mint::LEN as u64,
+                    &spl_token::ID,
+                ),
+                spl_token::instruction::initialize_mint(&spl_token::ID, &mint.pubkey(), &authority, None, 6).unwrap(),
+            ],
+            &[&mint],
+        ).await;
+
+        harness.send(&[Instruction {
+            program_id: ai_perp_dex::ID,
+            accounts: ai_perp_dex::accounts::Initialize {
+                authority,
+                exchange: pda(&[b"exchange"]),
+                collateral_mint: mint.pubkey(),
+                vault: pda(&[b"vault"]),
+                insurance_fund: pda(&[b"insurance_fund"]),
+                insurance_vault: pda(&[b"insurance_vault"]),
+                system_program: system_program::ID,
+                token_program: spl_token::ID,
+                rent: sysvar::rent::ID,
+            }.to_account_metas(None),
+            data: ai_perp_dex::instruction::Initialize { fee_rate_bps: 10 }.data(),
+        }], &[]).await;
+
+        harness.set_price(100).await;
+        let mut symbol = [0u8; 16];
+        symbol[..8].copy_from_slice(b"BTC-PERP");
+        harness.send(&[Instruction {
+            program_id: ai_perp_dex::ID,
+            accounts: ai_perp_dex::accounts::CreateMarket {
+                authority,
+                exchange: pda(&[b"exchange"]),
+                market: pda(&[b"market", &[MARKET]]),
+                oracle: harness.oracle,
+                system_program: system_program::ID,
+            }.to_account_metas(None),
+            data: ai_perp_dex::instruction::CreateMarket {
+                market_index: MARKET,
+                symbol,
+                initial_margin_rate: 1000,
+                maintenance_margin_rate: 500,
+                max_leverage: 10,
+            }.data(),
+        }], &[]).await;
+
+        harness
+    }
+
+    async fn send(&mut self, instructions: &[Instruction], signers: &[&Keypair]) {
+        self.try_send(instructions, signers).await.unwrap();
+    }
+
+    async fn try_send(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), solana_program_test::BanksClientError> {
+        let payer = self.ctx.payer.insecure_clone();
+        let mut all_signers = vec![&payer];
+        all_signers.extend_from_slice(signers);
+        let blockhash = self.ctx.banks_client.get_latest_blockhash().await.unwrap();
+        let tx = Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), &all_signers, blockhash);
+        self.ctx.banks_client.process_transaction(tx).await
+    }
+
+    /// Publish a fresh oracle price in whole dollars
+    async fn set_price(&mut self, price: u64) {
+        let clock: Clock = self.ctx.banks_client.get_sysvar().await.unwrap();
+        self.ctx.set_account(&self.oracle, &pyth_price_account(price, clock.unix_timestamp));
+    }
+
+    /// Register an agent for a new wallet and deposit `deposit` USDC into it
+    async fn agent(&mut self, deposit: u64) -> (Keypair, Pubkey) {
+        let owner = Keypair::new();
+        let token_account = Keypair::new();
+        let payer = self.ctx.payer.pubkey();
+        let agent = pda(&[b"agent", owner.pubkey().as_ref()]);
+        let rent = Rent::default();
+
+        self.send(
+            &[
+                system_instruction::transfer(&payer, &owner.pubkey(), 1_000_000_000),
+                system_instruction::create_account(
+                    &payer,
+                    &token_account.pubkey(),
+                    rent.minimum_balance(spl_token::state::Account::LEN),
+                    spl_token::state::Account::LEN as u64,
+                    &spl_token::ID,
+                ),
+                spl_token::instruction::initialize_account(
+                    &spl_token::ID,
+                    &token_account.pubkey(),
+                    &self.mint.pubkey(),
+                    &owner.pubkey(),
+                ).unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::ID,
+                    &self.mint.pubkey(),
+                    &token_account.pubkey(),
+                    &payer,
+                    &[],
+                    deposit,
+                ).unwrap(),
+            ],
+            &[&token_account],
+        ).await;
+
+        self.send(&[Instruction {
+            program_id: ai_perp_dex::ID,
+            accounts: ai_perp_dex::accounts::RegisterAgent {
+                owner: owner.pubkey(),
+                exchange: pda(&[b"exchange"]),
+                agent,
+                system_program: system_program::ID,
+            }.to_account_metas(None),
+            data: ai_perp_dex::instruction::RegisterAgent { name: "agent".to_string() }.data(),
+        }], &[&owner]).await;
+
+        if deposit > 0 {
+            self.send(&[Instruction {
+                program_id: ai_perp_dex::ID,
+                accounts: ai_perp_dex::accounts::Deposit {
+                    owner: owner.pubkey(),
+                    exchange: pda(&[b"exchange"]),
+                    agent,
+                    owner_token_account: token_account.pubkey(),
+                    vault: pda(&[b"vault"]),
+                    mint: self.mint.pubkey(),
+                    token_program: spl_token::ID,
+                }.to_account_metas(None),
+                data: ai_perp_dex::instruction::Deposit { amount: deposit }.data(),
+            }], &[&owner]).await;
+        }
+
+        (owner, agent)
+    }
+
+    async fn open_position(&mut self, agent: Pubkey, size: i64, entry_price: u64) {
+        let authority = self.ctx.payer.pubkey();
+        self.send(&[Instruction {
+            program_id: ai_perp_dex::ID,
+            accounts: ai_perp_dex::accounts::OpenPosition {
+                authority,
+                exchange: pda(&[b"exchange"]),
+                agent,
+                position: pda(&[b"position", agent.as_ref(), &[MARKET]]),
+                market: pda(&[b"market", &[MARKET]]),
+                payer: authority,
+                system_program: system_program::ID,
+            }.to_account_metas(None),
+            data: ai_perp_dex::instruction::OpenPosition { market_index: MARKET, size, entry_price }.data(),
+        }], &[]).await;
+    }
+
+    async fn liquidate(
+        &mut self,
+        liquidator: &Keypair,
+        agent: Pubkey,
+    ) -> Result<(), solana_program_test::BanksClientError> {
+        let instruction = Instruction {
+            program_id: ai_perp_dex::ID,
+            accounts: ai_perp_dex::accounts::Liquidate {
+                liquidator: liquidator.pubkey(),
+                exchange: pda(&[b"exchange"]),
+                agent,
+                position: pda(&[b"position", agent.as_ref(), &[MARKET]]),
+                liquidator_agent: pda(&[b"agent", liquidator.pubkey().as_ref()]),
+                market: pda(&[b"market", &[MARKET]]),
+                oracle: self.oracle,
+                insurance_fund: pda(&[b"insurance_fund"]),
+                vault: pda(&[b"vault"]),
+                insurance_vault: pda(&[b"insurance_vault"]),
+                mint: self.mint.pubkey(),
+                token_program: spl_token::ID,
+            }.to_account_metas(None),
+            data: ai_perp_dex::instruction::Liquidate { market_index: MARKET }.data(),
+        };
+        self.try_send(&[instruction], &[liquidator]).await
+    }
+
+    async fn account<T: AccountDeserialize>(&mut self, address: Pubkey) -> T {
+        let account = self.ctx.banks_client.get_account(address).await.unwrap().unwrap();
+        T::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+}
+
+#[tokio::test]
+async fn test_liquidates_underwater_long() {
+    let mut harness = Harness::new().await;
+    let (_trader, agent) = harness.agent(1_000 * USDC).await;
+    let (liquidator, liquidator_agent) = harness.agent(0).await;
+
+    // 10 units long at $100: $100 margin, liquidation at $95
+    harness.open_position(agent, 10_000_000, 100 * USDC).await;
+    let position: Position = harness.account(pda(&[b"position", agent.as_ref(), &[MARKET]])).await;
+    assert_eq!(position.margin, 100 * USDC);
+    assert_eq!(position.liquidation_price, 95 * USDC);
+
+    // Still healthy above the liquidation price
+    harness.set_price(99).await;
+    assert!(harness.liquidate(&liquidator, agent).await.is_err());
+
+    // -$60 at $94 leaves $40; the 5% penalty ($5) is split with the insurance fund
+    harness.set_price(94).await;
+    harness.liquidate(&liquidator, agent).await.unwrap();
+
+    let position: Position = harness.account(pda(&[b"position", agent.as_ref(), &[MARKET]])).await;
+    assert_eq!(position.size, 0);
+    assert_eq!(position.margin, 0);
+
+    let trader: Agent = harness.account(agent).await;
+    assert_eq!(trader.collateral, 900 * USDC + 35 * USDC);
+    assert_eq!(trader.realized_pnl, -60 * USDC as i64);
+
+    let liquidator: Agent = harness.account(liquidator_agent).await;
+    assert_eq!(liquidator.collateral, 2_500_000);
+}
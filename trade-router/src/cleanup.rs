@@ -0,0 +1,152 @@
+//! Cleanup task - removes expired trade requests and quotes
+//!
+//! Requests past `expires_at` are dropped together with all of their quotes;
+//! quotes past `valid_until` are dropped from requests that are still open.
+
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::time::interval;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::state::AppState;
+use crate::types::WsMessage;
+
+/// Start the cleanup task as a background task
+pub async fn start_cleanup_task(state: Arc<AppState>, every: Duration) {
+    info!("🧹 Cleanup task starting (interval: {}s)", every.as_secs());
+    
+    let mut ticker = interval(every);
+    
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        
+        let (requests, quotes) = run_cleanup(&state, Utc::now());
+        if requests > 0 || quotes > 0 {
+            info!("🧹 Removed {} expired requests, {} expired quotes", requests, quotes);
+        }
+    }
+    info!("🧹 Cleanup task stopped");
+}
+
+/// Run one cleanup pass, returning (expired requests, expired quotes) removed
+pub fn run_cleanup(state: &AppState, now: DateTime<Utc>) -> (usize, usize) {
+    let expired: Vec<Uuid> = state.requests.iter()
+        .filter(|r| r.expires_at <= now)
+        .map(|r| r.id)
+        .collect();
+    
+    for request_id in &expired {
+        state.requests.remove(request_id);
+        state.quotes.remove(request_id);
+        if let Err(e) = state.db.delete_trade_request(request_id) {
+            warn!("Failed to delete expired request {}: {}", request_id, e);
+        }
+        let _ = state.broadcast_tx.send(WsMessage::RequestExpired { request_id: *request_id });
+    }
+    
+    let mut expired_quotes = 0;
+    for mut entry in state.quotes.iter_mut() {
+        let (stale, live): (Vec<_>, Vec<_>) = entry.value_mut()
+            .drain(..)
+            .partition(|q| q.valid_until <= now);
+        *entry.value_mut() = live;
+        
+        for quote in stale {
+            if let Err(e) = state.db.delete_quote(&quote.id) {
+                warn!("Failed to delete expired quote {}: {}", quote.id, e);
+            }
+            expired_quotes += 1;
+        }
+    }
+    
+    (expired.len(), expired_quotes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Usd;
+    use crate::types::{MarginMode, Market, Quote, Side, TradeRequest};
+    use chrono::Duration as ChronoDuration;
+    
+    #[test]
+    fn test_cleanup_removes_expired_request_and_quotes() {
+        let state = AppState::with_db_path(":memory:");
+        let request = TradeRequest {
+            id: Uuid::new_v4(),
+            agent_id: "trader".to_string(),
+            market: Market::SolPerp,
+            side: Side::Long,
+            size_usdc: Usd::from(100.0),
+            leverage: 2,
+            max_funding_rate: 0.01,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: MarginMode::Isolated,
+            reduce_only: false,
+            expires_at: Utc::now() - ChronoDuration::seconds(1),
+            created_at: Utc::now() - ChronoDuration::seconds(60),
+        };
+        state.add_request(request.clone());
+        state.add_quote(Quote {
+            id: Uuid::new_v4(),
+            request_id: request.id,
+            agent_id: "mm".to_string(),
+            funding_rate: 0.001,
+            collateral_usdc: Usd::from(50.0),
+            valid_until: Utc::now() + ChronoDuration::seconds(30),
+            created_at: Utc::now(),
+            protection: None,
+        }).unwrap();
+        let mut rx = state.broadcast_tx.subscribe();
+        
+        assert_eq!(run_cleanup(&state, Utc::now()), (1, 0));
+        assert!(state.requests.is_empty());
+        assert!(state.quotes.is_empty());
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(WsMessage::RequestExpired { request_id }) if request_id == request.id
+        ));
+    }
+    
+    #[test]
+    fn test_cleanup_drops_stale_quotes_of_open_request() {
+        let state = AppState::with_db_path(":memory:");
+        let request_id = Uuid::new_v4();
+        state.add_request(TradeRequest {
+            id: request_id,
+            agent_id: "trader".to_string(),
+            market: Market::SolPerp,
+            side: Side::Short,
+            size_usdc: Usd::from(100.0),
+            leverage: 2,
+            max_funding_rate: 0.01,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: MarginMode::Isolated,
+            reduce_only: false,
+            expires_at: Utc::now() + ChronoDuration::seconds(60),
+            created_at: Utc::now(),
+        });
+        for valid_for in [-5, 30] {
+            state.add_quote(Quote {
+                id: Uuid::new_v4(),
+                request_id,
+                agent_id: "mm".to_string(),
+                funding_rate: 0.001,
+                collateral_usdc: Usd::from(50.0),
+                valid_until: Utc::now() + ChronoDuration::seconds(valid_for),
+                created_at: Utc::now(),
+                protection: None,
+            }).unwrap();
+        }
+        
+        assert_eq!(run_cleanup(&state, Utc::now()), (0, 1));
+        assert_eq!(state.get_quotes(request_id).len(), 1);
+    }
+}
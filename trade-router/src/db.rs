@@ -1,44 +1,193 @@
 //! SQLite persistence layer
 
-use rusqlite::{Connection, params};
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
 use std::sync::Mutex;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 
-use crate::types::{AgentInfo, AgentStats, Market, Position, PositionStatus, PositionWithPnl, Side};
-use crate::funding::{FundingPayment, FundingSummary};
+use crate::types::{
+    AgentInfo, AgentStats, FundingPaymentParams, InsuranceFundEvent, MarginMode, Market, Position, PositionStatus, PositionWithPnl, Quote, QuoteProtection, SettlementAction,
+    SettlementJob, SettlementJobStatus, Side, SocializedLoss, TradeRecord, TradeRequest,
+};
+use crate::funding::{FundingPayment, FundingRateBucket, FundingSummary};
+use crate::money::Usd;
 
-pub struct Database {
-    conn: Mutex<Connection>,
-}
+/// Schema 迁移，按顺序执行；第 N 个迁移完成后版本号为 N。
+/// 迁移必须是幂等的 (旧数据库可能已经有部分表)。
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
 
-impl Database {
-    pub fn new(path: &str) -> rusqlite::Result<Self> {
-        let conn = Connection::open(path)?;
-        let db = Self { conn: Mutex::new(conn) };
-        db.init_tables()?;
-        Ok(db)
-    }
-    
-    pub fn in_memory() -> rusqlite::Result<Self> {
-        Self::new(":memory:")
-    }
-    
-    fn init_tables(&self) -> rusqlite::Result<()> {
-        let conn = self.conn.lock().unwrap();
+const MIGRATIONS: &[Migration] = &[
+    // 1: 初始 schema
+    |tx| tx.execute_batch(r#"
+        -- Agents table
+        CREATE TABLE IF NOT EXISTS agents (
+            id TEXT PRIMARY KEY,
+            api_key TEXT UNIQUE NOT NULL,
+            name TEXT,
+            is_mm INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL
+        );
         
-        conn.execute_batch(r#"
-            -- Agents table
-            CREATE TABLE IF NOT EXISTS agents (
-                id TEXT PRIMARY KEY,
-                api_key TEXT UNIQUE NOT NULL,
-                name TEXT,
-                is_mm INTEGER NOT NULL DEFAULT 0,
-                created_at TEXT NOT NULL
-            );
-            
-            -- Positions table
-            CREATE TABLE IF NOT EXISTS positions (
+        -- Positions table
+        CREATE TABLE IF NOT EXISTS positions (
+            id TEXT PRIMARY KEY,
+            request_id TEXT NOT NULL,
+            quote_id TEXT NOT NULL,
+            trader_agent TEXT NOT NULL,
+            mm_agent TEXT NOT NULL,
+            market TEXT NOT NULL,
+            side TEXT NOT NULL,
+            size_usdc REAL NOT NULL,
+            leverage INTEGER NOT NULL,
+            entry_price REAL NOT NULL,
+            funding_rate REAL NOT NULL,
+            trader_collateral REAL NOT NULL,
+            mm_collateral REAL NOT NULL,
+            status TEXT NOT NULL DEFAULT 'active',
+            created_at TEXT NOT NULL,
+            closed_at TEXT,
+            pnl_trader REAL,
+            pnl_mm REAL
+        );
+        
+        -- Trades table (history)
+        CREATE TABLE IF NOT EXISTS trades (
+            id TEXT PRIMARY KEY,
+            position_id TEXT NOT NULL,
+            trader_agent TEXT NOT NULL,
+            mm_agent TEXT NOT NULL,
+            market TEXT NOT NULL,
+            side TEXT NOT NULL,
+            size_usdc REAL NOT NULL,
+            entry_price REAL NOT NULL,
+            exit_price REAL,
+            pnl_trader REAL,
+            pnl_mm REAL,
+            created_at TEXT NOT NULL,
+            closed_at TEXT
+        );
+        
+        -- Funding payments table
+        CREATE TABLE IF NOT EXISTS funding_payments (
+            id TEXT PRIMARY KEY,
+            position_id TEXT NOT NULL,
+            trader_agent TEXT NOT NULL,
+            mm_agent TEXT NOT NULL,
+            funding_rate REAL NOT NULL,
+            position_size REAL NOT NULL,
+            payment_amount REAL NOT NULL,
+            settled_at TEXT NOT NULL
+        );
+        
+        -- Create indexes
+        CREATE INDEX IF NOT EXISTS idx_positions_trader ON positions(trader_agent);
+        CREATE INDEX IF NOT EXISTS idx_positions_mm ON positions(mm_agent);
+        CREATE INDEX IF NOT EXISTS idx_positions_status ON positions(status);
+        CREATE INDEX IF NOT EXISTS idx_agents_api_key ON agents(api_key);
+        CREATE INDEX IF NOT EXISTS idx_funding_trader ON funding_payments(trader_agent);
+        CREATE INDEX IF NOT EXISTS idx_funding_mm ON funding_payments(mm_agent);
+        CREATE INDEX IF NOT EXISTS idx_funding_settled ON funding_payments(settled_at);
+    "#),
+    // 2: RFQ 持久化 + 交易历史索引
+    |tx| tx.execute_batch(r#"
+        CREATE TABLE IF NOT EXISTS trade_requests (
+            id TEXT PRIMARY KEY,
+            agent_id TEXT NOT NULL,
+            market TEXT NOT NULL,
+            side TEXT NOT NULL,
+            size_usdc REAL NOT NULL,
+            leverage INTEGER NOT NULL,
+            max_funding_rate REAL NOT NULL,
+            expires_at TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        );
+        
+        CREATE TABLE IF NOT EXISTS quotes (
+            id TEXT PRIMARY KEY,
+            request_id TEXT NOT NULL,
+            agent_id TEXT NOT NULL,
+            funding_rate REAL NOT NULL,
+            collateral_usdc REAL NOT NULL,
+            valid_until TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        );
+        
+        CREATE INDEX IF NOT EXISTS idx_quotes_request ON quotes(request_id);
+        CREATE INDEX IF NOT EXISTS idx_trades_trader ON trades(trader_agent);
+        CREATE INDEX IF NOT EXISTS idx_trades_mm ON trades(mm_agent);
+    "#),
+    // 3: 统一仓位状态大小写 (旧数据使用默认值 'active')
+    |tx| tx.execute_batch(r#"
+        UPDATE positions SET status = 'Active' WHERE status = 'active';
+    "#),
+    // 4: 止损/止盈
+    |tx| {
+        add_column_if_missing(tx, "positions", "stop_loss", "REAL")?;
+        add_column_if_missing(tx, "positions", "take_profit", "REAL")?;
+        add_column_if_missing(tx, "trade_requests", "stop_loss", "REAL")?;
+        add_column_if_missing(tx, "trade_requests", "take_profit", "REAL")
+    },
+    // 5: 保险基金流水
+    |tx| tx.execute_batch(r#"
+        CREATE TABLE IF NOT EXISTS insurance_fund_events (
+            id TEXT PRIMARY KEY,
+            position_id TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            amount REAL NOT NULL,
+            balance_after REAL NOT NULL,
+            created_at TEXT NOT NULL
+        );
+    "#),
+    // 6: 市场资金费率历史 (recorded_at 为 Unix 秒，便于按时间分桶)
+    |tx| tx.execute_batch(r#"
+        CREATE TABLE IF NOT EXISTS funding_rates (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            market TEXT NOT NULL,
+            rate REAL NOT NULL,
+            recorded_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_funding_rates_market_time ON funding_rates(market, recorded_at);
+    "#),
+    // 7: 保证金模式 (旧数据均为逐仓)
+    |tx| {
+        add_column_if_missing(tx, "positions", "margin_mode", "TEXT NOT NULL DEFAULT 'Isolated'")?;
+        add_column_if_missing(tx, "trade_requests", "margin_mode", "TEXT NOT NULL DEFAULT 'Isolated'")
+    },
+    // 8: 社会化亏损分摊
+    |tx| tx.execute_batch(r#"
+        CREATE TABLE IF NOT EXISTS socialized_losses (
+            id TEXT PRIMARY KEY,
+            position_id TEXT NOT NULL,
+            winner_position_id TEXT NOT NULL,
+            agent_id TEXT NOT NULL,
+            amount REAL NOT NULL,
+            created_at TEXT NOT NULL
+        );
+    "#),
+    // 9: 只减仓请求
+    |tx| add_column_if_missing(tx, "trade_requests", "reduce_only", "INTEGER NOT NULL DEFAULT 0"),
+    // 10: 链上结算重试队列 (时间戳为毫秒，便于按到期时间比较)
+    |tx| tx.execute_batch(r#"
+        CREATE TABLE IF NOT EXISTS settlement_queue (
+            position_id TEXT NOT NULL,
+            action TEXT NOT NULL,
+            owner TEXT NOT NULL,
+            market TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            price REAL NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at INTEGER NOT NULL,
+            last_error TEXT,
+            created_at TEXT NOT NULL,
+            PRIMARY KEY (position_id, action)
+        );
+        CREATE INDEX IF NOT EXISTS idx_settlement_queue_due ON settlement_queue(status, next_attempt_at);
+    "#),
+    // 11: 金额列改为 TEXT 存储十进制 (SQLite 不能修改列类型，按原列顺序重建表)
+    |tx| {
+        rebuild_table(tx, "positions", r#"
+            CREATE TABLE positions (
                 id TEXT PRIMARY KEY,
                 request_id TEXT NOT NULL,
                 quote_id TEXT NOT NULL,
@@ -46,61 +195,289 @@ impl Database {
                 mm_agent TEXT NOT NULL,
                 market TEXT NOT NULL,
                 side TEXT NOT NULL,
-                size_usdc REAL NOT NULL,
+                size_usdc TEXT NOT NULL,
                 leverage INTEGER NOT NULL,
-                entry_price REAL NOT NULL,
+                entry_price TEXT NOT NULL,
                 funding_rate REAL NOT NULL,
-                trader_collateral REAL NOT NULL,
-                mm_collateral REAL NOT NULL,
+                trader_collateral TEXT NOT NULL,
+                mm_collateral TEXT NOT NULL,
                 status TEXT NOT NULL DEFAULT 'active',
                 created_at TEXT NOT NULL,
                 closed_at TEXT,
-                pnl_trader REAL,
-                pnl_mm REAL
+                pnl_trader TEXT,
+                pnl_mm TEXT,
+                stop_loss REAL,
+                take_profit REAL,
+                margin_mode TEXT NOT NULL DEFAULT 'Isolated'
             );
-            
-            -- Trades table (history)
-            CREATE TABLE IF NOT EXISTS trades (
+            CREATE INDEX idx_positions_trader ON positions(trader_agent);
+            CREATE INDEX idx_positions_mm ON positions(mm_agent);
+            CREATE INDEX idx_positions_status ON positions(status);
+        "#)?;
+        rebuild_table(tx, "trades", r#"
+            CREATE TABLE trades (
                 id TEXT PRIMARY KEY,
                 position_id TEXT NOT NULL,
                 trader_agent TEXT NOT NULL,
                 mm_agent TEXT NOT NULL,
                 market TEXT NOT NULL,
                 side TEXT NOT NULL,
-                size_usdc REAL NOT NULL,
-                entry_price REAL NOT NULL,
-                exit_price REAL,
-                pnl_trader REAL,
-                pnl_mm REAL,
+                size_usdc TEXT NOT NULL,
+                entry_price TEXT NOT NULL,
+                exit_price TEXT,
+                pnl_trader TEXT,
+                pnl_mm TEXT,
                 created_at TEXT NOT NULL,
                 closed_at TEXT
             );
-            
-            -- Funding payments table
-            CREATE TABLE IF NOT EXISTS funding_payments (
+            CREATE INDEX idx_trades_trader ON trades(trader_agent);
+            CREATE INDEX idx_trades_mm ON trades(mm_agent);
+        "#)?;
+        rebuild_table(tx, "funding_payments", r#"
+            CREATE TABLE funding_payments (
                 id TEXT PRIMARY KEY,
                 position_id TEXT NOT NULL,
                 trader_agent TEXT NOT NULL,
                 mm_agent TEXT NOT NULL,
                 funding_rate REAL NOT NULL,
-                position_size REAL NOT NULL,
-                payment_amount REAL NOT NULL,
+                position_size TEXT NOT NULL,
+                payment_amount TEXT NOT NULL,
                 settled_at TEXT NOT NULL
             );
-            
-            -- Create indexes
-            CREATE INDEX IF NOT EXISTS idx_positions_trader ON positions(trader_agent);
-            CREATE INDEX IF NOT EXISTS idx_positions_mm ON positions(mm_agent);
-            CREATE INDEX IF NOT EXISTS idx_positions_status ON positions(status);
-            CREATE INDEX IF NOT EXISTS idx_agents_api_key ON agents(api_key);
-            CREATE INDEX IF NOT EXISTS idx_funding_trader ON funding_payments(trader_agent);
-            CREATE INDEX IF NOT EXISTS idx_funding_mm ON funding_payments(mm_agent);
-            CREATE INDEX IF NOT EXISTS idx_funding_settled ON funding_payments(settled_at);
+            CREATE INDEX idx_funding_trader ON funding_payments(trader_agent);
+            CREATE INDEX idx_funding_mm ON funding_payments(mm_agent);
+            CREATE INDEX idx_funding_settled ON funding_payments(settled_at);
         "#)?;
+        rebuild_table(tx, "trade_requests", r#"
+            CREATE TABLE trade_requests (
+                id TEXT PRIMARY KEY,
+                agent_id TEXT NOT NULL,
+                market TEXT NOT NULL,
+                side TEXT NOT NULL,
+                size_usdc TEXT NOT NULL,
+                leverage INTEGER NOT NULL,
+                max_funding_rate REAL NOT NULL,
+                expires_at TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                stop_loss REAL,
+                take_profit REAL,
+                margin_mode TEXT NOT NULL DEFAULT 'Isolated',
+                reduce_only INTEGER NOT NULL DEFAULT 0
+            );
+        "#)?;
+        rebuild_table(tx, "quotes", r#"
+            CREATE TABLE quotes (
+                id TEXT PRIMARY KEY,
+                request_id TEXT NOT NULL,
+                agent_id TEXT NOT NULL,
+                funding_rate REAL NOT NULL,
+                collateral_usdc TEXT NOT NULL,
+                valid_until TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            );
+            CREATE INDEX idx_quotes_request ON quotes(request_id);
+        "#)
+    },
+    // 12: 仓位强平价 (按市场、方向索引，用于查询临近强平的仓位)，回填已有仓位
+    |tx| {
+        add_column_if_missing(tx, "positions", "liquidation_price", "REAL")?;
+        tx.execute_batch("CREATE INDEX IF NOT EXISTS idx_positions_liquidation ON positions(market, side, liquidation_price)")?;
+        backfill_liquidation_prices(tx)
+    },
+    // 13: 报价保护 (tolerance_bps 为空表示未启用)
+    |tx| {
+        add_column_if_missing(tx, "quotes", "min_valid_ms", "INTEGER")?;
+        add_column_if_missing(tx, "quotes", "tolerance_bps", "INTEGER")?;
+        add_column_if_missing(tx, "quotes", "reference_price", "REAL")?;
+        add_column_if_missing(tx, "quotes", "reference_funding_rate", "REAL")
+    },
+    // 14: 仓位上次资金费结算时间 (用于开仓当期按比例收取)
+    |tx| add_column_if_missing(tx, "positions", "last_funding_at", "TEXT"),
+    // 15: 保险基金流水和社会化亏损的金额列改为 TEXT
+    |tx| {
+        rebuild_table(tx, "insurance_fund_events", r#"
+            CREATE TABLE insurance_fund_events (
+                id TEXT PRIMARY KEY,
+                position_id TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                amount TEXT NOT NULL,
+                balance_after TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            );
+        "#)?;
+        rebuild_table(tx, "socialized_losses", r#"
+            CREATE TABLE socialized_losses (
+                id TEXT PRIMARY KEY,
+                position_id TEXT NOT NULL,
+                winner_position_id TEXT NOT NULL,
+                agent_id TEXT NOT NULL,
+                amount TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            );
+        "#)
+    },
+    // 16: 结算任务改用独立 id 作主键，同一仓位可有多笔同类任务 (如多次部分平仓)；
+    // 已有任务按入队顺序保留并分配随机 id
+    |tx| tx.execute_batch(r#"
+        ALTER TABLE settlement_queue RENAME TO settlement_queue_old;
+        DROP INDEX IF EXISTS idx_settlement_queue_due;
+        CREATE TABLE settlement_queue (
+            id TEXT PRIMARY KEY,
+            position_id TEXT NOT NULL,
+            action TEXT NOT NULL,
+            owner TEXT NOT NULL,
+            market TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            price REAL NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at INTEGER NOT NULL,
+            last_error TEXT,
+            created_at TEXT NOT NULL
+        );
+        INSERT INTO settlement_queue
+            SELECT lower(hex(randomblob(16))), position_id, action, owner, market, size, price, status,
+                   attempts, next_attempt_at, last_error, created_at
+            FROM settlement_queue_old ORDER BY rowid;
+        DROP TABLE settlement_queue_old;
+        CREATE INDEX idx_settlement_queue_due ON settlement_queue(status, next_attempt_at);
+        CREATE INDEX idx_settlement_queue_position ON settlement_queue(position_id, action);
+    "#),
+    // 17: 强平价改为 TEXT 十进制 (索引按数值比较)，并按修正后的公式 (计入杠杆) 重新计算
+    |tx| {
+        rebuild_table(tx, "positions", r#"
+            CREATE TABLE positions (
+                id TEXT PRIMARY KEY,
+                request_id TEXT NOT NULL,
+                quote_id TEXT NOT NULL,
+                trader_agent TEXT NOT NULL,
+                mm_agent TEXT NOT NULL,
+                market TEXT NOT NULL,
+                side TEXT NOT NULL,
+                size_usdc TEXT NOT NULL,
+                leverage INTEGER NOT NULL,
+                entry_price TEXT NOT NULL,
+                funding_rate REAL NOT NULL,
+                trader_collateral TEXT NOT NULL,
+                mm_collateral TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'active',
+                created_at TEXT NOT NULL,
+                closed_at TEXT,
+                pnl_trader TEXT,
+                pnl_mm TEXT,
+                stop_loss REAL,
+                take_profit REAL,
+                margin_mode TEXT NOT NULL DEFAULT 'Isolated',
+                liquidation_price TEXT,
+                last_funding_at TEXT
+            );
+            CREATE INDEX idx_positions_trader ON positions(trader_agent);
+            CREATE INDEX idx_positions_mm ON positions(mm_agent);
+            CREATE INDEX idx_positions_status ON positions(status);
+            CREATE INDEX idx_positions_liquidation ON positions(market, side, CAST(liquidation_price AS REAL));
+        "#)?;
+        tx.execute_batch("UPDATE positions SET liquidation_price = NULL")?;
+        backfill_liquidation_prices(tx)
+    },
+];
+
+/// 为强平价为空的仓位计算并写入强平价
+fn backfill_liquidation_prices(tx: &Transaction) -> rusqlite::Result<()> {
+    let positions = tx.prepare("SELECT * FROM positions WHERE liquidation_price IS NULL")?
+        .query_map([], row_to_position)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for pos in positions {
+        tx.execute(
+            "UPDATE positions SET liquidation_price = ?1 WHERE id = ?2",
+            params![pos.liquidation_price, pos.id.to_string()],
+        )?;
+    }
+    Ok(())
+}
+
+/// 用 `create_sql` (含索引) 重建 `table`，列顺序须与原表一致。
+/// 写入 TEXT 列的 REAL 旧值由 SQLite 转为文本
+fn rebuild_table(tx: &Transaction, table: &str, create_sql: &str) -> rusqlite::Result<()> {
+    tx.execute_batch(&format!("ALTER TABLE {table} RENAME TO {table}_old"))?;
+    // 旧索引随旧表改名，需先删除旧表再建同名索引
+    let (create_table, create_indexes) = create_sql.split_once(';').unwrap_or((create_sql, ""));
+    tx.execute_batch(create_table)?;
+    tx.execute_batch(&format!("INSERT INTO {table} SELECT * FROM {table}_old; DROP TABLE {table}_old;"))?;
+    tx.execute_batch(create_indexes)
+}
+
+/// `ALTER TABLE ... ADD COLUMN` 本身不是幂等的，先检查列是否已存在
+fn add_column_if_missing(tx: &Transaction, table: &str, column: &str, decl: &str) -> rusqlite::Result<()> {
+    let exists = tx
+        .prepare(&format!("PRAGMA table_info({})", table))?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+    
+    if !exists {
+        tx.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl))?;
+    }
+    Ok(())
+}
+
+pub struct Database {
+    conn: Mutex<Connection>,
+}
+
+impl Database {
+    pub fn new(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        let db = Self { conn: Mutex::new(conn) };
+        db.run_migrations()?;
+        Ok(db)
+    }
+    
+    /// 健康检查: 执行一次 `SELECT 1`
+    pub fn ping(&self) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))?;
+        Ok(())
+    }
+    
+    #[allow(dead_code)]
+    pub fn in_memory() -> rusqlite::Result<Self> {
+        Self::new(":memory:")
+    }
+    
+    /// 依次执行未应用的迁移，每个迁移在独立事务中完成并更新版本号。
+    /// 任一迁移失败则返回错误，不会留下半迁移的数据库。
+    fn run_migrations(&self) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        
+        conn.execute_batch(r#"
+            CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER NOT NULL
+            );
+            INSERT INTO schema_version (version)
+                SELECT 0 WHERE NOT EXISTS (SELECT 1 FROM schema_version);
+        "#)?;
+        
+        let current: usize = conn.query_row("SELECT version FROM schema_version", [], |row| row.get(0))?;
+        
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+            let tx = conn.transaction()?;
+            migration(&tx)?;
+            tx.execute("UPDATE schema_version SET version = ?1", params![i + 1])?;
+            tx.commit()?;
+            tracing::info!("Applied database migration {}", i + 1);
+        }
         
         Ok(())
     }
     
+    /// 当前数据库 schema 版本
+    #[allow(dead_code)]
+    pub fn schema_version(&self) -> rusqlite::Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT version FROM schema_version", [], |row| row.get(0))
+    }
+    
     // ========== Agent Operations ==========
     
     pub fn save_agent(&self, agent: &AgentInfo) -> rusqlite::Result<()> {
@@ -166,8 +543,9 @@ impl Database {
             r#"INSERT OR REPLACE INTO positions 
                (id, request_id, quote_id, trader_agent, mm_agent, market, side, 
                 size_usdc, leverage, entry_price, funding_rate, trader_collateral, 
-                mm_collateral, status, created_at, closed_at)
-               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)"#,
+                mm_collateral, status, created_at, closed_at, stop_loss, take_profit, margin_mode, liquidation_price,
+                last_funding_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21)"#,
             params![
                 pos.id.to_string(),
                 pos.request_id.to_string(),
@@ -185,22 +563,48 @@ impl Database {
                 format!("{:?}", pos.status),
                 pos.created_at.to_rfc3339(),
                 pos.closed_at.map(|dt| dt.to_rfc3339()),
+                pos.stop_loss,
+                pos.take_profit,
+                format!("{:?}", pos.margin_mode),
+                pos.liquidation_price,
+                pos.last_funding_at.map(|dt| dt.to_rfc3339()),
             ],
         )?;
         Ok(())
     }
-    
+
+    /// 某市场中强平价临近的活跃仓位: 多头强平价不低于 `long_floor`，空头强平价不高于 `short_ceiling`
+    pub fn get_positions_near_liquidation(
+        &self,
+        market: Market,
+        long_floor: f64,
+        short_ceiling: f64,
+    ) -> rusqlite::Result<Vec<Position>> {
+        let conn = self.conn.lock().unwrap();
+        // 强平价为 TEXT，与索引相同的 CAST 表达式按数值比较
+        let mut stmt = conn.prepare(
+            r#"SELECT * FROM positions
+               WHERE market = ?1 AND status = 'Active'
+                 AND ((side = 'Long' AND CAST(liquidation_price AS REAL) >= ?2)
+                   OR (side = 'Short' AND CAST(liquidation_price AS REAL) <= ?3))"#
+        )?;
+        let positions = stmt.query_map(params![format!("{:?}", market), long_floor, short_ceiling], row_to_position)?
+            .collect();
+        positions
+    }
+
+    #[allow(dead_code)]
     pub fn get_positions_by_agent(&self, agent_id: &str) -> rusqlite::Result<Vec<Position>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT * FROM positions WHERE (trader_agent = ?1 OR mm_agent = ?1) AND status = 'Active'"
+            "SELECT * FROM positions WHERE (trader_agent = ?1 OR mm_agent = ?1) AND LOWER(status) = 'active'"
         )?;
         
         let mut positions = Vec::new();
         let mut rows = stmt.query(params![agent_id])?;
         
         while let Some(row) = rows.next()? {
-            if let Ok(pos) = self.row_to_position(row) {
+            if let Ok(pos) = row_to_position(row) {
                 positions.push(pos);
             }
         }
@@ -208,6 +612,29 @@ impl Database {
         Ok(positions)
     }
     
+    /// 查询所有活跃仓位 (启动时恢复内存状态)
+    pub fn get_all_active_positions(&self) -> rusqlite::Result<Vec<Position>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT * FROM positions WHERE LOWER(status) = 'active' ORDER BY created_at"
+        )?;
+        
+        let mut positions = Vec::new();
+        let mut rows = stmt.query([])?;
+        
+        while let Some(row) = rows.next()? {
+            positions.push(row_to_position(row)?);
+        }
+        
+        Ok(positions)
+    }
+    
+    /// 仓位总数 (含已平仓)，为 0 表示数据库是全新的
+    pub fn position_count(&self) -> rusqlite::Result<u64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT COUNT(*) FROM positions", [], |row| row.get(0))
+    }
+    
     /// 查询历史仓位 (已平仓)，支持分页
     pub fn get_closed_positions_by_agent(
         &self, 
@@ -236,10 +663,10 @@ impl Database {
         let mut rows = stmt.query(params![agent_id, limit, offset])?;
         
         while let Some(row) = rows.next()? {
-            if let Ok(pos) = self.row_to_position(row) {
+            if let Ok(pos) = row_to_position(row) {
                 // 读取 PnL 字段
-                let pnl_trader: Option<f64> = row.get(16).ok();
-                let pnl_mm: Option<f64> = row.get(17).ok();
+                let pnl_trader: Option<Usd> = row.get(16).ok().flatten();
+                let pnl_mm: Option<Usd> = row.get(17).ok().flatten();
                 
                 positions.push(PositionWithPnl {
                     position: pos,
@@ -252,7 +679,7 @@ impl Database {
         Ok((positions, total))
     }
     
-    pub fn close_position(&self, position_id: &Uuid, pnl_trader: f64, pnl_mm: f64) -> rusqlite::Result<()> {
+    pub fn close_position(&self, position_id: &Uuid, pnl_trader: Usd, pnl_mm: Usd) -> rusqlite::Result<()> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
             "UPDATE positions SET status = 'Closed', closed_at = ?1, pnl_trader = ?2, pnl_mm = ?3 WHERE id = ?4",
@@ -266,41 +693,482 @@ impl Database {
         Ok(())
     }
     
-    /// 获取 Agent 交易统计 (从 positions 表聚合)
-    pub fn get_agent_stats(&self, agent_id: &str) -> rusqlite::Result<AgentStats> {
+    // ========== RFQ Operations ==========
+    
+    pub fn save_trade_request(&self, req: &TradeRequest) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"INSERT OR REPLACE INTO trade_requests 
+               (id, agent_id, market, side, size_usdc, leverage, max_funding_rate, expires_at, created_at,
+                stop_loss, take_profit, margin_mode, reduce_only)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)"#,
+            params![
+                req.id.to_string(),
+                req.agent_id,
+                format!("{:?}", req.market),
+                format!("{:?}", req.side),
+                req.size_usdc,
+                req.leverage,
+                req.max_funding_rate,
+                req.expires_at.to_rfc3339(),
+                req.created_at.to_rfc3339(),
+                req.stop_loss,
+                req.take_profit,
+                format!("{:?}", req.margin_mode),
+                req.reduce_only,
+            ],
+        )?;
+        Ok(())
+    }
+    
+    /// 删除请求及其所有报价
+    pub fn delete_trade_request(&self, request_id: &Uuid) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM quotes WHERE request_id = ?1", params![request_id.to_string()])?;
+        conn.execute("DELETE FROM trade_requests WHERE id = ?1", params![request_id.to_string()])?;
+        Ok(())
+    }
+    
+    /// 加载所有未过期的请求
+    pub fn load_active_trade_requests(&self, now: DateTime<Utc>) -> rusqlite::Result<Vec<TradeRequest>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            r#"SELECT id, agent_id, market, side, size_usdc, leverage, max_funding_rate, expires_at, created_at,
+                      stop_loss, take_profit, margin_mode, reduce_only
+               FROM trade_requests"#
+        )?;
+        
+        let mut requests = Vec::new();
+        let mut rows = stmt.query([])?;
+        
+        while let Some(row) = rows.next()? {
+            let request = TradeRequest {
+                id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap_or_default(),
+                agent_id: row.get(1)?,
+                market: parse_market(&row.get::<_, String>(2)?),
+                side: parse_side(&row.get::<_, String>(3)?),
+                size_usdc: row.get(4)?,
+                leverage: row.get(5)?,
+                max_funding_rate: row.get(6)?,
+                expires_at: parse_timestamp(&row.get::<_, String>(7)?),
+                created_at: parse_timestamp(&row.get::<_, String>(8)?),
+                stop_loss: row.get(9)?,
+                take_profit: row.get(10)?,
+                margin_mode: parse_margin_mode(&row.get::<_, String>(11)?),
+                reduce_only: row.get(12)?,
+            };
+            if request.expires_at > now {
+                requests.push(request);
+            }
+        }
+        
+        Ok(requests)
+    }
+    
+    pub fn save_quote(&self, quote: &Quote) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"INSERT OR REPLACE INTO quotes 
+               (id, request_id, agent_id, funding_rate, collateral_usdc, valid_until, created_at,
+                min_valid_ms, tolerance_bps, reference_price, reference_funding_rate)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)"#,
+            params![
+                quote.id.to_string(),
+                quote.request_id.to_string(),
+                quote.agent_id,
+                quote.funding_rate,
+                quote.collateral_usdc,
+                quote.valid_until.to_rfc3339(),
+                quote.created_at.to_rfc3339(),
+                quote.protection.as_ref().map(|p| p.min_valid_ms as i64),
+                quote.protection.as_ref().map(|p| p.tolerance_bps),
+                quote.protection.as_ref().map(|p| p.reference_price),
+                quote.protection.as_ref().map(|p| p.reference_funding_rate),
+            ],
+        )?;
+        Ok(())
+    }
+    
+    pub fn delete_quote(&self, quote_id: &Uuid) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM quotes WHERE id = ?1", params![quote_id.to_string()])?;
+        Ok(())
+    }
+    
+    /// 加载某个请求的所有报价，按报价时间排序
+    pub fn load_quotes(&self, request_id: &Uuid) -> rusqlite::Result<Vec<Quote>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            r#"SELECT id, request_id, agent_id, funding_rate, collateral_usdc, valid_until, created_at,
+                      min_valid_ms, tolerance_bps, reference_price, reference_funding_rate
+               FROM quotes WHERE request_id = ?1
+               ORDER BY created_at"#
+        )?;
+        
+        let mut quotes = Vec::new();
+        let mut rows = stmt.query(params![request_id.to_string()])?;
+        
+        while let Some(row) = rows.next()? {
+            quotes.push(Quote {
+                id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap_or_default(),
+                request_id: Uuid::parse_str(&row.get::<_, String>(1)?).unwrap_or_default(),
+                agent_id: row.get(2)?,
+                funding_rate: row.get(3)?,
+                collateral_usdc: row.get(4)?,
+                valid_until: parse_timestamp(&row.get::<_, String>(5)?),
+                created_at: parse_timestamp(&row.get::<_, String>(6)?),
+                protection: match row.get::<_, Option<u32>>(8)? {
+                    Some(tolerance_bps) => Some(QuoteProtection {
+                        min_valid_ms: row.get::<_, Option<i64>>(7)?.unwrap_or(0) as u64,
+                        tolerance_bps,
+                        reference_price: row.get::<_, Option<f64>>(9)?.unwrap_or(0.0),
+                        reference_funding_rate: row.get::<_, Option<f64>>(10)?.unwrap_or(0.0),
+                    }),
+                    None => None,
+                },
+            });
+        }
+        
+        Ok(quotes)
+    }
+    
+    // ========== Insurance Fund Operations ==========
+    
+    /// 记录保险基金变动 (kind: "fee" 收入 / "draw" 支出)
+    pub fn record_insurance_event(
+        &self,
+        position_id: &Uuid,
+        kind: &str,
+        amount: Usd,
+        balance_after: Usd,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"INSERT INTO insurance_fund_events (id, position_id, kind, amount, balance_after, created_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6)"#,
+            params![
+                Uuid::new_v4().to_string(),
+                position_id.to_string(),
+                kind,
+                amount,
+                balance_after,
+                Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+    
+    /// 最近一次记录的保险基金余额
+    pub fn get_insurance_fund_balance(&self) -> rusqlite::Result<Usd> {
+        let conn = self.conn.lock().unwrap();
+        let balance: Option<Usd> = conn.query_row(
+            "SELECT balance_after FROM insurance_fund_events ORDER BY rowid DESC LIMIT 1",
+            [],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(balance.unwrap_or(Usd::ZERO))
+    }
+    
+    /// 最近的保险基金流水，可按 kind 过滤，新的在前
+    pub fn get_insurance_events(&self, kind: Option<&str>, limit: u32) -> rusqlite::Result<Vec<InsuranceFundEvent>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            r#"SELECT position_id, kind, amount, balance_after, created_at FROM insurance_fund_events
+               WHERE ?1 IS NULL OR kind = ?1
+               ORDER BY rowid DESC LIMIT ?2"#,
+        )?;
+        let events = stmt.query_map(params![kind, limit], |row| {
+            Ok(InsuranceFundEvent {
+                position_id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap_or_default(),
+                kind: row.get(1)?,
+                amount: row.get(2)?,
+                balance_after: row.get(3)?,
+                created_at: parse_timestamp(&row.get::<_, String>(4)?),
+            })
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(events)
+    }
+    
+    /// 记录一笔社会化亏损分摊
+    pub fn record_socialized_loss(&self, loss: &SocializedLoss) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"INSERT INTO socialized_losses (id, position_id, winner_position_id, agent_id, amount, created_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6)"#,
+            params![
+                Uuid::new_v4().to_string(),
+                loss.position_id.to_string(),
+                loss.winner_position_id.to_string(),
+                loss.agent_id,
+                loss.amount,
+                loss.created_at.to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+    
+    /// 最近的社会化亏损分摊，新的在前
+    pub fn get_socialized_losses(&self, limit: u32) -> rusqlite::Result<Vec<SocializedLoss>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            r#"SELECT position_id, winner_position_id, agent_id, amount, created_at FROM socialized_losses
+               ORDER BY rowid DESC LIMIT ?1"#,
+        )?;
+        let losses = stmt.query_map(params![limit], |row| {
+            Ok(SocializedLoss {
+                position_id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap_or_default(),
+                winner_position_id: Uuid::parse_str(&row.get::<_, String>(1)?).unwrap_or_default(),
+                agent_id: row.get(2)?,
+                amount: row.get(3)?,
+                created_at: parse_timestamp(&row.get::<_, String>(4)?),
+            })
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(losses)
+    }
+    
+    // ========== Settlement Queue ==========
+    
+    /// 加入结算任务。任务以 id 去重，同一 id 重复加入不做任何改动；
+    /// 同一仓位的多笔任务 (如多次部分平仓) 各自排队。返回是否写入
+    pub fn enqueue_settlement(&self, job: &SettlementJob) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            r#"INSERT OR IGNORE INTO settlement_queue
+               (id, position_id, action, owner, market, size, price, status, attempts, next_attempt_at, last_error, created_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)"#,
+            params![
+                job.id.to_string(),
+                job.position_id.to_string(),
+                job.action.as_str(),
+                job.owner,
+                format!("{:?}", job.market),
+                job.size,
+                job.price,
+                job.status.as_str(),
+                job.attempts,
+                job.next_attempt_at.timestamp_millis(),
+                job.last_error,
+                job.created_at.to_rfc3339(),
+            ],
+        )?;
+        Ok(changed > 0)
+    }
+    
+    /// 到期待重试的结算任务，最早到期的在前
+    pub fn get_due_settlements(&self, now: DateTime<Utc>, limit: u32) -> rusqlite::Result<Vec<SettlementJob>> {
+        self.query_settlements(
+            "WHERE status = 'pending' AND next_attempt_at <= ?1 ORDER BY next_attempt_at LIMIT ?2",
+            params![now.timestamp_millis(), limit],
+        )
+    }
+    
+    /// 尚未上链的结算任务 (pending + failed)，先入队的在前
+    pub fn get_unsettled(&self) -> rusqlite::Result<Vec<SettlementJob>> {
+        self.query_settlements("WHERE status != 'done' ORDER BY rowid", params![])
+    }
+    
+    /// 仓位最近入队的一笔 `action` 结算任务
+    #[cfg(test)]
+    pub fn get_settlement(&self, position_id: &Uuid, action: SettlementAction) -> rusqlite::Result<Option<SettlementJob>> {
+        Ok(self.query_settlements(
+            "WHERE position_id = ?1 AND action = ?2 ORDER BY rowid",
+            params![position_id.to_string(), action.as_str()],
+        )?.pop())
+    }
+    
+    /// 记录一次结算尝试的结果
+    pub fn update_settlement(&self, job: &SettlementJob) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"UPDATE settlement_queue SET status = ?2, attempts = ?3, next_attempt_at = ?4, last_error = ?5
+               WHERE id = ?1"#,
+            params![
+                job.id.to_string(),
+                job.status.as_str(),
+                job.attempts,
+                job.next_attempt_at.timestamp_millis(),
+                job.last_error,
+            ],
+        )?;
+        Ok(())
+    }
+    
+    fn query_settlements(&self, clause: &str, args: &[&dyn rusqlite::ToSql]) -> rusqlite::Result<Vec<SettlementJob>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            r#"SELECT id, position_id, action, owner, market, size, price, status, attempts, next_attempt_at, last_error, created_at
+               FROM settlement_queue {}"#,
+            clause,
+        ))?;
+        let jobs = stmt.query_map(args, |row| {
+            Ok(SettlementJob {
+                id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap_or_default(),
+                position_id: Uuid::parse_str(&row.get::<_, String>(1)?).unwrap_or_default(),
+                action: match row.get::<_, String>(2)?.as_str() {
+                    "close" => SettlementAction::Close,
+                    "reduce" => SettlementAction::Reduce,
+                    _ => SettlementAction::Open,
+                },
+                owner: row.get(3)?,
+                market: parse_market(&row.get::<_, String>(4)?),
+                size: row.get(5)?,
+                price: row.get(6)?,
+                status: match row.get::<_, String>(7)?.as_str() {
+                    "failed" => SettlementJobStatus::Failed,
+                    "done" => SettlementJobStatus::Done,
+                    _ => SettlementJobStatus::Pending,
+                },
+                attempts: row.get(8)?,
+                next_attempt_at: DateTime::from_timestamp_millis(row.get(9)?).unwrap_or_else(Utc::now),
+                last_error: row.get(10)?,
+                created_at: parse_timestamp(&row.get::<_, String>(11)?),
+            })
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(jobs)
+    }
+    
+    // ========== Trade Operations ==========
+    
+    pub fn save_trade(&self, trade: &TradeRecord) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"INSERT INTO trades 
+               (id, position_id, trader_agent, mm_agent, market, side, size_usdc,
+                entry_price, exit_price, pnl_trader, pnl_mm, created_at, closed_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)"#,
+            params![
+                trade.id.to_string(),
+                trade.position_id.to_string(),
+                trade.trader_agent,
+                trade.mm_agent,
+                format!("{:?}", trade.market),
+                format!("{:?}", trade.side),
+                trade.size_usdc,
+                trade.entry_price,
+                trade.exit_price,
+                trade.pnl_trader,
+                trade.pnl_mm,
+                trade.created_at.to_rfc3339(),
+                trade.closed_at.to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+    
+    /// Agent 自 `since` 以来的已实现盈亏 (trader 和 MM 两侧，含部分平仓)
+    pub fn get_realized_pnl_since(&self, agent_id: &str, since: DateTime<Utc>) -> rusqlite::Result<Usd> {
+        let conn = self.conn.lock().unwrap();
+        sum_usd(
+            &conn,
+            r#"SELECT CASE WHEN trader_agent = ?1 THEN pnl_trader ELSE pnl_mm END
+               FROM trades
+               WHERE (trader_agent = ?1 OR mm_agent = ?1) AND closed_at >= ?2"#,
+            params![agent_id, since.to_rfc3339()],
+        )
+    }
+    
+    /// 查询 Agent 的成交历史 (作为 trader 或 MM)，按平仓时间倒序分页
+    pub fn get_trades_by_agent(
+        &self,
+        agent_id: &str,
+        limit: u32,
+        offset: u32,
+    ) -> rusqlite::Result<(Vec<TradeRecord>, u32)> {
         let conn = self.conn.lock().unwrap();
         
-        // 查询该 agent 作为 trader 的已平仓仓位统计
-        let (total_trades, wins, losses, total_pnl, total_volume): (u32, u32, u32, f64, f64) = conn.query_row(
-            r#"SELECT 
-                COUNT(*) as total_trades,
-                SUM(CASE WHEN pnl_trader > 0 THEN 1 ELSE 0 END) as wins,
-                SUM(CASE WHEN pnl_trader <= 0 THEN 1 ELSE 0 END) as losses,
-                COALESCE(SUM(pnl_trader), 0) as total_pnl,
-                COALESCE(SUM(size_usdc), 0) as total_volume
-            FROM positions 
-            WHERE trader_agent = ?1 AND status = 'Closed'"#,
+        let total: u32 = conn.query_row(
+            "SELECT COUNT(*) FROM trades WHERE trader_agent = ?1 OR mm_agent = ?1",
             params![agent_id],
-            |row| Ok((
-                row.get::<_, u32>(0)?,
-                row.get::<_, u32>(1)?,
-                row.get::<_, u32>(2)?,
-                row.get::<_, f64>(3)?,
-                row.get::<_, f64>(4)?,
-            )),
+            |row| row.get(0),
         )?;
         
+        let mut stmt = conn.prepare(
+            r#"SELECT id, position_id, trader_agent, mm_agent, market, side, size_usdc,
+                      entry_price, exit_price, pnl_trader, pnl_mm, created_at, closed_at
+               FROM trades
+               WHERE trader_agent = ?1 OR mm_agent = ?1
+               ORDER BY closed_at DESC, id
+               LIMIT ?2 OFFSET ?3"#
+        )?;
+        
+        let mut trades = Vec::new();
+        let mut rows = stmt.query(params![agent_id, limit, offset])?;
+        
+        while let Some(row) = rows.next()? {
+            trades.push(TradeRecord {
+                id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap_or_default(),
+                position_id: Uuid::parse_str(&row.get::<_, String>(1)?).unwrap_or_default(),
+                trader_agent: row.get(2)?,
+                mm_agent: row.get(3)?,
+                market: parse_market(&row.get::<_, String>(4)?),
+                side: parse_side(&row.get::<_, String>(5)?),
+                size_usdc: row.get(6)?,
+                entry_price: row.get(7)?,
+                exit_price: row.get::<_, Option<Usd>>(8)?.unwrap_or_default(),
+                pnl_trader: row.get::<_, Option<Usd>>(9)?.unwrap_or_default(),
+                pnl_mm: row.get::<_, Option<Usd>>(10)?.unwrap_or_default(),
+                created_at: parse_timestamp(&row.get::<_, String>(11)?),
+                closed_at: row.get::<_, Option<String>>(12)?
+                    .map(|s| parse_timestamp(&s))
+                    .unwrap_or_else(Utc::now),
+            });
+        }
+        
+        Ok((trades, total))
+    }
+    
+    /// 某笔成交对应区间内 (上一次平仓之后到本次平仓) 的资金费合计，正数表示 trader 支付
+    pub fn funding_paid_for_trade(&self, position_id: &Uuid, closed_at: &DateTime<Utc>) -> rusqlite::Result<Usd> {
+        let conn = self.conn.lock().unwrap();
+        sum_usd(
+            &conn,
+            r#"SELECT payment_amount FROM funding_payments
+               WHERE position_id = ?1
+                 AND julianday(settled_at) <= julianday(?2)
+                 AND julianday(settled_at) > COALESCE(
+                     (SELECT MAX(julianday(closed_at)) FROM trades
+                      WHERE position_id = ?1 AND julianday(closed_at) < julianday(?2)),
+                     0)"#,
+            params![position_id.to_string(), closed_at.to_rfc3339()],
+        )
+    }
+    
+    /// 获取 Agent 交易统计 (从 positions 表聚合)
+    pub fn get_agent_stats(&self, agent_id: &str) -> rusqlite::Result<AgentStats> {
+        let conn = self.conn.lock().unwrap();
+        
+        // 已平仓仓位统计，trader 和 MM 两侧的结果都计入 (金额为 TEXT，在 Rust 中按十进制累加)
+        let closed = conn.prepare(
+            r#"SELECT CASE WHEN trader_agent = ?1 THEN pnl_trader ELSE pnl_mm END, size_usdc
+               FROM positions
+               WHERE (trader_agent = ?1 OR mm_agent = ?1) AND status = 'Closed'"#,
+        )?
+            .query_map(params![agent_id], |row| Ok((row.get::<_, Option<Usd>>(0)?.unwrap_or(Usd::ZERO), row.get::<_, Usd>(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let total_trades = closed.len() as u32;
+        let wins = closed.iter().filter(|(pnl, _)| pnl.is_positive()).count() as u32;
+        let losses = total_trades - wins;
+        let gross_pnl: Usd = closed.iter().map(|(pnl, _)| *pnl).sum();
+        let total_volume: Usd = closed.iter().map(|(_, size)| *size).sum();
+        
+        // 资金费净额: 作为 MM 收到的减去作为 trader 支付的 (payment_amount 正数表示 trader 支付)
+        let net_funding: Usd = conn.prepare(
+            r#"SELECT CASE WHEN mm_agent = ?1 THEN payment_amount ELSE '0' END,
+                      CASE WHEN trader_agent = ?1 THEN payment_amount ELSE '0' END
+               FROM funding_payments
+               WHERE trader_agent = ?1 OR mm_agent = ?1"#,
+        )?
+            .query_map(params![agent_id], |row| Ok(row.get::<_, Usd>(0)? - row.get::<_, Usd>(1)?))?
+            .sum::<rusqlite::Result<Usd>>()?;
+        let net_pnl = gross_pnl + net_funding;
+        
         let win_rate = if total_trades > 0 {
             wins as f64 / total_trades as f64
         } else {
             0.0
         };
         
-        let avg_pnl = if total_trades > 0 {
-            total_pnl / total_trades as f64
-        } else {
-            0.0
-        };
+        let avg_pnl = net_pnl.checked_div(total_trades).unwrap_or(Usd::ZERO);
         
         Ok(AgentStats {
             agent_id: agent_id.to_string(),
@@ -308,8 +1176,11 @@ impl Database {
             wins,
             losses,
             win_rate,
-            total_pnl,
+            total_pnl: net_pnl,
             avg_pnl,
+            gross_pnl,
+            net_funding,
+            net_pnl,
             total_volume,
         })
     }
@@ -336,18 +1207,84 @@ impl Database {
         Ok(())
     }
     
-    pub fn get_funding_payments(&self, agent_id: &str, limit: u32) -> rusqlite::Result<Vec<FundingPayment>> {
+    /// 记录一次结算周期的市场 8 小时资金费率
+    pub fn save_funding_rate(&self, market: Market, rate: f64, at: DateTime<Utc>) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO funding_rates (market, rate, recorded_at) VALUES (?1, ?2, ?3)",
+            params![market.symbol(), rate, at.timestamp()],
+        )?;
+        Ok(())
+    }
+    
+    /// `[from, to)` 内的资金费率按 `bucket_secs` 分桶 (桶起点对齐到 Unix 纪元的整数倍)，
+    /// 只返回有数据的桶，按时间升序
+    pub fn funding_rate_buckets(
+        &self,
+        market: Market,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        bucket_secs: i64,
+    ) -> rusqlite::Result<Vec<FundingRateBucket>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            r#"SELECT id, position_id, trader_agent, mm_agent, funding_rate, position_size, payment_amount, settled_at
-               FROM funding_payments 
-               WHERE trader_agent = ?1 OR mm_agent = ?1
-               ORDER BY settled_at DESC
-               LIMIT ?2"#
+            r#"SELECT (recorded_at / ?4) * ?4 AS bucket, AVG(rate), MIN(rate), MAX(rate), COUNT(*)
+               FROM funding_rates
+               WHERE market = ?1 AND recorded_at >= ?2 AND recorded_at < ?3
+               GROUP BY bucket
+               ORDER BY bucket"#
+        )?;
+        let rows = stmt.query_map(
+            params![market.symbol(), from.timestamp(), to.timestamp(), bucket_secs],
+            |row| {
+                Ok(FundingRateBucket {
+                    start: DateTime::from_timestamp(row.get(0)?, 0).unwrap_or_default(),
+                    avg_rate: row.get(1)?,
+                    min_rate: row.get(2)?,
+                    max_rate: row.get(3)?,
+                    samples: row.get(4)?,
+                })
+            },
+        )?;
+        rows.collect()
+    }
+    
+    /// 查询 Agent 参与的资金费支付 (作为 trader 或 MM)，按结算时间倒序分页，返回 (当前页, 总数)。
+    /// settled_at 统一以 UTC RFC 3339 存储，可以直接按字符串比较时间范围
+    pub fn get_funding_payments(
+        &self,
+        agent_id: &str,
+        params: &FundingPaymentParams,
+    ) -> rusqlite::Result<(Vec<FundingPayment>, u32)> {
+        let conn = self.conn.lock().unwrap();
+        // 市场不在支付记录里，需要关联仓位表
+        const FILTER: &str = r#"FROM funding_payments f
+               LEFT JOIN positions p ON p.id = f.position_id
+               WHERE (f.trader_agent = ?1 OR f.mm_agent = ?1)
+                 AND (?2 IS NULL OR p.market = ?2)
+                 AND (?3 IS NULL OR f.settled_at >= ?3)
+                 AND (?4 IS NULL OR f.settled_at < ?4)"#;
+        let market = params.market.map(|m| format!("{:?}", m));
+        let from = params.from.map(|t| t.to_rfc3339());
+        let to = params.to.map(|t| t.to_rfc3339());
+        
+        let total: u32 = conn.query_row(
+            &format!("SELECT COUNT(*) {}", FILTER),
+            params![agent_id, market, from, to],
+            |row| row.get(0),
         )?;
         
+        let mut stmt = conn.prepare(&format!(
+            r#"SELECT f.id, f.position_id, f.trader_agent, f.mm_agent, f.funding_rate, f.position_size,
+                      f.payment_amount, f.settled_at
+               {}
+               ORDER BY f.settled_at DESC
+               LIMIT ?5 OFFSET ?6"#,
+            FILTER
+        ))?;
+        
         let mut payments = Vec::new();
-        let mut rows = stmt.query(params![agent_id, limit])?;
+        let mut rows = stmt.query(params![agent_id, market, from, to, params.limit, params.offset])?;
         
         while let Some(row) = rows.next()? {
             payments.push(FundingPayment {
@@ -364,24 +1301,25 @@ impl Database {
             });
         }
         
-        Ok(payments)
+        Ok((payments, total))
     }
     
+    #[allow(dead_code)]
     pub fn get_funding_summary(&self, agent_id: &str) -> rusqlite::Result<FundingSummary> {
         let conn = self.conn.lock().unwrap();
         
         // Total paid as trader
-        let total_paid: f64 = conn.query_row(
-            "SELECT COALESCE(SUM(payment_amount), 0) FROM funding_payments WHERE trader_agent = ?1",
+        let total_paid = sum_usd(
+            &conn,
+            "SELECT payment_amount FROM funding_payments WHERE trader_agent = ?1",
             params![agent_id],
-            |row| row.get(0),
         )?;
         
         // Total received as MM
-        let total_received: f64 = conn.query_row(
-            "SELECT COALESCE(SUM(payment_amount), 0) FROM funding_payments WHERE mm_agent = ?1",
+        let total_received = sum_usd(
+            &conn,
+            "SELECT payment_amount FROM funding_payments WHERE mm_agent = ?1",
             params![agent_id],
-            |row| row.get(0),
         )?;
         
         // Payment count
@@ -399,30 +1337,65 @@ impl Database {
             payment_count,
         })
     }
-    
-    fn row_to_position(&self, row: &rusqlite::Row) -> rusqlite::Result<Position> {
-        Ok(Position {
-            id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap_or_default(),
-            request_id: Uuid::parse_str(&row.get::<_, String>(1)?).unwrap_or_default(),
-            quote_id: Uuid::parse_str(&row.get::<_, String>(2)?).unwrap_or_default(),
-            trader_agent: row.get(3)?,
-            mm_agent: row.get(4)?,
-            market: parse_market(&row.get::<_, String>(5)?),
-            side: parse_side(&row.get::<_, String>(6)?),
-            size_usdc: row.get(7)?,
-            leverage: row.get(8)?,
-            entry_price: row.get(9)?,
-            funding_rate: row.get(10)?,
-            trader_collateral: row.get(11)?,
-            mm_collateral: row.get(12)?,
-            status: parse_status(&row.get::<_, String>(13)?),
-            created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(14)?)
-                .map(|dt| dt.with_timezone(&Utc))
-                .unwrap_or_else(|_| Utc::now()),
-            closed_at: row.get::<_, Option<String>>(15)?
+}
+
+/// 读取一行仓位；迁移前的旧行没有强平价时按仓位重新计算
+fn row_to_position(row: &rusqlite::Row) -> rusqlite::Result<Position> {
+    let mut position = Position {
+        id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap_or_default(),
+        request_id: Uuid::parse_str(&row.get::<_, String>(1)?).unwrap_or_default(),
+        quote_id: Uuid::parse_str(&row.get::<_, String>(2)?).unwrap_or_default(),
+        trader_agent: row.get(3)?,
+        mm_agent: row.get(4)?,
+        market: parse_market(&row.get::<_, String>(5)?),
+        side: parse_side(&row.get::<_, String>(6)?),
+        size_usdc: row.get(7)?,
+        leverage: row.get(8)?,
+        entry_price: row.get(9)?,
+        funding_rate: row.get(10)?,
+        trader_collateral: row.get(11)?,
+        mm_collateral: row.get(12)?,
+        status: parse_status(&row.get::<_, String>(13)?),
+        created_at: parse_timestamp(&row.get::<_, String>(14)?),
+        closed_at: row.get::<_, Option<String>>(15)?
+            .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+            .map(|dt| dt.with_timezone(&Utc)),
+        stop_loss: row.get("stop_loss")?,
+        take_profit: row.get("take_profit")?,
+        margin_mode: parse_margin_mode(&row.get::<_, String>("margin_mode")?),
+        liquidation_price: Usd::ZERO,
+        // 迁移 12 回填时表中还没有该列
+        last_funding_at: match row.get::<_, Option<String>>("last_funding_at") {
+            Err(rusqlite::Error::InvalidColumnName(_)) => None,
+            value => value?
                 .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
                 .map(|dt| dt.with_timezone(&Utc)),
-        })
+        },
+    };
+    match row.get::<_, Option<Usd>>("liquidation_price")? {
+        Some(price) => position.liquidation_price = price,
+        None => position.refresh_liquidation_price(),
+    }
+    Ok(position)
+}
+
+/// 在 Rust 中精确累加单列金额 (SQLite 的 SUM 会按 REAL 计算)，NULL 视为 0
+fn sum_usd(conn: &Connection, sql: &str, args: impl rusqlite::Params) -> rusqlite::Result<Usd> {
+    let mut stmt = conn.prepare(sql)?;
+    let amounts = stmt.query_map(args, |row| row.get::<_, Option<Usd>>(0))?;
+    amounts.map(|amount| amount.map(Option::unwrap_or_default)).sum()
+}
+
+fn parse_timestamp(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now())
+}
+
+fn parse_margin_mode(s: &str) -> MarginMode {
+    match s {
+        "Cross" => MarginMode::Cross,
+        _ => MarginMode::Isolated,
     }
 }
 
@@ -447,10 +1420,172 @@ fn parse_side(s: &str) -> Side {
 }
 
 fn parse_status(s: &str) -> PositionStatus {
-    match s {
-        "Active" => PositionStatus::Active,
-        "Closed" => PositionStatus::Closed,
-        "Liquidated" => PositionStatus::Liquidated,
+    match s.to_lowercase().as_str() {
+        "active" => PositionStatus::Active,
+        "closing" => PositionStatus::Closing,
+        "closed" => PositionStatus::Closed,
+        "liquidated" => PositionStatus::Liquidated,
         _ => PositionStatus::Pending,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_market_round_trips_all_markets() {
+        for market in [
+            Market::BtcPerp, Market::EthPerp, Market::SolPerp,
+            Market::DogePerp, Market::AvaxPerp, Market::LinkPerp,
+        ] {
+            assert_eq!(parse_market(&format!("{:?}", market)), market);
+            assert_eq!(parse_market(market.symbol()), market);
+        }
+    }
+    
+    #[test]
+    fn test_migrates_old_schema_with_data_intact() {
+        let path = std::env::temp_dir()
+            .join(format!("trade-router-migrate-{}.db", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        
+        // 未引入版本管理之前的数据库
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(r#"
+                CREATE TABLE agents (
+                    id TEXT PRIMARY KEY,
+                    api_key TEXT UNIQUE NOT NULL,
+                    name TEXT,
+                    is_mm INTEGER NOT NULL DEFAULT 0,
+                    created_at TEXT NOT NULL
+                );
+                CREATE TABLE positions (
+                    id TEXT PRIMARY KEY,
+                    request_id TEXT NOT NULL,
+                    quote_id TEXT NOT NULL,
+                    trader_agent TEXT NOT NULL,
+                    mm_agent TEXT NOT NULL,
+                    market TEXT NOT NULL,
+                    side TEXT NOT NULL,
+                    size_usdc REAL NOT NULL,
+                    leverage INTEGER NOT NULL,
+                    entry_price REAL NOT NULL,
+                    funding_rate REAL NOT NULL,
+                    trader_collateral REAL NOT NULL,
+                    mm_collateral REAL NOT NULL,
+                    status TEXT NOT NULL DEFAULT 'active',
+                    created_at TEXT NOT NULL,
+                    closed_at TEXT,
+                    pnl_trader REAL,
+                    pnl_mm REAL
+                );
+                INSERT INTO agents VALUES ('agent_1', 'th_key', 'Trader', 0, '2024-01-01T00:00:00+00:00');
+                INSERT INTO positions (id, request_id, quote_id, trader_agent, mm_agent, market, side,
+                    size_usdc, leverage, entry_price, funding_rate, trader_collateral, mm_collateral, created_at)
+                VALUES ('7b0e4a3c-6d1f-4c1e-9f7a-2a9b8c0d1e2f', '00000000-0000-0000-0000-000000000001',
+                    '00000000-0000-0000-0000-000000000002', 'agent_1', 'mm_1', 'EthPerp', 'Short',
+                    1000.0, 5, 2000.0, 0.01, 200.0, 200.0, '2024-01-01T00:00:00+00:00');
+            "#).unwrap();
+        }
+        
+        let db = Database::new(&path).unwrap();
+        assert_eq!(db.schema_version().unwrap(), MIGRATIONS.len());
+        
+        let agent = db.get_agent("agent_1").unwrap().unwrap();
+        assert_eq!(agent.api_key, "th_key");
+        
+        let positions = db.get_all_active_positions().unwrap();
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[0].market, Market::EthPerp);
+        assert_eq!(positions[0].side, Side::Short);
+        assert_eq!(positions[0].status, PositionStatus::Active);
+        // 旧的 REAL 金额转为 TEXT 十进制
+        assert_eq!(positions[0].trader_collateral, Usd::from(200.0));
+        let stored: String = db.conn.lock().unwrap()
+            .query_row("SELECT typeof(size_usdc) FROM positions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, "text");
+        // 强平价已回填，以 TEXT 十进制存储
+        let (liquidation_price, stored): (Option<Usd>, String) = db.conn.lock().unwrap()
+            .query_row("SELECT liquidation_price, typeof(liquidation_price) FROM positions", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        let expected = crate::margin::liquidation_price(&positions[0], &crate::margin::MarginConfig::default());
+        assert_eq!(liquidation_price, Some(expected));
+        assert_eq!(stored, "text");
+        
+        // Re-opening an up-to-date DB is a no-op
+        drop(db);
+        let db = Database::new(&path).unwrap();
+        assert_eq!(db.schema_version().unwrap(), MIGRATIONS.len());
+        assert_eq!(db.get_all_active_positions().unwrap().len(), 1);
+        
+        std::fs::remove_file(&path).ok();
+    }
+    
+    fn closed_position(db: &Database, trader: &str, mm: &str, pnl_trader: f64) -> Uuid {
+        let position = Position {
+            id: Uuid::new_v4(),
+            request_id: Uuid::new_v4(),
+            quote_id: Uuid::new_v4(),
+            trader_agent: trader.to_string(),
+            mm_agent: mm.to_string(),
+            market: Market::BtcPerp,
+            side: Side::Long,
+            size_usdc: Usd::from(1000.0),
+            leverage: 5,
+            entry_price: Usd::from(84000.0),
+            funding_rate: 0.001,
+            trader_collateral: Usd::from(200.0),
+            mm_collateral: Usd::from(200.0),
+            status: PositionStatus::Active,
+            created_at: Utc::now(),
+            closed_at: None,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: MarginMode::Isolated,
+            liquidation_price: Usd::ZERO,
+            last_funding_at: None,
+        };
+        db.save_position(&position).unwrap();
+        db.close_position(&position.id, Usd::from(pnl_trader), Usd::from(-pnl_trader)).unwrap();
+        position.id
+    }
+    
+    fn funding(db: &Database, position_id: Uuid, trader: &str, mm: &str, amount: f64) {
+        db.save_funding_payment(&FundingPayment {
+            id: Uuid::new_v4(),
+            position_id,
+            trader_agent: trader.to_string(),
+            mm_agent: mm.to_string(),
+            funding_rate: 0.0001,
+            position_size: Usd::from(1000.0),
+            payment_amount: Usd::from(amount),
+            settled_at: Utc::now(),
+        }).unwrap();
+    }
+    
+    #[test]
+    fn test_agent_stats_net_of_funding_on_both_sides() {
+        let db = Database::new(":memory:").unwrap();
+        
+        // agent 作为 trader 赚 50，付了两次资金费共 3
+        let as_trader = closed_position(&db, "agent", "mm", 50.0);
+        funding(&db, as_trader, "agent", "mm", 1.0);
+        funding(&db, as_trader, "agent", "mm", 2.0);
+        // agent 作为 MM: 对手赚 20 (agent 亏 20)，收到资金费 0.5
+        let as_mm = closed_position(&db, "other", "agent", 20.0);
+        funding(&db, as_mm, "other", "agent", 0.5);
+        
+        let stats = db.get_agent_stats("agent").unwrap();
+        assert_eq!(stats.total_trades, 2);
+        assert_eq!((stats.wins, stats.losses), (1, 1));
+        assert_eq!(stats.gross_pnl, Usd::from(30.0));
+        assert_eq!(stats.net_funding, Usd::from(-2.5));
+        assert_eq!(stats.net_pnl, Usd::from(27.5));
+        assert_eq!(stats.total_pnl, stats.net_pnl);
+        assert_eq!(stats.total_volume, Usd::from(2000.0));
+    }
+}
@@ -1,13 +1,15 @@
 //! Demo Market Maker - 内置自动报价，方便单人测试
 
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::interval;
 use tracing::{info, debug};
 use uuid::Uuid;
 
+use crate::money::Usd;
 use crate::state::AppState;
-use crate::types::{Quote, Side};
+use crate::types::{Market, MarketConstraints, PositionStatus, Quote, Side, TradeRequest};
 
 /// Demo MM 配置
 #[derive(Clone)]
@@ -19,6 +21,13 @@ pub struct DemoMmConfig {
     pub quote_valid_secs: u64,
     pub poll_interval_secs: u64,
     pub enabled: bool,
+    /// 波动率样本窗口 (价格更新次数)
+    pub volatility_window: usize,
+    /// 每 1% 的单次收益率标准差使报价放大的比例
+    pub volatility_sensitivity: f64,
+    /// 库存达到该敞口 (USDC) 时，加仓方向的报价放大 `inventory_skew`
+    pub max_inventory_usdc: f64,
+    pub inventory_skew: f64,
 }
 
 impl Default for DemoMmConfig {
@@ -31,10 +40,104 @@ impl Default for DemoMmConfig {
             quote_valid_secs: 300,
             poll_interval_secs: 2,
             enabled: true,
+            volatility_window: 20,
+            volatility_sensitivity: 0.5,
+            max_inventory_usdc: 50000.0,
+            inventory_skew: 0.5,
         }
     }
 }
 
+/// 按市场滚动统计价格波动率 (相邻价格的对数收益率标准差)
+#[derive(Debug, Default)]
+pub struct VolatilityTracker {
+    window: usize,
+    samples: HashMap<Market, VecDeque<f64>>,
+}
+
+impl VolatilityTracker {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(2),
+            samples: HashMap::new(),
+        }
+    }
+    
+    /// 记录价格，与上一个样本相同 (价格源未更新) 时忽略
+    pub fn record(&mut self, market: Market, price: f64) {
+        if !(price > 0.0 && price.is_finite()) {
+            return;
+        }
+        let samples = self.samples.entry(market).or_default();
+        if samples.back() == Some(&price) {
+            return;
+        }
+        samples.push_back(price);
+        while samples.len() > self.window {
+            samples.pop_front();
+        }
+    }
+    
+    /// 单次更新收益率的标准差，样本不足时为 0
+    pub fn volatility(&self, market: Market) -> f64 {
+        let Some(samples) = self.samples.get(&market) else {
+            return 0.0;
+        };
+        let returns: Vec<f64> = samples.iter()
+            .zip(samples.iter().skip(1))
+            .map(|(a, b)| (b / a).ln())
+            .collect();
+        if returns.len() < 2 {
+            return 0.0;
+        }
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+        let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+        variance.sqrt()
+    }
+}
+
+/// MM 在某市场的净敞口 (USDC，正数为净多头)。MM 是交易方的对手方，方向相反
+pub fn mm_net_exposure(state: &AppState, agent_id: &str, market: Market) -> f64 {
+    state.positions.iter()
+        .filter(|p| p.mm_agent == agent_id && p.market == market && p.status == PositionStatus::Active)
+        .map(|p| match p.side {
+            Side::Long => -p.size_usdc,
+            Side::Short => p.size_usdc,
+        })
+        .sum::<Usd>()
+        .to_f64()
+}
+
+/// 计算报价费率: 杠杆基础费率 × 波动率放大 × 库存偏斜，不超过请求的上限。
+/// 基础费率已超过上限时不报价
+pub fn quote_funding_rate(
+    config: &DemoMmConfig,
+    request: &TradeRequest,
+    volatility: f64,
+    net_exposure: f64,
+) -> Option<f64> {
+    let leverage_mult = 1.0 + (request.leverage as f64 - 1.0) * 0.05;
+    let base_rate = config.base_funding_rate * leverage_mult;
+    if base_rate > request.max_funding_rate {
+        return None;
+    }
+    
+    let volatility_mult = 1.0 + config.volatility_sensitivity * volatility * 100.0;
+    
+    // 成交后 MM 的方向与交易方相反；与现有库存同向时报价更差
+    let mm_direction = match request.side {
+        Side::Long => -1.0,
+        Side::Short => 1.0,
+    };
+    let inventory_mult = if net_exposure * mm_direction > 0.0 && config.max_inventory_usdc > 0.0 {
+        1.0 + config.inventory_skew * (net_exposure.abs() / config.max_inventory_usdc).min(1.0)
+    } else {
+        1.0
+    };
+    
+    Some((base_rate * volatility_mult * inventory_mult).min(request.max_funding_rate))
+}
+
 /// 启动 Demo MM
 pub async fn start_demo_mm(state: Arc<AppState>, config: DemoMmConfig) {
     if !config.enabled {
@@ -48,9 +151,17 @@ pub async fn start_demo_mm(state: Arc<AppState>, config: DemoMmConfig) {
           config.collateral_ratio * 100.0);
     
     let mut ticker = interval(Duration::from_secs(config.poll_interval_secs));
+    let mut volatility = VolatilityTracker::new(config.volatility_window);
     
     loop {
-        ticker.tick().await;
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        
+        for entry in state.prices.iter() {
+            volatility.record(*entry.key(), *entry.value());
+        }
         
         // 遍历所有请求
         for entry in state.requests.iter() {
@@ -66,24 +177,33 @@ pub async fn start_demo_mm(state: Arc<AppState>, config: DemoMmConfig) {
                 continue;
             }
             
-            // 检查大小
-            if request.size_usdc > config.max_quote_size {
+            // 检查大小 (不超过自身上限，且满足市场约束)
+            if request.size_usdc > Usd::from(config.max_quote_size) {
                 debug!("Demo MM: skip {} (too large)", request_id);
                 continue;
             }
-            
-            // 计算 funding rate
-            let leverage_mult = 1.0 + (request.leverage as f64 - 1.0) * 0.05;
-            let funding_rate = config.base_funding_rate * leverage_mult;
-            
-            // 检查上限
-            if funding_rate > request.max_funding_rate {
-                debug!("Demo MM: funding rate {} > max {}", funding_rate, request.max_funding_rate);
+            if let Err(e) = MarketConstraints::for_market(request.market).check(request.size_usdc, request.leverage) {
+                debug!("Demo MM: skip {} ({})", request_id, e);
                 continue;
             }
             
+            // 计算 funding rate (考虑波动率与库存)
+            let net_exposure = mm_net_exposure(&state, &config.agent_id, request.market);
+            let Some(funding_rate) = quote_funding_rate(
+                &config,
+                request,
+                volatility.volatility(request.market),
+                net_exposure,
+            ) else {
+                debug!("Demo MM: base rate above max {} for {}", request.max_funding_rate, request_id);
+                continue;
+            };
+            
             // 计算抵押
-            let collateral = request.size_usdc * config.collateral_ratio / request.leverage as f64;
+            let Some(collateral) = crate::margin::initial_margin(request.size_usdc * config.collateral_ratio, request.leverage) else {
+                debug!("Demo MM: skipping zero-leverage request {}", request_id);
+                continue;
+            };
             
             // 创建报价
             let quote = Quote {
@@ -94,19 +214,121 @@ pub async fn start_demo_mm(state: Arc<AppState>, config: DemoMmConfig) {
                 collateral_usdc: collateral,
                 valid_until: chrono::Utc::now() + chrono::Duration::seconds(config.quote_valid_secs as i64),
                 created_at: chrono::Utc::now(),
+                protection: None,
             };
             
-            info!("🤖 Demo MM quoted: {:?} {} ${} @ {}%",
+            info!("🤖 Demo MM quoted: {:?} {} ${} @ {}% (inventory: ${:.0})",
                   request.market,
                   if request.side == Side::Long { "LONG" } else { "SHORT" },
                   request.size_usdc,
-                  funding_rate * 100.0);
+                  funding_rate * 100.0,
+                  net_exposure);
             
             // 存储报价
             state.quotes
                 .entry(request_id)
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(quote);
         }
     }
+    info!("🤖 Demo MM stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MarginMode;
+    use chrono::Utc;
+    
+    fn request(side: Side) -> TradeRequest {
+        TradeRequest {
+            id: Uuid::new_v4(),
+            agent_id: "trader".to_string(),
+            market: Market::BtcPerp,
+            side,
+            size_usdc: Usd::from(1000.0),
+            leverage: 1,
+            max_funding_rate: 0.05,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: MarginMode::Isolated,
+            reduce_only: false,
+            expires_at: Utc::now() + chrono::Duration::seconds(60),
+            created_at: Utc::now(),
+        }
+    }
+    
+    #[test]
+    fn test_high_volatility_widens_quote() {
+        let config = DemoMmConfig::default();
+        
+        let mut calm = VolatilityTracker::new(20);
+        let mut wild = VolatilityTracker::new(20);
+        for i in 0..20 {
+            calm.record(Market::BtcPerp, 84000.0 + (i % 2) as f64 * 10.0);
+            wild.record(Market::BtcPerp, 84000.0 + (i % 2) as f64 * 2000.0);
+        }
+        assert!(wild.volatility(Market::BtcPerp) > calm.volatility(Market::BtcPerp));
+        
+        let req = request(Side::Long);
+        let calm_rate = quote_funding_rate(&config, &req, calm.volatility(Market::BtcPerp), 0.0).unwrap();
+        let wild_rate = quote_funding_rate(&config, &req, wild.volatility(Market::BtcPerp), 0.0).unwrap();
+        assert!(wild_rate > calm_rate);
+        assert!(calm_rate >= config.base_funding_rate);
+        
+        // Never above the requester's cap
+        let mut capped = request(Side::Long);
+        capped.max_funding_rate = 0.009;
+        assert_eq!(quote_funding_rate(&config, &capped, 0.05, 0.0), Some(0.009));
+    }
+    
+    #[test]
+    fn test_inventory_skews_correlated_side() {
+        let config = DemoMmConfig::default();
+        // MM already net short 40k: another trader long would add to it
+        let exposure = -40000.0;
+        
+        let flat_rate = quote_funding_rate(&config, &request(Side::Long), 0.0, 0.0).unwrap();
+        let adding = quote_funding_rate(&config, &request(Side::Long), 0.0, exposure).unwrap();
+        let reducing = quote_funding_rate(&config, &request(Side::Short), 0.0, exposure).unwrap();
+        
+        assert!((adding - flat_rate * 1.4).abs() < 1e-12);
+        assert_eq!(reducing, flat_rate);
+    }
+    
+    #[test]
+    fn test_net_exposure_counts_mm_side() {
+        let state = AppState::with_db_path(":memory:");
+        let mut position = crate::types::Position {
+            id: Uuid::new_v4(),
+            request_id: Uuid::new_v4(),
+            quote_id: Uuid::new_v4(),
+            trader_agent: "trader".to_string(),
+            mm_agent: "demo_mm_bot".to_string(),
+            market: Market::BtcPerp,
+            side: Side::Long,
+            size_usdc: Usd::from(3000.0),
+            leverage: 2,
+            entry_price: Usd::from(84000.0),
+            funding_rate: 0.01,
+            trader_collateral: Usd::from(1500.0),
+            mm_collateral: Usd::from(225.0),
+            status: PositionStatus::Active,
+            created_at: Utc::now(),
+            closed_at: None,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: MarginMode::Isolated,
+            liquidation_price: Usd::ZERO,
+            last_funding_at: None,
+        };
+        state.positions.insert(position.id, position.clone());
+        position.id = Uuid::new_v4();
+        position.side = Side::Short;
+        position.size_usdc = Usd::from(1000.0);
+        state.positions.insert(position.id, position);
+        
+        assert_eq!(mm_net_exposure(&state, "demo_mm_bot", Market::BtcPerp), -2000.0);
+        assert_eq!(mm_net_exposure(&state, "demo_mm_bot", Market::EthPerp), 0.0);
+    }
 }
@@ -0,0 +1,142 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::types::{Market, MarketConstraints};
+
+/// API 错误 - 响应体为 `{ code, message, details }`，`code` 供 Agent 程序化判断
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub enum ApiError {
+    /// 参数不合法
+    BadRequest(String),
+    /// 未提供或无效的 API key
+    Unauthorized(String),
+    /// 无权操作该资源
+    Forbidden(String),
+    /// 资源不存在
+    NotFound(String),
+    /// 超出 Agent 风险限额
+    RiskLimitExceeded(String),
+    /// 不满足市场的最小规模或最大杠杆
+    MarketConstraintViolated { market: Market, constraints: MarketConstraints, reason: String },
+    /// 报价已过有效期
+    QuoteExpired { quote_id: Uuid, valid_until: DateTime<Utc> },
+    /// 没有满足条件的报价
+    InsufficientLiquidity { max_funding_rate: f64 },
+    /// 仓位已平仓或已清算
+    PositionNotActive(Uuid),
+    /// 请求或报价已被接受，无法撤销
+    AlreadyAccepted(String),
+    /// 市场暂无价格，无法计算依赖价格的结果
+    PriceUnavailable(Market),
+    /// 内部错误
+    Internal(String),
+}
+
+/// 错误响应体
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::RiskLimitExceeded(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::MarketConstraintViolated { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::QuoteExpired { .. } => StatusCode::GONE,
+            ApiError::InsufficientLiquidity { .. } => StatusCode::CONFLICT,
+            ApiError::PositionNotActive(_) => StatusCode::CONFLICT,
+            ApiError::AlreadyAccepted(_) => StatusCode::CONFLICT,
+            ApiError::PriceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// 稳定的错误码，新增变体时不要修改已有的值
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "BAD_REQUEST",
+            ApiError::Unauthorized(_) => "UNAUTHORIZED",
+            ApiError::Forbidden(_) => "FORBIDDEN",
+            ApiError::NotFound(_) => "NOT_FOUND",
+            ApiError::RiskLimitExceeded(_) => "RISK_LIMIT_EXCEEDED",
+            ApiError::MarketConstraintViolated { .. } => "MARKET_CONSTRAINT_VIOLATED",
+            ApiError::QuoteExpired { .. } => "QUOTE_EXPIRED",
+            ApiError::InsufficientLiquidity { .. } => "INSUFFICIENT_LIQUIDITY",
+            ApiError::PositionNotActive(_) => "POSITION_NOT_ACTIVE",
+            ApiError::AlreadyAccepted(_) => "ALREADY_ACCEPTED",
+            ApiError::PriceUnavailable(_) => "PRICE_UNAVAILABLE",
+            ApiError::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ApiError::BadRequest(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::AlreadyAccepted(msg)
+            | ApiError::Internal(msg) => msg.clone(),
+            ApiError::RiskLimitExceeded(msg) => format!("Risk limit exceeded: {}", msg),
+            ApiError::MarketConstraintViolated { market, reason, .. } => format!("{}: {}", market.symbol(), reason),
+            ApiError::QuoteExpired { quote_id, .. } => format!("Quote {} has expired", quote_id),
+            ApiError::InsufficientLiquidity { max_funding_rate } => format!(
+                "No valid quote with funding rate at or below {}",
+                max_funding_rate
+            ),
+            ApiError::PositionNotActive(id) => format!("Position {} is not active", id),
+            ApiError::PriceUnavailable(market) => format!("No live price for {}", market.symbol()),
+        }
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            ApiError::QuoteExpired { quote_id, valid_until } => Some(serde_json::json!({
+                "quote_id": quote_id,
+                "valid_until": valid_until,
+            })),
+            ApiError::InsufficientLiquidity { max_funding_rate } => Some(serde_json::json!({
+                "max_funding_rate": max_funding_rate,
+            })),
+            ApiError::PositionNotActive(id) => Some(serde_json::json!({ "position_id": id })),
+            ApiError::PriceUnavailable(market) => Some(serde_json::json!({ "market": market })),
+            ApiError::MarketConstraintViolated { market, constraints, .. } => Some(serde_json::json!({
+                "market": market,
+                "min_size_usdc": constraints.min_size_usdc,
+                "max_leverage": constraints.max_leverage,
+            })),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            details: self.details(),
+        };
+        (self.status(), Json(body)).into_response()
+    }
+}
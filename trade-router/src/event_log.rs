@@ -0,0 +1,337 @@
+//! 追加写入的事件日志 (JSONL)
+//!
+//! 每次状态变更前先写一条事件，带单调递增的序号和时间戳。数据库丢失时可以
+//! 按序重放事件确定性地重建仓位: 事件里记录了所有非确定性的输入 (仓位 ID、
+//! 成交价格)，重放不依赖当时的行情。
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::funding::FundingPayment;
+use crate::margin::MarginConfig;
+use crate::money::Usd;
+use crate::state::AppState;
+use crate::types::{Quote, TradeRequest};
+
+/// 会改变交易状态的事件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    /// 新交易请求
+    RequestOpened(TradeRequest),
+    /// MM 报价
+    QuoteSubmitted(Quote),
+    RequestCancelled { request_id: Uuid },
+    QuoteWithdrawn { quote_id: Uuid },
+    /// 接受报价；新开仓位 (含反向对冲后的剩余部分) 使用 `position_id`
+    QuoteAccepted {
+        request_id: Uuid,
+        quote_id: Uuid,
+        position_id: Uuid,
+        entry_price: f64,
+        opened_at: DateTime<Utc>,
+    },
+    PositionClosed { position_id: Uuid, size_percent: u8, exit_price: f64 },
+    /// 运维按零盈亏撤销仓位
+    PositionCancelled { position_id: Uuid },
+    StopLevelsSet { position_id: Uuid, stop_loss: Option<f64>, take_profit: Option<f64> },
+    MarginAdded { position_id: Uuid, agent_id: String, add_usdc: Usd },
+    Liquidated { position_id: Uuid, price: f64 },
+    FundingSettled(FundingPayment),
+}
+
+/// 日志中的一行
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventRecord {
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    pub event: Event,
+}
+
+struct Writer {
+    file: File,
+    next_seq: u64,
+}
+
+/// 事件日志；未打开文件时 (测试、重放期间) 写入为空操作
+#[derive(Default)]
+pub struct EventLog {
+    writer: Option<Mutex<Writer>>,
+}
+
+impl EventLog {
+    /// 不写任何内容的日志
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// 打开 (或创建) 日志文件，序号接着已有的最后一条继续
+    pub fn open(path: &str) -> io::Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let last_seq = Self::read(path)?.last().map(|r| r.seq).unwrap_or(0);
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: Some(Mutex::new(Writer { file, next_seq: last_seq + 1 })),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// 追加一条事件，返回其序号 (未启用时为 None)
+    pub fn append(&self, event: Event) -> io::Result<Option<u64>> {
+        let Some(writer) = &self.writer else {
+            return Ok(None);
+        };
+        let mut writer = writer.lock().unwrap();
+        let record = EventRecord { seq: writer.next_seq, at: Utc::now(), event };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        writer.file.write_all(line.as_bytes())?;
+        writer.next_seq += 1;
+        Ok(Some(record.seq))
+    }
+
+    /// 按文件顺序读出全部事件。文件不存在时为空；
+    /// 无法解析的行 (如崩溃时写了一半) 跳过并告警
+    pub fn read(path: &str) -> io::Result<Vec<EventRecord>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut records = Vec::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<EventRecord>(&line) {
+                Ok(record) => records.push(record),
+                Err(e) => tracing::warn!("Skipping malformed event on line {}: {}", index + 1, e),
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// 将事件按序号依次应用到状态上，返回成功应用的事件数。
+///
+/// 必须在给状态挂载事件日志之前调用，否则重放的事件会被再次写入。
+/// 序号不递增的记录 (重复行) 被忽略；单条事件失败只记录日志。
+pub fn replay_into(state: &AppState, records: Vec<EventRecord>) -> usize {
+    if state.event_log.is_enabled() {
+        tracing::error!("Refusing to replay events into a state with an active event log");
+        return 0;
+    }
+
+    let mut applied = 0;
+    let mut last_seq = 0;
+    for record in records {
+        if record.seq <= last_seq {
+            tracing::warn!("Skipping out-of-order event #{} (after #{})", record.seq, last_seq);
+            continue;
+        }
+        last_seq = record.seq;
+        match apply(state, record.event) {
+            Ok(()) => applied += 1,
+            Err(e) => tracing::warn!("Event #{} not replayed: {}", record.seq, e),
+        }
+    }
+    applied
+}
+
+fn apply(state: &AppState, event: Event) -> Result<(), String> {
+    match event {
+        Event::RequestOpened(request) => state.add_request(request),
+        Event::QuoteSubmitted(quote) => state.add_quote(quote)?,
+        Event::RequestCancelled { request_id } => {
+            state.cancel_request(request_id).ok_or("Trade request not found")?;
+        }
+        Event::QuoteWithdrawn { quote_id } => {
+            state.withdraw_quote(quote_id).ok_or("Quote not found")?;
+        }
+        Event::QuoteAccepted { request_id, quote_id, position_id, entry_price, opened_at } => {
+            state.accept_quote_at(request_id, quote_id, position_id, entry_price, opened_at)?;
+        }
+        Event::PositionClosed { position_id, size_percent, exit_price } => {
+            state.close_position_at(position_id, size_percent, exit_price)?;
+        }
+        Event::PositionCancelled { position_id } => state.unwind_position(position_id)?,
+        Event::StopLevelsSet { position_id, stop_loss, take_profit } => {
+            state.apply_stop_levels(position_id, stop_loss, take_profit)?;
+        }
+        Event::MarginAdded { position_id, agent_id, add_usdc } => {
+            state.add_margin(position_id, &agent_id, add_usdc)?;
+        }
+        Event::Liquidated { position_id, price } => {
+            let position = state.positions.get(&position_id)
+                .map(|p| p.clone())
+                .ok_or("Position not found")?;
+            crate::liquidation::settle_liquidation(state, &position, price, &MarginConfig::default())?;
+        }
+        Event::FundingSettled(payment) => crate::funding::apply_funding_settlement(state, &payment)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{MarginMode, Market, PositionStatus, Side};
+    use std::sync::Arc;
+
+    fn temp_log_path() -> String {
+        std::env::temp_dir()
+            .join(format!("trade-router-events-{}.jsonl", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn request(agent_id: &str, side: Side, size_usdc: f64) -> TradeRequest {
+        TradeRequest {
+            id: Uuid::new_v4(),
+            agent_id: agent_id.to_string(),
+            market: Market::BtcPerp,
+            side,
+            size_usdc: Usd::from(size_usdc),
+            leverage: 5,
+            max_funding_rate: 0.01,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: MarginMode::Isolated,
+            reduce_only: false,
+            expires_at: Utc::now() + chrono::Duration::seconds(60),
+            created_at: Utc::now(),
+        }
+    }
+
+    fn trade(state: &AppState, side: Side, size_usdc: f64, mm: &str, price: f64) -> Uuid {
+        state.prices.insert(Market::BtcPerp, price);
+        let req = request("trader", side, size_usdc);
+        let quote = Quote {
+            id: Uuid::new_v4(),
+            request_id: req.id,
+            agent_id: mm.to_string(),
+            funding_rate: 0.005,
+            collateral_usdc: Usd::from(size_usdc * 0.1),
+            valid_until: Utc::now() + chrono::Duration::seconds(60),
+            created_at: Utc::now(),
+            protection: None,
+        };
+        state.add_request(req.clone());
+        state.add_quote(quote.clone()).unwrap();
+        state.accept_quote(req.id, quote.id).unwrap().id
+    }
+
+    #[test]
+    fn test_replay_rebuilds_positions() {
+        let path = temp_log_path();
+        let mut live = AppState::with_db_path(":memory:");
+        live.event_log = Arc::new(EventLog::open(&path).unwrap());
+
+        // Open, add to, net against and partially close positions at moving prices
+        let merged = trade(&live, Side::Long, 1000.0, "mm-1", 84000.0);
+        assert_eq!(trade(&live, Side::Long, 500.0, "mm-1", 86000.0), merged);
+        let netted = trade(&live, Side::Long, 2000.0, "mm-2", 85000.0);
+        trade(&live, Side::Short, 2600.0, "mm-2", 83000.0);
+        live.add_margin(merged, "trader", Usd::from(50.0)).unwrap();
+        live.prices.insert(Market::BtcPerp, 88000.0);
+        live.set_stop_levels(merged, "trader", Some(80000.0), None).unwrap();
+        live.close_position(merged, "trader", 40).unwrap();
+
+        // A request still waiting for quotes, and one that was cancelled
+        let pending = request("trader", Side::Short, 300.0);
+        live.add_request(pending.clone());
+        let cancelled = request("trader", Side::Short, 300.0);
+        live.add_request(cancelled.clone());
+        live.cancel_request(cancelled.id).unwrap();
+
+        let records = EventLog::read(&path).unwrap();
+        assert!(records.windows(2).all(|w| w[1].seq == w[0].seq + 1));
+
+        // Fresh state with its own default prices
+        let rebuilt = AppState::with_db_path(":memory:");
+        assert_eq!(replay_into(&rebuilt, records.clone()), records.len());
+
+        assert_eq!(rebuilt.positions.len(), live.positions.len());
+        for original in live.positions.iter() {
+            let replayed = rebuilt.positions.get(original.key()).expect("position rebuilt");
+            assert_eq!(replayed.status, original.status);
+            assert_eq!(replayed.side, original.side);
+            assert_eq!(replayed.size_usdc, original.size_usdc);
+            assert_eq!(replayed.entry_price, original.entry_price);
+            assert_eq!(replayed.trader_collateral, original.trader_collateral);
+            assert_eq!(replayed.mm_collateral, original.mm_collateral);
+            assert_eq!(replayed.stop_loss, original.stop_loss);
+            assert_eq!(replayed.created_at, original.created_at);
+        }
+        assert_eq!(rebuilt.positions.get(&netted).unwrap().status, PositionStatus::Closed);
+        assert_eq!(rebuilt.open_interest(Market::BtcPerp), live.open_interest(Market::BtcPerp));
+        assert!(rebuilt.requests.contains_key(&pending.id));
+        assert!(!rebuilt.requests.contains_key(&cancelled.id));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_failed_accept_is_not_logged() {
+        let path = temp_log_path();
+        let mut live = AppState::with_db_path(":memory:");
+        live.event_log = Arc::new(EventLog::open(&path).unwrap());
+
+        // Reduce-only with nothing to reduce is rejected by accept_quote_at
+        let mut req = request("trader", Side::Short, 500.0);
+        req.reduce_only = true;
+        let quote = Quote {
+            id: Uuid::new_v4(),
+            request_id: req.id,
+            agent_id: "mm".to_string(),
+            funding_rate: 0.005,
+            collateral_usdc: Usd::from(50.0),
+            valid_until: Utc::now() + chrono::Duration::seconds(60),
+            created_at: Utc::now(),
+            protection: None,
+        };
+        live.add_request(req.clone());
+        live.add_quote(quote.clone()).unwrap();
+        assert!(live.accept_quote(req.id, quote.id).is_err());
+
+        let records = EventLog::read(&path).unwrap();
+        assert!(!records.iter().any(|r| matches!(r.event, Event::QuoteAccepted { .. })));
+        let rebuilt = AppState::with_db_path(":memory:");
+        replay_into(&rebuilt, records);
+        assert!(rebuilt.positions.is_empty());
+        assert!(rebuilt.requests.contains_key(&req.id));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_sequence_continues_after_reopen() {
+        let path = temp_log_path();
+        let event = Event::RequestCancelled { request_id: Uuid::new_v4() };
+
+        let log = EventLog::open(&path).unwrap();
+        assert_eq!(log.append(event.clone()).unwrap(), Some(1));
+        assert_eq!(log.append(event.clone()).unwrap(), Some(2));
+        drop(log);
+
+        let log = EventLog::open(&path).unwrap();
+        assert_eq!(log.append(event.clone()).unwrap(), Some(3));
+        assert_eq!(EventLog::disabled().append(event).unwrap(), None);
+
+        let records = EventLog::read(&path).unwrap();
+        assert_eq!(records.iter().map(|r| r.seq).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        std::fs::remove_file(&path).ok();
+    }
+}
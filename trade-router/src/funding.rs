@@ -1,8 +1,14 @@
 //! Funding rate settlement engine
 //!
-//! Runs every 8 hours to settle funding payments between traders and market makers.
-//! Trader pays funding to MM based on position size and funding rate.
+//! Settles funding payments between traders and market makers. Each market
+//! settles on its own cadence (8 hours unless configured otherwise), with cycles
+//! aligned to the Unix epoch; a position opened mid-cycle pays only for the part
+//! of the cycle it was open.
+//! The rate for each market is the premium of the mark price (orderbook mid from
+//! the matching engine) over the index price, clamped to `max_rate`. A positive
+//! rate means longs pay shorts.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::interval;
@@ -11,25 +17,97 @@ use chrono::{DateTime, Utc};
 use uuid::Uuid;
 use serde::{Deserialize, Serialize};
 
+use crate::event_log::Event;
+use crate::money::Usd;
 use crate::state::AppState;
-use crate::types::PositionStatus;
+use crate::types::{FundingPaymentParams, Market, PositionStatus, Side, WsMessage};
+
+/// How often the engine checks whether a market's settlement is due
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Settlement cycles per year of the 8-hour rate kept in `funding_rates`
+const RATE_PERIODS_PER_YEAR: f64 = 365.0 * 3.0;
 
 /// Funding settlement configuration
 #[derive(Debug, Clone)]
 pub struct FundingConfig {
-    /// Settlement interval in hours (default: 8)
+    /// Default settlement interval in hours (default: 8)
     pub interval_hours: u64,
+    /// Per-market settlement intervals in hours, overriding `interval_hours`
+    pub market_intervals: HashMap<Market, u64>,
     /// Whether to skip actual settlement (for testing)
     pub dry_run: bool,
+    /// Maximum absolute 8-hour funding rate
+    pub max_rate: f64,
+    /// Matching engine base URL used to read orderbook mid prices
+    pub matching_engine_url: Option<String>,
 }
 
 impl Default for FundingConfig {
     fn default() -> Self {
         Self {
             interval_hours: 8,
+            market_intervals: HashMap::new(),
             dry_run: false,
+            max_rate: 0.0075,  // 0.75% per 8h
+            matching_engine_url: None,
+        }
+    }
+}
+
+impl FundingConfig {
+    /// Defaults overridden by `FUNDING_INTERVAL_HOURS` and `FUNDING_MARKET_INTERVALS`
+    /// (e.g. `BTC-PERP=8,ETH-PERP=1`)
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let interval_hours = std::env::var("FUNDING_INTERVAL_HOURS").ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(default.interval_hours);
+        let market_intervals = std::env::var("FUNDING_MARKET_INTERVALS")
+            .map(|v| parse_market_intervals(&v))
+            .unwrap_or_default();
+        Self {
+            interval_hours,
+            market_intervals,
+            matching_engine_url: std::env::var("MATCHING_ENGINE_URL").ok(),
+            ..default
         }
     }
+    
+    /// Settlement interval of `market` in hours
+    pub fn interval_hours_for(&self, market: Market) -> u64 {
+        self.market_intervals.get(&market).copied().unwrap_or(self.interval_hours).max(1)
+    }
+    
+    /// Settlement cycles per year for `market`
+    pub fn periods_per_year(&self, market: Market) -> f64 {
+        365.0 * 24.0 / self.interval_hours_for(market) as f64
+    }
+    
+    /// Start of the settlement cycle of `market` containing `now`
+    pub fn cycle_start(&self, market: Market, now: DateTime<Utc>) -> DateTime<Utc> {
+        let secs = (self.interval_hours_for(market) * 3600) as i64;
+        let ts = now.timestamp();
+        DateTime::from_timestamp(ts - ts.rem_euclid(secs), 0).unwrap_or(now)
+    }
+}
+
+/// Parse `MARKET=hours` pairs, skipping malformed entries
+fn parse_market_intervals(spec: &str) -> HashMap<Market, u64> {
+    spec.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| {
+            let parsed = entry.split_once('=').and_then(|(market, hours)| {
+                let market = serde_json::from_value::<Market>(serde_json::Value::String(market.trim().to_string())).ok()?;
+                let hours = hours.trim().parse::<u64>().ok().filter(|h| *h > 0)?;
+                Some((market, hours))
+            });
+            if parsed.is_none() {
+                warn!("Ignoring funding interval entry {:?}", entry);
+            }
+            parsed
+        })
+        .collect()
 }
 
 /// Funding payment record
@@ -40,69 +118,195 @@ pub struct FundingPayment {
     pub trader_agent: String,
     pub mm_agent: String,
     pub funding_rate: f64,
-    pub position_size: f64,
-    pub payment_amount: f64,  // positive = trader pays MM
+    pub position_size: Usd,
+    pub payment_amount: Usd,  // positive = trader pays MM
     pub settled_at: DateTime<Utc>,
 }
 
 /// Start the funding settlement engine as a background task
 pub async fn start_funding_engine(state: Arc<AppState>, config: FundingConfig) {
     info!(
-        "💰 Funding engine starting (interval: {}h, dry_run: {})",
-        config.interval_hours, config.dry_run
+        "💰 Funding engine starting (default interval: {}h, per-market: {:?}, dry_run: {})",
+        config.interval_hours, config.market_intervals, config.dry_run
     );
 
-    let interval_duration = Duration::from_secs(config.interval_hours * 3600);
-    let mut ticker = interval(interval_duration);
+    let mut ticker = interval(SCHEDULE_CHECK_INTERVAL);
+    let mut last_settled = HashMap::new();
 
-    // Skip the first immediate tick - we want to wait for the full interval first
-    ticker.tick().await;
+    let client = reqwest::Client::new();
 
     loop {
-        ticker.tick().await;
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
         
-        info!("💰 Running funding settlement...");
+        let due = due_markets(&state, &config, &mut last_settled, Utc::now());
+        if due.is_empty() {
+            continue;
+        }
+        info!("💰 Running funding settlement for {} market(s)...", due.len());
+        
+        if let Some(url) = &config.matching_engine_url {
+            refresh_mark_prices(&state, &client, url).await;
+        }
+        update_funding_rates(&state, &config);
         
-        if let Err(e) = settle_funding(&state, &config).await {
-            warn!("Funding settlement failed: {}", e);
+        for (market, settle_at) in due {
+            if let Err(e) = settle_funding(&state, &config, market, settle_at).await {
+                warn!("{} funding settlement failed: {}", market.symbol(), e);
+            }
+        }
+    }
+    info!("💰 Funding engine stopped");
+}
+
+/// Markets whose settlement cycle rolled over since they were last settled, with
+/// the cycle boundary to settle at. A market seen for the first time waits for
+/// its next boundary rather than settling the cycle already under way.
+fn due_markets(
+    state: &AppState,
+    config: &FundingConfig,
+    last_settled: &mut HashMap<Market, DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Vec<(Market, DateTime<Utc>)> {
+    let markets: Vec<Market> = state.prices.iter().map(|p| *p.key()).collect();
+    
+    markets
+        .into_iter()
+        .filter_map(|market| {
+            let boundary = config.cycle_start(market, now);
+            let last = last_settled.entry(market).or_insert(boundary);
+            if boundary > *last {
+                *last = boundary;
+                Some((market, boundary))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// 8-hour funding rate from the mark/index premium, clamped to `max_rate`
+pub fn premium_funding_rate(mark_price: f64, index_price: f64, max_rate: f64) -> f64 {
+    if index_price <= 0.0 || mark_price <= 0.0 {
+        return 0.0;
+    }
+    ((mark_price - index_price) / index_price).clamp(-max_rate, max_rate)
+}
+
+/// Recompute the live funding rate of every market with an index price.
+/// Markets without a mark price are treated as trading at index (zero premium).
+pub fn update_funding_rates(state: &AppState, config: &FundingConfig) {
+    let index_prices: Vec<(Market, f64)> = state.prices.iter().map(|p| (*p.key(), *p.value())).collect();
+    
+    for (market, index_price) in index_prices {
+        let mark_price = state.mark_prices.get(&market).map(|p| *p).unwrap_or(index_price);
+        let rate = premium_funding_rate(mark_price, index_price, config.max_rate);
+        state.funding_rates.insert(market, rate);
+    }
+}
+
+/// Update mark prices from the matching engine's best bid/offer
+async fn refresh_mark_prices(state: &AppState, client: &reqwest::Client, base_url: &str) {
+    let markets: Vec<Market> = state.prices.iter().map(|p| *p.key()).collect();
+    
+    for market in markets {
+        match fetch_orderbook_mid(client, base_url, market).await {
+            Ok(Some(mid)) => {
+                state.mark_prices.insert(market, mid);
+            }
+            Ok(None) => {
+                // One-sided or empty book: fall back to index
+                state.mark_prices.remove(&market);
+            }
+            Err(e) => warn!("Failed to fetch {} orderbook mid: {}", market.symbol(), e),
         }
     }
 }
 
-/// Settle funding for all active positions
-async fn settle_funding(state: &AppState, config: &FundingConfig) -> Result<u32, String> {
-    // Get all active positions
+async fn fetch_orderbook_mid(
+    client: &reqwest::Client,
+    base_url: &str,
+    market: Market,
+) -> Result<Option<f64>, String> {
+    let data: serde_json::Value = client
+        .get(format!("{}/markets/{}/bbo", base_url, market.symbol()))
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Parse failed: {}", e))?;
+    
+    let price = |field: &str| {
+        data.get(field)
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.parse::<f64>().ok())
+    };
+    
+    Ok(match (price("best_bid"), price("best_ask")) {
+        (Some(bid), Some(ask)) => Some((bid + ask) / 2.0),
+        _ => None,
+    })
+}
+
+/// Settle one market's funding cycle ending at `settle_at` for its active positions
+async fn settle_funding(
+    state: &AppState,
+    config: &FundingConfig,
+    market: Market,
+    settle_at: DateTime<Utc>,
+) -> Result<u32, String> {
+    let rate_8h = state.funding_rates.get(&market).map(|r| *r).unwrap_or(0.0);
+    if !config.dry_run {
+        if let Err(e) = state.db.save_funding_rate(market, rate_8h, settle_at) {
+            warn!("Failed to record {} funding rate: {}", market.symbol(), e);
+        }
+    }
+    
+    // Get the market's active positions
     let positions: Vec<_> = state
         .positions
         .iter()
-        .filter(|p| p.status == PositionStatus::Active)
+        .filter(|p| p.status == PositionStatus::Active && p.market == market)
         .map(|p| p.clone())
         .collect();
 
     if positions.is_empty() {
-        info!("💰 No active positions to settle");
+        info!("💰 No active {} positions to settle", market.symbol());
         return Ok(0);
     }
 
+    // Live 8-hour rate scaled to this market's settlement interval.
+    // Longs pay shorts when positive; payment_amount is from the trader's side.
+    let period_rate = rate_8h * (RATE_PERIODS_PER_YEAR / config.periods_per_year(market));
+    let period_ms = (config.interval_hours_for(market) * 3600 * 1000) as f64;
     let mut settled_count = 0;
-    let now = Utc::now();
 
     for position in positions {
-        // Calculate funding payment
-        // funding_rate is annual rate, we pay every 8 hours = 3 times per day = 1095 times per year
-        // payment = position_size * funding_rate / 1095
-        let periods_per_year = 365.0 * 24.0 / config.interval_hours as f64;
-        let payment_amount = position.size_usdc * position.funding_rate / periods_per_year;
+        // A position opened mid-cycle pays for the part of the cycle it was open
+        let since = position.last_funding_at.unwrap_or(position.created_at);
+        let elapsed_ms = (settle_at - since).num_milliseconds() as f64;
+        if elapsed_ms <= 0.0 {
+            continue;
+        }
+        let funding_rate = period_rate * (elapsed_ms / period_ms).min(1.0);
+        let notional = position.size_usdc * funding_rate;
+        let payment_amount = match position.side {
+            Side::Long => notional,
+            Side::Short => -notional,
+        };
 
         let payment = FundingPayment {
             id: Uuid::new_v4(),
             position_id: position.id,
             trader_agent: position.trader_agent.clone(),
             mm_agent: position.mm_agent.clone(),
-            funding_rate: position.funding_rate,
+            funding_rate,
             position_size: position.size_usdc,
             payment_amount,
-            settled_at: now,
+            settled_at: settle_at,
         };
 
         info!(
@@ -110,41 +314,156 @@ async fn settle_funding(state: &AppState, config: &FundingConfig) -> Result<u32,
             payment.trader_agent,
             payment.mm_agent,
             payment_amount,
-            position.funding_rate * 100.0,
+            funding_rate * 100.0,
             position.size_usdc
         );
 
         if !config.dry_run {
-            // Record to database
-            if let Err(e) = state.db.save_funding_payment(&payment) {
-                warn!("Failed to save funding payment: {}", e);
+            match funding_shortfall(state, position.id, payment_amount) {
+                Ok(None) => {}
+                // A trader that can't cover is left for the liquidation engine
+                Ok(Some(Shortfall::Trader(short))) => {
+                    warn!("💰 Funding not applied to {}: trader is {:.4} short - flagged for liquidation", position.id, short);
+                    state.liquidation_flags.insert(position.id);
+                    continue;
+                }
+                // The MM's shortfall is covered by the insurance fund; the trader is never liquidated for it
+                Ok(Some(Shortfall::Mm(short))) => {
+                    let fund = *state.insurance_fund.lock().unwrap();
+                    if fund < short {
+                        warn!("💰 Funding not applied to {}: MM {} is {:.4} short and the insurance fund holds {:.4}",
+                              position.id, position.mm_agent, short, fund);
+                        continue;
+                    }
+                    warn!("💰 MM {} is {:.4} short on funding for {} - covered by the insurance fund",
+                          position.mm_agent, short, position.id);
+                }
+                Err(e) => {
+                    warn!("Failed to check funding for {}: {}", position.id, e);
+                    continue;
+                }
+            }
+            
+            if let Err(e) = apply_funding_settlement(state, &payment) {
+                warn!("Failed to settle funding for {}: {}", position.id, e);
                 continue;
             }
+            state.record(Event::FundingSettled(payment.clone()));
+            state.metrics.record_funding_payment();
         }
 
         settled_count += 1;
     }
 
     info!(
-        "💰 Funding settlement complete: {} positions processed",
+        "💰 {} funding settlement complete: {} positions processed",
+        market.symbol(),
         settled_count
     );
     Ok(settled_count)
 }
 
-/// Get funding payment history for an agent
+/// The side of a position that can't cover a funding payment, and by how much
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shortfall {
+    Trader(Usd),
+    Mm(Usd),
+}
+
+/// Which side (if any) lacks the collateral for `payment_amount`
+fn funding_shortfall(state: &AppState, position_id: Uuid, payment_amount: Usd) -> Result<Option<Shortfall>, String> {
+    let position = state.positions.get(&position_id)
+        .ok_or("Position not found")?;
+    let amount = payment_amount.abs();
+    Ok(if !payment_amount.is_negative() {
+        (position.trader_collateral < amount).then(|| Shortfall::Trader(amount - position.trader_collateral))
+    } else {
+        (position.mm_collateral < amount).then(|| Shortfall::Mm(amount - position.mm_collateral))
+    })
+}
+
+/// Top up an MM's collateral from the insurance fund to cover `shortfall` on a funding payment
+fn draw_insurance_for_funding(state: &AppState, position_id: Uuid, shortfall: Usd) -> Result<(), String> {
+    let mut fund = state.insurance_fund.lock().unwrap();
+    if *fund < shortfall {
+        return Err(format!("insurance fund {:.4} cannot cover MM shortfall {:.4}", *fund, shortfall));
+    }
+    *fund -= shortfall;
+    if let Err(e) = state.db.record_insurance_event(&position_id, "draw", shortfall, *fund) {
+        warn!("Failed to record insurance draw for {}: {}", position_id, e);
+    }
+    Ok(())
+}
+
+/// Move collateral for a settled payment and record it (also used by event replay)
+pub(crate) fn apply_funding_settlement(state: &AppState, payment: &FundingPayment) -> Result<(), String> {
+    apply_funding_payment(state, payment.position_id, payment.payment_amount, payment.settled_at)?;
+    
+    for (agent_id, amount) in [(&payment.trader_agent, payment.payment_amount), (&payment.mm_agent, -payment.payment_amount)] {
+        state.notify_agent(agent_id, WsMessage::FundingCharged {
+            position_id: payment.position_id,
+            funding_rate: payment.funding_rate,
+            amount,
+        });
+    }
+    state.db.save_funding_payment(payment)
+        .map_err(|e| format!("Failed to save funding payment: {}", e))
+}
+
+/// Transfer a funding payment between the trader's and MM's collateral.
+/// Positive `payment_amount` means the trader pays the MM.
+fn apply_funding_payment(
+    state: &AppState,
+    position_id: Uuid,
+    payment_amount: Usd,
+    settled_at: DateTime<Utc>,
+) -> Result<(), String> {
+    let mut position = state.positions.get_mut(&position_id)
+        .ok_or("Position not found")?;
+    
+    let position = &mut *position;
+    let (payer, receiver) = if !payment_amount.is_negative() {
+        (&mut position.trader_collateral, &mut position.mm_collateral)
+    } else {
+        (&mut position.mm_collateral, &mut position.trader_collateral)
+    };
+    
+    let amount = payment_amount.abs();
+    if *payer < amount {
+        // Only the MM's side is backstopped by the insurance fund
+        if !payment_amount.is_negative() {
+            return Err(format!("payer collateral {:.4} cannot cover {:.4}", payer, amount));
+        }
+        let shortfall = amount - *payer;
+        draw_insurance_for_funding(state, position_id, shortfall)?;
+        *payer += shortfall;
+    }
+    *payer -= amount;
+    *receiver += amount;
+    position.last_funding_at = Some(settled_at);
+    position.refresh_liquidation_price();
+    
+    if let Err(e) = state.db.save_position(position) {
+        warn!("Failed to persist collateral for {}: {}", position_id, e);
+    }
+    state.notify_position(position);
+    Ok(())
+}
+
+/// Get a page of an agent's funding payments, newest first, with the total matching count
 pub fn get_funding_history(
     state: &AppState,
     agent_id: &str,
-    limit: u32,
-) -> Result<Vec<FundingPayment>, String> {
+    params: &FundingPaymentParams,
+) -> Result<(Vec<FundingPayment>, u32), String> {
     state
         .db
-        .get_funding_payments(agent_id, limit)
+        .get_funding_payments(agent_id, params)
         .map_err(|e| format!("Database error: {}", e))
 }
 
 /// Calculate total funding paid/received by an agent
+#[allow(dead_code)]
 pub fn get_funding_summary(
     state: &AppState,
     agent_id: &str,
@@ -155,12 +474,309 @@ pub fn get_funding_summary(
         .map_err(|e| format!("Database error: {}", e))
 }
 
+/// Market funding rates over one time bucket
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FundingRateBucket {
+    /// Bucket start, aligned to a multiple of the bucket width
+    pub start: DateTime<Utc>,
+    pub avg_rate: f64,
+    pub min_rate: f64,
+    pub max_rate: f64,
+    /// Settlement cycles recorded in the bucket
+    pub samples: u32,
+}
+
+/// Bucket width for a requested interval: rounded up to a whole number of the
+/// market's settlement cycles so every bucket covers the same number of settlements
+pub fn funding_bucket_secs(requested_hours: Option<u64>, config: &FundingConfig, market: Market) -> i64 {
+    let cadence = config.interval_hours_for(market);
+    let hours = requested_hours.unwrap_or(cadence).max(1);
+    (hours.div_ceil(cadence) * cadence * 3600) as i64
+}
+
 /// Funding summary for an agent
 #[derive(Debug, Clone, Serialize)]
+#[allow(dead_code)]
 pub struct FundingSummary {
     pub agent_id: String,
-    pub total_paid: f64,      // As trader
-    pub total_received: f64,  // As MM
-    pub net: f64,             // received - paid
+    pub total_paid: Usd,      // As trader
+    pub total_received: Usd,  // As MM
+    pub net: Usd,             // received - paid
     pub payment_count: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::TestTrade;
+    use chrono::Duration as ChronoDuration;
+    
+    #[test]
+    fn test_premium_sets_sign_and_magnitude() {
+        // Mark 0.2% above index: longs pay 0.2%
+        assert!((premium_funding_rate(100.2, 100.0, 0.0075) - 0.002).abs() < 1e-12);
+        // Mark at a discount: shorts pay
+        assert!((premium_funding_rate(99.5, 100.0, 0.0075) - (-0.005)).abs() < 1e-12);
+        // Clamped at the cap either way
+        assert_eq!(premium_funding_rate(110.0, 100.0, 0.0075), 0.0075);
+        assert_eq!(premium_funding_rate(90.0, 100.0, 0.0075), -0.0075);
+        assert_eq!(premium_funding_rate(100.0, 0.0, 0.0075), 0.0);
+    }
+    
+    fn open_position(state: &AppState, side: Side) -> Uuid {
+        open_position_with_mm(state, side, "mm")
+    }
+    
+    fn open_position_with_mm(state: &AppState, side: Side, mm: &str) -> Uuid {
+        open_position_in(state, Market::EthPerp, side, mm)
+    }
+    
+    fn open_position_in(state: &AppState, market: Market, side: Side, mm: &str) -> Uuid {
+        TestTrade { market, side, mm, max_funding_rate: 0.05, funding_rate: 0.05, ..Default::default() }.open(state).id
+    }
+    
+    /// Settle ETH `cycles` full intervals from now, so positions opened now pay whole cycles
+    async fn settle_eth(state: &AppState, config: &FundingConfig, cycles: i64) -> u32 {
+        let at = Utc::now() + ChronoDuration::hours(config.interval_hours_for(Market::EthPerp) as i64 * cycles);
+        settle_funding(state, config, Market::EthPerp, at).await.unwrap()
+    }
+    
+    fn collateral(state: &AppState, id: Uuid) -> (Usd, Usd) {
+        let p = state.positions.get(&id).unwrap();
+        (p.trader_collateral, p.mm_collateral)
+    }
+    
+    #[tokio::test]
+    async fn test_settlement_uses_live_market_rate() {
+        let state = AppState::with_db_path(":memory:");
+        let config = FundingConfig::default();
+        open_position(&state, Side::Short);
+        
+        // ETH mark 0.1% over index; other markets at index
+        state.mark_prices.insert(Market::EthPerp, 2202.2);
+        update_funding_rates(&state, &config);
+        assert!((*state.funding_rates.get(&Market::EthPerp).unwrap() - 0.001).abs() < 1e-9);
+        assert_eq!(*state.funding_rates.get(&Market::BtcPerp).unwrap(), 0.0);
+        
+        assert_eq!(settle_eth(&state, &config, 1).await, 1);
+        let payments = state.db.get_funding_payments("trader", &FundingPaymentParams::default()).unwrap().0;
+        // Short trader receives: negative payment from the trader's side
+        assert!((payments[0].payment_amount.to_f64() - (-1.0)).abs() < 1e-9);
+        assert!((payments[0].funding_rate - 0.001).abs() < 1e-9);
+        
+        // The market rate is recorded for the rate history
+        let now = Utc::now();
+        let history = state.db
+            .funding_rate_buckets(Market::EthPerp, now, now + ChronoDuration::hours(9), 3600 * 8)
+            .unwrap();
+        assert_eq!(history.iter().map(|b| b.samples).sum::<u32>(), 1);
+        assert!((history[0].avg_rate - 0.001).abs() < 1e-9);
+    }
+    
+    #[tokio::test]
+    async fn test_funding_moves_collateral() {
+        let state = AppState::with_db_path(":memory:");
+        let config = FundingConfig::default();
+        let long = open_position(&state, Side::Long);
+        // A different MM, so the short is not netted against the long
+        let short = open_position_with_mm(&state, Side::Short, "mm-2");
+        
+        // Positive 0.5% rate: the long trader pays 5, the short trader receives 5
+        state.funding_rates.insert(Market::EthPerp, 0.005);
+        assert_eq!(settle_eth(&state, &config, 1).await, 2);
+        
+        let (trader, mm) = collateral(&state, long);
+        assert_eq!(trader, Usd::from(195.0));
+        assert_eq!(mm, Usd::from(205.0));
+        let (trader, mm) = collateral(&state, short);
+        assert_eq!(trader, Usd::from(205.0));
+        assert_eq!(mm, Usd::from(195.0));
+        
+        // Persisted, so a restart sees the moved collateral
+        let stored = state.db.get_all_active_positions().unwrap();
+        let stored_long = stored.iter().find(|p| p.id == long).unwrap();
+        assert_eq!(stored_long.trader_collateral, Usd::from(195.0));
+    }
+    
+    #[tokio::test]
+    async fn test_repeated_funding_and_pnl_stay_exact() {
+        let state = AppState::with_db_path(":memory:");
+        let config = FundingConfig::default();
+        state.prices.insert(Market::EthPerp, 2000.0);
+        let long = open_position(&state, Side::Long);
+        
+        // Ten 0.1 USDC debits, then a 0.3 USDC profit on close
+        state.funding_rates.insert(Market::EthPerp, 0.0001);
+        for cycle in 1..=10 {
+            assert_eq!(settle_eth(&state, &config, cycle).await, 1);
+        }
+        state.prices.insert(Market::EthPerp, 2000.12);
+        let remaining = collateral(&state, long).0;
+        let (pnl_trader, _) = state.close_position(long, "trader", 100).unwrap();
+        
+        assert_eq!(remaining, Usd::from(199.0));
+        assert_eq!(pnl_trader, Usd::from(0.3));
+        assert_eq!(remaining + pnl_trader, Usd::from(199.3));
+        
+        // The same sequence in f64 drifts
+        let mut f64_collateral = 200.0_f64;
+        for _ in 0..10 {
+            f64_collateral -= 1000.0 * 0.0001;
+        }
+        let f64_pnl = 1000.0 * (2000.12 - 2000.0) / 2000.0 * 5.0;
+        assert_ne!(f64_collateral, 199.0);
+        assert_ne!(f64_pnl, 0.3);
+    }
+    
+    #[tokio::test]
+    async fn test_undercollateralized_payer_is_flagged() {
+        let state = AppState::with_db_path(":memory:");
+        let config = FundingConfig::default();
+        let long = open_position(&state, Side::Long);
+        state.positions.get_mut(&long).unwrap().trader_collateral = Usd::from(2.0);
+        
+        state.funding_rates.insert(Market::EthPerp, 0.005);
+        assert_eq!(settle_eth(&state, &config, 1).await, 0);
+        
+        assert!(state.liquidation_flags.contains(&long));
+        assert_eq!(collateral(&state, long), (Usd::from(2.0), Usd::from(200.0)));
+        assert!(state.db.get_funding_payments("trader", &FundingPaymentParams::default()).unwrap().0.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_mm_shortfall_is_covered_by_insurance_fund_not_liquidation() {
+        let state = AppState::with_db_path(":memory:");
+        let config = FundingConfig::default();
+        // Positive rate: the short trader receives 5 from an MM holding only 2
+        let short = open_position(&state, Side::Short);
+        state.positions.get_mut(&short).unwrap().mm_collateral = Usd::from(2.0);
+        *state.insurance_fund.lock().unwrap() = Usd::from(10.0);
+        
+        state.funding_rates.insert(Market::EthPerp, 0.005);
+        assert_eq!(settle_eth(&state, &config, 1).await, 1);
+        
+        assert!(!state.liquidation_flags.contains(&short));
+        assert_eq!(collateral(&state, short), (Usd::from(205.0), Usd::ZERO));
+        assert_eq!(*state.insurance_fund.lock().unwrap(), Usd::from(7.0));
+        let draws = state.db.get_insurance_events(Some("draw"), 10).unwrap();
+        assert_eq!(draws.len(), 1);
+        assert_eq!(draws[0].amount, Usd::from(3.0));
+    }
+    
+    #[tokio::test]
+    async fn test_uncovered_mm_shortfall_skips_payment_without_flagging_trader() {
+        let state = AppState::with_db_path(":memory:");
+        let config = FundingConfig::default();
+        let short = open_position(&state, Side::Short);
+        state.positions.get_mut(&short).unwrap().mm_collateral = Usd::from(2.0);
+        
+        state.funding_rates.insert(Market::EthPerp, 0.005);
+        assert_eq!(settle_eth(&state, &config, 1).await, 0);
+        
+        assert!(!state.liquidation_flags.contains(&short));
+        assert_eq!(collateral(&state, short), (Usd::from(200.0), Usd::from(2.0)));
+        assert!(state.db.get_funding_payments("trader", &FundingPaymentParams::default()).unwrap().0.is_empty());
+    }
+    
+    fn two_cadence_config() -> FundingConfig {
+        FundingConfig {
+            market_intervals: HashMap::from([(Market::BtcPerp, 8), (Market::EthPerp, 1)]),
+            ..Default::default()
+        }
+    }
+    
+    fn cycle_epoch() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z").unwrap().with_timezone(&Utc)
+    }
+    
+    #[test]
+    fn test_market_intervals_from_spec() {
+        let intervals = parse_market_intervals("BTC-PERP=8, ETH-PERP=1,bogus=2,SOL-PERP=0");
+        assert_eq!(intervals, HashMap::from([(Market::BtcPerp, 8), (Market::EthPerp, 1)]));
+        
+        let config = two_cadence_config();
+        assert_eq!(config.interval_hours_for(Market::SolPerp), 8);
+        assert_eq!(config.periods_per_year(Market::BtcPerp), 1095.0);
+        assert_eq!(config.periods_per_year(Market::EthPerp), 8760.0);
+    }
+    
+    #[tokio::test]
+    async fn test_markets_settle_on_their_own_cadence() {
+        let state = AppState::with_db_path(":memory:");
+        let config = two_cadence_config();
+        let start = cycle_epoch();
+        let mut last_settled = HashMap::new();
+        let due = |last: &mut HashMap<_, _>, offset_mins: i64| {
+            due_markets(&state, &config, last, start + ChronoDuration::minutes(offset_mins))
+        };
+        
+        // First look: wait for the next boundary
+        assert!(due(&mut last_settled, 30).is_empty());
+        // ETH rolls over hourly, BTC does not
+        assert_eq!(due(&mut last_settled, 60), vec![(Market::EthPerp, start + ChronoDuration::hours(1))]);
+        assert!(due(&mut last_settled, 65).is_empty());
+        for hour in 2..8 {
+            assert_eq!(due(&mut last_settled, hour * 60 + 1), vec![(Market::EthPerp, start + ChronoDuration::hours(hour))]);
+        }
+        // At the 8h boundary both are due
+        let due_8h = due(&mut last_settled, 8 * 60);
+        assert!(due_8h.contains(&(Market::EthPerp, start + ChronoDuration::hours(8))));
+        assert!(due_8h.contains(&(Market::BtcPerp, start + ChronoDuration::hours(8))));
+    }
+    
+    #[tokio::test]
+    async fn test_mid_cycle_open_is_prorated_per_market() {
+        let state = AppState::with_db_path(":memory:");
+        let config = two_cadence_config();
+        let start = cycle_epoch();
+        let eth = open_position_in(&state, Market::EthPerp, Side::Long, "mm");
+        let btc = open_position_in(&state, Market::BtcPerp, Side::Long, "mm");
+        state.positions.get_mut(&eth).unwrap().created_at = start + ChronoDuration::minutes(15);
+        state.positions.get_mut(&btc).unwrap().created_at = start + ChronoDuration::hours(2);
+        // 8-hour rates: ETH pays 0.1% per 1h cycle, BTC 0.4% per 8h cycle
+        state.funding_rates.insert(Market::EthPerp, 0.008);
+        state.funding_rates.insert(Market::BtcPerp, 0.004);
+        
+        // ETH opened 15 minutes into its 1h cycle: pays 3/4 of a cycle
+        assert_eq!(settle_funding(&state, &config, Market::EthPerp, start + ChronoDuration::hours(1)).await.unwrap(), 1);
+        assert!((collateral(&state, eth).0.to_f64() - 199.25).abs() < 1e-9);
+        // Then whole cycles
+        settle_funding(&state, &config, Market::EthPerp, start + ChronoDuration::hours(2)).await.unwrap();
+        assert!((collateral(&state, eth).0.to_f64() - 198.25).abs() < 1e-9);
+        
+        // ETH settlements leave BTC untouched
+        assert_eq!(collateral(&state, btc).0, Usd::from(200.0));
+        assert_eq!(state.positions.get(&btc).unwrap().last_funding_at, None);
+        
+        // BTC opened 2h into its 8h cycle: pays 6/8 of 0.4%
+        assert_eq!(settle_funding(&state, &config, Market::BtcPerp, start + ChronoDuration::hours(8)).await.unwrap(), 1);
+        assert!((collateral(&state, btc).0.to_f64() - 197.0).abs() < 1e-9);
+        
+        // The settlement time is persisted for proration after a restart
+        let stored = state.db.get_all_active_positions().unwrap();
+        let stored_btc = stored.iter().find(|p| p.id == btc).unwrap();
+        assert_eq!(stored_btc.last_funding_at, Some(start + ChronoDuration::hours(8)));
+    }
+    
+    #[tokio::test]
+    async fn test_first_payment_prorated_from_open() {
+        let state = AppState::with_db_path(":memory:");
+        let config = FundingConfig::default();
+        let start = cycle_epoch();
+        let long = open_position(&state, Side::Long);
+        state.positions.get_mut(&long).unwrap().created_at = start + ChronoDuration::hours(4);
+        state.funding_rates.insert(Market::EthPerp, 0.005);
+        
+        // Opened halfway through the 8h cycle: half of the 5 USDC payment
+        settle_funding(&state, &config, Market::EthPerp, start + ChronoDuration::hours(8)).await.unwrap();
+        assert_eq!(collateral(&state, long), (Usd::from(197.5), Usd::from(202.5)));
+        // Every later cycle is charged in full
+        settle_funding(&state, &config, Market::EthPerp, start + ChronoDuration::hours(16)).await.unwrap();
+        assert_eq!(collateral(&state, long), (Usd::from(192.5), Usd::from(207.5)));
+        
+        let payments = state.db.get_funding_payments("trader", &FundingPaymentParams::default()).unwrap().0;
+        let mut rates: Vec<f64> = payments.iter().map(|p| p.funding_rate).collect();
+        rates.sort_by(f64::total_cmp);
+        assert_eq!(rates, vec![0.0025, 0.005]);
+    }
+}
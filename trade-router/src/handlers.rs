@@ -1,32 +1,49 @@
 use axum::{
+    body::Body,
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
-    Json,
+    Extension, Json,
 };
 use chrono::{Duration, Utc};
 use std::sync::Arc;
 use uuid::Uuid;
 
+use crate::error::ApiError;
+use crate::funding::{FundingConfig, FundingPayment, FundingRateBucket};
+use crate::money::Usd;
+use crate::settlement_queue;
 use crate::state::AppState;
 use crate::types::{
-    AcceptQuote, AgentInfo, AgentPublicInfo, AgentStats, ApiResponse, ClosePosition, CreateQuote,
-    CreateTradeRequest, Market, MarketInfo, PaginatedResponse, PaginationParams, Position,
-    PositionWithPnl, Quote, RegisterAgent, RiskLimits, SetRiskLimits, TradeRequest,
+    AcceptQuote, AddMargin, AgentInfo, AtRiskParams, AtRiskPosition, AutoAcceptQuote, AgentPublicInfo, AgentStats, ApiResponse, ClosePosition, CreateQuote,
+    CreateTradeRequest, ForceSettlePosition, FundingHistoryParams, FundingPaymentParams, InsuranceFundSummary, MarginQueryParams, Market, MarketConstraints, MarketInfo, ModifyPosition, PaginatedResponse, PaginationParams, Position,
+    PositionWithPnl, Quote, QuoteProtection, RegisterAgent, RiskLimits, SetRiskLimits, SettlementJob, TradeRecord, TradeRequest,
 };
 
+/// 取出认证中间件注入的 Agent，未携带有效 API Key 时返回 401
+fn authenticated_agent(agent: Option<Extension<AgentInfo>>) -> Result<AgentInfo, ApiError> {
+    agent
+        .map(|Extension(agent)| agent)
+        .ok_or_else(|| ApiError::Unauthorized("API key required. Use X-API-Key header.".to_string()))
+}
+
 /// POST /trade/request - 发起交易请求
 pub async fn create_trade_request(
     State(state): State<Arc<AppState>>,
     Json(input): Json<CreateTradeRequest>,
-) -> Result<Json<ApiResponse<TradeRequest>>, (StatusCode, Json<ApiResponse<()>>)> {
+) -> Result<Json<ApiResponse<TradeRequest>>, ApiError> {
+    // 市场约束先于 Agent 限额检查
+    let constraints = MarketConstraints::for_market(input.market);
+    constraints.check(input.size_usdc, input.leverage)
+        .map_err(|reason| ApiError::MarketConstraintViolated { market: input.market, constraints, reason })?;
+    
     // 检查风险限额
-    if let Err(e) = state.check_risk_limits(&input.agent_id, input.size_usdc, input.leverage) {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::err(format!("Risk limit exceeded: {}", e))),
-        ));
-    }
+    state.check_risk_limits(&input.agent_id, input.size_usdc, input.leverage, input.reduce_only)
+        .map_err(ApiError::RiskLimitExceeded)?;
+    
+    let reference_price = state.prices.get(&input.market).map(|p| *p).unwrap_or(0.0);
+    crate::liquidation::validate_stop_levels(input.side, input.stop_loss, input.take_profit, reference_price)
+        .map_err(ApiError::BadRequest)?;
     
     let request = TradeRequest {
         id: Uuid::new_v4(),
@@ -36,6 +53,10 @@ pub async fn create_trade_request(
         size_usdc: input.size_usdc,
         leverage: input.leverage,
         max_funding_rate: input.max_funding_rate,
+        stop_loss: input.stop_loss,
+        take_profit: input.take_profit,
+        margin_mode: input.margin_mode,
+        reduce_only: input.reduce_only,
         expires_at: Utc::now() + Duration::seconds(input.expires_in as i64),
         created_at: Utc::now(),
     };
@@ -45,18 +66,55 @@ pub async fn create_trade_request(
     Ok(Json(ApiResponse::ok(request)))
 }
 
+/// POST /trade/request/:id/cancel - 撤销尚未成交的交易请求
+pub async fn cancel_trade_request(
+    State(state): State<Arc<AppState>>,
+    Path(request_id): Path<Uuid>,
+    agent: Option<Extension<AgentInfo>>,
+) -> Result<Json<ApiResponse<TradeRequest>>, ApiError> {
+    let agent = authenticated_agent(agent)?;
+    
+    // 已接受的请求会从 requests 中移除
+    if state.accepted_requests.contains(&request_id) {
+        return Err(ApiError::AlreadyAccepted(format!("Trade request {} already has an accepted quote", request_id)));
+    }
+    
+    let owner = state.requests.get(&request_id).map(|r| r.agent_id.clone());
+    match owner {
+        None => return Err(ApiError::NotFound("Trade request not found".to_string())),
+        Some(owner) if owner != agent.id => {
+            return Err(ApiError::Forbidden("Only the requesting agent can cancel it".to_string()));
+        }
+        Some(_) => {}
+    }
+    
+    // 并发接受时请求可能已被移除
+    let request = state.cancel_request(request_id)
+        .ok_or_else(|| ApiError::AlreadyAccepted(format!("Trade request {} already has an accepted quote", request_id)))?;
+    Ok(Json(ApiResponse::ok(request)))
+}
+
 /// POST /trade/quote - 提交报价
 pub async fn create_quote(
     State(state): State<Arc<AppState>>,
     Json(input): Json<CreateQuote>,
-) -> Result<Json<ApiResponse<Quote>>, (StatusCode, Json<ApiResponse<()>>)> {
+) -> Result<Json<ApiResponse<Quote>>, ApiError> {
     // 验证请求是否存在
-    if !state.requests.contains_key(&input.request_id) {
-        return Err((
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::err("Trade request not found")),
-        ));
-    }
+    let market = state.requests.get(&input.request_id)
+        .map(|r| r.market)
+        .ok_or_else(|| ApiError::NotFound("Trade request not found".to_string()))?;
+    
+    // 报价保护: 记录当前市场价格和资金费率，接受时重新校验
+    let protection = match (input.tolerance_bps, input.min_valid_ms) {
+        (Some(tolerance_bps), min_valid_ms) => Some(QuoteProtection {
+            min_valid_ms: min_valid_ms.unwrap_or(0),
+            tolerance_bps,
+            reference_price: state.prices.get(&market).map(|p| *p).unwrap_or(0.0),
+            reference_funding_rate: state.funding_rates.get(&market).map(|r| *r).unwrap_or(0.0),
+        }),
+        (None, Some(_)) => return Err(ApiError::BadRequest("min_valid_ms requires tolerance_bps".to_string())),
+        (None, None) => None,
+    };
     
     let quote = Quote {
         id: Uuid::new_v4(),
@@ -66,103 +124,147 @@ pub async fn create_quote(
         collateral_usdc: input.collateral_usdc,
         valid_until: Utc::now() + Duration::seconds(input.valid_for as i64),
         created_at: Utc::now(),
+        protection,
     };
     
-    if let Err(e) = state.add_quote(quote.clone()) {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::err(e)),
-        ));
+    state.add_quote(quote.clone()).map_err(ApiError::BadRequest)?;
+    
+    Ok(Json(ApiResponse::ok(quote)))
+}
+
+/// POST /trade/quote/:quote_id/withdraw - MM 撤回尚未被接受的报价
+pub async fn withdraw_quote(
+    State(state): State<Arc<AppState>>,
+    Path(quote_id): Path<Uuid>,
+    agent: Option<Extension<AgentInfo>>,
+) -> Result<Json<ApiResponse<Quote>>, ApiError> {
+    let agent = authenticated_agent(agent)?;
+    
+    // 被接受的报价会随请求一起移除
+    if state.accepted_quotes.contains(&quote_id) {
+        return Err(ApiError::AlreadyAccepted(format!("Quote {} has already been accepted", quote_id)));
+    }
+    
+    let quote = state.find_quote(quote_id)
+        .ok_or_else(|| ApiError::NotFound("Quote not found".to_string()))?;
+    if quote.agent_id != agent.id {
+        return Err(ApiError::Forbidden("Only the quoting agent can withdraw it".to_string()));
     }
     
+    // 并发接受时报价可能已被移除
+    let quote = state.withdraw_quote(quote_id)
+        .ok_or_else(|| ApiError::AlreadyAccepted(format!("Quote {} has already been accepted", quote_id)))?;
     Ok(Json(ApiResponse::ok(quote)))
 }
 
 /// POST /trade/accept - 接受报价
 pub async fn accept_quote(
     State(state): State<Arc<AppState>>,
+    agent: Option<Extension<AgentInfo>>,
     Json(input): Json<AcceptQuote>,
-) -> Result<Json<ApiResponse<Position>>, (StatusCode, Json<ApiResponse<()>>)> {
-    match state.accept_quote(input.request_id, input.quote_id) {
-        Ok(position) => {
-            // 链上结算 (异步，不阻塞响应)
-            let settlement = state.settlement.clone();
-            let market = format!("{:?}", position.market);
-            let trader = position.trader_agent.clone();
-            let size = (position.size_usdc * 1000.0) as i64; // Convert to contract units
-            let price = position.entry_price;
-            
-            tokio::spawn(async move {
-                match settlement.settle_open_position(&trader, &market, size, price).await {
-                    Ok(resp) => {
-                        if resp.success {
-                            tracing::info!("Position settled on-chain: {:?}", resp.signature);
-                        } else {
-                            tracing::warn!("On-chain settlement failed: {:?}", resp.error);
-                        }
-                    }
-                    Err(e) => {
-                        tracing::warn!("Settlement service error: {}", e);
-                    }
-                }
-            });
-            
-            Ok(Json(ApiResponse::ok(position)))
-        }
-        Err(e) => Err((
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::err(e)),
-        )),
+) -> Result<Json<ApiResponse<Position>>, ApiError> {
+    let agent = authenticated_agent(agent)?;
+    let request = state.requests.get(&input.request_id)
+        .map(|r| r.clone())
+        .ok_or_else(|| ApiError::NotFound("Trade request not found".to_string()))?;
+    if request.agent_id != agent.id {
+        return Err(ApiError::Forbidden("Only the requesting agent can accept quotes".to_string()));
     }
+    let quote = state.quotes.get(&input.request_id)
+        .and_then(|quotes| quotes.iter().find(|q| q.id == input.quote_id).cloned())
+        .ok_or_else(|| ApiError::NotFound("Quote not found".to_string()))?;
+    if quote.valid_until <= Utc::now() {
+        return Err(ApiError::QuoteExpired { quote_id: quote.id, valid_until: quote.valid_until });
+    }
+    
+    let position = state.accept_quote(input.request_id, input.quote_id)
+        .map_err(ApiError::BadRequest)?;
+    settlement_queue::enqueue_open(&state, position.id, &request);
+    Ok(Json(ApiResponse::ok(position)))
+}
+
+/// POST /trade/auto-accept - 自动接受费率最低的有效报价
+pub async fn auto_accept_quote(
+    State(state): State<Arc<AppState>>,
+    agent: Option<Extension<AgentInfo>>,
+    Json(input): Json<AutoAcceptQuote>,
+) -> Result<Json<ApiResponse<Position>>, ApiError> {
+    let agent = authenticated_agent(agent)?;
+    let request = state.requests.get(&input.request_id)
+        .map(|r| r.clone())
+        .ok_or_else(|| ApiError::NotFound("Trade request not found".to_string()))?;
+    if request.agent_id != agent.id {
+        return Err(ApiError::Forbidden("Only the requesting agent can accept quotes".to_string()));
+    }
+    
+    let quote = state.best_quote(input.request_id, input.max_funding_rate)
+        .ok_or(ApiError::InsufficientLiquidity { max_funding_rate: input.max_funding_rate })?;
+    
+    let position = state.accept_quote(input.request_id, quote.id)
+        .map_err(ApiError::BadRequest)?;
+    settlement_queue::enqueue_open(&state, position.id, &request);
+    Ok(Json(ApiResponse::ok(position)))
+}
+
+/// 查找 Agent 作为交易方的活跃仓位
+fn active_trader_position(state: &AppState, position_id: Uuid, agent_id: &str, action: &str) -> Result<Position, ApiError> {
+    let position = state.positions.get(&position_id)
+        .map(|p| p.clone())
+        .ok_or_else(|| ApiError::NotFound("Position not found".to_string()))?;
+    if position.trader_agent != agent_id {
+        return Err(ApiError::Forbidden(format!("Only the position's trader can {} it", action)));
+    }
+    if position.status != crate::types::PositionStatus::Active {
+        return Err(ApiError::PositionNotActive(position_id));
+    }
+    Ok(position)
+}
+
+/// POST /trade/modify - 修改止损/止盈
+pub async fn modify_position(
+    State(state): State<Arc<AppState>>,
+    agent: Option<Extension<AgentInfo>>,
+    Json(input): Json<ModifyPosition>,
+) -> Result<Json<ApiResponse<Position>>, ApiError> {
+    let agent = authenticated_agent(agent)?;
+    active_trader_position(&state, input.position_id, &agent.id, "modify")?;
+    
+    let position = state.set_stop_levels(input.position_id, &agent.id, input.stop_loss, input.take_profit)
+        .map_err(ApiError::BadRequest)?;
+    Ok(Json(ApiResponse::ok(position)))
 }
 
 /// POST /trade/close - 平仓
 pub async fn close_position(
     State(state): State<Arc<AppState>>,
+    agent: Option<Extension<AgentInfo>>,
     Json(input): Json<ClosePosition>,
-) -> Result<Json<ApiResponse<serde_json::Value>>, (StatusCode, Json<ApiResponse<()>>)> {
+) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
+    let agent = authenticated_agent(agent)?;
     // 先获取仓位信息用于结算
-    let position_info = state.positions.get(&input.position_id)
-        .map(|p| (p.trader_agent.clone(), format!("{:?}", p.market)));
-    
-    match state.close_position(input.position_id, &input.agent_id) {
-        Ok((pnl_trader, pnl_mm)) => {
-            // 链上平仓结算 (异步)
-            if let Some((trader, market)) = position_info {
-                let settlement = state.settlement.clone();
-                let current_price = state.prices.get(&crate::types::Market::BtcPerp)
-                    .map(|p| *p)
-                    .unwrap_or(97000.0);
-                
-                tokio::spawn(async move {
-                    match settlement.settle_close_position(&trader, &market, current_price).await {
-                        Ok(resp) => {
-                            if resp.success {
-                                tracing::info!("Close settled on-chain: {:?}", resp.signature);
-                            } else {
-                                tracing::warn!("On-chain close settlement failed: {:?}", resp.error);
-                            }
-                        }
-                        Err(e) => {
-                            tracing::warn!("Settlement service error: {}", e);
-                        }
-                    }
-                });
-            }
-            
-            let data = serde_json::json!({
-                "position_id": input.position_id,
-                "pnl_trader": pnl_trader,
-                "pnl_mm": pnl_mm,
-                "status": "closed"
-            });
-            Ok(Json(ApiResponse::ok(data)))
-        }
-        Err(e) => Err((
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::err(e)),
-        )),
-    }
+    let position = active_trader_position(&state, input.position_id, &agent.id, "close")?;
+    
+    let (pnl_trader, pnl_mm) = state.close_position(input.position_id, &agent.id, input.size_percent)
+        .map_err(ApiError::BadRequest)?;
+    
+    let remaining_size_usdc = state.positions.get(&input.position_id)
+        .filter(|p| p.status == crate::types::PositionStatus::Active)
+        .map(|p| p.size_usdc)
+        .unwrap_or_default();
+    
+    // 链上平仓结算 (入队，由后台任务重试直至成功)；部分平仓只结算平掉的部分
+    let exit_price = state.prices.get(&position.market)
+        .map(|p| *p)
+        .unwrap_or(position.entry_price.to_f64());
+    settlement_queue::enqueue_close(&state, &position, position.size_usdc - remaining_size_usdc, exit_price);
+    let data = serde_json::json!({
+        "position_id": input.position_id,
+        "pnl_trader": pnl_trader,
+        "pnl_mm": pnl_mm,
+        "remaining_size_usdc": remaining_size_usdc,
+        "status": if remaining_size_usdc.is_positive() { "partially_closed" } else { "closed" }
+    });
+    Ok(Json(ApiResponse::ok(data)))
 }
 
 /// GET /positions/:agent_id - 获取 Agent 的仓位
@@ -194,6 +296,164 @@ pub async fn get_position_history(
     }
 }
 
+/// GET /agents/:agent_id/funding - Agent 的资金费支付记录，支持分页和按时间/市场过滤
+pub async fn get_funding_payments(
+    State(state): State<Arc<AppState>>,
+    Path(agent_id): Path<String>,
+    Query(params): Query<FundingPaymentParams>,
+) -> Result<Json<ApiResponse<PaginatedResponse<FundingPayment>>>, ApiError> {
+    if let (Some(from), Some(to)) = (params.from, params.to) {
+        if from > to {
+            return Err(ApiError::BadRequest("from must not be after to".to_string()));
+        }
+    }
+    
+    let (payments, total) = crate::funding::get_funding_history(&state, &agent_id, &params)
+        .map_err(ApiError::Internal)?;
+    Ok(Json(ApiResponse::ok(PaginatedResponse {
+        items: payments,
+        total,
+        limit: params.limit,
+        offset: params.offset,
+    })))
+}
+
+/// 资金费率历史默认回看时长
+const FUNDING_HISTORY_DEFAULT_DAYS: i64 = 7;
+
+/// GET /funding/:market/history - 按时间分桶的市场资金费率历史
+pub async fn get_funding_rate_history(
+    State(state): State<Arc<AppState>>,
+    Path(market): Path<Market>,
+    Query(params): Query<FundingHistoryParams>,
+) -> Result<Json<ApiResponse<Vec<FundingRateBucket>>>, ApiError> {
+    let to = params.to.unwrap_or_else(Utc::now);
+    let from = params.from.unwrap_or(to - Duration::days(FUNDING_HISTORY_DEFAULT_DAYS));
+    if from > to {
+        return Err(ApiError::BadRequest("from must not be after to".to_string()));
+    }
+    
+    let bucket_secs = crate::funding::funding_bucket_secs(params.interval, &FundingConfig::default(), market);
+    let buckets = state.db.funding_rate_buckets(market, from, to, bucket_secs)
+        .map_err(|e| ApiError::Internal(format!("Database error: {}", e)))?;
+    Ok(Json(ApiResponse::ok(buckets)))
+}
+
+/// 保险基金概览返回的最近记录条数
+const INSURANCE_FUND_RECENT_LIMIT: u32 = 20;
+
+/// GET /insurance-fund - 保险基金余额、最近支出和社会化亏损
+pub async fn get_insurance_fund(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ApiResponse<InsuranceFundSummary>>, ApiError> {
+    let balance = *state.insurance_fund.lock().unwrap();
+    let db_error = |e: rusqlite::Error| ApiError::Internal(format!("Database error: {}", e));
+    let recent_draws = state.db.get_insurance_events(Some("draw"), INSURANCE_FUND_RECENT_LIMIT)
+        .map_err(db_error)?;
+    let recent_socialized_losses = state.db.get_socialized_losses(INSURANCE_FUND_RECENT_LIMIT)
+        .map_err(db_error)?;
+    Ok(Json(ApiResponse::ok(InsuranceFundSummary { balance, recent_draws, recent_socialized_losses })))
+}
+
+/// CSV 导出每页读取的行数
+const CSV_PAGE_SIZE: u32 = 500;
+
+const CSV_HEADER: &str = "opened_at,closed_at,position_id,market,side,size_usdc,entry_price,exit_price,pnl_trader,pnl_mm,funding_paid\n";
+
+/// 固定 8 位小数，避免 -0 和科学计数法，保证输出稳定
+fn csv_number(value: Usd) -> String {
+    let value = if value.is_zero() { Usd::ZERO } else { value };
+    format!("{:.8}", value)
+}
+
+/// 一笔成交对应的 CSV 行，funding_paid 为该 Agent 视角 (正数表示支付)
+fn csv_row(state: &AppState, agent_id: &str, trade: &TradeRecord) -> String {
+    let funding = state.db.funding_paid_for_trade(&trade.position_id, &trade.closed_at)
+        .unwrap_or_else(|e| {
+            tracing::error!("Failed to load funding for {}: {}", trade.position_id, e);
+            Usd::ZERO
+        });
+    let funding_paid = if trade.trader_agent == agent_id { funding } else { -funding };
+    let side = match trade.side {
+        crate::types::Side::Long => "long",
+        crate::types::Side::Short => "short",
+    };
+    
+    format!(
+        "{},{},{},{},{},{},{},{},{},{},{}\n",
+        trade.created_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        trade.closed_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        trade.position_id,
+        trade.market.symbol(),
+        side,
+        csv_number(trade.size_usdc),
+        csv_number(trade.entry_price),
+        csv_number(trade.exit_price),
+        csv_number(trade.pnl_trader),
+        csv_number(trade.pnl_mm),
+        csv_number(funding_paid),
+    )
+}
+
+/// GET /positions/:agent_id/history.csv - 导出全部成交历史 (按页流式输出，不限行数)
+pub async fn export_position_history_csv(
+    State(state): State<Arc<AppState>>,
+    Path(agent_id): Path<String>,
+) -> impl IntoResponse {
+    let filename: String = agent_id.chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect();
+    
+    // 状态: Some(offset) 表示还有下一页，None 表示已结束
+    let pages = futures::stream::unfold(Some(0u32), move |offset| {
+        let state = state.clone();
+        let agent_id = agent_id.clone();
+        async move {
+            let offset = offset?;
+            match state.get_trade_history(&agent_id, CSV_PAGE_SIZE, offset) {
+                Ok((trades, _)) => {
+                    let next = (trades.len() as u32 == CSV_PAGE_SIZE).then_some(offset + CSV_PAGE_SIZE);
+                    let mut chunk = if offset == 0 { CSV_HEADER.to_string() } else { String::new() };
+                    for trade in &trades {
+                        chunk.push_str(&csv_row(&state, &agent_id, trade));
+                    }
+                    Some((Ok::<_, String>(chunk), next))
+                }
+                // 出错时终止流，客户端会收到截断的响应
+                Err(e) => Some((Err(e), None)),
+            }
+        }
+    });
+    
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}-history.csv\"", filename)),
+        ],
+        Body::from_stream(pages),
+    )
+}
+
+/// GET /trades/:agent_id - 获取 Agent 的成交历史
+pub async fn get_trade_history(
+    State(state): State<Arc<AppState>>,
+    Path(agent_id): Path<String>,
+    Query(params): Query<PaginationParams>,
+) -> Result<Json<ApiResponse<PaginatedResponse<TradeRecord>>>, StatusCode> {
+    match state.get_trade_history(&agent_id, params.limit, params.offset) {
+        Ok((trades, total)) => {
+            let response = PaginatedResponse {
+                items: trades,
+                total,
+                limit: params.limit,
+                offset: params.offset,
+            };
+            Ok(Json(ApiResponse::ok(response)))
+        }
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
 /// GET /requests - 获取所有活跃请求
 pub async fn get_requests(
     State(state): State<Arc<AppState>>,
@@ -215,60 +475,91 @@ pub async fn get_quotes(
 pub async fn get_markets(
     State(state): State<Arc<AppState>>,
 ) -> Json<ApiResponse<Vec<MarketInfo>>> {
-    let markets = vec![
-        MarketInfo {
-            market: Market::BtcPerp,
-            current_price: state.prices.get(&Market::BtcPerp).map(|p| *p).unwrap_or(84000.0),
-            funding_rate_24h: 0.01,
-            open_interest: 1000000.0,
-            volume_24h: 5000000.0,
-        },
-        MarketInfo {
-            market: Market::EthPerp,
-            current_price: state.prices.get(&Market::EthPerp).map(|p| *p).unwrap_or(2200.0),
-            funding_rate_24h: 0.008,
-            open_interest: 500000.0,
-            volume_24h: 2000000.0,
-        },
-        MarketInfo {
-            market: Market::SolPerp,
-            current_price: state.prices.get(&Market::SolPerp).map(|p| *p).unwrap_or(130.0),
-            funding_rate_24h: 0.012,
-            open_interest: 200000.0,
-            volume_24h: 800000.0,
-        },
-        MarketInfo {
-            market: Market::DogePerp,
-            current_price: state.prices.get(&Market::DogePerp).map(|p| *p).unwrap_or(0.18),
-            funding_rate_24h: 0.015,
-            open_interest: 100000.0,
-            volume_24h: 400000.0,
-        },
-        MarketInfo {
-            market: Market::AvaxPerp,
-            current_price: state.prices.get(&Market::AvaxPerp).map(|p| *p).unwrap_or(22.0),
-            funding_rate_24h: 0.011,
-            open_interest: 150000.0,
-            volume_24h: 600000.0,
-        },
-        MarketInfo {
-            market: Market::LinkPerp,
-            current_price: state.prices.get(&Market::LinkPerp).map(|p| *p).unwrap_or(14.0),
-            funding_rate_24h: 0.009,
-            open_interest: 120000.0,
-            volume_24h: 500000.0,
-        },
+    // (市场, 默认价格, 24h 资金费率, 24h 成交量)
+    let defaults = [
+        (Market::BtcPerp, 84000.0, 0.01, 5000000.0),
+        (Market::EthPerp, 2200.0, 0.008, 2000000.0),
+        (Market::SolPerp, 130.0, 0.012, 800000.0),
+        (Market::DogePerp, 0.18, 0.015, 400000.0),
+        (Market::AvaxPerp, 22.0, 0.011, 600000.0),
+        (Market::LinkPerp, 14.0, 0.009, 500000.0),
     ];
+    let markets = defaults
+        .into_iter()
+        .map(|(market, default_price, funding_rate_24h, volume_24h)| {
+            let oi = state.open_interest(market);
+            let constraints = MarketConstraints::for_market(market);
+            MarketInfo {
+                market,
+                current_price: state.prices.get(&market).map(|p| *p).unwrap_or(default_price),
+                price_updated_at: state.price_updated_at.get(&market).map(|t| *t),
+                funding_rate_24h,
+                open_interest: oi.total(),
+                long_open_interest: oi.long,
+                short_open_interest: oi.short,
+                volume_24h,
+                min_size_usdc: constraints.min_size_usdc,
+                max_leverage: constraints.max_leverage,
+            }
+        })
+        .collect();
     Json(ApiResponse::ok(markets))
 }
 
-/// GET /health - 健康检查
-pub async fn health() -> Json<serde_json::Value> {
-    Json(serde_json::json!({
-        "status": "healthy",
+/// 结算服务探活超时，避免拖慢健康检查
+const SETTLEMENT_PING_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// GET /health - 健康检查 (数据库、价格源、结算服务)，任一组件异常返回 503
+pub async fn health(State(state): State<Arc<AppState>>) -> (StatusCode, Json<serde_json::Value>) {
+    let now = std::time::Instant::now();
+    let max_age = crate::liquidation::DEFAULT_MAX_PRICE_AGE;
+    
+    // 数据库
+    let (db_ok, database) = match state.db.ping() {
+        Ok(()) => (true, serde_json::json!({ "status": "ok" })),
+        Err(e) => (false, serde_json::json!({ "status": "down", "error": e.to_string() })),
+    };
+    
+    // 各市场价格新鲜度 (过期的市场暂停强平)
+    let mut markets = serde_json::Map::new();
+    let mut any_stale = false;
+    for entry in state.prices.iter() {
+        let market = *entry.key();
+        let age_secs = state.last_price_update.get(&market)
+            .map(|t| now.saturating_duration_since(*t).as_secs());
+        let stale = !crate::liquidation::price_is_fresh(&state, market, now, max_age);
+        any_stale |= stale;
+        markets.insert(market.symbol().to_string(), serde_json::json!({
+            "age_secs": age_secs,
+            "stale": stale,
+        }));
+    }
+    
+    // 结算服务
+    let ping_started = std::time::Instant::now();
+    let settlement_ok = state.settlement.health_check(SETTLEMENT_PING_TIMEOUT).await;
+    let settlement = serde_json::json!({
+        "status": if settlement_ok { "ok" } else { "unreachable" },
+        "latency_ms": ping_started.elapsed().as_millis() as u64,
+    });
+    
+    let healthy = db_ok && !any_stale && settlement_ok;
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    
+    (status, Json(serde_json::json!({
+        "status": if healthy { "healthy" } else { "degraded" },
         "service": "trade-router",
-        "version": "0.1.0"
-    }))
+        "version": "0.1.0",
+        "components": {
+            "database": database,
+            "prices": {
+                "status": if any_stale { "stale" } else { "ok" },
+                "max_price_age_secs": max_age.as_secs(),
+                "markets": markets,
+            },
+            "settlement": settlement,
+        },
+    })))
 }
 
 /// POST /agents/register - 注册新 Agent
@@ -320,35 +611,63 @@ pub async fn get_agent_stats(
     }
 }
 
-/// GET /positions/:agent_id/margin - 获取仓位保证金信息
+/// GET /positions/:agent_id/margin - 获取仓位保证金信息及组合汇总，可按 ?market= 过滤
 pub async fn get_positions_margin(
     State(state): State<Arc<AppState>>,
     Path(agent_id): Path<String>,
-) -> Json<ApiResponse<Vec<crate::margin::PositionMarginInfo>>> {
+    Query(params): Query<MarginQueryParams>,
+) -> Result<Json<ApiResponse<crate::margin::PortfolioMargin>>, ApiError> {
     let config = crate::margin::MarginConfig::default();
     let positions = state.get_agent_positions(&agent_id);
     
-    let margin_infos: Vec<_> = positions
+    // 必须用实时价格计算；没有价格时报错，而不是退回开仓价给出虚假的健康度
+    let margin_infos = positions
         .iter()
         .filter(|p| p.status == crate::types::PositionStatus::Active)
+        .filter(|p| params.market.is_none_or(|m| p.market == m))
         .map(|p| {
-            let current_price = state.prices.get(&p.market).map(|pr| *pr).unwrap_or(p.entry_price);
-            crate::margin::PositionMarginInfo::from_position(p, current_price, &config)
+            let current_price = state.smoothed_price(p.market)
+                .ok_or(ApiError::PriceUnavailable(p.market))?;
+            Ok(crate::margin::PositionMarginInfo::from_position(p, current_price, &config))
         })
-        .collect();
+        .collect::<Result<Vec<_>, ApiError>>()?;
+    
+    let summary = crate::margin::PortfolioSummary::from_positions(&margin_infos);
+    Ok(Json(ApiResponse::ok(crate::margin::PortfolioMargin { positions: margin_infos, summary })))
+}
+
+/// POST /trade/positions/:position_id/margin - 追加保证金
+pub async fn add_margin(
+    State(state): State<Arc<AppState>>,
+    Path(position_id): Path<Uuid>,
+    agent: Option<Extension<AgentInfo>>,
+    Json(input): Json<AddMargin>,
+) -> Result<Json<ApiResponse<crate::margin::PositionMarginInfo>>, ApiError> {
+    let agent = authenticated_agent(agent)?;
+    let trader = state.positions.get(&position_id)
+        .map(|p| p.trader_agent.clone())
+        .ok_or_else(|| ApiError::NotFound("Position not found".to_string()))?;
+    if trader != agent.id {
+        return Err(ApiError::Forbidden("Only the position's trader can add margin".to_string()));
+    }
     
-    Json(ApiResponse::ok(margin_infos))
+    let info = state.add_margin(position_id, &agent.id, input.add_usdc)
+        .map_err(ApiError::BadRequest)?;
+    Ok(Json(ApiResponse::ok(info)))
 }
 
-/// POST /agents/:agent_id/limits - 设置 Agent 风险限额
+/// POST /agents/:agent_id/limits - 设置 Agent 风险限额 (运维接口，Agent 不能放宽自己的限额)
 pub async fn set_agent_limits(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Path(agent_id): Path<String>,
     Json(input): Json<SetRiskLimits>,
-) -> Result<Json<ApiResponse<RiskLimits>>, StatusCode> {
+) -> Result<Json<ApiResponse<RiskLimits>>, ApiError> {
+    require_admin(&state, &headers)?;
+    
     // 检查 Agent 是否存在
     if state.get_agent(&agent_id).is_none() {
-        return Err(StatusCode::NOT_FOUND);
+        return Err(ApiError::NotFound(format!("Agent {} not found", agent_id)));
     }
     
     // 获取当前限额或使用默认值
@@ -367,6 +686,9 @@ pub async fn set_agent_limits(
     if let Some(v) = input.daily_loss_limit {
         limits.daily_loss_limit = v;
     }
+    if let Some(v) = input.max_open_positions {
+        limits.max_open_positions = v;
+    }
     
     // 保存
     state.set_agent_limits(&agent_id, limits.clone());
@@ -401,3 +723,1140 @@ pub async fn get_mm_leaderboard(
         error: None,
     })
 }
+
+// ========== Admin ==========
+
+/// 校验运维 API key (`X-Admin-Key` 头)。Agent 的 API key 不能用于运维接口
+fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<(), ApiError> {
+    let expected = state.admin_key.as_deref()
+        .ok_or_else(|| ApiError::Forbidden("Admin API is disabled".to_string()))?;
+    let provided = headers.get("X-Admin-Key")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| ApiError::Unauthorized("Missing X-Admin-Key header".to_string()))?;
+    if provided != expected {
+        return Err(ApiError::Forbidden("Invalid admin key".to_string()));
+    }
+    Ok(())
+}
+
+/// POST /admin/positions/:id/settle - 按指定价格强制结算 (如预言机故障导致无法清算)
+pub async fn force_settle_position(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(position_id): Path<Uuid>,
+    Json(input): Json<ForceSettlePosition>,
+) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
+    require_admin(&state, &headers)?;
+    check_admin_target(&state, position_id)?;
+    
+    let (pnl_trader, pnl_mm) = state.force_settle(position_id, input.price)
+        .map_err(ApiError::BadRequest)?;
+    tracing::warn!("🛠️ Admin force-settled position {} at {} (pnl_trader: {:.2})", position_id, input.price, pnl_trader);
+    
+    Ok(Json(ApiResponse::ok(serde_json::json!({
+        "position_id": position_id,
+        "price": input.price,
+        "pnl_trader": pnl_trader,
+        "pnl_mm": pnl_mm,
+        "status": "closed",
+    }))))
+}
+
+/// GET /admin/settlement/pending - 尚未上链的结算任务 (等待重试 + 重试用尽)
+pub async fn get_pending_settlements(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<ApiResponse<Vec<SettlementJob>>>, ApiError> {
+    require_admin(&state, &headers)?;
+    let jobs = state.db.get_unsettled()
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(Json(ApiResponse::ok(jobs)))
+}
+
+/// GET /positions/at-risk?threshold= - 当前价距强平价在 threshold (%) 以内的活跃仓位，按距离升序
+pub async fn get_at_risk_positions(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<AtRiskParams>,
+) -> Result<Json<ApiResponse<Vec<AtRiskPosition>>>, ApiError> {
+    require_admin(&state, &headers)?;
+    if !params.threshold.is_finite() || params.threshold < 0.0 {
+        return Err(ApiError::BadRequest("threshold must be a non-negative percentage".to_string()));
+    }
+    let ratio = params.threshold / 100.0;
+
+    let markets: Vec<Market> = state.prices.iter().map(|p| *p.key()).collect();
+    let mut at_risk = Vec::new();
+    for market in markets {
+        let Some(current_price) = state.smoothed_price(market) else { continue };
+        if current_price <= 0.0 {
+            continue;
+        }
+        // 多头强平价在当前价下方，空头在上方
+        let positions = state.db
+            .get_positions_near_liquidation(market, current_price * (1.0 - ratio), current_price * (1.0 + ratio))
+            .map_err(|e| ApiError::Internal(e.to_string()))?;
+        at_risk.extend(positions.into_iter().map(|position| {
+            let distance = match position.side {
+                crate::types::Side::Long => current_price - position.liquidation_price.to_f64(),
+                crate::types::Side::Short => position.liquidation_price.to_f64() - current_price,
+            };
+            AtRiskPosition { position, current_price, distance_pct: distance / current_price * 100.0 }
+        }));
+    }
+    at_risk.sort_by(|a, b| a.distance_pct.total_cmp(&b.distance_pct));
+    Ok(Json(ApiResponse::ok(at_risk)))
+}
+
+/// POST /admin/positions/:id/cancel - 按零盈亏撤销仓位
+pub async fn cancel_position(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(position_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<serde_json::Value>>, ApiError> {
+    require_admin(&state, &headers)?;
+    check_admin_target(&state, position_id)?;
+    
+    state.cancel_position(position_id).map_err(ApiError::BadRequest)?;
+    tracing::warn!("🛠️ Admin cancelled position {} at zero PnL", position_id);
+    
+    Ok(Json(ApiResponse::ok(serde_json::json!({
+        "position_id": position_id,
+        "status": "cancelled",
+    }))))
+}
+
+/// 运维操作的目标仓位必须存在且仍活跃
+fn check_admin_target(state: &AppState, position_id: Uuid) -> Result<(), ApiError> {
+    let status = state.positions.get(&position_id)
+        .map(|p| p.status)
+        .ok_or_else(|| ApiError::NotFound("Position not found".to_string()))?;
+    if status != crate::types::PositionStatus::Active {
+        return Err(ApiError::PositionNotActive(position_id));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::TestTrade;
+    use crate::types::{MarginMode, Side, WsMessage};
+    
+    async fn error_body(err: ApiError) -> (StatusCode, serde_json::Value) {
+        let resp = err.into_response();
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+    
+    /// 模拟认证中间件注入的 Agent
+    fn authed(agent_id: &str) -> Extension<AgentInfo> {
+        Extension(AgentInfo {
+            id: agent_id.to_string(),
+            api_key: format!("th_{}", agent_id),
+            name: None,
+            is_mm: false,
+            created_at: Utc::now(),
+        })
+    }
+    
+    fn trade_request(agent_id: &str, size_usdc: f64) -> CreateTradeRequest {
+        CreateTradeRequest {
+            agent_id: agent_id.to_string(),
+            market: Market::BtcPerp,
+            side: Side::Long,
+            size_usdc: Usd::from(size_usdc),
+            leverage: 5,
+            max_funding_rate: 0.01,
+            expires_in: 60,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: MarginMode::Isolated,
+            reduce_only: false,
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_risk_limit_rejection_is_coded() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        state.set_agent_limits("trader", RiskLimits { max_position_size: Usd::from(500.0), ..Default::default() });
+        
+        let err = create_trade_request(State(state), Json(trade_request("trader", 1000.0)))
+            .await
+            .unwrap_err();
+        let (status, body) = error_body(err).await;
+        
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "RISK_LIMIT_EXCEEDED");
+        assert!(body["message"].as_str().unwrap().contains("exceeds max allowed 500"));
+        assert!(body["details"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_reduce_only_request_allowed_at_position_cap() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        state.prices.insert(Market::BtcPerp, 84000.0);
+        state.set_agent_limits("trader", RiskLimits { max_open_positions: 1, ..Default::default() });
+        open_position(&state, Side::Long);
+
+        let err = create_trade_request(State(state.clone()), Json(trade_request("trader", 500.0)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let mut input = trade_request("trader", 500.0);
+        input.side = Side::Short;
+        input.reduce_only = true;
+        let Json(request) = create_trade_request(State(state.clone()), Json(input)).await.unwrap();
+        assert!(request.data.unwrap().reduce_only);
+    }
+
+    #[tokio::test]
+    async fn test_request_below_market_minimum_is_rejected() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+
+        let err = create_trade_request(State(state.clone()), Json(trade_request("trader", 20.0)))
+            .await
+            .unwrap_err();
+        let (status, body) = error_body(err).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "MARKET_CONSTRAINT_VIOLATED");
+        assert!(body["message"].as_str().unwrap().contains("below the market minimum"));
+        assert_eq!(body["details"]["min_size_usdc"], 50.0);
+        assert!(state.requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_zero_leverage_is_rejected() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let mut input = trade_request("trader", 1000.0);
+        input.leverage = 0;
+
+        let err = create_trade_request(State(state.clone()), Json(input)).await.unwrap_err();
+        let (status, body) = error_body(err).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "MARKET_CONSTRAINT_VIOLATED");
+        assert!(body["message"].as_str().unwrap().contains("at least 1x"));
+        assert!(state.requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_market_leverage_cap_checked_before_risk_limits() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        // Agent 限额同时也会拒绝，但市场约束先生效
+        state.set_agent_limits("trader", RiskLimits { max_position_size: Usd::from(500.0), ..Default::default() });
+        let mut input = trade_request("trader", 1000.0);
+        input.market = Market::DogePerp;
+        input.leverage = 15;
+
+        let err = create_trade_request(State(state), Json(input)).await.unwrap_err();
+        let (status, body) = error_body(err).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "MARKET_CONSTRAINT_VIOLATED");
+        assert!(body["message"].as_str().unwrap().contains("exceeds the market maximum of 10x"));
+        assert_eq!(body["details"]["max_leverage"], 10);
+    }
+
+    #[tokio::test]
+    async fn test_expired_quote_is_coded() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let Json(request) = create_trade_request(State(state.clone()), Json(trade_request("trader", 1000.0)))
+            .await
+            .unwrap();
+        let request = request.data.unwrap();
+        
+        let quote = Quote {
+            id: Uuid::new_v4(),
+            request_id: request.id,
+            agent_id: "mm".to_string(),
+            funding_rate: 0.001,
+            collateral_usdc: Usd::from(200.0),
+            valid_until: Utc::now() - Duration::seconds(1),
+            created_at: Utc::now() - Duration::seconds(31),
+            protection: None,
+        };
+        state.add_quote(quote.clone()).unwrap();
+        
+        let err = accept_quote(
+            State(state.clone()),
+            Some(authed("trader")),
+            Json(AcceptQuote { request_id: request.id, quote_id: quote.id, signature: String::new() }),
+        )
+        .await
+        .unwrap_err();
+        let (status, body) = error_body(err).await;
+        
+        assert_eq!(status, StatusCode::GONE);
+        assert_eq!(body["code"], "QUOTE_EXPIRED");
+        assert_eq!(body["details"]["quote_id"], quote.id.to_string());
+        // 过期报价不会开仓
+        assert!(state.positions.is_empty());
+        assert!(state.requests.contains_key(&request.id));
+    }
+    
+    fn open_position(state: &AppState, side: Side) -> Position {
+        open_position_with_mm(state, side, "mm")
+    }
+    
+    fn open_position_with_mm(state: &AppState, side: Side, mm: &str) -> Position {
+        open_position_in(state, Market::BtcPerp, side, mm)
+    }
+    
+    fn open_position_in(state: &AppState, market: Market, side: Side, mm: &str) -> Position {
+        TestTrade { market, side, mm, ..Default::default() }.open(state)
+    }
+    
+    #[tokio::test]
+    async fn test_quote_protection_records_market_state() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let request = TradeRequest {
+            id: Uuid::new_v4(),
+            agent_id: "trader".to_string(),
+            market: Market::BtcPerp,
+            side: Side::Long,
+            size_usdc: Usd::from(1000.0),
+            leverage: 5,
+            max_funding_rate: 0.01,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: MarginMode::Isolated,
+            reduce_only: false,
+            expires_at: Utc::now() + Duration::seconds(60),
+            created_at: Utc::now(),
+        };
+        state.add_request(request.clone());
+        state.funding_rates.insert(Market::BtcPerp, 0.0003);
+        let input = |tolerance_bps: Option<u32>, min_valid_ms: Option<u64>| CreateQuote {
+            request_id: request.id,
+            agent_id: "mm".to_string(),
+            funding_rate: 0.001,
+            collateral_usdc: Usd::from(200.0),
+            valid_for: 30,
+            tolerance_bps,
+            min_valid_ms,
+        };
+        
+        let Json(resp) = create_quote(State(state.clone()), Json(input(Some(25), Some(500)))).await.unwrap();
+        assert_eq!(resp.data.unwrap().protection, Some(QuoteProtection {
+            min_valid_ms: 500,
+            tolerance_bps: 25,
+            reference_price: 84000.0,
+            reference_funding_rate: 0.0003,
+        }));
+        
+        let Json(resp) = create_quote(State(state.clone()), Json(input(None, None))).await.unwrap();
+        assert!(resp.data.unwrap().protection.is_none());
+        
+        let err = create_quote(State(state.clone()), Json(input(None, Some(500)))).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+    
+    #[tokio::test]
+    async fn test_funding_payments_paginate_and_filter() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let btc = open_position(&state, Side::Long);
+        // 仅写入数据库的 ETH 仓位，用于按市场过滤
+        let mut eth = btc.clone();
+        eth.id = Uuid::new_v4();
+        eth.market = Market::EthPerp;
+        state.db.save_position(&eth).unwrap();
+        
+        let base = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let pay = |position_id: Uuid, day: i64| {
+            state.db.save_funding_payment(&FundingPayment {
+                id: Uuid::new_v4(),
+                position_id,
+                trader_agent: "trader".to_string(),
+                mm_agent: "mm".to_string(),
+                funding_rate: 0.0001,
+                position_size: Usd::from(1000.0),
+                payment_amount: Usd::from(day as f64),
+                settled_at: base + Duration::days(day),
+            }).unwrap();
+        };
+        for day in 0..5 {
+            pay(btc.id, day);
+        }
+        pay(eth.id, 5);
+        
+        let query = |params: FundingPaymentParams| {
+            get_funding_payments(State(state.clone()), Path("trader".to_string()), Query(params))
+        };
+        let days = |page: &PaginatedResponse<FundingPayment>| -> Vec<i64> {
+            page.items.iter().map(|p| p.payment_amount.to_f64() as i64).collect()
+        };
+        
+        // 按结算时间倒序分页
+        let page = query(FundingPaymentParams { limit: 4, ..Default::default() }).await.unwrap().0.data.unwrap();
+        assert_eq!((days(&page), page.total), (vec![5, 4, 3, 2], 6));
+        let page = query(FundingPaymentParams { limit: 4, offset: 4, ..Default::default() }).await.unwrap().0.data.unwrap();
+        assert_eq!((days(&page), page.total), (vec![1, 0], 6));
+        let page = query(FundingPaymentParams { offset: 6, ..Default::default() }).await.unwrap().0.data.unwrap();
+        assert_eq!((days(&page), page.total), (vec![], 6));
+        
+        // from 含、to 不含
+        let page = query(FundingPaymentParams {
+            from: Some(base + Duration::days(1)),
+            to: Some(base + Duration::days(3)),
+            ..Default::default()
+        }).await.unwrap().0.data.unwrap();
+        assert_eq!((days(&page), page.total), (vec![2, 1], 2));
+        
+        let page = query(FundingPaymentParams { market: Some(Market::EthPerp), ..Default::default() })
+            .await.unwrap().0.data.unwrap();
+        assert_eq!((days(&page), page.total), (vec![5], 1));
+        let page = query(FundingPaymentParams {
+            market: Some(Market::BtcPerp),
+            from: Some(base + Duration::days(3)),
+            ..Default::default()
+        }).await.unwrap().0.data.unwrap();
+        assert_eq!((days(&page), page.total), (vec![4, 3], 2));
+        
+        let err = query(FundingPaymentParams {
+            from: Some(base + Duration::days(3)),
+            to: Some(base),
+            ..Default::default()
+        }).await.unwrap_err();
+        assert_eq!(err.code(), "BAD_REQUEST");
+    }
+    
+    #[tokio::test]
+    async fn test_positions_margin_uses_live_prices() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let btc = open_position(&state, Side::Long);
+        let link = open_position_in(&state, Market::LinkPerp, Side::Short, "mm");
+        state.prices.insert(Market::BtcPerp, 88200.0);
+        state.prices.insert(Market::LinkPerp, 13.3);
+        
+        let query = |market| {
+            get_positions_margin(State(state.clone()), Path("trader".to_string()), Query(MarginQueryParams { market }))
+        };
+        let portfolio = query(None).await.unwrap().0.data.unwrap();
+        assert_eq!(portfolio.positions.len(), 2);
+        let config = crate::margin::MarginConfig::default();
+        for (position, price) in [(&btc, 88200.0), (&link, 13.3)] {
+            let info = portfolio.positions.iter().find(|i| i.position_id == position.id.to_string()).unwrap();
+            assert_eq!(info.current_price, price);
+            assert_eq!(info.unrealized_pnl, crate::margin::unrealized_pnl(position, price));
+            assert!(info.unrealized_pnl > Usd::ZERO);
+            assert!((info.margin_health - crate::margin::margin_health(position, price, &config)).abs() < 1e-9);
+        }
+        
+        // 汇总等于逐仓之和
+        let summary = &portfolio.summary;
+        let sum = |f: fn(&crate::margin::PositionMarginInfo) -> Usd| portfolio.positions.iter().map(f).sum::<Usd>();
+        assert_eq!(summary.position_count, 2);
+        assert_eq!(summary.total_equity, sum(|i| i.equity));
+        assert_eq!(summary.total_maintenance_margin, sum(|i| i.maintenance_margin));
+        assert_eq!(summary.total_unrealized_pnl, sum(|i| i.unrealized_pnl));
+        assert!(summary.margin_health > 0.0 && summary.margin_health <= 100.0);
+        
+        let portfolio = query(Some(Market::LinkPerp)).await.unwrap().0.data.unwrap();
+        assert_eq!(portfolio.positions.len(), 1);
+        assert_eq!(portfolio.positions[0].position_id, link.id.to_string());
+        assert_eq!(portfolio.summary.total_equity, portfolio.positions[0].equity);
+        
+        // 缺少价格时不退回开仓价
+        state.prices.remove(&Market::LinkPerp);
+        let (status, body) = error_body(query(None).await.unwrap_err()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "PRICE_UNAVAILABLE");
+        assert!(query(Some(Market::BtcPerp)).await.is_ok());
+    }
+    
+    #[tokio::test]
+    async fn test_history_csv_round_trips() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let long = open_position(&state, Side::Long);
+        // 不同的 MM，空头不会与多头对冲合并
+        let short = open_position_with_mm(&state, Side::Short, "mm-2");
+        
+        // 多头收取一次资金费后平仓，空头分两次平仓
+        state.db.save_funding_payment(&FundingPayment {
+            id: Uuid::new_v4(),
+            position_id: long.id,
+            trader_agent: "trader".to_string(),
+            mm_agent: "mm".to_string(),
+            funding_rate: 0.0001,
+            position_size: Usd::from(1000.0),
+            payment_amount: Usd::from(0.1),
+            settled_at: Utc::now(),
+        }).unwrap();
+        state.prices.insert(Market::BtcPerp, 84840.0);
+        state.close_position(long.id, "trader", 100).unwrap();
+        state.close_position(short.id, "trader", 50).unwrap();
+        state.close_position(short.id, "trader", 100).unwrap();
+        
+        let resp = export_position_history_csv(State(state.clone()), Path("trader".to_string()))
+            .await
+            .into_response();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+        assert_eq!(
+            resp.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"trader-history.csv\""
+        );
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let csv = String::from_utf8(body.to_vec()).unwrap();
+        
+        let mut lines = csv.lines();
+        assert_eq!(format!("{}\n", lines.next().unwrap()), CSV_HEADER);
+        let rows: Vec<Vec<&str>> = lines.map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows.iter().all(|r| r.len() == 11));
+        
+        let long_rows: Vec<_> = rows.iter().filter(|r| r[2] == long.id.to_string()).collect();
+        assert_eq!(long_rows.len(), 1);
+        let row = long_rows[0];
+        assert_eq!(row[3], "BTC-PERP");
+        assert_eq!(row[4], "long");
+        assert_eq!(row[5], "1000.00000000");
+        assert_eq!(row[6], "84000.00000000");
+        assert_eq!(row[7], "84840.00000000");
+        // 1% 涨幅 x 5 倍杠杆
+        assert_eq!(row[8], "50.00000000");
+        assert_eq!(row[9], "-50.00000000");
+        assert_eq!(row[10], "0.10000000");
+        
+        let short_sizes: Vec<f64> = rows.iter()
+            .filter(|r| r[2] == short.id.to_string())
+            .map(|r| {
+                assert_eq!(r[4], "short");
+                assert_eq!(r[10], "0.00000000");
+                r[5].parse().unwrap()
+            })
+            .collect();
+        assert_eq!(short_sizes.iter().sum::<f64>(), 1000.0);
+        
+        // MM 视角的资金费符号相反
+        let resp = export_position_history_csv(State(state), Path("mm".to_string())).await.into_response();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let csv = String::from_utf8(body.to_vec()).unwrap();
+        assert!(csv.lines().any(|l| l.contains(&long.id.to_string()) && l.ends_with(",-0.10000000")));
+    }
+    
+    /// 启动一个只响应 /health 的本地结算服务
+    async fn mock_settlement() -> crate::settlement::SettlementClient {
+        let app = axum::Router::new().route("/health", axum::routing::get(|| async { "ok" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        crate::settlement::SettlementClient::with_url(&format!("http://{}", addr))
+    }
+    
+    async fn health_with_prices(price_age: std::time::Duration) -> (StatusCode, serde_json::Value) {
+        let mut state = AppState::with_db_path(":memory:");
+        state.settlement = mock_settlement().await;
+        let updated = std::time::Instant::now() - price_age;
+        for market in [Market::BtcPerp, Market::EthPerp, Market::SolPerp, Market::DogePerp, Market::AvaxPerp, Market::LinkPerp] {
+            state.last_price_update.insert(market, updated);
+        }
+        
+        let (status, Json(body)) = health(State(Arc::new(state))).await;
+        (status, body)
+    }
+    
+    #[tokio::test]
+    async fn test_health_all_components_ok() {
+        let (status, body) = health_with_prices(std::time::Duration::from_secs(1)).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "healthy");
+        assert_eq!(body["components"]["database"]["status"], "ok");
+        assert_eq!(body["components"]["prices"]["status"], "ok");
+        assert_eq!(body["components"]["settlement"]["status"], "ok");
+    }
+    
+    #[tokio::test]
+    async fn test_health_degraded_when_prices_stale() {
+        let (status, body) = health_with_prices(std::time::Duration::from_secs(300)).await;
+        
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["components"]["prices"]["status"], "stale");
+        assert_eq!(body["components"]["prices"]["markets"]["BTC-PERP"]["stale"], true);
+        // 其余组件仍正常
+        assert_eq!(body["components"]["database"]["status"], "ok");
+        assert_eq!(body["components"]["settlement"]["status"], "ok");
+    }
+    
+    #[tokio::test]
+    async fn test_cancel_open_request_clears_quotes() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let Json(request) = create_trade_request(State(state.clone()), Json(trade_request("trader", 1000.0)))
+            .await
+            .unwrap();
+        let request = request.data.unwrap();
+        state.add_quote(Quote {
+            id: Uuid::new_v4(),
+            request_id: request.id,
+            agent_id: "mm".to_string(),
+            funding_rate: 0.001,
+            collateral_usdc: Usd::from(200.0),
+            valid_until: Utc::now() + Duration::seconds(30),
+            created_at: Utc::now(),
+            protection: None,
+        }).unwrap();
+        let mut rx = state.broadcast_tx.subscribe();
+        
+        // 未认证请求被拒绝
+        let err = cancel_trade_request(State(state.clone()), Path(request.id), None).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        
+        // 只有请求方可以撤销
+        let err = cancel_trade_request(
+            State(state.clone()),
+            Path(request.id),
+            Some(authed("mm")),
+        ).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        
+        let Json(cancelled) = cancel_trade_request(
+            State(state.clone()),
+            Path(request.id),
+            Some(authed("trader")),
+        ).await.unwrap();
+        assert_eq!(cancelled.data.unwrap().id, request.id);
+        
+        assert!(!state.requests.contains_key(&request.id));
+        assert!(state.get_quotes(request.id).is_empty());
+        assert!(state.db.load_quotes(&request.id).unwrap().is_empty());
+        assert!(matches!(rx.try_recv(), Ok(WsMessage::RequestCancelled { request_id }) if request_id == request.id));
+    }
+    
+    #[tokio::test]
+    async fn test_cancel_rejected_after_acceptance() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let position = open_position(&state, Side::Long);
+        
+        let err = cancel_trade_request(
+            State(state.clone()),
+            Path(position.request_id),
+            Some(authed("trader")),
+        ).await.unwrap_err();
+        
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+        assert_eq!(err.code(), "ALREADY_ACCEPTED");
+        assert_eq!(state.positions.get(&position.id).unwrap().status, crate::types::PositionStatus::Active);
+    }
+    
+    #[tokio::test]
+    async fn test_cancel_and_withdraw_rejected_after_merged_acceptance() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let existing = open_position(&state, Side::Long);
+        
+        // 同向加仓合并到已有仓位，仓位不带新请求的 request_id
+        let Json(request) = create_trade_request(State(state.clone()), Json(trade_request("trader", 500.0)))
+            .await
+            .unwrap();
+        let request = request.data.unwrap();
+        let quote = Quote {
+            id: Uuid::new_v4(),
+            request_id: request.id,
+            agent_id: "mm".to_string(),
+            funding_rate: 0.001,
+            collateral_usdc: Usd::from(100.0),
+            valid_until: Utc::now() + Duration::seconds(30),
+            created_at: Utc::now(),
+            protection: None,
+        };
+        state.add_quote(quote.clone()).unwrap();
+        let merged = state.accept_quote(request.id, quote.id).unwrap();
+        assert_eq!(merged.id, existing.id);
+        assert_ne!(merged.request_id, request.id);
+        
+        let err = cancel_trade_request(State(state.clone()), Path(request.id), Some(authed("trader")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+        assert_eq!(err.code(), "ALREADY_ACCEPTED");
+        let err = withdraw_quote(State(state.clone()), Path(quote.id), Some(authed("mm")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+        
+        // 从未存在的请求仍是 404
+        let err = cancel_trade_request(State(state.clone()), Path(Uuid::new_v4()), Some(authed("trader")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    }
+    
+    #[tokio::test]
+    async fn test_withdraw_live_quote() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let Json(request) = create_trade_request(State(state.clone()), Json(trade_request("trader", 1000.0)))
+            .await
+            .unwrap();
+        let request = request.data.unwrap();
+        let quotes: Vec<Quote> = ["mm", "mm_2"].iter().map(|mm| Quote {
+            id: Uuid::new_v4(),
+            request_id: request.id,
+            agent_id: mm.to_string(),
+            funding_rate: 0.001,
+            collateral_usdc: Usd::from(200.0),
+            valid_until: Utc::now() + Duration::seconds(30),
+            created_at: Utc::now(),
+            protection: None,
+        }).collect();
+        for quote in &quotes {
+            state.add_quote(quote.clone()).unwrap();
+        }
+        let mut rx = state.broadcast_tx.subscribe();
+        
+        let err = withdraw_quote(State(state.clone()), Path(quotes[0].id), None).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        
+        let err = withdraw_quote(
+            State(state.clone()),
+            Path(quotes[0].id),
+            Some(authed("mm_2")),
+        ).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        
+        let Json(withdrawn) = withdraw_quote(
+            State(state.clone()),
+            Path(quotes[0].id),
+            Some(authed("mm")),
+        ).await.unwrap();
+        assert_eq!(withdrawn.data.unwrap().id, quotes[0].id);
+        
+        // 其他 MM 的报价不受影响
+        let remaining: Vec<Uuid> = state.get_quotes(request.id).iter().map(|q| q.id).collect();
+        assert_eq!(remaining, vec![quotes[1].id]);
+        assert_eq!(state.db.load_quotes(&request.id).unwrap().len(), 1);
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(WsMessage::QuoteWithdrawn { request_id, quote_id }) if request_id == request.id && quote_id == quotes[0].id
+        ));
+    }
+    
+    #[tokio::test]
+    async fn test_auto_accept_requires_the_requesting_agent() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let Json(request) = create_trade_request(State(state.clone()), Json(trade_request("trader", 1000.0)))
+            .await
+            .unwrap();
+        let request = request.data.unwrap();
+        let quote = Quote {
+            id: Uuid::new_v4(),
+            request_id: request.id,
+            agent_id: "mm".to_string(),
+            funding_rate: 0.001,
+            collateral_usdc: Usd::from(200.0),
+            valid_until: Utc::now() + Duration::seconds(30),
+            created_at: Utc::now(),
+            protection: None,
+        };
+        state.add_quote(quote.clone()).unwrap();
+        let input = || Json(AutoAcceptQuote { request_id: request.id, max_funding_rate: 0.01 });
+        
+        let err = auto_accept_quote(State(state.clone()), None, input()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        let err = auto_accept_quote(State(state.clone()), Some(authed("mm")), input()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        assert!(state.requests.contains_key(&request.id));
+        
+        let Json(position) = auto_accept_quote(State(state.clone()), Some(authed("trader")), input()).await.unwrap();
+        let position = position.data.unwrap();
+        assert_eq!(position.quote_id, quote.id);
+        assert_eq!(position.trader_agent, "trader");
+    }
+
+    #[tokio::test]
+    async fn test_accept_requires_the_requesting_agent() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let Json(request) = create_trade_request(State(state.clone()), Json(trade_request("trader", 1000.0)))
+            .await
+            .unwrap();
+        let request = request.data.unwrap();
+        let quote = Quote {
+            id: Uuid::new_v4(),
+            request_id: request.id,
+            agent_id: "mm".to_string(),
+            funding_rate: 0.001,
+            collateral_usdc: Usd::from(200.0),
+            valid_until: Utc::now() + Duration::seconds(30),
+            created_at: Utc::now(),
+            protection: None,
+        };
+        state.add_quote(quote.clone()).unwrap();
+        let input = || Json(AcceptQuote { request_id: request.id, quote_id: quote.id, signature: String::new() });
+
+        let err = accept_quote(State(state.clone()), None, input()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        let err = accept_quote(State(state.clone()), Some(authed("mm")), input()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        assert!(state.requests.contains_key(&request.id));
+
+        let Json(position) = accept_quote(State(state.clone()), Some(authed("trader")), input()).await.unwrap();
+        assert_eq!(position.data.unwrap().trader_agent, "trader");
+    }
+
+    #[tokio::test]
+    async fn test_add_margin_requires_the_positions_trader() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        state.prices.insert(Market::BtcPerp, 84000.0);
+        let position = open_position(&state, Side::Long);
+        let input = || Json(AddMargin { add_usdc: Usd::from(100.0) });
+        
+        let err = add_margin(State(state.clone()), Path(position.id), None, input()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        // body 中不再携带 agent_id，MM 无法冒充交易方
+        let err = add_margin(State(state.clone()), Path(position.id), Some(authed("mm")), input()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        assert_eq!(state.positions.get(&position.id).unwrap().trader_collateral, position.trader_collateral);
+        
+        let Json(info) = add_margin(State(state.clone()), Path(position.id), Some(authed("trader")), input()).await.unwrap();
+        assert!(info.success);
+        assert_eq!(
+            state.positions.get(&position.id).unwrap().trader_collateral,
+            position.trader_collateral + Usd::from(100.0)
+        );
+    }
+    
+    #[tokio::test]
+    async fn test_withdraw_rejected_after_acceptance() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let position = open_position(&state, Side::Long);
+        
+        let err = withdraw_quote(
+            State(state.clone()),
+            Path(position.quote_id),
+            Some(authed("mm")),
+        ).await.unwrap_err();
+        
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+        assert_eq!(err.code(), "ALREADY_ACCEPTED");
+    }
+    
+    #[tokio::test]
+    async fn test_funding_history_buckets_by_settlement_cadence() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        // 8 小时整数倍的时间点
+        let base = chrono::DateTime::from_timestamp(1_700_006_400, 0).unwrap();
+        let hours = |h: i64| base + Duration::hours(h);
+        for (h, rate) in [(-1, 0.009), (1, 0.001), (2, 0.003), (9, 0.002), (30, 0.004)] {
+            state.db.save_funding_rate(Market::BtcPerp, rate, hours(h)).unwrap();
+        }
+        state.db.save_funding_rate(Market::EthPerp, 0.05, hours(1)).unwrap();
+        
+        let query = |from, to, interval| {
+            get_funding_rate_history(
+                State(state.clone()),
+                Path(Market::BtcPerp),
+                Query(FundingHistoryParams { from: Some(from), to: Some(to), interval }),
+            )
+        };
+        let summary = |buckets: &[FundingRateBucket]| -> Vec<(i64, f64, u32)> {
+            buckets.iter()
+                .map(|b| ((b.start - base).num_hours(), (b.avg_rate * 1e6).round() / 1e6, b.samples))
+                .collect()
+        };
+        
+        let buckets = query(base, hours(32), None).await.unwrap().0.data.unwrap();
+        assert_eq!(summary(&buckets), vec![(0, 0.002, 2), (8, 0.002, 1), (24, 0.004, 1)]);
+        assert_eq!((buckets[0].min_rate, buckets[0].max_rate), (0.001, 0.003));
+        
+        // 12 小时向上取整为两个结算周期 (16 小时)
+        let buckets = query(base, hours(32), Some(12)).await.unwrap().0.data.unwrap();
+        assert_eq!(summary(&buckets), vec![(0, 0.002, 3), (16, 0.004, 1)]);
+        
+        assert!(query(hours(3), hours(3), None).await.unwrap().0.data.unwrap().is_empty());
+        assert!(query(hours(40), hours(48), None).await.unwrap().0.data.unwrap().is_empty());
+        let err = query(hours(8), base, None).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+    
+    fn admin_headers(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Admin-Key", key.parse().unwrap());
+        headers
+    }
+    
+    fn admin_state() -> Arc<AppState> {
+        let mut state = AppState::with_db_path(":memory:");
+        state.admin_key = Some("admin-secret".to_string());
+        state.register_agent(AgentInfo {
+            id: "trader".to_string(),
+            api_key: "th_trader_key".to_string(),
+            name: None,
+            is_mm: false,
+            created_at: Utc::now(),
+        });
+        Arc::new(state)
+    }
+    
+    #[tokio::test]
+    async fn test_admin_endpoints_reject_non_admin_keys() {
+        let state = admin_state();
+        let position = open_position(&state, Side::Long);
+        
+        // An agent's own API key is not an admin key
+        let err = force_settle_position(
+            State(state.clone()),
+            admin_headers("th_trader_key"),
+            Path(position.id),
+            Json(ForceSettlePosition { price: 90000.0 }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        
+        let err = cancel_position(State(state.clone()), HeaderMap::new(), Path(position.id))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        
+        // Disabled entirely without a configured key
+        let disabled = Arc::new(AppState::with_db_path(":memory:"));
+        let err = cancel_position(State(disabled), admin_headers("admin-secret"), Path(position.id))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        
+        assert_eq!(state.positions.get(&position.id).unwrap().status, crate::types::PositionStatus::Active);
+    }
+    
+    #[tokio::test]
+    async fn test_set_agent_limits_requires_admin_key() {
+        let state = admin_state();
+        let raise = |headers: HeaderMap| set_agent_limits(
+            State(state.clone()),
+            headers,
+            Path("trader".to_string()),
+            Json(SetRiskLimits {
+                max_position_size: None,
+                max_leverage: None,
+                max_total_exposure: None,
+                daily_loss_limit: None,
+                max_open_positions: Some(1000),
+            }),
+        );
+        
+        // The agent cannot raise its own limits with its API key
+        let err = raise(admin_headers("th_trader_key")).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        let err = raise(HeaderMap::new()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(state.get_agent_limits("trader").max_open_positions, RiskLimits::default().max_open_positions);
+        
+        let Json(resp) = raise(admin_headers("admin-secret")).await.unwrap();
+        assert_eq!(resp.data.unwrap().max_open_positions, 1000);
+        assert_eq!(state.get_agent_limits("trader").max_open_positions, 1000);
+    }
+    
+    #[tokio::test]
+    async fn test_admin_settle_closes_at_given_price() {
+        let state = admin_state();
+        let position = open_position(&state, Side::Long);
+        let mut rx = state.broadcast_tx.subscribe();
+        
+        // 10% above entry at 5x leverage
+        let price = position.entry_price.to_f64() * 1.1;
+        let Json(resp) = force_settle_position(
+            State(state.clone()),
+            admin_headers("admin-secret"),
+            Path(position.id),
+            Json(ForceSettlePosition { price }),
+        )
+        .await
+        .unwrap();
+        let data = resp.data.unwrap();
+        // 金额以字符串返回
+        let pnl_trader: Usd = serde_json::from_value(data["pnl_trader"].clone()).unwrap();
+        assert!((pnl_trader.to_f64() - 500.0).abs() < 1e-6);
+        assert_eq!(state.positions.get(&position.id).unwrap().status, crate::types::PositionStatus::Closed);
+        assert_eq!(state.open_interest(Market::BtcPerp).total(), Usd::ZERO);
+        
+        let trade = &state.get_trade_history("trader", 10, 0).unwrap().0[0];
+        assert_eq!(trade.exit_price, Usd::from(price));
+        
+        let settled = std::iter::from_fn(|| rx.try_recv().ok())
+            .find(|m| matches!(m, WsMessage::ForceSettled { .. }));
+        assert!(matches!(
+            settled,
+            Some(WsMessage::ForceSettled { price: Some(p), .. }) if p == price
+        ));
+        
+        // Already closed
+        let err = force_settle_position(
+            State(state.clone()),
+            admin_headers("admin-secret"),
+            Path(position.id),
+            Json(ForceSettlePosition { price }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+    }
+    
+    #[tokio::test]
+    async fn test_at_risk_positions_filter_by_distance() {
+        let state = admin_state();
+        // 5x 仓位的强平价距开仓价 2% (保证金 20%，亏到维持保证金 10% 时强平)
+        let long = open_position(&state, Side::Long);
+        let short = open_position_with_mm(&state, Side::Short, "mm-2");
+        let eth = open_position_in(&state, Market::EthPerp, Side::Long, "mm");
+        assert_eq!(long.liquidation_price, Usd::from(82320.0));
+        assert_eq!(short.liquidation_price, Usd::from(85680.0));
+        state.prices.insert(Market::BtcPerp, 82500.0);
+        
+        let query = |threshold: f64| {
+            get_at_risk_positions(State(state.clone()), admin_headers("admin-secret"), Query(AtRiskParams { threshold }))
+        };
+        let ids = |at_risk: &[AtRiskPosition]| at_risk.iter().map(|p| p.position.id).collect::<Vec<_>>();
+        
+        // 距离: BTC 多头约 0.22%，ETH 多头 2%，BTC 空头约 3.85%
+        let at_risk = query(0.5).await.unwrap().0.data.unwrap();
+        assert_eq!(ids(&at_risk), vec![long.id]);
+        assert!((at_risk[0].distance_pct - 180.0 / 82500.0 * 100.0).abs() < 1e-9);
+        assert_eq!(at_risk[0].current_price, 82500.0);
+        assert_eq!(ids(&query(3.0).await.unwrap().0.data.unwrap()), vec![long.id, eth.id]);
+        assert_eq!(ids(&query(5.0).await.unwrap().0.data.unwrap()), vec![long.id, eth.id, short.id]);
+        
+        assert!(query(0.1).await.unwrap().0.data.unwrap().is_empty());
+        assert_eq!(query(-1.0).await.unwrap_err().status(), StatusCode::BAD_REQUEST);
+        let err = get_at_risk_positions(State(state.clone()), HeaderMap::new(), Query(AtRiskParams { threshold: 1.0 }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+    }
+    
+    #[tokio::test]
+    async fn test_admin_cancel_unwinds_at_zero_pnl() {
+        let state = admin_state();
+        let position = open_position(&state, Side::Short);
+        
+        let Json(resp) = cancel_position(State(state.clone()), admin_headers("admin-secret"), Path(position.id))
+            .await
+            .unwrap();
+        assert_eq!(resp.data.unwrap()["status"], "cancelled");
+        
+        assert_eq!(state.positions.get(&position.id).unwrap().status, crate::types::PositionStatus::Closed);
+        let (closed, _) = state.get_closed_positions("trader", 10, 0).unwrap();
+        assert_eq!(closed[0].pnl_trader, Some(Usd::ZERO));
+    }
+    
+    #[tokio::test]
+    async fn test_close_is_queued_for_settlement() {
+        let state = admin_state();
+        let position = open_position_in(&state, Market::EthPerp, Side::Long, "mm");
+        state.prices.insert(Market::EthPerp, 3100.0);
+        
+        let _ = close_position(State(state.clone()), Some(authed("trader")), Json(ClosePosition {
+            position_id: position.id,
+            size_percent: 100,
+        }))
+        .await
+        .unwrap();
+        
+        let Json(resp) = get_pending_settlements(State(state.clone()), admin_headers("admin-secret"))
+            .await
+            .unwrap();
+        let jobs = resp.data.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].position_id, position.id);
+        assert_eq!(jobs[0].action, crate::types::SettlementAction::Close);
+        assert_eq!(jobs[0].size, 1_000_000);
+        // 按仓位所在市场的价格结算
+        assert_eq!(jobs[0].market, Market::EthPerp);
+        assert_eq!(jobs[0].price, 3100.0);
+        
+        let err = get_pending_settlements(State(state.clone()), admin_headers("th_trader_key"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+    }
+    
+    #[tokio::test]
+    async fn test_partial_close_settles_the_closed_size() {
+        let state = admin_state();
+        let position = open_position_in(&state, Market::EthPerp, Side::Short, "mm");
+        state.prices.insert(Market::EthPerp, 2900.0);
+        
+        let Json(resp) = close_position(State(state.clone()), Some(authed("trader")), Json(ClosePosition {
+            position_id: position.id,
+            size_percent: 40,
+        }))
+        .await
+        .unwrap();
+        assert_eq!(resp.data.unwrap()["status"], "partially_closed");
+        
+        let Json(resp) = get_pending_settlements(State(state.clone()), admin_headers("admin-secret"))
+            .await
+            .unwrap();
+        let jobs = resp.data.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].action, crate::types::SettlementAction::Reduce);
+        // 平掉 400 USDC 的空头 (合约单位，空头为负)
+        assert_eq!(jobs[0].size, -400_000);
+        assert_eq!(jobs[0].price, 2900.0);
+    }
+    
+    #[tokio::test]
+    async fn test_only_the_trader_can_close() {
+        let state = admin_state();
+        let position = open_position_in(&state, Market::BtcPerp, Side::Long, "mm");
+        let input = || Json(ClosePosition { position_id: position.id, size_percent: 100 });
+        
+        let err = close_position(State(state.clone()), None, input()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        for agent_id in ["mm", "someone-else"] {
+            let err = close_position(State(state.clone()), Some(authed(agent_id)), input()).await.unwrap_err();
+            assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        }
+        assert_eq!(state.positions.get(&position.id).unwrap().status, crate::types::PositionStatus::Active);
+        assert!(state.close_position(position.id, "mm", 100).is_err());
+        assert!(state.db.get_unsettled().unwrap().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_only_the_trader_can_modify_stop_levels() {
+        let state = admin_state();
+        let position = open_position_in(&state, Market::BtcPerp, Side::Long, "mm");
+        let input = || Json(ModifyPosition { position_id: position.id, stop_loss: Some(80000.0), take_profit: None });
+        
+        let err = modify_position(State(state.clone()), None, input()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        let err = modify_position(State(state.clone()), Some(authed("mm")), input()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        assert_eq!(state.positions.get(&position.id).unwrap().stop_loss, None);
+        
+        let Json(resp) = modify_position(State(state.clone()), Some(authed("trader")), input()).await.unwrap();
+        assert_eq!(resp.data.unwrap().stop_loss, Some(80000.0));
+    }
+    
+    #[tokio::test]
+    async fn test_insurance_fund_reports_balance_and_draws() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        let config = crate::margin::MarginConfig::default();
+        
+        // 未穿仓强平: 10 USDC 罚金流入保险基金
+        let topped_up = open_position_with_mm(&state, Side::Long, "mm-a");
+        crate::liquidation::settle_liquidation(&state, &topped_up, 84000.0 * 0.992, &config).unwrap();
+        let Json(body) = get_insurance_fund(State(state.clone())).await.unwrap();
+        let summary = body.data.unwrap();
+        assert_eq!(summary.balance, Usd::from(10.0));
+        assert!(summary.recent_draws.is_empty());
+        
+        // 穿仓 50 USDC: 基金只能覆盖 10，没有盈利方可分摊
+        let bankrupt = open_position_with_mm(&state, Side::Long, "mm-b");
+        let payout = crate::liquidation::settle_liquidation(&state, &bankrupt, 84000.0 * 0.95, &config).unwrap();
+        assert_eq!(payout.insurance_draw, Usd::from(10.0));
+        assert_eq!(payout.socialized_loss, Usd::ZERO);
+        
+        let Json(body) = get_insurance_fund(State(state.clone())).await.unwrap();
+        let summary = body.data.unwrap();
+        assert_eq!(summary.balance, Usd::ZERO);
+        assert_eq!(summary.recent_draws.len(), 1);
+        assert_eq!(summary.recent_draws[0].position_id, bankrupt.id);
+        assert_eq!(summary.recent_draws[0].amount, Usd::from(10.0));
+        assert_eq!(summary.recent_draws[0].balance_after, Usd::ZERO);
+        assert!(summary.recent_socialized_losses.is_empty());
+    }
+}
@@ -19,6 +19,7 @@ pub struct MmStats {
 }
 
 impl MmStats {
+    #[allow(dead_code)]
     pub fn fill_rate(&self) -> f64 {
         if self.total_quotes == 0 {
             0.0
@@ -41,9 +42,10 @@ pub async fn get_mm_leaderboard(state: Arc<AppState>) -> Vec<MmStats> {
                 ..Default::default()
             });
         
-        mm_stats.total_volume += pos.size_usdc;
+        let volume = pos.size_usdc.to_f64();
+        mm_stats.total_volume += volume;
         mm_stats.filled_quotes += 1;
-        mm_stats.total_points += pos.size_usdc / 1000.0 * 10.0;  // 10 points per $1k
+        mm_stats.total_points += volume / 1000.0 * 10.0;  // 10 points per $1k
     }
     
     // 统计报价数 (从 quotes)
@@ -2,14 +2,96 @@
 //!
 //! Runs as a background task, checking all active positions periodically.
 
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time::interval;
 use tracing::{info, warn};
 
+use crate::event_log::Event;
 use crate::margin::{should_liquidate, MarginConfig, PositionMarginInfo};
+use crate::money::Usd;
+use crate::settlement_queue;
 use crate::state::AppState;
-use crate::types::{PositionStatus, WsMessage};
+use crate::types::{MarginMode, Market, Position, PositionStatus, Side, SocializedLoss, StopTrigger, TradeRecord, WsMessage};
+
+/// How a liquidated position's collateral is split
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiquidationPayout {
+    /// Realized PnL of the trader (never worse than losing all collateral)
+    pub pnl_trader: Usd,
+    /// Realized PnL of the market maker
+    pub pnl_mm: Usd,
+    /// Liquidation penalty charged to the trader
+    pub fee: Usd,
+    /// Part of the penalty paid to the MM as liquidator
+    pub liquidator_reward: Usd,
+    /// Part of the penalty paid into the insurance fund
+    pub insurance_contribution: Usd,
+    /// Loss beyond the trader's collateral
+    pub shortfall: Usd,
+    /// Part of the shortfall covered by the insurance fund
+    pub insurance_draw: Usd,
+    /// Part of the shortfall charged to winning counterparties once the fund is exhausted
+    pub socialized_loss: Usd,
+}
+
+/// Split a liquidation at `current_price`.
+///
+/// While the trader's equity is positive, the MM receives the trader's full
+/// loss, the penalty (capped at the remaining equity) is split between the MM
+/// as liquidator and the insurance fund, and the rest is returned to the trader. Past bankruptcy, the trader loses
+/// all collateral and the insurance fund covers as much of the shortfall as
+/// its balance allows; the rest is left for [`socialize_loss`].
+pub fn liquidation_payout(
+    position: &Position,
+    current_price: f64,
+    config: &MarginConfig,
+    insurance_balance: Usd,
+) -> LiquidationPayout {
+    let pnl = crate::margin::unrealized_pnl(position, current_price);
+    let equity = position.trader_collateral + pnl;
+    
+    if !equity.is_negative() {
+        let fee = crate::margin::liquidation_penalty(position, config).min(equity);
+        let liquidator_reward = crate::margin::liquidator_reward(fee, config);
+        LiquidationPayout {
+            pnl_trader: pnl - fee,
+            pnl_mm: -pnl + liquidator_reward,
+            fee,
+            liquidator_reward,
+            insurance_contribution: crate::margin::insurance_contribution(fee, config),
+            shortfall: Usd::ZERO,
+            insurance_draw: Usd::ZERO,
+            socialized_loss: Usd::ZERO,
+        }
+    } else {
+        let shortfall = -equity;
+        let insurance_draw = shortfall.min(insurance_balance.max(Usd::ZERO));
+        LiquidationPayout {
+            pnl_trader: -position.trader_collateral,
+            pnl_mm: position.trader_collateral + insurance_draw,
+            fee: Usd::ZERO,
+            liquidator_reward: Usd::ZERO,
+            insurance_contribution: Usd::ZERO,
+            shortfall,
+            insurance_draw,
+            socialized_loss: Usd::ZERO,
+        }
+    }
+}
+
+/// Split `loss` across winners pro rata to their `capacities` (unrealized
+/// profit, capped at collateral). No winner pays more than its capacity, so
+/// anything beyond the combined capacity stays uncovered
+pub fn socialize_loss(loss: Usd, capacities: &[Usd]) -> Vec<Usd> {
+    let total: Usd = capacities.iter().map(|&c| c.max(Usd::ZERO)).sum();
+    if !loss.is_positive() || !total.is_positive() {
+        return vec![Usd::ZERO; capacities.len()];
+    }
+    let ratio = loss.ratio(total).unwrap_or_default().min(rust_decimal::Decimal::ONE);
+    capacities.iter().map(|&c| c.max(Usd::ZERO) * ratio).collect()
+}
 
 /// Liquidation engine configuration
 #[derive(Debug, Clone)]
@@ -20,14 +102,23 @@ pub struct LiquidationConfig {
     pub margin_config: MarginConfig,
     /// Whether to actually liquidate or just warn
     pub dry_run: bool,
+    /// Markets whose price is older than this are not checked
+    pub max_price_age: Duration,
+    /// Margin health (%) below which the trader gets a one-off margin call
+    pub margin_call_health: f64,
 }
 
+/// Default staleness threshold (the feed refreshes every 30s)
+pub const DEFAULT_MAX_PRICE_AGE: Duration = Duration::from_secs(120);
+
 impl Default for LiquidationConfig {
     fn default() -> Self {
         Self {
             check_interval_ms: 1000,  // Check every second
             margin_config: MarginConfig::default(),
             dry_run: false,
+            max_price_age: DEFAULT_MAX_PRICE_AGE,
+            margin_call_health: 25.0,
         }
     }
 }
@@ -39,95 +130,391 @@ pub struct LiquidationEvent {
     pub agent_id: String,
     pub market: String,
     pub side: String,
-    pub size_usdc: f64,
-    pub entry_price: f64,
-    pub liquidation_price: f64,
+    pub size_usdc: Usd,
+    pub entry_price: Usd,
+    pub liquidation_price: Usd,
     pub current_price: f64,
-    pub pnl: f64,
+    pub pnl: Usd,
 }
 
 /// Start the liquidation engine as a background task
 pub async fn start_liquidation_engine(state: Arc<AppState>, config: LiquidationConfig) {
-    info!("🔥 Liquidation engine starting (interval: {}ms, dry_run: {})", 
-          config.check_interval_ms, config.dry_run);
+    info!("🔥 Liquidation engine starting (interval: {}ms, dry_run: {}, max_price_age: {}s)", 
+          config.check_interval_ms, config.dry_run, config.max_price_age.as_secs());
     
     let mut ticker = interval(Duration::from_millis(config.check_interval_ms));
     
     loop {
-        ticker.tick().await;
-        
-        // Get all active positions
-        let positions: Vec<_> = state.positions.iter()
-            .filter(|p| p.status == PositionStatus::Active)
-            .map(|p| p.clone())
-            .collect();
-        
-        if positions.is_empty() {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        check_positions(&state, &config, Instant::now());
+    }
+    info!("🔥 Liquidation engine stopped");
+}
+
+/// Whether the feed has updated `market` within `max_age` of `now`.
+/// A market the feed has never updated is treated as stale.
+pub fn price_is_fresh(state: &AppState, market: Market, now: Instant, max_age: Duration) -> bool {
+    state.last_price_update.get(&market)
+        .is_some_and(|updated| now.saturating_duration_since(*updated) <= max_age)
+}
+
+/// Outcome of checking every trader's cross-margin account
+#[derive(Debug, Default)]
+struct CrossMarginCheck {
+    /// Pooled margin health per trader; traders with a stale market are missing
+    health: HashMap<String, f64>,
+    /// Cross positions chosen for liquidation
+    liquidate: HashSet<uuid::Uuid>,
+}
+
+/// Evaluate each trader's cross positions against their pooled collateral.
+/// An account holding any position in a stale market is not evaluated
+fn check_cross_accounts(
+    state: &AppState,
+    positions: &[Position],
+    stale_markets: &HashSet<Market>,
+    config: &MarginConfig,
+) -> CrossMarginCheck {
+    let mut accounts: HashMap<&str, Vec<(Position, f64)>> = HashMap::new();
+    for position in positions.iter().filter(|p| p.margin_mode == MarginMode::Cross) {
+        let current_price = state.smoothed_price(position.market).unwrap_or(position.entry_price.to_f64());
+        accounts.entry(position.trader_agent.as_str()).or_default().push((position.clone(), current_price));
+    }
+    
+    let mut check = CrossMarginCheck::default();
+    for (trader, account) in accounts {
+        if account.iter().any(|(p, _)| stale_markets.contains(&p.market)) {
+            continue;
+        }
+        let summary = crate::margin::cross_account_summary(&account, config);
+        check.health.insert(trader.to_string(), summary.margin_health);
+        check.liquidate.extend(crate::margin::cross_margin_liquidations(&account, config));
+    }
+    check
+}
+
+/// Run one pass over all active positions: liquidate the underwater ones and
+/// fire crossed stop orders. Markets with a stale price are skipped entirely,
+/// as are cross-margin accounts holding a position in one.
+pub fn check_positions(state: &AppState, config: &LiquidationConfig, now: Instant) -> Vec<LiquidationEvent> {
+    let mut events = Vec::new();
+    
+    // Get all active positions
+    let positions: Vec<_> = state.positions.iter()
+        .filter(|p| p.status == PositionStatus::Active)
+        .map(|p| p.clone())
+        .collect();
+    
+    if positions.is_empty() {
+        return events;
+    }
+    
+    let mut stale_markets = HashSet::new();
+    for position in &positions {
+        if !stale_markets.contains(&position.market)
+            && !price_is_fresh(state, position.market, now, config.max_price_age)
+        {
+            warn!("⏸️ Price for {} is stale, skipping liquidation checks", position.market.symbol());
+            stale_markets.insert(position.market);
+        }
+    }
+    let cross = check_cross_accounts(state, &positions, &stale_markets, &config.margin_config);
+    
+    // Check each position
+    for position in positions {
+        if stale_markets.contains(&position.market) {
             continue;
         }
+        let current_price = state.smoothed_price(position.market)
+            .unwrap_or(position.entry_price.to_f64());
         
-        // Check each position
-        for position in positions {
-            let current_price = state.prices.get(&position.market)
-                .map(|p| *p)
-                .unwrap_or(position.entry_price);
+        let (underwater, health) = match position.margin_mode {
+            MarginMode::Isolated => (
+                should_liquidate(&position, current_price, &config.margin_config),
+                crate::margin::margin_health(&position, current_price, &config.margin_config),
+            ),
+            MarginMode::Cross => match cross.health.get(&position.trader_agent) {
+                Some(&health) => (cross.liquidate.contains(&position.id), health),
+                None => continue,
+            },
+        };
+        
+        let flagged = state.liquidation_flags.remove(&position.id).is_some();
+        if flagged || underwater {
+            let event = LiquidationEvent {
+                position_id: position.id.to_string(),
+                agent_id: position.trader_agent.clone(),
+                market: format!("{:?}", position.market),
+                side: format!("{:?}", position.side),
+                size_usdc: position.size_usdc,
+                entry_price: position.entry_price,
+                liquidation_price: crate::margin::liquidation_price(&position, &config.margin_config),
+                current_price,
+                pnl: crate::margin::unrealized_pnl(&position, current_price),
+            };
+            
+            warn!("🔥 LIQUIDATION: {} {} {} @ ${:.2} (entry: ${:.2}, liq: ${:.2})",
+                  event.agent_id, event.market, event.side,
+                  current_price, event.entry_price, event.liquidation_price);
             
-            if should_liquidate(&position, current_price, &config.margin_config) {
-                let event = LiquidationEvent {
-                    position_id: position.id.to_string(),
-                    agent_id: position.trader_agent.clone(),
-                    market: format!("{:?}", position.market),
-                    side: format!("{:?}", position.side),
-                    size_usdc: position.size_usdc,
-                    entry_price: position.entry_price,
-                    liquidation_price: crate::margin::liquidation_price(&position, &config.margin_config),
-                    current_price,
-                    pnl: crate::margin::unrealized_pnl(&position, current_price),
-                };
-                
-                warn!("🔥 LIQUIDATION: {} {} {} @ ${:.2} (entry: ${:.2}, liq: ${:.2})",
-                      event.agent_id, event.market, event.side,
-                      current_price, event.entry_price, event.liquidation_price);
-                
-                if !config.dry_run {
-                    // Execute liquidation
-                    if let Err(e) = execute_liquidation(&state, &position, current_price).await {
-                        warn!("Liquidation failed for {}: {}", position.id, e);
-                    }
+            if !config.dry_run {
+                // Execute liquidation
+                if let Err(e) = execute_liquidation(state, &position, current_price, &config.margin_config) {
+                    warn!("Liquidation failed for {}: {}", position.id, e);
                 }
-                
-                // Broadcast liquidation event
-                let _ = state.broadcast_tx.send(WsMessage::Liquidation(event.clone()));
             }
+            
+            // Broadcast liquidation event
+            let _ = state.broadcast_tx.send(WsMessage::Liquidation(event.clone()));
+            events.push(event);
+            continue;
+        }
+        
+        margin_call(state, &position, current_price, health, config);
+        
+        if !config.dry_run {
+            trigger_stop_orders(state, &position, current_price);
+        }
+    }
+    
+    events
+}
+
+/// Warn the trader once when margin health (the account's, for cross margin)
+/// drops below the margin-call line; the warning re-arms after it recovers
+fn margin_call(state: &AppState, position: &Position, current_price: f64, health: f64, config: &LiquidationConfig) {
+    if health >= config.margin_call_health {
+        state.margin_calls.remove(&position.id);
+        return;
+    }
+    if !state.margin_calls.insert(position.id) {
+        return;
+    }
+    
+    info!("📣 Margin call for {} (health: {:.1}%)", position.id, health);
+    state.notify_agent(&position.trader_agent, WsMessage::MarginCall {
+        position_id: position.id,
+        margin_health: health,
+        liquidation_price: crate::margin::liquidation_price(position, &config.margin_config),
+        current_price,
+    });
+}
+
+/// Which protective level (if any) the current price has crossed.
+/// Long: stop-loss below, take-profit above. Short: the reverse.
+pub fn stop_trigger(position: &Position, current_price: f64) -> Option<(StopTrigger, f64)> {
+    let dir = direction(position.side);
+    
+    if let Some(sl) = position.stop_loss.filter(|&sl| dir * (current_price - sl) <= 0.0) {
+        return Some((StopTrigger::StopLoss, sl));
+    }
+    if let Some(tp) = position.take_profit.filter(|&tp| dir * (current_price - tp) >= 0.0) {
+        return Some((StopTrigger::TakeProfit, tp));
+    }
+    None
+}
+
+fn direction(side: Side) -> f64 {
+    match side {
+        Side::Long => 1.0,
+        Side::Short => -1.0,
+    }
+}
+
+/// Check stop-loss / take-profit levels are on the correct side of the reference price
+pub fn validate_stop_levels(
+    side: Side,
+    stop_loss: Option<f64>,
+    take_profit: Option<f64>,
+    reference_price: f64,
+) -> Result<(), String> {
+    for level in [stop_loss, take_profit].into_iter().flatten() {
+        if !(level > 0.0 && level.is_finite()) {
+            return Err("Stop levels must be positive".to_string());
+        }
+    }
+    if reference_price <= 0.0 {
+        return Ok(());
+    }
+    
+    let dir = direction(side);
+    let sl_ok = stop_loss.is_none_or(|sl| dir * (reference_price - sl) > 0.0);
+    let tp_ok = take_profit.is_none_or(|tp| dir * (tp - reference_price) > 0.0);
+    
+    if !sl_ok {
+        return Err(format!("Stop-loss is on the wrong side of the current price {}", reference_price));
+    }
+    if !tp_ok {
+        return Err(format!("Take-profit is on the wrong side of the current price {}", reference_price));
+    }
+    Ok(())
+}
+
+/// Close a position whose stop-loss or take-profit has been crossed
+pub fn trigger_stop_orders(state: &AppState, position: &Position, current_price: f64) -> Option<StopTrigger> {
+    let (trigger, trigger_price) = stop_trigger(position, current_price)?;
+    
+    info!("🎯 {:?} triggered for {} @ ${:.2} (level: ${:.2})",
+          trigger, position.id, current_price, trigger_price);
+    
+    // Realize PnL at the price that fired the trigger, the same price settled on-chain
+    match state.close_position_at(position.id, 100, current_price) {
+        Ok((pnl_trader, _)) => {
+            state.record(Event::PositionClosed { position_id: position.id, size_percent: 100, exit_price: current_price });
+            settlement_queue::enqueue_close(state, position, position.size_usdc, current_price);
+            let _ = state.broadcast_tx.send(WsMessage::StopTriggered {
+                position_id: position.id,
+                trigger,
+                trigger_price,
+                current_price,
+                pnl_trader,
+            });
+            Some(trigger)
+        }
+        Err(e) => {
+            warn!("Stop order close failed for {}: {}", position.id, e);
+            None
         }
     }
 }
 
 /// Execute a liquidation
-async fn execute_liquidation(
+fn execute_liquidation(
     state: &AppState, 
-    position: &crate::types::Position,
-    _current_price: f64,
-) -> Result<(), String> {
+    position: &Position,
+    current_price: f64,
+    config: &MarginConfig,
+) -> Result<LiquidationPayout, String> {
+    let payout = settle_liquidation(state, position, current_price, config)?;
+    state.record(Event::Liquidated { position_id: position.id, price: current_price });
+    state.metrics.record_liquidation(position.market);
+    
+    // On-chain settlement is queued and retried by the settlement worker
+    settlement_queue::enqueue_close(state, position, position.size_usdc, current_price);
+    
+    info!("✅ Liquidated position {} (fee: {:.2}, liquidator reward: {:.2}, insurance draw: {:.2})",
+          position.id, payout.fee, payout.liquidator_reward, payout.insurance_draw);
+    Ok(payout)
+}
+
+/// Mark a position liquidated, settle it against the insurance fund and record the
+/// close. Does not touch the chain, so event replay can reuse it
+pub(crate) fn settle_liquidation(
+    state: &AppState,
+    position: &Position,
+    current_price: f64,
+    config: &MarginConfig,
+) -> Result<LiquidationPayout, String> {
     // Mark position as liquidated
+    let mut liquidated = position.clone();
     if let Some(mut pos) = state.positions.get_mut(&position.id) {
+        if pos.status != PositionStatus::Active {
+            return Err("Position is not active".to_string());
+        }
         pos.status = PositionStatus::Liquidated;
         pos.closed_at = Some(chrono::Utc::now());
+        liquidated = pos.clone();
+        state.adjust_open_interest(pos.market, pos.side, -pos.size_usdc);
+    }
+    state.margin_calls.remove(&position.id);
+    state.notify_position(&liquidated);
+    
+    // Settle against the insurance fund
+    let mut payout = {
+        let mut fund = state.insurance_fund.lock().unwrap();
+        let payout = liquidation_payout(position, current_price, config, *fund);
+        if payout.insurance_contribution.is_positive() {
+            *fund += payout.insurance_contribution;
+            if let Err(e) = state.db.record_insurance_event(&position.id, "fee", payout.insurance_contribution, *fund) {
+                warn!("Failed to record insurance fee for {}: {}", position.id, e);
+            }
+        }
+        if payout.insurance_draw.is_positive() {
+            *fund -= payout.insurance_draw;
+            if let Err(e) = state.db.record_insurance_event(&position.id, "draw", payout.insurance_draw, *fund) {
+                warn!("Failed to record insurance draw for {}: {}", position.id, e);
+            }
+        }
+        payout
+    };
+    
+    let uncovered = payout.shortfall - payout.insurance_draw;
+    if uncovered.is_positive() {
+        payout.socialized_loss = socialize_shortfall(state, position, current_price, uncovered);
+        payout.pnl_mm += payout.socialized_loss;
+        warn!("⚠️ Insurance fund exhausted: {:.2} USDC of bad debt on {}, {:.2} socialized, rest absorbed by MM",
+              uncovered, position.id, payout.socialized_loss);
     }
     
     // Update database
-    if let Err(e) = state.db.close_position(&position.id, 
-        -position.trader_collateral,  // Trader loses collateral
-        position.trader_collateral * 0.99,  // MM gets most (minus fee)
-    ) {
+    if let Err(e) = state.db.close_position(&position.id, payout.pnl_trader, payout.pnl_mm) {
+        return Err(format!("DB error: {}", e));
+    }
+    let trade = TradeRecord::from_closed_position(&liquidated, Usd::from(current_price), payout.pnl_trader, payout.pnl_mm);
+    if let Err(e) = state.db.save_trade(&trade) {
         return Err(format!("DB error: {}", e));
     }
+    Ok(payout)
+}
+
+/// Charge `loss` to the winning side of every other active position in the
+/// market: each winner's collateral is debited by its share and the share is
+/// recorded. Returns the amount recovered
+fn socialize_shortfall(state: &AppState, position: &Position, current_price: f64, loss: Usd) -> Usd {
+    // (position, winning agent, winner is the trader, capacity)
+    let winners: Vec<(uuid::Uuid, String, bool, Usd)> = state.positions.iter()
+        .filter(|p| p.id != position.id && p.market == position.market && p.status == PositionStatus::Active)
+        .filter_map(|p| {
+            let pnl = crate::margin::unrealized_pnl(p.value(), current_price);
+            if pnl.is_positive() {
+                Some((p.id, p.trader_agent.clone(), true, pnl.min(p.trader_collateral)))
+            } else if pnl.is_negative() {
+                Some((p.id, p.mm_agent.clone(), false, (-pnl).min(p.mm_collateral)))
+            } else {
+                None
+            }
+        })
+        .collect();
+    let capacities: Vec<Usd> = winners.iter().map(|w| w.3).collect();
     
-    info!("✅ Liquidated position {}", position.id);
-    Ok(())
+    let mut recovered = Usd::ZERO;
+    for ((id, agent_id, is_trader, _), share) in winners.into_iter().zip(socialize_loss(loss, &capacities)) {
+        if !share.is_positive() {
+            continue;
+        }
+        let Some(mut winner) = state.positions.get_mut(&id) else { continue };
+        if is_trader {
+            winner.trader_collateral -= share;
+            winner.refresh_liquidation_price();
+        } else {
+            winner.mm_collateral -= share;
+        }
+        if let Err(e) = state.db.save_position(&winner) {
+            warn!("Failed to save position {}: {}", id, e);
+        }
+        state.notify_position(&winner);
+        drop(winner);
+        
+        let record = SocializedLoss {
+            position_id: position.id,
+            winner_position_id: id,
+            agent_id,
+            amount: share,
+            created_at: chrono::Utc::now(),
+        };
+        if let Err(e) = state.db.record_socialized_loss(&record) {
+            warn!("Failed to record socialized loss on {}: {}", id, e);
+        }
+        recovered += share;
+    }
+    recovered
 }
 
 /// Check if a specific position should be liquidated (for API use)
+#[allow(dead_code)]
 pub fn check_position(
     state: &AppState,
     position_id: &str,
@@ -136,9 +523,275 @@ pub fn check_position(
     let uuid = uuid::Uuid::parse_str(position_id).ok()?;
     let position = state.positions.get(&uuid)?;
     
-    let current_price = state.prices.get(&position.market)
-        .map(|p| *p)
-        .unwrap_or(position.entry_price);
+    let current_price = state.smoothed_price(position.market)
+        .unwrap_or(position.entry_price.to_f64());
     
     Some(PositionMarginInfo::from_position(&position, current_price, config))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::TestTrade;
+    use crate::types::{MarginMode, Market};
+    use uuid::Uuid;
+    
+    fn open_long(state: &AppState, stop_loss: Option<f64>, take_profit: Option<f64>) -> Position {
+        TestTrade { stop_loss, take_profit, ..Default::default() }.open(state)
+    }
+    
+    fn open_with_mode(state: &AppState, trader: &str, market: Market, side: Side, margin_mode: MarginMode) -> Position {
+        TestTrade { trader, market, side, margin_mode, ..Default::default() }.open(state)
+    }
+    
+    #[tokio::test]
+    async fn test_cross_margin_winner_saves_loser() {
+        let state = AppState::with_db_path(":memory:");
+        let config = LiquidationConfig::default();
+        let mut positions = Vec::new();
+        for (trader, mode) in [("isolated", MarginMode::Isolated), ("cross", MarginMode::Cross)] {
+            let loser = open_with_mode(&state, trader, Market::BtcPerp, Side::Long, mode);
+            let winner = open_with_mode(&state, trader, Market::EthPerp, Side::Short, mode);
+            positions.push((loser.id, winner.id));
+        }
+        let [(iso_loser, iso_winner), (cross_loser, cross_winner)] = positions[..] else { unreachable!() };
+        let status = |id| state.positions.get(&id).unwrap().status;
+        let check_at = |btc: f64, eth: f64| {
+            for (market, price) in [(Market::BtcPerp, btc), (Market::EthPerp, eth)] {
+                state.prices.insert(market, price);
+                state.last_price_update.insert(market, Instant::now());
+            }
+            check_positions(&state, &config, Instant::now())
+        };
+        
+        // BTC long: -2.5% at 5x leaves 75 equity against 100 maintenance.
+        // ETH short: +2% at 5x brings it to 300, so the cross account holds 375 against 200
+        let events = check_at(84000.0 * 0.975, 2200.0 * 0.98);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].position_id, iso_loser.to_string());
+        assert_eq!(status(iso_loser), PositionStatus::Liquidated);
+        assert_eq!(status(iso_winner), PositionStatus::Active);
+        assert_eq!(status(cross_loser), PositionStatus::Active);
+        assert_eq!(status(cross_winner), PositionStatus::Active);
+        
+        // ETH turns against the short too: 75 + 50 < 200, and neither position alone recovers it
+        check_at(84000.0 * 0.975, 2200.0 * 1.03);
+        assert_eq!(status(cross_loser), PositionStatus::Liquidated);
+        assert_eq!(status(cross_winner), PositionStatus::Liquidated);
+    }
+    
+    #[test]
+    fn test_long_stop_loss_fires_on_drop() {
+        let state = AppState::with_db_path(":memory:");
+        let position = open_long(&state, Some(83500.0), Some(86000.0));
+        let mut rx = state.broadcast_tx.subscribe();
+        
+        assert_eq!(trigger_stop_orders(&state, &position, 83800.0), None);
+        assert_eq!(trigger_stop_orders(&state, &position, 83400.0), Some(StopTrigger::StopLoss));
+        assert_eq!(state.positions.get(&position.id).unwrap().status, PositionStatus::Closed);
+        
+        let mut saw_stop = false;
+        while let Ok(msg) = rx.try_recv() {
+            if let WsMessage::StopTriggered { trigger, trigger_price, .. } = msg {
+                assert_eq!(trigger, StopTrigger::StopLoss);
+                assert_eq!(trigger_price, 83500.0);
+                saw_stop = true;
+            }
+        }
+        assert!(saw_stop);
+    }
+    
+    #[test]
+    fn test_long_take_profit_fires_on_rise() {
+        let state = AppState::with_db_path(":memory:");
+        let position = open_long(&state, Some(83500.0), Some(86000.0));
+        
+        // The raw price has moved past the smoothed price the trigger fired on
+        state.prices.insert(Market::BtcPerp, 86400.0);
+        assert_eq!(trigger_stop_orders(&state, &position, 86100.0), Some(StopTrigger::TakeProfit));
+        assert_eq!(state.positions.get(&position.id).unwrap().status, PositionStatus::Closed);
+        let trade = &state.get_trade_history("trader", 10, 0).unwrap().0[0];
+        assert_eq!(trade.exit_price, Usd::from(86100.0));
+        
+        // The close is queued for on-chain settlement like a manual close
+        let jobs = state.db.get_unsettled().unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].position_id, position.id);
+        assert_eq!(jobs[0].action, crate::types::SettlementAction::Close);
+        assert_eq!(jobs[0].price, 86100.0);
+    }
+    
+    #[tokio::test]
+    async fn test_liquidation_pays_fee_into_insurance_fund() {
+        let log_path = std::env::temp_dir()
+            .join(format!("trade-router-liquidation-{}.jsonl", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        let mut state = AppState::with_db_path(":memory:");
+        state.event_log = Arc::new(crate::event_log::EventLog::open(&log_path).unwrap());
+        let config = MarginConfig::default();
+        // 1000 USDC at 5x, 200 collateral: -0.8% move = -40 PnL
+        let position = open_long(&state, None, None);
+        let price = 84000.0 * 0.992;
+        
+        let payout = execute_liquidation(&state, &position, price, &config).unwrap();
+        assert_eq!(payout.pnl_mm, Usd::from(40.0));
+        assert_eq!(payout.fee, Usd::from(10.0));
+        assert_eq!(payout.pnl_trader, Usd::from(-50.0));
+        assert_eq!(payout.insurance_draw, Usd::ZERO);
+        assert_eq!(*state.insurance_fund.lock().unwrap(), Usd::from(10.0));
+        assert_eq!(state.positions.get(&position.id).unwrap().status, PositionStatus::Liquidated);
+        
+        // The balance survives a restart via the recorded fund events
+        assert_eq!(state.db.get_insurance_fund_balance().unwrap(), Usd::from(10.0));
+        
+        // A second liquidation of the same position is rejected, and not logged for replay
+        assert!(execute_liquidation(&state, &position, price, &config).is_err());
+        let records = crate::event_log::EventLog::read(&log_path).unwrap();
+        let liquidations = records.iter().filter(|r| matches!(r.event, Event::Liquidated { .. })).count();
+        assert_eq!(liquidations, 1);
+        std::fs::remove_file(&log_path).ok();
+    }
+    
+    #[tokio::test]
+    async fn test_liquidation_splits_penalty_with_liquidator() {
+        let state = AppState::with_db_path(":memory:");
+        let config = MarginConfig { liquidator_reward_share: 0.4, ..MarginConfig::default() };
+        let position = open_long(&state, None, None);
+        
+        let payout = execute_liquidation(&state, &position, 84000.0 * 0.992, &config).unwrap();
+        // 10 USDC penalty: 4 to the MM, 6 to the insurance fund
+        assert_eq!(payout.fee, Usd::from(10.0));
+        assert_eq!(payout.liquidator_reward, Usd::from(4.0));
+        assert_eq!(payout.insurance_contribution, Usd::from(6.0));
+        assert_eq!(payout.pnl_mm, Usd::from(44.0));
+        assert_eq!(payout.pnl_trader, Usd::from(-50.0));
+        assert_eq!(*state.insurance_fund.lock().unwrap(), Usd::from(6.0));
+        // Nothing is created or lost: trader's loss = MM gain + fund inflow
+        assert_eq!(payout.pnl_trader + payout.pnl_mm + payout.insurance_contribution, Usd::ZERO);
+    }
+    
+    #[tokio::test]
+    async fn test_bankrupt_liquidation_draws_insurance_fund() {
+        let state = AppState::with_db_path(":memory:");
+        let config = MarginConfig::default();
+        *state.insurance_fund.lock().unwrap() = Usd::from(30.0);
+        
+        // -5% at 5x on 1000 USDC = -250 PnL against 200 collateral: 50 shortfall
+        let position = open_long(&state, None, None);
+        let payout = execute_liquidation(&state, &position, 84000.0 * 0.95, &config).unwrap();
+        
+        assert_eq!(payout.fee, Usd::ZERO);
+        assert_eq!(payout.shortfall, Usd::from(50.0));
+        assert_eq!(payout.insurance_draw, Usd::from(30.0));
+        assert_eq!(payout.pnl_trader, Usd::from(-200.0));
+        assert_eq!(payout.pnl_mm, Usd::from(230.0));
+        assert_eq!(*state.insurance_fund.lock().unwrap(), Usd::ZERO);
+    }
+    
+    #[tokio::test]
+    async fn test_exhausted_fund_socializes_loss_across_winners() {
+        let state = AppState::with_db_path(":memory:");
+        let config = MarginConfig::default();
+        *state.insurance_fund.lock().unwrap() = Usd::from(30.0);
+        
+        let loser = open_with_mode(&state, "loser", Market::BtcPerp, Side::Long, MarginMode::Isolated);
+        let winners = [
+            open_with_mode(&state, "short-a", Market::BtcPerp, Side::Short, MarginMode::Isolated),
+            open_with_mode(&state, "short-b", Market::BtcPerp, Side::Short, MarginMode::Isolated),
+        ];
+        // Other markets are not charged
+        let bystander = open_with_mode(&state, "short-c", Market::EthPerp, Side::Short, MarginMode::Isolated);
+        
+        // 50 shortfall: 30 from the fund, the other 20 split across both shorts
+        let payout = execute_liquidation(&state, &loser, 84000.0 * 0.95, &config).unwrap();
+        assert_eq!(payout.insurance_draw, Usd::from(30.0));
+        assert_eq!(payout.socialized_loss, Usd::from(20.0));
+        assert_eq!(payout.pnl_mm, Usd::from(250.0));
+        for winner in &winners {
+            let collateral = state.positions.get(&winner.id).unwrap().trader_collateral;
+            assert_eq!(collateral, winner.trader_collateral - Usd::from(10.0));
+        }
+        assert_eq!(state.positions.get(&bystander.id).unwrap().trader_collateral, bystander.trader_collateral);
+        
+        let losses = state.db.get_socialized_losses(10).unwrap();
+        assert_eq!(losses.len(), 2);
+        assert!(losses.iter().all(|l| l.position_id == loser.id && l.amount == Usd::from(10.0)));
+    }
+    
+    #[test]
+    fn test_socialized_loss_capped_at_winner_capacity() {
+        let usd = |amounts: &[f64]| amounts.iter().map(|&a| Usd::from(a)).collect::<Vec<_>>();
+        assert_eq!(socialize_loss(Usd::from(40.0), &usd(&[100.0, 300.0])), usd(&[10.0, 30.0]));
+        
+        // More loss than the winners can cover: each pays its full capacity
+        assert_eq!(socialize_loss(Usd::from(500.0), &usd(&[100.0, 300.0, -5.0])), usd(&[100.0, 300.0, 0.0]));
+        assert!(socialize_loss(Usd::from(50.0), &[]).is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_stale_price_skips_liquidation_check() {
+        let state = AppState::with_db_path(":memory:");
+        let config = LiquidationConfig::default();
+        let position = open_long(&state, None, None);
+        
+        // Underwater price, last updated now
+        let updated = Instant::now();
+        state.prices.insert(Market::BtcPerp, 84000.0 * 0.95);
+        state.last_price_update.insert(Market::BtcPerp, updated);
+        
+        let later = updated + config.max_price_age + std::time::Duration::from_secs(1);
+        assert!(!price_is_fresh(&state, Market::BtcPerp, later, config.max_price_age));
+        assert!(check_positions(&state, &config, later).is_empty());
+        assert_eq!(state.positions.get(&position.id).unwrap().status, PositionStatus::Active);
+        
+        let events = check_positions(&state, &config, updated);
+        assert_eq!(events.len(), 1);
+        assert_eq!(state.positions.get(&position.id).unwrap().status, PositionStatus::Liquidated);
+    }
+    
+    #[test]
+    fn test_margin_call_sent_once_until_recovery() {
+        let state = AppState::with_db_path(":memory:");
+        let config = LiquidationConfig::default();
+        let position = open_long(&state, None, None);
+        let mut rx = state.private_tx.subscribe();
+        let margin_calls = |rx: &mut tokio::sync::broadcast::Receiver<crate::types::PrivateMessage>| {
+            std::iter::from_fn(|| rx.try_recv().ok())
+                .filter(|p| matches!(p.message, WsMessage::MarginCall { .. }))
+                .map(|p| p.agent_id)
+                .collect::<Vec<_>>()
+        };
+        let check_at = |price: f64| {
+            state.prices.insert(Market::BtcPerp, price);
+            state.last_price_update.insert(Market::BtcPerp, Instant::now());
+            check_positions(&state, &config, Instant::now())
+        };
+        
+        // 1000 USDC at 5x: -1.8% leaves ~11% health, above the liquidation line
+        assert!(check_at(82500.0).is_empty());
+        assert!(check_at(82500.0).is_empty());
+        assert_eq!(margin_calls(&mut rx), vec!["trader".to_string()]);
+        assert_eq!(state.positions.get(&position.id).unwrap().status, PositionStatus::Active);
+        
+        // Recovering re-arms the warning
+        check_at(84000.0);
+        check_at(82500.0);
+        assert_eq!(margin_calls(&mut rx).len(), 1);
+    }
+    
+    #[test]
+    fn test_short_stop_directions() {
+        let mut position = open_long(&AppState::with_db_path(":memory:"), None, None);
+        position.side = Side::Short;
+        position.stop_loss = Some(86000.0);
+        position.take_profit = Some(82000.0);
+        
+        assert_eq!(stop_trigger(&position, 85000.0), None);
+        assert_eq!(stop_trigger(&position, 86000.0).map(|t| t.0), Some(StopTrigger::StopLoss));
+        assert_eq!(stop_trigger(&position, 81900.0).map(|t| t.0), Some(StopTrigger::TakeProfit));
+        
+        assert!(validate_stop_levels(Side::Short, Some(86000.0), Some(82000.0), 84000.0).is_ok());
+        assert!(validate_stop_levels(Side::Long, Some(86000.0), None, 84000.0).is_err());
+    }
+}
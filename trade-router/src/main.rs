@@ -1,13 +1,20 @@
+mod cleanup;
 mod db;
 mod funding;
 mod handlers;
 mod liquidation;
 mod price_feed;
 mod demo_mm;
+mod error;
+mod event_log;
 mod incentives;
 mod margin;
+mod mark_price;
+mod metrics;
 mod middleware;
+mod money;
 mod settlement;
+mod settlement_queue;
 mod state;
 mod types;
 mod websocket;
@@ -23,7 +30,7 @@ use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::state::AppState;
-use crate::middleware::{auth_middleware, rate_limit_middleware, RateLimiter};
+use crate::middleware::{auth_middleware, rate_limit_middleware, RateLimiters};
 
 #[tokio::main]
 async fn main() {
@@ -33,52 +40,166 @@ async fn main() {
         .init();
 
     // 创建共享状态
-    let state = Arc::new(AppState::new());
+    let mut state = AppState::new();
+    
+    // 标记价格 EMA 半衰期 (默认 60 秒)
+    if let Some(secs) = std::env::var("MARK_PRICE_HALF_LIFE_SECS").ok().and_then(|s| s.parse().ok()) {
+        state.smoothed_prices = Arc::new(mark_price::MarkPrice::new(std::time::Duration::from_secs(secs)));
+    }
+    
+    // 结算服务地址 (默认 http://localhost:8081)
+    if let Ok(url) = std::env::var("SETTLEMENT_URL") {
+        state.settlement = settlement::SettlementClient::with_url(&url);
+    }
+    
+    // 事件日志 (默认 data/events.jsonl)。数据库中没有任何仓位时先重放日志重建状态，
+    // 重放完成后再挂载日志，避免重放的事件被重复写入
+    let event_log_path = std::env::var("EVENT_LOG_PATH")
+        .unwrap_or_else(|_| "data/events.jsonl".to_string());
+    if state.positions.is_empty() && state.db.position_count().unwrap_or(0) == 0 {
+        match event_log::EventLog::read(&event_log_path) {
+            Ok(records) if !records.is_empty() => {
+                let total = records.len();
+                let applied = event_log::replay_into(&state, records);
+                info!("📜 Replayed {}/{} events from {}", applied, total, event_log_path);
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("Failed to read event log {}: {}", event_log_path, e),
+        }
+    }
+    state.event_log = Arc::new(
+        event_log::EventLog::open(&event_log_path).expect("Failed to open event log"),
+    );
+    
+    // 运维接口的 API key，未设置时运维接口不可用
+    state.admin_key = std::env::var("ADMIN_API_KEY").ok().filter(|k| !k.is_empty());
+    let state = Arc::new(state);
+
+    // 后台任务，关闭时等待它们退出
+    let mut tasks = Vec::new();
 
     // 启动价格更新 (每30秒)
     let price_state = state.clone();
-    tokio::spawn(async move {
+    tasks.push(tokio::spawn(async move {
         price_feed::start_price_feed(price_state, 30).await;
-    });
+    }));
 
     // 启动强平引擎 (后台任务)
     let liq_state = state.clone();
-    tokio::spawn(async move {
+    tasks.push(tokio::spawn(async move {
         liquidation::start_liquidation_engine(
             liq_state,
             liquidation::LiquidationConfig::default(),
         ).await;
-    });
+    }));
 
-    // 启动 Funding 结算引擎 (每8小时)
+    // 启动 Funding 结算引擎 (各市场按自身周期结算，默认每8小时)
     let funding_state = state.clone();
-    tokio::spawn(async move {
+    tasks.push(tokio::spawn(async move {
         funding::start_funding_engine(
             funding_state,
-            funding::FundingConfig::default(),
+            funding::FundingConfig::from_env(),
         ).await;
-    });
+    }));
+
+    // 启动过期请求/报价清理 (每10秒)
+    let cleanup_state = state.clone();
+    tasks.push(tokio::spawn(async move {
+        cleanup::start_cleanup_task(cleanup_state, std::time::Duration::from_secs(10)).await;
+    }));
+
+    // 启动链上结算重试队列
+    let settlement_state = state.clone();
+    tasks.push(tokio::spawn(async move {
+        settlement_queue::start_settlement_worker(
+            settlement_state,
+            settlement_queue::SettlementQueueConfig::from_env(),
+        ).await;
+    }));
 
     // 启动 Demo MM (自动报价，方便测试)
     let demo_state = state.clone();
-    tokio::spawn(async move {
+    tasks.push(tokio::spawn(async move {
         demo_mm::start_demo_mm(
             demo_state,
             demo_mm::DemoMmConfig::default(),
         ).await;
+    }));
+
+    // 限流器: 100 请求/分钟/IP + 100 请求/分钟/API key
+    let rate_limiters = RateLimiters::default();
+
+    // 定期清理限流器过期条目
+    let cleanup_limiters = rate_limiters.clone();
+    let limiter_shutdown = state.shutdown.clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(cleanup_limiters.per_ip.window_duration());
+        loop {
+            tokio::select! {
+                _ = limiter_shutdown.cancelled() => break,
+                _ = ticker.tick() => cleanup_limiters.cleanup(),
+            }
+        }
     });
 
+    let app = build_router(state.clone(), rate_limiters);
+
+    let addr = "0.0.0.0:8080";
+    info!("🚀 Trade Router starting on {}", addr);
+    info!("📡 WebSocket endpoint: ws://{}/ws", addr);
+    info!("📋 REST API: http://{}/", addr);
+
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(state.clone()))
+        .await
+        .unwrap();
+
+    // 连接已全部关闭，等待后台任务结束当前一轮后写回状态
+    if tokio::time::timeout(SHUTDOWN_GRACE, futures::future::join_all(tasks)).await.is_err() {
+        tracing::warn!("Background tasks did not stop within {:?}", SHUTDOWN_GRACE);
+    }
+    let written = state.persist_all();
+    info!("💾 Persisted {} positions/requests/quotes, shutting down", written);
+}
+
+/// 等待后台任务退出的最长时间
+const SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// 收到 Ctrl-C 或 SIGTERM 后开始关闭: 停止接受新连接，通知客户端和后台任务
+async fn shutdown_signal(state: Arc<AppState>) {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("🛑 Shutdown signal received");
+    state.begin_shutdown();
+}
+
+/// 构建路由
+fn build_router(state: Arc<AppState>, rate_limiters: RateLimiters) -> Router {
     // CORS 配置
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
-    // 限流器: 100 请求/分钟/IP
-    let rate_limiter = Arc::new(RateLimiter::default());
-
-    // 构建路由
-    let app = Router::new()
+    Router::new()
         // 健康检查
         .route("/health", get(handlers::health))
         // Agent API
@@ -86,32 +207,42 @@ async fn main() {
         .route("/agents/:agent_id", get(handlers::get_agent))
         .route("/agents/:agent_id/stats", get(handlers::get_agent_stats))
         .route("/mm/leaderboard", get(handlers::get_mm_leaderboard))
+        .route("/agents/:agent_id/funding", get(handlers::get_funding_payments))
         .route("/agents/:agent_id/limits", get(handlers::get_agent_limits).post(handlers::set_agent_limits))
         // 交易 API
         .route("/trade/request", post(handlers::create_trade_request))
+        .route("/trade/request/:id/cancel", post(handlers::cancel_trade_request))
         .route("/trade/quote", post(handlers::create_quote))
+        .route("/trade/quote/:quote_id/withdraw", post(handlers::withdraw_quote))
         .route("/trade/accept", post(handlers::accept_quote))
+        .route("/trade/auto-accept", post(handlers::auto_accept_quote))
         .route("/trade/close", post(handlers::close_position))
+        .route("/trade/modify", post(handlers::modify_position))
+        .route("/trade/positions/:position_id/margin", post(handlers::add_margin))
         // 查询 API
+        .route("/positions/at-risk", get(handlers::get_at_risk_positions))
         .route("/positions/:agent_id", get(handlers::get_positions))
         .route("/positions/:agent_id/margin", get(handlers::get_positions_margin))
         .route("/positions/:agent_id/history", get(handlers::get_position_history))
+        .route("/positions/:agent_id/history.csv", get(handlers::export_position_history_csv))
+        .route("/trades/:agent_id", get(handlers::get_trade_history))
         .route("/requests", get(handlers::get_requests))
         .route("/quotes/:request_id", get(handlers::get_quotes))
         .route("/markets", get(handlers::get_markets))
+        .route("/funding/:market/history", get(handlers::get_funding_rate_history))
+        .route("/insurance-fund", get(handlers::get_insurance_fund))
+        // 运维 API (X-Admin-Key)
+        .route("/admin/positions/:id/settle", post(handlers::force_settle_position))
+        .route("/admin/positions/:id/cancel", post(handlers::cancel_position))
+        .route("/admin/settlement/pending", get(handlers::get_pending_settlements))
         // WebSocket
         .route("/ws", get(websocket::ws_handler))
-        // 中间件 (顺序: cors -> rate_limit -> auth)
+        // 中间件 (顺序: cors -> metrics -> auth -> rate_limit，按 API key 限流需要先认证)
+        .layer(axum_middleware::from_fn_with_state(rate_limiters, rate_limit_middleware))
         .layer(axum_middleware::from_fn_with_state(state.clone(), auth_middleware))
-        .layer(axum_middleware::from_fn_with_state(rate_limiter.clone(), rate_limit_middleware))
+        // Prometheus 抓取端点在 auth/限流之后注册，不受其影响
+        .route("/metrics", get(metrics::scrape))
+        .layer(axum_middleware::from_fn_with_state(state.clone(), metrics::metrics_middleware))
         .layer(cors)
-        .with_state(state);
-
-    let addr = "0.0.0.0:8080";
-    info!("🚀 Trade Router starting on {}", addr);
-    info!("📡 WebSocket endpoint: ws://{}/ws", addr);
-    info!("📋 REST API: http://{}/", addr);
-
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+        .with_state(state)
 }
@@ -2,18 +2,34 @@
 //!
 //! Key concepts:
 //! - Initial Margin: Required to open position (size / leverage)
-//! - Maintenance Margin: Minimum to keep position (typically 50% of initial)
+//! - Maintenance Margin: Minimum to keep position (typically 50% of initial,
+//!   rising in tiers for large notionals)
 //! - Liquidation: When equity falls below maintenance margin
+//! - Margin mode: isolated positions are checked on their own; a trader's
+//!   cross positions share collateral and are checked as one account
 
-use crate::types::{Position, Side, Market};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::money::Usd;
+use crate::types::{Position, Side};
 
 /// Margin configuration
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct MarginConfig {
     /// Maintenance margin ratio (0.5 = 50% of initial)
     pub maintenance_ratio: f64,
-    /// Liquidation fee (goes to insurance fund)
+    /// Stricter ratios by notional, as `(notional_threshold, maintenance_ratio)`.
+    /// Each tier applies to the part of the notional above its threshold,
+    /// `maintenance_ratio` to the part below the first one
+    pub maintenance_tiers: Vec<(f64, f64)>,
+    /// Liquidation penalty charged to the trader, as a fraction of the notional
     pub liquidation_fee: f64,
+    /// Share of the penalty paid to the liquidator (the MM taking over the
+    /// position); the rest goes to the insurance fund
+    pub liquidator_reward_share: f64,
     /// Maximum leverage allowed
     pub max_leverage: u8,
 }
@@ -22,60 +38,128 @@ impl Default for MarginConfig {
     fn default() -> Self {
         Self {
             maintenance_ratio: 0.5,
+            maintenance_tiers: vec![(100_000.0, 0.6), (1_000_000.0, 0.75)],
             liquidation_fee: 0.01,  // 1%
+            liquidator_reward_share: 0.0,
             max_leverage: 20,
         }
     }
 }
 
-/// Calculate required initial margin
-pub fn initial_margin(size_usdc: f64, leverage: u8) -> f64 {
-    size_usdc / leverage as f64
+/// Calculate required initial margin; `None` for zero leverage
+pub fn initial_margin(size_usdc: Usd, leverage: u8) -> Option<Usd> {
+    size_usdc.checked_div(leverage)
+}
+
+impl MarginConfig {
+    /// Effective maintenance ratio for a position of `notional` USDC: the
+    /// notional-weighted blend of the tiers it spans
+    pub fn maintenance_ratio_for(&self, notional: Usd) -> Decimal {
+        let base = Decimal::from_f64(self.maintenance_ratio).unwrap_or_default();
+        if !notional.is_positive() {
+            return base;
+        }
+        let mut tiers = self.maintenance_tiers.clone();
+        tiers.sort_by(|a, b| a.0.total_cmp(&b.0));
+        
+        let mut weighted = Usd::ZERO;
+        let mut floor = Usd::ZERO;
+        let mut ratio = self.maintenance_ratio;
+        for (threshold, tier_ratio) in tiers {
+            let threshold = Usd::from(threshold);
+            if notional <= threshold {
+                break;
+            }
+            weighted += (threshold - floor) * ratio;
+            floor = threshold;
+            ratio = tier_ratio;
+        }
+        weighted += (notional - floor) * ratio;
+        weighted.ratio(notional).unwrap_or(base)
+    }
 }
 
-/// Calculate maintenance margin
-pub fn maintenance_margin(initial: f64, config: &MarginConfig) -> f64 {
-    initial * config.maintenance_ratio
+/// Calculate maintenance margin for a position of `notional` USDC
+pub fn maintenance_margin(initial: Usd, notional: Usd, config: &MarginConfig) -> Usd {
+    initial * config.maintenance_ratio_for(notional)
 }
 
 /// Calculate unrealized PnL for a position
-pub fn unrealized_pnl(position: &Position, current_price: f64) -> f64 {
-    let price_change = (current_price - position.entry_price) / position.entry_price;
-    let leveraged_change = price_change * position.leverage as f64;
+pub fn unrealized_pnl(position: &Position, current_price: f64) -> Usd {
+    pnl_for_size(position, position.size_usdc, current_price)
+}
+
+/// Trader PnL on `size_usdc` of a position at `price` (used for partial closes)
+pub fn pnl_for_size(position: &Position, size_usdc: Usd, price: f64) -> Usd {
+    // size * (price - entry) / entry * leverage
+    let change = (Usd::from(price) - position.entry_price) * position.leverage;
+    let Some(change) = change.ratio(position.entry_price) else {
+        return Usd::ZERO;
+    };
+    let pnl = size_usdc * change;
     
     match position.side {
-        Side::Long => position.size_usdc * leveraged_change,
-        Side::Short => position.size_usdc * (-leveraged_change),
+        Side::Long => pnl,
+        Side::Short => -pnl,
     }
 }
 
 /// Calculate current equity (collateral + unrealized PnL)
-pub fn equity(position: &Position, current_price: f64) -> f64 {
+pub fn equity(position: &Position, current_price: f64) -> Usd {
     position.trader_collateral + unrealized_pnl(position, current_price)
 }
 
+/// Equity below which a position is liquidated (its maintenance margin)
+fn liquidation_threshold(position: &Position, config: &MarginConfig) -> Usd {
+    maintenance_margin(position.trader_collateral, position.size_usdc, config)
+}
+
 /// Check if position should be liquidated
 pub fn should_liquidate(position: &Position, current_price: f64, config: &MarginConfig) -> bool {
-    let current_equity = equity(position, current_price);
-    let maint_margin = maintenance_margin(position.trader_collateral, config);
-    
-    current_equity < maint_margin
+    equity(position, current_price) < liquidation_threshold(position, config)
 }
 
-/// Calculate liquidation price
-pub fn liquidation_price(position: &Position, config: &MarginConfig) -> f64 {
-    let maint_margin = maintenance_margin(position.trader_collateral, config);
-    // At liquidation: equity = maint_margin
-    // equity = collateral + pnl
-    // pnl = maint_margin - collateral (negative for liquidation)
-    let pnl_at_liq = maint_margin - position.trader_collateral;
-    
+/// Full liquidation penalty for a position
+pub fn liquidation_penalty(position: &Position, config: &MarginConfig) -> Usd {
+    position.size_usdc * config.liquidation_fee
+}
+
+/// Part of a charged `penalty` paid to the liquidator
+pub fn liquidator_reward(penalty: Usd, config: &MarginConfig) -> Usd {
+    penalty * config.liquidator_reward_share.clamp(0.0, 1.0)
+}
+
+/// Part of a charged `penalty` paid into the insurance fund
+pub fn insurance_contribution(penalty: Usd, config: &MarginConfig) -> Usd {
+    penalty - liquidator_reward(penalty, config)
+}
+
+/// Calculate liquidation price: where equity falls to the `should_liquidate` threshold
+pub fn liquidation_price(position: &Position, config: &MarginConfig) -> Usd {
+    price_at_equity(position, liquidation_threshold(position, config))
+}
+
+/// Price at which equity after paying the liquidation penalty falls to the
+/// `should_liquidate` threshold. Closer to entry than `liquidation_price`
+pub fn effective_liquidation_price(position: &Position, config: &MarginConfig) -> Usd {
+    price_at_equity(position, liquidation_threshold(position, config) + liquidation_penalty(position, config))
+}
+
+/// Inverse of `equity`: the price at which the position's equity equals `target`
+fn price_at_equity(position: &Position, target: Usd) -> Usd {
+    // equity = collateral + pnl, so pnl = target - collateral (negative for liquidation)
+    price_at_pnl(position, target - position.trader_collateral)
+}
+
+fn price_at_pnl(position: &Position, pnl_at_liq: Usd) -> Usd {
     // pnl = size * (price - entry) / entry * leverage
     // Solve for price:
     // pnl * entry / (size * leverage) = price - entry
     // price = entry + pnl * entry / (size * leverage)
-    let notional = position.size_usdc;
-    let price_change = pnl_at_liq * position.entry_price / notional;
+    let Some(fraction) = pnl_at_liq.ratio(position.size_usdc * position.leverage) else {
+        return position.entry_price;
+    };
+    let price_change = position.entry_price * fraction;
     
     match position.side {
         // Long loses when price drops
@@ -85,23 +169,28 @@ pub fn liquidation_price(position: &Position, config: &MarginConfig) -> f64 {
     }
 }
 
-/// Margin health as percentage (100% = healthy, 0% = liquidation)
-pub fn margin_health(position: &Position, current_price: f64, config: &MarginConfig) -> f64 {
-    let current_equity = equity(position, current_price);
-    let maint_margin = maintenance_margin(position.trader_collateral, config);
-    let initial = position.trader_collateral;
-    
-    if current_equity <= maint_margin {
+/// Health as a percentage of the buffer between initial and maintenance margin
+fn health_pct(equity: Usd, maint_margin: Usd, initial: Usd) -> f64 {
+    if equity <= maint_margin {
         return 0.0;
     }
     
     // Health = (equity - maint) / (initial - maint) * 100
     let buffer = initial - maint_margin;
-    if buffer <= 0.0 {
+    if !buffer.is_positive() {
         return 100.0;
     }
     
-    ((current_equity - maint_margin) / buffer * 100.0).min(100.0)
+    (equity - maint_margin).ratio(buffer)
+        .and_then(|h| h.to_f64())
+        .map_or(100.0, |h| (h * 100.0).min(100.0))
+}
+
+/// Margin health as percentage (100% = healthy, 0% = liquidation)
+pub fn margin_health(position: &Position, current_price: f64, config: &MarginConfig) -> f64 {
+    let current_equity = equity(position, current_price);
+    let maint_margin = maintenance_margin(position.trader_collateral, position.size_usdc, config);
+    health_pct(current_equity, maint_margin, position.trader_collateral)
 }
 
 /// Position summary with margin info
@@ -110,16 +199,18 @@ pub struct PositionMarginInfo {
     pub position_id: String,
     pub market: String,
     pub side: String,
-    pub size_usdc: f64,
+    pub size_usdc: Usd,
     pub leverage: u8,
-    pub entry_price: f64,
+    pub entry_price: Usd,
     pub current_price: f64,
-    pub unrealized_pnl: f64,
-    pub collateral: f64,
-    pub equity: f64,
-    pub initial_margin: f64,
-    pub maintenance_margin: f64,
-    pub liquidation_price: f64,
+    pub unrealized_pnl: Usd,
+    pub collateral: Usd,
+    pub equity: Usd,
+    pub initial_margin: Usd,
+    pub maintenance_margin: Usd,
+    pub liquidation_price: Usd,
+    /// Liquidation price once the penalty is taken into account
+    pub effective_liquidation_price: Usd,
     pub margin_health: f64,  // 0-100%
     pub is_liquidatable: bool,
 }
@@ -129,7 +220,7 @@ impl PositionMarginInfo {
         let pnl = unrealized_pnl(position, current_price);
         let eq = equity(position, current_price);
         let initial = position.trader_collateral;
-        let maint = maintenance_margin(initial, config);
+        let maint = maintenance_margin(initial, position.size_usdc, config);
         let liq_price = liquidation_price(position, config);
         let health = margin_health(position, current_price, config);
         let liquidatable = should_liquidate(position, current_price, config);
@@ -148,20 +239,101 @@ impl PositionMarginInfo {
             initial_margin: initial,
             maintenance_margin: maint,
             liquidation_price: liq_price,
+            effective_liquidation_price: effective_liquidation_price(position, config),
             margin_health: health,
             is_liquidatable: liquidatable,
         }
     }
 }
 
+/// Aggregate margin across an agent's positions
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PortfolioSummary {
+    pub position_count: usize,
+    pub total_collateral: Usd,
+    pub total_equity: Usd,
+    pub total_maintenance_margin: Usd,
+    pub total_unrealized_pnl: Usd,
+    /// Same formula as `margin_health`, applied to the summed amounts
+    pub margin_health: f64,
+}
+
+impl PortfolioSummary {
+    pub fn from_positions(infos: &[PositionMarginInfo]) -> Self {
+        let total_collateral: Usd = infos.iter().map(|i| i.collateral).sum();
+        let total_equity: Usd = infos.iter().map(|i| i.equity).sum();
+        let total_maintenance_margin: Usd = infos.iter().map(|i| i.maintenance_margin).sum();
+        let total_unrealized_pnl: Usd = infos.iter().map(|i| i.unrealized_pnl).sum();
+
+        let buffer = total_collateral - total_maintenance_margin;
+        let margin_health = if infos.is_empty() || !buffer.is_positive() {
+            100.0
+        } else {
+            health_pct(total_equity, total_maintenance_margin, total_collateral)
+        };
+
+        Self {
+            position_count: infos.len(),
+            total_collateral,
+            total_equity,
+            total_maintenance_margin,
+            total_unrealized_pnl,
+            margin_health,
+        }
+    }
+}
+
+/// Pooled margin of a trader's cross-margin positions, each paired with its
+/// current price
+pub fn cross_account_summary(positions: &[(Position, f64)], config: &MarginConfig) -> PortfolioSummary {
+    let infos: Vec<_> = positions.iter()
+        .map(|(position, price)| PositionMarginInfo::from_position(position, *price, config))
+        .collect();
+    PortfolioSummary::from_positions(&infos)
+}
+
+/// Cross-margin positions to liquidate once pooled equity is below pooled
+/// maintenance margin: the weakest positions (lowest equity over maintenance)
+/// go first, until what is left of the account is back above maintenance.
+/// Empty while the account is healthy, however bad a single position is
+pub fn cross_margin_liquidations(positions: &[(Position, f64)], config: &MarginConfig) -> Vec<Uuid> {
+    let mut remaining: Vec<_> = positions.iter()
+        .map(|(position, price)| {
+            let maint = maintenance_margin(position.trader_collateral, position.size_usdc, config);
+            (position.id, equity(position, *price), maint)
+        })
+        .collect();
+    remaining.sort_by_key(|r| r.1 - r.2);
+    
+    let mut liquidate = Vec::new();
+    while !remaining.is_empty() {
+        let total_equity: Usd = remaining.iter().map(|r| r.1).sum();
+        let total_maint: Usd = remaining.iter().map(|r| r.2).sum();
+        if total_equity >= total_maint {
+            break;
+        }
+        liquidate.push(remaining.remove(0).0);
+    }
+    liquidate
+}
+
+/// Per-position margin plus the portfolio aggregate
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PortfolioMargin {
+    pub positions: Vec<PositionMarginInfo>,
+    pub summary: PortfolioSummary,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::{MarginMode, Market};
     use uuid::Uuid;
     use chrono::Utc;
     use crate::types::PositionStatus;
     
     fn make_position(side: Side, entry: f64, size: f64, leverage: u8) -> Position {
+        let size = Usd::from(size);
         Position {
             id: Uuid::new_v4(),
             request_id: Uuid::new_v4(),
@@ -172,13 +344,18 @@ mod tests {
             side,
             size_usdc: size,
             leverage,
-            entry_price: entry,
+            entry_price: Usd::from(entry),
             funding_rate: 0.01,
-            trader_collateral: size / leverage as f64,
-            mm_collateral: size / leverage as f64,
+            trader_collateral: initial_margin(size, leverage).unwrap(),
+            mm_collateral: initial_margin(size, leverage).unwrap(),
             status: PositionStatus::Active,
             created_at: Utc::now(),
             closed_at: None,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: MarginMode::Isolated,
+            liquidation_price: Usd::ZERO,
+            last_funding_at: None,
         }
     }
     
@@ -188,17 +365,17 @@ mod tests {
         // Price up 10% = 110
         // Leveraged change = 10% * 10 = 100%
         // PnL = 1000 * 1.0 = 1000
-        assert!((unrealized_pnl(&pos, 110.0) - 1000.0).abs() < 0.01);
+        assert_eq!(unrealized_pnl(&pos, 110.0), Usd::from(1000.0));
         
         // Price down 5%
-        assert!((unrealized_pnl(&pos, 95.0) - (-500.0)).abs() < 0.01);
+        assert_eq!(unrealized_pnl(&pos, 95.0), Usd::from(-500.0));
     }
     
     #[test]
     fn test_pnl_short() {
         let pos = make_position(Side::Short, 100.0, 1000.0, 10);
         // Price down 10% = good for short
-        assert!((unrealized_pnl(&pos, 90.0) - 1000.0).abs() < 0.01);
+        assert_eq!(unrealized_pnl(&pos, 90.0), Usd::from(1000.0));
     }
     
     #[test]
@@ -218,4 +395,172 @@ mod tests {
         // For pnl = -50: (p/100 - 1) = -0.005, p = 99.5
         assert!(should_liquidate(&pos, 95.0, &config));  // Should liquidate
     }
+    
+    fn tiered_config() -> MarginConfig {
+        MarginConfig {
+            maintenance_tiers: vec![(100_000.0, 0.6), (1_000_000.0, 0.75)],
+            ..MarginConfig::default()
+        }
+    }
+    
+    #[test]
+    fn test_small_position_uses_base_tier() {
+        let config = tiered_config();
+        let pos = make_position(Side::Long, 100.0, 10_000.0, 1);
+        
+        assert_eq!(config.maintenance_ratio_for(pos.size_usdc).to_f64(), Some(0.5));
+        assert_eq!(maintenance_margin(pos.trader_collateral, pos.size_usdc, &config), Usd::from(5_000.0));
+        // Equity hits 5000 when pnl = -5000: 10000 * (p/100 - 1) = -5000
+        assert_eq!(liquidation_price(&pos, &config), Usd::from(50.0));
+        assert!(should_liquidate(&pos, 49.9, &config));
+        assert!(!should_liquidate(&pos, 50.1, &config));
+    }
+    
+    #[test]
+    fn test_liquidation_price_matches_should_liquidate_with_leverage() {
+        let config = MarginConfig::default();
+        // 10x long of 1000 at 84000: 100 collateral, liquidated once the
+        // leveraged loss reaches the 50 maintenance buffer (a 0.5% drop)
+        let long = make_position(Side::Long, 84000.0, 1000.0, 10);
+        assert_eq!(liquidation_price(&long, &config), Usd::from(83580.0));
+        let short = make_position(Side::Short, 84000.0, 1000.0, 10);
+        assert_eq!(liquidation_price(&short, &config), Usd::from(84420.0));
+        
+        for leverage in [1, 2, 5, 10, 20] {
+            for side in [Side::Long, Side::Short] {
+                let pos = make_position(side, 84000.0, 1000.0, leverage);
+                let liq = liquidation_price(&pos, &config).to_f64();
+                let (beyond, inside) = match side {
+                    Side::Long => (liq - 0.01, liq + 0.01),
+                    Side::Short => (liq + 0.01, liq - 0.01),
+                };
+                assert!(should_liquidate(&pos, beyond, &config), "{:?} {}x at {}", side, leverage, beyond);
+                assert!(!should_liquidate(&pos, inside, &config), "{:?} {}x at {}", side, leverage, inside);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_large_position_uses_stricter_tier() {
+        let config = tiered_config();
+        let flat = MarginConfig { maintenance_tiers: Vec::new(), ..MarginConfig::default() };
+        let pos = make_position(Side::Long, 100.0, 2_000_000.0, 1);
+        
+        // 100k @ 50% + 900k @ 60% + 1m @ 75% over 2m
+        let expected = (50_000.0 + 540_000.0 + 750_000.0) / 2_000_000.0;
+        assert_eq!(config.maintenance_ratio_for(pos.size_usdc).to_f64(), Some(expected));
+        
+        // Liquidates earlier (higher price for a long) than under a flat ratio
+        let tiered_liq = liquidation_price(&pos, &config);
+        assert!(tiered_liq > liquidation_price(&pos, &flat));
+        let between = (tiered_liq + liquidation_price(&pos, &flat)).checked_div(2u8).unwrap().to_f64();
+        assert!(should_liquidate(&pos, between, &config));
+        assert!(!should_liquidate(&pos, between, &flat));
+        assert!(margin_health(&pos, between, &flat) > 0.0);
+        assert_eq!(margin_health(&pos, between, &config), 0.0);
+    }
+    
+    #[test]
+    fn test_position_straddling_tier_boundary() {
+        let config = tiered_config();
+        
+        // 150k: 100k at the base ratio, 50k at the first tier
+        let ratio = config.maintenance_ratio_for(Usd::from(150_000.0)).to_f64().unwrap();
+        assert!((ratio - (50_000.0 + 30_000.0) / 150_000.0).abs() < 1e-12);
+        assert!(ratio > 0.5 && ratio < 0.6);
+        
+        // Continuous across the threshold
+        let ratio_at = |notional: f64| config.maintenance_ratio_for(Usd::from(notional)).to_f64().unwrap();
+        assert!((ratio_at(100_000.0) - 0.5).abs() < 1e-12);
+        assert!((ratio_at(100_000.01) - 0.5).abs() < 1e-6);
+        
+        let pos = make_position(Side::Short, 100.0, 150_000.0, 1);
+        let maint = maintenance_margin(pos.trader_collateral, pos.size_usdc, &config).to_f64();
+        assert!((maint - 150_000.0 * ratio).abs() < 1e-9);
+        // Short liquidates where equity = maint: pnl = maint - collateral
+        let expected_liq = 100.0 - (maint - pos.trader_collateral.to_f64()) * 100.0 / pos.size_usdc.to_f64();
+        assert!((liquidation_price(&pos, &config).to_f64() - expected_liq).abs() < 1e-9);
+        assert!(should_liquidate(&pos, expected_liq + 0.01, &config));
+        assert!(!should_liquidate(&pos, expected_liq - 0.01, &config));
+    }
+    
+    #[test]
+    fn test_liquidation_penalty_split_sums_to_penalty() {
+        let config = MarginConfig { liquidator_reward_share: 0.3, ..MarginConfig::default() };
+        let pos = make_position(Side::Long, 100.0, 10_000.0, 1);
+        
+        let penalty = liquidation_penalty(&pos, &config);
+        assert_eq!(penalty, Usd::from(100.0));
+        let reward = liquidator_reward(penalty, &config);
+        let insurance = insurance_contribution(penalty, &config);
+        assert_eq!(reward, Usd::from(30.0));
+        assert_eq!(insurance, Usd::from(70.0));
+        assert_eq!(reward + insurance, penalty);
+        
+        // Out-of-range shares are clamped, so the split never exceeds the penalty
+        let all = MarginConfig { liquidator_reward_share: 1.5, ..MarginConfig::default() };
+        assert_eq!(liquidator_reward(penalty, &all), penalty);
+        assert_eq!(insurance_contribution(penalty, &all), Usd::ZERO);
+    }
+    
+    #[test]
+    fn test_effective_liquidation_price_accounts_for_penalty() {
+        let config = MarginConfig::default();
+        let long = make_position(Side::Long, 100.0, 10_000.0, 1);
+        let short = make_position(Side::Short, 100.0, 10_000.0, 1);
+        
+        // Long: equity - 100 penalty hits 5000 maint at pnl = -4900, i.e. p = 51
+        assert_eq!(effective_liquidation_price(&long, &config), Usd::from(51.0));
+        assert!(effective_liquidation_price(&long, &config) > liquidation_price(&long, &config));
+        assert_eq!(effective_liquidation_price(&short, &config), Usd::from(149.0));
+        assert!(effective_liquidation_price(&short, &config) < liquidation_price(&short, &config));
+        
+        let info = PositionMarginInfo::from_position(&long, 100.0, &config);
+        assert_eq!(info.effective_liquidation_price, effective_liquidation_price(&long, &config));
+    }
+    
+    #[test]
+    fn test_effective_liquidation_price_agrees_with_should_liquidate() {
+        let config = MarginConfig::default();
+        for leverage in [1, 5, 10] {
+            for side in [Side::Long, Side::Short] {
+                let pos = make_position(side, 84000.0, 1000.0, leverage);
+                let penalty = liquidation_penalty(&pos, &config);
+                let effective = effective_liquidation_price(&pos, &config).to_f64();
+                
+                // Equity at the effective price is exactly the threshold plus the penalty
+                assert_eq!(equity(&pos, effective) - penalty, liquidation_threshold(&pos, &config));
+                // Just beyond it the penalty would push equity below the threshold
+                let beyond = match side {
+                    Side::Long => effective - 0.01,
+                    Side::Short => effective + 0.01,
+                };
+                let after_penalty = Position { trader_collateral: pos.trader_collateral - penalty, ..pos.clone() };
+                assert!(equity(&after_penalty, beyond) < liquidation_threshold(&pos, &config));
+                assert!(!should_liquidate(&pos, effective, &config));
+            }
+        }
+    }
+    
+    #[test]
+    fn test_cross_margin_pools_equity_across_positions() {
+        let config = MarginConfig::default();
+        let mut loser = make_position(Side::Long, 100.0, 10_000.0, 1);
+        loser.margin_mode = MarginMode::Cross;
+        let mut winner = make_position(Side::Short, 100.0, 10_000.0, 1);
+        winner.margin_mode = MarginMode::Cross;
+        
+        // Loser alone is below maintenance (4500 < 5000); the winner's 12000 equity covers it
+        assert!(should_liquidate(&loser, 45.0, &config));
+        let account = [(loser.clone(), 45.0), (winner.clone(), 80.0)];
+        assert!(cross_margin_liquidations(&account, &config).is_empty());
+        let summary = cross_account_summary(&account, &config);
+        assert_eq!(summary.total_equity, Usd::from(16_500.0));
+        assert!(summary.margin_health > 0.0);
+        
+        // Pooled 500 + 7000 < 10000: the weakest goes, the rest is healthy again
+        let account = [(loser.clone(), 5.0), (winner.clone(), 130.0)];
+        assert_eq!(cross_margin_liquidations(&account, &config), vec![loser.id]);
+        assert_eq!(cross_account_summary(&account, &config).margin_health, 0.0);
+    }
 }
@@ -0,0 +1,100 @@
+//! Mark price smoothing
+//!
+//! Index prices from the feed are noisy, so risk checks use an exponential
+//! moving average of them instead. The EMA is time-weighted: a tick arriving
+//! `dt` after the previous one moves the mark by `1 - 0.5^(dt / half_life)` of
+//! the gap, so a single outlier only shifts it by a bounded fraction.
+//!
+//! The raw index stays in `AppState::prices`; the funding premium is still
+//! measured against it.
+
+use std::time::{Duration, Instant};
+use dashmap::DashMap;
+
+use crate::types::Market;
+
+/// Default EMA half-life (about two feed intervals)
+pub const DEFAULT_HALF_LIFE: Duration = Duration::from_secs(60);
+
+/// Per-market EMA of the index price
+#[derive(Debug)]
+pub struct MarkPrice {
+    half_life: Duration,
+    values: DashMap<Market, (f64, Instant)>,
+}
+
+impl Default for MarkPrice {
+    fn default() -> Self {
+        Self::new(DEFAULT_HALF_LIFE)
+    }
+}
+
+impl MarkPrice {
+    pub fn new(half_life: Duration) -> Self {
+        Self {
+            half_life,
+            values: DashMap::new(),
+        }
+    }
+
+    /// Fold a new index tick into the average and return the smoothed mark.
+    /// The first tick for a market seeds the average directly.
+    pub fn update(&self, market: Market, index_price: f64, now: Instant) -> f64 {
+        let mut entry = self.values.entry(market).or_insert((index_price, now));
+        let (mark, last) = *entry;
+
+        let elapsed = now.saturating_duration_since(last).as_secs_f64();
+        let half_life = self.half_life.as_secs_f64();
+        let alpha = if half_life > 0.0 {
+            1.0 - 0.5_f64.powf(elapsed / half_life)
+        } else {
+            1.0
+        };
+
+        let smoothed = mark + alpha * (index_price - mark);
+        *entry = (smoothed, now.max(last));
+        smoothed
+    }
+
+    /// Current smoothed mark, if the market has received a tick
+    pub fn get(&self, market: Market) -> Option<f64> {
+        self.values.get(&market).map(|v| v.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_outlier_moves_by_expected_fraction() {
+        let smoother = MarkPrice::new(Duration::from_secs(60));
+        let t0 = Instant::now();
+        assert_eq!(smoother.update(Market::BtcPerp, 100_000.0, t0), 100_000.0);
+
+        // One half-life later a +10% spike only moves the mark halfway
+        let mark = smoother.update(Market::BtcPerp, 110_000.0, t0 + Duration::from_secs(60));
+        assert!((mark - 105_000.0).abs() < 1e-6);
+
+        // A tick 30s later moves it by 1 - 0.5^0.5 of the gap
+        let mark = smoother.update(Market::BtcPerp, 100_000.0, t0 + Duration::from_secs(90));
+        let expected = 105_000.0 - 5_000.0 * (1.0 - 0.5_f64.sqrt());
+        assert!((mark - expected).abs() < 1e-6);
+
+        // Other markets are independent
+        assert_eq!(smoother.get(Market::EthPerp), None);
+    }
+
+    #[test]
+    fn test_steady_prices_converge() {
+        let smoother = MarkPrice::new(Duration::from_secs(60));
+        let t0 = Instant::now();
+        smoother.update(Market::EthPerp, 2000.0, t0);
+
+        for i in 1..=40 {
+            smoother.update(Market::EthPerp, 2200.0, t0 + Duration::from_secs(30 * i));
+        }
+        // 20 half-lives: within 2200 * 2^-20 of the target
+        assert!((smoother.get(Market::EthPerp).unwrap() - 2200.0).abs() < 1e-3);
+    }
+}
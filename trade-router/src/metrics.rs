@@ -0,0 +1,177 @@
+//! Prometheus metrics
+//!
+//! Counters are bumped where the events happen (liquidation, funding); the
+//! position/request/quote gauges are refreshed from `AppState` on each scrape
+//! so they can never drift from the in-memory maps.
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::state::AppState;
+use crate::types::{Market, PositionStatus};
+
+pub struct Metrics {
+    registry: Registry,
+    open_positions: IntGauge,
+    active_requests: IntGauge,
+    active_quotes: IntGauge,
+    liquidations: IntCounterVec,
+    funding_payments: IntCounter,
+    http_requests: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("trade_router".to_string()), None)
+            .expect("valid metrics prefix");
+
+        let open_positions = IntGauge::new("open_positions", "Active positions").unwrap();
+        let active_requests = IntGauge::new("active_requests", "Trade requests awaiting quotes").unwrap();
+        let active_quotes = IntGauge::new("active_quotes", "Quotes on active trade requests").unwrap();
+        let liquidations = IntCounterVec::new(
+            Opts::new("liquidations_total", "Positions liquidated"),
+            &["market"],
+        ).unwrap();
+        let funding_payments = IntCounter::new(
+            "funding_payments_total",
+            "Funding payments settled between traders and market makers",
+        ).unwrap();
+        let http_requests = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &["method", "path", "status"],
+        ).unwrap();
+
+        registry.register(Box::new(open_positions.clone())).unwrap();
+        registry.register(Box::new(active_requests.clone())).unwrap();
+        registry.register(Box::new(active_quotes.clone())).unwrap();
+        registry.register(Box::new(liquidations.clone())).unwrap();
+        registry.register(Box::new(funding_payments.clone())).unwrap();
+        registry.register(Box::new(http_requests.clone())).unwrap();
+
+        Self {
+            registry,
+            open_positions,
+            active_requests,
+            active_quotes,
+            liquidations,
+            funding_payments,
+            http_requests,
+        }
+    }
+
+    pub fn record_liquidation(&self, market: Market) {
+        self.liquidations.with_label_values(&[market.symbol()]).inc();
+    }
+
+    pub fn record_funding_payment(&self) {
+        self.funding_payments.inc();
+    }
+
+    /// Refresh the gauges from `state` and encode everything in the text exposition format
+    pub fn render(&self, state: &AppState) -> String {
+        let open = state.positions.iter()
+            .filter(|p| p.status == PositionStatus::Active)
+            .count();
+        self.open_positions.set(open as i64);
+        self.active_requests.set(state.requests.len() as i64);
+        self.active_quotes.set(state.quotes.iter().map(|q| q.len()).sum::<usize>() as i64);
+
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .unwrap_or_default()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Records latency and status of every request, labelled by route template
+/// rather than raw path so agent/position ids don't blow up cardinality
+pub async fn metrics_middleware(
+    State(state): State<Arc<AppState>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    state.metrics.http_requests
+        .with_label_values(&[&method, &path, response.status().as_str()])
+        .observe(start.elapsed().as_secs_f64());
+    response
+}
+
+/// GET /metrics
+pub async fn scrape(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        state.metrics.render(&state),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::{RateLimiter, RateLimiters};
+    use crate::state::TestTrade;
+    use tower::ServiceExt;
+
+    fn open_position(state: &AppState) {
+        TestTrade::default().open(state);
+    }
+
+    async fn scrape_body(app: axum::Router) -> String {
+        let resp = app
+            .oneshot(Request::builder().uri("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_scrape_reports_open_positions() {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        open_position(&state);
+        state.metrics.record_liquidation(Market::EthPerp);
+
+        // One request per minute: /metrics must bypass the limiter
+        let limiters = RateLimiters::new(
+            RateLimiter::new(1, std::time::Duration::from_secs(60)),
+            RateLimiter::new(1, std::time::Duration::from_secs(60)),
+        );
+        let app = crate::build_router(state.clone(), limiters);
+
+        scrape_body(app.clone()).await;
+        let body = scrape_body(app).await;
+
+        assert!(body.contains("trade_router_open_positions 1"), "{}", body);
+        assert!(body.contains("trade_router_active_requests 0"));
+        assert!(body.contains("trade_router_liquidations_total{market=\"ETH-PERP\"} 1"));
+        assert!(body.contains(
+            "trade_router_http_request_duration_seconds_count{method=\"GET\",path=\"/metrics\",status=\"200\"} 1"
+        ));
+    }
+}
@@ -16,13 +16,16 @@ use std::time::{Duration, Instant};
 use crate::state::AppState;
 use crate::types::{AgentInfo, ApiResponse};
 
-/// Rate limiter state per IP
+/// Rate limiter state per key (IP or API key)
 #[derive(Debug, Clone)]
 struct RateLimitEntry {
     count: u32,
     window_start: Instant,
 }
 
+/// Entry count above which `check` purges stale entries inline
+const CLEANUP_THRESHOLD: usize = 10_000;
+
 /// Global rate limiter store
 pub struct RateLimiter {
     entries: DashMap<String, RateLimitEntry>,
@@ -38,10 +41,25 @@ impl RateLimiter {
             window_duration,
         }
     }
+    
+    pub fn window_duration(&self) -> Duration {
+        self.window_duration
+    }
+    
+    pub fn max_requests(&self) -> u32 {
+        self.max_requests
+    }
 
     /// Check if request is allowed, returns (allowed, remaining, reset_seconds)
     pub fn check(&self, ip: &str) -> (bool, u32, u64) {
-        let now = Instant::now();
+        self.check_at(ip, Instant::now())
+    }
+    
+    fn check_at(&self, ip: &str, now: Instant) -> (bool, u32, u64) {
+        // Must run before `entry()` below: `retain` locks every shard
+        if self.entries.len() > CLEANUP_THRESHOLD {
+            self.cleanup_at(now);
+        }
         
         let mut entry = self.entries.entry(ip.to_string()).or_insert(RateLimitEntry {
             count: 0,
@@ -70,7 +88,10 @@ impl RateLimiter {
     
     /// Cleanup old entries (call periodically)
     pub fn cleanup(&self) {
-        let now = Instant::now();
+        self.cleanup_at(Instant::now());
+    }
+    
+    fn cleanup_at(&self, now: Instant) {
         self.entries.retain(|_, v| {
             now.duration_since(v.window_start) < self.window_duration * 2
         });
@@ -84,35 +105,87 @@ impl Default for RateLimiter {
     }
 }
 
+/// Per-IP and per-agent (API key) limiters used by `rate_limit_middleware`
+#[derive(Clone)]
+pub struct RateLimiters {
+    pub per_ip: Arc<RateLimiter>,
+    pub per_agent: Arc<RateLimiter>,
+}
+
+impl RateLimiters {
+    pub fn new(per_ip: RateLimiter, per_agent: RateLimiter) -> Self {
+        Self {
+            per_ip: Arc::new(per_ip),
+            per_agent: Arc::new(per_agent),
+        }
+    }
+    
+    pub fn cleanup(&self) {
+        self.per_ip.cleanup();
+        self.per_agent.cleanup();
+    }
+}
+
+impl Default for RateLimiters {
+    fn default() -> Self {
+        // 100 requests per minute per IP and per API key
+        Self::new(RateLimiter::default(), RateLimiter::new(100, Duration::from_secs(60)))
+    }
+}
+
 /// Rate limiting middleware
+///
+/// Every request is limited per client IP. Requests carrying a valid API key
+/// (see `auth_middleware`, which must run first) are also limited per key;
+/// either bucket running out rejects the request.
 pub async fn rate_limit_middleware(
-    State(limiter): State<Arc<RateLimiter>>,
+    State(limiters): State<RateLimiters>,
     request: Request<Body>,
     next: Next,
 ) -> Response {
     // Extract client IP from connection info or headers
     let ip = extract_client_ip(&request);
+    let api_key = request.extensions().get::<AgentInfo>().map(|a| a.api_key.clone());
     
-    let (allowed, remaining, reset_secs) = limiter.check(&ip);
+    let mut buckets = vec![("ip", &limiters.per_ip, ip)];
+    if let Some(key) = api_key {
+        buckets.push(("agent", &limiters.per_agent, key));
+    }
     
-    if !allowed {
-        return (
-            StatusCode::TOO_MANY_REQUESTS,
-            [
-                ("X-RateLimit-Limit", "100"),
-                ("X-RateLimit-Remaining", "0"),
-                ("X-RateLimit-Reset", &reset_secs.to_string()),
-                ("Retry-After", &reset_secs.to_string()),
-            ],
-            Json(ApiResponse::<()>::err("Rate limit exceeded. Try again later.")),
-        ).into_response();
+    // Headers report the tightest bucket
+    let mut reported = (u32::MAX, 0, 0);
+    for (scope, limiter, key) in buckets {
+        let limit = limiter.max_requests();
+        let (allowed, remaining, reset_secs) = limiter.check(&key);
+        
+        if !allowed {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [
+                    ("X-RateLimit-Limit", limit.to_string()),
+                    ("X-RateLimit-Remaining", "0".to_string()),
+                    ("X-RateLimit-Reset", reset_secs.to_string()),
+                    ("X-RateLimit-Scope", scope.to_string()),
+                    ("Retry-After", reset_secs.to_string()),
+                ],
+                Json(ApiResponse::<()>::err(format!(
+                    "Rate limit exceeded ({} limit of {} requests). Try again later.",
+                    scope, limit
+                ))),
+            ).into_response();
+        }
+        
+        if remaining < reported.0 {
+            reported = (remaining, limit, reset_secs);
+        }
     }
+    let (remaining, limit, reset_secs) = reported;
     
     let mut response = next.run(request).await;
     
     // Add rate limit headers to response
     let headers = response.headers_mut();
-    headers.insert("X-RateLimit-Limit", "100".parse().unwrap());
+    headers.insert("X-RateLimit-Limit", limit.to_string().parse().unwrap());
     headers.insert("X-RateLimit-Remaining", remaining.to_string().parse().unwrap());
     headers.insert("X-RateLimit-Reset", reset_secs.to_string().parse().unwrap());
     
@@ -173,6 +246,7 @@ pub async fn auth_middleware(
 }
 
 /// Helper to extract API key from headers
+#[allow(dead_code)]
 pub fn extract_api_key(headers: &HeaderMap) -> Option<String> {
     headers
         .get("X-API-Key")
@@ -182,6 +256,7 @@ pub fn extract_api_key(headers: &HeaderMap) -> Option<String> {
 }
 
 /// Require authenticated agent - returns error if not authenticated
+#[allow(dead_code, clippy::result_large_err)]
 pub fn require_auth_from_ext<B>(request: &Request<B>) -> Result<AgentInfo, Response> {
     request
         .extensions()
@@ -194,3 +269,103 @@ pub fn require_auth_from_ext<B>(request: &Request<B>) -> Result<AgentInfo, Respo
             ).into_response()
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware::from_fn_with_state, routing::get, Router};
+    use chrono::Utc;
+    use tower::ServiceExt;
+    
+    fn limited_app(per_ip: u32, per_agent: u32) -> Router {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        state.register_agent(AgentInfo {
+            id: "agent_1".to_string(),
+            api_key: "th_test_key".to_string(),
+            name: None,
+            is_mm: false,
+            created_at: Utc::now(),
+        });
+        let limiters = RateLimiters::new(
+            RateLimiter::new(per_ip, Duration::from_secs(60)),
+            RateLimiter::new(per_agent, Duration::from_secs(60)),
+        );
+        
+        Router::new()
+            .route("/ping", get(|| async { "pong" }))
+            .layer(from_fn_with_state(limiters, rate_limit_middleware))
+            .layer(from_fn_with_state(state, auth_middleware))
+    }
+    
+    fn request(ip: &str, api_key: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().uri("/ping").header("X-Forwarded-For", ip);
+        if let Some(key) = api_key {
+            builder = builder.header("X-API-Key", key);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn test_agent_bucket_shared_across_ips() {
+        let app = limited_app(100, 3);
+        
+        for ip in ["1.1.1.1", "2.2.2.2", "1.1.1.1"] {
+            let resp = app.clone().oneshot(request(ip, Some("th_test_key"))).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        
+        let resp = app.clone().oneshot(request("2.2.2.2", Some("th_test_key"))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()["X-RateLimit-Scope"], "agent");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("agent limit"));
+        
+        // Unauthenticated traffic from the same IP only uses the IP bucket
+        let resp = app.oneshot(request("2.2.2.2", None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    
+    #[tokio::test]
+    async fn test_ip_bucket_still_applies() {
+        let app = limited_app(1, 100);
+        
+        let resp = app.clone().oneshot(request("3.3.3.3", None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = app.oneshot(request("3.3.3.3", Some("th_test_key"))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()["X-RateLimit-Scope"], "ip");
+    }
+    
+    #[test]
+    fn test_cleanup_purges_stale_entries() {
+        let limiter = RateLimiter::new(10, Duration::from_secs(60));
+        let start = Instant::now();
+        
+        for i in 0..500 {
+            limiter.check_at(&format!("10.0.{}.{}", i / 256, i % 256), start);
+        }
+        
+        // Past the retention window, one IP keeps sending requests
+        let later = start + Duration::from_secs(150);
+        limiter.check_at("10.9.9.9", later);
+        limiter.cleanup_at(later);
+        
+        assert_eq!(limiter.entries.len(), 1);
+        assert!(limiter.entries.contains_key("10.9.9.9"));
+    }
+    
+    #[test]
+    fn test_check_purges_when_over_threshold() {
+        let limiter = RateLimiter::new(10, Duration::from_secs(60));
+        let start = Instant::now();
+        
+        for i in 0..=CLEANUP_THRESHOLD {
+            limiter.check_at(&i.to_string(), start);
+        }
+        assert_eq!(limiter.entries.len(), CLEANUP_THRESHOLD + 1);
+        
+        let (allowed, _, _) = limiter.check_at("fresh", start + Duration::from_secs(150));
+        assert!(allowed);
+        assert_eq!(limiter.entries.len(), 1);
+    }
+}
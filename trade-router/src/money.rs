@@ -0,0 +1,235 @@
+//! 金额类型
+//! 用十进制定点数表示 USDC 金额和价格，资金费、盈亏和保证金在多次计算后不会累积
+//! f64 的舍入误差。JSON 中序列化为字符串 (反序列化也接受数字)，SQLite 中以 TEXT 存储
+
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// USDC 金额或价格
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Usd(Decimal);
+
+impl Usd {
+    pub const ZERO: Usd = Usd(Decimal::ZERO);
+
+    /// 从 f64 转换 (价格源、费率等外部输入)，取能还原该 f64 的最短十进制表示；NaN/无穷视为 0
+    pub fn from_f64(value: f64) -> Self {
+        Decimal::from_f64(value).map(Usd).unwrap_or(Usd::ZERO)
+    }
+
+    /// 转为 f64，用于展示比例、与价格源比较等不累积的计算
+    pub fn to_f64(self) -> f64 {
+        self.0.to_f64().unwrap_or(0.0)
+    }
+
+    pub fn abs(self) -> Self {
+        Usd(self.0.abs())
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        Ord::min(self, other)
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        Ord::max(self, other)
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// 严格大于 0
+    pub fn is_positive(self) -> bool {
+        self.0 > Decimal::ZERO
+    }
+
+    /// 严格小于 0
+    pub fn is_negative(self) -> bool {
+        self.0 < Decimal::ZERO
+    }
+
+    /// 除以杠杆、系数等，除数为 0 (或结果溢出) 时返回 None
+    pub fn checked_div(self, rhs: impl Into<Decimal>) -> Option<Usd> {
+        self.0.checked_div(rhs.into()).map(Usd)
+    }
+
+    /// 两个金额之比，分母为 0 时返回 None
+    pub fn ratio(self, rhs: Usd) -> Option<Decimal> {
+        self.0.checked_div(rhs.0)
+    }
+}
+
+impl From<Decimal> for Usd {
+    fn from(value: Decimal) -> Self {
+        Usd(value)
+    }
+}
+
+impl From<f64> for Usd {
+    fn from(value: f64) -> Self {
+        Usd::from_f64(value)
+    }
+}
+
+impl From<Usd> for Decimal {
+    fn from(value: Usd) -> Self {
+        value.0
+    }
+}
+
+impl fmt::Display for Usd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0.normalize(), f)
+    }
+}
+
+impl Add for Usd {
+    type Output = Usd;
+    fn add(self, rhs: Usd) -> Usd {
+        Usd(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Usd {
+    type Output = Usd;
+    fn sub(self, rhs: Usd) -> Usd {
+        Usd(self.0 - rhs.0)
+    }
+}
+
+impl AddAssign for Usd {
+    fn add_assign(&mut self, rhs: Usd) {
+        self.0 += rhs.0;
+    }
+}
+
+impl SubAssign for Usd {
+    fn sub_assign(&mut self, rhs: Usd) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Neg for Usd {
+    type Output = Usd;
+    fn neg(self) -> Usd {
+        Usd(-self.0)
+    }
+}
+
+impl Mul<Decimal> for Usd {
+    type Output = Usd;
+    fn mul(self, rhs: Decimal) -> Usd {
+        Usd(self.0 * rhs)
+    }
+}
+
+/// 乘以费率、比例等 f64 系数 (系数先按最短十进制表示转换)
+impl Mul<f64> for Usd {
+    type Output = Usd;
+    fn mul(self, rhs: f64) -> Usd {
+        Usd(self.0 * Decimal::from_f64(rhs).unwrap_or_default())
+    }
+}
+
+/// 乘以杠杆等整数倍数
+impl Mul<u8> for Usd {
+    type Output = Usd;
+    fn mul(self, rhs: u8) -> Usd {
+        Usd(self.0 * Decimal::from(rhs))
+    }
+}
+
+impl Sum for Usd {
+    fn sum<I: Iterator<Item = Usd>>(iter: I) -> Usd {
+        iter.fold(Usd::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Usd> for Usd {
+    fn sum<I: Iterator<Item = &'a Usd>>(iter: I) -> Usd {
+        iter.copied().sum()
+    }
+}
+
+impl Serialize for Usd {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Usd {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <Decimal as Deserialize>::deserialize(deserializer).map(Usd)
+    }
+}
+
+impl ToSql for Usd {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+/// 迁移前的旧数据为 REAL，同样可以读取
+impl FromSql for Usd {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Text(text) => std::str::from_utf8(text)
+                .ok()
+                .and_then(|s| s.parse::<Decimal>().ok().or_else(|| Decimal::from_scientific(s).ok()))
+                .map(Usd)
+                .ok_or(FromSqlError::InvalidType),
+            ValueRef::Real(real) => Ok(Usd::from_f64(real)),
+            ValueRef::Integer(int) => Ok(Usd(Decimal::from(int))),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serializes_as_string_and_reads_numbers() {
+        let amount = Usd::from(1234.5);
+        assert_eq!(serde_json::to_string(&amount).unwrap(), "\"1234.5\"");
+        assert_eq!(serde_json::from_str::<Usd>("\"0.1\"").unwrap(), Usd::from(0.1));
+        assert_eq!(serde_json::from_str::<Usd>("0.1").unwrap(), Usd::from(0.1));
+        assert_eq!(serde_json::from_str::<Usd>("250").unwrap(), Usd::from(250.0));
+    }
+
+    #[test]
+    fn test_from_f64_keeps_the_shortest_decimal() {
+        assert_eq!(Usd::from(0.1).to_string(), "0.1");
+        assert_eq!(Usd::from(84000.0).to_string(), "84000");
+        assert_eq!(Usd::from(f64::NAN), Usd::ZERO);
+    }
+
+    #[test]
+    fn test_division_by_zero_is_none() {
+        assert_eq!(Usd::from(1000.0).checked_div(5u8), Some(Usd::from(200.0)));
+        assert_eq!(Usd::from(1000.0).checked_div(0u8), None);
+        assert_eq!(Usd::from(250.0).ratio(Usd::from(1000.0)), Some(Decimal::new(25, 2)));
+        assert_eq!(Usd::from(250.0).ratio(Usd::ZERO), None);
+    }
+
+    #[test]
+    fn test_sqlite_round_trip_as_text() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (amount TEXT, legacy REAL)").unwrap();
+        conn.execute("INSERT INTO t VALUES (?1, 0.3)", [Usd::from(0.1) + Usd::from(0.2)]).unwrap();
+
+        let (stored, amount, legacy): (String, Usd, Usd) = conn
+            .query_row("SELECT amount, amount, legacy FROM t", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap();
+        assert_eq!(stored, "0.3");
+        assert_eq!(amount, Usd::from(0.3));
+        assert_eq!(legacy, Usd::from(0.3));
+    }
+}
@@ -1,7 +1,14 @@
 //! Background price feed - keeps prices up to date
+//!
+//! Providers are tried in order; a market missing from one provider (or a
+//! provider that errors) is filled in by the next. A market keeps its last
+//! price only when every provider fails for it.
 
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use futures::future::BoxFuture;
 use tokio::time::interval;
 use tracing::{info, warn};
 
@@ -9,88 +16,296 @@ use crate::state::AppState;
 use crate::types::Market;
 
 const COINGECKO_URL: &str = "https://api.coingecko.com/api/v3/simple/price";
+const BINANCE_URL: &str = "https://api.binance.com/api/v3/ticker/price";
+
+const ALL_MARKETS: [Market; 6] = [
+    Market::BtcPerp,
+    Market::EthPerp,
+    Market::SolPerp,
+    Market::DogePerp,
+    Market::AvaxPerp,
+    Market::LinkPerp,
+];
+
+/// A source of index prices
+pub trait PriceProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn fetch<'a>(&'a self, client: &'a reqwest::Client) -> BoxFuture<'a, Result<HashMap<Market, f64>, String>>;
+}
+
+/// CoinGecko simple price API
+pub struct CoinGecko;
+
+impl CoinGecko {
+    fn coin_id(market: Market) -> &'static str {
+        match market {
+            Market::BtcPerp => "bitcoin",
+            Market::EthPerp => "ethereum",
+            Market::SolPerp => "solana",
+            Market::DogePerp => "dogecoin",
+            Market::AvaxPerp => "avalanche-2",
+            Market::LinkPerp => "chainlink",
+        }
+    }
+}
+
+impl PriceProvider for CoinGecko {
+    fn name(&self) -> &'static str {
+        "coingecko"
+    }
+
+    fn fetch<'a>(&'a self, client: &'a reqwest::Client) -> BoxFuture<'a, Result<HashMap<Market, f64>, String>> {
+        Box::pin(async move {
+            let ids = ALL_MARKETS.iter().map(|m| Self::coin_id(*m)).collect::<Vec<_>>().join(",");
+            let resp = client
+                .get(COINGECKO_URL)
+                .query(&[("ids", ids.as_str()), ("vs_currencies", "usd")])
+                .header("User-Agent", "AI-Perp-DEX/1.0")
+                .send()
+                .await
+                .map_err(|e| format!("Request failed: {}", e))?;
+
+            let text = resp.text().await.map_err(|e| format!("Read failed: {}", e))?;
+            let data: serde_json::Value = serde_json::from_str(&text)
+                .map_err(|e| format!("Parse failed: {} - body: {}", e, &text[..100.min(text.len())]))?;
+
+            tracing::debug!("API response: {:?}", data);
+
+            Ok(Self::parse_prices(&data))
+        })
+    }
+}
+
+impl CoinGecko {
+    /// Extract `{coin_id: {usd: price}}` entries for every market
+    fn parse_prices(data: &serde_json::Value) -> HashMap<Market, f64> {
+        let mut prices = HashMap::new();
+        for market in ALL_MARKETS {
+            let coin = Self::coin_id(market);
+            if let Some(price) = data.get(coin).and_then(|v| v.get("usd")).and_then(|v| v.as_f64()) {
+                prices.insert(market, price);
+            } else {
+                warn!("Missing price for {} in data: {:?}", coin, data.get(coin));
+            }
+        }
+        prices
+    }
+}
+
+/// Binance spot ticker API (USDT pairs)
+pub struct Binance;
+
+impl Binance {
+    fn symbol(market: Market) -> &'static str {
+        match market {
+            Market::BtcPerp => "BTCUSDT",
+            Market::EthPerp => "ETHUSDT",
+            Market::SolPerp => "SOLUSDT",
+            Market::DogePerp => "DOGEUSDT",
+            Market::AvaxPerp => "AVAXUSDT",
+            Market::LinkPerp => "LINKUSDT",
+        }
+    }
+}
+
+impl PriceProvider for Binance {
+    fn name(&self) -> &'static str {
+        "binance"
+    }
+
+    fn fetch<'a>(&'a self, client: &'a reqwest::Client) -> BoxFuture<'a, Result<HashMap<Market, f64>, String>> {
+        Box::pin(async move {
+            let symbols = serde_json::to_string(
+                &ALL_MARKETS.iter().map(|m| Self::symbol(*m)).collect::<Vec<_>>()
+            ).map_err(|e| e.to_string())?;
+
+            let data: Vec<serde_json::Value> = client
+                .get(BINANCE_URL)
+                .query(&[("symbols", symbols)])
+                .send()
+                .await
+                .map_err(|e| format!("Request failed: {}", e))?
+                .error_for_status()
+                .map_err(|e| format!("Request failed: {}", e))?
+                .json()
+                .await
+                .map_err(|e| format!("Parse failed: {}", e))?;
+
+            let mut prices = HashMap::new();
+            for market in ALL_MARKETS {
+                let price = data.iter()
+                    .find(|t| t.get("symbol").and_then(|s| s.as_str()) == Some(Self::symbol(market)))
+                    .and_then(|t| t.get("price"))
+                    .and_then(|p| p.as_str())
+                    .and_then(|p| p.parse::<f64>().ok());
+                if let Some(price) = price {
+                    prices.insert(market, price);
+                }
+            }
+            Ok(prices)
+        })
+    }
+}
+
+/// Default provider order: CoinGecko, then Binance
+pub fn default_providers() -> Vec<Box<dyn PriceProvider>> {
+    vec![Box::new(CoinGecko), Box::new(Binance)]
+}
 
 /// Start background price updater
 pub async fn start_price_feed(state: Arc<AppState>, interval_secs: u64) {
     info!("📈 Price feed starting (interval: {}s)", interval_secs);
-    
+
     let mut ticker = interval(Duration::from_secs(interval_secs));
     let client = reqwest::Client::new();
-    
+    let providers = default_providers();
+
     loop {
-        ticker.tick().await;
-        
-        match fetch_prices(&client).await {
-            Ok(prices) => {
-                // Update state
-                if let Some(btc) = prices.get("bitcoin") {
-                    state.prices.insert(Market::BtcPerp, *btc);
-                }
-                if let Some(eth) = prices.get("ethereum") {
-                    state.prices.insert(Market::EthPerp, *eth);
-                }
-                if let Some(sol) = prices.get("solana") {
-                    state.prices.insert(Market::SolPerp, *sol);
-                }
-                if let Some(doge) = prices.get("dogecoin") {
-                    state.prices.insert(Market::DogePerp, *doge);
-                }
-                if let Some(avax) = prices.get("avalanche-2") {
-                    state.prices.insert(Market::AvaxPerp, *avax);
-                }
-                if let Some(link) = prices.get("chainlink") {
-                    state.prices.insert(Market::LinkPerp, *link);
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+
+        let updated = update_prices(&state, &client, &providers).await;
+        if updated == 0 {
+            warn!("Price fetch failed on all providers, keeping last prices");
+            continue;
+        }
+
+        info!("📈 Prices updated ({} markets): BTC=${:.0}, ETH=${:.0}, SOL=${:.0}, DOGE=${:.4}, AVAX=${:.1}, LINK=${:.1}",
+              updated,
+              state.prices.get(&Market::BtcPerp).map(|p| *p).unwrap_or(0.0),
+              state.prices.get(&Market::EthPerp).map(|p| *p).unwrap_or(0.0),
+              state.prices.get(&Market::SolPerp).map(|p| *p).unwrap_or(0.0),
+              state.prices.get(&Market::DogePerp).map(|p| *p).unwrap_or(0.0),
+              state.prices.get(&Market::AvaxPerp).map(|p| *p).unwrap_or(0.0),
+              state.prices.get(&Market::LinkPerp).map(|p| *p).unwrap_or(0.0));
+    }
+    info!("📈 Price feed stopped");
+}
+
+/// Fetch from the providers in order and apply the result to `state`.
+/// Returns the number of markets whose price was updated.
+pub async fn update_prices(
+    state: &AppState,
+    client: &reqwest::Client,
+    providers: &[Box<dyn PriceProvider>],
+) -> usize {
+    let mut prices: HashMap<Market, f64> = HashMap::new();
+
+    for provider in providers {
+        if prices.len() == ALL_MARKETS.len() {
+            break;
+        }
+        match provider.fetch(client).await {
+            Ok(fetched) => {
+                for (market, price) in fetched {
+                    if price > 0.0 && price.is_finite() {
+                        prices.entry(market).or_insert(price);
+                    }
                 }
-                
-                info!("📈 Prices updated: BTC=${:.0}, ETH=${:.0}, SOL=${:.0}, DOGE=${:.4}, AVAX=${:.1}, LINK=${:.1}",
-                      prices.get("bitcoin").unwrap_or(&0.0),
-                      prices.get("ethereum").unwrap_or(&0.0),
-                      prices.get("solana").unwrap_or(&0.0),
-                      prices.get("dogecoin").unwrap_or(&0.0),
-                      prices.get("avalanche-2").unwrap_or(&0.0),
-                      prices.get("chainlink").unwrap_or(&0.0));
-            }
-            Err(e) => {
-                warn!("Price fetch failed: {}", e);
             }
+            Err(e) => warn!("Price provider {} failed: {}", provider.name(), e),
         }
     }
+
+    let (now, instant) = (Utc::now(), Instant::now());
+    for (market, price) in &prices {
+        state.prices.insert(*market, *price);
+        state.price_updated_at.insert(*market, now);
+        state.last_price_update.insert(*market, instant);
+        state.smoothed_prices.update(*market, *price, instant);
+    }
+    prices.len()
 }
 
-async fn fetch_prices(client: &reqwest::Client) -> Result<std::collections::HashMap<String, f64>, String> {
-    let resp = client
-        .get(COINGECKO_URL)
-        .query(&[("ids", "bitcoin,ethereum,solana,dogecoin,avalanche-2,chainlink"), ("vs_currencies", "usd")])
-        .header("User-Agent", "AI-Perp-DEX/1.0")
-        .send()
-        .await
-        .map_err(|e| format!("Request failed: {}", e))?;
-    
-    let text = resp.text().await.map_err(|e| format!("Read failed: {}", e))?;
-    let data: serde_json::Value = serde_json::from_str(&text)
-        .map_err(|e| format!("Parse failed: {} - body: {}", e, &text[..100.min(text.len())]))?;
-    
-    let mut prices = std::collections::HashMap::new();
-    
-    // Parse with better error handling
-    tracing::debug!("API response: {:?}", data);
-    
-    for (coin, _) in [
-        ("bitcoin", "BTC"), 
-        ("ethereum", "ETH"), 
-        ("solana", "SOL"),
-        ("dogecoin", "DOGE"),
-        ("avalanche-2", "AVAX"),
-        ("chainlink", "LINK"),
-    ] {
-        if let Some(price) = data.get(coin).and_then(|v| v.get("usd")).and_then(|v| v.as_f64()) {
-            prices.insert(coin.to_string(), price);
-            tracing::debug!("Parsed {} = ${}", coin, price);
-        } else {
-            tracing::warn!("Missing price for {} in data: {:?}", coin, data.get(coin));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Failing;
+
+    impl PriceProvider for Failing {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn fetch<'a>(&'a self, _client: &'a reqwest::Client) -> BoxFuture<'a, Result<HashMap<Market, f64>, String>> {
+            Box::pin(async { Err("service unavailable".to_string()) })
+        }
+    }
+
+    struct Fixed(Vec<(Market, f64)>);
+
+    impl PriceProvider for Fixed {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        fn fetch<'a>(&'a self, _client: &'a reqwest::Client) -> BoxFuture<'a, Result<HashMap<Market, f64>, String>> {
+            Box::pin(async move { Ok(self.0.iter().copied().collect()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_supplies_price_when_primary_fails() {
+        let state = AppState::with_db_path(":memory:");
+        let client = reqwest::Client::new();
+        let providers: Vec<Box<dyn PriceProvider>> = vec![
+            Box::new(Failing),
+            Box::new(Fixed(vec![(Market::BtcPerp, 90000.0)])),
+        ];
+
+        assert_eq!(update_prices(&state, &client, &providers).await, 1);
+        assert_eq!(*state.prices.get(&Market::BtcPerp).unwrap(), 90000.0);
+        assert!(state.price_updated_at.contains_key(&Market::BtcPerp));
+
+        // Markets no provider covered keep their last price and timestamp
+        assert_eq!(*state.prices.get(&Market::EthPerp).unwrap(), 2200.0);
+        assert!(!state.price_updated_at.contains_key(&Market::EthPerp));
+    }
+
+    #[tokio::test]
+    async fn test_primary_wins_and_fallback_fills_gaps() {
+        let state = AppState::with_db_path(":memory:");
+        let client = reqwest::Client::new();
+        let providers: Vec<Box<dyn PriceProvider>> = vec![
+            Box::new(Fixed(vec![(Market::BtcPerp, 90000.0)])),
+            Box::new(Fixed(vec![(Market::BtcPerp, 1.0), (Market::SolPerp, 150.0)])),
+        ];
+
+        assert_eq!(update_prices(&state, &client, &providers).await, 2);
+        assert_eq!(*state.prices.get(&Market::BtcPerp).unwrap(), 90000.0);
+        assert_eq!(*state.prices.get(&Market::SolPerp).unwrap(), 150.0);
+
+        let all_failing: Vec<Box<dyn PriceProvider>> = vec![Box::new(Failing)];
+        assert_eq!(update_prices(&state, &client, &all_failing).await, 0);
+        assert_eq!(*state.prices.get(&Market::BtcPerp).unwrap(), 90000.0);
+    }
+
+    #[tokio::test]
+    async fn test_coingecko_response_prices_all_markets() {
+        let state = AppState::with_db_path(":memory:");
+        let client = reqwest::Client::new();
+        let response = serde_json::json!({
+            "bitcoin": {"usd": 95000.0},
+            "ethereum": {"usd": 3300.0},
+            "solana": {"usd": 190.0},
+            "dogecoin": {"usd": 0.32},
+            "avalanche-2": {"usd": 38.5},
+            "chainlink": {"usd": 21.7},
+        });
+        let parsed = CoinGecko::parse_prices(&response);
+        assert_eq!(parsed.len(), ALL_MARKETS.len());
+
+        let providers: Vec<Box<dyn PriceProvider>> = vec![Box::new(Fixed(parsed.into_iter().collect()))];
+        assert_eq!(update_prices(&state, &client, &providers).await, ALL_MARKETS.len());
+        assert_eq!(*state.prices.get(&Market::DogePerp).unwrap(), 0.32);
+        assert_eq!(*state.prices.get(&Market::AvaxPerp).unwrap(), 38.5);
+        assert_eq!(*state.prices.get(&Market::LinkPerp).unwrap(), 21.7);
+        for market in ALL_MARKETS {
+            assert!(state.price_updated_at.contains_key(&market), "{:?} not updated", market);
         }
     }
-    
-    tracing::info!("Fetched {} prices", prices.len());
-    Ok(prices)
 }
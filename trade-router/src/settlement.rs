@@ -2,7 +2,7 @@
 //! 调用 Python Settlement Service 进行链上结算
 
 use serde::{Deserialize, Serialize};
-use tracing::{info, warn, error};
+use tracing::{info, warn};
 
 const SETTLEMENT_URL: &str = "http://localhost:8081";
 
@@ -27,6 +27,14 @@ pub struct ClosePositionRequest {
     pub exit_price: u64,
 }
 
+#[derive(Debug, Serialize)]
+pub struct ReducePositionRequest {
+    pub owner: String,
+    pub market_index: u8,
+    pub close_size: u64,
+    pub exit_price: u64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SettlementResponse {
     pub success: bool,
@@ -35,6 +43,7 @@ pub struct SettlementResponse {
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct CollateralResponse {
     pub agent: String,
     pub owner: String,
@@ -57,15 +66,16 @@ impl SettlementClient {
         }
     }
 
-    /// 检查服务健康状态
-    pub async fn health_check(&self) -> bool {
-        match self.client.get(format!("{}/health", self.base_url)).send().await {
+    /// 检查服务健康状态，超时视为不可达
+    pub async fn health_check(&self, timeout: std::time::Duration) -> bool {
+        match self.client.get(format!("{}/health", self.base_url)).timeout(timeout).send().await {
             Ok(resp) => resp.status().is_success(),
             Err(_) => false,
         }
     }
 
     /// 查询链上抵押金
+    #[allow(dead_code)]
     pub async fn get_collateral(&self, owner: &str) -> Result<CollateralResponse, String> {
         let url = format!("{}/collateral/{}", self.base_url, owner);
         
@@ -169,6 +179,52 @@ impl SettlementClient {
 
         Ok(result)
     }
+
+    /// 链上部分平仓结算 (close_size 为平掉的合约数量)
+    pub async fn settle_reduce_position(
+        &self,
+        owner: &str,
+        market: &str,
+        close_size: u64,
+        exit_price: f64,
+    ) -> Result<SettlementResponse, String> {
+        let market_index = match market {
+            "BTC-PERP" => 0,
+            "ETH-PERP" => 1,
+            "SOL-PERP" => 2,
+            _ => return Err(format!("Unknown market: {}", market)),
+        };
+
+        let price_raw = (exit_price * 1_000_000.0) as u64;
+
+        let req = ReducePositionRequest {
+            owner: owner.to_string(),
+            market_index,
+            close_size,
+            exit_price: price_raw,
+        };
+
+        info!("Settling partial close on-chain: {:?}", req);
+
+        let resp = self.client
+            .post(format!("{}/settle/reduce", self.base_url))
+            .json(&req)
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        let result: SettlementResponse = resp.json()
+            .await
+            .map_err(|e| format!("Parse failed: {}", e))?;
+
+        if result.success {
+            info!("Partial close settled: {:?}", result.signature);
+        } else {
+            warn!("Settlement failed: {:?}", result.error);
+        }
+
+        Ok(result)
+    }
 }
 
 impl Default for SettlementClient {
@@ -183,8 +239,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_health_check() {
-        let client = SettlementClient::new();
-        // This will fail if settlement service is not running
-        let _ = client.health_check().await;
+        // 不可达的服务在超时内返回 false
+        let client = SettlementClient::with_url("http://127.0.0.1:1");
+        assert!(!client.health_check(std::time::Duration::from_millis(500)).await);
     }
 }
@@ -0,0 +1,418 @@
+//! Settlement queue - persists on-chain settlement jobs and retries failures
+//!
+//! Opens, closes and partial closes are enqueued in `settlement_queue` (one
+//! job per fill or close, keyed by job id) and a background worker pushes them
+//! to the settlement service, backing off exponentially between attempts. Jobs
+//! that run out of attempts stay in the table as `failed` for operators.
+
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::time::interval;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::money::Usd;
+use crate::settlement::SettlementClient;
+use crate::state::AppState;
+use crate::types::{Market, Position, SettlementAction, SettlementJob, SettlementJobStatus, Side, TradeRequest};
+
+/// Settlement retry configuration
+#[derive(Debug, Clone)]
+pub struct SettlementQueueConfig {
+    /// How often the worker looks for due jobs
+    pub poll_interval: Duration,
+    /// Delay before the first retry; doubles on every further failure
+    pub base_delay: Duration,
+    /// Upper bound on the delay between two attempts
+    pub max_delay: Duration,
+    /// Attempts after which a job is marked failed
+    pub max_attempts: u32,
+    /// Jobs attempted per poll
+    pub batch_size: u32,
+}
+
+impl Default for SettlementQueueConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(2),
+            base_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(600),
+            max_attempts: 10,
+            batch_size: 50,
+        }
+    }
+}
+
+impl SettlementQueueConfig {
+    /// Defaults overridden by `SETTLEMENT_RETRY_BASE_SECS`, `SETTLEMENT_RETRY_MAX_SECS`
+    /// and `SETTLEMENT_RETRY_MAX_ATTEMPTS`
+    pub fn from_env() -> Self {
+        let env = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        let default = Self::default();
+        Self {
+            base_delay: env("SETTLEMENT_RETRY_BASE_SECS").map(Duration::from_secs).unwrap_or(default.base_delay),
+            max_delay: env("SETTLEMENT_RETRY_MAX_SECS").map(Duration::from_secs).unwrap_or(default.max_delay),
+            max_attempts: env("SETTLEMENT_RETRY_MAX_ATTEMPTS").map(|n| n as u32).unwrap_or(default.max_attempts),
+            ..default
+        }
+    }
+
+    /// Delay before the next attempt once `attempts` attempts have failed
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// Queue the on-chain open for a filled request. Only the filled size is sent
+/// (negative for shorts); merging with an existing position happens on-chain
+pub fn enqueue_open(state: &AppState, position_id: Uuid, filled: &TradeRequest) {
+    let size = contract_size(filled.size_usdc, filled.side);
+    let price = state.prices.get(&filled.market).map(|p| *p).unwrap_or(0.0);
+    enqueue(state, new_job(position_id, SettlementAction::Open, &filled.agent_id, filled.market, size, price));
+}
+
+/// Queue the on-chain close of `closed_size` of `position` (its size before
+/// the close) at `exit_price`. Closing less than the whole position reduces
+/// it on-chain by the closed size instead of closing it outright
+pub fn enqueue_close(state: &AppState, position: &Position, closed_size: Usd, exit_price: f64) {
+    let action = if closed_size < position.size_usdc {
+        SettlementAction::Reduce
+    } else {
+        SettlementAction::Close
+    };
+    let size = contract_size(closed_size, position.side);
+    enqueue(state, new_job(position.id, action, &position.trader_agent, position.market, size, exit_price));
+}
+
+/// Signed on-chain size (negative for shorts) of `size_usdc`
+fn contract_size(size_usdc: Usd, side: Side) -> i64 {
+    let size = (size_usdc.to_f64() * 1000.0) as i64; // Convert to contract units
+    match side {
+        Side::Long => size,
+        Side::Short => -size,
+    }
+}
+
+fn new_job(position_id: Uuid, action: SettlementAction, owner: &str, market: Market, size: i64, price: f64) -> SettlementJob {
+    let now = Utc::now();
+    SettlementJob {
+        id: Uuid::new_v4(),
+        position_id,
+        action,
+        owner: owner.to_string(),
+        market,
+        size,
+        price,
+        status: SettlementJobStatus::Pending,
+        attempts: 0,
+        next_attempt_at: now,
+        last_error: None,
+        created_at: now,
+    }
+}
+
+fn enqueue(state: &AppState, job: SettlementJob) {
+    match state.db.enqueue_settlement(&job) {
+        Ok(true) => {}
+        Ok(false) => info!("Settlement job {} already queued", job.id),
+        Err(e) => error!("Failed to queue settlement {} for {}: {}", job.action.as_str(), job.position_id, e),
+    }
+}
+
+/// Start the settlement worker as a background task
+pub async fn start_settlement_worker(state: Arc<AppState>, config: SettlementQueueConfig) {
+    info!("⛓️ Settlement worker starting (poll: {}s, base backoff: {}s, max attempts: {})",
+          config.poll_interval.as_secs(), config.base_delay.as_secs(), config.max_attempts);
+
+    let mut ticker = interval(config.poll_interval);
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+
+        let (settled, failed) = process_due(&state, &config, Utc::now()).await;
+        if settled > 0 || failed > 0 {
+            info!("⛓️ Settled {} jobs on-chain, {} failed", settled, failed);
+        }
+    }
+    info!("⛓️ Settlement worker stopped");
+}
+
+/// Attempt every job due at `now`, returning (settled, failed attempts)
+pub async fn process_due(state: &AppState, config: &SettlementQueueConfig, now: DateTime<Utc>) -> (usize, usize) {
+    let jobs = match state.db.get_due_settlements(now, config.batch_size) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            error!("Failed to load due settlements: {}", e);
+            return (0, 0);
+        }
+    };
+
+    let (mut settled, mut failed) = (0, 0);
+    for mut job in jobs {
+        job.attempts += 1;
+        match attempt(&state.settlement, &job).await {
+            Ok(signature) => {
+                info!("Settlement {} for {} confirmed: {:?}", job.action.as_str(), job.position_id, signature);
+                job.status = SettlementJobStatus::Done;
+                job.last_error = None;
+                settled += 1;
+            }
+            Err(e) => {
+                if job.attempts >= config.max_attempts {
+                    error!("Settlement {} for {} gave up after {} attempts: {}",
+                           job.action.as_str(), job.position_id, job.attempts, e);
+                    job.status = SettlementJobStatus::Failed;
+                } else {
+                    let delay = config.backoff(job.attempts);
+                    warn!("Settlement {} for {} failed (attempt {}), retrying in {}s: {}",
+                          job.action.as_str(), job.position_id, job.attempts, delay.as_secs(), e);
+                    job.next_attempt_at = now + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX);
+                }
+                job.last_error = Some(e);
+                failed += 1;
+            }
+        }
+        if let Err(e) = state.db.update_settlement(&job) {
+            error!("Failed to update settlement {} for {}: {}", job.action.as_str(), job.position_id, e);
+        }
+    }
+    (settled, failed)
+}
+
+/// Send one job to the settlement service; a rejected transaction counts as a failure
+async fn attempt(client: &SettlementClient, job: &SettlementJob) -> Result<Option<String>, String> {
+    let market = job.market.symbol();
+    let resp = match job.action {
+        SettlementAction::Open => client.settle_open_position(&job.owner, market, job.size, job.price).await?,
+        SettlementAction::Close => client.settle_close_position(&job.owner, market, job.price).await?,
+        SettlementAction::Reduce => client.settle_reduce_position(&job.owner, market, job.size.unsigned_abs(), job.price).await?,
+    };
+    if resp.success {
+        Ok(resp.signature)
+    } else {
+        Err(resp.error.unwrap_or_else(|| "Settlement rejected".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Usd;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use axum::{Json, Router, routing::post};
+
+    /// Settlement service stub that rejects the first `failures` calls
+    async fn stub_settlement(failures: usize) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let handler = move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if n < failures {
+                    Json(serde_json::json!({ "success": false, "signature": null, "error": "rpc unavailable" }))
+                } else {
+                    Json(serde_json::json!({ "success": true, "signature": "sig", "error": null }))
+                }
+            }
+        };
+        let app = Router::new()
+            .route("/settle/open", post(handler.clone()))
+            .route("/settle/close", post(handler));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, calls)
+    }
+
+    fn request(market: Market, side: Side) -> TradeRequest {
+        TradeRequest {
+            id: Uuid::new_v4(),
+            agent_id: "trader".to_string(),
+            market,
+            side,
+            size_usdc: Usd::from(100.0),
+            leverage: 5,
+            max_funding_rate: 0.01,
+            expires_at: Utc::now(),
+            created_at: Utc::now(),
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: Default::default(),
+            reduce_only: false,
+        }
+    }
+
+    fn position(id: Uuid, market: Market, side: Side) -> Position {
+        Position {
+            id,
+            request_id: Uuid::new_v4(),
+            quote_id: Uuid::new_v4(),
+            trader_agent: "trader".to_string(),
+            mm_agent: "mm".to_string(),
+            market,
+            side,
+            size_usdc: Usd::from(100.0),
+            leverage: 5,
+            entry_price: Usd::from(3000.0),
+            funding_rate: 0.001,
+            trader_collateral: Usd::from(20.0),
+            mm_collateral: Usd::from(20.0),
+            status: crate::types::PositionStatus::Active,
+            created_at: Utc::now(),
+            closed_at: None,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: Default::default(),
+            liquidation_price: Usd::ZERO,
+            last_funding_at: None,
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let config = SettlementQueueConfig {
+            base_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(60),
+            ..Default::default()
+        };
+        assert_eq!(config.backoff(1), Duration::from_secs(5));
+        assert_eq!(config.backoff(2), Duration::from_secs(10));
+        assert_eq!(config.backoff(4), Duration::from_secs(40));
+        assert_eq!(config.backoff(5), Duration::from_secs(60));
+        assert_eq!(config.backoff(64), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_every_fill_and_close_is_queued() {
+        let state = AppState::with_db_path(":memory:");
+        let position_id = Uuid::new_v4();
+
+        // Two fills merged into one position settle as two opens
+        enqueue_open(&state, position_id, &request(Market::EthPerp, Side::Short));
+        enqueue_open(&state, position_id, &request(Market::EthPerp, Side::Short));
+        let short = position(position_id, Market::EthPerp, Side::Short);
+        enqueue_close(&state, &short, short.size_usdc, 3000.0);
+
+        let jobs = state.db.get_unsettled().unwrap();
+        assert_eq!(jobs.len(), 3);
+        assert_eq!(jobs[0].action, SettlementAction::Open);
+        assert_eq!(jobs[0].size, -100_000);
+        assert_eq!(jobs[0].market, Market::EthPerp);
+        assert_eq!(jobs[1].action, SettlementAction::Open);
+        assert_ne!(jobs[0].id, jobs[1].id);
+        assert_eq!(jobs[2].action, SettlementAction::Close);
+        assert_eq!(jobs[2].size, -100_000);
+        assert_eq!(jobs[2].price, 3000.0);
+
+        // Re-enqueueing the same job is a no-op
+        assert!(!state.db.enqueue_settlement(&jobs[0]).unwrap());
+        assert_eq!(state.db.get_unsettled().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_repeated_partial_closes_each_settle() {
+        let state = AppState::with_db_path(":memory:");
+        let long = position(Uuid::new_v4(), Market::BtcPerp, Side::Long);
+
+        // The first reduce is still pending when the second is queued
+        enqueue_close(&state, &long, Usd::from(25.0), 97000.0);
+        enqueue_close(&state, &long, Usd::from(30.0), 98000.0);
+
+        let jobs = state.db.get_unsettled().unwrap();
+        assert_eq!(jobs.iter().map(|j| (j.action, j.size)).collect::<Vec<_>>(), vec![
+            (SettlementAction::Reduce, 25_000),
+            (SettlementAction::Reduce, 30_000),
+        ]);
+
+        // Marking one done leaves the other pending
+        let mut first = jobs[0].clone();
+        first.status = SettlementJobStatus::Done;
+        state.db.update_settlement(&first).unwrap();
+        let pending = state.db.get_unsettled().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, jobs[1].id);
+    }
+
+    #[test]
+    fn test_partial_close_reduces_by_the_closed_size() {
+        let state = AppState::with_db_path(":memory:");
+        let long = position(Uuid::new_v4(), Market::BtcPerp, Side::Long);
+
+        enqueue_close(&state, &long, Usd::from(25.0), 97000.0);
+
+        let job = state.db.get_settlement(&long.id, SettlementAction::Reduce).unwrap().unwrap();
+        assert_eq!(job.size, 25_000);
+        assert_eq!(job.owner, "trader");
+        assert!(state.db.get_settlement(&long.id, SettlementAction::Close).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failed_settlement_is_retried_until_done() {
+        let (url, calls) = stub_settlement(2).await;
+        let mut state = AppState::with_db_path(":memory:");
+        state.settlement = SettlementClient::with_url(&url);
+        let config = SettlementQueueConfig::default();
+        let position_id = Uuid::new_v4();
+        let long = position(position_id, Market::BtcPerp, Side::Long);
+        enqueue_close(&state, &long, long.size_usdc, 97000.0);
+
+        // First attempt is rejected and pushed back by the base delay
+        let now = Utc::now();
+        assert_eq!(process_due(&state, &config, now).await, (0, 1));
+        let job = state.db.get_settlement(&position_id, SettlementAction::Close).unwrap().unwrap();
+        assert_eq!(job.status, SettlementJobStatus::Pending);
+        assert_eq!(job.attempts, 1);
+        assert_eq!(job.last_error.as_deref(), Some("rpc unavailable"));
+        assert_eq!(job.next_attempt_at.timestamp_millis(), (now + chrono::Duration::seconds(5)).timestamp_millis());
+
+        // Not due again until the backoff elapses
+        assert_eq!(process_due(&state, &config, now).await, (0, 0));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Second failure doubles the delay
+        let now = job.next_attempt_at;
+        assert_eq!(process_due(&state, &config, now).await, (0, 1));
+        let job = state.db.get_settlement(&position_id, SettlementAction::Close).unwrap().unwrap();
+        assert_eq!(job.next_attempt_at.timestamp_millis(), (now + chrono::Duration::seconds(10)).timestamp_millis());
+
+        // Third attempt succeeds
+        assert_eq!(process_due(&state, &config, job.next_attempt_at).await, (1, 0));
+        let job = state.db.get_settlement(&position_id, SettlementAction::Close).unwrap().unwrap();
+        assert_eq!(job.status, SettlementJobStatus::Done);
+        assert_eq!(job.attempts, 3);
+        assert!(job.last_error.is_none());
+        assert!(state.db.get_unsettled().unwrap().is_empty());
+
+        // Done jobs are never attempted again
+        assert_eq!(process_due(&state, &config, job.next_attempt_at + chrono::Duration::days(1)).await, (0, 0));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_job_is_marked_failed_after_max_attempts() {
+        let mut state = AppState::with_db_path(":memory:");
+        // Nothing listens here, so every attempt errors out
+        state.settlement = SettlementClient::with_url("http://127.0.0.1:1");
+        let config = SettlementQueueConfig { max_attempts: 2, ..Default::default() };
+        let position_id = Uuid::new_v4();
+        enqueue_open(&state, position_id, &request(Market::BtcPerp, Side::Long));
+
+        let now = Utc::now();
+        assert_eq!(process_due(&state, &config, now).await, (0, 1));
+        let far_future = now + chrono::Duration::days(1);
+        assert_eq!(process_due(&state, &config, far_future).await, (0, 1));
+
+        let job = state.db.get_settlement(&position_id, SettlementAction::Open).unwrap().unwrap();
+        assert_eq!(job.status, SettlementJobStatus::Failed);
+        assert_eq!(job.attempts, 2);
+        assert!(job.last_error.is_some());
+        // Failed jobs stay visible but are no longer retried
+        assert_eq!(state.db.get_unsettled().unwrap().len(), 1);
+        assert_eq!(process_due(&state, &config, far_future).await, (0, 0));
+    }
+}
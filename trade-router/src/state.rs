@@ -1,12 +1,20 @@
 use crate::db::Database;
+use crate::event_log::{Event, EventLog};
+use crate::margin::{MarginConfig, PositionMarginInfo};
+use crate::mark_price::MarkPrice;
+use crate::money::Usd;
+use crate::metrics::Metrics;
 use crate::settlement::SettlementClient;
 use crate::types::{
-    AgentInfo, AgentStats, Market, Position, PositionStatus, PositionWithPnl, Quote, RiskLimits,
-    Side, TradeRequest, WsMessage,
+    AgentInfo, AgentStats, Market, OpenInterest, Position, PositionStatus, PositionWithPnl, PrivateMessage,
+    Quote, RiskLimits, Side, TradeRecord, TradeRequest, WsMessage,
 };
-use dashmap::DashMap;
-use std::sync::Arc;
+use dashmap::{DashMap, DashSet};
+use rust_decimal::Decimal;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 /// 应用状态 - 线程安全
@@ -22,8 +30,16 @@ pub struct AppState {
     pub agent_positions: Arc<DashMap<String, Vec<Uuid>>>,
     /// WebSocket 广播频道
     pub broadcast_tx: broadcast::Sender<WsMessage>,
+    /// 私有消息广播 (每个连接只转发给自己 Agent 的消息)
+    pub private_tx: broadcast::Sender<PrivateMessage>,
     /// 模拟价格 (实际应从 Oracle 获取)
     pub prices: Arc<DashMap<Market, f64>>,
+    /// 每个市场价格最后一次成功更新的时间
+    pub price_updated_at: Arc<DashMap<Market, chrono::DateTime<chrono::Utc>>>,
+    /// 指数价格的 EMA 平滑值，强平与保证金检查使用
+    pub smoothed_prices: Arc<MarkPrice>,
+    /// 每个市场价格最后一次更新的单调时钟时间，用于判断价格是否过期
+    pub last_price_update: Arc<DashMap<Market, Instant>>,
     /// 注册的 Agent (内存缓存)
     pub agents: Arc<DashMap<String, AgentInfo>>,
     /// API Key -> Agent ID 映射
@@ -34,6 +50,30 @@ pub struct AppState {
     pub db: Arc<Database>,
     /// 链上结算客户端
     pub settlement: SettlementClient,
+    /// 标记价格 (撮合引擎盘口中间价)，用于计算资金费率溢价
+    pub mark_prices: Arc<DashMap<Market, f64>>,
+    /// 当前 8 小时资金费率 (正数: 多头付给空头)
+    pub funding_rates: Arc<DashMap<Market, f64>>,
+    /// 无法支付资金费、等待强平的仓位
+    pub liquidation_flags: Arc<DashSet<Uuid>>,
+    /// 已发出保证金预警、尚未恢复的仓位
+    pub margin_calls: Arc<DashSet<Uuid>>,
+    /// 已被接受的交易请求 (合并到已有仓位时仓位不带该 request_id)
+    pub accepted_requests: Arc<DashSet<Uuid>>,
+    /// 已被接受的报价
+    pub accepted_quotes: Arc<DashSet<Uuid>>,
+    /// 保险基金余额 (清算手续费流入，穿仓亏损从中支出)
+    pub insurance_fund: Arc<Mutex<Usd>>,
+    /// Prometheus 指标
+    pub metrics: Arc<Metrics>,
+    /// 每个市场活跃仓位的多空未平仓量
+    pub open_interest: Arc<DashMap<Market, OpenInterest>>,
+    /// 关闭信号，后台任务和 WebSocket 连接收到后退出
+    pub shutdown: CancellationToken,
+    /// 状态变更事件日志 (默认不写入，由 main 挂载文件)
+    pub event_log: Arc<EventLog>,
+    /// 运维 API key (与 Agent key 独立)，None 时关闭运维接口
+    pub admin_key: Option<String>,
 }
 
 impl AppState {
@@ -49,6 +89,7 @@ impl AppState {
         
         let db = Database::new(db_path).expect("Failed to open database");
         let (broadcast_tx, _) = broadcast::channel(1000);
+        let (private_tx, _) = broadcast::channel(1000);
         
         let state = Self {
             requests: Arc::new(DashMap::new()),
@@ -56,14 +97,65 @@ impl AppState {
             positions: Arc::new(DashMap::new()),
             agent_positions: Arc::new(DashMap::new()),
             broadcast_tx,
+            private_tx,
             prices: Arc::new(DashMap::new()),
+            price_updated_at: Arc::new(DashMap::new()),
+            last_price_update: Arc::new(DashMap::new()),
+            smoothed_prices: Arc::new(MarkPrice::default()),
             agents: Arc::new(DashMap::new()),
             api_keys: Arc::new(DashMap::new()),
             agent_limits: Arc::new(DashMap::new()),
             db: Arc::new(db),
             settlement: SettlementClient::new(),
+            mark_prices: Arc::new(DashMap::new()),
+            funding_rates: Arc::new(DashMap::new()),
+            liquidation_flags: Arc::new(DashSet::new()),
+            margin_calls: Arc::new(DashSet::new()),
+            accepted_requests: Arc::new(DashSet::new()),
+            accepted_quotes: Arc::new(DashSet::new()),
+            insurance_fund: Arc::new(Mutex::new(Usd::ZERO)),
+            metrics: Arc::new(Metrics::new()),
+            open_interest: Arc::new(DashMap::new()),
+            shutdown: CancellationToken::new(),
+            event_log: Arc::new(EventLog::disabled()),
+            admin_key: None,
         };
         
+        match state.db.get_insurance_fund_balance() {
+            Ok(balance) => *state.insurance_fund.lock().unwrap() = balance,
+            Err(e) => tracing::error!("Failed to load insurance fund balance: {}", e),
+        }
+        
+        // 恢复活跃仓位并重建 agent 索引
+        match state.db.get_all_active_positions() {
+            Ok(positions) => {
+                for pos in positions {
+                    state.agent_positions.entry(pos.trader_agent.clone()).or_default().push(pos.id);
+                    state.agent_positions.entry(pos.mm_agent.clone()).or_default().push(pos.id);
+                    state.adjust_open_interest(pos.market, pos.side, pos.size_usdc);
+                    state.accepted_requests.insert(pos.request_id);
+                    state.accepted_quotes.insert(pos.quote_id);
+                    state.positions.insert(pos.id, pos);
+                }
+            }
+            Err(e) => tracing::error!("Failed to load active positions from DB: {}", e),
+        }
+        
+        // 恢复未过期的请求及其报价
+        match state.db.load_active_trade_requests(chrono::Utc::now()) {
+            Ok(requests) => {
+                for req in requests {
+                    let quotes = state.db.load_quotes(&req.id).unwrap_or_else(|e| {
+                        tracing::error!("Failed to load quotes for {}: {}", req.id, e);
+                        Vec::new()
+                    });
+                    state.quotes.insert(req.id, quotes);
+                    state.requests.insert(req.id, req);
+                }
+            }
+            Err(e) => tracing::error!("Failed to load trade requests from DB: {}", e),
+        }
+        
         // 初始化模拟价格
         state.prices.insert(Market::BtcPerp, 84000.0);
         state.prices.insert(Market::EthPerp, 2200.0);
@@ -75,6 +167,63 @@ impl AppState {
         state
     }
     
+    /// 开始优雅关闭: 通知 WebSocket 客户端，并让后台任务在当前一轮结束后退出
+    pub fn begin_shutdown(&self) {
+        if self.shutdown.is_cancelled() {
+            return;
+        }
+        // 先广播再取消，连接在关闭前能收到这条消息
+        let _ = self.broadcast_tx.send(WsMessage::ShuttingDown);
+        self.shutdown.cancel();
+    }
+    
+    /// 将内存中的活跃仓位、请求和报价写回数据库 (关闭前调用)，返回写入的记录数。
+    /// 单条写入失败只记录日志，不影响其余记录
+    pub fn persist_all(&self) -> usize {
+        let mut written = 0;
+        for position in self.positions.iter().filter(|p| p.status == PositionStatus::Active) {
+            match self.db.save_position(&position) {
+                Ok(()) => written += 1,
+                Err(e) => tracing::error!("Failed to persist position {}: {}", position.id, e),
+            }
+        }
+        for request in self.requests.iter() {
+            match self.db.save_trade_request(&request) {
+                Ok(()) => written += 1,
+                Err(e) => tracing::error!("Failed to persist trade request {}: {}", request.id, e),
+            }
+        }
+        for quote in self.quotes.iter().flat_map(|q| q.value().clone()) {
+            match self.db.save_quote(&quote) {
+                Ok(()) => written += 1,
+                Err(e) => tracing::error!("Failed to persist quote {}: {}", quote.id, e),
+            }
+        }
+        written
+    }
+    
+    /// 市场当前的多空未平仓量
+    pub fn open_interest(&self, market: Market) -> OpenInterest {
+        self.open_interest.get(&market).map(|oi| *oi).unwrap_or_default()
+    }
+    
+    /// 仓位开、减、平或被清算时调整未平仓量 (`delta_usdc` 为负表示减少)
+    pub fn adjust_open_interest(&self, market: Market, side: Side, delta_usdc: Usd) {
+        let mut oi = self.open_interest.entry(market).or_default();
+        let side_oi = match side {
+            Side::Long => &mut oi.long,
+            Side::Short => &mut oi.short,
+        };
+        // 未平仓量不会为负数
+        *side_oi = (*side_oi + delta_usdc).max(Usd::ZERO);
+    }
+    
+    /// 风控使用的平滑标记价格，尚无平滑值时退回原始指数价格
+    pub fn smoothed_price(&self, market: Market) -> Option<f64> {
+        self.smoothed_prices.get(market)
+            .or_else(|| self.prices.get(&market).map(|p| *p))
+    }
+    
     /// 注册 Agent (内存 + 持久化)
     pub fn register_agent(&self, agent: AgentInfo) {
         // Persist to database
@@ -123,9 +272,31 @@ impl AppState {
         None
     }
     
+    /// 推送只给 `agent_id` 的消息 (没有在线连接时丢弃)
+    pub fn notify_agent(&self, agent_id: &str, message: WsMessage) {
+        let _ = self.private_tx.send(PrivateMessage { agent_id: agent_id.to_string(), message });
+    }
+    
+    /// 把仓位最新状态推送给交易方和 MM
+    pub fn notify_position(&self, position: &Position) {
+        self.notify_agent(&position.trader_agent, WsMessage::PositionUpdated(position.clone()));
+        self.notify_agent(&position.mm_agent, WsMessage::PositionUpdated(position.clone()));
+    }
+    
+    /// 在修改状态之前写入事件日志；写入失败只记录日志，不阻止操作
+    pub fn record(&self, event: Event) {
+        if let Err(e) = self.event_log.append(event) {
+            tracing::error!("Failed to append to event log: {}", e);
+        }
+    }
+    
     /// 添加交易请求
     pub fn add_request(&self, req: TradeRequest) {
         let id = req.id;
+        self.record(Event::RequestOpened(req.clone()));
+        if let Err(e) = self.db.save_trade_request(&req) {
+            tracing::error!("Failed to save trade request to DB: {}", e);
+        }
         self.requests.insert(id, req.clone());
         self.quotes.insert(id, Vec::new());
         
@@ -144,6 +315,10 @@ impl AppState {
         
         // 添加报价
         if let Some(mut quotes) = self.quotes.get_mut(&request_id) {
+            self.record(Event::QuoteSubmitted(quote.clone()));
+            if let Err(e) = self.db.save_quote(&quote) {
+                tracing::error!("Failed to save quote to DB: {}", e);
+            }
             quotes.push(quote);
             Ok(())
         } else {
@@ -151,8 +326,101 @@ impl AppState {
         }
     }
     
-    /// 接受报价，创建仓位
+    /// 撤销交易请求并清除其报价 (归属由调用方校验)
+    pub fn cancel_request(&self, request_id: Uuid) -> Option<TradeRequest> {
+        if !self.requests.contains_key(&request_id) {
+            return None;
+        }
+        self.record(Event::RequestCancelled { request_id });
+        let (_, request) = self.requests.remove(&request_id)?;
+        self.quotes.remove(&request_id);
+        if let Err(e) = self.db.delete_trade_request(&request_id) {
+            tracing::error!("Failed to delete trade request from DB: {}", e);
+        }
+        
+        let _ = self.broadcast_tx.send(WsMessage::RequestCancelled { request_id });
+        Some(request)
+    }
+    
+    /// 查找仍在报价列表中的报价
+    pub fn find_quote(&self, quote_id: Uuid) -> Option<Quote> {
+        self.quotes.iter()
+            .find_map(|quotes| quotes.iter().find(|q| q.id == quote_id).cloned())
+    }
+    
+    /// 从请求的报价列表中撤回报价 (归属由调用方校验)
+    pub fn withdraw_quote(&self, quote_id: Uuid) -> Option<Quote> {
+        let request_id = self.find_quote(quote_id)?.request_id;
+        self.record(Event::QuoteWithdrawn { quote_id });
+        let quote = {
+            let mut quotes = self.quotes.get_mut(&request_id)?;
+            let index = quotes.iter().position(|q| q.id == quote_id)?;
+            quotes.remove(index)
+        };
+        if let Err(e) = self.db.delete_quote(&quote_id) {
+            tracing::error!("Failed to delete quote from DB: {}", e);
+        }
+        
+        let _ = self.broadcast_tx.send(WsMessage::QuoteWithdrawn { request_id, quote_id });
+        Some(quote)
+    }
+    
+    /// 选出资金费率最低、仍在有效期内且不超过上限的报价
+    pub fn best_quote(&self, request_id: Uuid, max_funding_rate: f64) -> Option<Quote> {
+        let now = chrono::Utc::now();
+        self.quotes.get(&request_id)?
+            .iter()
+            .filter(|q| q.valid_until > now && q.funding_rate <= max_funding_rate)
+            .min_by(|a, b| a.funding_rate.total_cmp(&b.funding_rate))
+            .cloned()
+    }
+    
+    /// 接受报价，创建仓位。同一 trader/MM 在同一市场、同一杠杆下已有活跃仓位时合并到该仓位
+    /// (与链上 `open_position` 一致)：同向加仓按名义价值混合开仓价，反向则先对冲
+    /// 已有仓位，超出部分再开新仓
     pub fn accept_quote(&self, request_id: Uuid, quote_id: Uuid) -> Result<Position, String> {
+        let request = self.requests.get(&request_id)
+            .ok_or("Trade request not found")?
+            .clone();
+        
+        // 获取当前价格
+        let entry_price = self.prices.get(&request.market)
+            .map(|p| *p)
+            .unwrap_or(0.0);
+        
+        let quote = self.quotes.get(&request_id)
+            .and_then(|quotes| quotes.iter().find(|q| q.id == quote_id).cloned());
+        if let Some(quote) = quote {
+            let funding_rate = self.funding_rates.get(&request.market).map(|r| *r).unwrap_or(0.0);
+            check_quote_protection(&quote, entry_price, funding_rate, chrono::Utc::now())?;
+            
+            // 只有开新仓 (不与已有仓位合并) 时才占用仓位数
+            if !request.reduce_only && self.find_mergeable_position(&request, &quote).is_none() {
+                let active = self.get_agent_positions(&request.agent_id)
+                    .iter()
+                    .filter(|p| p.status == PositionStatus::Active)
+                    .count();
+                check_open_positions(active, &self.get_agent_limits(&request.agent_id))?;
+            }
+        }
+        
+        let position_id = Uuid::new_v4();
+        let opened_at = chrono::Utc::now();
+        let position = self.accept_quote_at(request_id, quote_id, position_id, entry_price, opened_at)?;
+        // 只记录成功的接受，失败的接受重放时不能生成仓位
+        self.record(Event::QuoteAccepted { request_id, quote_id, position_id, entry_price, opened_at });
+        Ok(position)
+    }
+    
+    /// 按给定价格接受报价；需要开新仓时使用 `position_id` 和 `opened_at` (供事件重放)
+    pub fn accept_quote_at(
+        &self,
+        request_id: Uuid,
+        quote_id: Uuid,
+        position_id: Uuid,
+        entry_price: f64,
+        opened_at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Position, String> {
         // 获取请求
         let request = self.requests.get(&request_id)
             .ok_or("Trade request not found")?
@@ -166,34 +434,102 @@ impl AppState {
             .cloned()
             .ok_or("Quote not found")?;
         
-        // 获取当前价格
-        let entry_price = self.prices.get(&request.market)
-            .map(|p| *p)
-            .unwrap_or(0.0);
+        let new_position = NewPosition { id: position_id, entry_price, opened_at };
+        let mergeable = self.find_mergeable_position(&request, &quote);
+        if request.reduce_only {
+            check_reduce_only(&request, mergeable.as_ref())?;
+        }
+        let position = match mergeable {
+            Some(existing) if entry_price <= 0.0 || !existing.entry_price.is_positive() => {
+                return Err(format!("No price available to merge into position {}", existing.id));
+            }
+            Some(existing) if existing.side == request.side => {
+                self.increase_position(existing.id, &request, &quote, entry_price)?
+            }
+            Some(existing) => self.net_position(existing.id, &request, &quote, new_position)?,
+            None => self.open_position(&request, &quote, request.size_usdc, new_position)?,
+        };
         
-        // 创建仓位
-        let position = Position {
-            id: Uuid::new_v4(),
-            request_id,
-            quote_id,
+        // 清理请求和报价 (先标记为已接受，撤销/撤回时据此区分已接受和不存在)
+        self.accepted_requests.insert(request_id);
+        self.accepted_quotes.insert(quote_id);
+        self.requests.remove(&request_id);
+        self.quotes.remove(&request_id);
+        if let Err(e) = self.db.delete_trade_request(&request_id) {
+            tracing::error!("Failed to delete trade request from DB: {}", e);
+        }
+        
+        // 广播
+        let _ = self.broadcast_tx.send(WsMessage::QuoteAccepted { 
+            request_id, 
+            quote_id, 
+            position_id: position.id 
+        });
+        for (agent_id, side) in [(&request.agent_id, request.side), (&quote.agent_id, request.side.opposite())] {
+            self.notify_agent(agent_id, WsMessage::OrderFilled {
+                request_id,
+                quote_id,
+                position_id: position.id,
+                market: request.market,
+                side,
+                size_usdc: request.size_usdc,
+                price: entry_price,
+            });
+        }
+        
+        Ok(position)
+    }
+    
+    /// 同一 trader 与 MM 在同一市场、同一杠杆下最早的活跃仓位
+    fn find_mergeable_position(&self, request: &TradeRequest, quote: &Quote) -> Option<Position> {
+        self.get_agent_positions(&request.agent_id)
+            .into_iter()
+            .filter(|p| {
+                p.status == PositionStatus::Active
+                    && p.trader_agent == request.agent_id
+                    && p.mm_agent == quote.agent_id
+                    && p.market == request.market
+                    && p.leverage == request.leverage
+                    && p.margin_mode == request.margin_mode
+            })
+            .min_by_key(|p| p.created_at)
+    }
+    
+    /// 为请求开一个 `size_usdc` 大小的新仓位 (可能小于请求大小，MM 保证金按比例计)
+    fn open_position(&self, request: &TradeRequest, quote: &Quote, size_usdc: Usd, new: NewPosition) -> Result<Position, String> {
+        let trader_collateral = crate::margin::initial_margin(size_usdc, request.leverage)
+            .ok_or("Leverage must be at least 1x")?;
+        let mm_share = size_usdc.ratio(request.size_usdc)
+            .ok_or("Trade request size must be positive")?;
+        let mut position = Position {
+            id: new.id,
+            request_id: request.id,
+            quote_id: quote.id,
             trader_agent: request.agent_id.clone(),
             mm_agent: quote.agent_id.clone(),
             market: request.market,
             side: request.side,
-            size_usdc: request.size_usdc,
+            size_usdc,
             leverage: request.leverage,
-            entry_price,
+            entry_price: Usd::from(new.entry_price),
             funding_rate: quote.funding_rate,
-            trader_collateral: request.size_usdc / request.leverage as f64,
-            mm_collateral: quote.collateral_usdc,
+            trader_collateral,
+            mm_collateral: quote.collateral_usdc * mm_share,
             status: PositionStatus::Active,
-            created_at: chrono::Utc::now(),
+            created_at: new.opened_at,
             closed_at: None,
+            stop_loss: request.stop_loss,
+            take_profit: request.take_profit,
+            margin_mode: request.margin_mode,
+            liquidation_price: Usd::ZERO,
+            last_funding_at: None,
         };
+        position.refresh_liquidation_price();
         
         // 保存仓位到内存
         let pos_id = position.id;
         self.positions.insert(pos_id, position.clone());
+        self.adjust_open_interest(position.market, position.side, position.size_usdc);
         
         // 持久化到数据库
         if let Err(e) = self.db.save_position(&position) {
@@ -201,61 +537,230 @@ impl AppState {
         }
         
         // 更新 agent 索引
-        self.agent_positions.entry(request.agent_id).or_insert(Vec::new()).push(pos_id);
-        self.agent_positions.entry(quote.agent_id).or_insert(Vec::new()).push(pos_id);
-        
-        // 清理请求和报价
-        self.requests.remove(&request_id);
-        self.quotes.remove(&request_id);
+        self.agent_positions.entry(request.agent_id.clone()).or_default().push(pos_id);
+        self.agent_positions.entry(quote.agent_id.clone()).or_default().push(pos_id);
         
-        // 广播
-        let _ = self.broadcast_tx.send(WsMessage::QuoteAccepted { 
-            request_id, 
-            quote_id, 
-            position_id: pos_id 
-        });
         let _ = self.broadcast_tx.send(WsMessage::PositionOpened(position.clone()));
-        
+        self.notify_position(&position);
         Ok(position)
     }
     
-    /// 平仓
-    pub fn close_position(&self, position_id: Uuid, _agent_id: &str) -> Result<(f64, f64), String> {
+    /// 同向加仓：规模与保证金相加，开仓价按名义价值混合 (保持合约数量不变)
+    fn increase_position(&self, position_id: Uuid, request: &TradeRequest, quote: &Quote, price: f64) -> Result<Position, String> {
         let mut position = self.positions.get_mut(&position_id)
             .ok_or("Position not found")?;
         
+        let added = request.size_usdc;
+        let total = position.size_usdc + added;
+        let added_collateral = crate::margin::initial_margin(added, request.leverage)
+            .ok_or("Leverage must be at least 1x")?;
+        let contracts = position.size_usdc.ratio(position.entry_price)
+            .zip(added.ratio(Usd::from(price)))
+            .map(|(held, added)| held + added)
+            .ok_or("No price available to merge into position")?;
+        position.entry_price = total.checked_div(contracts)
+            .ok_or("No size to merge into position")?;
+        position.funding_rate = (position.funding_rate * position.size_usdc.to_f64() + quote.funding_rate * added.to_f64()) / total.to_f64();
+        position.size_usdc = total;
+        position.trader_collateral += added_collateral;
+        position.mm_collateral += quote.collateral_usdc;
+        if request.stop_loss.is_some() {
+            position.stop_loss = request.stop_loss;
+        }
+        if request.take_profit.is_some() {
+            position.take_profit = request.take_profit;
+        }
+        position.refresh_liquidation_price();
+        self.adjust_open_interest(position.market, position.side, added);
+        
+        if let Err(e) = self.db.save_position(&position) {
+            tracing::error!("Failed to save position to DB: {}", e);
+        }
+        
+        let _ = self.broadcast_tx.send(WsMessage::PositionIncreased {
+            position_id,
+            added_size_usdc: added,
+            size_usdc: position.size_usdc,
+            entry_price: position.entry_price,
+        });
+        self.notify_position(&position);
+        
+        Ok(position.clone())
+    }
+    
+    /// 反向成交：先按当前价平掉已有仓位的对冲部分，剩余部分开反向新仓
+    fn net_position(&self, position_id: Uuid, request: &TradeRequest, quote: &Quote, new: NewPosition) -> Result<Position, String> {
+        let (netted, existing) = {
+            let mut position = self.positions.get_mut(&position_id)
+                .ok_or("Position not found")?;
+            let netted = request.size_usdc.min(position.size_usdc);
+            self.realize(&mut position, netted, new.entry_price);
+            (netted, position.clone())
+        };
+        
+        let remainder = request.size_usdc - netted;
+        if remainder.is_positive() {
+            self.open_position(request, quote, remainder, new)
+        } else {
+            Ok(existing)
+        }
+    }
+    
+    /// 平仓 `size_percent`% (相对当前剩余仓位)，返回已实现的 (pnl_trader, pnl_mm)
+    pub fn close_position(&self, position_id: Uuid, agent_id: &str, size_percent: u8) -> Result<(Usd, Usd), String> {
+        if size_percent == 0 || size_percent > 100 {
+            return Err("size_percent must be between 1 and 100".to_string());
+        }
+        
+        let position = self.positions.get(&position_id)
+            .map(|p| p.clone())
+            .ok_or("Position not found")?;
+        
+        if position.trader_agent != agent_id {
+            return Err("Only the position's trader can close it".to_string());
+        }
         if position.status != PositionStatus::Active {
             return Err("Position is not active".to_string());
         }
         
         // 获取当前价格
-        let current_price = self.prices.get(&position.market)
+        let exit_price = self.prices.get(&position.market)
             .map(|p| *p)
-            .unwrap_or(position.entry_price);
+            .unwrap_or(position.entry_price.to_f64());
         
-        // 计算 PnL
-        let price_change = (current_price - position.entry_price) / position.entry_price;
-        let leveraged_change = price_change * position.leverage as f64;
+        let pnl = self.close_position_at(position_id, size_percent, exit_price)?;
+        self.record(Event::PositionClosed { position_id, size_percent, exit_price });
+        Ok(pnl)
+    }
+    
+    /// 按给定价格平仓 `size_percent`% (供事件重放)
+    pub fn close_position_at(&self, position_id: Uuid, size_percent: u8, exit_price: f64) -> Result<(Usd, Usd), String> {
+        let mut position = self.positions.get_mut(&position_id)
+            .ok_or("Position not found")?;
+        
+        if position.status != PositionStatus::Active {
+            return Err("Position is not active".to_string());
+        }
         
-        let (pnl_trader, pnl_mm) = match position.side {
-            Side::Long => {
-                let trader_pnl = position.size_usdc * leveraged_change;
-                (trader_pnl, -trader_pnl)
+        let closed_size = position.size_usdc * Decimal::new(size_percent.into(), 2);
+        Ok(self.realize(&mut position, closed_size, exit_price))
+    }
+    
+    /// 运维强制结算: 按给定价格全部平仓并实现盈亏 (不触发链上结算)，返回 (pnl_trader, pnl_mm)
+    pub fn force_settle(&self, position_id: Uuid, price: f64) -> Result<(Usd, Usd), String> {
+        if !(price > 0.0 && price.is_finite()) {
+            return Err("price must be positive".to_string());
+        }
+        self.ensure_active(position_id)?;
+        
+        let (pnl_trader, pnl_mm) = self.close_position_at(position_id, 100, price)?;
+        self.record(Event::PositionClosed { position_id, size_percent: 100, exit_price: price });
+        
+        let _ = self.broadcast_tx.send(WsMessage::ForceSettled {
+            position_id,
+            price: Some(price),
+            pnl_trader,
+            pnl_mm,
+        });
+        Ok((pnl_trader, pnl_mm))
+    }
+    
+    /// 运维撤销仓位: 按零盈亏关闭，双方保证金原样退回
+    pub fn cancel_position(&self, position_id: Uuid) -> Result<(), String> {
+        self.ensure_active(position_id)?;
+        self.record(Event::PositionCancelled { position_id });
+        self.unwind_position(position_id)
+    }
+    
+    /// 按零盈亏关闭仓位 (供事件重放)
+    pub fn unwind_position(&self, position_id: Uuid) -> Result<(), String> {
+        let mut position = self.positions.get_mut(&position_id)
+            .ok_or("Position not found")?;
+        if position.status != PositionStatus::Active {
+            return Err("Position is not active".to_string());
+        }
+        
+        position.status = PositionStatus::Closed;
+        position.closed_at = Some(chrono::Utc::now());
+        self.adjust_open_interest(position.market, position.side, -position.size_usdc);
+        if let Err(e) = self.db.close_position(&position_id, Usd::ZERO, Usd::ZERO) {
+            tracing::error!("Failed to close position in DB: {}", e);
+        }
+        
+        let _ = self.broadcast_tx.send(WsMessage::ForceSettled {
+            position_id,
+            price: None,
+            pnl_trader: Usd::ZERO,
+            pnl_mm: Usd::ZERO,
+        });
+        self.notify_position(&position);
+        Ok(())
+    }
+    
+    fn ensure_active(&self, position_id: Uuid) -> Result<(), String> {
+        let position = self.positions.get(&position_id)
+            .ok_or("Position not found")?;
+        if position.status != PositionStatus::Active {
+            return Err("Position is not active".to_string());
+        }
+        Ok(())
+    }
+    
+    /// 按 `exit_price` 平掉仓位中 `closed_size` 的部分并记录成交，返回已实现的 (pnl_trader, pnl_mm)
+    fn realize(&self, position: &mut Position, closed_size: Usd, exit_price: f64) -> (Usd, Usd) {
+        let position_id = position.id;
+        
+        // 计算平仓部分的 PnL
+        let pnl_trader = crate::margin::pnl_for_size(position, closed_size, exit_price);
+        let pnl_mm = -pnl_trader;
+        let exit_price = Usd::from(exit_price);
+        
+        if closed_size < position.size_usdc {
+            // 部分平仓: 仓位和保证金按比例减少，剩余部分保持活跃
+            let mut closed = position.clone();
+            closed.size_usdc = closed_size;
+            closed.closed_at = Some(chrono::Utc::now());
+            
+            let fraction = closed_size.ratio(position.size_usdc).unwrap_or(Decimal::ONE);
+            position.size_usdc -= closed_size;
+            self.adjust_open_interest(position.market, position.side, -closed_size);
+            position.trader_collateral -= position.trader_collateral * fraction;
+            position.mm_collateral -= position.mm_collateral * fraction;
+            position.refresh_liquidation_price();
+            
+            if let Err(e) = self.db.save_position(position) {
+                tracing::error!("Failed to save reduced position to DB: {}", e);
             }
-            Side::Short => {
-                let trader_pnl = position.size_usdc * (-leveraged_change);
-                (trader_pnl, -trader_pnl)
+            let trade = TradeRecord::from_closed_position(&closed, exit_price, pnl_trader, pnl_mm);
+            if let Err(e) = self.db.save_trade(&trade) {
+                tracing::error!("Failed to save trade to DB: {}", e);
             }
-        };
+            
+            let _ = self.broadcast_tx.send(WsMessage::PositionReduced {
+                position_id,
+                closed_size_usdc: closed_size,
+                remaining_size_usdc: position.size_usdc,
+                pnl_trader,
+                pnl_mm,
+            });
+            self.notify_position(position);
+            
+            return (pnl_trader, pnl_mm);
+        }
         
         // 更新状态
         position.status = PositionStatus::Closed;
         position.closed_at = Some(chrono::Utc::now());
+        self.adjust_open_interest(position.market, position.side, -position.size_usdc);
         
         // 持久化到数据库
         if let Err(e) = self.db.close_position(&position_id, pnl_trader, pnl_mm) {
             tracing::error!("Failed to close position in DB: {}", e);
         }
+        let trade = TradeRecord::from_closed_position(position, exit_price, pnl_trader, pnl_mm);
+        if let Err(e) = self.db.save_trade(&trade) {
+            tracing::error!("Failed to save trade to DB: {}", e);
+        }
         
         // 广播
         let _ = self.broadcast_tx.send(WsMessage::PositionClosed { 
@@ -263,8 +768,98 @@ impl AppState {
             pnl_trader, 
             pnl_mm 
         });
+        self.notify_position(position);
         
-        Ok((pnl_trader, pnl_mm))
+        (pnl_trader, pnl_mm)
+    }
+    
+    /// 设置止损/止盈价格 (None 表示取消)
+    pub fn set_stop_levels(
+        &self,
+        position_id: Uuid,
+        agent_id: &str,
+        stop_loss: Option<f64>,
+        take_profit: Option<f64>,
+    ) -> Result<Position, String> {
+        {
+            let position = self.positions.get(&position_id)
+                .ok_or("Position not found")?;
+            
+            if position.trader_agent != agent_id {
+                return Err("Only the position's trader can modify it".to_string());
+            }
+            if position.status != PositionStatus::Active {
+                return Err("Position is not active".to_string());
+            }
+            
+            let current_price = self.prices.get(&position.market)
+                .map(|p| *p)
+                .unwrap_or(position.entry_price.to_f64());
+            crate::liquidation::validate_stop_levels(position.side, stop_loss, take_profit, current_price)?;
+        }
+        
+        self.record(Event::StopLevelsSet { position_id, stop_loss, take_profit });
+        self.apply_stop_levels(position_id, stop_loss, take_profit)
+    }
+    
+    /// 不做价格校验地写入止损/止盈 (校验在 `set_stop_levels` 中完成，供事件重放)
+    pub fn apply_stop_levels(
+        &self,
+        position_id: Uuid,
+        stop_loss: Option<f64>,
+        take_profit: Option<f64>,
+    ) -> Result<Position, String> {
+        let mut position = self.positions.get_mut(&position_id)
+            .ok_or("Position not found")?;
+        
+        position.stop_loss = stop_loss;
+        position.take_profit = take_profit;
+        
+        if let Err(e) = self.db.save_position(&position) {
+            tracing::error!("Failed to save position to DB: {}", e);
+        }
+        self.notify_position(&position);
+        
+        Ok(position.clone())
+    }
+    
+    /// 追加交易方保证金，返回更新后的保证金信息
+    pub fn add_margin(&self, position_id: Uuid, agent_id: &str, add_usdc: Usd) -> Result<PositionMarginInfo, String> {
+        if !add_usdc.is_positive() {
+            return Err("add_usdc must be positive".to_string());
+        }
+        
+        let mut position = self.positions.get_mut(&position_id)
+            .ok_or("Position not found")?;
+        
+        if position.trader_agent != agent_id {
+            return Err("Only the position's trader can add margin".to_string());
+        }
+        if position.status != PositionStatus::Active {
+            return Err("Position is not active".to_string());
+        }
+        
+        self.record(Event::MarginAdded { position_id, agent_id: agent_id.to_string(), add_usdc });
+        position.trader_collateral += add_usdc;
+        position.refresh_liquidation_price();
+        
+        if let Err(e) = self.db.save_position(&position) {
+            tracing::error!("Failed to save position to DB: {}", e);
+        }
+        
+        let current_price = self.smoothed_price(position.market)
+            .unwrap_or(position.entry_price.to_f64());
+        let info = PositionMarginInfo::from_position(&position, current_price, &MarginConfig::default());
+        
+        let _ = self.broadcast_tx.send(WsMessage::MarginAdded {
+            position_id,
+            add_usdc,
+            trader_collateral: position.trader_collateral,
+            liquidation_price: info.liquidation_price,
+        });
+        self.notify_position(&position);
+        
+        Ok(info)
     }
     
     /// 获取 agent 的所有仓位
@@ -305,6 +900,17 @@ impl AppState {
             .map_err(|e| format!("Database error: {}", e))
     }
     
+    /// 获取 agent 的成交历史，支持分页
+    pub fn get_trade_history(
+        &self,
+        agent_id: &str,
+        limit: u32,
+        offset: u32,
+    ) -> Result<(Vec<TradeRecord>, u32), String> {
+        self.db.get_trades_by_agent(agent_id, limit, offset)
+            .map_err(|e| format!("Database error: {}", e))
+    }
+    
     /// 获取 Agent 交易统计
     pub fn get_agent_stats(&self, agent_id: &str) -> Result<AgentStats, String> {
         self.db.get_agent_stats(agent_id)
@@ -325,7 +931,8 @@ impl AppState {
     }
     
     /// 检查交易请求是否符合风险限额
-    pub fn check_risk_limits(&self, agent_id: &str, size_usdc: f64, leverage: u8) -> Result<(), String> {
+    /// 只减仓请求 (`reduce_only`) 用于降低风险，不受仓位数、总敞口和日亏损限额约束
+    pub fn check_risk_limits(&self, agent_id: &str, size_usdc: Usd, leverage: u8, reduce_only: bool) -> Result<(), String> {
         let limits = self.get_agent_limits(agent_id);
         
         // 检查单仓大小
@@ -344,14 +951,20 @@ impl AppState {
             ));
         }
         
-        // 计算当前总敞口
-        let current_exposure: f64 = self.get_agent_positions(agent_id)
-            .iter()
+        if reduce_only {
+            return Ok(());
+        }
+        
+        let active: Vec<Position> = self.get_agent_positions(agent_id)
+            .into_iter()
             .filter(|p| p.status == PositionStatus::Active)
-            .map(|p| p.size_usdc)
-            .sum();
+            .collect();
+        
+        // 检查活跃仓位数
+        check_open_positions(active.len(), &limits)?;
         
         // 检查新仓位是否会超过总敞口限额
+        let current_exposure: Usd = active.iter().map(|p| p.size_usdc).sum();
         if current_exposure + size_usdc > limits.max_total_exposure {
             return Err(format!(
                 "Total exposure {} would exceed max allowed {}",
@@ -359,44 +972,14 @@ impl AppState {
             ));
         }
         
-        // 检查日亏损限额
-        let today_start = chrono::Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap();
-        let today_start = chrono::DateTime::<chrono::Utc>::from_naive_utc_and_offset(today_start, chrono::Utc);
-        
-        let daily_loss: f64 = self.positions
-            .iter()
-            .filter(|p| {
-                (p.trader_agent == agent_id || p.mm_agent == agent_id) &&
-                p.status == PositionStatus::Closed &&
-                p.closed_at.map(|t| t >= today_start).unwrap_or(false)
-            })
-            .map(|p| {
-                // 简化: 从 DB 查更准确，这里用内存估算
-                let current_price = self.prices.get(&p.market).map(|pr| *pr).unwrap_or(p.entry_price);
-                let price_change = (current_price - p.entry_price) / p.entry_price;
-                let leveraged_change = price_change * p.leverage as f64;
-                
-                let pnl = if p.trader_agent == agent_id {
-                    match p.side {
-                        Side::Long => p.size_usdc * leveraged_change,
-                        Side::Short => p.size_usdc * (-leveraged_change),
-                    }
-                } else {
-                    // MM 方向相反
-                    match p.side {
-                        Side::Long => -p.size_usdc * leveraged_change,
-                        Side::Short => -p.size_usdc * (-leveraged_change),
-                    }
-                };
-                
-                if pnl < 0.0 { -pnl } else { 0.0 }
-            })
-            .sum();
-        
-        if daily_loss > limits.daily_loss_limit {
+        // 检查日亏损限额 (当日 UTC 已实现盈亏)
+        let today_start = chrono::Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let realized = self.db.get_realized_pnl_since(agent_id, today_start)
+            .map_err(|e| format!("Failed to load realized PnL: {}", e))?;
+        if -realized >= limits.daily_loss_limit {
             return Err(format!(
-                "Daily loss {} exceeds limit {}",
-                daily_loss, limits.daily_loss_limit
+                "Daily loss {:.2} reached limit {}",
+                -realized, limits.daily_loss_limit
             ));
         }
         
@@ -404,8 +987,730 @@ impl AppState {
     }
 }
 
+/// 活跃仓位数已达上限时不能再开新仓
+fn check_open_positions(active: usize, limits: &RiskLimits) -> Result<(), String> {
+    if active >= limits.max_open_positions as usize {
+        return Err(format!(
+            "Open positions {} reached max allowed {}",
+            active, limits.max_open_positions
+        ));
+    }
+    Ok(())
+}
+
+/// 带保护的报价过了确定期后，市场价格或资金费率相对报价时的变动不能超过容差
+fn check_quote_protection(quote: &Quote, price: f64, funding_rate: f64, now: chrono::DateTime<chrono::Utc>) -> Result<(), String> {
+    let Some(protection) = &quote.protection else { return Ok(()) };
+    if now < quote.created_at + chrono::Duration::milliseconds(protection.min_valid_ms as i64) {
+        return Ok(());
+    }
+    let tolerance = protection.tolerance_bps as f64;
+    if protection.reference_price > 0.0 {
+        let price_move = (price - protection.reference_price).abs() / protection.reference_price * 10_000.0;
+        if price_move > tolerance {
+            return Err(format!(
+                "Price moved {:.1} bps since quote {} (tolerance {} bps)",
+                price_move, quote.id, protection.tolerance_bps
+            ));
+        }
+    }
+    let funding_move = (funding_rate - protection.reference_funding_rate).abs() * 10_000.0;
+    if funding_move > tolerance {
+        return Err(format!(
+            "Funding rate moved {:.1} bps since quote {} (tolerance {} bps)",
+            funding_move, quote.id, protection.tolerance_bps
+        ));
+    }
+    Ok(())
+}
+
+/// 接受报价时新开仓位的参数 (在事件中记录以便确定性重放)
+#[derive(Debug, Clone, Copy)]
+struct NewPosition {
+    id: Uuid,
+    entry_price: f64,
+    opened_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// 只减仓请求只能在与同一 MM 的反向仓位内抵消，不能开仓、加仓或反手
+fn check_reduce_only(request: &TradeRequest, existing: Option<&Position>) -> Result<(), String> {
+    match existing {
+        None => Err("Reduce-only request has no position to reduce".to_string()),
+        Some(position) if position.side == request.side => {
+            Err(format!("Reduce-only request would increase position {}", position.id))
+        }
+        Some(position) if request.size_usdc > position.size_usdc => {
+            Err(format!(
+                "Reduce-only request of {} USDC exceeds position {} of {} USDC",
+                request.size_usdc, position.id, position.size_usdc
+            ))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
 impl Default for AppState {
     fn default() -> Self {
         Self::new()
     }
 }
+
+/// 测试用 RFQ 成交: 默认 "trader" 与 "mm" 在 BTC 开 1000 USDC、5x 逐仓多单，MM 按初始保证金出资
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub(crate) struct TestTrade<'a> {
+    pub trader: &'a str,
+    pub mm: &'a str,
+    pub market: Market,
+    pub side: Side,
+    pub size_usdc: f64,
+    pub leverage: u8,
+    pub max_funding_rate: f64,
+    pub funding_rate: f64,
+    pub stop_loss: Option<f64>,
+    pub take_profit: Option<f64>,
+    pub margin_mode: crate::types::MarginMode,
+    pub reduce_only: bool,
+}
+
+#[cfg(test)]
+impl Default for TestTrade<'_> {
+    fn default() -> Self {
+        Self {
+            trader: "trader",
+            mm: "mm",
+            market: Market::BtcPerp,
+            side: Side::Long,
+            size_usdc: 1000.0,
+            leverage: 5,
+            max_funding_rate: 0.01,
+            funding_rate: 0.001,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: crate::types::MarginMode::Isolated,
+            reduce_only: false,
+        }
+    }
+}
+
+#[cfg(test)]
+impl TestTrade<'_> {
+    /// 发起请求、报价并接受，返回接受报价的结果
+    pub(crate) fn submit(&self, state: &AppState) -> Result<Position, String> {
+        let now = chrono::Utc::now();
+        let request = TradeRequest {
+            id: Uuid::new_v4(),
+            agent_id: self.trader.to_string(),
+            market: self.market,
+            side: self.side,
+            size_usdc: Usd::from(self.size_usdc),
+            leverage: self.leverage,
+            max_funding_rate: self.max_funding_rate,
+            stop_loss: self.stop_loss,
+            take_profit: self.take_profit,
+            margin_mode: self.margin_mode,
+            reduce_only: self.reduce_only,
+            expires_at: now + chrono::Duration::seconds(60),
+            created_at: now,
+        };
+        let quote = Quote {
+            id: Uuid::new_v4(),
+            request_id: request.id,
+            agent_id: self.mm.to_string(),
+            funding_rate: self.funding_rate,
+            collateral_usdc: crate::margin::initial_margin(request.size_usdc, self.leverage).unwrap_or_default(),
+            valid_until: now + chrono::Duration::seconds(30),
+            created_at: now,
+            protection: None,
+        };
+        let (request_id, quote_id) = (request.id, quote.id);
+        
+        state.add_request(request);
+        state.add_quote(quote).unwrap();
+        state.accept_quote(request_id, quote_id)
+    }
+    
+    /// 同 `submit`，但必须开仓成功
+    pub(crate) fn open(&self, state: &AppState) -> Position {
+        self.submit(state).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{MarginMode, QuoteProtection};
+    use chrono::{Duration, Utc};
+    
+    /// 发起请求、报价并接受，返回新开的仓位
+    fn open_position(state: &AppState, side: Side, size_usdc: f64, leverage: u8) -> Position {
+        open_position_in(state, Market::BtcPerp, "mm", side, size_usdc, leverage)
+    }
+    
+    fn open_position_in(state: &AppState, market: Market, mm: &str, side: Side, size_usdc: f64, leverage: u8) -> Position {
+        submit_rfq(state, market, mm, side, size_usdc, leverage, false).unwrap()
+    }
+    
+    /// 走完整个 RFQ 流程，返回接受报价的结果
+    fn submit_rfq(
+        state: &AppState,
+        market: Market,
+        mm: &str,
+        side: Side,
+        size_usdc: f64,
+        leverage: u8,
+        reduce_only: bool,
+    ) -> Result<Position, String> {
+        TestTrade { market, mm, side, size_usdc, leverage, reduce_only, ..Default::default() }.submit(state)
+    }
+    
+    #[test]
+    fn test_close_position_records_trade() {
+        let state = AppState::with_db_path(":memory:");
+        let position = open_position(&state, Side::Long, 1000.0, 10);
+        
+        state.prices.insert(Market::BtcPerp, 88200.0);
+        let (pnl_trader, pnl_mm) = state.close_position(position.id, "trader", 100).unwrap();
+        
+        let (trades, total) = state.get_trade_history("trader", 20, 0).unwrap();
+        assert_eq!(total, 1);
+        let trade = &trades[0];
+        assert_eq!(trade.position_id, position.id);
+        assert_eq!(trade.side, Side::Long);
+        assert_eq!(trade.entry_price, Usd::from(84000.0));
+        assert_eq!(trade.exit_price, Usd::from(88200.0));
+        assert_eq!(trade.pnl_trader, Usd::from(500.0));
+        assert_eq!(trade.pnl_trader, pnl_trader);
+        assert_eq!(trade.pnl_mm, pnl_mm);
+        
+        // The MM sees the same trade from the other side
+        let (mm_trades, _) = state.get_trade_history("mm", 20, 0).unwrap();
+        assert_eq!(mm_trades[0].id, trade.id);
+    }
+    
+    #[test]
+    fn test_trade_history_pagination() {
+        let state = AppState::with_db_path(":memory:");
+        for _ in 0..3 {
+            let position = open_position(&state, Side::Short, 500.0, 5);
+            state.close_position(position.id, "trader", 100).unwrap();
+        }
+        
+        let (page, total) = state.get_trade_history("trader", 2, 0).unwrap();
+        assert_eq!((page.len(), total), (2, 3));
+        let (page, _) = state.get_trade_history("trader", 2, 2).unwrap();
+        assert_eq!(page.len(), 1);
+    }
+    
+    /// 按市场汇总活跃仓位的多空名义价值
+    fn active_notional(state: &AppState, market: Market) -> OpenInterest {
+        let mut oi = OpenInterest::default();
+        for p in state.positions.iter().filter(|p| p.market == market && p.status == PositionStatus::Active) {
+            match p.side {
+                Side::Long => oi.long += p.size_usdc,
+                Side::Short => oi.short += p.size_usdc,
+            }
+        }
+        oi
+    }
+    
+    #[test]
+    fn test_open_interest_tracks_active_notional() {
+        let state = AppState::with_db_path(":memory:");
+        let btc_long = open_position_in(&state, Market::BtcPerp, "mm", Side::Long, 1000.0, 5);
+        let btc_short = open_position_in(&state, Market::BtcPerp, "mm-2", Side::Short, 400.0, 5);
+        open_position_in(&state, Market::EthPerp, "mm", Side::Short, 2500.0, 2);
+        
+        assert_eq!(state.open_interest(Market::BtcPerp), OpenInterest { long: Usd::from(1000.0), short: Usd::from(400.0) });
+        assert_eq!(state.open_interest(Market::EthPerp), OpenInterest { long: Usd::ZERO, short: Usd::from(2500.0) });
+        assert_eq!(state.open_interest(Market::SolPerp).total(), Usd::ZERO);
+        
+        // 部分平仓只减少平掉的部分
+        state.close_position(btc_long.id, "trader", 25).unwrap();
+        assert_eq!(state.open_interest(Market::BtcPerp).long, Usd::from(750.0));
+        
+        state.close_position(btc_short.id, "trader", 100).unwrap();
+        for market in [Market::BtcPerp, Market::EthPerp] {
+            assert_eq!(state.open_interest(market), active_notional(&state, market));
+        }
+        assert_eq!(state.open_interest(Market::BtcPerp).short, Usd::ZERO);
+    }
+    
+    #[test]
+    fn test_zero_leverage_request_fails_without_panicking() {
+        let state = AppState::with_db_path(":memory:");
+        state.prices.insert(Market::BtcPerp, 84000.0);
+        
+        // 绕过创建时的市场约束 (如旧数据库中的请求)
+        let err = submit_rfq(&state, Market::BtcPerp, "mm", Side::Long, 1000.0, 0, false).unwrap_err();
+        assert!(err.contains("at least 1x"));
+        assert!(state.positions.is_empty());
+    }
+    
+    #[test]
+    fn test_adding_to_position_blends_entry() {
+        let state = AppState::with_db_path(":memory:");
+        let first = open_position(&state, Side::Long, 1000.0, 5);
+        state.prices.insert(Market::BtcPerp, 88000.0);
+        let merged = open_position(&state, Side::Long, 1000.0, 5);
+        
+        assert_eq!(merged.id, first.id);
+        assert_eq!(state.get_agent_positions("trader").len(), 1);
+        assert_eq!(merged.size_usdc, Usd::from(2000.0));
+        assert_eq!(merged.trader_collateral, Usd::from(400.0));
+        assert_eq!(merged.mm_collateral, Usd::from(400.0));
+        // 合约数量不变: 2000 / (1000/84000 + 1000/88000)
+        let expected_entry = 2000.0 / (1000.0 / 84000.0 + 1000.0 / 88000.0);
+        assert!((merged.entry_price.to_f64() - expected_entry).abs() < 1e-6);
+        assert_eq!(state.open_interest(Market::BtcPerp).long, Usd::from(2000.0));
+        
+        // 不同杠杆或不同 MM 仍开新仓
+        open_position(&state, Side::Long, 500.0, 10);
+        open_position_in(&state, Market::BtcPerp, "mm-2", Side::Long, 500.0, 5);
+        assert_eq!(state.get_agent_positions("trader").len(), 3);
+    }
+    
+    #[test]
+    fn test_opposite_side_nets_against_position() {
+        let state = AppState::with_db_path(":memory:");
+        let long = open_position(&state, Side::Long, 1000.0, 5);
+        state.prices.insert(Market::BtcPerp, 88200.0);
+        
+        // 较小的反向成交只减少已有仓位，并按当前价实现盈亏
+        let reduced = open_position(&state, Side::Short, 400.0, 5);
+        assert_eq!(reduced.id, long.id);
+        assert_eq!(reduced.side, Side::Long);
+        assert_eq!(reduced.size_usdc, Usd::from(600.0));
+        assert_eq!(reduced.trader_collateral, Usd::from(120.0));
+        let (trades, _) = state.get_trade_history("trader", 10, 0).unwrap();
+        assert_eq!(trades[0].pnl_trader, Usd::from(100.0));
+        
+        // 超出部分翻转为新的空头仓位
+        let flipped = open_position(&state, Side::Short, 1000.0, 5);
+        assert_ne!(flipped.id, long.id);
+        assert_eq!(flipped.side, Side::Short);
+        assert_eq!(flipped.size_usdc, Usd::from(400.0));
+        assert_eq!(flipped.trader_collateral, Usd::from(80.0));
+        assert_eq!(state.positions.get(&long.id).unwrap().status, PositionStatus::Closed);
+        assert_eq!(state.open_interest(Market::BtcPerp), OpenInterest { long: Usd::ZERO, short: Usd::from(400.0) });
+        
+        // 完全对冲后不留仓位
+        let closed = open_position(&state, Side::Long, 400.0, 5);
+        assert_eq!(closed.status, PositionStatus::Closed);
+        assert_eq!(state.open_interest(Market::BtcPerp).total(), Usd::ZERO);
+    }
+    
+    fn temp_db_path() -> String {
+        std::env::temp_dir()
+            .join(format!("trade-router-test-{}.db", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned()
+    }
+    
+    fn make_request(agent_id: &str, expires_in: i64) -> TradeRequest {
+        TradeRequest {
+            id: Uuid::new_v4(),
+            agent_id: agent_id.to_string(),
+            market: Market::EthPerp,
+            side: Side::Short,
+            size_usdc: Usd::from(2000.0),
+            leverage: 4,
+            max_funding_rate: 0.01,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: MarginMode::Isolated,
+            reduce_only: false,
+            expires_at: Utc::now() + Duration::seconds(expires_in),
+            created_at: Utc::now(),
+        }
+    }
+    
+    #[test]
+    fn test_reduce_only_accept_only_offsets_existing_position() {
+        let state = AppState::with_db_path(":memory:");
+        let reduce = |side, size_usdc| submit_rfq(&state, Market::BtcPerp, "mm", side, size_usdc, 5, true);
+        
+        // 没有仓位时不能开仓
+        assert!(reduce(Side::Short, 400.0).unwrap_err().contains("no position"));
+        assert!(state.get_agent_positions("trader").is_empty());
+        
+        let long = open_position(&state, Side::Long, 1000.0, 5);
+        state.prices.insert(Market::BtcPerp, 88200.0);
+        
+        // 同向加仓、超过仓位大小 (会反手) 都被拒绝，仓位不变
+        assert!(reduce(Side::Long, 100.0).unwrap_err().contains("increase"));
+        assert!(reduce(Side::Short, 1200.0).unwrap_err().contains("exceeds"));
+        assert_eq!(state.positions.get(&long.id).unwrap().size_usdc, Usd::from(1000.0));
+        
+        // 反向只减仓: 按当前价实现盈亏
+        let reduced = reduce(Side::Short, 400.0).unwrap();
+        assert_eq!(reduced.id, long.id);
+        assert_eq!(reduced.size_usdc, Usd::from(600.0));
+        let (trades, _) = state.get_trade_history("trader", 10, 0).unwrap();
+        assert_eq!(trades[0].pnl_trader, Usd::from(100.0));
+        
+        // 恰好等于剩余仓位时完全平仓
+        let closed = reduce(Side::Short, 600.0).unwrap();
+        assert_eq!(closed.status, PositionStatus::Closed);
+        assert_eq!(state.open_interest(Market::BtcPerp).total(), Usd::ZERO);
+    }
+    
+    #[test]
+    fn test_max_open_positions_blocks_new_positions() {
+        let state = AppState::with_db_path(":memory:");
+        state.set_agent_limits("trader", RiskLimits { max_open_positions: 2, ..Default::default() });
+        
+        // 上限以内可以开仓
+        open_position_in(&state, Market::BtcPerp, "mm", Side::Long, 1000.0, 5);
+        assert!(state.check_risk_limits("trader", Usd::from(1000.0), 5, false).is_ok());
+        open_position_in(&state, Market::EthPerp, "mm", Side::Short, 1000.0, 5);
+        
+        // 达到上限后创建请求和开新仓都被拒绝
+        let err = state.check_risk_limits("trader", Usd::from(1000.0), 5, false).unwrap_err();
+        assert!(err.contains("Open positions 2"));
+        let err = submit_rfq(&state, Market::SolPerp, "mm", Side::Long, 1000.0, 5, false).unwrap_err();
+        assert!(err.contains("Open positions 2"));
+        assert_eq!(state.get_agent_positions("trader").len(), 2);
+        
+        // 合并到已有仓位不占用新的仓位数
+        let increased = submit_rfq(&state, Market::BtcPerp, "mm", Side::Long, 500.0, 5, false).unwrap();
+        assert_eq!(increased.size_usdc, Usd::from(1500.0));
+        
+        // 达到上限时仍可只减仓
+        assert!(state.check_risk_limits("trader", Usd::from(500.0), 5, true).is_ok());
+        let reduced = submit_rfq(&state, Market::BtcPerp, "mm", Side::Short, 500.0, 5, true).unwrap();
+        assert_eq!(reduced.size_usdc, Usd::from(1000.0));
+    }
+    
+    #[test]
+    fn test_daily_loss_limit_trips_on_realized_loss() {
+        let state = AppState::with_db_path(":memory:");
+        state.set_agent_limits("trader", RiskLimits { daily_loss_limit: Usd::from(300.0), ..Default::default() });
+        let position = open_position(&state, Side::Long, 1000.0, 10);
+        
+        // 未实现亏损不计入
+        state.prices.insert(Market::BtcPerp, 79800.0);
+        assert!(state.check_risk_limits("trader", Usd::from(1000.0), 5, false).is_ok());
+        
+        // 平掉一半实现 -250，尚未触发
+        state.close_position(position.id, "trader", 50).unwrap();
+        assert!(state.check_risk_limits("trader", Usd::from(1000.0), 5, false).is_ok());
+        
+        // 全部平仓后当日已实现亏损 500，超过限额
+        state.close_position(position.id, "trader", 100).unwrap();
+        let err = state.check_risk_limits("trader", Usd::from(1000.0), 5, false).unwrap_err();
+        assert!(err.contains("Daily loss 500.00"));
+        // MM 一侧是盈利，不受影响
+        assert!(state.check_risk_limits("mm", Usd::from(1000.0), 5, false).is_ok());
+        // 触发日亏损限额后仍可只减仓
+        assert!(state.check_risk_limits("trader", Usd::from(1000.0), 5, true).is_ok());
+    }
+    
+    #[tokio::test]
+    async fn test_shutdown_stops_tasks_and_persists_state() {
+        let path = temp_db_path();
+        let state = Arc::new(AppState::with_db_path(&path));
+        let position = open_position(&state, Side::Long, 1000.0, 5);
+        let request = make_request("trader", 60);
+        state.add_request(request.clone());
+        // 仅在内存中的修改，关闭时写回
+        state.positions.get_mut(&position.id).unwrap().trader_collateral = Usd::from(321.0);
+        let mut ws_rx = state.broadcast_tx.subscribe();
+        
+        let tasks = vec![
+            tokio::spawn(crate::liquidation::start_liquidation_engine(
+                state.clone(),
+                crate::liquidation::LiquidationConfig { check_interval_ms: 10, ..Default::default() },
+            )),
+            tokio::spawn(crate::funding::start_funding_engine(state.clone(), Default::default())),
+            tokio::spawn(crate::cleanup::start_cleanup_task(state.clone(), std::time::Duration::from_millis(10))),
+        ];
+        tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+        
+        state.begin_shutdown();
+        let stopped = tokio::time::timeout(std::time::Duration::from_secs(2), futures::future::join_all(tasks)).await;
+        assert!(stopped.expect("background tasks did not stop").iter().all(|t| t.is_ok()));
+        assert!(matches!(ws_rx.recv().await.unwrap(), WsMessage::ShuttingDown));
+        assert_eq!(state.persist_all(), 2);
+        drop(state);
+        
+        let reloaded = AppState::with_db_path(&path);
+        assert_eq!(reloaded.positions.get(&position.id).unwrap().trader_collateral, Usd::from(321.0));
+        assert!(reloaded.requests.contains_key(&request.id));
+        
+        std::fs::remove_file(&path).ok();
+    }
+    
+    #[test]
+    fn test_requests_and_quotes_survive_restart() {
+        let path = temp_db_path();
+        let request = make_request("trader", 60);
+        let expired = make_request("trader", -1);
+        let quote = Quote {
+            id: Uuid::new_v4(),
+            request_id: request.id,
+            agent_id: "mm".to_string(),
+            funding_rate: 0.002,
+            collateral_usdc: Usd::from(500.0),
+            valid_until: Utc::now() + Duration::seconds(30),
+            created_at: Utc::now(),
+            protection: None,
+        };
+        
+        {
+            let state = AppState::with_db_path(&path);
+            state.add_request(request.clone());
+            state.add_request(expired.clone());
+            state.add_quote(quote.clone()).unwrap();
+        }
+        
+        let state = AppState::with_db_path(&path);
+        let requests = state.get_active_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].id, request.id);
+        assert_eq!(requests[0].market, Market::EthPerp);
+        assert_eq!(requests[0].side, Side::Short);
+        assert!(!state.requests.contains_key(&expired.id));
+        
+        let quotes = state.get_quotes(request.id);
+        assert_eq!(quotes.len(), 1);
+        assert_eq!(quotes[0].id, quote.id);
+        
+        // Accepting removes the persisted RFQ so it does not come back again
+        state.accept_quote(request.id, quote.id).unwrap();
+        let state = AppState::with_db_path(&path);
+        assert!(state.get_active_requests().is_empty());
+        
+        std::fs::remove_file(&path).ok();
+    }
+    
+    #[test]
+    fn test_active_positions_reload_on_startup() {
+        let path = temp_db_path();
+        let (first, second) = {
+            let state = AppState::with_db_path(&path);
+            let first = open_position(&state, Side::Long, 1000.0, 10);
+            let second = open_position(&state, Side::Short, 500.0, 5);
+            (first, second)
+        };
+        
+        let state = AppState::with_db_path(&path);
+        let mut ids: Vec<Uuid> = state.get_agent_positions("trader").iter().map(|p| p.id).collect();
+        ids.sort();
+        let mut expected = vec![first.id, second.id];
+        expected.sort();
+        assert_eq!(ids, expected);
+        
+        let reloaded = state.positions.get(&first.id).unwrap().clone();
+        assert_eq!(reloaded.status, PositionStatus::Active);
+        assert_eq!(reloaded.market, first.market);
+        assert_eq!(reloaded.side, first.side);
+        assert_eq!(state.positions.get(&second.id).unwrap().side, Side::Short);
+        assert_eq!(state.get_agent_positions("mm").len(), 2);
+        
+        std::fs::remove_file(&path).ok();
+    }
+    
+    #[test]
+    fn test_partial_close_matches_full_close() {
+        let state = AppState::with_db_path(":memory:");
+        let partial = open_position(&state, Side::Long, 1000.0, 10);
+        let full = open_position_in(&state, Market::BtcPerp, "mm-2", Side::Long, 1000.0, 10);
+        state.prices.insert(Market::BtcPerp, 88200.0);
+        
+        let (first_trader, first_mm) = state.close_position(partial.id, "trader", 25).unwrap();
+        {
+            let remaining = state.positions.get(&partial.id).unwrap();
+            assert_eq!(remaining.status, PositionStatus::Active);
+            assert_eq!(remaining.size_usdc, Usd::from(750.0));
+            assert_eq!(remaining.trader_collateral, Usd::from(75.0));
+        }
+        assert_eq!(first_trader, Usd::from(125.0));
+        
+        // Closing 100% of what is left closes the remaining 75%
+        let (rest_trader, rest_mm) = state.close_position(partial.id, "trader", 100).unwrap();
+        assert_eq!(state.positions.get(&partial.id).unwrap().status, PositionStatus::Closed);
+        
+        let (full_trader, full_mm) = state.close_position(full.id, "trader", 100).unwrap();
+        assert_eq!(first_trader + rest_trader, full_trader);
+        assert_eq!(first_mm + rest_mm, full_mm);
+        
+        let (trades, _) = state.get_trade_history("trader", 20, 0).unwrap();
+        let mut sizes: Vec<Usd> = trades.iter()
+            .filter(|t| t.position_id == partial.id)
+            .map(|t| t.size_usdc)
+            .collect();
+        sizes.sort();
+        assert_eq!(sizes, vec![Usd::from(250.0), Usd::from(750.0)]);
+        
+        assert!(state.close_position(full.id, "trader", 0).is_err());
+    }
+    
+    fn quote_for(request_id: Uuid, mm: &str, funding_rate: f64, valid_for: i64) -> Quote {
+        Quote {
+            id: Uuid::new_v4(),
+            request_id,
+            agent_id: mm.to_string(),
+            funding_rate,
+            collateral_usdc: Usd::from(200.0),
+            valid_until: Utc::now() + Duration::seconds(valid_for),
+            created_at: Utc::now(),
+            protection: None,
+        }
+    }
+    
+    /// ETH 报价时价格 2200、资金费率 0，`created_ago_ms` 毫秒前报价
+    fn protected_quote(request_id: Uuid, tolerance_bps: u32, min_valid_ms: u64, created_ago_ms: i64) -> Quote {
+        let mut quote = quote_for(request_id, "mm", 0.002, 30);
+        quote.created_at = Utc::now() - Duration::milliseconds(created_ago_ms);
+        quote.protection = Some(QuoteProtection {
+            min_valid_ms,
+            tolerance_bps,
+            reference_price: 2200.0,
+            reference_funding_rate: 0.0,
+        });
+        quote
+    }
+    
+    #[test]
+    fn test_protected_quote_accepted_within_tolerance() {
+        let state = AppState::with_db_path(":memory:");
+        let request = make_request("trader", 60);
+        state.add_request(request.clone());
+        let quote = protected_quote(request.id, 50, 0, 1_000);
+        state.add_quote(quote.clone()).unwrap();
+        
+        // 约 22.7 bps，容差 50 bps
+        state.prices.insert(Market::EthPerp, 2205.0);
+        state.funding_rates.insert(Market::EthPerp, 0.0004);
+        let position = state.accept_quote(request.id, quote.id).unwrap();
+        assert_eq!(position.quote_id, quote.id);
+    }
+    
+    #[test]
+    fn test_protected_quote_rejected_after_adverse_move() {
+        let state = AppState::with_db_path(":memory:");
+        let request = make_request("trader", 60);
+        state.add_request(request.clone());
+        let quote = protected_quote(request.id, 50, 0, 1_000);
+        state.add_quote(quote.clone()).unwrap();
+        
+        // 价格上涨约 136 bps
+        state.prices.insert(Market::EthPerp, 2230.0);
+        let err = state.accept_quote(request.id, quote.id).unwrap_err();
+        assert!(err.contains("Price moved"), "{}", err);
+        
+        // 价格回到容差内，但资金费率变动 60 bps
+        state.prices.insert(Market::EthPerp, 2200.0);
+        state.funding_rates.insert(Market::EthPerp, 0.006);
+        let err = state.accept_quote(request.id, quote.id).unwrap_err();
+        assert!(err.contains("Funding rate moved"), "{}", err);
+        
+        // 拒绝后请求和报价仍保留，没有开仓
+        assert!(state.requests.contains_key(&request.id));
+        assert!(state.find_quote(quote.id).is_some());
+        assert!(state.get_agent_positions("trader").is_empty());
+        
+        // 保护设置随报价持久化
+        assert_eq!(state.db.load_quotes(&request.id).unwrap()[0].protection, quote.protection);
+    }
+    
+    #[test]
+    fn test_protected_quote_firm_during_min_valid_window() {
+        let state = AppState::with_db_path(":memory:");
+        let request = make_request("trader", 60);
+        state.add_request(request.clone());
+        let quote = protected_quote(request.id, 50, 60_000, 0);
+        state.add_quote(quote.clone()).unwrap();
+        
+        state.prices.insert(Market::EthPerp, 2230.0);
+        assert!(state.accept_quote(request.id, quote.id).is_ok());
+    }
+    
+    #[test]
+    fn test_best_quote_picks_cheapest_valid() {
+        let state = AppState::with_db_path(":memory:");
+        let request = make_request("trader", 60);
+        state.add_request(request.clone());
+        
+        let expensive = quote_for(request.id, "mm1", 0.004, 30);
+        let cheapest_expired = quote_for(request.id, "mm2", 0.001, -1);
+        let cheapest_valid = quote_for(request.id, "mm3", 0.002, 30);
+        for q in [&expensive, &cheapest_expired, &cheapest_valid] {
+            state.add_quote(q.clone()).unwrap();
+        }
+        
+        let best = state.best_quote(request.id, 0.005).unwrap();
+        assert_eq!(best.id, cheapest_valid.id);
+        
+        let position = state.accept_quote(request.id, best.id).unwrap();
+        assert_eq!(position.mm_agent, "mm3");
+        assert_eq!(position.funding_rate, 0.002);
+    }
+    
+    #[test]
+    fn test_best_quote_none_above_cap() {
+        let state = AppState::with_db_path(":memory:");
+        let request = make_request("trader", 60);
+        state.add_request(request.clone());
+        state.add_quote(quote_for(request.id, "mm1", 0.004, 30)).unwrap();
+        state.add_quote(quote_for(request.id, "mm2", 0.003, 30)).unwrap();
+        
+        assert!(state.best_quote(request.id, 0.002).is_none());
+        // Nothing was accepted
+        assert!(state.requests.contains_key(&request.id));
+    }
+    
+    #[test]
+    fn test_add_margin_rescues_borderline_position() {
+        let state = AppState::with_db_path(":memory:");
+        let position = open_position(&state, Side::Long, 1000.0, 10);
+        let config = MarginConfig::default();
+        let liq_before = crate::margin::liquidation_price(&position, &config);
+        
+        // Down ~0.6%: 10x leveraged loss of ~60 USDC leaves equity under maintenance
+        state.prices.insert(Market::BtcPerp, 83500.0);
+        assert!(crate::margin::should_liquidate(&position, 83500.0, &config));
+        
+        let info = state.add_margin(position.id, "trader", Usd::from(100.0)).unwrap();
+        assert_eq!(info.collateral, Usd::from(200.0));
+        assert!(info.liquidation_price < liq_before);
+        assert!(!info.is_liquidatable);
+        
+        let updated = state.positions.get(&position.id).unwrap().clone();
+        assert!(!crate::margin::should_liquidate(&updated, 83500.0, &config));
+        
+        assert!(state.add_margin(position.id, "mm", Usd::from(50.0)).is_err());
+        assert!(state.add_margin(position.id, "trader", Usd::from(-5.0)).is_err());
+    }
+    
+    #[test]
+    fn test_stored_liquidation_price_follows_margin_changes() {
+        let state = AppState::with_db_path(":memory:");
+        let config = MarginConfig::default();
+        let stored = |id: Uuid| state.db.get_all_active_positions().unwrap()
+            .into_iter()
+            .find(|p| p.id == id)
+            .unwrap();
+        
+        // 开仓时写入
+        let position = open_position(&state, Side::Long, 1000.0, 5);
+        let expected = crate::margin::liquidation_price(&position, &config);
+        assert!(expected.is_positive() && expected < Usd::from(84000.0));
+        assert_eq!(position.liquidation_price, expected);
+        assert_eq!(stored(position.id).liquidation_price, expected);
+        
+        // 追加保证金后更新
+        state.add_margin(position.id, "trader", Usd::from(100.0)).unwrap();
+        let updated = state.positions.get(&position.id).unwrap().clone();
+        let expected = crate::margin::liquidation_price(&updated, &config);
+        assert!(expected < position.liquidation_price);
+        assert_eq!(updated.liquidation_price, expected);
+        assert_eq!(stored(position.id).liquidation_price, expected);
+        
+        // 部分平仓后更新
+        state.close_position(position.id, "trader", 50).unwrap();
+        let reduced = state.positions.get(&position.id).unwrap().clone();
+        assert_eq!(stored(position.id).liquidation_price, crate::margin::liquidation_price(&reduced, &config));
+    }
+}
@@ -2,7 +2,10 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::money::Usd;
+
 /// 交易市场
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum Market {
     #[serde(rename = "BTC-PERP")]
@@ -19,6 +22,20 @@ pub enum Market {
     LinkPerp,
 }
 
+impl Market {
+    /// 对外使用的交易对名称 (与 serde 序列化一致)
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Market::BtcPerp => "BTC-PERP",
+            Market::EthPerp => "ETH-PERP",
+            Market::SolPerp => "SOL-PERP",
+            Market::DogePerp => "DOGE-PERP",
+            Market::AvaxPerp => "AVAX-PERP",
+            Market::LinkPerp => "LINK-PERP",
+        }
+    }
+}
+
 /// 交易方向
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
@@ -27,6 +44,16 @@ pub enum Side {
     Short,
 }
 
+impl Side {
+    /// 对手方方向 (MM 与交易方相反)
+    pub fn opposite(self) -> Self {
+        match self {
+            Side::Long => Side::Short,
+            Side::Short => Side::Long,
+        }
+    }
+}
+
 /// 交易请求 - Agent A 发起
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TradeRequest {
@@ -34,9 +61,18 @@ pub struct TradeRequest {
     pub agent_id: String,
     pub market: Market,
     pub side: Side,
-    pub size_usdc: f64,
+    pub size_usdc: Usd,
     pub leverage: u8,
     pub max_funding_rate: f64,
+    #[serde(default)]
+    pub stop_loss: Option<f64>,
+    #[serde(default)]
+    pub take_profit: Option<f64>,
+    #[serde(default)]
+    pub margin_mode: MarginMode,
+    /// 只减仓: 只能抵消与该 MM 的反向仓位，不能开仓或加仓
+    #[serde(default)]
+    pub reduce_only: bool,
     pub expires_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
 }
@@ -47,10 +83,18 @@ pub struct CreateTradeRequest {
     pub agent_id: String,
     pub market: Market,
     pub side: Side,
-    pub size_usdc: f64,
+    pub size_usdc: Usd,
     pub leverage: u8,
     pub max_funding_rate: f64,
     pub expires_in: u64, // 秒
+    #[serde(default)]
+    pub stop_loss: Option<f64>,
+    #[serde(default)]
+    pub take_profit: Option<f64>,
+    #[serde(default)]
+    pub margin_mode: MarginMode,
+    #[serde(default)]
+    pub reduce_only: bool,
 }
 
 /// 报价 - MM Agent 响应
@@ -60,9 +104,25 @@ pub struct Quote {
     pub request_id: Uuid,
     pub agent_id: String,
     pub funding_rate: f64,
-    pub collateral_usdc: f64,
+    pub collateral_usdc: Usd,
     pub valid_until: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub protection: Option<QuoteProtection>,
+}
+
+/// 报价保护: 报价时记录市场状态，确定期过后接受时若价格或资金费率变动超过容差则拒绝，
+/// 避免 MM 在市场变动后以过期费率成交
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QuoteProtection {
+    /// 报价创建后的确定期 (毫秒)，期内接受不做校验
+    pub min_valid_ms: u64,
+    /// 容差 (基点): 价格按相对变动、资金费率按绝对变动计算
+    pub tolerance_bps: u32,
+    /// 报价时的市场价格
+    pub reference_price: f64,
+    /// 报价时的市场资金费率
+    pub reference_funding_rate: f64,
 }
 
 /// 创建报价的输入
@@ -71,18 +131,41 @@ pub struct CreateQuote {
     pub request_id: Uuid,
     pub agent_id: String,
     pub funding_rate: f64,
-    pub collateral_usdc: f64,
+    pub collateral_usdc: Usd,
     pub valid_for: u64, // 秒
+    /// 设置容差 (基点) 后启用报价保护
+    #[serde(default)]
+    pub tolerance_bps: Option<u32>,
+    /// 报价保护的确定期 (毫秒)，需同时设置 tolerance_bps
+    #[serde(default)]
+    pub min_valid_ms: Option<u64>,
 }
 
 /// 接受报价
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct AcceptQuote {
     pub request_id: Uuid,
     pub quote_id: Uuid,
     pub signature: String,
 }
 
+/// 自动接受最优报价
+#[derive(Debug, Deserialize)]
+pub struct AutoAcceptQuote {
+    pub request_id: Uuid,
+    pub max_funding_rate: f64,
+}
+
+/// 保证金模式: 逐仓只用本仓位的保证金，全仓与该 Agent 其他全仓仓位共享保证金
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum MarginMode {
+    #[default]
+    Isolated,
+    Cross,
+}
+
 /// 仓位状态
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
@@ -104,32 +187,134 @@ pub struct Position {
     pub mm_agent: String,        // 做市商
     pub market: Market,
     pub side: Side,              // trader 的方向
-    pub size_usdc: f64,
+    pub size_usdc: Usd,
     pub leverage: u8,
-    pub entry_price: f64,
+    pub entry_price: Usd,
     pub funding_rate: f64,
-    pub trader_collateral: f64,
-    pub mm_collateral: f64,
+    pub trader_collateral: Usd,
+    pub mm_collateral: Usd,
     pub status: PositionStatus,
     pub created_at: DateTime<Utc>,
     pub closed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub stop_loss: Option<f64>,
+    #[serde(default)]
+    pub take_profit: Option<f64>,
+    #[serde(default)]
+    pub margin_mode: MarginMode,
+    /// 强平价 (按默认保证金参数计算，开仓、加减仓和保证金变化时更新)
+    #[serde(default)]
+    pub liquidation_price: Usd,
+    /// 上次结算资金费的时间 (为空表示尚未结算，按开仓时间计)
+    #[serde(default)]
+    pub last_funding_at: Option<DateTime<Utc>>,
+}
+
+impl Position {
+    /// 按当前规模和保证金重新计算强平价
+    pub fn refresh_liquidation_price(&mut self) {
+        self.liquidation_price = crate::margin::liquidation_price(self, &crate::margin::MarginConfig::default());
+    }
+}
+
+/// 已完成的交易记录 (对应 trades 表)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeRecord {
+    pub id: Uuid,
+    pub position_id: Uuid,
+    pub trader_agent: String,
+    pub mm_agent: String,
+    pub market: Market,
+    pub side: Side,
+    pub size_usdc: Usd,
+    pub entry_price: Usd,
+    pub exit_price: Usd,
+    pub pnl_trader: Usd,
+    pub pnl_mm: Usd,
+    pub created_at: DateTime<Utc>,
+    pub closed_at: DateTime<Utc>,
+}
+
+impl TradeRecord {
+    /// 根据平仓结果生成交易记录
+    pub fn from_closed_position(position: &Position, exit_price: Usd, pnl_trader: Usd, pnl_mm: Usd) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            position_id: position.id,
+            trader_agent: position.trader_agent.clone(),
+            mm_agent: position.mm_agent.clone(),
+            market: position.market,
+            side: position.side,
+            size_usdc: position.size_usdc,
+            entry_price: position.entry_price,
+            exit_price,
+            pnl_trader,
+            pnl_mm,
+            created_at: position.created_at,
+            closed_at: position.closed_at.unwrap_or_else(Utc::now),
+        }
+    }
 }
 
 /// 平仓请求
 #[derive(Debug, Deserialize)]
 pub struct ClosePosition {
     pub position_id: Uuid,
-    pub agent_id: String,
     pub size_percent: u8, // 1-100
 }
 
+/// 修改止损/止盈 (传 null 表示取消)
+#[derive(Debug, Deserialize)]
+pub struct ModifyPosition {
+    pub position_id: Uuid,
+    #[serde(default)]
+    pub stop_loss: Option<f64>,
+    #[serde(default)]
+    pub take_profit: Option<f64>,
+}
+
+/// 止损/止盈触发类型
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StopTrigger {
+    StopLoss,
+    TakeProfit,
+}
+
+/// 运维强制结算请求
+#[derive(Debug, Deserialize)]
+pub struct ForceSettlePosition {
+    pub price: f64,
+}
+
+/// 临近强平仓位查询参数: 当前价距强平价在 `threshold` (%) 以内
+#[derive(Debug, Deserialize)]
+pub struct AtRiskParams {
+    pub threshold: f64,
+}
+
+/// 临近强平的仓位，`distance_pct` 为当前价到强平价的距离 (%，已越过强平价时为负)
+#[derive(Debug, Clone, Serialize)]
+pub struct AtRiskPosition {
+    #[serde(flatten)]
+    pub position: Position,
+    pub current_price: f64,
+    pub distance_pct: f64,
+}
+
+/// 追加保证金请求
+#[derive(Debug, Deserialize)]
+pub struct AddMargin {
+    pub add_usdc: Usd,
+}
+
 /// 包含 PnL 的仓位信息
 #[derive(Debug, Clone, Serialize)]
 pub struct PositionWithPnl {
     #[serde(flatten)]
     pub position: Position,
-    pub pnl_trader: Option<f64>,
-    pub pnl_mm: Option<f64>,
+    pub pnl_trader: Option<Usd>,
+    pub pnl_mm: Option<Usd>,
 }
 
 /// 分页查询参数
@@ -143,6 +328,122 @@ pub struct PaginationParams {
 
 fn default_limit() -> u32 { 20 }
 
+/// 资金费率历史查询参数，时间为 RFC 3339；interval 单位为小时，会向上取整到结算周期的整数倍
+#[derive(Debug, Default, Deserialize)]
+pub struct FundingHistoryParams {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub interval: Option<u64>,
+}
+
+/// 仓位保证金查询参数
+#[derive(Debug, Default, Deserialize)]
+pub struct MarginQueryParams {
+    pub market: Option<Market>,
+}
+
+/// 资金费支付查询参数: 分页 + 可选的时间范围 [from, to) 和市场
+#[derive(Debug, Deserialize)]
+pub struct FundingPaymentParams {
+    #[serde(default = "default_limit")]
+    pub limit: u32,
+    #[serde(default)]
+    pub offset: u32,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub market: Option<Market>,
+}
+
+impl Default for FundingPaymentParams {
+    fn default() -> Self {
+        Self { limit: default_limit(), offset: 0, from: None, to: None, market: None }
+    }
+}
+
+/// 保险基金流水 (对应 insurance_fund_events 表)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InsuranceFundEvent {
+    pub position_id: Uuid,
+    pub kind: String,            // "fee" 收入 / "draw" 支出
+    pub amount: Usd,
+    pub balance_after: Usd,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 社会化亏损分摊 (对应 socialized_losses 表): 保险基金耗尽后，穿仓缺口由盈利方分摊
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SocializedLoss {
+    pub position_id: Uuid,         // 穿仓的仓位
+    pub winner_position_id: Uuid,  // 分摊亏损的盈利仓位
+    pub agent_id: String,
+    pub amount: Usd,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 保险基金概览: 当前余额 + 最近的支出和社会化亏损
+#[derive(Debug, Clone, Serialize)]
+pub struct InsuranceFundSummary {
+    pub balance: Usd,
+    pub recent_draws: Vec<InsuranceFundEvent>,
+    pub recent_socialized_losses: Vec<SocializedLoss>,
+}
+
+/// 链上结算动作
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SettlementAction {
+    Open,
+    Close,
+    /// 部分平仓: size 为平掉的部分
+    Reduce,
+}
+
+impl SettlementAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettlementAction::Open => "open",
+            SettlementAction::Close => "close",
+            SettlementAction::Reduce => "reduce",
+        }
+    }
+}
+
+/// 结算任务状态: pending 等待 (重)试，failed 重试次数用尽，done 已上链
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SettlementJobStatus {
+    Pending,
+    Failed,
+    Done,
+}
+
+impl SettlementJobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettlementJobStatus::Pending => "pending",
+            SettlementJobStatus::Failed => "failed",
+            SettlementJobStatus::Done => "done",
+        }
+    }
+}
+
+/// 链上结算任务 (对应 settlement_queue 表)，以 id 去重
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettlementJob {
+    pub id: Uuid,
+    pub position_id: Uuid,
+    pub action: SettlementAction,
+    pub owner: String,
+    pub market: Market,
+    pub size: i64,               // 开仓/平仓数量 (合约单位，空头为负)
+    pub price: f64,              // 开仓价 / 平仓价
+    pub status: SettlementJobStatus,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
 /// 分页响应
 #[derive(Debug, Serialize)]
 pub struct PaginatedResponse<T> {
@@ -159,16 +460,50 @@ pub enum WsMessage {
     // Server -> Client
     #[serde(rename = "trade_request")]
     TradeRequest(TradeRequest),
+    #[serde(rename = "request_expired")]
+    RequestExpired { request_id: Uuid },
+    #[serde(rename = "request_cancelled")]
+    RequestCancelled { request_id: Uuid },
+    #[serde(rename = "quote_withdrawn")]
+    QuoteWithdrawn { request_id: Uuid, quote_id: Uuid },
     #[serde(rename = "quote_accepted")]
     QuoteAccepted { request_id: Uuid, quote_id: Uuid, position_id: Uuid },
     #[serde(rename = "position_opened")]
     PositionOpened(Position),
     #[serde(rename = "position_closed")]
-    PositionClosed { position_id: Uuid, pnl_trader: f64, pnl_mm: f64 },
+    PositionClosed { position_id: Uuid, pnl_trader: Usd, pnl_mm: Usd },
+    #[serde(rename = "position_increased")]
+    PositionIncreased { position_id: Uuid, added_size_usdc: Usd, size_usdc: Usd, entry_price: Usd },
+    #[serde(rename = "position_reduced")]
+    PositionReduced { position_id: Uuid, closed_size_usdc: Usd, remaining_size_usdc: Usd, pnl_trader: Usd, pnl_mm: Usd },
+    #[serde(rename = "stop_triggered")]
+    StopTriggered { position_id: Uuid, trigger: StopTrigger, trigger_price: f64, current_price: f64, pnl_trader: Usd },
+    #[serde(rename = "margin_added")]
+    MarginAdded { position_id: Uuid, add_usdc: Usd, trader_collateral: Usd, liquidation_price: Usd },
+    /// 运维强制结算 (`price` 为 None 表示按零盈亏撤销)
+    #[serde(rename = "force_settled")]
+    ForceSettled { position_id: Uuid, price: Option<f64>, pnl_trader: Usd, pnl_mm: Usd },
     #[serde(rename = "liquidation")]
     Liquidation(crate::liquidation::LiquidationEvent),
     #[serde(rename = "error")]
     Error { message: String },
+    /// 服务即将关闭，客户端应稍后重连
+    #[serde(rename = "shutting_down")]
+    ShuttingDown,
+    
+    // Server -> Client，仅推送给相关 Agent (见 `PrivateMessage`)
+    /// 报价被接受成交；`side` 为收到消息的 Agent 自己的方向
+    #[serde(rename = "order_filled")]
+    OrderFilled { request_id: Uuid, quote_id: Uuid, position_id: Uuid, market: Market, side: Side, size_usdc: Usd, price: f64 },
+    /// 仓位的最新状态 (开仓、加减仓、平仓、保证金变化)
+    #[serde(rename = "position_updated")]
+    PositionUpdated(Position),
+    /// 资金费结算；`amount` 为正表示该 Agent 支付
+    #[serde(rename = "funding_charged")]
+    FundingCharged { position_id: Uuid, funding_rate: f64, amount: Usd },
+    /// 保证金健康度跌破预警线
+    #[serde(rename = "margin_call")]
+    MarginCall { position_id: Uuid, margin_health: f64, liquidation_price: Usd, current_price: f64 },
     
     // Client -> Server
     #[serde(rename = "subscribe")]
@@ -177,14 +512,41 @@ pub enum WsMessage {
     Unsubscribe { markets: Vec<Market> },
 }
 
+/// 只发给某个 Agent 的 WebSocket 消息
+#[derive(Debug, Clone)]
+pub struct PrivateMessage {
+    pub agent_id: String,
+    pub message: WsMessage,
+}
+
 /// 市场信息
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MarketInfo {
     pub market: Market,
     pub current_price: f64,
+    /// 价格最后一次成功更新的时间 (None: 启动后尚未更新)
+    pub price_updated_at: Option<DateTime<Utc>>,
     pub funding_rate_24h: f64,
-    pub open_interest: f64,
+    /// 多空未平仓量合计 (USDC)
+    pub open_interest: Usd,
+    pub long_open_interest: Usd,
+    pub short_open_interest: Usd,
     pub volume_24h: f64,
+    pub min_size_usdc: f64,
+    pub max_leverage: u8,
+}
+
+/// 单个市场的未平仓名义价值 (USDC)，对应链上 `Market.long_open_interest` / `short_open_interest`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct OpenInterest {
+    pub long: Usd,
+    pub short: Usd,
+}
+
+impl OpenInterest {
+    pub fn total(&self) -> Usd {
+        self.long + self.short
+    }
 }
 
 /// API 响应
@@ -252,33 +614,84 @@ pub struct AgentStats {
     pub wins: u32,
     pub losses: u32,
     pub win_rate: f64,
-    pub total_pnl: f64,
-    pub avg_pnl: f64,
-    pub total_volume: f64,
+    /// 含资金费的净收益 (同 net_pnl，保留以兼容旧客户端)
+    pub total_pnl: Usd,
+    pub avg_pnl: Usd,
+    /// 已平仓仓位的价格盈亏 (trader 与 MM 两侧)
+    pub gross_pnl: Usd,
+    /// 资金费净额 (收到为正，支付为负)
+    pub net_funding: Usd,
+    /// gross_pnl + net_funding
+    pub net_pnl: Usd,
+    pub total_volume: Usd,
 }
 
 // ============ 风险限额 ============
 
+/// 市场级交易约束，对所有 Agent 相同 (Agent 自己的限额见 `RiskLimits`)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct MarketConstraints {
+    /// 最小请求大小 (USDC)
+    pub min_size_usdc: f64,
+    /// 交易所允许的最大杠杆
+    pub max_leverage: u8,
+}
+
+impl MarketConstraints {
+    /// 各市场的约束
+    pub fn for_market(market: Market) -> Self {
+        let (min_size_usdc, max_leverage) = match market {
+            Market::BtcPerp => (50.0, 50),
+            Market::EthPerp => (20.0, 50),
+            Market::SolPerp => (10.0, 20),
+            Market::DogePerp | Market::AvaxPerp | Market::LinkPerp => (10.0, 10),
+        };
+        Self { min_size_usdc, max_leverage }
+    }
+    
+    /// 检查请求大小和杠杆是否满足约束
+    pub fn check(&self, size_usdc: Usd, leverage: u8) -> Result<(), String> {
+        if size_usdc < Usd::from(self.min_size_usdc) {
+            return Err(format!("Size {} is below the market minimum of {} USDC", size_usdc, self.min_size_usdc));
+        }
+        if leverage == 0 {
+            return Err("Leverage must be at least 1x".to_string());
+        }
+        if leverage > self.max_leverage {
+            return Err(format!("Leverage {}x exceeds the market maximum of {}x", leverage, self.max_leverage));
+        }
+        Ok(())
+    }
+}
+
 /// Agent 风险限额
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RiskLimits {
     /// 最大单仓大小 (USDC)
-    pub max_position_size: f64,
+    pub max_position_size: Usd,
     /// 最大杠杆
     pub max_leverage: u8,
     /// 最大总敞口 (USDC)
-    pub max_total_exposure: f64,
-    /// 日亏损限额 (USDC)
-    pub daily_loss_limit: f64,
+    pub max_total_exposure: Usd,
+    /// 日亏损限额 (USDC)，按当日已实现盈亏计算
+    pub daily_loss_limit: Usd,
+    /// 最多同时持有的活跃仓位数
+    #[serde(default = "default_max_open_positions")]
+    pub max_open_positions: u32,
+}
+
+fn default_max_open_positions() -> u32 {
+    50
 }
 
 impl Default for RiskLimits {
     fn default() -> Self {
         Self {
-            max_position_size: 100_000.0,  // 10万 USDC
+            max_position_size: Usd::from(100_000.0),  // 10万 USDC
             max_leverage: 20,
-            max_total_exposure: 500_000.0, // 50万 USDC
-            daily_loss_limit: 10_000.0,    // 1万 USDC
+            max_total_exposure: Usd::from(500_000.0), // 50万 USDC
+            daily_loss_limit: Usd::from(10_000.0),    // 1万 USDC
+            max_open_positions: default_max_open_positions(),
         }
     }
 }
@@ -286,8 +699,10 @@ impl Default for RiskLimits {
 /// 设置风险限额的输入
 #[derive(Debug, Deserialize)]
 pub struct SetRiskLimits {
-    pub max_position_size: Option<f64>,
+    pub max_position_size: Option<Usd>,
     pub max_leverage: Option<u8>,
-    pub max_total_exposure: Option<f64>,
-    pub daily_loss_limit: Option<f64>,
+    pub max_total_exposure: Option<Usd>,
+    pub daily_loss_limit: Option<Usd>,
+    #[serde(default)]
+    pub max_open_positions: Option<u32>,
 }
@@ -1,41 +1,160 @@
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Query, State,
     },
+    http::HeaderMap,
     response::Response,
 };
 use futures::{SinkExt, StreamExt};
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast;
+use tokio::time::{interval_at, Instant};
 use tracing::{info, warn};
 
 use crate::state::AppState;
-use crate::types::WsMessage;
+use crate::types::{AgentInfo, Market, WsMessage};
+
+/// 心跳参数: 定期发送 Ping，超过 `idle_timeout` 未收到客户端任何帧则断开
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    pub ping_interval: Duration,
+    pub idle_timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(20),
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// WebSocket 认证参数
+#[derive(Debug, serde::Deserialize)]
+pub struct WsAuthParams {
+    pub api_key: Option<String>,
+}
 
 /// WebSocket 升级处理
+///
+/// API key 通过 `?api_key=` 或 `Sec-WebSocket-Protocol` 传入；
+/// 无效时升级后立即以 policy violation 关闭，不发送任何数据。
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
+    Query(params): Query<WsAuthParams>,
+    headers: HeaderMap,
 ) -> Response {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+    let protocol_key = headers
+        .get("Sec-WebSocket-Protocol")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|s| s.trim().to_string());
+    
+    // 通过子协议传 key 时需要回显该协议，否则客户端会断开
+    let ws = match &protocol_key {
+        Some(key) if params.api_key.is_none() => ws.protocols([key.clone()]),
+        _ => ws,
+    };
+    
+    let agent = params.api_key
+        .or(protocol_key)
+        .and_then(|key| state.validate_api_key(&key));
+    
+    ws.on_upgrade(move |socket| async move {
+        match agent {
+            Some(agent) => handle_socket(socket, state, agent, Heartbeat::default()).await,
+            None => reject_socket(socket).await,
+        }
+    })
+}
+
+/// 认证失败: 以 policy violation 关闭连接
+async fn reject_socket(mut socket: WebSocket) {
+    warn!("WebSocket connection rejected: invalid or missing API key");
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code: close_code::POLICY,
+            reason: "Invalid or missing API key".into(),
+        })))
+        .await;
+}
+
+/// 单个连接的市场订阅 (空集合表示全部市场)
+#[derive(Debug, Default)]
+struct Subscriptions {
+    markets: HashSet<Market>,
+}
+
+impl Subscriptions {
+    /// 处理客户端的订阅/取消订阅消息
+    fn apply(&mut self, msg: WsMessage) {
+        match msg {
+            WsMessage::Subscribe { markets } => {
+                info!("Client subscribed to markets: {:?}", markets);
+                self.markets.extend(markets);
+            }
+            WsMessage::Unsubscribe { markets } => {
+                info!("Client unsubscribed from markets: {:?}", markets);
+                for market in markets {
+                    self.markets.remove(&market);
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    /// 广播消息是否需要转发给该连接
+    fn should_forward(&self, state: &AppState, msg: &WsMessage) -> bool {
+        if self.markets.is_empty() {
+            return true;
+        }
+        match message_market(state, msg) {
+            Some(market) => self.markets.contains(&market),
+            None => true,
+        }
+    }
+}
+
+/// 提取广播消息对应的市场；只带 position_id 的消息从仓位中查找
+fn message_market(state: &AppState, msg: &WsMessage) -> Option<Market> {
+    let position_id = match msg {
+        WsMessage::TradeRequest(req) => return Some(req.market),
+        WsMessage::PositionOpened(position) => return Some(position.market),
+        WsMessage::Liquidation(event) => event.position_id.parse().ok()?,
+        WsMessage::QuoteAccepted { position_id, .. }
+        | WsMessage::PositionClosed { position_id, .. }
+        | WsMessage::PositionReduced { position_id, .. }
+        | WsMessage::PositionIncreased { position_id, .. }
+        | WsMessage::StopTriggered { position_id, .. }
+        | WsMessage::MarginAdded { position_id, .. }
+        | WsMessage::ForceSettled { position_id, .. } => *position_id,
+        _ => return None,
+    };
+    state.positions.get(&position_id).map(|p| p.market)
 }
 
 /// 处理 WebSocket 连接
-async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
+async fn handle_socket(socket: WebSocket, state: Arc<AppState>, agent: AgentInfo, heartbeat: Heartbeat) {
     let (mut sender, mut receiver) = socket.split();
     
-    // 订阅广播频道
+    // 订阅广播频道和私有消息频道
     let mut broadcast_rx = state.broadcast_tx.subscribe();
+    let mut private_rx = state.private_tx.subscribe();
     
-    info!("New WebSocket connection established");
+    info!("New WebSocket connection established for agent {}", agent.id);
     
     // 发送欢迎消息
     let welcome = serde_json::json!({
         "type": "connected",
-        "message": "Welcome to AI Perp DEX P2P Trading"
+        "message": "Welcome to AI Perp DEX P2P Trading",
+        "agent_id": agent.id,
     });
-    if sender.send(Message::Text(welcome.to_string().into())).await.is_err() {
+    if sender.send(Message::Text(welcome.to_string())).await.is_err() {
         return;
     }
     
@@ -43,31 +162,41 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
     for req in state.get_active_requests() {
         let msg = WsMessage::TradeRequest(req);
         if let Ok(json) = serde_json::to_string(&msg) {
-            if sender.send(Message::Text(json.into())).await.is_err() {
+            if sender.send(Message::Text(json)).await.is_err() {
                 return;
             }
         }
     }
     
+    let mut subscriptions = Subscriptions::default();
+    
+    // 收到客户端的任何帧 (包括 Pong) 都算活跃
+    let mut last_seen = Instant::now();
+    let mut ping_ticker = interval_at(Instant::now() + heartbeat.ping_interval, heartbeat.ping_interval);
+    
     // 并发处理: 接收客户端消息 + 转发广播
     loop {
         tokio::select! {
+            // 关闭信号最后检查，确保先转发 ShuttingDown 广播
+            biased;
+            
             // 接收客户端消息
             msg = receiver.next() => {
+                if matches!(msg, Some(Ok(_))) {
+                    last_seen = Instant::now();
+                }
                 match msg {
                     Some(Ok(Message::Text(text))) => {
                         // 解析并处理客户端消息
                         if let Ok(ws_msg) = serde_json::from_str::<WsMessage>(&text) {
-                            match ws_msg {
-                                WsMessage::Subscribe { markets } => {
-                                    info!("Client subscribed to markets: {:?}", markets);
-                                    // TODO: 实现市场过滤
-                                }
-                                WsMessage::Unsubscribe { markets } => {
-                                    info!("Client unsubscribed from markets: {:?}", markets);
-                                }
-                                _ => {}
-                            }
+                            subscriptions.apply(ws_msg);
+                        }
+                    }
+                    // 回应客户端 Ping
+                    Some(Ok(Message::Ping(data))) => {
+                        let pong = sender.send(Message::Pong(data)).await;
+                        if pong.is_err() {
+                            break;
                         }
                     }
                     Some(Ok(Message::Close(_))) => {
@@ -87,8 +216,11 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
             broadcast_msg = broadcast_rx.recv() => {
                 match broadcast_msg {
                     Ok(ws_msg) => {
+                        if !subscriptions.should_forward(&state, &ws_msg) {
+                            continue;
+                        }
                         if let Ok(json) = serde_json::to_string(&ws_msg) {
-                            if sender.send(Message::Text(json.into())).await.is_err() {
+                            if sender.send(Message::Text(json)).await.is_err() {
                                 break;
                             }
                         }
@@ -99,8 +231,277 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
                     Err(_) => break,
                 }
             }
+            
+            // 转发发给本 Agent 的私有消息 (不受市场订阅限制)
+            private_msg = private_rx.recv() => {
+                match private_msg {
+                    Ok(private) if private.agent_id == agent.id => {
+                        if let Ok(json) = serde_json::to_string(&private.message) {
+                            if sender.send(Message::Text(json)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("WebSocket client lagged {} private messages", n);
+                    }
+                    Err(_) => break,
+                }
+            }
+            
+            // 心跳: 超时未活跃则关闭，否则发送 Ping
+            _ = ping_ticker.tick() => {
+                if last_seen.elapsed() >= heartbeat.idle_timeout {
+                    warn!("WebSocket client {} idle for {:?}, disconnecting", agent.id, last_seen.elapsed());
+                    let _ = sender
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::AWAY,
+                            reason: "Idle timeout".into(),
+                        })))
+                        .await;
+                    break;
+                }
+                if sender.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+            }
+            
+            // 服务关闭
+            _ = state.shutdown.cancelled() => {
+                let _ = sender.send(Message::Close(None)).await;
+                break;
+            }
         }
     }
     
     info!("WebSocket connection closed");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Usd;
+    use crate::types::{MarginMode, Side, TradeRequest};
+    use axum::{routing::get, Router};
+    use chrono::{Duration, Utc};
+    use tokio_tungstenite::{connect_async, tungstenite};
+    use uuid::Uuid;
+    
+    async fn spawn_server() -> std::net::SocketAddr {
+        spawn_server_with_state().await.0
+    }
+    
+    async fn spawn_server_with_state() -> (std::net::SocketAddr, Arc<AppState>) {
+        let state = Arc::new(AppState::with_db_path(":memory:"));
+        for (id, api_key) in [("agent_ws", "th_ws_key"), ("agent_b", "th_b_key")] {
+            state.register_agent(AgentInfo {
+                id: id.to_string(),
+                api_key: api_key.to_string(),
+                name: None,
+                is_mm: true,
+                created_at: Utc::now(),
+            });
+        }
+        let app = Router::new().route("/ws", get(ws_handler)).with_state(state.clone());
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (addr, state)
+    }
+    
+    #[tokio::test]
+    async fn test_invalid_key_closed_before_welcome() {
+        let addr = spawn_server().await;
+        let (mut socket, _) = connect_async(format!("ws://{}/ws?api_key=wrong", addr)).await.unwrap();
+        
+        match socket.next().await {
+            Some(Ok(tungstenite::Message::Close(Some(frame)))) => {
+                assert_eq!(frame.code, tungstenite::protocol::frame::coding::CloseCode::Policy);
+            }
+            other => panic!("expected policy close, got {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_valid_key_receives_welcome() {
+        let addr = spawn_server().await;
+        let (mut socket, _) = connect_async(format!("ws://{}/ws?api_key=th_ws_key", addr)).await.unwrap();
+        
+        match socket.next().await {
+            Some(Ok(tungstenite::Message::Text(text))) => {
+                let welcome: serde_json::Value = serde_json::from_str(&text).unwrap();
+                assert_eq!(welcome["type"], "connected");
+                assert_eq!(welcome["agent_id"], "agent_ws");
+            }
+            other => panic!("expected welcome, got {:?}", other),
+        }
+    }
+    
+    fn trade_request(market: Market) -> WsMessage {
+        WsMessage::TradeRequest(TradeRequest {
+            id: Uuid::new_v4(),
+            agent_id: "trader".to_string(),
+            market,
+            side: Side::Long,
+            size_usdc: Usd::from(100.0),
+            leverage: 2,
+            max_funding_rate: 0.01,
+            stop_loss: None,
+            take_profit: None,
+            margin_mode: MarginMode::Isolated,
+            reduce_only: false,
+            expires_at: Utc::now() + Duration::seconds(60),
+            created_at: Utc::now(),
+        })
+    }
+    
+    #[test]
+    fn test_market_subscription_filtering() {
+        let state = AppState::with_db_path(":memory:");
+        let mut subs = Subscriptions::default();
+        
+        // Empty subscription set receives everything
+        assert!(subs.should_forward(&state, &trade_request(Market::EthPerp)));
+        
+        let client_msg = serde_json::from_str::<WsMessage>(
+            r#"{"type":"subscribe","data":{"markets":["BTC-PERP","SOL-PERP"]}}"#
+        ).unwrap();
+        subs.apply(client_msg);
+        assert!(subs.should_forward(&state, &trade_request(Market::BtcPerp)));
+        assert!(!subs.should_forward(&state, &trade_request(Market::EthPerp)));
+        
+        // Messages without a market always pass through
+        assert!(subs.should_forward(&state, &WsMessage::Error { message: "oops".to_string() }));
+        
+        subs.apply(WsMessage::Unsubscribe { markets: vec![Market::BtcPerp] });
+        assert!(!subs.should_forward(&state, &trade_request(Market::BtcPerp)));
+        assert!(subs.should_forward(&state, &trade_request(Market::SolPerp)));
+    }
+    
+    /// Text frames received until `timeout` passes without a new one
+    async fn drain_types<S>(socket: &mut S, timeout: std::time::Duration) -> Vec<String>
+    where
+        S: StreamExt<Item = Result<tungstenite::Message, tungstenite::Error>> + Unpin,
+    {
+        let mut types = Vec::new();
+        while let Ok(Some(Ok(msg))) = tokio::time::timeout(timeout, socket.next()).await {
+            if let tungstenite::Message::Text(text) = msg {
+                let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+                types.push(value["type"].as_str().unwrap_or_default().to_string());
+            }
+        }
+        types
+    }
+    
+    #[tokio::test]
+    async fn test_private_fill_only_reaches_own_socket() {
+        let (addr, state) = spawn_server_with_state().await;
+        let (mut socket_a, _) = connect_async(format!("ws://{}/ws?api_key=th_ws_key", addr)).await.unwrap();
+        let (mut socket_b, _) = connect_async(format!("ws://{}/ws?api_key=th_b_key", addr)).await.unwrap();
+        let short = std::time::Duration::from_millis(200);
+        drain_types(&mut socket_a, short).await;
+        drain_types(&mut socket_b, short).await;
+        
+        // agent_ws trades against a third-party MM
+        let WsMessage::TradeRequest(request) = trade_request(Market::BtcPerp) else { unreachable!() };
+        let request = TradeRequest { agent_id: "agent_ws".to_string(), ..request };
+        let quote = crate::types::Quote {
+            id: Uuid::new_v4(),
+            request_id: request.id,
+            agent_id: "mm".to_string(),
+            funding_rate: 0.001,
+            collateral_usdc: Usd::from(20.0),
+            valid_until: Utc::now() + Duration::seconds(30),
+            created_at: Utc::now(),
+            protection: None,
+        };
+        state.add_request(request.clone());
+        state.add_quote(quote.clone()).unwrap();
+        state.accept_quote(request.id, quote.id).unwrap();
+        
+        let received_a = drain_types(&mut socket_a, short).await;
+        let received_b = drain_types(&mut socket_b, short).await;
+        
+        assert!(received_a.contains(&"order_filled".to_string()));
+        assert!(received_a.contains(&"position_updated".to_string()));
+        // Public messages share the same socket
+        assert!(received_a.contains(&"quote_accepted".to_string()));
+        assert!(received_b.contains(&"quote_accepted".to_string()));
+        assert!(!received_b.contains(&"order_filled".to_string()));
+        assert!(!received_b.contains(&"position_updated".to_string()));
+    }
+    
+    /// Server whose sockets all belong to agent_ws, with a fast heartbeat
+    async fn spawn_heartbeat_server(heartbeat: Heartbeat) -> (std::net::SocketAddr, Arc<AppState>) {
+        let (_, state) = spawn_server_with_state().await;
+        let app = Router::new()
+            .route("/ws", get(move |ws: WebSocketUpgrade, State(state): State<Arc<AppState>>| async move {
+                let agent = state.validate_api_key("th_ws_key").unwrap();
+                ws.on_upgrade(move |socket| handle_socket(socket, state, agent, heartbeat))
+            }))
+            .with_state(state.clone());
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (addr, state)
+    }
+    
+    const FAST_HEARTBEAT: Heartbeat = Heartbeat {
+        ping_interval: std::time::Duration::from_millis(50),
+        idle_timeout: std::time::Duration::from_millis(200),
+    };
+    
+    #[tokio::test]
+    async fn test_idle_client_is_disconnected() {
+        let (addr, state) = spawn_heartbeat_server(FAST_HEARTBEAT).await;
+        let (mut socket, _) = connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        
+        // Not reading means the server's pings go unanswered
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        // The connection's broadcast subscription is dropped with it
+        assert_eq!(state.broadcast_tx.receiver_count(), 0);
+        
+        // Buffered frames drain, then the stream ends (the close may surface as a
+        // write error when the client tries to answer the stale pings)
+        let ended = tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while let Some(Ok(_)) = socket.next().await {}
+        });
+        assert!(ended.await.is_ok());
+    }
+    
+    #[tokio::test]
+    async fn test_responsive_client_stays_connected() {
+        let (addr, state) = spawn_heartbeat_server(FAST_HEARTBEAT).await;
+        let (mut socket, _) = connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        
+        // Reading lets the client answer each ping with a pong
+        let mut pings = 0;
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_millis(600);
+        while let Ok(msg) = tokio::time::timeout_at(deadline, socket.next()).await {
+            match msg {
+                Some(Ok(tungstenite::Message::Ping(_))) => pings += 1,
+                Some(Ok(tungstenite::Message::Close(frame))) => panic!("unexpected close: {:?}", frame),
+                Some(Ok(_)) => {}
+                other => panic!("connection ended: {:?}", other),
+            }
+        }
+        assert!(pings >= 3);
+        assert_eq!(state.broadcast_tx.receiver_count(), 1);
+        
+        // Client pings are answered with a pong carrying the same payload
+        socket.send(tungstenite::Message::Ping(b"hb".to_vec())).await.unwrap();
+        loop {
+            match socket.next().await {
+                Some(Ok(tungstenite::Message::Pong(data))) => {
+                    assert_eq!(data, b"hb".to_vec());
+                    break;
+                }
+                Some(Ok(_)) => {}
+                other => panic!("expected pong, got {:?}", other),
+            }
+        }
+    }
+}